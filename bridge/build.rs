@@ -0,0 +1,40 @@
+//! Generates the gRPC service stubs
+//!
+//! The service is declared with `tonic_build::manual` so the build does not
+//! depend on `protoc`. It must match `proto/hoc_bridge.proto`.
+
+use tonic_build::manual::{Builder, Method, Service};
+
+const PROTO_PATH: &str = "crate::server::grpc::proto";
+
+fn method(name: &str, route: &str, input: &str, output: &str) -> tonic_build::manual::MethodBuilder {
+    Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("{}::{}", PROTO_PATH, input))
+        .output_type(format!("{}::{}", PROTO_PATH, output))
+        .codec_path("tonic::codec::ProstCodec")
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto/hoc_bridge.proto");
+
+    let service = Service::builder()
+        .name("HocBridge")
+        .package("hoc.bridge.v1")
+        .method(method("list_agents", "ListAgents", "ListAgentsRequest", "ListAgentsResponse").build())
+        .method(method("get_agent_status", "GetAgentStatus", "AgentRequest", "AgentInfo").build())
+        .method(method("spawn_agent", "SpawnAgent", "SpawnAgentRequest", "AgentInfo").build())
+        .method(method("send_input", "SendInput", "SendInputRequest", "Empty").build())
+        .method(method("resize_terminal", "ResizeTerminal", "ResizeTerminalRequest", "Empty").build())
+        .method(method("kill_agent", "KillAgent", "KillAgentRequest", "Empty").build())
+        .method(
+            method("stream_events", "StreamEvents", "StreamEventsRequest", "AgentEvent")
+                .server_streaming()
+                .build(),
+        )
+        .build();
+
+    Builder::new().compile(&[service]);
+}
@@ -0,0 +1,314 @@
+//! Agent backend traits
+//!
+//! The servers drive agents through these traits rather than a concrete
+//! [`AgentManager`], so they can be constructed with mock implementations for
+//! protocol tests, or with a backend that runs agents somewhere else.
+//! [`AgentSpawner`] starts agents; [`AgentBackend`] covers everything done
+//! with them afterwards.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use super::{
+    AgentEvent, AgentManager, ApprovalDecision, HistoryEntry, ManagerError, ManagerResult,
+    SessionError, SpawnConfig,
+};
+use crate::protocol::{AgentInfo, ScreenState};
+use crate::pty::{EchoState, InputModes};
+
+/// Starts agents
+#[async_trait]
+pub trait AgentSpawner: Send + Sync {
+    /// Start an agent, returning its ID
+    async fn spawn_agent(&self, config: SpawnConfig) -> ManagerResult<Uuid>;
+
+    /// Re-attach agents that outlived a previous run, returning how many
+    async fn reattach_persistent(&self) -> usize {
+        0
+    }
+}
+
+/// Registry of running agents and their I/O
+///
+/// Optional features (history, macros, keybindings, confirmations) have
+/// defaults that behave as if nothing is configured.
+#[async_trait]
+pub trait AgentBackend: Send + Sync {
+    /// Subscribe to events of all agents
+    fn subscribe(&self) -> broadcast::Receiver<AgentEvent>;
+
+    /// Number of running agents
+    async fn session_count(&self) -> usize;
+
+    /// Information about every agent
+    async fn list_agents(&self) -> Vec<AgentInfo>;
+
+    /// Whether an agent exists
+    async fn agent_exists(&self, agent_id: Uuid) -> bool;
+
+    /// What an agent's terminal shows
+    async fn screen_state(&self, agent_id: Uuid) -> ManagerResult<ScreenState> {
+        Err(ManagerError::AgentNotFound(agent_id))
+    }
+
+    /// Input modes an agent's terminal application has switched on
+    async fn input_modes(&self, _agent_id: Uuid) -> ManagerResult<InputModes> {
+        Ok(InputModes::default())
+    }
+
+    /// Start recording an agent's output, returning the recording's ID
+    async fn start_recording(&self, agent_id: Uuid) -> ManagerResult<String> {
+        Err(ManagerError::AgentNotFound(agent_id))
+    }
+
+    /// Stop recording an agent's output, returning the recording's ID and length
+    async fn stop_recording(&self, agent_id: Uuid) -> ManagerResult<(String, Duration)> {
+        Err(ManagerError::AgentNotFound(agent_id))
+    }
+
+    /// Agents belonging to a connection or session token
+    async fn agents_owned_by(&self, _owner: &str) -> Vec<Uuid> {
+        Vec::new()
+    }
+
+    /// Information about one agent
+    async fn get_agent_status(&self, agent_id: Uuid) -> ManagerResult<AgentInfo>;
+
+    /// Terminate an agent
+    async fn kill_agent(&self, agent_id: Uuid) -> ManagerResult<()>;
+
+    /// Stop an agent, giving it `grace` to exit before it is killed
+    async fn kill_agent_with_timeout(&self, agent_id: Uuid, _grace: Duration) -> ManagerResult<()> {
+        self.kill_agent(agent_id).await
+    }
+
+    /// Remove and then add tags, returning the agent's new tags
+    async fn tag_agent(
+        &self,
+        agent_id: Uuid,
+        _add: Vec<String>,
+        _remove: Vec<String>,
+    ) -> ManagerResult<Vec<String>> {
+        Err(ManagerError::AgentNotFound(agent_id))
+    }
+
+    /// Change an agent's display name
+    async fn rename_agent(&self, agent_id: Uuid, _name: Option<String>) -> ManagerResult<()> {
+        Err(ManagerError::AgentNotFound(agent_id))
+    }
+
+    /// Replace an agent's process with a new one, keeping its ID
+    async fn restart_agent(&self, agent_id: Uuid, _preserve_size: bool) -> ManagerResult<()> {
+        Err(ManagerError::AgentNotFound(agent_id))
+    }
+
+    /// Suspend an agent until it is resumed
+    async fn pause_agent(&self, _agent_id: Uuid) -> ManagerResult<()> {
+        Err(SessionError::PauseUnsupported.into())
+    }
+
+    /// Continue a paused agent
+    async fn resume_agent(&self, _agent_id: Uuid) -> ManagerResult<()> {
+        Err(SessionError::PauseUnsupported.into())
+    }
+
+    /// Send a signal to an agent's process
+    async fn signal_agent(&self, _agent_id: Uuid, _signal: i32) -> ManagerResult<()> {
+        Err(SessionError::SignalUnsupported.into())
+    }
+
+    /// Whether an agent's terminal echoes input and is in raw mode
+    async fn echo_state(&self, _agent_id: Uuid) -> ManagerResult<EchoState> {
+        Err(SessionError::EchoUnsupported.into())
+    }
+
+    /// Switch an agent's terminal to raw mode or back to cooked mode
+    async fn set_raw_mode(&self, _agent_id: Uuid, _raw: bool) -> ManagerResult<EchoState> {
+        Err(SessionError::EchoUnsupported.into())
+    }
+
+    /// Write text to an agent
+    async fn send_input(&self, agent_id: Uuid, input: &str) -> ManagerResult<()>;
+
+    /// Write raw bytes to an agent
+    async fn send_bytes(&self, agent_id: Uuid, input: &[u8]) -> ManagerResult<()>;
+
+    /// Resize an agent's terminal
+    async fn resize_agent(&self, agent_id: Uuid, cols: u16, rows: u16) -> ManagerResult<()>;
+
+    /// Record input in an agent's history
+    async fn record_input(&self, _agent_id: Uuid, _input: &str) -> ManagerResult<()> {
+        Ok(())
+    }
+
+    /// An agent's most recent inputs, oldest first
+    async fn input_history(&self, _agent_id: Uuid, _limit: usize) -> ManagerResult<Vec<HistoryEntry>> {
+        Ok(Vec::new())
+    }
+
+    /// Expand one of an agent's input macros
+    async fn expand_macro(&self, _agent_id: Uuid, name: &str) -> ManagerResult<String> {
+        Err(ManagerError::MacroNotFound(name.to_string()))
+    }
+
+    /// Key sequence bound to an action for an agent
+    async fn key_sequence(&self, _agent_id: Uuid, action: &str) -> ManagerResult<String> {
+        Err(ManagerError::KeyNotBound(action.to_string()))
+    }
+
+    /// Answer an agent's pending confirmation prompt
+    async fn answer_confirmation(&self, agent_id: Uuid, _option: usize) -> ManagerResult<()> {
+        Err(ManagerError::NoPendingConfirmation(agent_id))
+    }
+
+    /// Approve or deny the tool an agent is asking permission to run
+    async fn answer_approval(
+        &self,
+        agent_id: Uuid,
+        _decision: ApprovalDecision,
+    ) -> ManagerResult<()> {
+        Err(ManagerError::NoPendingApproval(agent_id))
+    }
+}
+
+#[async_trait]
+impl AgentSpawner for AgentManager {
+    async fn spawn_agent(&self, config: SpawnConfig) -> ManagerResult<Uuid> {
+        AgentManager::spawn_agent(self, config).await
+    }
+
+    async fn reattach_persistent(&self) -> usize {
+        AgentManager::reattach_persistent(self).await
+    }
+}
+
+#[async_trait]
+impl AgentBackend for AgentManager {
+    fn subscribe(&self) -> broadcast::Receiver<AgentEvent> {
+        AgentManager::subscribe(self)
+    }
+
+    async fn session_count(&self) -> usize {
+        AgentManager::session_count(self).await
+    }
+
+    async fn list_agents(&self) -> Vec<AgentInfo> {
+        AgentManager::list_agents(self).await
+    }
+
+    async fn agent_exists(&self, agent_id: Uuid) -> bool {
+        AgentManager::agent_exists(self, agent_id).await
+    }
+
+    async fn screen_state(&self, agent_id: Uuid) -> ManagerResult<ScreenState> {
+        AgentManager::screen_state(self, agent_id).await
+    }
+
+    async fn input_modes(&self, agent_id: Uuid) -> ManagerResult<InputModes> {
+        AgentManager::input_modes(self, agent_id).await
+    }
+
+    async fn start_recording(&self, agent_id: Uuid) -> ManagerResult<String> {
+        AgentManager::start_recording(self, agent_id).await
+    }
+
+    async fn stop_recording(&self, agent_id: Uuid) -> ManagerResult<(String, Duration)> {
+        AgentManager::stop_recording(self, agent_id).await
+    }
+
+    async fn agents_owned_by(&self, owner: &str) -> Vec<Uuid> {
+        AgentManager::agents_owned_by(self, owner).await
+    }
+
+    async fn get_agent_status(&self, agent_id: Uuid) -> ManagerResult<AgentInfo> {
+        AgentManager::get_agent_status(self, agent_id).await
+    }
+
+    async fn kill_agent(&self, agent_id: Uuid) -> ManagerResult<()> {
+        AgentManager::kill_agent(self, agent_id).await
+    }
+
+    async fn kill_agent_with_timeout(&self, agent_id: Uuid, grace: Duration) -> ManagerResult<()> {
+        AgentManager::kill_agent_with_timeout(self, agent_id, grace).await
+    }
+
+    async fn tag_agent(
+        &self,
+        agent_id: Uuid,
+        add: Vec<String>,
+        remove: Vec<String>,
+    ) -> ManagerResult<Vec<String>> {
+        AgentManager::tag_agent(self, agent_id, add, remove).await
+    }
+
+    async fn rename_agent(&self, agent_id: Uuid, name: Option<String>) -> ManagerResult<()> {
+        AgentManager::rename_agent(self, agent_id, name).await
+    }
+
+    async fn restart_agent(&self, agent_id: Uuid, preserve_size: bool) -> ManagerResult<()> {
+        AgentManager::restart_agent(self, agent_id, preserve_size).await
+    }
+
+    async fn pause_agent(&self, agent_id: Uuid) -> ManagerResult<()> {
+        AgentManager::pause_agent(self, agent_id).await
+    }
+
+    async fn resume_agent(&self, agent_id: Uuid) -> ManagerResult<()> {
+        AgentManager::resume_agent(self, agent_id).await
+    }
+
+    async fn signal_agent(&self, agent_id: Uuid, signal: i32) -> ManagerResult<()> {
+        AgentManager::signal_agent(self, agent_id, signal).await
+    }
+
+    async fn echo_state(&self, agent_id: Uuid) -> ManagerResult<EchoState> {
+        AgentManager::echo_state(self, agent_id).await
+    }
+
+    async fn set_raw_mode(&self, agent_id: Uuid, raw: bool) -> ManagerResult<EchoState> {
+        AgentManager::set_raw_mode(self, agent_id, raw).await
+    }
+
+    async fn send_input(&self, agent_id: Uuid, input: &str) -> ManagerResult<()> {
+        AgentManager::send_input(self, agent_id, input).await
+    }
+
+    async fn send_bytes(&self, agent_id: Uuid, input: &[u8]) -> ManagerResult<()> {
+        AgentManager::send_bytes(self, agent_id, input).await
+    }
+
+    async fn resize_agent(&self, agent_id: Uuid, cols: u16, rows: u16) -> ManagerResult<()> {
+        AgentManager::resize_agent(self, agent_id, cols, rows).await
+    }
+
+    async fn record_input(&self, agent_id: Uuid, input: &str) -> ManagerResult<()> {
+        AgentManager::record_input(self, agent_id, input).await
+    }
+
+    async fn input_history(&self, agent_id: Uuid, limit: usize) -> ManagerResult<Vec<HistoryEntry>> {
+        AgentManager::input_history(self, agent_id, limit).await
+    }
+
+    async fn expand_macro(&self, agent_id: Uuid, name: &str) -> ManagerResult<String> {
+        AgentManager::expand_macro(self, agent_id, name).await
+    }
+
+    async fn key_sequence(&self, agent_id: Uuid, action: &str) -> ManagerResult<String> {
+        AgentManager::key_sequence(self, agent_id, action).await
+    }
+
+    async fn answer_confirmation(&self, agent_id: Uuid, option: usize) -> ManagerResult<()> {
+        AgentManager::answer_confirmation(self, agent_id, option).await
+    }
+
+    async fn answer_approval(
+        &self,
+        agent_id: Uuid,
+        decision: ApprovalDecision,
+    ) -> ManagerResult<()> {
+        AgentManager::answer_approval(self, agent_id, decision).await
+    }
+}
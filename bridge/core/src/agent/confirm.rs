@@ -0,0 +1,295 @@
+//! Confirmation prompt detection
+//!
+//! Recognizes yes/no questions and Claude's numbered permission menus in an
+//! agent's output, so clients can show them as dialogs and answer with a
+//! `confirmation_reply` instead of typing into the terminal.
+//!
+//! Claude's tool-use permission prompts are recognized by the title of the
+//! box they are drawn in ("Bash command", "Edit file", ...) and also carry
+//! the tool and what it is about to do, so clients can offer approve, always
+//! approve and deny buttons without knowing which menu option is which.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use super::expect::{push_window, OUTPUT_WINDOW};
+
+/// `Question? (y/n)` and its variants
+static YES_NO: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(.*\S)\s*[(\[](?i:y|yes)/(?i:n|no)[)\]]\s*:?$").expect("yes/no pattern")
+});
+
+/// A numbered menu entry, optionally marked as selected: `❯ 1. Yes`
+static MENU_OPTION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:[❯>]\s*)?(\d)\.\s+(.+)$").expect("menu option pattern"));
+
+/// Titles of the boxes Claude draws its tool permission prompts in
+const TOOL_TITLES: &[&str] = &[
+    "Bash command",
+    "Edit file",
+    "Create file",
+    "Write file",
+    "Read file",
+    "Fetch",
+    "Web search",
+    "Notebook edit",
+    "Tool use",
+];
+
+/// How far above its question a permission prompt's title is looked for
+const MAX_DETAIL_LINES: usize = 40;
+
+/// One answer to a confirmation prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfirmOption {
+    /// Text shown to the user
+    pub label: String,
+    /// Keys written to the agent when this option is chosen
+    pub keys: String,
+}
+
+/// A detected prompt waiting for an answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Confirmation {
+    /// The question asked
+    pub question: String,
+    /// Possible answers, in display order
+    pub options: Vec<ConfirmOption>,
+    /// The tool asking, when this is a tool permission prompt
+    pub approval: Option<Approval>,
+}
+
+/// A tool asking permission before it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Approval {
+    /// Title of the prompt, naming the tool: "Bash command", "Edit file", ...
+    pub tool: String,
+    /// What the tool is about to do, such as the command line or file path
+    pub detail: String,
+}
+
+/// An answer to a tool permission prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalDecision {
+    /// Let the tool run this once
+    Approve,
+    /// Let the tool run, and not ask again for similar uses
+    AlwaysApprove,
+    /// Stop the tool
+    Deny,
+}
+
+impl Confirmation {
+    /// Labels of the options
+    pub fn labels(&self) -> Vec<String> {
+        self.options.iter().map(|o| o.label.clone()).collect()
+    }
+
+    /// Keys that answer the prompt with a decision, if it offers it
+    ///
+    /// Prompts without a "No" option are denied with Esc, which cancels
+    /// Claude's menus.
+    pub fn approval_keys(&self, decision: ApprovalDecision) -> Option<String> {
+        let option = |matches: fn(&str) -> bool| {
+            self.options
+                .iter()
+                .find(|option| matches(&option.label.to_lowercase()))
+                .map(|option| option.keys.clone())
+        };
+        match decision {
+            ApprovalDecision::Approve => {
+                option(|label| label.starts_with("yes") && !label.starts_with("yes, and"))
+            }
+            ApprovalDecision::AlwaysApprove => option(|label| label.starts_with("yes, and")),
+            ApprovalDecision::Deny => {
+                option(|label| label.starts_with("no")).or_else(|| Some("\x1b".to_string()))
+            }
+        }
+    }
+}
+
+/// Tracks an agent's output and its unanswered prompt
+#[derive(Debug, Default)]
+pub struct PromptDetector {
+    window: String,
+    pending: Option<Confirmation>,
+}
+
+impl PromptDetector {
+    /// Feed output, returning a newly detected prompt
+    ///
+    /// A prompt that is redrawn without changing is reported once.
+    pub fn feed(&mut self, output: &[u8]) -> Option<Confirmation> {
+        push_window(&mut self.window, output, OUTPUT_WINDOW);
+        let confirmation = detect(&self.window)?;
+        if self.pending.as_ref() == Some(&confirmation) {
+            return None;
+        }
+        self.pending = Some(confirmation.clone());
+        Some(confirmation)
+    }
+
+    /// The prompt currently waiting for an answer
+    pub fn pending(&self) -> Option<&Confirmation> {
+        self.pending.as_ref()
+    }
+
+    /// Take the pending prompt once it is answered, forgetting the output
+    /// that contained it
+    pub fn take_pending(&mut self) -> Option<Confirmation> {
+        self.window.clear();
+        self.pending.take()
+    }
+
+    /// Forget the pending prompt and output, e.g. after input was sent
+    pub fn reset(&mut self) {
+        self.window.clear();
+        self.pending = None;
+    }
+}
+
+/// Trim whitespace and the box drawing characters Claude frames prompts with
+fn clean_line(line: &str) -> &str {
+    line.trim_matches(|c: char| c.is_whitespace() || ('\u{2500}'..='\u{257f}').contains(&c))
+}
+
+/// Find a prompt at the end of the output
+fn detect(text: &str) -> Option<Confirmation> {
+    let lines: Vec<&str> = text
+        .split(['\n', '\r'])
+        .map(clean_line)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    detect_menu(&lines).or_else(|| detect_yes_no(lines.last()?))
+}
+
+/// A question followed by options numbered from 1, e.g. Claude's permission prompts
+fn detect_menu(lines: &[&str]) -> Option<Confirmation> {
+    let question_at = lines.iter().rposition(|line| line.ends_with('?'))?;
+    let mut options = Vec::new();
+    for line in &lines[question_at + 1..] {
+        let Some(captures) = MENU_OPTION.captures(line) else {
+            // Footer lines like "Esc to cancel" may follow the options
+            if options.is_empty() {
+                return None;
+            }
+            break;
+        };
+        let number = &captures[1];
+        if number != (options.len() + 1).to_string() {
+            break;
+        }
+        options.push(ConfirmOption {
+            label: captures[2].trim().to_string(),
+            keys: number.to_string(),
+        });
+    }
+
+    (options.len() >= 2).then(|| Confirmation {
+        question: lines[question_at].to_string(),
+        options,
+        approval: detect_approval(&lines[..question_at]),
+    })
+}
+
+/// The tool title and detail lines above a permission prompt's question
+fn detect_approval(lines: &[&str]) -> Option<Approval> {
+    let start = lines.len().saturating_sub(MAX_DETAIL_LINES);
+    let title_at = start + lines[start..].iter().rposition(|line| TOOL_TITLES.contains(line))?;
+    Some(Approval {
+        tool: lines[title_at].to_string(),
+        detail: lines[title_at + 1..].join("\n"),
+    })
+}
+
+/// A `(y/n)` question on the last line
+fn detect_yes_no(line: &str) -> Option<Confirmation> {
+    let captures = YES_NO.captures(line)?;
+    let answer = |label: &str, keys: &str| ConfirmOption {
+        label: label.to_string(),
+        keys: keys.to_string(),
+    };
+    Some(Confirmation {
+        question: captures[1].to_string(),
+        options: vec![answer("Yes", "y\n"), answer("No", "n\n")],
+        approval: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_yes_no() {
+        let mut detector = PromptDetector::default();
+        let confirmation = detector
+            .feed(b"Copying files\r\n\x1b[1mOverwrite config.toml?\x1b[0m [Y/n] ")
+            .unwrap();
+        assert_eq!(confirmation.question, "Overwrite config.toml?");
+        assert_eq!(confirmation.labels(), vec!["Yes", "No"]);
+        assert_eq!(confirmation.options[0].keys, "y\n");
+        // Redrawing the same prompt is not reported again
+        assert!(detector.feed(b"\r\nOverwrite config.toml? [Y/n] ").is_none());
+    }
+
+    #[test]
+    fn test_detect_claude_permission_menu() {
+        let output = "╭──────────────╮\r\n\
+            │ Bash command │\r\n\
+            │   rm -rf target │\r\n\
+            │ Do you want to proceed? │\r\n\
+            │ \x1b[36m❯ 1. Yes\x1b[0m │\r\n\
+            │   2. Yes, and don't ask again for rm commands │\r\n\
+            │   3. No, and tell Claude what to do differently (esc) │\r\n\
+            ╰──────────────╯\r\n";
+        let confirmation = detect(&crate::agent::expect::strip_escape_sequences(output)).unwrap();
+        assert_eq!(confirmation.question, "Do you want to proceed?");
+        assert_eq!(confirmation.options.len(), 3);
+        assert_eq!(confirmation.options[0].label, "Yes");
+        assert_eq!(confirmation.options[2].keys, "3");
+
+        let approval = confirmation.approval.as_ref().unwrap();
+        assert_eq!(approval.tool, "Bash command");
+        assert_eq!(approval.detail, "rm -rf target");
+        let keys = |decision| confirmation.approval_keys(decision);
+        assert_eq!(keys(ApprovalDecision::Approve).as_deref(), Some("1"));
+        assert_eq!(keys(ApprovalDecision::AlwaysApprove).as_deref(), Some("2"));
+        assert_eq!(keys(ApprovalDecision::Deny).as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn test_approval_without_always_or_no() {
+        let output = "Edit file\nsrc/main.rs\n+ fn main() {}\n\
+            Do you want to make this edit to main.rs?\n1. Yes\n2. Skip\n";
+        let confirmation = detect(output).unwrap();
+        let approval = confirmation.approval.as_ref().unwrap();
+        assert_eq!(approval.tool, "Edit file");
+        assert_eq!(approval.detail, "src/main.rs\n+ fn main() {}");
+        assert!(confirmation.approval_keys(ApprovalDecision::AlwaysApprove).is_none());
+        assert_eq!(confirmation.approval_keys(ApprovalDecision::Deny).as_deref(), Some("\x1b"));
+
+        // Menus without a tool title are plain confirmations
+        let plain = detect("Pick a color?\n1. Red\n2. Blue").unwrap();
+        assert!(plain.approval.is_none());
+    }
+
+    #[test]
+    fn test_no_prompt() {
+        assert!(detect("What should I do next?\nI'll run the tests.").is_none());
+        assert!(detect("Pick one?\n1. only option").is_none());
+    }
+
+    #[test]
+    fn test_take_pending() {
+        let mut detector = PromptDetector::default();
+        detector.feed(b"Continue? (y/n)");
+        assert!(detector.pending().is_some());
+        assert!(detector.take_pending().is_some());
+        assert!(detector.pending().is_none());
+        // The answered prompt is gone from the output window
+        assert!(detector.feed(b"\r\n").is_none());
+    }
+}
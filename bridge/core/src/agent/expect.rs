@@ -0,0 +1,182 @@
+//! Expect-style prompt answering
+//!
+//! Watches an agent's output for patterns from its preset's expect rules and
+//! answers them, so unattended runs don't stall on questions like
+//! "Overwrite? (y/n)" that no client is looking at. Output is matched with
+//! escape sequences removed, against a bounded window of recent text.
+
+use regex::Regex;
+
+use crate::config::ExpectRule;
+
+/// Amount of recent output text kept for matching
+pub(super) const OUTPUT_WINDOW: usize = 4096;
+
+/// A compiled expect rule and its remaining uses
+#[derive(Debug)]
+struct ActiveRule {
+    pattern: Regex,
+    response: String,
+    remaining: Option<u32>,
+}
+
+/// Matches agent output against expect rules
+#[derive(Debug, Default)]
+pub struct Expecter {
+    rules: Vec<ActiveRule>,
+    window: String,
+}
+
+impl Expecter {
+    /// Compile a set of rules
+    pub fn new(rules: &[ExpectRule]) -> Result<Self, regex::Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Ok(ActiveRule {
+                    pattern: Regex::new(&rule.pattern)?,
+                    response: if rule.submit {
+                        format!("{}\n", rule.response)
+                    } else {
+                        rule.response.clone()
+                    },
+                    remaining: rule.max_uses,
+                })
+            })
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Self {
+            rules,
+            window: String::new(),
+        })
+    }
+
+    /// Whether any rule can still fire
+    pub fn is_active(&self) -> bool {
+        self.rules.iter().any(|rule| rule.remaining != Some(0))
+    }
+
+    /// Feed output, returning the response to send if a rule matched
+    ///
+    /// The window is cleared after a match so the same prompt is answered
+    /// only once.
+    pub fn feed(&mut self, output: &[u8]) -> Option<String> {
+        if !self.is_active() {
+            return None;
+        }
+
+        push_window(&mut self.window, output, OUTPUT_WINDOW);
+
+        let rule = self
+            .rules
+            .iter_mut()
+            .filter(|rule| rule.remaining != Some(0))
+            .find(|rule| rule.pattern.is_match(&self.window))?;
+        if let Some(ref mut remaining) = rule.remaining {
+            *remaining -= 1;
+        }
+        self.window.clear();
+        Some(rule.response.clone())
+    }
+}
+
+/// Append output to a window of recent text, keeping at most `max` bytes;
+/// returns the number of bytes dropped from the front
+pub fn push_window(window: &mut String, output: &[u8], max: usize) -> usize {
+    window.push_str(&strip_escape_sequences(&String::from_utf8_lossy(output)));
+    if window.len() <= max {
+        return 0;
+    }
+    let mut start = window.len() - max;
+    while !window.is_char_boundary(start) {
+        start += 1;
+    }
+    window.drain(..start);
+    start
+}
+
+/// Remove CSI, OSC and other escape sequences from terminal output
+pub(super) fn strip_escape_sequences(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters up to a final byte in @..~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // String sequences run to BEL or ST
+            Some(']' | 'P' | '_' | '^' | 'X') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // nF sequences (e.g. charset selection): intermediates, then a final byte
+            Some(' '..='/') => {
+                for c in chars.by_ref() {
+                    if !(' '..='/').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, response: &str, max_uses: Option<u32>) -> ExpectRule {
+        ExpectRule {
+            pattern: pattern.to_string(),
+            response: response.to_string(),
+            submit: true,
+            max_uses,
+        }
+    }
+
+    #[test]
+    fn test_answers_prompt_split_across_output() {
+        let mut expecter = Expecter::new(&[rule(r"Overwrite\? \(y/n\)", "y", None)]).unwrap();
+        assert_eq!(expecter.feed(b"Writing file...\r\n\x1b[1mOverwr"), None);
+        assert_eq!(expecter.feed(b"ite?\x1b[0m (y/n) "), Some("y\n".to_string()));
+        // The answered prompt is not matched again
+        assert_eq!(expecter.feed(b"\r\n"), None);
+        assert_eq!(expecter.feed(b"Overwrite? (y/n)"), Some("y\n".to_string()));
+    }
+
+    #[test]
+    fn test_max_uses() {
+        let mut expecter = Expecter::new(&[rule("Continue\\?", "yes", Some(1))]).unwrap();
+        assert!(expecter.feed(b"Continue?").is_some());
+        assert!(!expecter.is_active());
+        assert_eq!(expecter.feed(b"Continue?"), None);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(Expecter::new(&[rule("(", "y", None)]).is_err());
+    }
+
+    #[test]
+    fn test_strip_escape_sequences() {
+        assert_eq!(
+            strip_escape_sequences("\x1b]0;title\x07\x1b[2K\x1b[32mok\x1b[0m\x1b(B"),
+            "ok"
+        );
+    }
+}
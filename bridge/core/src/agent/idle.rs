@@ -0,0 +1,122 @@
+//! Idle agent timeout
+//!
+//! Agents left running with no input and no output for a configured time are
+//! stopped, so forgotten `claude` processes don't pile up on a long-running
+//! bridge. Clients are warned shortly before, and any activity in between
+//! keeps the agent alive.
+
+use std::time::Duration;
+
+/// How long before an idle agent is stopped clients are warned about it
+pub const IDLE_WARNING: Duration = Duration::from_secs(60);
+
+/// Most time between two checks of an agent's activity
+const MAX_CHECK_PERIOD: Duration = Duration::from_secs(5);
+
+/// Least time between two checks of an agent's activity
+const MIN_CHECK_PERIOD: Duration = Duration::from_millis(10);
+
+/// What to do about an agent that has been idle for a while
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Warn clients that the agent will be stopped after `stop_in` more
+    Warn {
+        /// Time left before the agent is stopped
+        stop_in: Duration,
+    },
+    /// Stop the agent
+    Stop,
+}
+
+/// Tracks one agent against its idle timeout
+#[derive(Debug, Clone)]
+pub struct IdleWatch {
+    timeout: Duration,
+    /// Idle time after which the warning is sent
+    warn_after: Duration,
+    warned: bool,
+}
+
+impl IdleWatch {
+    /// Watch for `timeout` without activity
+    ///
+    /// The warning comes [`IDLE_WARNING`] before the timeout, or halfway
+    /// through timeouts shorter than twice that.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            warn_after: timeout - IDLE_WARNING.min(timeout / 2),
+            warned: false,
+        }
+    }
+
+    /// How often the agent's activity should be checked
+    pub fn period(&self) -> Duration {
+        ((self.timeout - self.warn_after) / 4).clamp(MIN_CHECK_PERIOD, MAX_CHECK_PERIOD)
+    }
+
+    /// Check the time since the agent's last activity, returning what to do
+    /// about it
+    ///
+    /// The warning is given once per idle stretch; activity re-arms it.
+    pub fn check(&mut self, idle: Duration) -> Option<IdleAction> {
+        if idle >= self.timeout {
+            return Some(IdleAction::Stop);
+        }
+        if idle < self.warn_after {
+            self.warned = false;
+            return None;
+        }
+        if self.warned {
+            return None;
+        }
+        self.warned = true;
+        Some(IdleAction::Warn {
+            stop_in: self.timeout - idle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warns_then_stops() {
+        let mut watch = IdleWatch::new(Duration::from_secs(600));
+        assert_eq!(watch.period(), MAX_CHECK_PERIOD);
+        assert_eq!(watch.check(Duration::from_secs(500)), None);
+        assert_eq!(
+            watch.check(Duration::from_secs(545)),
+            Some(IdleAction::Warn {
+                stop_in: Duration::from_secs(55)
+            })
+        );
+        // Warned once per idle stretch
+        assert_eq!(watch.check(Duration::from_secs(550)), None);
+        assert_eq!(
+            watch.check(Duration::from_secs(600)),
+            Some(IdleAction::Stop)
+        );
+
+        // Activity re-arms the warning
+        watch.check(Duration::from_secs(1));
+        assert!(matches!(
+            watch.check(Duration::from_secs(590)),
+            Some(IdleAction::Warn { .. })
+        ));
+    }
+
+    #[test]
+    fn test_short_timeouts() {
+        let mut watch = IdleWatch::new(Duration::from_millis(400));
+        assert_eq!(watch.period(), Duration::from_millis(50));
+        assert_eq!(watch.check(Duration::from_millis(100)), None);
+        assert_eq!(
+            watch.check(Duration::from_millis(200)),
+            Some(IdleAction::Warn {
+                stop_in: Duration::from_millis(200)
+            })
+        );
+    }
+}
@@ -0,0 +1,35 @@
+//! Agent session management module
+//!
+//! Handles spawning and managing Claude Code agent sessions with PTY support.
+
+mod backend;
+mod confirm;
+mod expect;
+mod history;
+mod idle;
+mod manager;
+mod phase;
+mod ready;
+mod recording;
+mod session;
+mod shell;
+mod stream_json;
+mod transcript;
+mod trigger;
+mod utf8;
+
+pub use backend::*;
+pub use confirm::*;
+pub use expect::*;
+pub use history::*;
+pub use idle::*;
+pub use manager::*;
+pub use phase::*;
+pub use ready::*;
+pub use recording::*;
+pub use session::*;
+pub use shell::*;
+pub use stream_json::*;
+pub use transcript::*;
+pub use trigger::*;
+pub use utf8::*;
@@ -0,0 +1,76 @@
+//! Activity phase detection
+//!
+//! Claude Code announces each tool call on its own line, e.g.
+//! `⏺ Bash(cargo test)`. The most recent tool seen while the agent is working
+//! is reported as its activity phase, so clients can show what each agent is
+//! doing without rendering its terminal.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use super::expect::push_window;
+
+/// Output kept for matching; tool lines are short
+const PHASE_WINDOW: usize = 1024;
+
+/// A tool call line: `⏺ Edit(src/main.rs)`
+static TOOL_CALL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[⏺●]\s*([A-Za-z][\w:.-]*)\(").expect("tool call pattern")
+});
+
+/// Tracks the tool an agent is running
+#[derive(Debug, Default)]
+pub struct PhaseDetector {
+    window: String,
+    phase: Option<String>,
+}
+
+impl PhaseDetector {
+    /// Feed output, returning the new phase if it changed
+    pub fn feed(&mut self, output: &[u8]) -> Option<&str> {
+        push_window(&mut self.window, output, PHASE_WINDOW);
+        let tool = self
+            .window
+            .rsplit(['\n', '\r'])
+            .find_map(|line| TOOL_CALL.captures(line.trim()))?;
+        if self.phase.as_deref() == Some(&tool[1]) {
+            return None;
+        }
+        self.phase = Some(tool[1].to_string());
+        self.phase.as_deref()
+    }
+
+    /// The current phase, if the agent is known to be running a tool
+    pub fn phase(&self) -> Option<&str> {
+        self.phase.as_deref()
+    }
+
+    /// Forget the phase, e.g. once the agent has gone quiet
+    pub fn reset(&mut self) {
+        self.window.clear();
+        self.phase = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_tool_calls() {
+        let mut detector = PhaseDetector::default();
+        assert_eq!(detector.feed(b"I'll run the tests first.\r\n"), None);
+        assert_eq!(
+            detector.feed(b"\x1b[32m\xe2\x8f\xba\x1b[0m \x1b[1mBash\x1b[0m(cargo test)\r\n"),
+            Some("Bash")
+        );
+        // Tool output and redraws keep the phase
+        assert_eq!(detector.feed(b"  \xe2\x8e\xbf  running 12 tests\r\n"), None);
+        assert_eq!(detector.phase(), Some("Bash"));
+
+        assert_eq!(detector.feed("● Edit(src/lib.rs)\n".as_bytes()), Some("Edit"));
+        detector.reset();
+        assert_eq!(detector.phase(), None);
+    }
+}
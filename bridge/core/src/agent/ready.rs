@@ -0,0 +1,150 @@
+//! Readiness detection and initial prompt delivery
+//!
+//! A preset's ready pattern matches the prompt the agent shows once it is
+//! ready for its first command. When the agent's output (escape sequences
+//! removed) matches it, the agent enters the `ready` state, so clients know
+//! when they can safely send that command instead of guessing with timers.
+//!
+//! The preset's initial prompt is typed into the agent at that point.
+//! Written too early it lands in the shell before `claude` has taken over
+//! the terminal, or is swallowed while the TUI starts. If the pattern
+//! doesn't match within the ready timeout the prompt is sent anyway; without
+//! a pattern it goes out after a short fixed delay.
+
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use super::expect::{push_window, OUTPUT_WINDOW};
+
+/// Default time an initial prompt waits for the readiness pattern
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before an initial prompt is sent when there is no readiness pattern
+pub const DEFAULT_PROMPT_DELAY: Duration = Duration::from_millis(500);
+
+/// An initial prompt that was written to an agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptDelivery {
+    /// Time from the agent's start until the prompt was written
+    pub waited: Duration,
+    /// Whether the readiness pattern never matched and the prompt was sent
+    /// when the timeout passed
+    pub timed_out: bool,
+}
+
+/// Watches an agent's output for the prompt it shows once ready
+#[derive(Debug)]
+pub struct ReadyDetector {
+    pattern: Regex,
+    window: String,
+}
+
+impl ReadyDetector {
+    /// Compile a ready pattern
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            window: String::new(),
+        })
+    }
+
+    /// Feed output, returning whether the agent is now ready
+    pub fn feed(&mut self, output: &[u8]) -> bool {
+        push_window(&mut self.window, output, OUTPUT_WINDOW);
+        self.pattern.is_match(&self.window)
+    }
+}
+
+/// Holds an initial prompt back until the agent is ready for it
+#[derive(Debug)]
+pub struct PromptGate {
+    prompt: String,
+    /// Whether the prompt waits for a ready pattern rather than a fixed delay
+    awaits_ready: bool,
+    started: Instant,
+    deadline: Instant,
+}
+
+impl PromptGate {
+    /// Wait for the agent to become ready for at most `timeout` from `now`,
+    /// or, when there is no ready pattern to wait for, a short delay
+    pub fn new(
+        prompt: impl Into<String>,
+        awaits_ready: bool,
+        timeout: Duration,
+        now: Instant,
+    ) -> Self {
+        let wait = if awaits_ready {
+            timeout
+        } else {
+            DEFAULT_PROMPT_DELAY
+        };
+        Self {
+            prompt: prompt.into(),
+            awaits_ready,
+            started: now,
+            deadline: now + wait,
+        }
+    }
+
+    /// Whether the prompt is to be sent without waiting any longer
+    pub fn is_due(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+
+    /// When the prompt is sent even if the agent hasn't shown it is ready
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// The input to write, followed by a newline, and how it was delivered
+    pub fn open(self, ready: bool, now: Instant) -> (String, PromptDelivery) {
+        let delivery = PromptDelivery {
+            waited: now.saturating_duration_since(self.started),
+            timed_out: self.awaits_ready && !ready,
+        };
+        (format!("{}\n", self.prompt), delivery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_ready_prompt() {
+        let mut detector = ReadyDetector::new(r"(?m)^> $").unwrap();
+        assert!(!detector.feed(b"$ claude\r\n"));
+        // Matched with escape sequences removed
+        assert!(detector.feed(b"\x1b[1m> \x1b[0m"));
+        assert!(ReadyDetector::new("(").is_err());
+    }
+
+    #[test]
+    fn test_waits_for_ready_agent() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(10);
+        let gate = PromptGate::new("fix the build", true, timeout, start);
+        assert!(!gate.is_due(start + Duration::from_secs(1)));
+
+        let (input, delivery) = gate.open(true, start + Duration::from_secs(2));
+        assert_eq!(input, "fix the build\n");
+        assert_eq!(delivery.waited, Duration::from_secs(2));
+        assert!(!delivery.timed_out);
+    }
+
+    #[test]
+    fn test_sent_anyway_after_timeout() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(10);
+        let gate = PromptGate::new("hello", true, timeout, start);
+        assert!(gate.is_due(start + timeout));
+        assert!(gate.open(false, start + timeout).1.timed_out);
+
+        // Without a pattern the prompt only waits a moment and isn't late
+        let gate = PromptGate::new("hello", false, timeout, start);
+        assert!(gate.is_due(start + DEFAULT_PROMPT_DELAY));
+        assert!(!gate.open(false, start + DEFAULT_PROMPT_DELAY).1.timed_out);
+    }
+}
@@ -0,0 +1,309 @@
+//! Agent output recordings
+//!
+//! Recordings are asciicast v2 files (as written by `asciinema rec`), kept in
+//! the project's `.hoc/recordings` directory so a run can be reviewed after the
+//! agent is gone, by the bridge or with any asciinema player. A recording holds
+//! the output clients were sent, so redacted secrets stay redacted.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::CONFIG_DIR;
+use crate::protocol::RecordingInfo;
+
+/// Directory under the project's `.hoc` directory holding recordings
+const RECORDINGS_DIR: &str = "recordings";
+
+/// File extension of asciicast files
+const CAST_EXTENSION: &str = "cast";
+
+/// Directory holding a project's recordings
+pub fn recordings_dir(project_path: &Path) -> PathBuf {
+    project_path.join(CONFIG_DIR).join(RECORDINGS_DIR)
+}
+
+/// Path of a recording, `None` if the ID could not name one
+pub fn recording_path(project_path: &Path, recording_id: &str) -> Option<PathBuf> {
+    let valid = !recording_id.is_empty()
+        && recording_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then(|| {
+        recordings_dir(project_path).join(format!("{}.{}", recording_id, CAST_EXTENSION))
+    })
+}
+
+/// First line of an asciicast file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CastHeader {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Start of the recording, in seconds since the Unix epoch
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Something that happened during a recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CastEvent {
+    /// The agent printed `data`
+    Output { at: Duration, data: String },
+    /// The terminal was resized
+    Resize { at: Duration, cols: u16, rows: u16 },
+}
+
+impl CastEvent {
+    /// Time since the start of the recording
+    pub fn at(&self) -> Duration {
+        match self {
+            CastEvent::Output { at, .. } | CastEvent::Resize { at, .. } => *at,
+        }
+    }
+}
+
+/// A recording read back from disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cast {
+    pub header: CastHeader,
+    pub events: Vec<CastEvent>,
+}
+
+impl Cast {
+    /// Read an asciicast v2 file, skipping events this reader doesn't know
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: CastHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).map_err(invalid_data)?,
+            None => return Err(invalid_data("empty recording")),
+        };
+        if header.version != 2 {
+            return Err(invalid_data(format!(
+                "unsupported asciicast version {}",
+                header.version
+            )));
+        }
+
+        let mut events = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (time, code, data): (f64, String, String) =
+                serde_json::from_str(&line).map_err(invalid_data)?;
+            let at = Duration::from_secs_f64(time.max(0.0));
+            match code.as_str() {
+                "o" => events.push(CastEvent::Output { at, data }),
+                "r" => {
+                    if let Some((cols, rows)) = parse_size(&data) {
+                        events.push(CastEvent::Resize { at, cols, rows });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { header, events })
+    }
+}
+
+/// Writes an agent's output to a new recording as it is produced
+#[derive(Debug)]
+pub struct Recorder {
+    id: String,
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Start a recording of an agent whose terminal is `cols` x `rows`
+    pub fn create(project_path: &Path, agent_id: Uuid, cols: u16, rows: u16) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let id = format!("{}-{}", timestamp, &agent_id.simple().to_string()[..8]);
+        let dir = recordings_dir(project_path);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.{}", id, CAST_EXTENSION));
+
+        let mut file = BufWriter::new(File::create_new(path)?);
+        let header = CastHeader {
+            version: 2,
+            width: cols,
+            height: rows,
+            timestamp,
+            title: Some(format!("Agent {}", agent_id)),
+        };
+        serde_json::to_writer(&mut file, &header)?;
+        file.write_all(b"\n")?;
+        Ok(Self {
+            id,
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    /// ID clients name the recording by
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Time since the recording started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Record output (invalid UTF-8 is replaced, as asciicast holds text)
+    pub fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        self.event("o", &String::from_utf8_lossy(data))
+    }
+
+    /// Record a terminal resize
+    pub fn resize(&mut self, cols: u16, rows: u16) -> io::Result<()> {
+        self.event("r", &format!("{}x{}", cols, rows))
+    }
+
+    /// Write out everything recorded
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> io::Result<()> {
+        let time = self.started.elapsed().as_secs_f64();
+        serde_json::to_writer(&mut self.file, &(time, code, data))?;
+        self.file.write_all(b"\n")
+    }
+}
+
+/// Recordings of a project, oldest first
+pub fn list_recordings(project_path: &Path) -> io::Result<Vec<RecordingInfo>> {
+    let entries = match std::fs::read_dir(recordings_dir(project_path)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut recordings = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(CAST_EXTENSION) {
+            continue;
+        }
+        let Some(recording_id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let Some(header) = read_header(&path) else {
+            continue;
+        };
+        recordings.push(RecordingInfo {
+            recording_id: recording_id.to_string(),
+            started_at_ms: header.timestamp * 1000,
+            cols: header.width,
+            rows: header.height,
+            size_bytes: path.metadata().map(|m| m.len()).unwrap_or(0),
+        });
+    }
+    recordings.sort_by(|a, b| {
+        (a.started_at_ms, &a.recording_id).cmp(&(b.started_at_ms, &b.recording_id))
+    });
+    Ok(recordings)
+}
+
+fn read_header(path: &Path) -> Option<CastHeader> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).ok()?)
+        .read_line(&mut line)
+        .ok()?;
+    serde_json::from_str(&line).ok()
+}
+
+/// Parse a `COLSxROWS` resize event
+fn parse_size(size: &str) -> Option<(u16, u16)> {
+    let (cols, rows) = size.split_once('x')?;
+    Some((cols.parse().ok()?, rows.parse().ok()?))
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent_id = Uuid::new_v4();
+
+        let mut recorder = Recorder::create(dir.path(), agent_id, 80, 24).unwrap();
+        let id = recorder.id().to_string();
+        recorder.output(b"$ ls\r\n").unwrap();
+        recorder.resize(120, 40).unwrap();
+        recorder.output(b"\x1b[1mCargo.toml\x1b[0m \xff\r\n").unwrap();
+        recorder.finish().unwrap();
+
+        let path = recording_path(dir.path(), &id).unwrap();
+        let cast = Cast::read(&path).unwrap();
+        assert_eq!((cast.header.width, cast.header.height), (80, 24));
+        assert_eq!(cast.events.len(), 3);
+        assert!(matches!(&cast.events[0], CastEvent::Output { data, .. } if data == "$ ls\r\n"));
+        assert!(matches!(
+            cast.events[1],
+            CastEvent::Resize {
+                cols: 120,
+                rows: 40,
+                ..
+            }
+        ));
+        assert!(cast.events[1].at() >= cast.events[0].at());
+        assert!(
+            matches!(&cast.events[2], CastEvent::Output { data, .. } if data.ends_with(" \u{fffd}\r\n"))
+        );
+
+        let recordings = list_recordings(dir.path()).unwrap();
+        assert_eq!(recordings.len(), 1);
+        assert_eq!(recordings[0].recording_id, id);
+        assert_eq!(recordings[0].cols, 80);
+    }
+
+    #[test]
+    fn test_asciinema_files_and_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(list_recordings(dir.path()).unwrap().is_empty());
+        assert!(recording_path(dir.path(), "../../etc/passwd").is_none());
+        assert!(recording_path(dir.path(), "").is_none());
+
+        // Written by asciinema itself, with input events and extra header fields
+        let path = recording_path(dir.path(), "demo").unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            concat!(
+                r#"{"version": 2, "width": 100, "height": 30, "env": {"TERM": "xterm"}}"#,
+                "\n",
+                r#"[0.5, "i", "l"]"#,
+                "\n",
+                r#"[1.25, "o", "hello"]"#,
+                "\n"
+            ),
+        )
+        .unwrap();
+        let cast = Cast::read(&path).unwrap();
+        assert_eq!(
+            cast.events,
+            vec![CastEvent::Output {
+                at: Duration::from_millis(1250),
+                data: "hello".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,2230 @@
+//! Individual agent session
+//!
+//! Manages a single Claude Code agent with PTY terminal emulation.
+//! Handles the full lifecycle: spawn, I/O routing, and cleanup.
+
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::sync::{broadcast, RwLock};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{
+    CommandMark, Confirmation, Expecter, HistoryEntry, InputHistory, PhaseDetector,
+    PromptDelivery, PromptDetector, PromptGate, ReadyDetector, Recorder, Redactor, ShellMarks,
+    stream_json_args, StreamEvent, StreamJsonParser, Transcript, TriggerMatch, Triggers,
+    Utf8Reassembler, DEFAULT_READY_TIMEOUT,
+};
+use crate::config::{
+    AgentPreset, ExpectRule, InputMacro, KeyBindings, SecretEnv, TranscriptConfig, TriggerRule,
+    DEFAULT_PROFILE,
+};
+use crate::pty::{
+    ensure_managed_session, kill_managed_session, managed_session, EchoState, ExitReason,
+    ExternalSession, InputModes, NativePtyBackend, ProcessExit, PtyBackend, PtyError, PtyHandle,
+    SshTarget, TerminalScreen, TerminalSize, SIGINT, SIGKILL, SIGTERM,
+};
+#[cfg(unix)]
+use crate::pty::{SIGCONT, SIGSTOP};
+use crate::protocol::{
+    AgentMode, AgentState, RunStats, ScreenState, DEFAULT_TERMINAL_COLS, DEFAULT_TERMINAL_ROWS,
+};
+
+/// Errors that can occur during agent session operations
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("Failed to spawn agent: {0}")]
+    SpawnFailed(String),
+
+    #[error("Agent not running")]
+    NotRunning,
+
+    #[error("Agent already running")]
+    AlreadyRunning,
+
+    #[error("PTY error: {0}")]
+    PtyError(#[from] PtyError),
+
+    #[error("Invalid project path: {0}")]
+    InvalidPath(String),
+
+    #[error("Send error: {0}")]
+    SendError(String),
+
+    #[error("Agent is already being recorded")]
+    AlreadyRecording,
+
+    #[error("Agent is not being recorded")]
+    NotRecording,
+
+    #[error("Recording failed: {0}")]
+    RecordingFailed(std::io::Error),
+
+    #[error("Signals can only be sent to local, non-persistent agents")]
+    SignalUnsupported,
+
+    #[error("Agents can only be paused on Unix")]
+    PauseUnsupported,
+
+    #[error("Terminal echo can only be read and set for local, non-persistent agents on Unix")]
+    EchoUnsupported,
+}
+
+/// Result type for session operations
+pub type SessionResult<T> = Result<T, SessionError>;
+
+/// How long a busy agent must stay quiet before it counts as idle
+pub const DEFAULT_IDLE_AFTER: Duration = Duration::from_secs(2);
+
+/// How long an agent gets to exit after SIGTERM before it is killed
+pub const DEFAULT_KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Signals that suspend and continue a process, where there are any
+#[cfg(unix)]
+const PAUSE_SIGNALS: Option<(i32, i32)> = Some((SIGSTOP, SIGCONT));
+#[cfg(not(unix))]
+const PAUSE_SIGNALS: Option<(i32, i32)> = None;
+
+/// Output data from the agent
+#[derive(Debug, Clone)]
+pub struct AgentOutput {
+    /// The output data (may contain ANSI escape sequences)
+    pub data: Vec<u8>,
+}
+
+/// Event when agent exits
+#[derive(Debug, Clone)]
+pub struct AgentExit {
+    /// Agent session ID
+    pub session_id: Uuid,
+    /// Exit code if available
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the process, on Unix
+    pub signal: Option<i32>,
+    /// Exit reason
+    pub reason: ExitReason,
+    /// Preset the agent was spawned with
+    pub preset: Option<String>,
+    /// Duration and I/O totals of the run
+    pub stats: RunStats,
+}
+
+/// Running totals behind [`RunStats`], plus activity timestamps
+#[derive(Debug, Default)]
+struct RunCounters {
+    started: Mutex<Option<Instant>>,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Wall-clock spawn time in Unix milliseconds (0 before the first run)
+    spawned_at_ms: AtomicU64,
+    /// Wall-clock time of the last input or output in Unix milliseconds
+    last_activity_ms: AtomicU64,
+    /// Secrets removed from recorded input and streamed output
+    redactions: AtomicU64,
+}
+
+impl RunCounters {
+    /// Reset the totals for a new run
+    fn start(&self) {
+        *self.started.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+        self.bytes_in.store(0, Ordering::Relaxed);
+        self.bytes_out.store(0, Ordering::Relaxed);
+        self.redactions.store(0, Ordering::Relaxed);
+        let now = now_ms();
+        self.spawned_at_ms.store(now, Ordering::Relaxed);
+        self.last_activity_ms.store(now, Ordering::Relaxed);
+    }
+
+    fn add_in(&self, len: usize) {
+        self.bytes_in.fetch_add(len as u64, Ordering::Relaxed);
+        self.last_activity_ms.store(now_ms(), Ordering::Relaxed);
+    }
+
+    fn add_out(&self, len: usize) {
+        self.bytes_out.fetch_add(len as u64, Ordering::Relaxed);
+        self.last_activity_ms.store(now_ms(), Ordering::Relaxed);
+    }
+
+    fn add_redactions(&self, count: usize) {
+        self.redactions.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn spawned_at_ms(&self) -> Option<u64> {
+        Some(self.spawned_at_ms.load(Ordering::Relaxed)).filter(|&ms| ms > 0)
+    }
+
+    fn last_activity_ms(&self) -> Option<u64> {
+        Some(self.last_activity_ms.load(Ordering::Relaxed)).filter(|&ms| ms > 0)
+    }
+
+    fn snapshot(&self) -> RunStats {
+        let started = *self.started.lock().unwrap_or_else(|e| e.into_inner());
+        RunStats {
+            duration_ms: started.map_or(0, |t| t.elapsed().as_millis() as u64),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            redactions: self.redactions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Event when the agent's state changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateChange {
+    /// State before the change
+    pub old_state: AgentState,
+    /// State after the change
+    pub new_state: AgentState,
+    /// When the change happened, in Unix milliseconds
+    pub timestamp_ms: u64,
+}
+
+/// Something the session observed that may move the agent to another state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Activity {
+    /// The agent produced output
+    Output,
+    /// A confirmation prompt was detected in the output
+    Prompt,
+    /// No output for the idle threshold
+    Quiet,
+    /// Input was written to the agent
+    Input,
+    /// The agent showed the prompt it shows once ready for a command
+    Ready,
+}
+
+/// State an activity moves the agent to, if any
+///
+/// Only the activity states change here; starting, pausing and stopping are
+/// set explicitly, and a pending question, like the readiness for a first
+/// command, holds until input arrives.
+fn transition(state: AgentState, activity: Activity) -> Option<AgentState> {
+    use AgentState::*;
+
+    match (state, activity) {
+        (Running | Idle, Activity::Output) => Some(Busy),
+        (Running | Idle | Busy, Activity::Prompt) => Some(WaitingForInput),
+        (Running | Busy, Activity::Quiet) => Some(Idle),
+        (WaitingForInput, Activity::Input) => Some(Busy),
+        (Running | Idle | Busy, Activity::Ready) => Some(Ready),
+        (Ready, Activity::Input) => Some(Running),
+        (Ready, Activity::Prompt) => Some(WaitingForInput),
+        _ => None,
+    }
+}
+
+/// Agent state shared by the session and its output forwarder
+#[derive(Clone)]
+struct StateCell {
+    state: Arc<RwLock<AgentState>>,
+    tx: broadcast::Sender<StateChange>,
+}
+
+impl StateCell {
+    fn new() -> Self {
+        let (tx, _) = broadcast::channel(64);
+        Self {
+            state: Arc::new(RwLock::new(AgentState::Stopped)),
+            tx,
+        }
+    }
+
+    async fn get(&self) -> AgentState {
+        *self.state.read().await
+    }
+
+    /// Set the state, notifying subscribers if it changed
+    async fn set(&self, new_state: AgentState) {
+        let mut state = self.state.write().await;
+        let old_state = std::mem::replace(&mut *state, new_state);
+        if old_state != new_state {
+            let _ = self.tx.send(StateChange {
+                old_state,
+                new_state,
+                timestamp_ms: now_ms(),
+            });
+        }
+    }
+
+    /// Apply the transition for an activity
+    async fn observe(&self, activity: Activity) {
+        let current = self.get().await;
+        if let Some(new_state) = transition(current, activity) {
+            self.set(new_state).await;
+        }
+    }
+}
+
+/// Configuration for spawning an agent
+#[derive(Debug, Clone)]
+pub struct SpawnConfig {
+    /// Path to the project directory
+    pub project_path: String,
+    /// Terminal columns
+    pub cols: u16,
+    /// Terminal rows
+    pub rows: u16,
+    /// Optional preset name
+    pub preset: Option<String>,
+    /// Command-line arguments for the agent
+    pub args: Vec<String>,
+    /// How claude is run
+    pub mode: AgentMode,
+    /// Initial prompt to send after spawn
+    pub initial_prompt: Option<String>,
+    /// Pattern the agent's output matches once it is ready for the initial prompt
+    pub ready_pattern: Option<String>,
+    /// Time the initial prompt waits for the ready pattern
+    pub ready_timeout: Duration,
+    /// Remote host to run the agent on (local PTY when `None`)
+    pub remote: Option<SshTarget>,
+    /// Existing multiplexer session to attach to instead of starting claude
+    pub adopt: Option<ExternalSession>,
+    /// Run the agent inside a managed tmux session that survives bridge restarts
+    pub persistent: bool,
+    /// Fixed agent ID (a fresh one is generated when `None`)
+    pub agent_id: Option<Uuid>,
+    /// Input macros available to clients
+    pub macros: Vec<InputMacro>,
+    /// Key sequences for abstract actions sent with `send_key`
+    pub keybindings: KeyBindings,
+    /// Prompts answered automatically from the agent's output
+    pub expect: Vec<ExpectRule>,
+    /// Patterns in the agent's output reported to clients
+    pub triggers: Vec<TriggerRule>,
+    /// Quiet time after which a busy agent is reported idle
+    pub idle_after: Duration,
+    /// Time without input or output after which the agent is stopped
+    pub idle_timeout: Option<Duration>,
+    /// Client that requested the agent
+    pub spawned_by: Option<String>,
+    /// Connection or session token the agent belongs to
+    pub owner: Option<String>,
+    /// Labels attached to the agent
+    pub tags: Vec<String>,
+    /// Group the agent belongs to
+    pub group: Option<String>,
+    /// Display name
+    pub name: Option<String>,
+    /// Names of the secrets the agent needs
+    pub secrets: Vec<String>,
+    /// Secret values set in the agent's environment
+    pub secret_env: SecretEnv,
+    /// Further environment variables set for the agent
+    pub env: BTreeMap<String, String>,
+    /// Directory the agent runs in, relative to the project path (the
+    /// project directory when `None`)
+    pub cwd: Option<String>,
+    /// Redaction applied to the output streamed to clients (none when `None`)
+    pub output_redactor: Option<Redactor>,
+    /// Transcript logging of the agent's terminal (none when `None`)
+    pub transcript: Option<TranscriptConfig>,
+    /// Report changes to the terminal's echo and raw mode
+    pub report_echo: bool,
+}
+
+impl SpawnConfig {
+    /// Create a new spawn config with default terminal size
+    pub fn new(project_path: impl Into<String>) -> Self {
+        Self {
+            project_path: project_path.into(),
+            cols: DEFAULT_TERMINAL_COLS,
+            rows: DEFAULT_TERMINAL_ROWS,
+            preset: None,
+            args: Vec::new(),
+            mode: AgentMode::Interactive,
+            initial_prompt: None,
+            ready_pattern: None,
+            ready_timeout: DEFAULT_READY_TIMEOUT,
+            remote: None,
+            adopt: None,
+            persistent: false,
+            agent_id: None,
+            macros: Vec::new(),
+            keybindings: KeyBindings::builtin(DEFAULT_PROFILE).unwrap_or_default(),
+            expect: Vec::new(),
+            triggers: Vec::new(),
+            idle_after: DEFAULT_IDLE_AFTER,
+            idle_timeout: None,
+            spawned_by: None,
+            owner: None,
+            tags: Vec::new(),
+            group: None,
+            name: None,
+            secrets: Vec::new(),
+            secret_env: SecretEnv::default(),
+            env: BTreeMap::new(),
+            cwd: None,
+            output_redactor: None,
+            transcript: None,
+            report_echo: false,
+        }
+    }
+
+    /// Set terminal dimensions
+    pub fn with_size(mut self, cols: u16, rows: u16) -> Self {
+        self.cols = cols;
+        self.rows = rows;
+        self
+    }
+
+    /// Set preset name
+    pub fn with_preset(mut self, preset: impl Into<String>) -> Self {
+        self.preset = Some(preset.into());
+        self
+    }
+
+    /// Set command-line arguments
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Set how claude is run
+    pub fn with_mode(mut self, mode: AgentMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set initial prompt
+    pub fn with_initial_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.initial_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Hold the initial prompt back until the agent's output matches a
+    /// regular expression
+    pub fn with_ready_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.ready_pattern = Some(pattern.into());
+        self
+    }
+
+    /// Set how long the initial prompt waits for the ready pattern
+    pub fn with_ready_timeout(mut self, timeout: Duration) -> Self {
+        self.ready_timeout = timeout;
+        self
+    }
+
+    /// Run the agent on a remote host over SSH
+    pub fn with_remote(mut self, remote: SshTarget) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    /// Attach to an existing multiplexer session instead of starting claude
+    pub fn with_adopt(mut self, session: ExternalSession) -> Self {
+        self.adopt = Some(session);
+        self
+    }
+
+    /// Keep the agent in a managed tmux session that outlives the bridge
+    ///
+    /// Only applies to local agents; remote and adopted agents are unaffected.
+    pub fn with_persistence(mut self) -> Self {
+        self.persistent = true;
+        self
+    }
+
+    /// Use a fixed agent ID, e.g. when re-attaching a persistent agent
+    pub fn with_agent_id(mut self, agent_id: Uuid) -> Self {
+        self.agent_id = Some(agent_id);
+        self
+    }
+
+    /// Add input macros, replacing existing macros of the same name
+    pub fn with_macros(mut self, macros: impl IntoIterator<Item = InputMacro>) -> Self {
+        for input_macro in macros {
+            self.macros.retain(|m| m.name != input_macro.name);
+            self.macros.push(input_macro);
+        }
+        self
+    }
+
+    /// Set the keybindings used for abstract key actions
+    pub fn with_keybindings(mut self, keybindings: KeyBindings) -> Self {
+        self.keybindings = keybindings;
+        self
+    }
+
+    /// Add rules that answer prompts in the agent's output
+    pub fn with_expect_rules(mut self, rules: impl IntoIterator<Item = ExpectRule>) -> Self {
+        self.expect.extend(rules);
+        self
+    }
+
+    /// Add output triggers
+    pub fn with_triggers(mut self, triggers: impl IntoIterator<Item = TriggerRule>) -> Self {
+        self.triggers.extend(triggers);
+        self
+    }
+
+    /// Report the agent idle after `idle_after` without output
+    pub fn with_idle_after(mut self, idle_after: Duration) -> Self {
+        self.idle_after = idle_after;
+        self
+    }
+
+    /// Stop the agent after `timeout` without input or output
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Record the client that requested the agent
+    pub fn with_spawned_by(mut self, client: impl Into<String>) -> Self {
+        self.spawned_by = Some(client.into());
+        self
+    }
+
+    /// Associate the agent with the connection or session token that owns it
+    pub fn with_owner(mut self, owner: impl Into<String>) -> Self {
+        self.owner = Some(owner.into());
+        self
+    }
+
+    /// Attach labels to the agent
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Place the agent in a group
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Give the agent a display name
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set looked-up secrets in the agent's environment
+    pub fn with_secret_env(mut self, env: SecretEnv) -> Self {
+        self.secret_env = env;
+        self
+    }
+
+    /// Set environment variables for the agent, replacing any of the same
+    /// name set before
+    pub fn with_env(mut self, env: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.env.extend(env);
+        self
+    }
+
+    /// Run the agent in a directory below the project path, which is still
+    /// where its configuration and logs are kept
+    pub fn with_cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Redact secrets from the output streamed to clients
+    pub fn with_output_redactor(mut self, redactor: Redactor) -> Self {
+        self.output_redactor = Some(redactor);
+        self
+    }
+
+    /// Log the agent's terminal to its project's `.hoc/logs`
+    pub fn with_transcript(mut self, transcript: TranscriptConfig) -> Self {
+        self.transcript = Some(transcript);
+        self
+    }
+
+    /// Report when the agent's terminal starts or stops echoing input, or
+    /// switches between raw and cooked mode
+    pub fn with_echo_reports(mut self) -> Self {
+        self.report_echo = true;
+        self
+    }
+
+    /// Apply the settings of a project preset
+    pub fn apply_preset(mut self, preset: &AgentPreset) -> Self {
+        self = self.with_preset(&preset.name);
+        if !preset.args.is_empty() {
+            self = self.with_args(preset.args.clone());
+        }
+        if let Some(ref prompt) = preset.initial_prompt {
+            self = self.with_initial_prompt(prompt.as_str());
+        }
+        if let Some(ref pattern) = preset.ready_pattern {
+            self = self.with_ready_pattern(pattern.as_str());
+        }
+        if let Some(secs) = preset.ready_timeout_secs {
+            self = self.with_ready_timeout(Duration::from_secs(secs));
+        }
+        if let Some(ref host) = preset.host {
+            let mut remote = SshTarget::new(host.as_str());
+            if let Some(ref dir) = preset.remote_dir {
+                remote = remote.with_remote_dir(dir.as_str());
+            }
+            self = self.with_remote(remote);
+        }
+        if let Some(mins) = preset.idle_timeout_mins {
+            self = self.with_idle_timeout(Duration::from_secs(mins * 60));
+        }
+        if let Some(transcript) = preset.transcript {
+            self = self.with_transcript(transcript);
+        }
+        self.secrets = preset.secrets.clone();
+        self.with_env(preset.env.clone())
+            .with_macros(preset.macros.iter().cloned())
+            .with_expect_rules(preset.expect.iter().cloned())
+            .with_triggers(preset.triggers.iter().cloned())
+    }
+}
+
+/// What the output forwarder watches an agent's output for
+struct OutputWatchers {
+    expecter: Expecter,
+    triggers: Triggers,
+    ready_detector: Option<ReadyDetector>,
+    prompt_gate: Option<PromptGate>,
+    stream_parser: Option<StreamJsonParser>,
+}
+
+/// Represents a single agent session with full lifecycle management
+pub struct AgentSession {
+    /// Unique identifier for this session
+    id: Uuid,
+    /// Working directory for the agent
+    project_path: String,
+    /// Terminal dimensions
+    cols: u16,
+    rows: u16,
+    /// Command-line arguments for the agent
+    args: Vec<String>,
+    /// Preset the agent was spawned with
+    preset: Option<String>,
+    /// Client that requested the agent
+    spawned_by: Option<String>,
+    /// Connection or session token the agent belongs to
+    owner: Option<String>,
+    /// Labels attached to the agent
+    tags: Vec<String>,
+    /// Group the agent belongs to
+    group: Option<String>,
+    /// Secret values set in the agent's environment
+    secret_env: SecretEnv,
+    /// Redaction applied to streamed output
+    output_redactor: Option<Redactor>,
+    /// Initial prompt to send after spawn
+    initial_prompt: Option<String>,
+    /// Remote host to run the agent on
+    remote: Option<SshTarget>,
+    /// Adopted multiplexer session
+    adopt: Option<ExternalSession>,
+    /// Whether the agent lives in a managed tmux session
+    persistent: bool,
+    /// Input macros available to clients
+    macros: Vec<InputMacro>,
+    /// Key sequences for abstract actions
+    keybindings: KeyBindings,
+    /// Prompts answered automatically
+    expect: Vec<ExpectRule>,
+    /// Recent (redacted) input sent to the agent
+    history: Mutex<InputHistory>,
+    /// Confirmation prompts detected in the output
+    prompts: Arc<Mutex<PromptDetector>>,
+    /// Tool the agent is running, from its output
+    phases: Arc<Mutex<PhaseDetector>>,
+    /// What the agent's terminal shows
+    screen: Arc<Mutex<TerminalScreen>>,
+    /// Recording of the agent's output, while one is being made
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    /// Transcript of the agent's terminal, while it runs with one
+    transcript: Arc<Mutex<Option<Transcript>>>,
+    /// Current state of the agent
+    state: StateCell,
+    /// State a paused agent returns to when resumed
+    resume_state: Mutex<Option<AgentState>>,
+    /// Duration and I/O totals of the current run
+    counters: Arc<RunCounters>,
+    /// Exit reason to report instead of the process's own, once the bridge
+    /// has asked it to stop
+    stop_reason: Arc<Mutex<Option<ExitReason>>>,
+    /// Echo state last reported to subscribers
+    last_echo: Arc<Mutex<Option<EchoState>>>,
+    /// Quiet time after which a busy agent is reported idle
+    idle_after: Duration,
+    /// Starts the agent's process
+    pty: Arc<dyn PtyBackend>,
+    /// The PTY process (when running)
+    process: Arc<RwLock<Option<Box<dyn PtyHandle>>>>,
+    /// Channel for sending output to subscribers
+    output_tx: broadcast::Sender<AgentOutput>,
+    /// Channel for signaling exit
+    exit_tx: broadcast::Sender<AgentExit>,
+    /// Channel for detected confirmation prompts
+    confirm_tx: broadcast::Sender<Confirmation>,
+    /// Channel for command boundaries reported by shell integration
+    command_tx: broadcast::Sender<CommandMark>,
+    /// Channel for the delivery of the initial prompt
+    prompt_tx: broadcast::Sender<PromptDelivery>,
+    /// Channel for output triggers that fired
+    trigger_tx: broadcast::Sender<TriggerMatch>,
+    /// Channel for the events of a `stream_json` agent
+    stream_tx: broadcast::Sender<StreamEvent>,
+    /// Channel for changes to the terminal's echo state
+    echo_tx: broadcast::Sender<EchoState>,
+    /// Shutdown signal
+    shutdown_tx: broadcast::Sender<()>,
+    /// Configuration the agent was created with, to restart it from
+    spawn_config: SpawnConfig,
+}
+
+impl AgentSession {
+    /// Create a new agent session (not yet spawned)
+    pub fn new(project_path: impl Into<String>) -> Self {
+        let (output_tx, _) = broadcast::channel(1024);
+        let (exit_tx, _) = broadcast::channel(1);
+        let (confirm_tx, _) = broadcast::channel(16);
+        let (command_tx, _) = broadcast::channel(64);
+        let (prompt_tx, _) = broadcast::channel(1);
+        let (trigger_tx, _) = broadcast::channel(64);
+        let (stream_tx, _) = broadcast::channel(256);
+        let (echo_tx, _) = broadcast::channel(16);
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let spawn_config = SpawnConfig::new(project_path);
+
+        Self {
+            id: Uuid::new_v4(),
+            project_path: spawn_config.project_path.clone(),
+            cols: DEFAULT_TERMINAL_COLS,
+            rows: DEFAULT_TERMINAL_ROWS,
+            args: Vec::new(),
+            preset: None,
+            spawned_by: None,
+            owner: None,
+            tags: Vec::new(),
+            group: None,
+            secret_env: SecretEnv::default(),
+            output_redactor: None,
+            initial_prompt: None,
+            remote: None,
+            adopt: None,
+            persistent: false,
+            macros: Vec::new(),
+            keybindings: KeyBindings::builtin(DEFAULT_PROFILE).unwrap_or_default(),
+            expect: Vec::new(),
+            idle_after: DEFAULT_IDLE_AFTER,
+            history: Mutex::new(InputHistory::default()),
+            prompts: Arc::new(Mutex::new(PromptDetector::default())),
+            phases: Arc::new(Mutex::new(PhaseDetector::default())),
+            screen: Arc::new(Mutex::new(TerminalScreen::new(
+                DEFAULT_TERMINAL_COLS,
+                DEFAULT_TERMINAL_ROWS,
+            ))),
+            recorder: Arc::new(Mutex::new(None)),
+            transcript: Arc::new(Mutex::new(None)),
+            state: StateCell::new(),
+            resume_state: Mutex::new(None),
+            counters: Arc::new(RunCounters::default()),
+            stop_reason: Arc::new(Mutex::new(None)),
+            last_echo: Arc::new(Mutex::new(None)),
+            pty: Arc::new(NativePtyBackend),
+            process: Arc::new(RwLock::new(None)),
+            output_tx,
+            exit_tx,
+            confirm_tx,
+            command_tx,
+            prompt_tx,
+            trigger_tx,
+            stream_tx,
+            echo_tx,
+            shutdown_tx,
+            spawn_config,
+        }
+    }
+
+    /// Create a new agent session with specific configuration
+    pub fn with_config(config: SpawnConfig) -> Self {
+        let (output_tx, _) = broadcast::channel(1024);
+        let (exit_tx, _) = broadcast::channel(1);
+        let (confirm_tx, _) = broadcast::channel(16);
+        let (command_tx, _) = broadcast::channel(64);
+        let (prompt_tx, _) = broadcast::channel(1);
+        let (trigger_tx, _) = broadcast::channel(64);
+        let (stream_tx, _) = broadcast::channel(256);
+        let (echo_tx, _) = broadcast::channel(16);
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let spawn_config = config.clone();
+
+        Self {
+            id: config.agent_id.unwrap_or_else(Uuid::new_v4),
+            project_path: config.project_path,
+            cols: config.cols,
+            rows: config.rows,
+            args: config.args,
+            preset: config.preset,
+            spawned_by: config.spawned_by,
+            owner: config.owner,
+            tags: config.tags,
+            group: config.group,
+            secret_env: config.secret_env,
+            output_redactor: config.output_redactor,
+            initial_prompt: config.initial_prompt,
+            remote: config.remote,
+            adopt: config.adopt,
+            persistent: config.persistent,
+            macros: config.macros,
+            keybindings: config.keybindings,
+            expect: config.expect,
+            idle_after: config.idle_after,
+            history: Mutex::new(InputHistory::default()),
+            prompts: Arc::new(Mutex::new(PromptDetector::default())),
+            phases: Arc::new(Mutex::new(PhaseDetector::default())),
+            screen: Arc::new(Mutex::new(TerminalScreen::new(config.cols, config.rows))),
+            recorder: Arc::new(Mutex::new(None)),
+            transcript: Arc::new(Mutex::new(None)),
+            state: StateCell::new(),
+            resume_state: Mutex::new(None),
+            counters: Arc::new(RunCounters::default()),
+            stop_reason: Arc::new(Mutex::new(None)),
+            last_echo: Arc::new(Mutex::new(None)),
+            pty: Arc::new(NativePtyBackend),
+            process: Arc::new(RwLock::new(None)),
+            output_tx,
+            exit_tx,
+            confirm_tx,
+            command_tx,
+            prompt_tx,
+            trigger_tx,
+            stream_tx,
+            echo_tx,
+            shutdown_tx,
+            spawn_config,
+        }
+    }
+
+    /// Start the agent's process through the given backend
+    pub fn with_pty_backend(mut self, pty: Arc<dyn PtyBackend>) -> Self {
+        self.pty = pty;
+        self
+    }
+
+    /// Get the session ID
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Get the project path
+    pub fn project_path(&self) -> &str {
+        &self.project_path
+    }
+
+    /// Get terminal columns
+    pub fn cols(&self) -> u16 {
+        self.cols
+    }
+
+    /// Get terminal rows
+    pub fn rows(&self) -> u16 {
+        self.rows
+    }
+
+    /// Configuration the agent was created with
+    pub fn spawn_config(&self) -> &SpawnConfig {
+        &self.spawn_config
+    }
+
+    /// Get the current state
+    pub async fn state(&self) -> AgentState {
+        self.state.get().await
+    }
+
+    /// Subscribe to state changes
+    pub fn subscribe_state(&self) -> broadcast::Receiver<StateChange> {
+        self.state.tx.subscribe()
+    }
+
+    /// Subscribe to output events
+    pub fn subscribe_output(&self) -> broadcast::Receiver<AgentOutput> {
+        self.output_tx.subscribe()
+    }
+
+    /// Subscribe to exit events
+    pub fn subscribe_exit(&self) -> broadcast::Receiver<AgentExit> {
+        self.exit_tx.subscribe()
+    }
+
+    /// Subscribe to detected confirmation prompts
+    pub fn subscribe_confirmations(&self) -> broadcast::Receiver<Confirmation> {
+        self.confirm_tx.subscribe()
+    }
+
+    /// Subscribe to command boundaries reported by shell integration
+    pub fn subscribe_commands(&self) -> broadcast::Receiver<CommandMark> {
+        self.command_tx.subscribe()
+    }
+
+    /// Subscribe to the delivery of the initial prompt
+    pub fn subscribe_prompt_delivery(&self) -> broadcast::Receiver<PromptDelivery> {
+        self.prompt_tx.subscribe()
+    }
+
+    /// Subscribe to output triggers firing
+    pub fn subscribe_triggers(&self) -> broadcast::Receiver<TriggerMatch> {
+        self.trigger_tx.subscribe()
+    }
+
+    /// Subscribe to the JSON stream events of a `stream_json` agent
+    pub fn subscribe_stream_events(&self) -> broadcast::Receiver<StreamEvent> {
+        self.stream_tx.subscribe()
+    }
+
+    /// Subscribe to changes of the terminal's echo state, reported for
+    /// agents spawned with echo reports
+    pub fn subscribe_echo(&self) -> broadcast::Receiver<EchoState> {
+        self.echo_tx.subscribe()
+    }
+
+    /// The confirmation prompt waiting for an answer, if any
+    pub fn pending_confirmation(&self) -> Option<Confirmation> {
+        self.prompts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pending()
+            .cloned()
+    }
+
+    /// Take the pending confirmation prompt to answer it
+    pub fn take_confirmation(&self) -> Option<Confirmation> {
+        self.prompts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take_pending()
+    }
+
+    /// Forget the pending confirmation prompt
+    pub fn clear_confirmation(&self) {
+        self.prompts.lock().unwrap_or_else(|e| e.into_inner()).reset();
+    }
+
+    /// Spawn the claude command with PTY
+    ///
+    /// This starts the Claude Code agent in the specified project directory.
+    pub async fn spawn(&self) -> SessionResult<()> {
+        // Check if already running
+        {
+            let state = self.state.get().await;
+            if state.is_alive() || state == AgentState::Starting {
+                return Err(SessionError::AlreadyRunning);
+            }
+        }
+
+        // Validate project path
+        let project_path = Path::new(&self.project_path);
+        if !project_path.exists() {
+            return Err(SessionError::InvalidPath(format!(
+                "Project path does not exist: {}",
+                self.project_path
+            )));
+        }
+        if !project_path.is_dir() {
+            return Err(SessionError::InvalidPath(format!(
+                "Project path is not a directory: {}",
+                self.project_path
+            )));
+        }
+
+        let cwd = self.spawn_config.cwd.as_deref();
+        let workdir = cwd.map_or_else(|| project_path.to_path_buf(), |cwd| project_path.join(cwd));
+        // A remote agent's directory is on the remote host
+        if self.remote.is_none() && !workdir.is_dir() {
+            return Err(SessionError::InvalidPath(format!(
+                "Working directory is not a directory: {}",
+                workdir.display()
+            )));
+        }
+
+        let expecter = Expecter::new(&self.expect)
+            .map_err(|e| SessionError::SpawnFailed(format!("Invalid expect pattern: {}", e)))?;
+        let triggers = Triggers::new(&self.spawn_config.triggers)
+            .map_err(|e| SessionError::SpawnFailed(format!("Invalid trigger pattern: {}", e)))?;
+        let ready_detector = self
+            .spawn_config
+            .ready_pattern
+            .as_deref()
+            .map(ReadyDetector::new)
+            .transpose()
+            .map_err(|e| SessionError::SpawnFailed(format!("Invalid ready pattern: {}", e)))?;
+        let prompt = self.initial_prompt.as_deref().filter(|p| !p.is_empty());
+
+        // A stream_json agent is given its prompt on the command line instead
+        // of having it typed in
+        let (agent_args, prompt_gate, stream_parser) = match self.spawn_config.mode {
+            AgentMode::Interactive => {
+                let prompt_gate = prompt.map(|prompt| {
+                    let timeout = self.spawn_config.ready_timeout;
+                    PromptGate::new(prompt, ready_detector.is_some(), timeout, Instant::now())
+                });
+                (self.args.clone(), prompt_gate, None)
+            }
+            AgentMode::StreamJson => {
+                let prompt = prompt.ok_or_else(|| {
+                    SessionError::SpawnFailed("stream_json agents need a prompt".to_string())
+                })?;
+                let mut args = self.args.clone();
+                args.extend(stream_json_args(prompt));
+                (args, None, Some(StreamJsonParser::default()))
+            }
+        };
+
+        // The environment only reaches a process the bridge starts itself;
+        // passing it on to ssh or tmux would put the values on a command line
+        let env = &self.spawn_config.env;
+        if (!self.secret_env.is_empty() || !env.is_empty())
+            && (self.remote.is_some() || self.persistent)
+        {
+            return Err(SessionError::SpawnFailed(
+                "Secrets and environment variables can only be set for local, non-persistent \
+                 agents"
+                    .to_string(),
+            ));
+        }
+
+        // Update state to starting
+        self.state.set(AgentState::Starting).await;
+
+        // Spawn the claude command with args from preset (or a client attaching
+        // to the adopted session), wrapped in ssh for remote agents
+        let size = TerminalSize::new(self.cols, self.rows);
+        let (command, args) = match (&self.adopt, &self.remote) {
+            (Some(adopt), Some(remote)) => {
+                let (command, args) = adopt.command();
+                remote.command_in_home(&command, &args)
+            }
+            (Some(adopt), None) => adopt.command(),
+            (None, Some(remote)) => {
+                // The working directory is below the remote project directory
+                let remote_dir = remote.remote_dir.as_deref().unwrap_or(&self.project_path);
+                let dir = cwd.map_or_else(
+                    || remote_dir.to_string(),
+                    |cwd| Path::new(remote_dir).join(cwd).to_string_lossy().into_owned(),
+                );
+                let remote = remote.clone().with_remote_dir(dir);
+                remote.command("claude", &agent_args, &self.project_path)
+            }
+            (None, None) => ("claude".to_string(), agent_args),
+        };
+
+        // Persistent agents run claude in a managed tmux session; the PTY only
+        // hosts a client attached to it
+        let (command, args) = if self.persistent && self.adopt.is_none() && self.remote.is_none() {
+            if let Err(e) =
+                ensure_managed_session(self.id, &workdir.to_string_lossy(), &command, &args, size)
+                    .await
+            {
+                self.state.set(AgentState::Stopped).await;
+                return Err(SessionError::SpawnFailed(e.to_string()));
+            }
+            managed_session(self.id).command()
+        } else {
+            (command, args)
+        };
+        // Secrets take precedence over plain variables of the same name
+        let mut vars: HashMap<String, String> = env.clone().into_iter().collect();
+        vars.extend(self.secret_env.vars());
+        let env = (!vars.is_empty()).then_some(vars);
+        let process = match self.pty.spawn(&command, &args, &workdir, env.as_ref(), size) {
+            Ok(process) => process,
+            Err(e) => {
+                self.state.set(AgentState::Stopped).await;
+                return Err(SessionError::SpawnFailed(e.to_string()));
+            }
+        };
+
+        // Store the process
+        *self.process.write().await = Some(process);
+        self.counters.start();
+        if let Some(ref config) = self.spawn_config.transcript {
+            let opened = Transcript::open(project_path, self.id, config);
+            match opened {
+                Ok(transcript) => {
+                    *self.transcript.lock().unwrap_or_else(|e| e.into_inner()) = Some(transcript);
+                }
+                Err(e) => warn!("Failed to open transcript of agent {}: {}", self.id, e),
+            }
+        }
+
+        // Update state to running
+        self.state.set(AgentState::Running).await;
+
+        // Start the output forwarding task, which also detects when the agent
+        // is ready and sends the initial prompt then
+        let watchers = OutputWatchers {
+            expecter,
+            triggers,
+            ready_detector,
+            prompt_gate,
+            stream_parser,
+        };
+        self.start_output_forwarder(watchers).await;
+
+        Ok(())
+    }
+
+    /// Get the initial prompt if set
+    pub fn initial_prompt(&self) -> Option<&str> {
+        self.initial_prompt.as_deref()
+    }
+
+    /// Get the command arguments
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Get the preset the agent was spawned with, if any
+    pub fn preset(&self) -> Option<&str> {
+        self.preset.as_deref()
+    }
+
+    /// Client that requested the agent, if known
+    pub fn spawned_by(&self) -> Option<&str> {
+        self.spawned_by.as_deref()
+    }
+
+    /// Connection or session token the agent belongs to, if any
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    /// Labels attached to the agent
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Group the agent belongs to, if any
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// Replace the agent's tags, which a restart keeps
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        self.spawn_config.tags = tags.clone();
+        self.tags = tags;
+    }
+
+    /// Display name, if the agent has one
+    pub fn name(&self) -> Option<&str> {
+        self.spawn_config.name.as_deref()
+    }
+
+    /// Change the display name, which a restart keeps
+    pub fn set_name(&mut self, name: Option<String>) {
+        self.spawn_config.name = name;
+    }
+
+    /// Tool the agent is running, if it is working on one
+    pub fn phase(&self) -> Option<String> {
+        let phases = self.phases.lock().unwrap_or_else(|e| e.into_inner());
+        phases.phase().map(str::to_string)
+    }
+
+    /// What the agent's terminal shows right now
+    pub fn screen_state(&self) -> ScreenState {
+        self.screen.lock().unwrap_or_else(|e| e.into_inner()).state()
+    }
+
+    /// Input modes the agent's terminal application has switched on
+    pub fn input_modes(&self) -> InputModes {
+        self.screen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .input_modes()
+    }
+
+    /// Start recording the agent's output, returning the recording's ID
+    pub async fn start_recording(&self) -> SessionResult<String> {
+        if self.process.read().await.is_none() {
+            return Err(SessionError::NotRunning);
+        }
+        let mut recorder = self.recorder.lock().unwrap_or_else(|e| e.into_inner());
+        if recorder.is_some() {
+            return Err(SessionError::AlreadyRecording);
+        }
+        let started = Recorder::create(Path::new(&self.project_path), self.id, self.cols, self.rows)
+            .map_err(SessionError::RecordingFailed)?;
+        let id = started.id().to_string();
+        *recorder = Some(started);
+        Ok(id)
+    }
+
+    /// Stop recording the agent's output, returning the recording's ID and length
+    pub fn stop_recording(&self) -> SessionResult<(String, Duration)> {
+        let recorder = self
+            .recorder
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .ok_or(SessionError::NotRecording)?;
+        let id = recorder.id().to_string();
+        let elapsed = recorder.elapsed();
+        recorder.finish().map_err(SessionError::RecordingFailed)?;
+        Ok((id, elapsed))
+    }
+
+    /// ID of the recording being made, if any
+    pub fn recording_id(&self) -> Option<String> {
+        let recorder = self.recorder.lock().unwrap_or_else(|e| e.into_inner());
+        recorder.as_ref().map(|r| r.id().to_string())
+    }
+
+    /// Duration and I/O totals of the current run so far
+    pub fn run_stats(&self) -> RunStats {
+        self.counters.snapshot()
+    }
+
+    /// When the agent was spawned, in Unix milliseconds
+    pub fn spawned_at_ms(&self) -> Option<u64> {
+        self.counters.spawned_at_ms()
+    }
+
+    /// When the agent last produced output or received input, in Unix milliseconds
+    pub fn last_activity_ms(&self) -> Option<u64> {
+        self.counters.last_activity_ms()
+    }
+
+    /// Time since the agent last produced output or received input
+    pub fn idle_for(&self) -> Option<Duration> {
+        let last = self.counters.last_activity_ms()?;
+        Some(Duration::from_millis(now_ms().saturating_sub(last)))
+    }
+
+    /// OS process ID of the agent's process, if it runs locally
+    pub async fn pid(&self) -> Option<u32> {
+        self.process.read().await.as_ref().and_then(|p| p.pid())
+    }
+
+    /// Get the remote host the agent runs on, if any
+    pub fn remote(&self) -> Option<&SshTarget> {
+        self.remote.as_ref()
+    }
+
+    /// Get the adopted multiplexer session, if any
+    pub fn adopted(&self) -> Option<&ExternalSession> {
+        self.adopt.as_ref()
+    }
+
+    /// Whether the agent lives in a managed tmux session
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Get the agent's input macros
+    pub fn macros(&self) -> &[InputMacro] {
+        &self.macros
+    }
+
+    /// Look up an input macro by name
+    pub fn find_macro(&self, name: &str) -> Option<&InputMacro> {
+        self.macros.iter().find(|m| m.name == name)
+    }
+
+    /// Get the agent's expect rules
+    pub fn expect_rules(&self) -> &[ExpectRule] {
+        &self.expect
+    }
+
+    /// Get the agent's keybindings
+    pub fn keybindings(&self) -> &KeyBindings {
+        &self.keybindings
+    }
+
+    /// Add an input to the agent's history (callers redact it first and pass
+    /// the number of secrets removed)
+    pub fn record_input(&self, input: String, redactions: usize) {
+        self.counters.add_redactions(redactions);
+        self.history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(input);
+    }
+
+    /// The agent's most recent `limit` inputs, oldest first
+    pub fn input_history(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .recent(limit)
+    }
+
+    /// Start the background task that forwards PTY output to subscribers
+    ///
+    /// The task wakes only for output, the process exiting, the agent going
+    /// quiet or the initial prompt falling due; it never polls.
+    async fn start_output_forwarder(&self, watchers: OutputWatchers) {
+        let OutputWatchers {
+            mut expecter,
+            mut triggers,
+            mut ready_detector,
+            mut prompt_gate,
+            mut stream_parser,
+        } = watchers;
+        let process = Arc::clone(&self.process);
+        let state = self.state.clone();
+        let idle_after = self.idle_after;
+        let output_tx = self.output_tx.clone();
+        let exit_tx = self.exit_tx.clone();
+        let confirm_tx = self.confirm_tx.clone();
+        let command_tx = self.command_tx.clone();
+        let prompt_tx = self.prompt_tx.clone();
+        let trigger_tx = self.trigger_tx.clone();
+        let stream_tx = self.stream_tx.clone();
+        let echo_tx = self.echo_tx.clone();
+        let last_echo = Arc::clone(&self.last_echo);
+        let report_echo = self.spawn_config.report_echo && self.has_own_terminal();
+        let prompts = Arc::clone(&self.prompts);
+        let phases = Arc::clone(&self.phases);
+        let screen = Arc::clone(&self.screen);
+        let recorder = Arc::clone(&self.recorder);
+        let transcript = Arc::clone(&self.transcript);
+        let redactor = self.output_redactor.clone();
+        let counters = Arc::clone(&self.counters);
+        let stop_reason = Arc::clone(&self.stop_reason);
+        let preset = self.preset.clone();
+        let session_id = self.id;
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+
+        // The forwarder owns the output channel, so it waits for output
+        // without holding the process
+        let output_rx = process.write().await.as_mut().and_then(|p| p.take_output());
+        let Some(mut output_rx) = output_rx else {
+            return;
+        };
+        // State changes made elsewhere, like input to a ready agent, can
+        // make a quiet agent idle again
+        let mut state_rx = state.tx.subscribe();
+
+        tokio::spawn(async move {
+            let mut last_output = Instant::now();
+            // Whether the agent was seen to be quiet since its last output
+            let mut quiet = false;
+            let mut shell_marks = ShellMarks::default();
+            let mut utf8 = Utf8Reassembler::default();
+            loop {
+                let quiet_at = tokio::time::Instant::from_std(last_output + idle_after);
+                let prompt_at = prompt_gate
+                    .as_ref()
+                    .map(|gate| tokio::time::Instant::from_std(gate.deadline()));
+                let mut ready = false;
+                tokio::select! {
+                    // Check for shutdown signal
+                    _ = shutdown_rx.recv() => {
+                        break;
+                    }
+                    output = output_rx.recv() => {
+                        // The channel closes once the process has exited
+                        let exited = output.is_none();
+                        // Output is redacted together with whatever else has
+                        // already arrived, so secrets split across reads are
+                        // still caught, and passed on in whole characters
+                        let mut outputs = Vec::new();
+                        match output {
+                            Some(output) => {
+                                outputs.push(utf8.push(&output.data));
+                                while let Ok(output) = output_rx.try_recv() {
+                                    outputs.push(utf8.push(&output.data));
+                                }
+                            }
+                            None => outputs.push(utf8.finish()),
+                        }
+                        outputs.retain(|data| !data.is_empty());
+                        if !outputs.is_empty() {
+                            last_output = Instant::now();
+                            quiet = false;
+                        }
+                        let proc_guard = process.read().await;
+                        let mut pending = Vec::new();
+                        for data in outputs {
+                            if let Some(ref mut detector) = ready_detector {
+                                ready |= detector.feed(&data);
+                            }
+                            counters.add_out(data.len());
+                            state.observe(Activity::Output).await;
+                            phases
+                                .lock()
+                                .unwrap_or_else(|e| e.into_inner())
+                                .feed(&data);
+                            for mark in shell_marks.feed(&data) {
+                                let _ = command_tx.send(mark);
+                            }
+                            if let Some(response) = expecter.feed(&data) {
+                                if let Some(ref proc) = *proc_guard {
+                                    if proc.write_str(&response).await.is_ok() {
+                                        counters.add_in(response.len());
+                                    }
+                                }
+                                prompts.lock().unwrap_or_else(|e| e.into_inner()).reset();
+                            } else {
+                                let detected = prompts
+                                    .lock()
+                                    .unwrap_or_else(|e| e.into_inner())
+                                    .feed(&data);
+                                if let Some(confirmation) = detected {
+                                    state.observe(Activity::Prompt).await;
+                                    phases.lock().unwrap_or_else(|e| e.into_inner()).reset();
+                                    let _ = confirm_tx.send(confirmation);
+                                }
+                            }
+                            match redactor {
+                                Some(_) => pending.extend(data),
+                                None => {
+                                    screen
+                                        .lock()
+                                        .unwrap_or_else(|e| e.into_inner())
+                                        .feed(&data);
+                                    record(&recorder, |r| r.output(&data));
+                                    record(&transcript, |t| t.output(&data));
+                                    for fired in triggers.feed(&data) {
+                                        let _ = trigger_tx.send(fired);
+                                    }
+                                    if let Some(ref mut parser) = stream_parser {
+                                        for event in parser.feed(&data) {
+                                            let _ = stream_tx.send(event);
+                                        }
+                                    }
+                                    let _ = output_tx.send(AgentOutput { data });
+                                }
+                            }
+                        }
+                        if let (Some(redactor), false) = (&redactor, pending.is_empty()) {
+                            let (data, count) = redactor.redact_bytes(&pending);
+                            counters.add_redactions(count);
+                            // The screen shows what clients are shown
+                            screen.lock().unwrap_or_else(|e| e.into_inner()).feed(&data);
+                            record(&recorder, |r| r.output(&data));
+                            record(&transcript, |t| t.output(&data));
+                            for fired in triggers.feed(&data) {
+                                let _ = trigger_tx.send(fired);
+                            }
+                            if let Some(ref mut parser) = stream_parser {
+                                for event in parser.feed(&data) {
+                                    let _ = stream_tx.send(event);
+                                }
+                            }
+                            let _ = output_tx.send(AgentOutput { data });
+                        }
+
+                        // Programs switch echo and raw mode as they start
+                        // or prompt, which shows in the output around it
+                        if let (true, false, Some(proc)) = (report_echo, exited, &*proc_guard) {
+                            if let Ok(echo) = proc.echo_state().await {
+                                publish_echo(&last_echo, &echo_tx, echo);
+                            }
+                        }
+                        drop(proc_guard);
+
+                        if ready {
+                            ready_detector = None;
+                            state.observe(Activity::Ready).await;
+                        }
+
+                        if exited {
+                            let exit_info = {
+                                let proc_guard = process.read().await;
+                                match proc_guard.as_ref() {
+                                    Some(proc) => proc.exit_info().await,
+                                    None => None,
+                                }
+                            };
+                            let (exit_code, signal, reason) = match exit_info {
+                                Some(info) => (info.exit_code, info.signal, info.reason),
+                                None => (None, None, ExitReason::Unknown),
+                            };
+                            let reason = stop_reason
+                                .lock()
+                                .unwrap_or_else(|e| e.into_inner())
+                                .take()
+                                .unwrap_or(reason);
+
+                            // Update state
+                            state.set(AgentState::Stopped).await;
+
+                            // Send exit notification
+                            let _ = exit_tx.send(AgentExit {
+                                session_id,
+                                exit_code,
+                                signal,
+                                reason,
+                                preset: preset.clone(),
+                                stats: counters.snapshot(),
+                            });
+
+                            // Clear the process
+                            *process.write().await = None;
+                            break;
+                        }
+                    }
+                    _ = tokio::time::sleep_until(quiet_at), if !quiet => {
+                        quiet = true;
+                        state.observe(Activity::Quiet).await;
+                        phases.lock().unwrap_or_else(|e| e.into_inner()).reset();
+                        if let (true, Some(proc)) = (report_echo, &*process.read().await) {
+                            if let Ok(echo) = proc.echo_state().await {
+                                publish_echo(&last_echo, &echo_tx, echo);
+                            }
+                        }
+                    }
+                    _ = state_rx.recv() => {
+                        quiet = false;
+                    }
+                    // The prompt is sent below once it is due
+                    _ = tokio::time::sleep_until(prompt_at.unwrap_or(quiet_at)),
+                        if prompt_at.is_some() => {}
+                }
+
+                let now = Instant::now();
+                if let Some(gate) = prompt_gate.take_if(|g| ready || g.is_due(now)) {
+                    let (input, delivery) = gate.open(ready, now);
+                    let written = match *process.read().await {
+                        Some(ref proc) => proc.write_str(&input).await.is_ok(),
+                        None => false,
+                    };
+                    if written {
+                        counters.add_in(input.len());
+                        state.observe(Activity::Input).await;
+                        let _ = prompt_tx.send(delivery);
+                    }
+                }
+            }
+
+            // Keep what was recorded of a run that ended without a stop request
+            let unfinished = recorder.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some(unfinished) = unfinished {
+                let _ = unfinished.finish();
+            }
+            transcript.lock().unwrap_or_else(|e| e.into_inner()).take();
+        });
+    }
+
+    /// Write input to the agent's stdin
+    pub async fn write_input(&self, input: &[u8]) -> SessionResult<()> {
+        let proc_guard = self.process.read().await;
+        if let Some(ref process) = *proc_guard {
+            process.write(input).await.map_err(SessionError::PtyError)?;
+            self.counters.add_in(input.len());
+            record(&self.transcript, |t| t.input(input));
+            self.state.observe(Activity::Input).await;
+            Ok(())
+        } else {
+            Err(SessionError::NotRunning)
+        }
+    }
+
+    /// Write a string to the agent's stdin
+    pub async fn write_str(&self, input: &str) -> SessionResult<()> {
+        self.write_input(input.as_bytes()).await
+    }
+
+    /// Resize the terminal
+    pub async fn resize(&mut self, cols: u16, rows: u16) -> SessionResult<()> {
+        let proc_guard = self.process.read().await;
+        if let Some(ref process) = *proc_guard {
+            process
+                .resize(cols, rows)
+                .await
+                .map_err(SessionError::PtyError)?;
+            self.cols = cols;
+            self.rows = rows;
+            self.screen
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .resize(cols, rows);
+            record(&self.recorder, |r| r.resize(cols, rows));
+            Ok(())
+        } else {
+            Err(SessionError::NotRunning)
+        }
+    }
+
+    /// Kill the agent process
+    pub async fn kill(&self) -> SessionResult<()> {
+        // Update state to stopping
+        self.state.set(AgentState::Stopping).await;
+
+        // Signal shutdown to the forwarder
+        let _ = self.shutdown_tx.send(());
+
+        // Kill the process
+        let proc_guard = self.process.read().await;
+        if let Some(ref process) = *proc_guard {
+            process.kill().await.map_err(SessionError::PtyError)?;
+        }
+
+        // Killing the client only detaches it; end the managed session too
+        if self.persistent {
+            kill_managed_session(self.id)
+                .await
+                .map_err(SessionError::PtyError)?;
+        }
+
+        // The forwarder was shut down, so it won't see the process go
+        self.state.set(AgentState::Stopped).await;
+        Ok(())
+    }
+
+    /// Stop the agent, giving it `grace` to exit after SIGTERM (Ctrl+C on
+    /// Windows) before it is sent SIGKILL
+    ///
+    /// Unlike [`kill`](Self::kill) the exit is reported like any other, with
+    /// the reason `Terminated` if the agent stopped in time and `Killed` if it
+    /// had to be killed. Agents that can't be signalled are killed outright.
+    pub async fn kill_with_timeout(&self, grace: Duration) -> SessionResult<()> {
+        self.terminate(grace, ExitReason::Terminated).await
+    }
+
+    /// Stop the agent for exceeding a time limit
+    ///
+    /// Like [`kill_with_timeout`](Self::kill_with_timeout), but the exit is
+    /// reported as `TimedOut` however the agent went.
+    pub async fn time_out(&self, grace: Duration) -> SessionResult<()> {
+        self.terminate(grace, ExitReason::TimedOut).await
+    }
+
+    async fn terminate(&self, grace: Duration, reason: ExitReason) -> SessionResult<()> {
+        if self.remote.is_some() || self.adopt.is_some() || self.persistent {
+            return self.kill().await;
+        }
+        if !self.is_running().await {
+            return Err(SessionError::NotRunning);
+        }
+
+        let mut exit_rx = self.exit_tx.subscribe();
+        let paused = self.state.get().await == AgentState::Paused;
+        self.state.set(AgentState::Stopping).await;
+        self.set_stop_reason(reason);
+        let terminate = if cfg!(windows) { SIGINT } else { SIGTERM };
+        match self.signal(terminate).await {
+            Err(SessionError::NotRunning) => return Ok(()),
+            result => result?,
+        }
+        // A stopped process only acts on SIGTERM once it is continued
+        if let (true, Some((_, resume))) = (paused, PAUSE_SIGNALS) {
+            let _ = self.signal(resume).await;
+        }
+        if tokio::time::timeout(grace, exit_rx.recv()).await.is_ok() {
+            return Ok(());
+        }
+
+        // Still running: escalate
+        if reason == ExitReason::Terminated {
+            self.set_stop_reason(ExitReason::Killed);
+        }
+        match self.signal(SIGKILL).await {
+            Err(SessionError::NotRunning) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Suspend the agent's process (SIGSTOP) until [`resume`](Self::resume)
+    ///
+    /// Output and activity stop with it, so the agent stays `Paused` until it
+    /// is resumed or killed. Only local agents on Unix can be paused.
+    pub async fn pause(&self) -> SessionResult<()> {
+        let (pause, _) = PAUSE_SIGNALS.ok_or(SessionError::PauseUnsupported)?;
+        let state = self.state.get().await;
+        if state == AgentState::Paused {
+            return Ok(());
+        }
+        if !state.is_alive() {
+            return Err(SessionError::NotRunning);
+        }
+        self.signal(pause).await?;
+        *self.resume_state.lock().unwrap_or_else(|e| e.into_inner()) = Some(state);
+        self.state.set(AgentState::Paused).await;
+        Ok(())
+    }
+
+    /// Continue a paused agent (SIGCONT) in the state it was paused in
+    pub async fn resume(&self) -> SessionResult<()> {
+        let (_, resume) = PAUSE_SIGNALS.ok_or(SessionError::PauseUnsupported)?;
+        if self.state.get().await != AgentState::Paused {
+            return Ok(());
+        }
+        self.signal(resume).await?;
+        let state = self
+            .resume_state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .unwrap_or(AgentState::Running);
+        self.state.set(state).await;
+        Ok(())
+    }
+
+    fn set_stop_reason(&self, reason: ExitReason) {
+        *self.stop_reason.lock().unwrap_or_else(|e| e.into_inner()) = Some(reason);
+    }
+
+    /// Whether the agent's own process is attached to the local terminal,
+    /// rather than ssh or a tmux client
+    fn has_own_terminal(&self) -> bool {
+        self.remote.is_none() && self.adopt.is_none() && !self.persistent
+    }
+
+    /// Whether the agent's terminal echoes input and is in raw mode
+    ///
+    /// Remote, adopted and persistent agents are refused, as the terminal's
+    /// modes would be those of ssh or a tmux client.
+    pub async fn echo_state(&self) -> SessionResult<EchoState> {
+        if !self.has_own_terminal() {
+            return Err(SessionError::EchoUnsupported);
+        }
+        let proc_guard = self.process.read().await;
+        let process = proc_guard.as_ref().ok_or(SessionError::NotRunning)?;
+        process.echo_state().await.map_err(echo_error)
+    }
+
+    /// Switch the agent's terminal to raw mode or back to cooked mode,
+    /// returning the state it is left in
+    pub async fn set_raw_mode(&self, raw: bool) -> SessionResult<EchoState> {
+        if !self.has_own_terminal() {
+            return Err(SessionError::EchoUnsupported);
+        }
+        let proc_guard = self.process.read().await;
+        let process = proc_guard.as_ref().ok_or(SessionError::NotRunning)?;
+        let echo = process.set_raw_mode(raw).await.map_err(echo_error)?;
+        if self.spawn_config.report_echo {
+            publish_echo(&self.last_echo, &self.echo_tx, echo);
+        }
+        Ok(echo)
+    }
+
+    /// Send a signal to the agent's process
+    ///
+    /// The forwarder keeps running, so an agent the signal ends is reported as
+    /// exiting like any other. Remote, adopted and persistent agents are
+    /// refused: the local process is only ssh or a tmux client.
+    pub async fn signal(&self, signal: i32) -> SessionResult<()> {
+        if self.remote.is_some() || self.adopt.is_some() || self.persistent {
+            return Err(SessionError::SignalUnsupported);
+        }
+        let proc_guard = self.process.read().await;
+        let process = proc_guard.as_ref().ok_or(SessionError::NotRunning)?;
+        process.signal(signal).await.map_err(|e| match e {
+            PtyError::ProcessExited => SessionError::NotRunning,
+            e => SessionError::PtyError(e),
+        })
+    }
+
+    /// Check if the agent is running
+    pub async fn is_running(&self) -> bool {
+        self.state.get().await.is_alive()
+    }
+
+    /// Get exit information if the agent has exited
+    pub async fn exit_info(&self) -> Option<ProcessExit> {
+        let proc_guard = self.process.read().await;
+        if let Some(ref process) = *proc_guard {
+            process.exit_info().await
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for AgentSession {
+    fn drop(&mut self) {
+        // Signal shutdown
+        let _ = self.shutdown_tx.send(());
+    }
+}
+
+fn echo_error(error: PtyError) -> SessionError {
+    match error {
+        PtyError::ProcessExited => SessionError::NotRunning,
+        PtyError::ModesUnavailable => SessionError::EchoUnsupported,
+        e => SessionError::PtyError(e),
+    }
+}
+
+/// Record the echo state, passing it on if it differs from the last one
+fn publish_echo(
+    last: &Mutex<Option<EchoState>>,
+    echo_tx: &broadcast::Sender<EchoState>,
+    echo: EchoState,
+) {
+    let previous = last.lock().unwrap_or_else(|e| e.into_inner()).replace(echo);
+    if previous != Some(echo) {
+        let _ = echo_tx.send(echo);
+    }
+}
+
+/// Handle for receiving agent output asynchronously
+pub struct OutputReceiver {
+    rx: broadcast::Receiver<AgentOutput>,
+}
+
+impl OutputReceiver {
+    /// Create from a broadcast receiver
+    pub fn new(rx: broadcast::Receiver<AgentOutput>) -> Self {
+        Self { rx }
+    }
+
+    /// Receive the next output
+    pub async fn recv(&mut self) -> Option<AgentOutput> {
+        self.rx.recv().await.ok()
+    }
+}
+
+/// Add an event to a recording or transcript, if one is being made; one that
+/// can't be written to is stopped rather than failing the agent's I/O
+fn record<T>(recorder: &Mutex<Option<T>>, event: impl FnOnce(&mut T) -> std::io::Result<()>) {
+    let mut recorder = recorder.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(active) = recorder.as_mut() {
+        if event(active).is_err() {
+            *recorder = None;
+        }
+    }
+}
+
+/// Handle for receiving agent exit notification
+pub struct ExitReceiver {
+    rx: broadcast::Receiver<AgentExit>,
+}
+
+impl ExitReceiver {
+    /// Create from a broadcast receiver
+    pub fn new(rx: broadcast::Receiver<AgentExit>) -> Self {
+        Self { rx }
+    }
+
+    /// Wait for the agent to exit
+    pub async fn wait(&mut self) -> Option<AgentExit> {
+        self.rx.recv().await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spawn_config_new() {
+        let config = SpawnConfig::new("/test/path");
+        assert_eq!(config.project_path, "/test/path");
+        assert_eq!(config.cols, 80);
+        assert_eq!(config.rows, 24);
+        assert!(config.preset.is_none());
+        assert!(config.args.is_empty());
+        assert!(config.initial_prompt.is_none());
+    }
+
+    #[test]
+    fn test_spawn_config_with_size() {
+        let config = SpawnConfig::new("/test/path").with_size(120, 40);
+        assert_eq!(config.cols, 120);
+        assert_eq!(config.rows, 40);
+    }
+
+    #[test]
+    fn test_spawn_config_with_preset() {
+        let config = SpawnConfig::new("/test/path").with_preset("code-review");
+        assert_eq!(config.preset, Some("code-review".to_string()));
+    }
+
+    #[test]
+    fn test_spawn_config_with_args() {
+        let config = SpawnConfig::new("/test/path")
+            .with_args(vec!["--verbose".to_string(), "-p".to_string()]);
+        assert_eq!(config.args, vec!["--verbose", "-p"]);
+    }
+
+    #[test]
+    fn test_spawn_config_with_initial_prompt() {
+        let config = SpawnConfig::new("/test/path").with_initial_prompt("npm test");
+        assert_eq!(config.initial_prompt, Some("npm test".to_string()));
+    }
+
+    #[test]
+    fn test_spawn_config_apply_remote_preset() {
+        let preset = AgentPreset {
+            name: "remote".to_string(),
+            args: vec!["--verbose".to_string()],
+            initial_prompt: Some("check the build".to_string()),
+            ready_pattern: Some("^> ".to_string()),
+            ready_timeout_secs: Some(90),
+            host: Some("build-box".to_string()),
+            remote_dir: Some("/srv/app".to_string()),
+            macros: Vec::new(),
+            keybindings: None,
+            expect: Vec::new(),
+            triggers: Vec::new(),
+            secrets: Vec::new(),
+            env: BTreeMap::from([("LANG".to_string(), "C.UTF-8".to_string())]),
+            idle_timeout_mins: Some(30),
+            transcript: None,
+        };
+        let config = SpawnConfig::new("/test/path")
+            .apply_preset(&preset)
+            .with_env([("LANG".to_string(), "C".to_string())]);
+        // Variables set with the spawn override the preset's
+        assert_eq!(config.env["LANG"], "C");
+        assert_eq!(config.preset, Some("remote".to_string()));
+        assert_eq!(config.args, vec!["--verbose"]);
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(config.ready_pattern.as_deref(), Some("^> "));
+        assert_eq!(config.ready_timeout, Duration::from_secs(90));
+        let remote = config.remote.expect("remote target");
+        assert_eq!(remote.host, "build-box");
+        assert_eq!(remote.remote_dir, Some("/srv/app".to_string()));
+    }
+
+    #[test]
+    fn test_spawn_config_persistent_agent_id() {
+        let id = Uuid::new_v4();
+        let config = SpawnConfig::new("/test/path")
+            .with_persistence()
+            .with_agent_id(id);
+        assert!(config.persistent);
+        let session = AgentSession::with_config(config);
+        assert_eq!(session.id(), id);
+        assert!(session.is_persistent());
+    }
+
+    #[test]
+    fn test_spawn_config_preset_macros_override_project() {
+        let input_macro = |name: &str, input: &str| InputMacro {
+            name: name.to_string(),
+            input: input.to_string(),
+            submit: true,
+        };
+        let preset = AgentPreset {
+            name: "review".to_string(),
+            args: Vec::new(),
+            initial_prompt: None,
+            ready_pattern: None,
+            ready_timeout_secs: None,
+            host: None,
+            remote_dir: None,
+            macros: vec![input_macro("approve", "lgtm")],
+            keybindings: None,
+            expect: Vec::new(),
+            triggers: Vec::new(),
+            secrets: Vec::new(),
+            env: BTreeMap::new(),
+            idle_timeout_mins: None,
+            transcript: None,
+        };
+        let config = SpawnConfig::new("/test/path")
+            .with_macros(vec![input_macro("approve", "yes"), input_macro("test", "run tests")])
+            .apply_preset(&preset);
+
+        let session = AgentSession::with_config(config);
+        assert_eq!(session.macros().len(), 2);
+        assert_eq!(session.find_macro("approve").unwrap().expand(), "lgtm\n");
+        assert!(session.find_macro("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_rejects_invalid_expect_pattern() {
+        let dir = std::env::temp_dir();
+        let config = SpawnConfig::new(dir.to_string_lossy()).with_expect_rules(vec![ExpectRule {
+            pattern: "(unclosed".to_string(),
+            response: "y".to_string(),
+            submit: true,
+            max_uses: None,
+        }]);
+        let session = AgentSession::with_config(config);
+        assert_eq!(session.expect_rules().len(), 1);
+        assert!(matches!(session.spawn().await, Err(SessionError::SpawnFailed(_))));
+        assert_eq!(session.state().await, AgentState::Stopped);
+
+        let config = SpawnConfig::new(dir.to_string_lossy()).with_triggers(vec![TriggerRule {
+            name: "broken".to_string(),
+            pattern: "[".to_string(),
+        }]);
+        let session = AgentSession::with_config(config);
+        assert!(matches!(session.spawn().await, Err(SessionError::SpawnFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_secrets_only_for_local_agents() {
+        let dir = std::env::temp_dir();
+        let env: SecretEnv = [("API_KEY".to_string(), "secret".to_string())]
+            .into_iter()
+            .collect();
+        let config = SpawnConfig::new(dir.to_string_lossy())
+            .with_remote(SshTarget::new("build-box"))
+            .with_secret_env(env);
+        assert!(!format!("{:?}", config).contains("secret\""));
+
+        let session = AgentSession::with_config(config);
+        assert!(matches!(session.spawn().await, Err(SessionError::SpawnFailed(_))));
+        assert_eq!(session.state().await, AgentState::Stopped);
+
+        let config = SpawnConfig::new(dir.to_string_lossy())
+            .with_persistence()
+            .with_env([("LANG".to_string(), "C".to_string())]);
+        let session = AgentSession::with_config(config);
+        assert!(matches!(session.spawn().await, Err(SessionError::SpawnFailed(_))));
+    }
+
+    #[test]
+    fn test_agent_session_new() {
+        let session = AgentSession::new("/test/path");
+        assert_eq!(session.project_path(), "/test/path");
+        assert_eq!(session.cols(), 80);
+        assert_eq!(session.rows(), 24);
+    }
+
+    #[test]
+    fn test_agent_session_with_config() {
+        let config = SpawnConfig::new("/test/path")
+            .with_size(100, 50)
+            .with_preset("test");
+        let session = AgentSession::with_config(config);
+        assert_eq!(session.project_path(), "/test/path");
+        assert_eq!(session.cols(), 100);
+        assert_eq!(session.rows(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_agent_session_initial_state() {
+        let session = AgentSession::new("/test/path");
+        assert_eq!(session.state().await, AgentState::Stopped);
+        assert!(!session.is_running().await);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_invalid_path() {
+        let session = AgentSession::new("/nonexistent/path/that/does/not/exist");
+        let result = session.spawn().await;
+        assert!(result.is_err());
+        match result {
+            Err(SessionError::InvalidPath(_)) => {}
+            _ => panic!("Expected InvalidPath error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_input_not_running() {
+        let session = AgentSession::new("/tmp");
+        let result = session.write_input(b"test").await;
+        assert!(result.is_err());
+        match result {
+            Err(SessionError::NotRunning) => {}
+            _ => panic!("Expected NotRunning error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_output() {
+        let session = AgentSession::new("/tmp");
+        let _rx = session.subscribe_output();
+        // Just test that we can subscribe
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_exit() {
+        let session = AgentSession::new("/tmp");
+        let _rx = session.subscribe_exit();
+        // Just test that we can subscribe
+    }
+
+    #[test]
+    fn test_state_transitions() {
+        use AgentState::*;
+
+        assert_eq!(transition(Running, Activity::Output), Some(Busy));
+        assert_eq!(transition(Busy, Activity::Quiet), Some(Idle));
+        assert_eq!(transition(Idle, Activity::Output), Some(Busy));
+        assert_eq!(transition(Busy, Activity::Prompt), Some(WaitingForInput));
+        // A pending question outlasts further output and silence
+        assert_eq!(transition(WaitingForInput, Activity::Output), None);
+        assert_eq!(transition(WaitingForInput, Activity::Quiet), None);
+        assert_eq!(transition(WaitingForInput, Activity::Input), Some(Busy));
+        // Readiness holds through redraws until the first command is sent
+        assert_eq!(transition(Busy, Activity::Ready), Some(Ready));
+        assert_eq!(transition(Ready, Activity::Output), None);
+        assert_eq!(transition(Ready, Activity::Quiet), None);
+        assert_eq!(transition(Ready, Activity::Input), Some(Running));
+        assert_eq!(transition(WaitingForInput, Activity::Ready), None);
+        // Paused and stopping agents are left alone
+        assert_eq!(transition(Paused, Activity::Output), None);
+        assert_eq!(transition(Stopping, Activity::Output), None);
+    }
+
+    async fn next_state(changes: &mut broadcast::Receiver<StateChange>) -> AgentState {
+        tokio::time::timeout(Duration::from_secs(2), changes.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .new_state
+    }
+
+    #[tokio::test]
+    async fn test_state_changes_from_output() {
+        use crate::pty::{PtyScript, ScriptedPtyBackend};
+
+        let script = PtyScript::echo().on_input("rm", "Remove file? (y/n) ");
+        let config = SpawnConfig::new(std::env::temp_dir().display().to_string())
+            .with_idle_after(Duration::from_millis(50));
+        let session = AgentSession::with_config(config)
+            .with_pty_backend(Arc::new(ScriptedPtyBackend::new(script)));
+        let mut changes = session.subscribe_state();
+
+        session.spawn().await.unwrap();
+        assert_eq!(next_state(&mut changes).await, AgentState::Starting);
+        assert_eq!(next_state(&mut changes).await, AgentState::Running);
+        assert!(session.is_running().await);
+
+        // Nothing printed yet, so the agent goes idle
+        assert_eq!(next_state(&mut changes).await, AgentState::Idle);
+        session.write_str("ls\n").await.unwrap();
+        assert_eq!(next_state(&mut changes).await, AgentState::Busy);
+        assert_eq!(next_state(&mut changes).await, AgentState::Idle);
+
+        session.write_str("rm\n").await.unwrap();
+        assert_eq!(next_state(&mut changes).await, AgentState::Busy);
+        assert_eq!(next_state(&mut changes).await, AgentState::WaitingForInput);
+        session.write_str("y\n").await.unwrap();
+        assert_eq!(next_state(&mut changes).await, AgentState::Busy);
+    }
+
+    #[tokio::test]
+    async fn test_output_in_whole_characters() {
+        use crate::pty::{PtyScript, ScriptedPtyBackend};
+
+        let config = SpawnConfig::new(std::env::temp_dir().display().to_string());
+        let session = AgentSession::with_config(config)
+            .with_pty_backend(Arc::new(ScriptedPtyBackend::new(PtyScript::echo())));
+        let mut output = session.subscribe_output();
+
+        session.spawn().await.unwrap();
+        // Echoed back in two reads, splitting the check mark
+        session.write_input(b"ok \xe2\x9c").await.unwrap();
+        session.write_input(b"\x93").await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < "ok ✓".len() {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), output.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(std::str::from_utf8(&chunk.data).is_ok());
+            received.extend(chunk.data);
+        }
+        assert_eq!(received, "ok ✓".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_quiet_after_input_without_output() {
+        use crate::pty::{PtyScript, ScriptedPtyBackend};
+
+        let script = PtyScript::new().with_output("> ");
+        let config = SpawnConfig::new(std::env::temp_dir().display().to_string())
+            .with_ready_pattern("> $")
+            .with_idle_after(Duration::from_millis(50));
+        let session = AgentSession::with_config(config)
+            .with_pty_backend(Arc::new(ScriptedPtyBackend::new(script)));
+        let mut changes = session.subscribe_state();
+
+        session.spawn().await.unwrap();
+        assert_eq!(next_state(&mut changes).await, AgentState::Starting);
+        assert_eq!(next_state(&mut changes).await, AgentState::Running);
+        assert_eq!(next_state(&mut changes).await, AgentState::Busy);
+        assert_eq!(next_state(&mut changes).await, AgentState::Ready);
+
+        // The agent prints nothing more, yet still goes idle once it has
+        // been given a command
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        session.write_str("ls\n").await.unwrap();
+        assert_eq!(next_state(&mut changes).await, AgentState::Running);
+        assert_eq!(next_state(&mut changes).await, AgentState::Idle);
+    }
+
+    async fn next_echo(echo: &mut broadcast::Receiver<EchoState>) -> EchoState {
+        tokio::time::timeout(Duration::from_secs(5), echo.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_echo_reports() {
+        use crate::pty::{PtyScript, ScriptedPtyBackend};
+
+        let script = PtyScript::echo().with_output("$ ").raw_on("vim");
+        let config =
+            SpawnConfig::new(std::env::temp_dir().display().to_string()).with_echo_reports();
+        let session = AgentSession::with_config(config)
+            .with_pty_backend(Arc::new(ScriptedPtyBackend::new(script)));
+        let mut echo = session.subscribe_echo();
+        let cooked = EchoState {
+            echo: true,
+            raw: false,
+        };
+        let raw = EchoState {
+            echo: false,
+            raw: true,
+        };
+
+        session.spawn().await.unwrap();
+        assert_eq!(next_echo(&mut echo).await, cooked);
+        // The program switches to raw mode itself
+        session.write_str("vim\r").await.unwrap();
+        assert_eq!(next_echo(&mut echo).await, raw);
+        assert_eq!(session.echo_state().await.unwrap(), raw);
+        // A switch made through the session is reported once
+        assert_eq!(session.set_raw_mode(false).await.unwrap(), cooked);
+        assert_eq!(next_echo(&mut echo).await, cooked);
+        session.write_str("ls\r").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(echo.try_recv().is_err());
+
+        // Remote agents' terminals belong to ssh
+        let config = SpawnConfig::new("/srv/app").with_remote(SshTarget::new("build-box"));
+        let remote = AgentSession::with_config(config);
+        assert!(matches!(
+            remote.echo_state().await,
+            Err(SessionError::EchoUnsupported)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_transcript() {
+        use super::super::transcript_path;
+        use crate::pty::{PtyScript, ScriptedPtyBackend};
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = PtyScript::echo().with_output("Welcome\r\n");
+        let transcript = TranscriptConfig {
+            input: true,
+            ..Default::default()
+        };
+        let config = SpawnConfig::new(dir.path().display().to_string()).with_transcript(transcript);
+        let session = AgentSession::with_config(config)
+            .with_pty_backend(Arc::new(ScriptedPtyBackend::new(script)));
+        let mut output = session.subscribe_output();
+
+        session.spawn().await.unwrap();
+        session.write_str("ls\n").await.unwrap();
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(5), output.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let logged = std::fs::read_to_string(transcript_path(dir.path(), session.id())).unwrap();
+        assert!(logged.starts_with("Welcome\r\n"), "Logged {:?}", logged);
+        let input = dir.path().join(format!(".hoc/logs/{}.input.log", session.id()));
+        assert_eq!(std::fs::read_to_string(input).unwrap(), "ls\n");
+    }
+}
@@ -0,0 +1,143 @@
+//! Shell integration marks
+//!
+//! Shells set up for shell integration emit OSC 133 "semantic prompt"
+//! sequences around each prompt and command, and the exit status when a
+//! command finishes (`ESC ] 133 ; D ; 1 BEL`). When an agent's output carries
+//! these marks, its commands are reported with exact boundaries instead of
+//! being guessed from the text.
+
+/// Introducer of an OSC 133 sequence
+const OSC_133: &[u8] = b"\x1b]133;";
+
+/// Longest unterminated mark kept across reads; real marks are a few bytes
+const MAX_MARK: usize = 256;
+
+/// A command boundary reported by the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandMark {
+    /// A command was entered and started running
+    Started,
+    /// The running command finished
+    Finished { exit_code: Option<i32> },
+}
+
+/// Tracks command boundaries from OSC 133 marks in output
+#[derive(Debug, Default)]
+pub struct ShellMarks {
+    /// Start of a mark split across reads
+    partial: Vec<u8>,
+    /// Whether a command is running
+    running: bool,
+}
+
+impl ShellMarks {
+    /// Feed output, returning the command boundaries it contains
+    pub fn feed(&mut self, output: &[u8]) -> Vec<CommandMark> {
+        let mut data = std::mem::take(&mut self.partial);
+        data.extend_from_slice(output);
+
+        let mut marks = Vec::new();
+        let mut pos = 0;
+        while let Some(start) = find(&data[pos..], OSC_133).map(|i| pos + i) {
+            let body = start + OSC_133.len();
+            let Some((len, terminator)) = terminator(&data[body..]) else {
+                if data.len() - start <= MAX_MARK {
+                    self.partial = data[start..].to_vec();
+                }
+                return marks;
+            };
+            marks.extend(self.mark(&data[body..body + len]));
+            pos = body + len + terminator;
+        }
+
+        // Keep a trailing piece of the introducer for the next read
+        let tail = &data[pos..];
+        if let Some(n) = (1..OSC_133.len()).rev().find(|&n| tail.ends_with(&OSC_133[..n])) {
+            self.partial = OSC_133[..n].to_vec();
+        }
+        marks
+    }
+
+    /// Handle one mark's parameters, e.g. `D;0`
+    fn mark(&mut self, body: &[u8]) -> Option<CommandMark> {
+        let body = String::from_utf8_lossy(body);
+        let mut params = body.split(';');
+        match params.next()? {
+            // Output of the command begins
+            "C" if !self.running => {
+                self.running = true;
+                Some(CommandMark::Started)
+            }
+            "D" if self.running => {
+                self.running = false;
+                let exit_code = params.next().and_then(|code| code.trim().parse().ok());
+                Some(CommandMark::Finished { exit_code })
+            }
+            // A new prompt without a `D` for the last command
+            "A" if self.running => {
+                self.running = false;
+                Some(CommandMark::Finished { exit_code: None })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Position of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Length of a mark's parameters and of its BEL or ST terminator, `None` if
+/// the terminator hasn't arrived yet
+fn terminator(data: &[u8]) -> Option<(usize, usize)> {
+    let end = data.iter().position(|&b| b == 0x07 || b == 0x1b)?;
+    match (data[end], data.get(end + 1)) {
+        (0x07, _) => Some((end, 1)),
+        (_, Some(b'\\')) => Some((end, 2)),
+        // A stray escape ends the mark
+        (_, Some(_)) => Some((end, 0)),
+        (_, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_boundaries() {
+        let mut marks = ShellMarks::default();
+        assert_eq!(marks.feed(b"\x1b]133;A\x07$ \x1b]133;B\x07"), vec![]);
+        assert_eq!(
+            marks.feed(b"cargo test\r\n\x1b]133;C\x07running 3 tests\r\n"),
+            vec![CommandMark::Started]
+        );
+        assert_eq!(
+            marks.feed(b"\x1b]133;D;101\x1b\\\x1b]133;A\x07$ "),
+            vec![CommandMark::Finished {
+                exit_code: Some(101)
+            }]
+        );
+        // A prompt with no command in between finishes nothing
+        assert_eq!(marks.feed(b"\x1b]133;D;0\x07\x1b]133;A\x07"), vec![]);
+    }
+
+    #[test]
+    fn test_marks_split_across_reads() {
+        let mut marks = ShellMarks::default();
+        assert_eq!(marks.feed(b"ls\r\n\x1b]1"), vec![]);
+        assert_eq!(marks.feed(b"33;C\x07file\r\n\x1b]133;D;"), vec![CommandMark::Started]);
+        assert_eq!(
+            marks.feed(b"2\x07"),
+            vec![CommandMark::Finished { exit_code: Some(2) }]
+        );
+
+        // A new prompt ends a command the shell didn't report finishing
+        marks.feed(b"\x1b]133;C\x07");
+        assert_eq!(
+            marks.feed(b"\x1b]133;A;cl=m\x07"),
+            vec![CommandMark::Finished { exit_code: None }]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! Claude's JSON event stream
+//!
+//! Agents spawned in `stream_json` mode run `claude -p` non-interactively
+//! with `--output-format stream-json`, which prints one JSON object per line
+//! instead of drawing a terminal UI. The lines are parsed into typed events,
+//! the assistant's text, the tools it uses and their results, and what the
+//! run cost, so clients can show the work without interpreting ANSI output.
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Arguments that make `claude` answer `prompt` as a JSON event stream
+pub fn stream_json_args(prompt: &str) -> Vec<String> {
+    ["-p", prompt, "--output-format", "stream-json", "--verbose"]
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Lines longer than this are dropped rather than buffered
+const MAX_LINE_BYTES: usize = 8 * 1024 * 1024;
+
+/// An event from Claude's JSON stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// Text the assistant wrote
+    AssistantText { text: String },
+    /// The assistant called a tool
+    ToolUse {
+        /// ID the tool's result refers back to
+        id: String,
+        name: String,
+        input: Value,
+    },
+    /// A tool call finished
+    ToolResult {
+        tool_use_id: String,
+        /// Text the tool returned
+        content: String,
+        is_error: bool,
+    },
+    /// The run finished, with what it cost
+    Cost {
+        total_cost_usd: f64,
+        duration_ms: u64,
+        num_turns: u32,
+        input_tokens: u64,
+        output_tokens: u64,
+    },
+}
+
+/// One line of the stream; only the parts events are made of are read
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line {
+    Assistant {
+        message: Message,
+    },
+    User {
+        message: Message,
+    },
+    Result {
+        #[serde(default)]
+        total_cost_usd: f64,
+        #[serde(default)]
+        duration_ms: u64,
+        #[serde(default)]
+        num_turns: u32,
+        #[serde(default)]
+        usage: Usage,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    #[serde(default)]
+    content: Content,
+}
+
+/// Message content: a list of blocks, or plain text without any events
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Content {
+    Blocks(Vec<Block>),
+    Text(IgnoredAny),
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Content::Blocks(Vec::new())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default)]
+        content: Value,
+        #[serde(default)]
+        is_error: bool,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Usage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+}
+
+/// Splits an agent's output into lines and parses them into events
+#[derive(Debug, Default)]
+pub struct StreamJsonParser {
+    line: Vec<u8>,
+    /// Whether the current line grew too long and is being skipped
+    overlong: bool,
+}
+
+impl StreamJsonParser {
+    /// Feed output, returning the events of the lines it completed
+    pub fn feed(&mut self, output: &[u8]) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        for chunk in output.split_inclusive(|&b| b == b'\n') {
+            if !self.overlong {
+                self.line.extend_from_slice(chunk);
+                if self.line.len() > MAX_LINE_BYTES {
+                    self.line.clear();
+                    self.overlong = true;
+                }
+            }
+            if chunk.ends_with(b"\n") {
+                if !self.overlong {
+                    events.extend(parse_line(&self.line));
+                }
+                self.line.clear();
+                self.overlong = false;
+            }
+        }
+        events
+    }
+}
+
+/// Events of one line of the stream; lines that aren't JSON are skipped
+fn parse_line(line: &[u8]) -> Vec<StreamEvent> {
+    let line = line.trim_ascii();
+    let Ok(line) = serde_json::from_slice::<Line>(line) else {
+        return Vec::new();
+    };
+    match line {
+        Line::Assistant { message } | Line::User { message } => match message.content {
+            Content::Blocks(blocks) => blocks.into_iter().filter_map(block_event).collect(),
+            Content::Text(_) => Vec::new(),
+        },
+        Line::Result {
+            total_cost_usd,
+            duration_ms,
+            num_turns,
+            usage,
+        } => vec![StreamEvent::Cost {
+            total_cost_usd,
+            duration_ms,
+            num_turns,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        }],
+        Line::Other => Vec::new(),
+    }
+}
+
+fn block_event(block: Block) -> Option<StreamEvent> {
+    match block {
+        Block::Text { text } if !text.is_empty() => Some(StreamEvent::AssistantText { text }),
+        Block::ToolUse { id, name, input } => Some(StreamEvent::ToolUse { id, name, input }),
+        Block::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+        } => Some(StreamEvent::ToolResult {
+            tool_use_id,
+            content: result_text(content),
+            is_error,
+        }),
+        Block::Text { .. } | Block::Other => None,
+    }
+}
+
+/// A tool result's text, given as a string or as a list of text blocks
+fn result_text(content: Value) -> String {
+    match content {
+        Value::String(text) => text,
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block.get("text")?.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stream() {
+        let stream = concat!(
+            r#"{"type":"system","subtype":"init","session_id":"abc","tools":["Bash"]}"#,
+            "\r\n",
+            r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Running tests"},"#,
+            r#"{"type":"tool_use","id":"toolu_1","name":"Bash","#,
+            r#""input":{"command":"cargo test"}}]}}"#,
+            "\r\n",
+            r#"{"type":"user","message":{"content":[{"type":"tool_result","#,
+            r#""tool_use_id":"toolu_1","#,
+            r#""content":[{"type":"text","text":"ok"}],"is_error":false}]}}"#,
+            "\r\n",
+            r#"{"type":"result","subtype":"success","total_cost_usd":0.0125,"duration_ms":4200,"#,
+            r#""num_turns":2,"usage":{"input_tokens":900,"output_tokens":120}}"#,
+            "\r\n",
+        );
+        let mut parser = StreamJsonParser::default();
+        // Lines are parsed once complete, however the output is split
+        let (first, rest) = stream.as_bytes().split_at(100);
+        let mut events = parser.feed(first);
+        events.extend(parser.feed(rest));
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[0],
+            StreamEvent::AssistantText {
+                text: "Running tests".to_string()
+            }
+        );
+        let StreamEvent::ToolUse { name, input, .. } = &events[1] else {
+            panic!("expected a tool use, got {:?}", events[1]);
+        };
+        assert_eq!(name, "Bash");
+        assert_eq!(input["command"], "cargo test");
+        assert!(matches!(&events[2], StreamEvent::ToolResult { content, .. } if content == "ok"));
+        assert!(matches!(
+            events[3],
+            StreamEvent::Cost {
+                num_turns: 2,
+                output_tokens: 120,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_skips_other_output() {
+        let mut parser = StreamJsonParser::default();
+        assert!(parser.feed(b"Error: not logged in\r\n").is_empty());
+        assert!(parser
+            .feed(b"{\"type\":\"user\",\"message\":{\"content\":\"hi\"}}\n")
+            .is_empty());
+
+        let mut long = vec![b'x'; MAX_LINE_BYTES + 1];
+        long.push(b'\n');
+        assert!(parser.feed(&long).is_empty());
+        let result = parser.feed(b"{\"type\":\"result\",\"total_cost_usd\":1.5}\n");
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_args() {
+        let args = stream_json_args("fix the build");
+        assert_eq!(args[..2], ["-p", "fix the build"]);
+        assert!(args.contains(&"stream-json".to_string()));
+    }
+}
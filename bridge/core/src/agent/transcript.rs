@@ -0,0 +1,165 @@
+//! Agent transcripts
+//!
+//! With transcripts enabled, everything an agent prints is appended as is to
+//! `.hoc/logs/<agent-id>.log` in its project, and optionally the input it is
+//! sent to `<agent-id>.input.log`, so a session can be audited after every
+//! client has gone. Unlike recordings they need no client to start them and
+//! keep no timing. A log that reaches its size limit is rotated to `.log.1`,
+//! `.log.2` and so on, dropping the oldest.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::config::{TranscriptConfig, CONFIG_DIR};
+
+/// Directory under the project's `.hoc` directory holding transcripts
+const LOGS_DIR: &str = "logs";
+
+/// Directory holding a project's transcripts
+pub fn transcripts_dir(project_path: &Path) -> PathBuf {
+    project_path.join(CONFIG_DIR).join(LOGS_DIR)
+}
+
+/// Path of an agent's output transcript
+pub fn transcript_path(project_path: &Path, agent_id: Uuid) -> PathBuf {
+    transcripts_dir(project_path).join(format!("{}.log", agent_id))
+}
+
+/// A log file that is rotated when it grows too large
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, config: &TranscriptConfig) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes: config.max_bytes.max(1),
+            keep: config.keep,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + data.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Shift the rotated logs up by one and start an empty log
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match std::fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Appends an agent's output, and optionally its input, to its transcripts
+#[derive(Debug)]
+pub struct Transcript {
+    output: RotatingFile,
+    input: Option<RotatingFile>,
+}
+
+impl Transcript {
+    /// Open an agent's transcripts, appending to those of an earlier run
+    pub fn open(
+        project_path: &Path,
+        agent_id: Uuid,
+        config: &TranscriptConfig,
+    ) -> io::Result<Self> {
+        std::fs::create_dir_all(transcripts_dir(project_path))?;
+        let output = RotatingFile::open(transcript_path(project_path, agent_id), config)?;
+        let input = if config.input {
+            let path = transcripts_dir(project_path).join(format!("{}.input.log", agent_id));
+            Some(RotatingFile::open(path, config)?)
+        } else {
+            None
+        };
+        Ok(Self { output, input })
+    }
+
+    /// Log output
+    pub fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        self.output.write(data)
+    }
+
+    /// Log input, if input is logged
+    pub fn input(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.input {
+            Some(input) => input.write(data),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript_and_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent_id = Uuid::new_v4();
+        let config = TranscriptConfig {
+            input: true,
+            max_bytes: 10,
+            keep: 2,
+        };
+
+        let mut transcript = Transcript::open(dir.path(), agent_id, &config).unwrap();
+        transcript.output(b"\x1b[1mhello").unwrap();
+        transcript.input(b"ls\n").unwrap();
+        let path = transcript_path(dir.path(), agent_id);
+        assert_eq!(std::fs::read(&path).unwrap(), b"\x1b[1mhello");
+        let input = transcripts_dir(dir.path()).join(format!("{}.input.log", agent_id));
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), "ls\n");
+
+        // Writes past the limit start a new log, keeping two old ones
+        for chunk in ["aaaaaa", "bbbbbb", "cccccc"] {
+            transcript.output(chunk.as_bytes()).unwrap();
+        }
+        let rotated = |n: u32| format!("{}.{}", path.display(), n);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "cccccc");
+        assert_eq!(std::fs::read_to_string(rotated(1)).unwrap(), "bbbbbb");
+        assert_eq!(std::fs::read_to_string(rotated(2)).unwrap(), "aaaaaa");
+        assert!(!Path::new(&rotated(3)).exists());
+
+        // A restarted agent appends to its transcript
+        let config = TranscriptConfig::default();
+        let mut transcript = Transcript::open(dir.path(), agent_id, &config).unwrap();
+        transcript.output(b"!").unwrap();
+        transcript.input(b"ignored").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "cccccc!");
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), "ls\n");
+    }
+}
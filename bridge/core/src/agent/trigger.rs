@@ -0,0 +1,155 @@
+//! Output triggers
+//!
+//! Presets name patterns worth reacting to, like "tests passed" or "error:",
+//! and clients are told whenever one appears in an agent's output, with the
+//! text its capture groups matched. A VR client can then flash or chime
+//! without parsing the terminal itself. Output is matched as clients see
+//! it, redacted and with escape sequences removed, and every occurrence
+//! fires once.
+
+use std::collections::BTreeMap;
+
+use regex::Regex;
+
+use super::expect::{push_window, OUTPUT_WINDOW};
+use crate::config::TriggerRule;
+
+/// A trigger that matched an agent's output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriggerMatch {
+    /// Name of the trigger
+    pub trigger: String,
+    /// Text matched by the pattern's capture groups, by group name, or by
+    /// number for unnamed groups
+    pub captures: BTreeMap<String, String>,
+}
+
+/// A compiled trigger and how far into the window it has matched
+#[derive(Debug)]
+struct ActiveTrigger {
+    name: String,
+    pattern: Regex,
+    /// Window offset from which the next occurrence is looked for
+    from: usize,
+}
+
+/// Matches agent output against a preset's triggers
+#[derive(Debug, Default)]
+pub struct Triggers {
+    triggers: Vec<ActiveTrigger>,
+    window: String,
+}
+
+impl Triggers {
+    /// Compile a set of triggers
+    pub fn new(rules: &[TriggerRule]) -> Result<Self, regex::Error> {
+        let triggers = rules
+            .iter()
+            .map(|rule| {
+                Ok(ActiveTrigger {
+                    name: rule.name.clone(),
+                    pattern: Regex::new(&rule.pattern)?,
+                    from: 0,
+                })
+            })
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Self {
+            triggers,
+            window: String::new(),
+        })
+    }
+
+    /// Feed output, returning the triggers it fired, in order of appearance
+    /// for each trigger
+    pub fn feed(&mut self, output: &[u8]) -> Vec<TriggerMatch> {
+        if self.triggers.is_empty() {
+            return Vec::new();
+        }
+
+        let dropped = push_window(&mut self.window, output, OUTPUT_WINDOW);
+        let mut fired = Vec::new();
+        for trigger in &mut self.triggers {
+            trigger.from = trigger.from.saturating_sub(dropped);
+            while !self.window.is_char_boundary(trigger.from) {
+                trigger.from += 1;
+            }
+            while let Some(captures) = trigger.pattern.captures_at(&self.window, trigger.from) {
+                let whole = captures.get(0).expect("group 0 is the whole match");
+                // Empty matches would fire forever at the same spot
+                if whole.is_empty() {
+                    match self.window[whole.end()..].chars().next() {
+                        Some(c) => trigger.from = whole.end() + c.len_utf8(),
+                        None => break,
+                    }
+                    continue;
+                }
+                trigger.from = whole.end();
+                fired.push(TriggerMatch {
+                    trigger: trigger.name.clone(),
+                    captures: capture_map(&trigger.pattern, &captures),
+                });
+            }
+        }
+        fired
+    }
+}
+
+/// Text of the capture groups that took part in a match
+fn capture_map(pattern: &Regex, captures: &regex::Captures<'_>) -> BTreeMap<String, String> {
+    pattern
+        .capture_names()
+        .enumerate()
+        .skip(1)
+        .filter_map(|(index, name)| {
+            let text = captures.get(index)?.as_str().to_string();
+            let key = name.map_or_else(|| index.to_string(), str::to_string);
+            Some((key, text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, pattern: &str) -> TriggerRule {
+        TriggerRule {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fires_once_per_occurrence() {
+        let mut triggers = Triggers::new(&[
+            rule("tests-passed", r"test result: ok\. (?<passed>\d+) passed"),
+            rule("error", r"error(?:\[(E\d+)\])?:"),
+        ])
+        .unwrap();
+        let fired = triggers.feed(b"\x1b[31merror[E0308]\x1b[0m: mismatched types\r\nerror: ");
+        assert_eq!(fired.len(), 2);
+        assert_eq!(fired[0].trigger, "error");
+        assert_eq!(fired[0].captures["1"], "E0308");
+        assert!(fired[1].captures.is_empty());
+
+        // Matched across reads, and not again on later output
+        assert!(triggers.feed(b"test result: ok. 1").is_empty());
+        let fired = triggers.feed(b"2 passed; 0 failed\r\n");
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].captures["passed"], "12");
+        assert!(triggers.feed(b"done\r\n").is_empty());
+    }
+
+    #[test]
+    fn test_window_overflow() {
+        let mut triggers = Triggers::new(&[rule("done", "done")]).unwrap();
+        assert_eq!(triggers.feed(b"done").len(), 1);
+        assert!(triggers.feed(&vec![b'.'; OUTPUT_WINDOW]).is_empty());
+        assert_eq!(triggers.feed(b"done").len(), 1);
+        // Patterns matching nothing don't fire, or loop
+        let mut empty = Triggers::new(&[rule("empty", "x*")]).unwrap();
+        assert!(empty.feed(b"ab").is_empty());
+        assert_eq!(empty.feed(b"x").len(), 1);
+        assert!(Triggers::new(&[rule("bad", "(")]).is_err());
+    }
+}
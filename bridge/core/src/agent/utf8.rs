@@ -0,0 +1,87 @@
+//! UTF-8 reassembly of agent output
+//!
+//! The terminal is read in fixed-size chunks, so a character encoded in
+//! several bytes can be cut in two between reads. Decoded on its own, each
+//! half turns into a replacement character, in what clients are shown as
+//! much as in recordings and the text patterns are matched against. Output
+//! is therefore passed on only up to the last complete character, and the
+//! bytes of a character cut short wait for the rest of it.
+
+/// Holds back characters split between chunks of output
+#[derive(Debug, Default)]
+pub struct Utf8Reassembler {
+    /// Start of a character whose remaining bytes are still to come
+    partial: Vec<u8>,
+}
+
+impl Utf8Reassembler {
+    /// Take a chunk of output, returning it with the start of a character
+    /// held back from the previous chunk in front, and without the start of
+    /// a character it ends in
+    ///
+    /// Bytes that are not UTF-8 at all are passed on as they are.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.partial);
+        data.extend_from_slice(chunk);
+        let complete = data.len() - incomplete_suffix(&data);
+        self.partial = data.split_off(complete);
+        data
+    }
+
+    /// Take whatever is held back, once no more output follows
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.partial)
+    }
+}
+
+/// Length of the character cut short at the end of `data`, if it ends in one
+fn incomplete_suffix(data: &[u8]) -> usize {
+    let mut start = 0;
+    loop {
+        match std::str::from_utf8(&data[start..]) {
+            Ok(_) => return 0,
+            // Invalid bytes can't be completed by what follows
+            Err(e) => match e.error_len() {
+                Some(invalid) => start += e.valid_up_to() + invalid,
+                None => return data.len() - start - e.valid_up_to(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_characters() {
+        let text = "tests ✓ passed 🎉".as_bytes();
+        // Text split at every position reaches the other side intact
+        for split in 0..=text.len() {
+            let mut utf8 = Utf8Reassembler::default();
+            let mut out = utf8.push(&text[..split]);
+            assert!(std::str::from_utf8(&out).is_ok());
+            out.extend(utf8.push(&text[split..]));
+            assert_eq!(out, text);
+            assert!(utf8.finish().is_empty());
+        }
+
+        // A character split three ways
+        let mut utf8 = Utf8Reassembler::default();
+        assert!(utf8.push(&[0xf0, 0x9f]).is_empty());
+        assert!(utf8.push(&[0x8e]).is_empty());
+        assert_eq!(utf8.push(&[0x89, b'!']), "🎉!".as_bytes());
+    }
+
+    #[test]
+    fn test_invalid_bytes_pass() {
+        let mut utf8 = Utf8Reassembler::default();
+        assert_eq!(utf8.push(b"ok \xff\xfe"), b"ok \xff\xfe");
+        // An invalid byte before the end doesn't hide a split character
+        assert_eq!(utf8.push(b"\xff a \xe2\x9c"), b"\xff a ");
+        assert_eq!(utf8.push(b"\x93"), "✓".as_bytes());
+        // A character never completed is handed over at the end
+        assert!(utf8.push(b"\xe2").is_empty());
+        assert_eq!(utf8.finish(), b"\xe2");
+    }
+}
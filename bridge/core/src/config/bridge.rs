@@ -0,0 +1,242 @@
+//! Bridge configuration file
+//!
+//! Server-wide settings read at startup from `~/.config/hoc/bridge.toml`, or
+//! the file given with `--config`, so a bridge needn't be run with a long
+//! command line. Command-line flags override the file.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::{AgentPreset, ConfigError, ScheduleConfig, TranscriptConfig, WebhookConfig};
+use crate::protocol::Role;
+
+/// Directory below the user's configuration directory holding the file
+pub const BRIDGE_CONFIG_DIR: &str = "hoc";
+/// Configuration file name
+pub const BRIDGE_CONFIG_FILE: &str = "bridge.toml";
+
+/// Where log messages go and how many
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Level or filter directives, e.g. `debug` or `info,hoc_bridge=trace`
+    #[serde(default)]
+    pub level: Option<String>,
+    /// File log messages are appended to instead of standard output
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+/// A token clients may authenticate with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub token: String,
+    /// What clients presenting the token may do
+    pub role: Role,
+    /// Who the token was issued to, for the logs
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Bridge configuration
+///
+/// Unset values fall back to the command line's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeConfig {
+    /// Address to bind to
+    #[serde(default)]
+    pub bind: Option<String>,
+    /// Port to listen on
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Authentication token for remote connections, granting the admin role
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Further tokens, each with its own role
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    /// Directories clients may access files under
+    #[serde(default)]
+    pub allowed_roots: Vec<PathBuf>,
+    /// Origins of browser pages allowed to connect, `*` for any
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Host names browsers may reach the bridge by, besides IP addresses and
+    /// `localhost`
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// Terminal size for clients that don't request one, as `COLSxROWS`
+    #[serde(default)]
+    pub default_terminal_size: Option<String>,
+    /// Largest terminal clients may request, as `COLSxROWS`
+    #[serde(default)]
+    pub max_terminal_size: Option<String>,
+    /// Maximum agents running at once
+    #[serde(default)]
+    pub max_agents: Option<usize>,
+    /// Maximum agents running at once for each client
+    #[serde(default)]
+    pub max_agents_per_client: Option<usize>,
+    /// File connections, spawns, input, kills and configuration changes
+    /// are recorded in
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+    /// Transcript logging of agents' terminals to `.hoc/logs`
+    #[serde(default)]
+    pub transcript: Option<TranscriptConfig>,
+    /// Log level and destination
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Presets available in every project, besides the project's own
+    #[serde(default)]
+    pub presets: Vec<AgentPreset>,
+    /// Agents spawned at set times
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+    /// URLs agent events are POSTed to
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl BridgeConfig {
+    /// The file read when none is given: `$XDG_CONFIG_HOME/hoc/bridge.toml`,
+    /// else `~/.config/hoc/bridge.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".config")))?;
+        Some(config_dir.join(BRIDGE_CONFIG_DIR).join(BRIDGE_CONFIG_FILE))
+    }
+
+    /// Load configuration from a file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    /// Parse configuration, expanding a leading `~/` in paths
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let mut config: Self = toml::from_str(content)?;
+        for root in &mut config.allowed_roots {
+            *root = expand_home(root);
+        }
+        if let Some(file) = &mut config.logging.file {
+            *file = expand_home(file);
+        }
+        if let Some(file) = &mut config.audit_log {
+            *file = expand_home(file);
+        }
+        for schedule in &mut config.schedules {
+            schedule.project_path = expand_home(Path::new(&schedule.project_path))
+                .display()
+                .to_string();
+        }
+        for webhook in &mut config.webhooks {
+            for project in &mut webhook.projects {
+                *project = expand_home(Path::new(project)).display().to_string();
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Replace a leading `~` with the user's home directory
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bridge_config() {
+        let config = BridgeConfig::parse(
+            r#"
+bind = "0.0.0.0"
+port = 9100
+token = "secret"
+allowed_roots = ["/srv/projects"]
+default_terminal_size = "120x40"
+max_agents = 8
+audit_log = "/var/log/hoc-audit.jsonl"
+
+[[tokens]]
+token = "watch-only"
+role = "observer"
+name = "wall display"
+
+[transcript]
+max_bytes = 1048576
+
+[logging]
+level = "debug"
+file = "/var/log/hoc-bridge.log"
+
+[[presets]]
+name = "reviewer"
+initial_prompt = "Review the latest commit"
+
+[[schedules]]
+name = "nightly-review"
+cron = "0 3 * * *"
+project_path = "/srv/projects/app"
+preset = "reviewer"
+
+[[webhooks]]
+url = "https://ci.example.com/hooks/bridge"
+events = ["agent_exited", "trigger_fired"]
+headers = { Authorization = "Bearer ci-token" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.bind.as_deref(), Some("0.0.0.0"));
+        assert_eq!(config.port, Some(9100));
+        assert_eq!(config.allowed_roots, [PathBuf::from("/srv/projects")]);
+        assert_eq!(config.default_terminal_size.as_deref(), Some("120x40"));
+        assert_eq!(config.max_agents, Some(8));
+        assert_eq!(config.max_agents_per_client, None);
+        assert_eq!(
+            config.audit_log,
+            Some(PathBuf::from("/var/log/hoc-audit.jsonl"))
+        );
+        assert_eq!(config.tokens[0].role, Role::Observer);
+        assert_eq!(config.tokens[0].name.as_deref(), Some("wall display"));
+        assert_eq!(config.logging.level.as_deref(), Some("debug"));
+        let transcript = config.transcript.unwrap();
+        assert_eq!(transcript.max_bytes, 1_048_576);
+        assert_eq!(transcript.keep, TranscriptConfig::default().keep);
+        assert_eq!(config.presets[0].name, "reviewer");
+        assert_eq!(config.schedules[0].cron.as_str(), "0 3 * * *");
+        assert_eq!(config.webhooks[0].url.host(), "ci.example.com");
+        assert_eq!(config.webhooks[0].events.len(), 2);
+
+        assert_eq!(BridgeConfig::parse("").unwrap(), BridgeConfig::default());
+        // Misspelt settings are errors rather than silently ignored
+        assert!(matches!(
+            BridgeConfig::parse("max_agent = 3"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_expand_home() {
+        let Some(home) = home_dir() else {
+            return;
+        };
+        assert_eq!(expand_home(Path::new("~/code")), home.join("code"));
+        assert_eq!(expand_home(Path::new("/srv/~")), PathBuf::from("/srv/~"));
+    }
+}
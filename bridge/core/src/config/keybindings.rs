@@ -0,0 +1,159 @@
+//! Keybinding profiles
+//!
+//! Maps abstract key actions (`interrupt`, `clear`, `scroll-up`, ...) to the
+//! byte sequences the agent's terminal application expects, so clients can
+//! send actions without knowing which CLI is running. Profiles are selected
+//! per preset; custom profiles from the project config override or extend
+//! the built-in ones.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Name of the profile used when a preset does not select one
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Bindings shared by every profile unless overridden
+const BASE_BINDINGS: &[(&str, &str)] = &[
+    ("interrupt", "\x03"),
+    ("eof", "\x04"),
+    ("clear", "\x15"),
+    ("accept", "\r"),
+    ("cancel", "\x1b"),
+    ("tab", "\t"),
+    ("up", "\x1b[A"),
+    ("down", "\x1b[B"),
+    ("right", "\x1b[C"),
+    ("left", "\x1b[D"),
+    ("scroll-up", "\x1b[5~"),
+    ("scroll-down", "\x1b[6~"),
+];
+
+/// Overrides of the built-in `claude` profile
+const CLAUDE_BINDINGS: &[(&str, &str)] = &[
+    // Esc stops the current response; Ctrl-C would clear or exit
+    ("interrupt", "\x1b"),
+    ("clear", "\x03"),
+    ("cycle-mode", "\x1b[Z"),
+];
+
+/// A named set of action bindings from the project config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeybindingProfile {
+    /// Profile name referenced by presets
+    pub name: String,
+    /// Built-in or custom profile to start from (the default profile when unset)
+    #[serde(default)]
+    pub extends: Option<String>,
+    /// Action name to byte sequence
+    #[serde(default)]
+    pub bindings: HashMap<String, String>,
+}
+
+/// Resolved action bindings for one agent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyBindings {
+    bindings: HashMap<String, String>,
+}
+
+impl KeyBindings {
+    /// Resolve a profile by name
+    ///
+    /// Custom profiles take precedence over built-in ones of the same name.
+    /// Returns `None` for unknown profiles or cyclic `extends` chains.
+    pub fn resolve(name: &str, profiles: &[KeybindingProfile]) -> Option<Self> {
+        Self::resolve_depth(name, profiles, profiles.len() + 1)
+    }
+
+    fn resolve_depth(name: &str, profiles: &[KeybindingProfile], depth: usize) -> Option<Self> {
+        if let Some(profile) = profiles.iter().find(|p| p.name == name) {
+            // A custom profile named after a built-in builds on that built-in
+            let fallback = if Self::builtin(name).is_some() {
+                name
+            } else {
+                DEFAULT_PROFILE
+            };
+            let parent = profile.extends.as_deref().unwrap_or(fallback);
+            let mut resolved = if parent == name {
+                Self::builtin(parent)?
+            } else if depth == 0 {
+                return None;
+            } else {
+                Self::resolve_depth(parent, profiles, depth - 1)?
+            };
+            resolved.bindings.extend(profile.bindings.clone());
+            return Some(resolved);
+        }
+        Self::builtin(name)
+    }
+
+    /// One of the built-in profiles (`default`, `claude`)
+    pub fn builtin(name: &str) -> Option<Self> {
+        let overrides = match name {
+            DEFAULT_PROFILE => &[][..],
+            "claude" => CLAUDE_BINDINGS,
+            _ => return None,
+        };
+        let bindings = BASE_BINDINGS
+            .iter()
+            .chain(overrides)
+            .map(|(action, keys)| (action.to_string(), keys.to_string()))
+            .collect();
+        Some(Self { bindings })
+    }
+
+    /// Byte sequence bound to an action
+    pub fn get(&self, action: &str) -> Option<&str> {
+        self.bindings.get(action).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, extends: Option<&str>, bindings: &[(&str, &str)]) -> KeybindingProfile {
+        KeybindingProfile {
+            name: name.to_string(),
+            extends: extends.map(str::to_string),
+            bindings: bindings
+                .iter()
+                .map(|(a, k)| (a.to_string(), k.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_builtin_profiles() {
+        let default = KeyBindings::builtin(DEFAULT_PROFILE).unwrap();
+        assert_eq!(default.get("interrupt"), Some("\x03"));
+        let claude = KeyBindings::builtin("claude").unwrap();
+        assert_eq!(claude.get("interrupt"), Some("\x1b"));
+        assert_eq!(claude.get("scroll-up"), Some("\x1b[5~"));
+        assert!(KeyBindings::builtin("vim").is_none());
+    }
+
+    #[test]
+    fn test_custom_profile_extends() {
+        let profiles = vec![
+            profile("aider", None, &[("accept", "\n")]),
+            profile("aider-dev", Some("aider"), &[("save", "\x13")]),
+            profile("claude", None, &[("interrupt", "\x1b\x1b")]),
+        ];
+        let dev = KeyBindings::resolve("aider-dev", &profiles).unwrap();
+        assert_eq!(dev.get("accept"), Some("\n"));
+        assert_eq!(dev.get("save"), Some("\x13"));
+        assert_eq!(dev.get("interrupt"), Some("\x03"));
+
+        // Overriding a built-in keeps its other bindings
+        let claude = KeyBindings::resolve("claude", &profiles).unwrap();
+        assert_eq!(claude.get("interrupt"), Some("\x1b\x1b"));
+        assert_eq!(claude.get("clear"), Some("\x03"));
+    }
+
+    #[test]
+    fn test_cyclic_profiles_fail() {
+        let profiles = vec![profile("a", Some("b"), &[]), profile("b", Some("a"), &[])];
+        assert!(KeyBindings::resolve("a", &profiles).is_none());
+        assert!(KeyBindings::resolve("missing", &profiles).is_none());
+    }
+}
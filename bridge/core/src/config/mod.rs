@@ -0,0 +1,25 @@
+//! Configuration module
+//!
+//! Handles loading and saving project configuration, pipelines and workspace
+//! layouts, and loading the bridge's own configuration file with its schedules and webhooks.
+
+mod bridge;
+mod keybindings;
+mod pipeline;
+#[allow(dead_code)]
+mod project;
+mod schedule;
+mod secrets;
+mod webhook;
+#[allow(dead_code)]
+mod workspace;
+
+pub use bridge::*;
+pub use keybindings::*;
+pub use pipeline::*;
+pub use project::*;
+pub use schedule::*;
+pub use secrets::*;
+pub use webhook::*;
+#[allow(unused_imports)]
+pub use workspace::*;
@@ -0,0 +1,227 @@
+//! Pipelines
+//!
+//! A pipeline is a named list of steps from the project config, run one after
+//! the other by the bridge: spawn an agent with a prompt, wait for it to
+//! become ready, fire a trigger or exit, send it input, spawn the next agent
+//! in a worktree of its own, and so on. Steps name the agents they act on by
+//! the name of the `spawn` step that started them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// Reasons a pipeline definition is refused
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PipelineError {
+    #[error("Pipeline {0} has no steps")]
+    Empty(String),
+
+    #[error("Step {step} of pipeline {pipeline} names agent {agent}, which no earlier step spawns")]
+    UnknownAgent {
+        pipeline: String,
+        step: usize,
+        agent: String,
+    },
+
+    #[error("Step {step} of pipeline {pipeline} spawns agent {agent} a second time")]
+    DuplicateAgent {
+        pipeline: String,
+        step: usize,
+        agent: String,
+    },
+}
+
+/// What a `wait` step waits for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitUntil {
+    /// The agent shows its prompt and takes input
+    Ready,
+    /// The agent exits with exit code 0
+    #[default]
+    Exit,
+}
+
+/// One step of a pipeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PipelineStep {
+    /// Spawn an agent in the project
+    Spawn {
+        /// Name later steps refer to the agent by, also its display name
+        agent: String,
+        /// Preset the agent is spawned with (the project's default when unset)
+        #[serde(default)]
+        preset: Option<String>,
+        /// Initial prompt, sent once the agent is ready
+        #[serde(default)]
+        prompt: Option<String>,
+        /// Run the agent in a new worktree checked out on this new branch
+        #[serde(default)]
+        worktree: Option<String>,
+    },
+    /// Wait for an agent to become ready, fire a trigger or exit
+    Wait {
+        agent: String,
+        /// Name of a trigger of the agent's preset to wait for
+        #[serde(default)]
+        trigger: Option<String>,
+        /// What to wait for when no trigger is named
+        #[serde(default)]
+        until: WaitUntil,
+        /// Seconds after which the pipeline fails (waits for ever when unset)
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+    },
+    /// Send input to an agent
+    Input {
+        agent: String,
+        /// Text sent to the agent
+        input: String,
+        /// Submit the input with a trailing carriage return
+        #[serde(default = "default_submit")]
+        submit: bool,
+    },
+    /// Stop an agent
+    Kill { agent: String },
+}
+
+fn default_submit() -> bool {
+    true
+}
+
+impl PipelineStep {
+    /// Name of the agent the step acts on
+    pub fn agent(&self) -> &str {
+        match self {
+            PipelineStep::Spawn { agent, .. }
+            | PipelineStep::Wait { agent, .. }
+            | PipelineStep::Input { agent, .. }
+            | PipelineStep::Kill { agent } => agent,
+        }
+    }
+
+    /// The step's `action`, as written in the config
+    pub fn action(&self) -> &'static str {
+        match self {
+            PipelineStep::Spawn { .. } => "spawn",
+            PipelineStep::Wait { .. } => "wait",
+            PipelineStep::Input { .. } => "input",
+            PipelineStep::Kill { .. } => "kill",
+        }
+    }
+}
+
+/// A named pipeline from the project config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Name clients run the pipeline by
+    pub name: String,
+    /// Steps run in order; the pipeline stops at the first that fails
+    #[serde(default)]
+    pub steps: Vec<PipelineStep>,
+}
+
+impl PipelineConfig {
+    /// Check that the pipeline has steps and that each step acts on an agent
+    /// spawned by an earlier one
+    pub fn validate(&self) -> Result<(), PipelineError> {
+        if self.steps.is_empty() {
+            return Err(PipelineError::Empty(self.name.clone()));
+        }
+        let mut spawned = HashSet::new();
+        for (step, action) in self.steps.iter().enumerate() {
+            let agent = action.agent();
+            if let PipelineStep::Spawn { .. } = action {
+                if !spawned.insert(agent) {
+                    return Err(PipelineError::DuplicateAgent {
+                        pipeline: self.name.clone(),
+                        step,
+                        agent: agent.to_string(),
+                    });
+                }
+            } else if !spawned.contains(agent) {
+                return Err(PipelineError::UnknownAgent {
+                    pipeline: self.name.clone(),
+                    step,
+                    agent: agent.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pipeline() {
+        let config: PipelineConfig = toml::from_str(
+            r#"
+            name = "fix-and-review"
+
+            [[steps]]
+            action = "spawn"
+            agent = "fixer"
+            prompt = "Fix the failing tests"
+
+            [[steps]]
+            action = "wait"
+            agent = "fixer"
+            trigger = "tests-passed"
+            timeout_secs = 600
+
+            [[steps]]
+            action = "spawn"
+            agent = "reviewer"
+            worktree = "review"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.steps.len(), 3);
+        assert_eq!(
+            config.steps[1],
+            PipelineStep::Wait {
+                agent: "fixer".to_string(),
+                trigger: Some("tests-passed".to_string()),
+                until: WaitUntil::Exit,
+                timeout_secs: Some(600),
+            }
+        );
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_agent_names() {
+        let spawn = |agent: &str| PipelineStep::Spawn {
+            agent: agent.to_string(),
+            preset: None,
+            prompt: None,
+            worktree: None,
+        };
+        let kill = |agent: &str| PipelineStep::Kill {
+            agent: agent.to_string(),
+        };
+        let pipeline = |steps| PipelineConfig {
+            name: "p".to_string(),
+            steps,
+        };
+
+        assert_eq!(
+            pipeline(Vec::new()).validate(),
+            Err(PipelineError::Empty("p".to_string()))
+        );
+        assert!(matches!(
+            pipeline(vec![kill("a"), spawn("a")]).validate(),
+            Err(PipelineError::UnknownAgent { step: 0, .. })
+        ));
+        assert!(matches!(
+            pipeline(vec![spawn("a"), spawn("a")]).validate(),
+            Err(PipelineError::DuplicateAgent { step: 1, .. })
+        ));
+        assert!(pipeline(vec![spawn("a"), kill("a")]).validate().is_ok());
+    }
+}
@@ -0,0 +1,227 @@
+//! Project configuration
+//!
+//! Loads project-specific configuration from .hoc/config.toml
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use thiserror::Error;
+
+use super::{KeyBindings, KeybindingProfile, PipelineConfig, DEFAULT_PROFILE};
+
+/// Configuration file name
+pub const CONFIG_DIR: &str = ".hoc";
+pub const CONFIG_FILE: &str = "config.toml";
+pub const WORKSPACE_FILE: &str = "workspace.json";
+
+/// Errors that can occur during config operations
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Failed to parse config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Failed to serialize config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+/// Named input macro, expanded server-side by `run_macro`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputMacro {
+    /// Name clients refer to the macro by
+    pub name: String,
+    /// Text sent to the agent
+    pub input: String,
+    /// Submit the input with a trailing newline
+    #[serde(default = "default_submit")]
+    pub submit: bool,
+}
+
+fn default_submit() -> bool {
+    true
+}
+
+impl InputMacro {
+    /// The bytes written to the agent when the macro runs
+    pub fn expand(&self) -> String {
+        if self.submit {
+            format!("{}\n", self.input)
+        } else {
+            self.input.clone()
+        }
+    }
+}
+
+/// Automatic answer to an interactive prompt, e.g. "Overwrite? (y/n)"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectRule {
+    /// Regular expression matched against the agent's output (escape sequences removed)
+    pub pattern: String,
+    /// Text sent to the agent when the pattern matches
+    pub response: String,
+    /// Submit the response with a trailing newline
+    #[serde(default = "default_submit")]
+    pub submit: bool,
+    /// Number of times the rule may fire (unlimited when unset)
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+}
+
+/// Named pattern reported to clients whenever it appears in an agent's
+/// output, e.g. "tests passed"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TriggerRule {
+    /// Name clients know the trigger by
+    pub name: String,
+    /// Regular expression matched against the agent's output (escape sequences removed)
+    pub pattern: String,
+}
+
+/// Transcript logging of an agent's terminal to `.hoc/logs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TranscriptConfig {
+    /// Also log the input sent to the agent, unredacted
+    #[serde(default)]
+    pub input: bool,
+    /// Size in bytes a log grows to before it is rotated
+    #[serde(default = "default_transcript_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated logs kept next to the current one
+    #[serde(default = "default_transcript_keep")]
+    pub keep: usize,
+}
+
+fn default_transcript_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_transcript_keep() -> usize {
+    3
+}
+
+impl Default for TranscriptConfig {
+    fn default() -> Self {
+        Self {
+            input: false,
+            max_bytes: default_transcript_max_bytes(),
+            keep: default_transcript_keep(),
+        }
+    }
+}
+
+/// Agent preset configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentPreset {
+    /// Name of the preset
+    pub name: String,
+    /// Additional command line arguments
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Initial prompt to send to agent
+    pub initial_prompt: Option<String>,
+    /// Regular expression the agent's output matches once it is ready for the
+    /// initial prompt
+    #[serde(default)]
+    pub ready_pattern: Option<String>,
+    /// Seconds the initial prompt waits for `ready_pattern` before it is sent anyway
+    #[serde(default)]
+    pub ready_timeout_secs: Option<u64>,
+    /// Remote host to run the agent on over SSH (host name or ssh config alias)
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Working directory on the remote host (defaults to the project path)
+    #[serde(default)]
+    pub remote_dir: Option<String>,
+    /// Macros for agents using this preset (override project macros of the same name)
+    #[serde(default)]
+    pub macros: Vec<InputMacro>,
+    /// Keybinding profile for `send_key` (built-in `default` or `claude`, or a custom profile)
+    #[serde(default)]
+    pub keybindings: Option<String>,
+    /// Prompts answered automatically for agents using this preset
+    #[serde(default)]
+    pub expect: Vec<ExpectRule>,
+    /// Patterns in the output of agents using this preset reported to clients
+    #[serde(default)]
+    pub triggers: Vec<TriggerRule>,
+    /// Secrets set in the agent's environment, by name (looked up by the bridge)
+    #[serde(default)]
+    pub secrets: Vec<String>,
+    /// Environment variables set for the agent, e.g. `ANTHROPIC_MODEL` or `LANG`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Minutes without input or output after which the agent is stopped
+    #[serde(default)]
+    pub idle_timeout_mins: Option<u64>,
+    /// Log the agent's terminal to `.hoc/logs`, overriding the server's setting
+    #[serde(default)]
+    pub transcript: Option<TranscriptConfig>,
+}
+
+/// Project configuration
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProjectConfig {
+    /// Agent presets
+    #[serde(default)]
+    pub presets: Vec<AgentPreset>,
+    /// Default preset name
+    pub default_preset: Option<String>,
+    /// Macros available to every agent of the project
+    #[serde(default)]
+    pub macros: Vec<InputMacro>,
+    /// Custom keybinding profiles
+    #[serde(default)]
+    pub keybinding_profiles: Vec<KeybindingProfile>,
+    /// Pipelines clients can run with `run_pipeline`
+    #[serde(default)]
+    pub pipelines: Vec<PipelineConfig>,
+}
+
+impl ProjectConfig {
+    /// Load configuration from a project directory
+    pub fn load(project_path: &Path) -> Result<Self, ConfigError> {
+        let config_path = project_path.join(CONFIG_DIR).join(CONFIG_FILE);
+
+        if !config_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&config_path)?;
+        let config: ProjectConfig = toml::from_str(&content)?;
+        Ok(config)
+    }
+
+    /// Add server-wide presets; the project's own presets take precedence
+    /// over global presets of the same name
+    pub fn with_global_presets(mut self, presets: &[AgentPreset]) -> Self {
+        for preset in presets {
+            if self.get_preset(&preset.name).is_none() {
+                self.presets.push(preset.clone());
+            }
+        }
+        self
+    }
+
+    /// Get a preset by name
+    pub fn get_preset(&self, name: &str) -> Option<&AgentPreset> {
+        self.presets.iter().find(|p| p.name == name)
+    }
+
+    /// Get a pipeline by name
+    pub fn get_pipeline(&self, name: &str) -> Option<&PipelineConfig> {
+        self.pipelines.iter().find(|p| p.name == name)
+    }
+
+    /// Get the default preset
+    pub fn default_preset(&self) -> Option<&AgentPreset> {
+        self.default_preset
+            .as_ref()
+            .and_then(|name| self.get_preset(name))
+    }
+
+    /// Resolve a keybinding profile (the default profile when `None`)
+    pub fn keybindings(&self, profile: Option<&str>) -> Option<KeyBindings> {
+        KeyBindings::resolve(profile.unwrap_or(DEFAULT_PROFILE), &self.keybinding_profiles)
+    }
+}
@@ -0,0 +1,275 @@
+//! Scheduled spawns
+//!
+//! Schedules in the bridge configuration spawn an agent with a preset at the
+//! times a cron expression gives, e.g. a nightly agent updating a project's
+//! dependencies. Expressions have the usual five fields (minute, hour, day
+//! of month, month, day of week) and are evaluated in UTC.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+/// Days searched for a schedule's next run, covering every leap day
+const MAX_SEARCH_DAYS: i64 = 8 * 366;
+
+/// Reasons a cron expression is refused
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CronError {
+    #[error("Cron expression {0:?} needs 5 fields")]
+    FieldCount(String),
+
+    #[error("Invalid {field} field {value:?} in cron expression")]
+    Field { field: &'static str, value: String },
+}
+
+/// A five-field cron expression, as a bit set of the values each field
+/// matches
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Both day fields are restricted, so a day matching either one counts
+    either_day: bool,
+}
+
+impl CronSchedule {
+    /// Parse an expression like `30 2 * * 1-5`, or one of `@hourly`,
+    /// `@daily`, `@weekly` and `@monthly`
+    pub fn parse(expression: &str) -> Result<Self, CronError> {
+        let fields = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(CronError::FieldCount(expression.to_string()));
+        };
+        let mut weekdays = parse_field("day of week", weekday, 0, 7)?;
+        // Sunday is both 0 and 7
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: parse_field("minute", minute, 0, 59)?,
+            hours: parse_field("hour", hour, 0, 23)?,
+            days: parse_field("day of month", day, 1, 31)?,
+            months: parse_field("month", month, 1, 12)?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// The expression as written
+    pub fn as_str(&self) -> &str {
+        &self.expression
+    }
+
+    /// The first time the schedule matches after `after`, in seconds since
+    /// the Unix epoch, or `None` if it never does (e.g. February 30)
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        // Start at the next whole minute
+        let start = after / 60 + 1;
+        let first_day = (start / (24 * 60)) as i64;
+        for day in first_day..first_day + MAX_SEARCH_DAYS {
+            let (_, month, day_of_month) = civil_from_days(day);
+            let weekday = (day + 4).rem_euclid(7) as u32;
+            if !self.matches_day(month, day_of_month, weekday) {
+                continue;
+            }
+            let day_start = day as u64 * 24 * 60;
+            let from = start.saturating_sub(day_start);
+            for minute_of_day in from..24 * 60 {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & (1 << hour) != 0 && self.minutes & (1 << minute) != 0 {
+                    return Some((day_start + minute_of_day) * 60);
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, month: u32, day: u32, weekday: u32) -> bool {
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        let by_day = self.days & (1 << day) != 0;
+        let by_weekday = self.weekdays & (1 << weekday) != 0;
+        if self.either_day {
+            by_day || by_weekday
+        } else {
+            by_day && by_weekday
+        }
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = CronError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        Self::parse(&expression)
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.expression
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// Parse one field: `*`, values, ranges and steps, separated by commas
+fn parse_field(field: &'static str, value: &str, min: u32, max: u32) -> Result<u64, CronError> {
+    let invalid = || CronError::Field {
+        field,
+        value: value.to_string(),
+    };
+    let number = |s: &str| {
+        s.parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(invalid)
+    };
+    let mut bits = 0u64;
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(invalid)?;
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (number(first)?, number(last)?),
+                // `5/15` runs from 5 to the end of the range
+                None if part.contains('/') => (number(range)?, max),
+                None => {
+                    let n = number(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for n in (first..=last).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+/// Year, month and day of a day counted from the Unix epoch
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// An agent spawned on a schedule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Name clients refer to the schedule by, also the agents' display name
+    pub name: String,
+    /// When the agent is spawned, as a cron expression in UTC
+    pub cron: CronSchedule,
+    /// Project the agent runs in
+    pub project_path: String,
+    /// Preset the agent is spawned with (the project's default when unset)
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Initial prompt, overriding the preset's
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// Whether the schedule runs; clients can switch it at runtime
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-16 (a Friday) 12:00 UTC
+    const NOON: u64 = 1_792_152_000;
+
+    #[test]
+    fn test_parse_fields() {
+        let cron = CronSchedule::parse("*/15 9-17 * * 1-5").unwrap();
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.hours.count_ones(), 9);
+        assert_eq!(cron.weekdays, 0b11_1110);
+        assert_eq!(CronSchedule::parse("0 0 * * 7").unwrap().weekdays, 1);
+        let from_five = CronSchedule::parse("5/20 * * * *").unwrap();
+        assert_eq!(from_five.minutes, 1 << 5 | 1 << 25 | 1 << 45);
+
+        assert!(matches!(
+            CronSchedule::parse("0 0 * *"),
+            Err(CronError::FieldCount(_))
+        ));
+        assert!(matches!(
+            CronSchedule::parse("60 0 * * *"),
+            Err(CronError::Field { field: "minute", .. })
+        ));
+        assert!(CronSchedule::parse("0 5-1 * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        assert_eq!(civil_from_days((NOON / 86_400) as i64), (2026, 10, 16));
+        let daily = CronSchedule::parse("0 3 * * *").unwrap();
+        assert_eq!(daily.next_after(NOON), Some(NOON + 15 * 3600));
+        // A run is never due at the time it is asked for
+        let hourly = CronSchedule::parse("@hourly").unwrap();
+        assert_eq!(hourly.next_after(NOON), Some(NOON + 3600));
+        assert_eq!(hourly.next_after(NOON - 1), Some(NOON));
+
+        // Monday at 9, from a Friday
+        let monday = CronSchedule::parse("0 9 * * 1").unwrap();
+        assert_eq!(monday.next_after(NOON), Some(NOON + 2 * 86_400 + 21 * 3600));
+        // The 1st of the month or any Sunday
+        let either = CronSchedule::parse("0 0 1 * 0").unwrap();
+        assert_eq!(either.next_after(NOON), Some(NOON + 86_400 + 12 * 3600));
+        assert_eq!(CronSchedule::parse("0 0 30 2 *").unwrap().next_after(NOON), None);
+    }
+
+    #[test]
+    fn test_parse_schedule_config() {
+        let schedule: ScheduleConfig = toml::from_str(
+            "name = \"deps\"\ncron = \"@daily\"\nproject_path = \"/srv/app\"\n",
+        )
+        .unwrap();
+        assert_eq!(schedule.cron.as_str(), "@daily");
+        assert!(schedule.enabled);
+        assert!(toml::from_str::<ScheduleConfig>(
+            "name = \"deps\"\ncron = \"daily\"\nproject_path = \"/srv/app\"\n"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Secrets for agent environments
+//!
+//! Presets name the secrets their agents need (`secrets = ["ANTHROPIC_API_KEY"]`)
+//! and the bridge looks the values up when the agent is spawned, so keys live
+//! neither in project config nor in the bridge's own environment, and only
+//! reach the agents that ask for them. Values are never logged or sent to
+//! clients.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+
+use thiserror::Error;
+use tokio::process::Command;
+
+/// Errors looking up secrets
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("Secret {0} not found")]
+    NotFound(String),
+
+    #[error("Invalid secret name: {0:?}")]
+    InvalidName(String),
+
+    #[error("Failed to read secrets file {path}: {message}")]
+    File { path: String, message: String },
+
+    #[error("Secrets command failed: {0}")]
+    Command(String),
+}
+
+/// Where secret values are looked up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretSource {
+    /// A file of `NAME=value` lines
+    EnvFile(PathBuf),
+    /// The OS keyring: entries of the given service, with the secret name as account
+    Keyring(String),
+    /// A command printing the value of the secret named by its last argument
+    Command(String),
+}
+
+impl SecretSource {
+    /// Look up one secret, `None` if this source doesn't have it
+    async fn lookup(&self, name: &str) -> Result<Option<String>, SecretError> {
+        match self {
+            SecretSource::EnvFile(path) => {
+                let content = tokio::fs::read_to_string(path).await.map_err(|e| {
+                    SecretError::File {
+                        path: path.display().to_string(),
+                        message: e.to_string(),
+                    }
+                })?;
+                Ok(parse_env_file(&content).remove(name))
+            }
+            SecretSource::Keyring(service) => {
+                let (program, args) = if cfg!(target_os = "macos") {
+                    ("security", vec!["find-generic-password", "-s", service, "-a", name, "-w"])
+                } else {
+                    ("secret-tool", vec!["lookup", "service", service, "account", name])
+                };
+                run(program, &args).await
+            }
+            SecretSource::Command(command) => {
+                let mut words = command.split_whitespace();
+                let program = words
+                    .next()
+                    .ok_or_else(|| SecretError::Command("empty command".to_string()))?;
+                let mut args: Vec<&str> = words.collect();
+                args.push(name);
+                run(program, &args).await
+            }
+        }
+    }
+}
+
+/// Run a lookup command, treating a failed or empty lookup as not found
+async fn run(program: &str, args: &[&str]) -> Result<Option<String>, SecretError> {
+    let output = Command::new(program)
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| SecretError::Command(format!("{}: {}", program, e)))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let value = String::from_utf8_lossy(&output.stdout);
+    let value = value.trim_end_matches(['\r', '\n']);
+    Ok((!value.is_empty()).then(|| value.to_string()))
+}
+
+/// Parse `NAME=value` lines, skipping blanks and `#` comments
+///
+/// An `export ` prefix and quotes around the value are removed.
+pub fn parse_env_file(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = ['"', '\'']
+                .iter()
+                .find_map(|&q| value.strip_prefix(q)?.strip_suffix(q))
+                .unwrap_or(value);
+            Some((name.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Whether `name` can be used as an environment variable name
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Secret values to set in an agent's environment
+///
+/// Debug output lists the names only.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretEnv(BTreeMap<String, String>);
+
+impl SecretEnv {
+    /// Whether there are no secrets
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Names of the secrets
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    /// Environment variables to set
+    pub fn vars(&self) -> HashMap<String, String> {
+        self.0.clone().into_iter().collect()
+    }
+}
+
+impl fmt::Debug for SecretEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+impl FromIterator<(String, String)> for SecretEnv {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Secret sources, consulted in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecretStore {
+    sources: Vec<SecretSource>,
+}
+
+impl SecretStore {
+    /// Add a source, consulted after the ones already added
+    pub fn with_source(mut self, source: SecretSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Look up every named secret
+    pub async fn resolve(&self, names: &[String]) -> Result<SecretEnv, SecretError> {
+        let mut env = BTreeMap::new();
+        for name in names {
+            if !is_valid_name(name) {
+                return Err(SecretError::InvalidName(name.clone()));
+            }
+            let mut value = None;
+            for source in &self.sources {
+                value = source.lookup(name).await?;
+                if value.is_some() {
+                    break;
+                }
+            }
+            let value = value.ok_or_else(|| SecretError::NotFound(name.clone()))?;
+            env.insert(name.clone(), value);
+        }
+        Ok(SecretEnv(env))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_file() {
+        let vars = parse_env_file(
+            "# API keys\n\nANTHROPIC_API_KEY=sk-ant-123\nexport GH_TOKEN=\"ghp abc\"\nNOT A VAR\n",
+        );
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars["ANTHROPIC_API_KEY"], "sk-ant-123");
+        assert_eq!(vars["GH_TOKEN"], "ghp abc");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_from_sources_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("secrets.env");
+        std::fs::write(&file, "API_KEY=from-file\n").unwrap();
+        let store = SecretStore::default()
+            .with_source(SecretSource::EnvFile(file))
+            .with_source(SecretSource::Command("echo value-of".to_string()));
+
+        let env = store
+            .resolve(&["API_KEY".to_string(), "TOKEN".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(env.vars()["API_KEY"], "from-file");
+        assert_eq!(env.vars()["TOKEN"], "value-of TOKEN");
+        // Values stay out of debug output
+        assert_eq!(format!("{:?}", env), r#"{"API_KEY", "TOKEN"}"#);
+
+        assert!(matches!(
+            SecretStore::default().resolve(&["API_KEY".to_string()]).await,
+            Err(SecretError::NotFound(_))
+        ));
+        assert!(matches!(
+            store.resolve(&["A=B".to_string()]).await,
+            Err(SecretError::InvalidName(_))
+        ));
+    }
+}
@@ -0,0 +1,206 @@
+//! Webhooks
+//!
+//! Webhooks in the bridge configuration have agent events POSTed to them as
+//! JSON, so chat and CI systems can follow the bridge without holding a
+//! connection to it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use thiserror::Error;
+
+/// Reasons a webhook URL is refused
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum WebhookUrlError {
+    #[error("Webhook URL {0:?} must start with http:// or https://")]
+    Scheme(String),
+
+    #[error("Webhook URL {0:?} has no host")]
+    Host(String),
+
+    #[error("Webhook URL {0:?} has an invalid port")]
+    Port(String),
+}
+
+/// An `http://` or `https://` URL events are POSTed to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct WebhookUrl {
+    url: String,
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl WebhookUrl {
+    /// Parse a URL like `https://hooks.example.com/bridge?key=1`
+    pub fn parse(url: &str) -> Result<Self, WebhookUrlError> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(WebhookUrlError::Scheme(url.to_string()));
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(at) => rest.split_at(at),
+            None => (rest, "/"),
+        };
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.rsplit_once(':') {
+            // A bracketed IPv6 address without a port
+            Some((_, port)) if port.ends_with(']') => (authority, default_port),
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| WebhookUrlError::Port(url.to_string()))?,
+            ),
+            None => (authority, default_port),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() || host.contains('@') {
+            return Err(WebhookUrlError::Host(url.to_string()));
+        }
+        let path = if path.starts_with('?') {
+            format!("/{}", path)
+        } else {
+            path.to_string()
+        };
+        Ok(Self {
+            url: url.to_string(),
+            tls,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// The URL as written
+    pub fn as_str(&self) -> &str {
+        &self.url
+    }
+
+    /// Whether the URL is `https://`
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Host name or address, without brackets
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Port, 443 or 80 unless given
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Path and query string the request is made for
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl TryFrom<String> for WebhookUrl {
+    type Error = WebhookUrlError;
+
+    fn try_from(url: String) -> Result<Self, Self::Error> {
+        Self::parse(&url)
+    }
+}
+
+impl From<WebhookUrl> for String {
+    fn from(url: WebhookUrl) -> Self {
+        url.url
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.url)
+    }
+}
+
+/// Agent events a webhook can be sent, named after the messages clients get
+/// for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    AgentSpawned,
+    AgentRestarted,
+    AgentExited,
+    TriggerFired,
+}
+
+impl WebhookEvent {
+    /// Every event
+    pub fn all() -> Vec<Self> {
+        vec![
+            WebhookEvent::AgentSpawned,
+            WebhookEvent::AgentRestarted,
+            WebhookEvent::AgentExited,
+            WebhookEvent::TriggerFired,
+        ]
+    }
+}
+
+/// A URL agent events are POSTed to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: WebhookUrl,
+    /// Events sent to the URL (all of them when unset)
+    #[serde(default = "WebhookEvent::all")]
+    pub events: Vec<WebhookEvent>,
+    /// Only send events about agents in these projects (any when empty)
+    #[serde(default)]
+    pub projects: Vec<String>,
+    /// Extra request headers, e.g. `Authorization`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url = WebhookUrl::parse("https://hooks.example.com/services/T0/B0").unwrap();
+        assert!(url.tls());
+        assert_eq!((url.host(), url.port()), ("hooks.example.com", 443));
+        assert_eq!(url.path(), "/services/T0/B0");
+
+        let url = WebhookUrl::parse("http://127.0.0.1:8080").unwrap();
+        assert!(!url.tls());
+        assert_eq!((url.host(), url.port(), url.path()), ("127.0.0.1", 8080, "/"));
+        let url = WebhookUrl::parse("http://[::1]?key=1").unwrap();
+        assert_eq!((url.host(), url.port(), url.path()), ("::1", 80, "/?key=1"));
+
+        assert!(matches!(
+            WebhookUrl::parse("ftp://example.com"),
+            Err(WebhookUrlError::Scheme(_))
+        ));
+        assert!(matches!(
+            WebhookUrl::parse("http:///path"),
+            Err(WebhookUrlError::Host(_))
+        ));
+        assert!(matches!(
+            WebhookUrl::parse("http://example.com:port/"),
+            Err(WebhookUrlError::Port(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_webhook_config() {
+        let webhook: WebhookConfig = toml::from_str(
+            "url = \"https://ci.example.com/hook\"\nevents = [\"agent_exited\"]\n",
+        )
+        .unwrap();
+        assert_eq!(webhook.events, [WebhookEvent::AgentExited]);
+        let webhook: WebhookConfig = toml::from_str("url = \"http://localhost:9000\"\n").unwrap();
+        assert_eq!(webhook.events, WebhookEvent::all());
+        assert!(toml::from_str::<WebhookConfig>("url = \"localhost:9000\"\n").is_err());
+    }
+}
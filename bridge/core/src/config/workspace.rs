@@ -7,6 +7,7 @@ use std::path::Path;
 use thiserror::Error;
 
 use super::{CONFIG_DIR, WORKSPACE_FILE};
+use crate::protocol::{DEFAULT_TERMINAL_COLS, DEFAULT_TERMINAL_ROWS};
 
 /// Errors that can occur during workspace operations
 #[derive(Error, Debug)]
@@ -48,7 +49,7 @@ impl Default for Size {
 }
 
 /// Layout information for a single terminal/agent panel
-#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PanelLayout {
     /// Panel identifier (matches agent preset or custom name)
     pub id: String,
@@ -69,16 +70,29 @@ pub struct PanelLayout {
     pub rows: u16,
 }
 
+impl Default for PanelLayout {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            position: Position::default(),
+            size: Size::default(),
+            visible: default_visible(),
+            cols: default_cols(),
+            rows: default_rows(),
+        }
+    }
+}
+
 fn default_visible() -> bool {
     true
 }
 
 fn default_cols() -> u16 {
-    80
+    DEFAULT_TERMINAL_COLS
 }
 
 fn default_rows() -> u16 {
-    24
+    DEFAULT_TERMINAL_ROWS
 }
 
 /// A named workspace layout configuration
@@ -339,6 +353,8 @@ mod tests {
         assert!(panel.visible);
         assert_eq!(panel.cols, 80);
         assert_eq!(panel.rows, 24);
+        // Panels built in code get the same defaults
+        assert_eq!(PanelLayout::default(), PanelLayout { id: String::new(), ..panel });
     }
 
     #[test]
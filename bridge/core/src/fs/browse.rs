@@ -0,0 +1,199 @@
+//! Directory listings, file reads and writes, and stats
+
+use std::fs::Metadata;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use super::{FsError, FsResult};
+
+/// Most entries returned for one directory; the rest are dropped
+pub const MAX_DIRECTORY_ENTRIES: usize = 10_000;
+
+/// Bytes returned by a read when the client sets no limit
+pub const DEFAULT_READ_BYTES: u64 = 256 * 1024;
+
+/// Most bytes returned by one read
+pub const MAX_READ_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Most bytes written by one write
+pub const MAX_WRITE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// What a directory entry is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    /// Sockets, devices and the like
+    Other,
+}
+
+/// A file or directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// File name, without its directory
+    pub name: String,
+    pub kind: FileKind,
+    /// Size in bytes
+    pub size: u64,
+    /// Last modification, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_ms: Option<u64>,
+}
+
+impl FileEntry {
+    fn new(name: String, meta: &Metadata) -> Self {
+        let file_type = meta.file_type();
+        let kind = if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Directory
+        } else if file_type.is_file() {
+            FileKind::File
+        } else {
+            FileKind::Other
+        };
+        let modified_ms = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64);
+        Self {
+            name,
+            kind,
+            size: meta.len(),
+            modified_ms,
+        }
+    }
+}
+
+/// Entries of a directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectoryListing {
+    /// Directories first, then by name
+    pub entries: Vec<FileEntry>,
+    /// Whether entries were dropped past [`MAX_DIRECTORY_ENTRIES`]
+    pub truncated: bool,
+}
+
+/// Part of a file's contents
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileChunk {
+    pub data: Vec<u8>,
+    /// Size of the whole file in bytes
+    pub size: u64,
+}
+
+/// List a directory; dotfiles are skipped unless `hidden` is set
+///
+/// Symlinks are listed as such rather than followed.
+pub fn list_directory(dir: &Path, hidden: bool) -> FsResult<DirectoryListing> {
+    if !dir.is_dir() {
+        return Err(FsError::NotADirectory(dir.display().to_string()));
+    }
+    let mut listing = DirectoryListing::default();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !hidden && name.starts_with('.') {
+            continue;
+        }
+        if listing.entries.len() == MAX_DIRECTORY_ENTRIES {
+            listing.truncated = true;
+            break;
+        }
+        // Entries can vanish while the directory is read
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        listing.entries.push(FileEntry::new(name, &meta));
+    }
+    listing.entries.sort_by(|a, b| {
+        (a.kind != FileKind::Directory, &a.name).cmp(&(b.kind != FileKind::Directory, &b.name))
+    });
+    Ok(listing)
+}
+
+/// Read up to `limit` bytes of a file starting at byte `offset`
+pub fn read_file(path: &Path, offset: u64, limit: u64) -> FsResult<FileChunk> {
+    if path.is_dir() {
+        return Err(FsError::IsADirectory(path.display().to_string()));
+    }
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::new();
+    file.take(limit.min(MAX_READ_BYTES))
+        .read_to_end(&mut data)?;
+    Ok(FileChunk { data, size })
+}
+
+/// Replace a file's contents, creating it if needed
+pub fn write_file(path: &Path, data: &[u8]) -> FsResult<()> {
+    if path.is_dir() {
+        return Err(FsError::IsADirectory(path.display().to_string()));
+    }
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+/// Stat a file or directory
+pub fn stat_path(path: &Path) -> FsResult<FileEntry> {
+    let meta = std::fs::metadata(path)?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    Ok(FileEntry::new(name, &meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_read_and_stat() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("b.txt"), "hello world").unwrap();
+        std::fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+
+        let listing = list_directory(dir.path(), false).unwrap();
+        let names: Vec<_> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["src", "b.txt"]);
+        assert_eq!(listing.entries[0].kind, FileKind::Directory);
+        assert_eq!(listing.entries[1].size, 11);
+        assert!(!listing.truncated);
+        assert_eq!(list_directory(dir.path(), true).unwrap().entries.len(), 3);
+
+        let file = dir.path().join("b.txt");
+        let chunk = read_file(&file, 6, 3).unwrap();
+        assert_eq!(chunk.data, b"wor");
+        assert_eq!(chunk.size, 11);
+        assert!(read_file(&file, 20, 3).unwrap().data.is_empty());
+        assert!(matches!(
+            read_file(dir.path(), 0, 3),
+            Err(FsError::IsADirectory(_))
+        ));
+        assert!(matches!(
+            list_directory(&file, false),
+            Err(FsError::NotADirectory(_))
+        ));
+
+        write_file(&file, b"bye").unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"bye");
+        assert!(matches!(
+            write_file(dir.path(), b""),
+            Err(FsError::IsADirectory(_))
+        ));
+
+        let entry = stat_path(&file).unwrap();
+        assert_eq!(entry.name, "b.txt");
+        assert_eq!(entry.kind, FileKind::File);
+        assert!(entry.modified_ms.is_some());
+    }
+}
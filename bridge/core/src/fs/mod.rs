@@ -0,0 +1,195 @@
+//! Filesystem access for clients
+//!
+//! Lets clients browse, preview and watch the files agents work on without a
+//! shell. Every path a client names is resolved through a [`Sandbox`] first,
+//! which only admits paths below its roots once symlinks and `..` are
+//! resolved.
+
+mod browse;
+mod watch;
+
+pub use browse::*;
+pub use watch::*;
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// Errors that can occur during filesystem operations
+#[derive(Error, Debug)]
+pub enum FsError {
+    #[error("Path must be absolute: {0}")]
+    NotAbsolute(String),
+    #[error("Path is outside the allowed roots: {0}")]
+    OutsideRoots(String),
+    #[error("No such file or directory: {0}")]
+    NotFound(String),
+    #[error("Not a directory: {0}")]
+    NotADirectory(String),
+    #[error("Is a directory: {0}")]
+    IsADirectory(String),
+    #[error("Filesystem operation failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to watch path: {0}")]
+    Watch(#[from] notify::Error),
+}
+
+/// Result type for filesystem operations
+pub type FsResult<T> = Result<T, FsError>;
+
+/// Directories clients may access, and everything below them
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    /// Canonical root directories
+    roots: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// Sandbox admitting the given roots; roots that don't exist are left
+    /// out, so an empty sandbox admits nothing
+    pub fn new<P: AsRef<Path>>(roots: impl IntoIterator<Item = P>) -> Self {
+        let mut canonical: Vec<PathBuf> = roots
+            .into_iter()
+            .filter_map(|root| root.as_ref().canonicalize().ok())
+            .collect();
+        canonical.sort();
+        canonical.dedup();
+        Self { roots: canonical }
+    }
+
+    /// Sandbox admitting one project directory, with the directory's
+    /// canonical path
+    pub fn project(project_path: &str) -> FsResult<(Self, PathBuf)> {
+        let sandbox = Self::new([project_path]);
+        match sandbox.roots.first().cloned() {
+            Some(project) => Ok((sandbox, project)),
+            None => Err(FsError::NotFound(project_path.to_string())),
+        }
+    }
+
+    /// Canonical roots of the sandbox
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Whether a canonical path is one of the roots or below one
+    pub fn contains(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Resolve an existing absolute path to its canonical form, refusing it
+    /// if it lies outside every root
+    pub fn resolve(&self, path: &str) -> FsResult<PathBuf> {
+        if !Path::new(path).is_absolute() {
+            return Err(FsError::NotAbsolute(path.to_string()));
+        }
+        let canonical = Path::new(path).canonicalize().map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => FsError::NotFound(path.to_string()),
+            _ => FsError::Io(e),
+        })?;
+        if !self.contains(&canonical) {
+            return Err(FsError::OutsideRoots(path.to_string()));
+        }
+        Ok(canonical)
+    }
+
+    /// Resolve an absolute path to write to, which need not exist yet as
+    /// long as its directory does
+    ///
+    /// Dangling symlinks are refused rather than written through.
+    pub fn resolve_new(&self, path: &str) -> FsResult<PathBuf> {
+        match self.resolve(path) {
+            Err(FsError::NotFound(_)) => {}
+            resolved => return resolved,
+        }
+        let new = Path::new(path);
+        if new.symlink_metadata().is_ok() {
+            return Err(FsError::OutsideRoots(path.to_string()));
+        }
+        // Paths ending in `..` have no file name
+        let (Some(dir), Some(name)) = (new.parent(), new.file_name()) else {
+            return Err(FsError::NotFound(path.to_string()));
+        };
+        Ok(self.resolve(&dir.to_string_lossy())?.join(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(dir.path().join("secret"), "x").unwrap();
+        let sandbox = Sandbox::new([&root, &dir.path().join("missing")]);
+        assert_eq!(sandbox.roots().len(), 1);
+
+        let src = root.join("src");
+        assert_eq!(
+            sandbox.resolve(src.to_str().unwrap()).unwrap(),
+            src.canonicalize().unwrap()
+        );
+        let escape = format!("{}/src/../../secret", root.display());
+        assert!(matches!(
+            sandbox.resolve(&escape),
+            Err(FsError::OutsideRoots(_))
+        ));
+        assert!(matches!(
+            sandbox.resolve("src"),
+            Err(FsError::NotAbsolute(_))
+        ));
+        assert!(matches!(
+            sandbox.resolve(root.join("nope").to_str().unwrap()),
+            Err(FsError::NotFound(_))
+        ));
+        assert!(matches!(
+            Sandbox::default().resolve(src.to_str().unwrap()),
+            Err(FsError::OutsideRoots(_))
+        ));
+    }
+
+    #[test]
+    fn test_sandbox_resolve_new() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sandbox, project) = Sandbox::project(&dir.path().display().to_string()).unwrap();
+        let new = format!("{}/new.rs", dir.path().display());
+        assert_eq!(sandbox.resolve_new(&new).unwrap(), project.join("new.rs"));
+
+        let missing_dir = format!("{}/missing/new.rs", dir.path().display());
+        assert!(matches!(
+            sandbox.resolve_new(&missing_dir),
+            Err(FsError::NotFound(_))
+        ));
+        let outside = format!("{}/../new.rs", dir.path().display());
+        assert!(matches!(
+            sandbox.resolve_new(&outside),
+            Err(FsError::OutsideRoots(_))
+        ));
+        assert!(Sandbox::project("/nonexistent/project").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sandbox_follows_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(dir.path().join("secret"), "x").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), root.join("link")).unwrap();
+
+        std::os::unix::fs::symlink(dir.path().join("missing"), root.join("dangling")).unwrap();
+
+        let sandbox = Sandbox::new([&root]);
+        assert!(matches!(
+            sandbox.resolve(root.join("link").to_str().unwrap()),
+            Err(FsError::OutsideRoots(_))
+        ));
+        assert!(matches!(
+            sandbox.resolve_new(root.join("dangling").to_str().unwrap()),
+            Err(FsError::OutsideRoots(_))
+        ));
+    }
+}
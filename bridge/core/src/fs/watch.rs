@@ -0,0 +1,138 @@
+//! Watching directories for changes
+
+use std::path::{Component, Path, PathBuf};
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use super::FsResult;
+
+/// How a file changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change to a file below a watched directory
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// Reports changes below a directory for as long as it is kept
+///
+/// Changes inside `.git` directories are left out; they churn on every git
+/// command and clients show repository state through the git messages.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    /// Watch `path` and everything below it, sending changes to `tx`
+    pub fn new(path: &Path, tx: mpsc::UnboundedSender<FileChange>) -> FsResult<Self> {
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                for change in changes(event) {
+                    let _ = tx.send(change);
+                }
+            }
+        })?;
+        watcher.watch(path, RecursiveMode::Recursive)?;
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+impl std::fmt::Debug for FileWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileWatcher").finish_non_exhaustive()
+    }
+}
+
+/// Changes described by a watcher event
+fn changes(event: Event) -> Vec<FileChange> {
+    let kinds = match event.kind {
+        EventKind::Create(_) => [ChangeKind::Created; 2],
+        EventKind::Remove(_) => [ChangeKind::Removed; 2],
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => [ChangeKind::Removed; 2],
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => [ChangeKind::Created; 2],
+        // Renames within the watched tree name the old path, then the new one
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            [ChangeKind::Removed, ChangeKind::Created]
+        }
+        EventKind::Modify(_) | EventKind::Any => [ChangeKind::Modified; 2],
+        EventKind::Access(_) | EventKind::Other => return Vec::new(),
+    };
+    event
+        .paths
+        .into_iter()
+        .zip(kinds.into_iter().chain(std::iter::repeat(kinds[1])))
+        .filter(|(path, _)| !in_git_dir(path))
+        .map(|(path, kind)| FileChange { path, kind })
+        .collect()
+}
+
+/// Whether a path lies inside a `.git` directory
+fn in_git_dir(path: &Path) -> bool {
+    path.components()
+        .any(|component| component == Component::Normal(".git".as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind};
+    use std::time::Duration;
+
+    #[test]
+    fn test_changes() {
+        let create = Event::new(EventKind::Create(CreateKind::File)).add_path("/p/a.rs".into());
+        assert_eq!(
+            changes(create),
+            [FileChange {
+                path: "/p/a.rs".into(),
+                kind: ChangeKind::Created
+            }]
+        );
+
+        let rename = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path("/p/old.rs".into())
+            .add_path("/p/new.rs".into());
+        let kinds: Vec<_> = changes(rename).into_iter().map(|c| c.kind).collect();
+        assert_eq!(kinds, [ChangeKind::Removed, ChangeKind::Created]);
+
+        let git =
+            Event::new(EventKind::Create(CreateKind::File)).add_path("/p/.git/index.lock".into());
+        assert!(changes(git).is_empty());
+        let access = Event::new(EventKind::Access(AccessKind::Any)).add_path("/p/a.rs".into());
+        assert!(changes(access).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_watch_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let watcher = FileWatcher::new(&root, tx).unwrap();
+
+        std::fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.path, root.join("main.rs"));
+
+        // Dropping the watcher ends the stream of changes
+        drop(watcher);
+        while tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .is_some()
+        {}
+    }
+}
@@ -0,0 +1,260 @@
+//! Branch management
+//!
+//! Lists, creates, checks out and deletes branches, so a client can point an
+//! agent's worktree at a new branch. A branch checked out in one worktree is
+//! never checked out in or deleted from another.
+
+use std::path::Path;
+
+use git2::build::CheckoutBuilder;
+use git2::{BranchType, Repository};
+use serde::{Deserialize, Serialize};
+
+use super::GitError;
+
+/// A local or remote-tracking branch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BranchInfo {
+    /// Branch name (`origin/main` for remote-tracking branches)
+    pub name: String,
+    /// Whether this is a remote-tracking branch
+    #[serde(default)]
+    pub remote: bool,
+    /// Whether this branch is checked out in the queried worktree
+    #[serde(default)]
+    pub is_head: bool,
+    /// Upstream of a local branch, if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    /// Commit the branch points at
+    pub commit_id: String,
+    /// First line of that commit's message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// List local branches, and with `include_remote` remote-tracking ones too
+pub fn list_branches(
+    repo: &Repository,
+    include_remote: bool,
+) -> Result<Vec<BranchInfo>, GitError> {
+    let filter = if include_remote {
+        None
+    } else {
+        Some(BranchType::Local)
+    };
+    let mut branches = Vec::new();
+    for entry in repo.branches(filter)? {
+        let (branch, kind) = entry?;
+        // Symbolic refs such as origin/HEAD don't point at a commit of their own
+        if branch.get().symbolic_target().is_some() {
+            continue;
+        }
+        branches.push(branch_info(&branch, kind)?);
+    }
+    branches.sort_by(|a, b| (a.remote, &a.name).cmp(&(b.remote, &b.name)));
+    Ok(branches)
+}
+
+/// Create a local branch at `start_point` (any revision; HEAD when omitted)
+pub fn create_branch(
+    repo: &Repository,
+    name: &str,
+    start_point: Option<&str>,
+) -> Result<BranchInfo, GitError> {
+    let commit = match start_point {
+        Some(rev) => repo
+            .revparse_single(rev)
+            .map_err(|_| GitError::BranchNotFound(rev.to_string()))?
+            .peel_to_commit()?,
+        None => repo.head()?.peel_to_commit()?,
+    };
+    let branch = repo.branch(name, &commit, false)?;
+    branch_info(&branch, BranchType::Local)
+}
+
+/// Check a local branch out in the worktree, keeping uncommitted changes
+///
+/// Fails rather than overwriting changes that conflict with the branch.
+pub fn checkout_branch(repo: &Repository, name: &str) -> Result<(), GitError> {
+    let branch = repo
+        .find_branch(name, BranchType::Local)
+        .map_err(|_| GitError::BranchNotFound(name.to_string()))?;
+    if branch.is_head() {
+        return Ok(());
+    }
+    if let Some(path) = checked_out_in(repo, name)? {
+        return Err(GitError::BranchCheckedOut(name.to_string(), path));
+    }
+
+    let reference = branch.into_reference();
+    let target = reference.peel_to_commit()?;
+    repo.checkout_tree(target.as_object(), Some(CheckoutBuilder::new().safe()))?;
+    let refname = reference
+        .name()
+        .ok_or_else(|| GitError::BranchNotFound(name.to_string()))?;
+    repo.set_head(refname)?;
+    Ok(())
+}
+
+/// Delete a local branch
+///
+/// Branches checked out in any worktree are kept, as are ones whose commits
+/// are not in HEAD unless `force` is set.
+pub fn delete_branch(repo: &Repository, name: &str, force: bool) -> Result<(), GitError> {
+    let mut branch = repo
+        .find_branch(name, BranchType::Local)
+        .map_err(|_| GitError::BranchNotFound(name.to_string()))?;
+    let checked_out = if branch.is_head() {
+        repo.workdir().map(|w| w.display().to_string())
+    } else {
+        checked_out_in(repo, name)?
+    };
+    if let Some(path) = checked_out {
+        return Err(GitError::BranchCheckedOut(name.to_string(), path));
+    }
+
+    if !force {
+        let tip = branch.get().peel_to_commit()?.id();
+        let head = repo.head()?.peel_to_commit()?.id();
+        if tip != head && !repo.graph_descendant_of(head, tip)? {
+            return Err(GitError::BranchNotMerged(name.to_string()));
+        }
+    }
+    branch.delete()?;
+    Ok(())
+}
+
+/// Path of another worktree of the repository that has `name` checked out
+pub(super) fn checked_out_in(repo: &Repository, name: &str) -> Result<Option<String>, GitError> {
+    let refname = format!("refs/heads/{}", name);
+    let has_checked_out = |other: &Repository| {
+        other.head().ok().and_then(|h| h.name().map(String::from)) == Some(refname.clone())
+    };
+
+    // The main worktree and each linked one, which may include this one. A
+    // linked worktree's git dir is <main git dir>/worktrees/<name>
+    let main_git_dir = if repo.is_worktree() {
+        repo.path().parent().and_then(Path::parent).unwrap_or(repo.path())
+    } else {
+        repo.path()
+    };
+    let main = Repository::open(main_git_dir)?;
+    let mut others = vec![main];
+    for worktree in repo.worktrees()?.iter().flatten() {
+        if let Ok(worktree) = repo.find_worktree(worktree) {
+            if let Ok(other) = Repository::open_from_worktree(&worktree) {
+                others.push(other);
+            }
+        }
+    }
+    let this = repo.workdir().and_then(|w| w.canonicalize().ok());
+    Ok(others
+        .iter()
+        .filter(|other| other.workdir().and_then(|w| w.canonicalize().ok()) != this)
+        .find(|other| has_checked_out(other))
+        .and_then(|other| other.workdir().map(|w| w.display().to_string())))
+}
+
+fn branch_info(branch: &git2::Branch, kind: BranchType) -> Result<BranchInfo, GitError> {
+    let commit = branch.get().peel_to_commit()?;
+    Ok(BranchInfo {
+        name: branch.name()?.unwrap_or_default().to_string(),
+        remote: kind == BranchType::Remote,
+        is_head: branch.is_head(),
+        upstream: branch
+            .upstream()
+            .ok()
+            .and_then(|u| u.name().ok().flatten().map(String::from)),
+        commit_id: commit.id().to_string(),
+        summary: commit.summary().map(String::from),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::create_worktree;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn commit(repo: &Repository, name: &str, message: &str) -> git2::Oid {
+        fs::write(repo.workdir().unwrap().join(name), message).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_create_checkout_and_delete() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let first = commit(&repo, "a.txt", "First");
+        let main = repo.head().unwrap().shorthand().unwrap().to_string();
+
+        let created = create_branch(&repo, "agent/feature", None).unwrap();
+        assert_eq!(created.commit_id, first.to_string());
+        assert!(!created.is_head);
+        assert!(create_branch(&repo, "other", Some("no-such-rev")).is_err());
+
+        checkout_branch(&repo, "agent/feature").unwrap();
+        commit(&repo, "b.txt", "Second");
+        let branches = list_branches(&repo, false).unwrap();
+        let names: Vec<_> = branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["agent/feature", main.as_str()]);
+        assert!(branches[0].is_head);
+        assert_eq!(branches[0].summary.as_deref(), Some("Second"));
+
+        // The checked-out branch stays; the unmerged one needs force
+        assert!(matches!(
+            delete_branch(&repo, "agent/feature", true),
+            Err(GitError::BranchCheckedOut(..))
+        ));
+        checkout_branch(&repo, &main).unwrap();
+        assert!(!temp_dir.path().join("b.txt").exists());
+        assert!(matches!(
+            delete_branch(&repo, "agent/feature", false),
+            Err(GitError::BranchNotMerged(_))
+        ));
+        delete_branch(&repo, "agent/feature", true).unwrap();
+        assert_eq!(list_branches(&repo, true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_branch_checked_out_in_another_worktree() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path().join("main")).unwrap();
+        commit(&repo, "a.txt", "First");
+        create_branch(&repo, "agent-1", None).unwrap();
+        let worktree_path = temp_dir.path().join("agent-1");
+        create_worktree(&repo, &worktree_path, "agent-1").unwrap();
+
+        assert!(matches!(
+            checkout_branch(&repo, "agent-1"),
+            Err(GitError::BranchCheckedOut(..))
+        ));
+        assert!(matches!(
+            delete_branch(&repo, "agent-1", true),
+            Err(GitError::BranchCheckedOut(..))
+        ));
+
+        // The worktree itself can move to another branch
+        let worktree = Repository::open(&worktree_path).unwrap();
+        create_branch(&worktree, "agent-1-retry", None).unwrap();
+        checkout_branch(&worktree, "agent-1-retry").unwrap();
+        assert_eq!(worktree.head().unwrap().shorthand(), Some("agent-1-retry"));
+        delete_branch(&repo, "agent-1", false).unwrap();
+    }
+}
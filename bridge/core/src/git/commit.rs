@@ -0,0 +1,197 @@
+//! Staging and committing
+//!
+//! Lets a client approve an agent's work: stage or unstage files in the
+//! agent's repository and commit what is staged.
+
+use std::path::Path;
+
+use git2::{ApplyLocation, Diff, IndexAddOption, Repository, Signature};
+
+use super::GitError;
+
+/// Committer used when the repository has no `user.name`/`user.email`
+const FALLBACK_NAME: &str = "HoC Bridge";
+const FALLBACK_EMAIL: &str = "bridge@localhost";
+
+/// Stage changes to the files matching `paths` (pathspecs relative to the
+/// repository root), deletions included
+pub fn stage_paths(repo: &Repository, paths: &[String]) -> Result<(), GitError> {
+    let mut index = repo.index()?;
+    index.add_all(paths, IndexAddOption::DEFAULT, None)?;
+    // add_all only adds; update_all also stages files deleted from the worktree
+    index.update_all(paths, None)?;
+    index.write()?;
+    Ok(())
+}
+
+/// Stage one file, given by its path in the worktree
+pub fn stage_file(repo: &Repository, file: &Path) -> Result<(), GitError> {
+    let workdir = repo
+        .workdir()
+        .and_then(|workdir| workdir.canonicalize().ok())
+        .ok_or_else(|| GitError::InvalidPath(file.display().to_string()))?;
+    let relative = file
+        .strip_prefix(&workdir)
+        .map_err(|_| GitError::InvalidPath(file.display().to_string()))?;
+    let mut index = repo.index()?;
+    index.add_path(relative)?;
+    index.write()?;
+    Ok(())
+}
+
+/// Apply a unified diff to the worktree, and to the index as well when
+/// `stage` is set, returning the paths it touches
+///
+/// Nothing is changed unless the whole patch applies.
+pub fn apply_patch(repo: &Repository, patch: &str, stage: bool) -> Result<Vec<String>, GitError> {
+    let diff = Diff::from_buffer(patch.as_bytes())?;
+    let paths = diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    let location = if stage {
+        ApplyLocation::Both
+    } else {
+        ApplyLocation::WorkDir
+    };
+    repo.apply(&diff, location, None)?;
+    Ok(paths)
+}
+
+/// Unstage the files matching `paths`, leaving the worktree untouched
+pub fn unstage_paths(repo: &Repository, paths: &[String]) -> Result<(), GitError> {
+    match repo.head().ok().and_then(|h| h.peel_to_commit().ok()) {
+        Some(head) => repo.reset_default(Some(head.as_object()), paths)?,
+        // Before the first commit, unstaging removes the files from the index
+        None => {
+            let mut index = repo.index()?;
+            for path in paths {
+                index.remove_all([path], None)?;
+            }
+            index.write()?;
+        }
+    }
+    Ok(())
+}
+
+/// Commit what is staged on the current branch, returning the commit ID
+///
+/// The author is `author` (name, email) when given, otherwise the
+/// repository's configured user.
+pub fn commit_staged(
+    repo: &Repository,
+    message: &str,
+    author: Option<(&str, &str)>,
+) -> Result<String, GitError> {
+    let mut index = repo.index()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+    let unchanged = match &parent {
+        Some(parent) => parent.tree_id() == tree.id(),
+        None => tree.is_empty(),
+    };
+    if unchanged {
+        return Err(GitError::NothingToCommit);
+    }
+
+    let committer = repo
+        .signature()
+        .or_else(|_| Signature::now(FALLBACK_NAME, FALLBACK_EMAIL))?;
+    let author = match author {
+        Some((name, email)) => Signature::now(name, email)?,
+        None => committer.clone(),
+    };
+    let parents: Vec<_> = parent.iter().collect();
+    let id = repo.commit(Some("HEAD"), &author, &committer, message, &tree, &parents)?;
+    Ok(id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn staged_paths(repo: &Repository) -> Vec<String> {
+        let mut paths: Vec<_> = repo
+            .statuses(None)
+            .unwrap()
+            .iter()
+            .filter(|s| s.status().is_index_new() || s.status().is_index_deleted())
+            .filter_map(|s| s.path().map(String::from))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_stage_unstage_and_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "a").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "b").unwrap();
+
+        assert!(matches!(
+            commit_staged(&repo, "Empty", None),
+            Err(GitError::NothingToCommit)
+        ));
+        stage_paths(&repo, &[".".to_string()]).unwrap();
+        assert_eq!(staged_paths(&repo), vec!["a.txt", "b.txt"]);
+        unstage_paths(&repo, &["b.txt".to_string()]).unwrap();
+        assert_eq!(staged_paths(&repo), vec!["a.txt"]);
+
+        let first = commit_staged(&repo, "Add a", Some(("Agent", "agent@example.com"))).unwrap();
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(commit.id().to_string(), first);
+        assert_eq!(commit.message(), Some("Add a"));
+        assert_eq!(commit.author().name(), Some("Agent"));
+
+        // Deletions are staged too, and unstaging restores the committed entry
+        fs::remove_file(temp_dir.path().join("a.txt")).unwrap();
+        stage_paths(&repo, &["a.txt".to_string()]).unwrap();
+        assert_eq!(staged_paths(&repo), vec!["a.txt"]);
+        unstage_paths(&repo, &["a.txt".to_string()]).unwrap();
+        assert!(staged_paths(&repo).is_empty());
+        assert!(repo.index().unwrap().get_path(Path::new("a.txt"), 0).is_some());
+
+        stage_paths(&repo, &["a.txt".to_string()]).unwrap();
+        let second = commit_staged(&repo, "Remove a", None).unwrap();
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(commit.id().to_string(), second);
+        assert_eq!(commit.parent_id(0).unwrap().to_string(), first);
+        assert!(commit.tree().unwrap().get_name("a.txt").is_none());
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "one\ntwo\n").unwrap();
+        stage_paths(&repo, &[".".to_string()]).unwrap();
+        commit_staged(&repo, "Add a", None).unwrap();
+
+        let patch = "diff --git a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n\
+                     @@ -1,2 +1,2 @@\n one\n-two\n+three\n";
+        assert_eq!(apply_patch(&repo, patch, true).unwrap(), vec!["a.txt"]);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("a.txt")).unwrap(),
+            "one\nthree\n"
+        );
+        let status = repo.status_file(Path::new("a.txt")).unwrap();
+        assert!(status.is_index_modified() && !status.is_wt_modified());
+
+        fs::write(temp_dir.path().join("b.txt"), "b").unwrap();
+        let workdir = temp_dir.path().canonicalize().unwrap();
+        stage_file(&repo, &workdir.join("b.txt")).unwrap();
+        assert_eq!(staged_paths(&repo), vec!["b.txt"]);
+        assert!(matches!(
+            stage_file(&repo, Path::new("/elsewhere/b.txt")),
+            Err(GitError::InvalidPath(_))
+        ));
+
+        // The same patch no longer applies, and nothing changes
+        assert!(apply_patch(&repo, patch, false).is_err());
+        assert!(apply_patch(&repo, "not a patch", false).is_err());
+    }
+}
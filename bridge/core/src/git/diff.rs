@@ -0,0 +1,273 @@
+//! Git diffs
+//!
+//! Computes what changed in a worktree as structured files, hunks and lines,
+//! so clients can show an agent's changes without parsing unified diff text.
+
+use git2::{Delta, DiffOptions, Patch, Repository};
+use serde::{Deserialize, Serialize};
+
+use super::GitError;
+
+/// Most diff lines returned for one request; the rest are dropped
+pub const MAX_DIFF_LINES: usize = 20_000;
+
+/// How a file changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+    TypeChanged,
+    Untracked,
+}
+
+/// Changes to one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDiff {
+    /// Path relative to the repository root
+    pub path: String,
+    /// Previous path, for renames and copies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    pub status: FileStatus,
+    /// Binary files have no hunks
+    #[serde(default)]
+    pub binary: bool,
+    pub hunks: Vec<DiffHunk>,
+}
+
+/// A contiguous block of changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffHunk {
+    /// The `@@ -a,b +c,d @@` header, including any function context
+    pub header: String,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    pub lines: Vec<DiffLine>,
+}
+
+/// Kind of a diff line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineKind {
+    Context,
+    Added,
+    Removed,
+}
+
+/// One line of a hunk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    /// Line content without its line ending
+    pub content: String,
+    /// Line number before the change (not set for added lines)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_lineno: Option<u32>,
+    /// Line number after the change (not set for removed lines)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_lineno: Option<u32>,
+}
+
+/// Diff of a repository's worktree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorktreeDiff {
+    pub files: Vec<FileDiff>,
+    /// Whether lines were dropped to stay under [`MAX_DIFF_LINES`]
+    pub truncated: bool,
+}
+
+/// Diff the changes in a worktree, optionally limited to `path`
+///
+/// With `staged`, shows what is staged for the next commit (HEAD against the
+/// index); otherwise what is not staged yet (the index against the working
+/// directory, untracked files included).
+pub fn diff_worktree(
+    repo: &Repository,
+    path: Option<&str>,
+    staged: bool,
+) -> Result<WorktreeDiff, GitError> {
+    let mut options = DiffOptions::new();
+    if let Some(path) = path {
+        options.pathspec(path);
+    }
+
+    let mut diff = if staged {
+        // An unborn branch has no HEAD tree, so everything staged is added
+        let head = repo.head().ok().and_then(|h| h.peel_to_tree().ok());
+        repo.diff_tree_to_index(head.as_ref(), None, Some(&mut options))?
+    } else {
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        repo.diff_index_to_workdir(None, Some(&mut options))?
+    };
+    diff.find_similar(None)?;
+
+    let mut result = WorktreeDiff::default();
+    let mut budget = MAX_DIFF_LINES;
+    for index in 0..diff.deltas().len() {
+        let Some(delta) = diff.get_delta(index) else {
+            continue;
+        };
+        let new_path = delta.new_file().path().map(|p| p.display().to_string());
+        let old_path = delta.old_file().path().map(|p| p.display().to_string());
+        let Some(path) = new_path.clone().or_else(|| old_path.clone()) else {
+            continue;
+        };
+        let status = match delta.status() {
+            Delta::Added => FileStatus::Added,
+            Delta::Deleted => FileStatus::Deleted,
+            Delta::Renamed => FileStatus::Renamed,
+            Delta::Copied => FileStatus::Copied,
+            Delta::Typechange => FileStatus::TypeChanged,
+            Delta::Untracked => FileStatus::Untracked,
+            _ => FileStatus::Modified,
+        };
+        let mut file = FileDiff {
+            old_path: old_path.filter(|old| Some(old) != new_path.as_ref()),
+            path,
+            status,
+            binary: delta.flags().is_binary(),
+            hunks: Vec::new(),
+        };
+
+        if let Some(patch) = Patch::from_diff(&diff, index)? {
+            file.binary |= patch.delta().flags().is_binary();
+            for hunk_index in 0..patch.num_hunks() {
+                let (hunk, line_count) = patch.hunk(hunk_index)?;
+                let mut lines = Vec::new();
+                for line_index in 0..line_count {
+                    let line = patch.line_in_hunk(hunk_index, line_index)?;
+                    let kind = match line.origin() {
+                        ' ' => LineKind::Context,
+                        '+' => LineKind::Added,
+                        '-' => LineKind::Removed,
+                        // "No newline at end of file" markers
+                        _ => continue,
+                    };
+                    if budget == 0 {
+                        result.truncated = true;
+                        break;
+                    }
+                    budget -= 1;
+                    let content = String::from_utf8_lossy(line.content());
+                    lines.push(DiffLine {
+                        kind,
+                        content: content.trim_end_matches(['\n', '\r']).to_string(),
+                        old_lineno: line.old_lineno(),
+                        new_lineno: line.new_lineno(),
+                    });
+                }
+                file.hunks.push(DiffHunk {
+                    header: String::from_utf8_lossy(hunk.header()).trim_end().to_string(),
+                    old_start: hunk.old_start(),
+                    old_lines: hunk.old_lines(),
+                    new_start: hunk.new_start(),
+                    new_lines: hunk.new_lines(),
+                    lines,
+                });
+                if result.truncated {
+                    break;
+                }
+            }
+        }
+        result.files.push(file);
+        if result.truncated {
+            break;
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn commit_file(repo: &Repository, name: &str, content: &str) {
+        let workdir = repo.workdir().unwrap();
+        fs::write(workdir.join(name), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Commit",
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_unstaged_and_staged_diffs() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        commit_file(&repo, "main.rs", "fn main() {\n    println!(\"hi\");\n}\n");
+
+        fs::write(
+            temp_dir.path().join("main.rs"),
+            "fn main() {\n    println!(\"hello\");\n}\n",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("notes.md"), "todo\n").unwrap();
+
+        let diff = diff_worktree(&repo, None, false).unwrap();
+        assert!(!diff.truncated);
+        assert_eq!(diff.files.len(), 2);
+        let main = diff.files.iter().find(|f| f.path == "main.rs").unwrap();
+        assert_eq!(main.status, FileStatus::Modified);
+        assert_eq!(main.hunks.len(), 1);
+        let hunk = &main.hunks[0];
+        assert!(hunk.header.starts_with("@@ -1,3 +1,3 @@"));
+        let removed = hunk
+            .lines
+            .iter()
+            .find(|l| l.kind == LineKind::Removed)
+            .unwrap();
+        assert_eq!(removed.content, "    println!(\"hi\");");
+        assert_eq!((removed.old_lineno, removed.new_lineno), (Some(2), None));
+        let notes = diff.files.iter().find(|f| f.path == "notes.md").unwrap();
+        assert_eq!(notes.status, FileStatus::Untracked);
+        assert_eq!(notes.hunks[0].lines[0].kind, LineKind::Added);
+
+        // Nothing is staged yet; then only the staged file shows
+        assert!(diff_worktree(&repo, None, true).unwrap().files.is_empty());
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("notes.md")).unwrap();
+        index.write().unwrap();
+        let staged = diff_worktree(&repo, None, true).unwrap();
+        assert_eq!(staged.files.len(), 1);
+        assert_eq!(staged.files[0].status, FileStatus::Added);
+
+        let only_main = diff_worktree(&repo, Some("main.rs"), false).unwrap();
+        assert_eq!(only_main.files.len(), 1);
+    }
+
+    #[test]
+    fn test_binary_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        fs::write(temp_dir.path().join("image.bin"), [0u8, 159, 146, 150, 0, 1]).unwrap();
+
+        let diff = diff_worktree(&repo, None, false).unwrap();
+        assert_eq!(diff.files.len(), 1);
+        assert!(diff.files[0].binary);
+        assert!(diff.files[0].hunks.is_empty());
+    }
+}
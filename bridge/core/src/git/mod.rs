@@ -0,0 +1,18 @@
+//! Git operations module
+//!
+//! Provides git repository detection, worktree and branch management, diffs,
+//! commits, and pushing to and pulling from remotes.
+
+mod branch;
+mod commit;
+mod diff;
+mod remote;
+#[allow(dead_code)]
+mod worktree;
+
+pub use branch::*;
+pub use commit::*;
+pub use diff::*;
+pub use remote::*;
+#[allow(unused_imports)]
+pub use worktree::*;
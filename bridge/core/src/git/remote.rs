@@ -0,0 +1,359 @@
+//! Pushing and pulling
+//!
+//! Moves branches between a repository and its remotes. Credentials come
+//! from the ssh-agent for SSH remotes, and from a token or the user's git
+//! credential helper for HTTPS ones; transfer progress is reported as it
+//! happens so clients can show it.
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use git2::build::CheckoutBuilder;
+use git2::{
+    BranchType, Cred, CredentialType, FetchOptions, PushOptions, RemoteCallbacks, Repository,
+};
+use serde::{Deserialize, Serialize};
+
+use super::branch::checked_out_in;
+use super::GitError;
+
+/// Remote used when none is named
+pub const DEFAULT_REMOTE: &str = "origin";
+
+/// Username sent with a token when the remote URL doesn't name one (hosts
+/// that take tokens as passwords ignore it)
+const TOKEN_USERNAME: &str = "git";
+
+/// Times credentials are offered before giving up, as libgit2 keeps asking
+/// while the remote rejects them
+const MAX_CREDENTIAL_ATTEMPTS: u32 = 3;
+
+/// Shortest interval between two progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How far a push or fetch has got
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferProgress {
+    /// Objects sent or received so far
+    pub objects: usize,
+    /// Objects to transfer in total
+    pub total_objects: usize,
+    /// Bytes sent or received so far
+    pub bytes: usize,
+}
+
+/// Result of a pull
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullOutcome {
+    /// Commit the branch points at afterwards
+    pub commit_id: String,
+    /// Whether the branch moved
+    pub updated: bool,
+}
+
+/// Push a local branch to the branch of the same name on `remote`
+///
+/// A branch without an upstream gets the pushed one as its upstream. With
+/// `force`, the remote branch is overwritten even if that loses commits.
+pub fn push_branch(
+    repo: &Repository,
+    remote: &str,
+    branch: &str,
+    force: bool,
+    token: Option<&str>,
+    progress: impl FnMut(TransferProgress),
+) -> Result<(), GitError> {
+    let mut local = repo
+        .find_branch(branch, BranchType::Local)
+        .map_err(|_| GitError::BranchNotFound(branch.to_string()))?;
+    let mut remote_handle = repo.find_remote(remote)?;
+
+    let rejected = RefCell::new(None);
+    let progress = RefCell::new(Throttled::new(progress));
+    let mut callbacks = remote_callbacks(token);
+    callbacks.push_transfer_progress(|objects, total_objects, bytes| {
+        progress.borrow_mut().report(TransferProgress {
+            objects,
+            total_objects,
+            bytes,
+        });
+    });
+    callbacks.push_update_reference(|refname, status| {
+        if let Some(status) = status {
+            *rejected.borrow_mut() = Some(format!("{}: {}", refname, status));
+        }
+        Ok(())
+    });
+
+    let refspec = format!(
+        "{}refs/heads/{}:refs/heads/{}",
+        if force { "+" } else { "" },
+        branch,
+        branch
+    );
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+    remote_handle.push(&[refspec.as_str()], Some(&mut options))?;
+    drop(options);
+
+    if let Some(reason) = rejected.into_inner() {
+        return Err(GitError::PushRejected(reason));
+    }
+    progress.into_inner().finish();
+    if local.upstream().is_err() {
+        // Best effort, like `git push -u`; the push itself succeeded
+        let _ = local.set_upstream(Some(&format!("{}/{}", remote, branch)));
+    }
+    Ok(())
+}
+
+/// Fetch `branch` from `remote` and fast-forward the local branch to it
+///
+/// A missing local branch is created tracking the remote one. The worktree
+/// is updated when the branch is checked out in it, keeping uncommitted
+/// changes that don't conflict; branches checked out in another worktree
+/// are left alone. Branches that have diverged are never merged.
+pub fn pull_branch(
+    repo: &Repository,
+    remote: &str,
+    branch: &str,
+    token: Option<&str>,
+    progress: impl FnMut(TransferProgress),
+) -> Result<PullOutcome, GitError> {
+    let mut remote_handle = repo.find_remote(remote)?;
+    let progress = RefCell::new(Throttled::new(progress));
+    let mut callbacks = remote_callbacks(token);
+    callbacks.transfer_progress(|stats| {
+        progress.borrow_mut().report(TransferProgress {
+            objects: stats.received_objects(),
+            total_objects: stats.total_objects(),
+            bytes: stats.received_bytes(),
+        });
+        true
+    });
+
+    let tracking = format!("refs/remotes/{}/{}", remote, branch);
+    let refspec = format!("+refs/heads/{}:{}", branch, tracking);
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    remote_handle.fetch(&[refspec.as_str()], Some(&mut options), None)?;
+    drop(options);
+    progress.into_inner().finish();
+
+    let target = repo
+        .find_reference(&tracking)
+        .map_err(|_| GitError::BranchNotFound(format!("{}/{}", remote, branch)))?
+        .peel_to_commit()?;
+    let commit_id = target.id().to_string();
+
+    let mut local = match repo.find_branch(branch, BranchType::Local) {
+        Ok(local) => local,
+        Err(_) => {
+            let mut created = repo.branch(branch, &target, false)?;
+            created.set_upstream(Some(&format!("{}/{}", remote, branch)))?;
+            return Ok(PullOutcome {
+                commit_id,
+                updated: true,
+            });
+        }
+    };
+
+    let tip = local.get().peel_to_commit()?.id();
+    if tip == target.id() || repo.graph_descendant_of(tip, target.id())? {
+        return Ok(PullOutcome {
+            commit_id: tip.to_string(),
+            updated: false,
+        });
+    }
+    if !repo.graph_descendant_of(target.id(), tip)? {
+        return Err(GitError::NotFastForward(branch.to_string()));
+    }
+
+    if local.is_head() {
+        repo.checkout_tree(target.as_object(), Some(CheckoutBuilder::new().safe()))?;
+    } else if let Some(path) = checked_out_in(repo, branch)? {
+        return Err(GitError::BranchCheckedOut(branch.to_string(), path));
+    }
+    let message = format!("pull: fast-forward to {}/{}", remote, branch);
+    local.get_mut().set_target(target.id(), &message)?;
+    Ok(PullOutcome {
+        commit_id,
+        updated: true,
+    })
+}
+
+/// Callbacks offering the ssh-agent, then `token` or the credential helper
+fn remote_callbacks<'a>(token: Option<&'a str>) -> RemoteCallbacks<'a> {
+    let mut attempts = 0;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        attempts += 1;
+        if attempts > MAX_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str("Authentication failed"));
+        }
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username.unwrap_or(TOKEN_USERNAME));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or(TOKEN_USERNAME));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if let Some(token) = token {
+                return Cred::userpass_plaintext(username.unwrap_or(TOKEN_USERNAME), token);
+            }
+            let config = git2::Config::open_default()?;
+            return Cred::credential_helper(&config, url, username);
+        }
+        if allowed.contains(CredentialType::DEFAULT) {
+            return Cred::default();
+        }
+        Err(git2::Error::from_str("No supported credentials for remote"))
+    });
+    callbacks
+}
+
+/// Passes progress on at most every [`PROGRESS_INTERVAL`]
+struct Throttled<F> {
+    report: F,
+    last: Option<Instant>,
+    pending: Option<TransferProgress>,
+}
+
+impl<F: FnMut(TransferProgress)> Throttled<F> {
+    fn new(report: F) -> Self {
+        Self {
+            report,
+            last: None,
+            pending: None,
+        }
+    }
+
+    fn report(&mut self, progress: TransferProgress) {
+        if self.last.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+            self.pending = Some(progress);
+            return;
+        }
+        self.last = Some(Instant::now());
+        self.pending = None;
+        (self.report)(progress);
+    }
+
+    /// Pass on the last progress held back, so the final count is seen
+    fn finish(mut self) {
+        if let Some(progress) = self.pending.take() {
+            (self.report)(progress);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn commit(repo: &Repository, name: &str, message: &str) {
+        fs::write(repo.workdir().unwrap().join(name), message).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .unwrap();
+    }
+
+    /// A bare "upstream" repository and two clones of it
+    fn clones(dir: &TempDir) -> (Repository, Repository) {
+        let upstream = dir.path().join("upstream.git");
+        Repository::init_bare(&upstream).unwrap();
+        let url = upstream.display().to_string();
+        let first = Repository::init(dir.path().join("first")).unwrap();
+        first.remote(DEFAULT_REMOTE, &url).unwrap();
+        let second = Repository::init(dir.path().join("second")).unwrap();
+        second.remote(DEFAULT_REMOTE, &url).unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn test_push_and_pull() {
+        let dir = TempDir::new().unwrap();
+        let (first, second) = clones(&dir);
+        commit(&first, "a.txt", "First");
+        let branch = first.head().unwrap().shorthand().unwrap().to_string();
+
+        let mut reports = Vec::new();
+        push_branch(&first, DEFAULT_REMOTE, &branch, false, None, |p| reports.push(p)).unwrap();
+        assert!(!reports.is_empty());
+        let upstream = first.find_branch(&branch, BranchType::Local).unwrap();
+        assert!(upstream.upstream().is_ok());
+
+        // A new local branch is created, then fast-forwarded in the worktree
+        let pulled = pull_branch(&second, DEFAULT_REMOTE, &branch, None, |_| {}).unwrap();
+        assert!(pulled.updated);
+        second.set_head(&format!("refs/heads/{}", branch)).unwrap();
+        second
+            .checkout_head(Some(CheckoutBuilder::new().force()))
+            .unwrap();
+        commit(&first, "b.txt", "Second");
+        push_branch(&first, DEFAULT_REMOTE, &branch, false, None, |_| {}).unwrap();
+        let pulled = pull_branch(&second, DEFAULT_REMOTE, &branch, None, |_| {}).unwrap();
+        assert!(pulled.updated);
+        assert_eq!(
+            pulled.commit_id,
+            first.head().unwrap().target().unwrap().to_string()
+        );
+        assert!(second.workdir().unwrap().join("b.txt").exists());
+        let again = pull_branch(&second, DEFAULT_REMOTE, &branch, None, |_| {}).unwrap();
+        assert!(!again.updated);
+    }
+
+    #[test]
+    fn test_diverged_branches() {
+        let dir = TempDir::new().unwrap();
+        let (first, second) = clones(&dir);
+        commit(&first, "a.txt", "First");
+        let branch = first.head().unwrap().shorthand().unwrap().to_string();
+        push_branch(&first, DEFAULT_REMOTE, &branch, false, None, |_| {}).unwrap();
+        commit(&second, "b.txt", "Unrelated");
+
+        // Neither side overwrites the other's history without force
+        assert!(matches!(
+            push_branch(&second, DEFAULT_REMOTE, &branch, false, None, |_| {}),
+            Err(GitError::PushRejected(_) | GitError::Git(_))
+        ));
+        assert!(matches!(
+            pull_branch(&second, DEFAULT_REMOTE, &branch, None, |_| {}),
+            Err(GitError::NotFastForward(_))
+        ));
+        push_branch(&second, DEFAULT_REMOTE, &branch, true, None, |_| {}).unwrap();
+        assert!(matches!(
+            push_branch(&first, DEFAULT_REMOTE, "no-such-branch", false, None, |_| {}),
+            Err(GitError::BranchNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_throttled_progress() {
+        let mut reports = Vec::new();
+        let mut throttled = Throttled::new(|p: TransferProgress| reports.push(p.objects));
+        for objects in 1..=5 {
+            throttled.report(TransferProgress {
+                objects,
+                total_objects: 5,
+                bytes: 0,
+            });
+        }
+        throttled.finish();
+        assert_eq!(reports, vec![1, 5]);
+    }
+}
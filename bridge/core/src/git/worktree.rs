@@ -2,8 +2,9 @@
 //!
 //! Manages git worktrees for isolated agent workspaces.
 
-use git2::{BranchType, Repository};
-use std::path::Path;
+use git2::{BranchType, Repository, StatusOptions, WorktreePruneOptions};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 /// Errors that can occur during git operations
@@ -19,10 +20,26 @@ pub enum GitError {
     BranchNotFound(String),
     #[error("Invalid worktree path: {0}")]
     InvalidPath(String),
+    #[error("Worktree not found: {0}")]
+    WorktreeNotFound(String),
+    #[error("Worktree has uncommitted changes: {0}")]
+    WorktreeDirty(String),
+    #[error("Worktree is locked: {0}")]
+    WorktreeLocked(String),
+    #[error("Nothing staged to commit")]
+    NothingToCommit,
+    #[error("Branch {0} is checked out in {1}")]
+    BranchCheckedOut(String, String),
+    #[error("Branch {0} is not merged into HEAD")]
+    BranchNotMerged(String),
+    #[error("Push rejected: {0}")]
+    PushRejected(String),
+    #[error("Branch {0} has diverged from the remote and can't be fast-forwarded")]
+    NotFastForward(String),
 }
 
 /// Information about a git worktree
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct WorktreeInfo {
     /// Path to the worktree
     pub path: String,
@@ -59,13 +76,17 @@ pub fn list_worktrees(repo: &Repository) -> Result<Vec<WorktreeInfo>, GitError>
         });
     }
 
-    // Add linked worktrees
+    // Add linked worktrees, with the branch each has checked out now
     for name in worktrees.iter().flatten() {
         if let Ok(wt) = repo.find_worktree(name) {
             if let Some(path) = wt.path().to_str() {
+                let branch = Repository::open_from_worktree(&wt)
+                    .ok()
+                    .and_then(|r| r.head().ok()?.shorthand().map(String::from))
+                    .unwrap_or_else(|| name.to_string());
                 result.push(WorktreeInfo {
                     path: path.to_string(),
-                    branch: Some(name.to_string()),
+                    branch: Some(branch),
                     is_main: false,
                 });
             }
@@ -75,6 +96,25 @@ pub fn list_worktrees(repo: &Repository) -> Result<Vec<WorktreeInfo>, GitError>
     Ok(result)
 }
 
+/// Get the worktree containing `path` and the branch checked out in it
+pub fn worktree_for(path: &Path) -> Result<WorktreeInfo, GitError> {
+    let repo = open_repository(path)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| GitError::NotARepository(path.display().to_string()))?;
+    let branch = repo
+        .head()
+        .ok()
+        .and_then(|h| h.shorthand().map(String::from));
+
+    Ok(WorktreeInfo {
+        // Drops the trailing separator git2 reports
+        path: workdir.components().collect::<PathBuf>().display().to_string(),
+        branch,
+        is_main: !repo.is_worktree(),
+    })
+}
+
 /// Create a new worktree for the specified branch
 ///
 /// # Arguments
@@ -147,6 +187,92 @@ pub fn create_worktree(
     })
 }
 
+/// Create a worktree for a client: at `worktree_path` or the default path,
+/// creating missing parent directories, and with `create_branch` the branch
+/// itself from HEAD if it doesn't exist yet
+pub fn add_worktree(
+    repo: &Repository,
+    branch_name: &str,
+    worktree_path: Option<&Path>,
+    create_branch: bool,
+) -> Result<WorktreeInfo, GitError> {
+    if create_branch && repo.find_branch(branch_name, BranchType::Local).is_err() {
+        let head = repo.head()?.peel_to_commit()?;
+        repo.branch(branch_name, &head, false)?;
+    }
+    let worktree_path = match worktree_path {
+        Some(path) => path.to_path_buf(),
+        None => default_worktree_path(repo, branch_name)?,
+    };
+    if let Some(parent) = worktree_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| GitError::InvalidPath(format!("{}: {}", parent.display(), e)))?;
+    }
+    create_worktree(repo, &worktree_path, branch_name)
+}
+
+/// Where a new worktree for `branch_name` goes when no path is given: a
+/// `<repo>-worktrees` directory next to the main worktree
+pub fn default_worktree_path(repo: &Repository, branch_name: &str) -> Result<PathBuf, GitError> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| GitError::InvalidPath("Repository has no working directory".into()))?;
+    let workdir = workdir.components().collect::<PathBuf>();
+    let name = workdir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| GitError::InvalidPath(workdir.display().to_string()))?;
+    let parent = workdir.parent().unwrap_or(&workdir);
+    Ok(parent
+        .join(format!("{}-worktrees", name))
+        .join(branch_name.replace('/', "-")))
+}
+
+/// Remove a linked worktree along with its working directory
+///
+/// The branch it had checked out is kept. Worktrees with uncommitted changes
+/// (untracked files included) or locked ones are only removed with `force`.
+pub fn remove_worktree(
+    repo: &Repository,
+    worktree_path: &Path,
+    force: bool,
+) -> Result<(), GitError> {
+    let target = worktree_path
+        .canonicalize()
+        .unwrap_or_else(|_| worktree_path.to_path_buf());
+    if repo.workdir().and_then(|w| w.canonicalize().ok()) == Some(target.clone()) {
+        return Err(GitError::InvalidPath("Cannot remove the main worktree".into()));
+    }
+
+    let worktrees = repo.worktrees()?;
+    let worktree = worktrees
+        .iter()
+        .flatten()
+        .filter_map(|name| repo.find_worktree(name).ok())
+        .find(|wt| wt.path().canonicalize().ok().as_deref() == Some(target.as_path()))
+        .ok_or_else(|| GitError::WorktreeNotFound(worktree_path.display().to_string()))?;
+
+    if !force {
+        if matches!(worktree.is_locked()?, git2::WorktreeLockStatus::Locked(_)) {
+            return Err(GitError::WorktreeLocked(worktree_path.display().to_string()));
+        }
+        let checkout = Repository::open_from_worktree(&worktree)?;
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).include_ignored(false);
+        if !checkout.statuses(Some(&mut options))?.is_empty() {
+            return Err(GitError::WorktreeDirty(worktree_path.display().to_string()));
+        }
+    }
+
+    worktree.prune(Some(
+        WorktreePruneOptions::new()
+            .valid(true)
+            .locked(force)
+            .working_tree(true),
+    ))?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -216,6 +342,26 @@ mod tests {
             .contains(temp_dir.path().to_str().unwrap()));
     }
 
+    #[test]
+    fn test_worktree_for() {
+        let (temp_dir, repo) = create_test_repo();
+        let head_commit = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("feature", &head_commit, false).unwrap();
+        let worktree_path = temp_dir.path().join("feature");
+        create_worktree(&repo, &worktree_path, "feature").unwrap();
+
+        let subdir = worktree_path.join("src");
+        fs::create_dir_all(&subdir).unwrap();
+        let info = worktree_for(&subdir).unwrap();
+        assert_eq!(Path::new(&info.path), worktree_path);
+        assert_eq!(info.branch, Some("feature".to_string()));
+        assert!(!info.is_main);
+
+        assert!(worktree_for(temp_dir.path()).unwrap().is_main);
+        let not_a_repo = TempDir::new().unwrap();
+        assert!(worktree_for(not_a_repo.path()).is_err());
+    }
+
     #[test]
     fn test_create_worktree_success() {
         let (temp_dir, repo) = create_test_repo();
@@ -269,6 +415,37 @@ mod tests {
         assert!(matches!(result, Err(GitError::InvalidPath(_))));
     }
 
+    #[test]
+    fn test_remove_worktree() {
+        let (temp_dir, repo) = create_test_repo();
+
+        let info = add_worktree(&repo, "feature/x", None, true).unwrap();
+        let worktree_path = PathBuf::from(&info.path);
+        assert!(worktree_path.ends_with("feature-x"));
+        assert!(worktree_path.exists());
+
+        // Untracked work is not thrown away without force
+        fs::write(worktree_path.join("notes.txt"), "wip").unwrap();
+        assert!(matches!(
+            remove_worktree(&repo, &worktree_path, false),
+            Err(GitError::WorktreeDirty(_))
+        ));
+        remove_worktree(&repo, &worktree_path, true).unwrap();
+        assert!(!worktree_path.exists());
+        assert_eq!(list_worktrees(&repo).unwrap().len(), 1);
+        assert!(repo.find_branch("feature/x", BranchType::Local).is_ok());
+
+        assert!(matches!(
+            remove_worktree(&repo, &worktree_path, false),
+            Err(GitError::WorktreeNotFound(_))
+        ));
+        assert!(matches!(
+            remove_worktree(&repo, temp_dir.path(), true),
+            Err(GitError::InvalidPath(_))
+        ));
+        fs::remove_dir_all(worktree_path.parent().unwrap()).unwrap();
+    }
+
     #[test]
     fn test_list_worktrees_after_create() {
         let (temp_dir, repo) = create_test_repo();
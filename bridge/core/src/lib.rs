@@ -0,0 +1,42 @@
+//! Halls of Creation bridge core
+//!
+//! The parts of the bridge that don't depend on a network front end: agent
+//! sessions and their manager, PTY handling, project configuration, git
+//! worktrees and the client/server protocol. The `hoc-bridge` server is built
+//! on this crate; embedders (a GDExtension host process, tests, client tools)
+//! can use it directly to run agents or speak the protocol.
+//!
+//! ```no_run
+//! use hoc_bridge_core::agent::{AgentEvent, AgentManager, SpawnConfig};
+//!
+//! # async fn run() -> Result<(), hoc_bridge_core::agent::ManagerError> {
+//! let manager = AgentManager::new();
+//! let mut events = manager.subscribe();
+//! let agent_id = manager
+//!     .spawn_agent(SpawnConfig::new("/path/to/project").with_size(120, 40))
+//!     .await?;
+//! manager.send_input(agent_id, "Run the tests\n").await?;
+//! while let Ok(event) = events.recv().await {
+//!     if let AgentEvent::Output { data, .. } = event {
+//!         print!("{}", String::from_utf8_lossy(&data));
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Modules:
+//! - [`agent`]: spawning agents and routing their input, output and events
+//! - [`pty`]: PTY processes, SSH targets and tmux/screen sessions
+//! - [`config`]: `.hoc/config.toml` project configuration, workspace layouts and
+//!   the `bridge.toml` server configuration
+//! - [`git`]: repository detection and worktree management
+//! - [`fs`]: sandboxed file browsing and watching for clients
+//! - [`protocol`]: JSON messages exchanged with clients
+
+pub mod agent;
+pub mod config;
+pub mod fs;
+pub mod git;
+pub mod protocol;
+pub mod pty;
@@ -0,0 +1,5584 @@
+//! Protocol message definitions
+//!
+//! Defines the message types exchanged between Godot clients and the bridge server.
+//! All messages are JSON-encoded and include version information for compatibility.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::fs::{ChangeKind, FileEntry, MAX_READ_BYTES, MAX_WRITE_BYTES};
+use crate::git::{BranchInfo, FileDiff, TransferProgress, WorktreeInfo};
+use crate::pty::{is_supported_signal, key_sequence, ExitReason, InputModes, Multiplexer};
+
+/// Current protocol version
+/// Increment when making breaking changes to message format
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Minimum supported protocol version
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Maximum terminal dimensions unless configured otherwise
+pub const MAX_TERMINAL_COLS: u16 = 500;
+pub const MAX_TERMINAL_ROWS: u16 = 200;
+
+/// Default terminal dimensions unless configured otherwise
+pub const DEFAULT_TERMINAL_COLS: u16 = 80;
+pub const DEFAULT_TERMINAL_ROWS: u16 = 24;
+
+/// Largest terminal dimensions that can be configured
+pub const TERMINAL_COLS_CEILING: u16 = 4000;
+pub const TERMINAL_ROWS_CEILING: u16 = 1000;
+
+/// Maximum input length (1MB)
+pub const MAX_INPUT_LENGTH: usize = 1024 * 1024;
+
+/// Maximum number of agents listed in one `broadcast_input`
+pub const MAX_BROADCAST_AGENTS: usize = 256;
+
+/// Maximum length of text copied to an agent's clipboard (1MB)
+pub const MAX_CLIPBOARD_LENGTH: usize = 1024 * 1024;
+
+/// Maximum path length
+pub const MAX_PATH_LENGTH: usize = 4096;
+
+/// Maximum preset name length
+pub const MAX_PRESET_NAME_LENGTH: usize = 256;
+
+/// Maximum pipeline name length
+pub const MAX_PIPELINE_NAME_LENGTH: usize = 256;
+
+/// Maximum schedule name length
+pub const MAX_SCHEDULE_NAME_LENGTH: usize = 256;
+
+/// Maximum macro name length
+pub const MAX_MACRO_NAME_LENGTH: usize = 256;
+
+/// Maximum key action name length
+pub const MAX_KEY_ACTION_LENGTH: usize = 64;
+
+/// Default and maximum number of input history entries per request
+pub const DEFAULT_HISTORY_LIMIT: u32 = 20;
+pub const MAX_HISTORY_LIMIT: u32 = 200;
+
+/// Maximum number of chunks in a chunked paste
+pub const MAX_PASTE_PARTS: u32 = 4096;
+
+/// Maximum length of an adopted session target or host name
+pub const MAX_SESSION_TARGET_LENGTH: usize = 256;
+
+/// Maximum length of a client-chosen request ID
+pub const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Maximum number of tags on an agent, and length of each tag or group ID
+pub const MAX_TAGS: usize = 32;
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// Maximum length of an agent's display name
+pub const MAX_AGENT_NAME_LENGTH: usize = 64;
+
+/// Maximum length of a session token
+pub const MAX_SESSION_TOKEN_LENGTH: usize = 256;
+
+/// Maximum length of a git branch name
+pub const MAX_BRANCH_NAME_LENGTH: usize = 256;
+
+/// Maximum number of paths staged or unstaged at once
+pub const MAX_GIT_PATHS: usize = 1024;
+
+/// Maximum length of a commit message (64KB)
+pub const MAX_COMMIT_MESSAGE_LENGTH: usize = 64 * 1024;
+
+/// Maximum length of a commit author's name or email
+pub const MAX_AUTHOR_LENGTH: usize = 256;
+
+/// Maximum number of environment variables set for an agent, and length of
+/// each name and value
+pub const MAX_ENV_VARS: usize = 64;
+pub const MAX_ENV_NAME_LENGTH: usize = 256;
+pub const MAX_ENV_VALUE_LENGTH: usize = 32 * 1024;
+
+/// Range of recording playback speeds
+pub const MIN_REPLAY_SPEED: f64 = 0.25;
+pub const MAX_REPLAY_SPEED: f64 = 16.0;
+
+// ============================================================================
+// Error Types
+// ============================================================================
+
+/// Protocol-related errors
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("JSON serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("Protocol version {0} not supported (min: {MIN_PROTOCOL_VERSION}, current: {PROTOCOL_VERSION})")]
+    UnsupportedVersion(u32),
+
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+
+    #[error("Validation error: {0}")]
+    ValidationError(String),
+
+    #[error("Validation error: {message}")]
+    InvalidField {
+        /// Name of the offending field
+        field: String,
+        message: String,
+        /// Largest accepted value or length, if the field is bounded
+        max: Option<u64>,
+    },
+}
+
+impl ProtocolError {
+    /// A field failed validation
+    pub fn invalid_field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ProtocolError::InvalidField {
+            field: field.into(),
+            message: message.into(),
+            max: None,
+        }
+    }
+
+    /// A field exceeded its limit of `max`
+    pub fn field_limit(field: impl Into<String>, message: impl Into<String>, max: u64) -> Self {
+        ProtocolError::InvalidField {
+            field: field.into(),
+            message: message.into(),
+            max: Some(max),
+        }
+    }
+
+    /// Machine-readable details reported alongside the error
+    pub fn details(&self) -> BTreeMap<String, serde_json::Value> {
+        let mut details = BTreeMap::new();
+        match self {
+            ProtocolError::UnsupportedVersion(version) => {
+                details.insert("version".to_string(), (*version).into());
+                details.insert("min_version".to_string(), MIN_PROTOCOL_VERSION.into());
+                details.insert("max_version".to_string(), PROTOCOL_VERSION.into());
+            }
+            ProtocolError::InvalidField { field, max, .. } => {
+                details.insert("field".to_string(), field.as_str().into());
+                if let Some(max) = max {
+                    details.insert("max".to_string(), (*max).into());
+                }
+            }
+            ProtocolError::SerializationError(_)
+            | ProtocolError::InvalidMessage(_)
+            | ProtocolError::ValidationError(_) => {}
+        }
+        details
+    }
+}
+
+/// Result type for protocol operations
+pub type ProtocolResult<T> = Result<T, ProtocolError>;
+
+// ============================================================================
+// Terminal Limits
+// ============================================================================
+
+/// Terminal sizes clients may request, and the size used when they don't
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalLimits {
+    /// Maximum columns
+    pub max_cols: u16,
+    /// Maximum rows
+    pub max_rows: u16,
+    /// Columns when a client doesn't ask for a size
+    pub default_cols: u16,
+    /// Rows when a client doesn't ask for a size
+    pub default_rows: u16,
+}
+
+impl Default for TerminalLimits {
+    fn default() -> Self {
+        Self {
+            max_cols: MAX_TERMINAL_COLS,
+            max_rows: MAX_TERMINAL_ROWS,
+            default_cols: DEFAULT_TERMINAL_COLS,
+            default_rows: DEFAULT_TERMINAL_ROWS,
+        }
+    }
+}
+
+impl TerminalLimits {
+    /// Set the largest size clients may request
+    pub fn with_max_size(mut self, cols: u16, rows: u16) -> Self {
+        self.max_cols = cols;
+        self.max_rows = rows;
+        self
+    }
+
+    /// Set the size used when clients don't ask for one
+    pub fn with_default_size(mut self, cols: u16, rows: u16) -> Self {
+        self.default_cols = cols;
+        self.default_rows = rows;
+        self
+    }
+
+    /// Check that the limits are usable: maximums within the ceilings and
+    /// defaults within the maximums
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_cols == 0 || self.max_cols > TERMINAL_COLS_CEILING {
+            return Err(format!(
+                "maximum columns must be between 1 and {}",
+                TERMINAL_COLS_CEILING
+            ));
+        }
+        if self.max_rows == 0 || self.max_rows > TERMINAL_ROWS_CEILING {
+            return Err(format!(
+                "maximum rows must be between 1 and {}",
+                TERMINAL_ROWS_CEILING
+            ));
+        }
+        if self.default_cols == 0 || self.default_cols > self.max_cols {
+            return Err(format!("default columns must be between 1 and {}", self.max_cols));
+        }
+        if self.default_rows == 0 || self.default_rows > self.max_rows {
+            return Err(format!("default rows must be between 1 and {}", self.max_rows));
+        }
+        Ok(())
+    }
+
+    /// Check requested dimensions, either of which may be absent
+    pub fn check_size(&self, cols: Option<u16>, rows: Option<u16>) -> ProtocolResult<()> {
+        if let Some(cols) = cols {
+            if cols == 0 || cols > self.max_cols {
+                return Err(ProtocolError::field_limit(
+                    "cols",
+                    format!("cols must be between 1 and {}", self.max_cols),
+                    self.max_cols as u64,
+                ));
+            }
+        }
+        if let Some(rows) = rows {
+            if rows == 0 || rows > self.max_rows {
+                return Err(ProtocolError::field_limit(
+                    "rows",
+                    format!("rows must be between 1 and {}", self.max_rows),
+                    self.max_rows as u64,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Message Envelope
+// ============================================================================
+
+/// Protocol envelope wrapping all client messages
+/// Includes version for compatibility checking
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientEnvelope {
+    /// Protocol version used by the client
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// Oldest protocol version the client can speak (`version` when absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<u32>,
+    /// Newest protocol version the client can speak (`version` when absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_version: Option<u32>,
+    /// Client-chosen ID echoed in error responses to this message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// The actual message payload
+    #[serde(flatten)]
+    pub message: ClientMessage,
+}
+
+/// Protocol envelope wrapping all server messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerEnvelope {
+    /// Protocol version used by the server
+    pub version: u32,
+    /// The actual message payload
+    #[serde(flatten)]
+    pub message: ServerMessage,
+}
+
+/// Messages without a version come from clients written before versioning
+fn default_version() -> u32 {
+    MIN_PROTOCOL_VERSION
+}
+
+impl ClientEnvelope {
+    /// Create a new client envelope with the current protocol version
+    pub fn new(message: ClientMessage) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_version: None,
+            max_version: None,
+            request_id: None,
+            message,
+        }
+    }
+
+    /// Offer a range of protocol versions for the server to choose from
+    pub fn with_version_range(mut self, min_version: u32, max_version: u32) -> Self {
+        self.min_version = Some(min_version);
+        self.max_version = Some(max_version);
+        self
+    }
+
+    /// Protocol versions the client can speak
+    pub fn version_range(&self) -> RangeInclusive<u32> {
+        self.min_version.unwrap_or(self.version)..=self.max_version.unwrap_or(self.version)
+    }
+
+    /// Newest protocol version both the client and this server speak
+    ///
+    /// Only meaningful for a validated envelope, whose range overlaps the
+    /// server's.
+    pub fn negotiated_version(&self) -> u32 {
+        (*self.version_range().end()).min(PROTOCOL_VERSION)
+    }
+
+    /// Tag the message with a request ID
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    /// Request ID of a message, even one that fails to parse
+    pub fn peek_request_id(json: &str) -> Option<String> {
+        let value: serde_json::Value = serde_json::from_str(json).ok()?;
+        let request_id = value.get("request_id")?.as_str()?;
+        (request_id.len() <= MAX_REQUEST_ID_LENGTH).then(|| request_id.to_string())
+    }
+
+    /// Parse and validate a client envelope from JSON
+    pub fn from_json(json: &str) -> ProtocolResult<Self> {
+        Self::from_json_with_limits(json, &TerminalLimits::default())
+    }
+
+    /// Parse a client envelope from JSON, validating it against the given
+    /// terminal limits
+    pub fn from_json_with_limits(json: &str, limits: &TerminalLimits) -> ProtocolResult<Self> {
+        let envelope: Self = serde_json::from_str(json)?;
+        envelope.validate_with_limits(limits)?;
+        Ok(envelope)
+    }
+
+    /// Validate the envelope and its contents
+    pub fn validate(&self) -> ProtocolResult<()> {
+        self.validate_with_limits(&TerminalLimits::default())
+    }
+
+    /// Validate the envelope and its contents against the given terminal limits
+    pub fn validate_with_limits(&self, limits: &TerminalLimits) -> ProtocolResult<()> {
+        // Check that the client speaks a version this server does
+        let range = self.version_range();
+        if range.is_empty() {
+            return Err(ProtocolError::invalid_field(
+                "min_version",
+                "min_version must not exceed max_version",
+            ));
+        }
+        if *range.end() < MIN_PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion(*range.end()));
+        }
+        if *range.start() > PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion(*range.start()));
+        }
+        if self
+            .request_id
+            .as_ref()
+            .is_some_and(|id| id.len() > MAX_REQUEST_ID_LENGTH)
+        {
+            return Err(ProtocolError::field_limit(
+                "request_id",
+                format!(
+                    "request_id exceeds maximum length of {} characters",
+                    MAX_REQUEST_ID_LENGTH
+                ),
+                MAX_REQUEST_ID_LENGTH as u64,
+            ));
+        }
+
+        // Validate the message contents
+        self.message.validate_with_limits(limits)
+    }
+
+    /// Serialize the envelope to JSON
+    pub fn to_json(&self) -> ProtocolResult<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+impl ServerEnvelope {
+    /// Create a new server envelope with the current protocol version
+    pub fn new(message: ServerMessage) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+
+    /// Serialize the envelope to JSON
+    pub fn to_json(&self) -> ProtocolResult<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Parse a server envelope from JSON (primarily for testing)
+    pub fn from_json(json: &str) -> ProtocolResult<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+// ============================================================================
+// Client Messages
+// ============================================================================
+
+/// Messages sent from client (Godot) to server
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Authentication message - must be sent first when token is required
+    Authenticate {
+        /// The authentication token
+        token: String,
+    },
+
+    /// Connection keepalive ping
+    Ping {
+        /// Sequence number for tracking round-trip time
+        seq: u64,
+    },
+
+    /// Request to spawn a new agent session
+    SpawnAgent {
+        /// Path to the project directory
+        project_path: String,
+        /// Optional preset name from project config
+        #[serde(skip_serializing_if = "Option::is_none")]
+        preset: Option<String>,
+        /// Optional initial terminal columns
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cols: Option<u16>,
+        /// Optional initial terminal rows
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rows: Option<u16>,
+        /// Labels to attach to the agent
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+        /// Group to place the agent in
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Display name, e.g. "frontend-fixer"
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        /// How claude is run (`interactive` when absent)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<AgentMode>,
+        /// Initial prompt, instead of the preset's; the prompt a
+        /// `stream_json` agent answers
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prompt: Option<String>,
+        /// Environment variables for the agent, added to the preset's
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        env: BTreeMap<String, String>,
+        /// Directory the agent runs in, relative to the project path (the
+        /// project directory itself when absent)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cwd: Option<String>,
+        /// Send `echo_state` whenever the agent's terminal starts or stops
+        /// echoing input, or switches between raw and cooked mode
+        #[serde(default, skip_serializing_if = "is_false")]
+        report_echo: bool,
+    },
+
+    /// Send input to an existing agent
+    AgentInput {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Input data to send to the agent's stdin
+        input: String,
+        /// Send the input as a paste, between bracketed-paste markers when
+        /// the agent's terminal application has asked for them
+        #[serde(default, skip_serializing_if = "is_false")]
+        paste: bool,
+    },
+
+    /// Send the same input to several agents, e.g. to run the tests in each
+    BroadcastInput {
+        /// UUIDs of the target agents
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        agent_ids: Vec<Uuid>,
+        /// Also send the input to every agent in this session group
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Input data to send to each agent's stdin
+        input: String,
+        /// Send the input as a paste, as in `agent_input`
+        #[serde(default, skip_serializing_if = "is_false")]
+        paste: bool,
+    },
+
+    /// Send raw bytes to an agent, for input that is not valid UTF-8
+    AgentInputRaw {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Base64-encoded bytes
+        data: String,
+    },
+
+    /// One part of a large paste, assembled server-side and written with pacing
+    AgentInputChunk {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Zero-based index of this chunk; chunk 0 starts a new paste
+        part: u32,
+        /// Total number of chunks
+        of: u32,
+        /// Chunk contents
+        data: String,
+    },
+
+    /// Send the input of a configured macro to an agent
+    RunMacro {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Macro name, as listed in the agent's `macros`
+        name: String,
+    },
+
+    /// Press a named key in an agent's terminal, e.g. "enter", "esc",
+    /// "ctrl+c", "up" or "tab"
+    AgentKey {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Key name
+        key: String,
+    },
+
+    /// Copy text to an agent's clipboard on the bridge
+    SetClipboard {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Text to copy, replacing what the clipboard held
+        text: String,
+    },
+
+    /// Fetch what was copied to an agent's clipboard
+    GetClipboard {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// Ask whether an agent's terminal echoes input and is in raw mode
+    GetEchoState {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// Switch an agent's terminal to raw mode, or back to cooked mode
+    SetRawMode {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Raw mode (no echo or line editing) when true
+        raw: bool,
+    },
+
+    /// Send the key sequence bound to an abstract action (e.g. "interrupt")
+    SendKey {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Action name from the agent's keybinding profile
+        action: String,
+    },
+
+    /// Request to terminate an agent
+    KillAgent {
+        /// UUID of the agent to terminate
+        agent_id: Uuid,
+        /// Signal to send instead, e.g. SIGINT to interrupt or SIGSTOP and
+        /// SIGCONT to suspend and continue (the agent is killed when absent)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signal: Option<i32>,
+    },
+
+    /// Replace an agent's process with a new one started from the same
+    /// configuration, keeping the agent's ID
+    RestartAgent {
+        /// UUID of the agent to restart
+        agent_id: Uuid,
+        /// Start the new process at the agent's current terminal size rather
+        /// than the size it was spawned with
+        #[serde(default, skip_serializing_if = "is_false")]
+        preserve_size: bool,
+    },
+
+    /// Suspend an agent's process (SIGSTOP) until it is resumed
+    PauseAgent {
+        /// UUID of the agent to pause
+        agent_id: Uuid,
+    },
+
+    /// Continue a paused agent (SIGCONT)
+    ResumeAgent {
+        /// UUID of the agent to resume
+        agent_id: Uuid,
+    },
+
+    /// Add or remove an agent's tags
+    TagAgent {
+        /// UUID of the agent to tag
+        agent_id: Uuid,
+        /// Tags to add
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        add: Vec<String>,
+        /// Tags to remove
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        remove: Vec<String>,
+    },
+
+    /// Change an agent's display name
+    RenameAgent {
+        /// UUID of the agent to rename
+        agent_id: Uuid,
+        /// New name (`None` removes it)
+        #[serde(default)]
+        name: Option<String>,
+    },
+
+    /// Resize an agent's terminal
+    ResizeTerminal {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// New terminal width in columns
+        cols: u16,
+        /// New terminal height in rows
+        rows: u16,
+    },
+
+    /// Adopt an existing tmux/screen session as an agent
+    AdoptSession {
+        /// Multiplexer hosting the session
+        multiplexer: Multiplexer,
+        /// Session target (tmux target such as "work:1.0", or screen session name)
+        target: String,
+        /// Host the session runs on, reached over SSH (local when omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host: Option<String>,
+        /// Path reported for the agent (home directory when omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        project_path: Option<String>,
+        /// Initial terminal columns
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cols: Option<u16>,
+        /// Initial terminal rows
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rows: Option<u16>,
+    },
+
+    /// Attach to an existing session or process as an agent
+    AttachExternal {
+        /// `tmux:<target>`, `screen:<target>`, or a process running inside
+        /// tmux or screen as `pid:<pid>` or a bare PID
+        target: String,
+    },
+
+    /// List active agents, optionally only those matching a filter
+    ListAgents {
+        /// Conditions agents must meet to be listed
+        #[serde(flatten)]
+        filter: AgentFilter,
+    },
+
+    /// List connected clients with their bandwidth use
+    ListClients,
+
+    /// Re-read the server's configuration file, applying its token, agent
+    /// limits and allowed roots
+    ReloadConfig,
+
+    /// Stop the server, as SIGTERM would
+    Shutdown,
+
+    /// Request agent status
+    GetAgentStatus {
+        /// UUID of the agent to query
+        agent_id: Uuid,
+    },
+
+    /// Request the most recent inputs sent to an agent
+    GetInputHistory {
+        /// UUID of the agent to query
+        agent_id: Uuid,
+        /// Maximum number of entries (default 20)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<u32>,
+    },
+
+    /// Ask the client controlling an agent's input to hand over control
+    RequestControl {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// Hand control of an agent's input to a client that requested it
+    GrantControl {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Requesting client, as reported in `control_requested`
+        client: String,
+    },
+
+    /// Give up control of an agent's input
+    ReleaseControl {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// Answer an agent's pending confirmation prompt
+    ConfirmationReply {
+        /// UUID of the agent asking
+        agent_id: Uuid,
+        /// Index of the chosen option in the request's `options`
+        option: usize,
+    },
+
+    /// Let the tool an agent is asking permission for run
+    ApproveAction {
+        /// UUID of the agent asking
+        agent_id: Uuid,
+        /// Also stop asking for similar uses of the tool, when the prompt
+        /// offers it (`can_always`)
+        #[serde(default)]
+        always: bool,
+    },
+
+    /// Refuse the tool an agent is asking permission for
+    DenyAction {
+        /// UUID of the agent asking
+        agent_id: Uuid,
+    },
+
+    /// Choose how agent events are delivered on this connection
+    SetStreamMode {
+        /// Raw terminal output and events, or plain-language summaries
+        mode: StreamMode,
+    },
+
+    /// Receive an agent's output on this connection
+    AttachAgent {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// Stop receiving an agent's output on this connection
+    DetachAgent {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// Create a session group and join it
+    ///
+    /// Creating a group that exists joins it.
+    CreateGroup {
+        /// Name of the group, as given to `spawn_agent`
+        group: String,
+    },
+
+    /// Join a session group, receiving the output of all its agents,
+    /// including those spawned into it later
+    JoinGroup {
+        /// Name of the group
+        group: String,
+    },
+
+    /// Leave a session group, no longer receiving its agents' output
+    LeaveGroup {
+        /// Name of the group
+        group: String,
+    },
+
+    /// List session groups with their agents and members
+    ListGroups,
+
+    /// Request the current screen of an agent's terminal
+    GetScreenState {
+        /// UUID of the agent to query
+        agent_id: Uuid,
+    },
+
+    /// List the git worktrees of a project's repository
+    ListWorktrees {
+        /// Path inside the repository
+        project_path: String,
+    },
+
+    /// Check a branch out into a new linked worktree
+    CreateWorktree {
+        /// Path inside the repository
+        project_path: String,
+        /// Branch to check out
+        branch: String,
+        /// Where to create the worktree (next to the repository when omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        /// Create the branch from HEAD if it doesn't exist
+        #[serde(default, skip_serializing_if = "is_false")]
+        create_branch: bool,
+    },
+
+    /// Remove a linked worktree and its working directory
+    RemoveWorktree {
+        /// Path inside the repository
+        project_path: String,
+        /// Path of the worktree to remove
+        path: String,
+        /// Remove it even with uncommitted changes or a lock
+        #[serde(default, skip_serializing_if = "is_false")]
+        force: bool,
+    },
+
+    /// List the branches of a project's repository
+    ListBranches {
+        /// Path inside the repository
+        project_path: String,
+        /// Include remote-tracking branches
+        #[serde(default, skip_serializing_if = "is_false")]
+        remote: bool,
+    },
+
+    /// Create a branch
+    CreateBranch {
+        /// Path inside the repository
+        project_path: String,
+        /// Name of the new branch
+        branch: String,
+        /// Revision the branch starts at (HEAD when omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        start_point: Option<String>,
+    },
+
+    /// Check a branch out in the worktree containing `project_path`
+    CheckoutBranch {
+        /// Path inside the worktree
+        project_path: String,
+        /// Branch to check out
+        branch: String,
+    },
+
+    /// Delete a branch
+    DeleteBranch {
+        /// Path inside the repository
+        project_path: String,
+        /// Branch to delete
+        branch: String,
+        /// Delete it even if it isn't merged into HEAD
+        #[serde(default, skip_serializing_if = "is_false")]
+        force: bool,
+    },
+
+    /// Push a branch to a remote, reporting `transfer_progress` as it goes
+    PushBranch {
+        /// Path inside the repository
+        project_path: String,
+        /// Remote to push to (default `origin`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        remote: Option<String>,
+        /// Branch to push (default the one checked out at `project_path`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        branch: Option<String>,
+        /// Overwrite the remote branch even if that loses commits
+        #[serde(default, skip_serializing_if = "is_false")]
+        force: bool,
+        /// Secret holding a token for HTTPS remotes (otherwise the
+        /// ssh-agent or git credential helper is used)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token_secret: Option<String>,
+    },
+
+    /// Fetch a branch from a remote and fast-forward the local branch to it,
+    /// reporting `transfer_progress` as it goes
+    PullBranch {
+        /// Path inside the repository
+        project_path: String,
+        /// Remote to pull from (default `origin`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        remote: Option<String>,
+        /// Branch to pull (default the one checked out at `project_path`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        branch: Option<String>,
+        /// Secret holding a token for HTTPS remotes
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token_secret: Option<String>,
+    },
+
+    /// Changes in the repository an agent works in
+    GetDiff {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Limit the diff to a file or directory, relative to the repository root
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        /// Show staged changes instead of unstaged ones
+        #[serde(default, skip_serializing_if = "is_false")]
+        staged: bool,
+    },
+
+    /// Stage files in the repository an agent works in
+    StageFiles {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Pathspecs relative to the repository root ("." for everything)
+        paths: Vec<String>,
+    },
+
+    /// Unstage files in the repository an agent works in
+    UnstageFiles {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Pathspecs relative to the repository root ("." for everything)
+        paths: Vec<String>,
+    },
+
+    /// Commit what is staged in the repository an agent works in
+    CommitChanges {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Commit message
+        message: String,
+        /// Author name (the repository's configured user when omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author_name: Option<String>,
+        /// Author email, required with `author_name`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author_email: Option<String>,
+    },
+
+    /// List a directory below the server's allowed roots
+    ListDirectory {
+        /// Absolute path of the directory
+        path: String,
+        /// Include dotfiles
+        #[serde(default, skip_serializing_if = "is_false")]
+        hidden: bool,
+    },
+
+    /// Read part of a file below the server's allowed roots
+    ReadFile {
+        /// Absolute path of the file
+        path: String,
+        /// Byte to start at (default 0)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+        /// Most bytes to return (default 256 KiB, at most 4 MiB)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<u64>,
+    },
+
+    /// Stat a file or directory below the server's allowed roots
+    StatPath {
+        /// Absolute path
+        path: String,
+    },
+
+    /// Receive `file_changed` for changes below an agent's project directory
+    WatchPath {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Directory to watch, relative to the project directory (the whole
+        /// project when omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+
+    /// Stop watching a path
+    UnwatchPath {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Path as given to `watch_path` (every watch of the agent when omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+
+    /// Replace a file in an agent's project, creating it if needed
+    WriteFile {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Path relative to the project directory
+        path: String,
+        /// New contents, encoded as given by `encoding`
+        content: String,
+        /// Encoding of `content`; UTF-8 text when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<OutputEncoding>,
+        /// Stage the file in the project's repository afterwards
+        #[serde(default, skip_serializing_if = "is_false")]
+        stage: bool,
+    },
+
+    /// Apply a unified diff to the repository an agent works in
+    ApplyPatch {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Patch with paths relative to the repository root, as from `git diff`
+        patch: String,
+        /// Apply it to the index as well (like `git apply --index`)
+        #[serde(default, skip_serializing_if = "is_false")]
+        stage: bool,
+    },
+
+    /// Start recording an agent's output
+    StartRecording {
+        /// UUID of the agent to record
+        agent_id: Uuid,
+    },
+
+    /// Stop recording an agent's output
+    StopRecording {
+        /// UUID of the agent being recorded
+        agent_id: Uuid,
+    },
+
+    /// List the recordings of a project
+    ListRecordings {
+        /// Project the agents were recorded in
+        project_path: String,
+    },
+
+    /// Play a recording back on this connection
+    ReplayRecording {
+        /// Project the agent was recorded in
+        project_path: String,
+        /// Recording to play, as reported in `recording_started`
+        recording_id: String,
+        /// Playback speed (default 1.0)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        speed: Option<f64>,
+    },
+
+    /// Own agents by a token that outlives the connection, attaching the
+    /// agents already owned by it
+    ClaimSession {
+        /// Token chosen by the client, e.g. one stored from an earlier run
+        session_token: String,
+    },
+
+    /// Pick up where a dropped connection left off: its attachments, the
+    /// input control it held and the output it missed
+    ResumeSession {
+        /// Token the dropped connection was given in `resume_token`
+        resume_token: String,
+    },
+
+    /// Queue a prompt to be run by an agent of its own once the project is free
+    EnqueueTask {
+        /// Project to run the task in
+        project_path: String,
+        /// What the agent is asked to do
+        prompt: String,
+        /// Preset the agent is spawned with (the project's default when absent)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preset: Option<String>,
+    },
+
+    /// List queued and running tasks
+    ListTasks,
+
+    /// Remove a task from the queue before it starts
+    CancelTask {
+        /// Task to cancel, as reported in `task_queued`
+        task_id: Uuid,
+    },
+
+    /// Run a pipeline from the project config, reported with
+    /// `pipeline_step` as each step starts and `pipeline_completed` at the end
+    RunPipeline {
+        /// Project whose config defines the pipeline, and that its agents run in
+        project_path: String,
+        /// Name of the pipeline
+        pipeline: String,
+    },
+
+    /// List the server's schedules
+    ListSchedules,
+
+    /// Switch a schedule on or off until the server restarts
+    EnableSchedule {
+        /// Name of the schedule
+        schedule: String,
+        enabled: bool,
+    },
+
+    /// List the presets agents can be spawned with in a project: the
+    /// project's own and the server's global presets
+    ListPresets {
+        /// Project whose presets to list
+        project_path: String,
+    },
+
+    /// Check whether an agent could be spawned, without spawning it
+    ValidateSpawn {
+        /// Path to the project directory
+        project_path: String,
+        /// Preset name, the project's default when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preset: Option<String>,
+    },
+}
+
+impl ClientMessage {
+    /// Validate message contents
+    pub fn validate(&self) -> ProtocolResult<()> {
+        self.validate_with_limits(&TerminalLimits::default())
+    }
+
+    /// Validate message contents against the given terminal limits
+    pub fn validate_with_limits(&self, limits: &TerminalLimits) -> ProtocolResult<()> {
+        match self {
+            ClientMessage::Authenticate { token } => {
+                if token.is_empty() {
+                    return Err(ProtocolError::invalid_field("token", "token cannot be empty"));
+                }
+                Ok(())
+            }
+
+            ClientMessage::Ping { .. } => Ok(()),
+
+            ClientMessage::SpawnAgent {
+                project_path,
+                preset,
+                cols,
+                rows,
+                tags,
+                group,
+                name,
+                prompt,
+                env,
+                cwd,
+                ..
+            } => {
+                // Validate project path
+                if project_path.is_empty() {
+                    return Err(ProtocolError::invalid_field(
+                        "project_path",
+                        "project_path cannot be empty",
+                    ));
+                }
+                if project_path.len() > MAX_PATH_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "project_path",
+                        format!(
+                            "project_path exceeds maximum length of {} characters",
+                            MAX_PATH_LENGTH
+                        ),
+                        MAX_PATH_LENGTH as u64,
+                    ));
+                }
+
+                // Validate preset name
+                if let Some(p) = preset {
+                    if p.is_empty() {
+                        return Err(ProtocolError::invalid_field(
+                            "preset",
+                            "preset name cannot be empty when specified",
+                        ));
+                    }
+                    if p.len() > MAX_PRESET_NAME_LENGTH {
+                        return Err(ProtocolError::field_limit(
+                            "preset",
+                            format!(
+                                "preset name exceeds maximum length of {} characters",
+                                MAX_PRESET_NAME_LENGTH
+                            ),
+                            MAX_PRESET_NAME_LENGTH as u64,
+                        ));
+                    }
+                }
+
+                // Validate labels
+                if tags.len() > MAX_TAGS {
+                    return Err(ProtocolError::field_limit(
+                        "tags",
+                        format!("an agent can have at most {} tags", MAX_TAGS),
+                        MAX_TAGS as u64,
+                    ));
+                }
+                let labels = tags
+                    .iter()
+                    .map(|t| ("tags", t))
+                    .chain(group.iter().map(|g| ("group", g)));
+                for (field, label) in labels {
+                    check_tag(field, label)?;
+                }
+
+                if let Some(name) = name {
+                    check_agent_name(name)?;
+                }
+
+                if prompt.as_ref().is_some_and(|p| p.len() > MAX_INPUT_LENGTH) {
+                    return Err(ProtocolError::field_limit(
+                        "prompt",
+                        format!("prompt exceeds maximum length of {} bytes", MAX_INPUT_LENGTH),
+                        MAX_INPUT_LENGTH as u64,
+                    ));
+                }
+
+                check_env(env)?;
+                if let Some(cwd) = cwd {
+                    check_cwd(cwd)?;
+                }
+
+                // Validate terminal dimensions
+                limits.check_size(*cols, *rows)
+            }
+
+            ClientMessage::AgentInput { input, .. } => {
+                if input.len() > MAX_INPUT_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "input",
+                        format!("input exceeds maximum length of {} bytes", MAX_INPUT_LENGTH),
+                        MAX_INPUT_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::BroadcastInput {
+                agent_ids,
+                group,
+                input,
+                ..
+            } => {
+                if agent_ids.is_empty() && group.is_none() {
+                    return Err(ProtocolError::invalid_field(
+                        "agent_ids",
+                        "agent_ids or group is required",
+                    ));
+                }
+                if agent_ids.len() > MAX_BROADCAST_AGENTS {
+                    return Err(ProtocolError::field_limit(
+                        "agent_ids",
+                        format!("input can be sent to at most {} agents", MAX_BROADCAST_AGENTS),
+                        MAX_BROADCAST_AGENTS as u64,
+                    ));
+                }
+                if let Some(group) = group {
+                    check_tag("group", group)?;
+                }
+                if input.len() > MAX_INPUT_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "input",
+                        format!("input exceeds maximum length of {} bytes", MAX_INPUT_LENGTH),
+                        MAX_INPUT_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::SetClipboard { text, .. } => {
+                if text.len() > MAX_CLIPBOARD_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "text",
+                        format!(
+                            "clipboard text exceeds maximum length of {} bytes",
+                            MAX_CLIPBOARD_LENGTH
+                        ),
+                        MAX_CLIPBOARD_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::AgentInputRaw { data, .. } => {
+                if decode_raw_input(data)?.len() > MAX_INPUT_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "data",
+                        format!("input exceeds maximum length of {} bytes", MAX_INPUT_LENGTH),
+                        MAX_INPUT_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::AgentInputChunk { part, of, data, .. } => {
+                if *of == 0 || *of > MAX_PASTE_PARTS {
+                    return Err(ProtocolError::field_limit(
+                        "of",
+                        format!("of must be between 1 and {}", MAX_PASTE_PARTS),
+                        MAX_PASTE_PARTS as u64,
+                    ));
+                }
+                if part >= of {
+                    return Err(ProtocolError::invalid_field(
+                        "part",
+                        "part must be less than of",
+                    ));
+                }
+                if data.len() > MAX_INPUT_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "data",
+                        format!("data exceeds maximum length of {} bytes", MAX_INPUT_LENGTH),
+                        MAX_INPUT_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::RunMacro { name, .. } => {
+                if name.is_empty() || name.len() > MAX_MACRO_NAME_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "name",
+                        format!(
+                            "macro name must be between 1 and {} characters",
+                            MAX_MACRO_NAME_LENGTH
+                        ),
+                        MAX_MACRO_NAME_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::AgentKey { key, .. } => {
+                if key.len() > MAX_KEY_ACTION_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "key",
+                        format!("key exceeds maximum length of {} bytes", MAX_KEY_ACTION_LENGTH),
+                        MAX_KEY_ACTION_LENGTH as u64,
+                    ));
+                }
+                if key_sequence(key, InputModes::default()).is_none() {
+                    return Err(ProtocolError::invalid_field(
+                        "key",
+                        format!("unknown key: {}", key),
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::SendKey { action, .. } => {
+                if action.is_empty() || action.len() > MAX_KEY_ACTION_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "action",
+                        format!(
+                            "action must be between 1 and {} characters",
+                            MAX_KEY_ACTION_LENGTH
+                        ),
+                        MAX_KEY_ACTION_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::KillAgent { signal, .. } => {
+                // Validate the signal can be delivered on this platform
+                match signal {
+                    Some(sig) if !is_supported_signal(*sig) && cfg!(windows) => {
+                        Err(ProtocolError::field_limit(
+                            "signal",
+                            format!("signal {} is not supported on Windows (1, 2, 9 or 15)", sig),
+                            15,
+                        ))
+                    }
+                    Some(sig) if !is_supported_signal(*sig) => Err(ProtocolError::field_limit(
+                        "signal",
+                        format!("signal {} is not a valid Unix signal (1-31)", sig),
+                        31,
+                    )),
+                    _ => Ok(()),
+                }
+            }
+
+            ClientMessage::ResizeTerminal { cols, rows, .. } => {
+                limits.check_size(Some(*cols), Some(*rows))
+            }
+
+            ClientMessage::AdoptSession {
+                target,
+                host,
+                project_path,
+                cols,
+                rows,
+                ..
+            } => {
+                // Leading dashes would be parsed as options by tmux/screen/ssh
+                for (name, value) in [("target", Some(target)), ("host", host.as_ref())] {
+                    let Some(value) = value else { continue };
+                    if value.is_empty() || value.starts_with('-') {
+                        return Err(ProtocolError::invalid_field(
+                            name,
+                            format!("{} must be non-empty and must not start with '-'", name),
+                        ));
+                    }
+                    if value.len() > MAX_SESSION_TARGET_LENGTH {
+                        return Err(ProtocolError::field_limit(
+                            name,
+                            format!(
+                                "{} exceeds maximum length of {} characters",
+                                name, MAX_SESSION_TARGET_LENGTH
+                            ),
+                            MAX_SESSION_TARGET_LENGTH as u64,
+                        ));
+                    }
+                }
+
+                if let Some(p) = project_path {
+                    if p.len() > MAX_PATH_LENGTH {
+                        return Err(ProtocolError::field_limit(
+                            "project_path",
+                            format!(
+                                "project_path exceeds maximum length of {} characters",
+                                MAX_PATH_LENGTH
+                            ),
+                            MAX_PATH_LENGTH as u64,
+                        ));
+                    }
+                }
+
+                limits.check_size(*cols, *rows)
+            }
+
+            ClientMessage::AttachExternal { target } => {
+                // What follows the kind is passed to tmux/screen as an argument
+                let (kind, value) = target.split_once(':').unwrap_or(("pid", target));
+                if !matches!(kind, "tmux" | "screen" | "pid") {
+                    return Err(ProtocolError::invalid_field(
+                        "target",
+                        "target must start with tmux:, screen: or pid:, or be a PID",
+                    ));
+                }
+                if value.is_empty() || value.starts_with('-') {
+                    return Err(ProtocolError::invalid_field(
+                        "target",
+                        "target must be non-empty and must not start with '-'",
+                    ));
+                }
+                if kind == "pid" && value.parse::<u32>().is_err() {
+                    return Err(ProtocolError::invalid_field(
+                        "target",
+                        format!("{} is not a process ID", value),
+                    ));
+                }
+                if target.len() > MAX_SESSION_TARGET_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "target",
+                        format!(
+                            "target exceeds maximum length of {} characters",
+                            MAX_SESSION_TARGET_LENGTH
+                        ),
+                        MAX_SESSION_TARGET_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::ListAgents { filter } => match filter.tag {
+                Some(ref tag) => check_tag("tag", tag),
+                None => Ok(()),
+            },
+
+            ClientMessage::ListClients
+            | ClientMessage::ListGroups
+            | ClientMessage::ReloadConfig
+            | ClientMessage::Shutdown => Ok(()),
+
+            ClientMessage::CreateGroup { group }
+            | ClientMessage::JoinGroup { group }
+            | ClientMessage::LeaveGroup { group } => check_tag("group", group),
+
+            ClientMessage::TagAgent { add, remove, .. } => {
+                if add.len() > MAX_TAGS {
+                    return Err(ProtocolError::field_limit(
+                        "add",
+                        format!("an agent can have at most {} tags", MAX_TAGS),
+                        MAX_TAGS as u64,
+                    ));
+                }
+                add.iter()
+                    .map(|t| ("add", t))
+                    .chain(remove.iter().map(|t| ("remove", t)))
+                    .try_for_each(|(field, tag)| check_tag(field, tag))
+            }
+
+            ClientMessage::GetAgentStatus { .. }
+            | ClientMessage::GetScreenState { .. }
+            | ClientMessage::GetClipboard { .. }
+            | ClientMessage::GetEchoState { .. }
+            | ClientMessage::SetRawMode { .. } => Ok(()),
+
+            ClientMessage::ConfirmationReply { .. }
+            | ClientMessage::ApproveAction { .. }
+            | ClientMessage::DenyAction { .. } => Ok(()),
+
+            ClientMessage::RestartAgent { .. }
+            | ClientMessage::PauseAgent { .. }
+            | ClientMessage::ResumeAgent { .. } => Ok(()),
+
+            ClientMessage::RenameAgent { name, .. } => {
+                name.as_deref().map_or(Ok(()), check_agent_name)
+            }
+
+            ClientMessage::RequestControl { .. }
+            | ClientMessage::GrantControl { .. }
+            | ClientMessage::ReleaseControl { .. } => Ok(()),
+
+            ClientMessage::SetStreamMode { .. } => Ok(()),
+
+            ClientMessage::AttachAgent { .. } | ClientMessage::DetachAgent { .. } => Ok(()),
+
+            ClientMessage::ListWorktrees { project_path } => check_project_path(project_path),
+
+            ClientMessage::CreateWorktree {
+                project_path,
+                branch,
+                path,
+                ..
+            } => {
+                check_project_path(project_path)?;
+                check_branch_name("branch", branch)?;
+                match path {
+                    Some(path) => check_path_field("path", path),
+                    None => Ok(()),
+                }
+            }
+
+            ClientMessage::RemoveWorktree {
+                project_path, path, ..
+            } => {
+                check_project_path(project_path)?;
+                check_path_field("path", path)
+            }
+
+            ClientMessage::ListBranches { project_path, .. } => check_project_path(project_path),
+
+            ClientMessage::CreateBranch {
+                project_path,
+                branch,
+                start_point,
+            } => {
+                check_project_path(project_path)?;
+                check_branch_name("branch", branch)?;
+                match start_point {
+                    Some(start_point) => check_branch_name("start_point", start_point),
+                    None => Ok(()),
+                }
+            }
+
+            ClientMessage::CheckoutBranch {
+                project_path,
+                branch,
+            }
+            | ClientMessage::DeleteBranch {
+                project_path,
+                branch,
+                ..
+            } => {
+                check_project_path(project_path)?;
+                check_branch_name("branch", branch)
+            }
+
+            ClientMessage::PushBranch {
+                project_path,
+                remote,
+                branch,
+                token_secret,
+                ..
+            }
+            | ClientMessage::PullBranch {
+                project_path,
+                remote,
+                branch,
+                token_secret,
+            } => {
+                check_project_path(project_path)?;
+                let fields = [
+                    ("remote", remote),
+                    ("branch", branch),
+                    ("token_secret", token_secret),
+                ];
+                for (field, value) in fields {
+                    if let Some(value) = value {
+                        check_branch_name(field, value)?;
+                    }
+                }
+                Ok(())
+            }
+
+            ClientMessage::GetDiff { path, .. } => match path {
+                Some(path) => check_path_field("path", path),
+                None => Ok(()),
+            },
+
+            ClientMessage::StageFiles { paths, .. } | ClientMessage::UnstageFiles { paths, .. } => {
+                if paths.is_empty() {
+                    return Err(ProtocolError::invalid_field(
+                        "paths",
+                        "paths cannot be empty",
+                    ));
+                }
+                if paths.len() > MAX_GIT_PATHS {
+                    return Err(ProtocolError::field_limit(
+                        "paths",
+                        format!("at most {} paths can be given", MAX_GIT_PATHS),
+                        MAX_GIT_PATHS as u64,
+                    ));
+                }
+                paths.iter().try_for_each(|path| check_path_field("paths", path))
+            }
+
+            ClientMessage::CommitChanges {
+                message,
+                author_name,
+                author_email,
+                ..
+            } => {
+                if message.trim().is_empty() {
+                    return Err(ProtocolError::invalid_field(
+                        "message",
+                        "message cannot be empty",
+                    ));
+                }
+                if message.len() > MAX_COMMIT_MESSAGE_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "message",
+                        format!(
+                            "message exceeds maximum length of {} bytes",
+                            MAX_COMMIT_MESSAGE_LENGTH
+                        ),
+                        MAX_COMMIT_MESSAGE_LENGTH as u64,
+                    ));
+                }
+                match (author_name, author_email) {
+                    (None, None) => Ok(()),
+                    (Some(name), Some(email)) => {
+                        for (field, value) in [("author_name", name), ("author_email", email)] {
+                            if value.is_empty() || value.len() > MAX_AUTHOR_LENGTH {
+                                return Err(ProtocolError::field_limit(
+                                    field,
+                                    format!(
+                                        "{} must be between 1 and {} characters",
+                                        field, MAX_AUTHOR_LENGTH
+                                    ),
+                                    MAX_AUTHOR_LENGTH as u64,
+                                ));
+                            }
+                        }
+                        Ok(())
+                    }
+                    _ => Err(ProtocolError::invalid_field(
+                        "author_name",
+                        "author_name and author_email must be given together",
+                    )),
+                }
+            }
+
+            ClientMessage::ListDirectory { path, .. } | ClientMessage::StatPath { path } => {
+                check_path_field("path", path)
+            }
+
+            ClientMessage::ReadFile { path, limit, .. } => {
+                check_path_field("path", path)?;
+                match limit {
+                    Some(limit) if *limit == 0 || *limit > MAX_READ_BYTES => {
+                        Err(ProtocolError::field_limit(
+                            "limit",
+                            format!("limit must be between 1 and {} bytes", MAX_READ_BYTES),
+                            MAX_READ_BYTES,
+                        ))
+                    }
+                    _ => Ok(()),
+                }
+            }
+
+            ClientMessage::WatchPath { path, .. } | ClientMessage::UnwatchPath { path, .. } => {
+                match path {
+                    Some(path) => check_path_field("path", path),
+                    None => Ok(()),
+                }
+            }
+
+            ClientMessage::WriteFile {
+                path,
+                content,
+                encoding,
+                ..
+            } => {
+                check_path_field("path", path)?;
+                if decode_file_content(content, *encoding)?.len() as u64 > MAX_WRITE_BYTES {
+                    return Err(ProtocolError::field_limit(
+                        "content",
+                        format!("content exceeds maximum size of {} bytes", MAX_WRITE_BYTES),
+                        MAX_WRITE_BYTES,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::ApplyPatch { patch, .. } => {
+                if patch.is_empty() {
+                    return Err(ProtocolError::invalid_field("patch", "patch cannot be empty"));
+                }
+                if patch.len() as u64 > MAX_WRITE_BYTES {
+                    return Err(ProtocolError::field_limit(
+                        "patch",
+                        format!("patch exceeds maximum size of {} bytes", MAX_WRITE_BYTES),
+                        MAX_WRITE_BYTES,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::StartRecording { .. } | ClientMessage::StopRecording { .. } => Ok(()),
+
+            ClientMessage::ListRecordings { project_path } => check_project_path(project_path),
+
+            ClientMessage::EnqueueTask {
+                project_path,
+                prompt,
+                preset,
+            } => {
+                check_project_path(project_path)?;
+                if prompt.trim().is_empty() {
+                    return Err(ProtocolError::invalid_field("prompt", "prompt cannot be empty"));
+                }
+                if prompt.len() > MAX_INPUT_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "prompt",
+                        format!("prompt exceeds maximum length of {} bytes", MAX_INPUT_LENGTH),
+                        MAX_INPUT_LENGTH as u64,
+                    ));
+                }
+                if preset.as_ref().is_some_and(|p| p.is_empty() || p.len() > MAX_PRESET_NAME_LENGTH)
+                {
+                    return Err(ProtocolError::invalid_field(
+                        "preset",
+                        format!(
+                            "preset name must be 1 to {} characters",
+                            MAX_PRESET_NAME_LENGTH
+                        ),
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::ListTasks | ClientMessage::CancelTask { .. } => Ok(()),
+
+            ClientMessage::ListSchedules => Ok(()),
+
+            ClientMessage::EnableSchedule { schedule, .. } => {
+                if schedule.is_empty() || schedule.len() > MAX_SCHEDULE_NAME_LENGTH {
+                    return Err(ProtocolError::invalid_field(
+                        "schedule",
+                        format!(
+                            "schedule name must be 1 to {} characters",
+                            MAX_SCHEDULE_NAME_LENGTH
+                        ),
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::RunPipeline {
+                project_path,
+                pipeline,
+            } => {
+                check_project_path(project_path)?;
+                if pipeline.is_empty() || pipeline.len() > MAX_PIPELINE_NAME_LENGTH {
+                    return Err(ProtocolError::invalid_field(
+                        "pipeline",
+                        format!(
+                            "pipeline name must be 1 to {} characters",
+                            MAX_PIPELINE_NAME_LENGTH
+                        ),
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::ListPresets { project_path } => check_project_path(project_path),
+
+            ClientMessage::ValidateSpawn {
+                project_path,
+                preset,
+            } => {
+                check_project_path(project_path)?;
+                if preset.as_ref().is_some_and(|p| p.is_empty() || p.len() > MAX_PRESET_NAME_LENGTH)
+                {
+                    return Err(ProtocolError::invalid_field(
+                        "preset",
+                        format!(
+                            "preset name must be 1 to {} characters",
+                            MAX_PRESET_NAME_LENGTH
+                        ),
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::ReplayRecording {
+                project_path,
+                recording_id,
+                speed,
+            } => {
+                check_project_path(project_path)?;
+                if recording_id.is_empty() {
+                    return Err(ProtocolError::invalid_field(
+                        "recording_id",
+                        "recording_id cannot be empty",
+                    ));
+                }
+                if let Some(speed) = speed {
+                    if !(MIN_REPLAY_SPEED..=MAX_REPLAY_SPEED).contains(speed) {
+                        return Err(ProtocolError::invalid_field(
+                            "speed",
+                            format!(
+                                "speed must be between {} and {}",
+                                MIN_REPLAY_SPEED, MAX_REPLAY_SPEED
+                            ),
+                        ));
+                    }
+                }
+                Ok(())
+            }
+
+            ClientMessage::ClaimSession { session_token } => {
+                if session_token.is_empty() {
+                    return Err(ProtocolError::invalid_field(
+                        "session_token",
+                        "session_token cannot be empty",
+                    ));
+                }
+                if session_token.len() > MAX_SESSION_TOKEN_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "session_token",
+                        format!(
+                            "session_token exceeds maximum length of {}",
+                            MAX_SESSION_TOKEN_LENGTH
+                        ),
+                        MAX_SESSION_TOKEN_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::ResumeSession { resume_token } => {
+                if resume_token.is_empty() {
+                    return Err(ProtocolError::invalid_field(
+                        "resume_token",
+                        "resume_token cannot be empty",
+                    ));
+                }
+                if resume_token.len() > MAX_SESSION_TOKEN_LENGTH {
+                    return Err(ProtocolError::field_limit(
+                        "resume_token",
+                        format!(
+                            "resume_token exceeds maximum length of {}",
+                            MAX_SESSION_TOKEN_LENGTH
+                        ),
+                        MAX_SESSION_TOKEN_LENGTH as u64,
+                    ));
+                }
+                Ok(())
+            }
+
+            ClientMessage::GetInputHistory { limit, .. } => {
+                if let Some(l) = limit {
+                    if *l == 0 || *l > MAX_HISTORY_LIMIT {
+                        return Err(ProtocolError::field_limit(
+                            "limit",
+                            format!("limit must be between 1 and {}", MAX_HISTORY_LIMIT),
+                            MAX_HISTORY_LIMIT as u64,
+                        ));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The agent this message targets, if any
+    pub fn agent_id(&self) -> Option<Uuid> {
+        match self {
+            ClientMessage::AgentInput { agent_id, .. }
+            | ClientMessage::AgentInputRaw { agent_id, .. }
+            | ClientMessage::AgentInputChunk { agent_id, .. }
+            | ClientMessage::RunMacro { agent_id, .. }
+            | ClientMessage::AgentKey { agent_id, .. }
+            | ClientMessage::GetEchoState { agent_id }
+            | ClientMessage::SetRawMode { agent_id, .. }
+            | ClientMessage::SetClipboard { agent_id, .. }
+            | ClientMessage::GetClipboard { agent_id }
+            | ClientMessage::SendKey { agent_id, .. }
+            | ClientMessage::KillAgent { agent_id, .. }
+            | ClientMessage::RestartAgent { agent_id, .. }
+            | ClientMessage::PauseAgent { agent_id }
+            | ClientMessage::ResumeAgent { agent_id }
+            | ClientMessage::RenameAgent { agent_id, .. }
+            | ClientMessage::TagAgent { agent_id, .. }
+            | ClientMessage::ResizeTerminal { agent_id, .. }
+            | ClientMessage::GetAgentStatus { agent_id }
+            | ClientMessage::GetScreenState { agent_id }
+            | ClientMessage::GetInputHistory { agent_id, .. }
+            | ClientMessage::ConfirmationReply { agent_id, .. }
+            | ClientMessage::ApproveAction { agent_id, .. }
+            | ClientMessage::DenyAction { agent_id }
+            | ClientMessage::RequestControl { agent_id }
+            | ClientMessage::GrantControl { agent_id, .. }
+            | ClientMessage::ReleaseControl { agent_id }
+            | ClientMessage::AttachAgent { agent_id }
+            | ClientMessage::DetachAgent { agent_id }
+            | ClientMessage::GetDiff { agent_id, .. }
+            | ClientMessage::StageFiles { agent_id, .. }
+            | ClientMessage::UnstageFiles { agent_id, .. }
+            | ClientMessage::CommitChanges { agent_id, .. }
+            | ClientMessage::WatchPath { agent_id, .. }
+            | ClientMessage::UnwatchPath { agent_id, .. }
+            | ClientMessage::WriteFile { agent_id, .. }
+            | ClientMessage::ApplyPatch { agent_id, .. }
+            | ClientMessage::StartRecording { agent_id }
+            | ClientMessage::StopRecording { agent_id } => Some(*agent_id),
+            ClientMessage::Authenticate { .. }
+            | ClientMessage::Ping { .. }
+            | ClientMessage::SpawnAgent { .. }
+            | ClientMessage::AdoptSession { .. }
+            | ClientMessage::AttachExternal { .. }
+            | ClientMessage::ListAgents { .. }
+            | ClientMessage::ListClients
+            | ClientMessage::ReloadConfig
+            | ClientMessage::Shutdown
+            | ClientMessage::SetStreamMode { .. }
+            | ClientMessage::CreateGroup { .. }
+            | ClientMessage::JoinGroup { .. }
+            | ClientMessage::LeaveGroup { .. }
+            | ClientMessage::ListGroups
+            | ClientMessage::BroadcastInput { .. }
+            | ClientMessage::ClaimSession { .. }
+            | ClientMessage::ResumeSession { .. }
+            | ClientMessage::ListRecordings { .. }
+            | ClientMessage::ReplayRecording { .. }
+            | ClientMessage::ListWorktrees { .. }
+            | ClientMessage::CreateWorktree { .. }
+            | ClientMessage::RemoveWorktree { .. }
+            | ClientMessage::ListBranches { .. }
+            | ClientMessage::CreateBranch { .. }
+            | ClientMessage::CheckoutBranch { .. }
+            | ClientMessage::DeleteBranch { .. }
+            | ClientMessage::PushBranch { .. }
+            | ClientMessage::PullBranch { .. }
+            | ClientMessage::ListDirectory { .. }
+            | ClientMessage::ReadFile { .. }
+            | ClientMessage::StatPath { .. }
+            | ClientMessage::EnqueueTask { .. }
+            | ClientMessage::ListTasks
+            | ClientMessage::CancelTask { .. }
+            | ClientMessage::RunPipeline { .. }
+            | ClientMessage::ListSchedules
+            | ClientMessage::EnableSchedule { .. }
+            | ClientMessage::ListPresets { .. }
+            | ClientMessage::ValidateSpawn { .. } => None,
+        }
+    }
+
+    /// The project directory this message works in, if it names one
+    pub fn project_path(&self) -> Option<&str> {
+        match self {
+            ClientMessage::SpawnAgent { project_path, .. }
+            | ClientMessage::ListWorktrees { project_path }
+            | ClientMessage::CreateWorktree { project_path, .. }
+            | ClientMessage::RemoveWorktree { project_path, .. }
+            | ClientMessage::ListBranches { project_path, .. }
+            | ClientMessage::CreateBranch { project_path, .. }
+            | ClientMessage::CheckoutBranch { project_path, .. }
+            | ClientMessage::DeleteBranch { project_path, .. }
+            | ClientMessage::PushBranch { project_path, .. }
+            | ClientMessage::PullBranch { project_path, .. }
+            | ClientMessage::ListRecordings { project_path }
+            | ClientMessage::ReplayRecording { project_path, .. }
+            | ClientMessage::EnqueueTask { project_path, .. }
+            | ClientMessage::RunPipeline { project_path, .. }
+            | ClientMessage::ListPresets { project_path }
+            | ClientMessage::ValidateSpawn { project_path, .. } => Some(project_path),
+            _ => None,
+        }
+    }
+
+    /// The least role a client needs to send this message
+    ///
+    /// Observers may only look: list and inspect agents, follow their output
+    /// and read files, diffs and recordings.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ClientMessage::ReloadConfig | ClientMessage::Shutdown => Role::Admin,
+            ClientMessage::Authenticate { .. }
+            | ClientMessage::Ping { .. }
+            | ClientMessage::ListAgents { .. }
+            | ClientMessage::ListClients
+            | ClientMessage::GetAgentStatus { .. }
+            | ClientMessage::GetInputHistory { .. }
+            | ClientMessage::SetStreamMode { .. }
+            | ClientMessage::ResumeSession { .. }
+            | ClientMessage::AttachAgent { .. }
+            | ClientMessage::DetachAgent { .. }
+            | ClientMessage::JoinGroup { .. }
+            | ClientMessage::LeaveGroup { .. }
+            | ClientMessage::ListGroups
+            | ClientMessage::GetScreenState { .. }
+            | ClientMessage::GetEchoState { .. }
+            | ClientMessage::GetClipboard { .. }
+            | ClientMessage::ListWorktrees { .. }
+            | ClientMessage::ListBranches { .. }
+            | ClientMessage::GetDiff { .. }
+            | ClientMessage::ListDirectory { .. }
+            | ClientMessage::ReadFile { .. }
+            | ClientMessage::StatPath { .. }
+            | ClientMessage::WatchPath { .. }
+            | ClientMessage::UnwatchPath { .. }
+            | ClientMessage::ListRecordings { .. }
+            | ClientMessage::ReplayRecording { .. }
+            | ClientMessage::ListTasks
+            | ClientMessage::ListSchedules
+            | ClientMessage::ListPresets { .. } => Role::Observer,
+            _ => Role::Operator,
+        }
+    }
+
+    /// The capability a server must have enabled to handle this message, if any
+    pub fn capability(&self) -> Option<Capability> {
+        match self {
+            ClientMessage::SpawnAgent { .. }
+            | ClientMessage::AdoptSession { .. }
+            | ClientMessage::AttachExternal { .. }
+            | ClientMessage::RestartAgent { .. }
+            | ClientMessage::EnqueueTask { .. }
+            | ClientMessage::RunPipeline { .. }
+            | ClientMessage::ValidateSpawn { .. } => Some(Capability::Spawn),
+            ClientMessage::ListWorktrees { .. }
+            | ClientMessage::CreateWorktree { .. }
+            | ClientMessage::RemoveWorktree { .. }
+            | ClientMessage::ListBranches { .. }
+            | ClientMessage::CreateBranch { .. }
+            | ClientMessage::CheckoutBranch { .. }
+            | ClientMessage::DeleteBranch { .. }
+            | ClientMessage::PushBranch { .. }
+            | ClientMessage::PullBranch { .. }
+            | ClientMessage::GetDiff { .. }
+            | ClientMessage::StageFiles { .. }
+            | ClientMessage::UnstageFiles { .. }
+            | ClientMessage::CommitChanges { .. } => Some(Capability::Git),
+            ClientMessage::ListDirectory { .. }
+            | ClientMessage::ReadFile { .. }
+            | ClientMessage::StatPath { .. }
+            | ClientMessage::WatchPath { .. }
+            | ClientMessage::UnwatchPath { .. }
+            | ClientMessage::WriteFile { .. }
+            | ClientMessage::ApplyPatch { .. } => Some(Capability::Files),
+            ClientMessage::SetClipboard { .. } | ClientMessage::GetClipboard { .. } => {
+                Some(Capability::Clipboard)
+            }
+            _ => None,
+        }
+    }
+
+    /// Create a Ping message
+    pub fn ping(seq: u64) -> Self {
+        ClientMessage::Ping { seq }
+    }
+
+    /// Create a ListAgents message listing every agent
+    pub fn list_agents() -> Self {
+        ClientMessage::ListAgents {
+            filter: AgentFilter::default(),
+        }
+    }
+
+    /// Create a SpawnAgent message
+    pub fn spawn_agent(project_path: impl Into<String>) -> Self {
+        ClientMessage::SpawnAgent {
+            project_path: project_path.into(),
+            preset: None,
+            cols: None,
+            rows: None,
+            tags: Vec::new(),
+            group: None,
+            name: None,
+            mode: None,
+            prompt: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            report_echo: false,
+        }
+    }
+
+    /// Create a SpawnAgent message with preset
+    pub fn spawn_agent_with_preset(
+        project_path: impl Into<String>,
+        preset: impl Into<String>,
+    ) -> Self {
+        ClientMessage::SpawnAgent {
+            project_path: project_path.into(),
+            preset: Some(preset.into()),
+            cols: None,
+            rows: None,
+            tags: Vec::new(),
+            group: None,
+            name: None,
+            mode: None,
+            prompt: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            report_echo: false,
+        }
+    }
+
+    /// Create an AgentInput message
+    pub fn agent_input(agent_id: Uuid, input: impl Into<String>) -> Self {
+        ClientMessage::AgentInput {
+            agent_id,
+            input: input.into(),
+            paste: false,
+        }
+    }
+
+    /// Create a KillAgent message
+    pub fn kill_agent(agent_id: Uuid) -> Self {
+        ClientMessage::KillAgent {
+            agent_id,
+            signal: None,
+        }
+    }
+
+    /// Create a ResizeTerminal message
+    pub fn resize_terminal(agent_id: Uuid, cols: u16, rows: u16) -> Self {
+        ClientMessage::ResizeTerminal {
+            agent_id,
+            cols,
+            rows,
+        }
+    }
+}
+
+/// Decode the base64 payload of an `agent_input_raw` message
+pub fn decode_raw_input(data: &str) -> ProtocolResult<Vec<u8>> {
+    use base64::Engine;
+
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| ProtocolError::ValidationError(format!("invalid base64 data: {}", e)))
+}
+
+/// Decode the `content` of a `write_file` message
+pub fn decode_file_content(
+    content: &str,
+    encoding: Option<OutputEncoding>,
+) -> ProtocolResult<Vec<u8>> {
+    match encoding {
+        Some(OutputEncoding::Base64) => decode_raw_input(content),
+        None => Ok(content.as_bytes().to_vec()),
+    }
+}
+
+/// Validate a `project_path` naming an existing project
+fn check_project_path(project_path: &str) -> ProtocolResult<()> {
+    check_path_field("project_path", project_path)
+}
+
+/// Validate a required path field
+fn check_path_field(field: &str, path: &str) -> ProtocolResult<()> {
+    if path.is_empty() {
+        return Err(ProtocolError::invalid_field(
+            field,
+            format!("{} cannot be empty", field),
+        ));
+    }
+    if path.len() > MAX_PATH_LENGTH {
+        return Err(ProtocolError::field_limit(
+            field,
+            format!(
+                "{} exceeds maximum length of {} characters",
+                field, MAX_PATH_LENGTH
+            ),
+            MAX_PATH_LENGTH as u64,
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a git branch name or revision (git itself checks the ref name rules)
+fn check_branch_name(field: &str, branch: &str) -> ProtocolResult<()> {
+    if branch.is_empty() || branch.starts_with('-') {
+        return Err(ProtocolError::invalid_field(
+            field,
+            format!("{} must be non-empty and must not start with '-'", field),
+        ));
+    }
+    if branch.len() > MAX_BRANCH_NAME_LENGTH {
+        return Err(ProtocolError::field_limit(
+            field,
+            format!(
+                "{} exceeds maximum length of {} characters",
+                field, MAX_BRANCH_NAME_LENGTH
+            ),
+            MAX_BRANCH_NAME_LENGTH as u64,
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a tag or group ID
+fn check_tag(field: &str, tag: &str) -> ProtocolResult<()> {
+    if tag.is_empty() || tag.len() > MAX_TAG_LENGTH {
+        return Err(ProtocolError::field_limit(
+            field,
+            format!("{} must be 1 to {} characters", field, MAX_TAG_LENGTH),
+            MAX_TAG_LENGTH as u64,
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a working directory: a relative path that stays inside the
+/// project
+fn check_cwd(cwd: &str) -> ProtocolResult<()> {
+    check_path_field("cwd", cwd)?;
+    let inside = Path::new(cwd)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(ProtocolError::invalid_field(
+            "cwd",
+            "cwd must be a path inside the project, relative to it",
+        ));
+    }
+    Ok(())
+}
+
+/// Validate environment variables: portable names, values without NUL bytes
+fn check_env(env: &BTreeMap<String, String>) -> ProtocolResult<()> {
+    if env.len() > MAX_ENV_VARS {
+        return Err(ProtocolError::field_limit(
+            "env",
+            format!("at most {} environment variables can be set", MAX_ENV_VARS),
+            MAX_ENV_VARS as u64,
+        ));
+    }
+    for (name, value) in env {
+        let valid_name = !name.starts_with(|c: char| c.is_ascii_digit())
+            && !name.is_empty()
+            && name.len() <= MAX_ENV_NAME_LENGTH
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(ProtocolError::invalid_field(
+                "env",
+                format!("invalid environment variable name {:?}", name),
+            ));
+        }
+        if value.len() > MAX_ENV_VALUE_LENGTH || value.contains('\0') {
+            return Err(ProtocolError::invalid_field(
+                "env",
+                format!(
+                    "the value of {} must be at most {} bytes without NUL characters",
+                    name, MAX_ENV_VALUE_LENGTH
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validate an agent's display name
+fn check_agent_name(name: &str) -> ProtocolResult<()> {
+    if name.trim().is_empty() || name.chars().any(char::is_control) {
+        return Err(ProtocolError::invalid_field(
+            "name",
+            "name must not be blank or contain control characters",
+        ));
+    }
+    if name.len() > MAX_AGENT_NAME_LENGTH {
+        return Err(ProtocolError::field_limit(
+            "name",
+            format!(
+                "name exceeds maximum length of {} characters",
+                MAX_AGENT_NAME_LENGTH
+            ),
+            MAX_AGENT_NAME_LENGTH as u64,
+        ));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Server Messages
+// ============================================================================
+
+/// Messages sent from server to client (Godot)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Welcome message sent on connection
+    Welcome {
+        /// Newest protocol version the server speaks
+        version: u32,
+        /// Oldest protocol version the server speaks
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_version: Option<u32>,
+        /// Server identifier/name
+        #[serde(skip_serializing_if = "Option::is_none")]
+        server_id: Option<String>,
+        /// Whether authentication is required
+        #[serde(skip_serializing_if = "Option::is_none")]
+        auth_required: Option<bool>,
+        /// Capabilities the server has enabled (all of them when absent)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capabilities: Option<Vec<Capability>>,
+    },
+
+    /// Authentication successful
+    AuthSuccess {
+        /// What the client's token lets it do
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+    },
+
+    /// Protocol version used for the rest of the connection: the newest
+    /// version both sides speak, from the range of the client's first message
+    VersionNegotiated {
+        /// Negotiated protocol version
+        version: u32,
+    },
+
+    /// Response to Ping
+    Pong {
+        /// Echo back the sequence number
+        seq: u64,
+    },
+
+    /// Agent successfully spawned
+    AgentSpawned {
+        /// UUID of the new agent
+        agent_id: Uuid,
+        /// Confirmed project path
+        project_path: String,
+        /// Terminal columns
+        cols: u16,
+        /// Terminal rows
+        rows: u16,
+    },
+
+    /// Output data from an agent
+    AgentOutput {
+        /// UUID of the source agent
+        agent_id: Uuid,
+        /// Output data (may contain ANSI escape sequences), encoded as given
+        /// by `encoding`
+        data: String,
+        /// Encoding of `data`; plain text when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<OutputEncoding>,
+    },
+
+    /// Agent process exited
+    AgentExited {
+        /// UUID of the exited agent
+        agent_id: Uuid,
+        /// Exit code if available
+        #[serde(skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+        /// Signal that terminated the process, on Unix
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signal: Option<i32>,
+        /// Why the agent exited
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<AgentExitReason>,
+        /// Preset the agent was spawned with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preset: Option<String>,
+        /// Duration and I/O totals of the run
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stats: Option<RunStats>,
+    },
+
+    /// An agent's process was restarted; its terminal starts over
+    AgentRestarted {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Terminal columns of the new process
+        cols: u16,
+        /// Terminal rows of the new process
+        rows: u16,
+    },
+
+    /// An agent has been idle long enough that it is about to be stopped;
+    /// any input or output keeps it running
+    AgentIdleWarning {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Time since the agent's last input or output, in milliseconds
+        idle_ms: u64,
+        /// Time left before it is stopped, in milliseconds
+        stop_in_ms: u64,
+    },
+
+    /// A trigger of the agent's preset matched its output
+    TriggerFired {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Name of the trigger
+        trigger: String,
+        /// Text matched by the trigger's capture groups, by group name, or by
+        /// number for unnamed groups
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        captures: BTreeMap<String, String>,
+    },
+
+    /// An agent showed its prompt and is ready for its first command
+    AgentReady {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// Text written by a `stream_json` agent
+    AssistantText {
+        /// UUID of the agent
+        agent_id: Uuid,
+        text: String,
+    },
+
+    /// A `stream_json` agent called a tool
+    ToolUse {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// ID the matching `tool_result` refers to
+        tool_use_id: String,
+        /// Name of the tool, e.g. "Bash" or "Edit"
+        name: String,
+        /// The tool's arguments
+        input: serde_json::Value,
+    },
+
+    /// A tool called by a `stream_json` agent finished
+    ToolResult {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// ID of the `tool_use` this answers
+        tool_use_id: String,
+        /// Text the tool returned
+        content: String,
+        /// Whether the tool failed
+        #[serde(default, skip_serializing_if = "is_false")]
+        is_error: bool,
+    },
+
+    /// A `stream_json` agent finished its prompt
+    Cost {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Cost of the run in US dollars
+        total_cost_usd: f64,
+        /// Duration of the run in milliseconds
+        duration_ms: u64,
+        /// Turns the assistant took
+        num_turns: u32,
+        /// Tokens sent to the model
+        input_tokens: u64,
+        /// Tokens the model generated
+        output_tokens: u64,
+    },
+
+    /// An agent's initial prompt was typed into it, once the agent was ready
+    /// for input or its ready timeout passed
+    InitialPromptDelivered {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Time from the agent's start until the prompt was sent, in milliseconds
+        waited_ms: u64,
+        /// Whether the ready pattern never matched and the prompt was sent anyway
+        #[serde(default, skip_serializing_if = "is_false")]
+        timed_out: bool,
+    },
+
+    /// An agent's tags changed
+    AgentTagged {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// All of the agent's tags after the change
+        tags: Vec<String>,
+    },
+
+    /// An agent's display name changed
+    AgentRenamed {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// New name (`None` when it was removed)
+        name: Option<String>,
+    },
+
+    /// A signal was delivered to an agent's process
+    AgentSignalled {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Signal number
+        signal: i32,
+    },
+
+    /// Agent terminal resized
+    AgentResized {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// New terminal columns
+        cols: u16,
+        /// New terminal rows
+        rows: u16,
+    },
+
+    /// List of active agents
+    AgentList {
+        /// List of agent information
+        agents: Vec<AgentInfo>,
+    },
+
+    /// Connected clients
+    ClientList {
+        /// One entry per connection
+        clients: Vec<ClientInfo>,
+    },
+
+    /// The configuration file was reloaded
+    ConfigReloaded {
+        /// Names of the settings that changed, e.g. `token` or `max_agents`
+        changed: Vec<String>,
+    },
+
+    /// The server is stopping at a client's request
+    ShuttingDown,
+
+    /// The server changed the quality of this connection's agent output to
+    /// keep up with its bandwidth
+    QualityChanged {
+        /// Quality now in effect
+        quality: QualityTier,
+    },
+
+    /// Status of a specific agent
+    AgentStatus {
+        /// Everything known about the agent
+        #[serde(flatten)]
+        info: Box<AgentInfo>,
+    },
+
+    /// A chunk of a paste was received
+    InputChunkAck {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Index of the acknowledged chunk
+        part: u32,
+        /// Total number of chunks
+        of: u32,
+    },
+
+    /// A chunked paste has been completely written to the agent
+    PasteWritten {
+        /// UUID of the target agent
+        agent_id: Uuid,
+        /// Number of bytes written
+        bytes: usize,
+    },
+
+    /// Another client is sending input to an agent
+    ClientTyping {
+        /// UUID of the agent receiving input
+        agent_id: Uuid,
+        /// Client sending the input (its address)
+        client: String,
+    },
+
+    /// Recent inputs sent to an agent, oldest first
+    InputHistory {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// History entries (secrets redacted)
+        entries: Vec<InputHistoryEntry>,
+    },
+
+    /// The client controlling an agent's input changed
+    ControlChanged {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Controlling client (`None` when nobody holds control)
+        owner: Option<String>,
+    },
+
+    /// Another client asks for control of an agent you control
+    ControlRequested {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Requesting client; pass it to `grant_control` to hand over
+        client: String,
+    },
+
+    /// An agent moved to a different lifecycle state
+    AgentStateChanged {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// State before the change
+        old_state: AgentState,
+        /// State after the change
+        new_state: AgentState,
+        /// When the change happened, in Unix milliseconds
+        #[serde(default)]
+        timestamp_ms: u64,
+    },
+
+    /// A command started in an agent's shell, as marked by shell integration
+    CommandStarted {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// A command marked by shell integration finished
+    CommandFinished {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Exit code, if the shell reported one
+        exit_code: Option<i32>,
+    },
+
+    /// Stream mode in effect for the connection
+    StreamModeSet {
+        /// The mode now in effect
+        mode: StreamMode,
+    },
+
+    /// What an agent's clipboard holds, in answer to `set_clipboard` or
+    /// `get_clipboard`
+    Clipboard {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Copied text, empty if nothing was copied
+        text: String,
+        /// Client that copied the text
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        set_by: Option<String>,
+        /// When the text was copied, in Unix milliseconds
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        set_at_ms: Option<u64>,
+    },
+
+    /// Whether an agent's terminal echoes input and is in raw mode
+    ///
+    /// Clients echo typed characters locally only while the terminal doesn't.
+    EchoState {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// The terminal echoes typed characters
+        echo: bool,
+        /// Input reaches the agent as it is typed, not a line at a time
+        raw: bool,
+    },
+
+    /// The current screen of an agent's terminal
+    ScreenState {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Cursor and cells of the screen
+        screen: ScreenState,
+    },
+
+    /// Branches of a repository, local ones first
+    BranchList {
+        /// Branches with the commit each points at
+        branches: Vec<BranchInfo>,
+    },
+
+    /// A branch was created
+    BranchCreated {
+        /// The new branch
+        branch: BranchInfo,
+    },
+
+    /// A branch was checked out
+    BranchCheckedOut {
+        /// Path of the worktree it was checked out in
+        project_path: String,
+        /// Branch now checked out
+        branch: String,
+    },
+
+    /// A branch was deleted
+    BranchDeleted {
+        /// Name of the deleted branch
+        branch: String,
+    },
+
+    /// How far a push or pull has got
+    TransferProgress {
+        /// Repository the transfer is for, as requested
+        project_path: String,
+        /// Whether this is a push or a pull
+        operation: TransferOperation,
+        /// Objects and bytes transferred so far
+        progress: TransferProgress,
+    },
+
+    /// A branch was pushed
+    BranchPushed {
+        /// Repository, as requested
+        project_path: String,
+        /// Remote pushed to
+        remote: String,
+        /// Branch pushed
+        branch: String,
+    },
+
+    /// A branch was pulled
+    BranchPulled {
+        /// Repository, as requested
+        project_path: String,
+        /// Remote pulled from
+        remote: String,
+        /// Branch pulled
+        branch: String,
+        /// Commit the local branch points at now
+        commit_id: String,
+        /// Whether the branch moved (false when already up to date)
+        updated: bool,
+    },
+
+    /// Changes in the repository an agent works in
+    Diff {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Whether these are the staged changes
+        staged: bool,
+        /// Changed files with their hunks
+        files: Vec<FileDiff>,
+        /// Whether the diff was cut short for its size
+        #[serde(default, skip_serializing_if = "is_false")]
+        truncated: bool,
+    },
+
+    /// Files were staged in an agent's repository
+    FilesStaged {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Pathspecs that were staged
+        paths: Vec<String>,
+    },
+
+    /// Files were unstaged in an agent's repository
+    FilesUnstaged {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Pathspecs that were unstaged
+        paths: Vec<String>,
+    },
+
+    /// Staged changes in an agent's repository were committed
+    ChangesCommitted {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// ID of the new commit
+        commit_id: String,
+        /// Branch the commit was made on (absent with a detached HEAD)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        branch: Option<String>,
+    },
+
+    /// Entries of a directory
+    DirectoryListing {
+        /// Canonical path of the directory
+        path: String,
+        /// Directories first, then by name
+        entries: Vec<FileEntry>,
+        /// Whether entries were dropped for the directory's size
+        #[serde(default, skip_serializing_if = "is_false")]
+        truncated: bool,
+    },
+
+    /// Part of a file's contents
+    FileContents {
+        /// Canonical path of the file
+        path: String,
+        /// Byte the contents start at
+        offset: u64,
+        /// Size of the whole file in bytes
+        size: u64,
+        /// The contents, encoded as given by `encoding`
+        data: String,
+        /// Encoding of `data`; UTF-8 text when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<OutputEncoding>,
+    },
+
+    /// A file or directory
+    PathStat {
+        /// Canonical path
+        path: String,
+        /// What the path is, its size and modification time
+        entry: FileEntry,
+    },
+
+    /// Changes below a path are being watched
+    PathWatched {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Path as given to `watch_path`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+
+    /// A path is no longer watched
+    PathUnwatched {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Path as given to `unwatch_path`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+
+    /// A file was written
+    FileWritten {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Path as given to `write_file`
+        path: String,
+        /// Size of the file in bytes
+        size: u64,
+        /// Whether the file was staged
+        #[serde(default, skip_serializing_if = "is_false")]
+        staged: bool,
+    },
+
+    /// A patch was applied
+    PatchApplied {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// Paths the patch touched, relative to the repository root
+        paths: Vec<String>,
+        /// Whether the changes were staged as well
+        #[serde(default, skip_serializing_if = "is_false")]
+        staged: bool,
+    },
+
+    /// A file below a watched path changed
+    FileChanged {
+        /// UUID of the agent whose project the file is in
+        agent_id: Uuid,
+        /// Path relative to the project directory
+        path: String,
+        /// How it changed
+        change: ChangeKind,
+    },
+
+    /// Worktrees of a repository, the main worktree first
+    WorktreeList {
+        /// Main and linked worktrees
+        worktrees: Vec<WorktreeInfo>,
+    },
+
+    /// A worktree was created
+    WorktreeCreated {
+        /// The new worktree
+        worktree: WorktreeInfo,
+    },
+
+    /// A worktree was removed
+    WorktreeRemoved {
+        /// Path of the removed worktree
+        path: String,
+    },
+
+    /// An agent's output is being recorded
+    RecordingStarted {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// ID to replay the recording by
+        recording_id: String,
+    },
+
+    /// Recording of an agent's output stopped
+    RecordingStopped {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// ID of the finished recording
+        recording_id: String,
+        /// Length of the recording in milliseconds
+        duration_ms: u64,
+    },
+
+    /// Recordings of a project
+    RecordingList {
+        /// Recordings, oldest first
+        recordings: Vec<RecordingInfo>,
+    },
+
+    /// Playback of a recording begins; its terminal starts out at this size
+    ReplayStarted {
+        /// Recording being played
+        recording_id: String,
+        /// Terminal width in columns
+        cols: u16,
+        /// Terminal height in rows
+        rows: u16,
+    },
+
+    /// Recorded output, sent with the timing it was recorded with
+    ReplayOutput {
+        /// Recording being played
+        recording_id: String,
+        /// Terminal output
+        data: String,
+    },
+
+    /// The recorded terminal was resized
+    ReplayResized {
+        /// Recording being played
+        recording_id: String,
+        /// New width in columns
+        cols: u16,
+        /// New height in rows
+        rows: u16,
+    },
+
+    /// Playback of a recording finished
+    ReplayFinished {
+        /// Recording that was played
+        recording_id: String,
+    },
+
+    /// The connection now receives an agent's output
+    AgentAttached {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// The connection no longer receives an agent's output
+    AgentDetached {
+        /// UUID of the agent
+        agent_id: Uuid,
+    },
+
+    /// The connection joined a session group and now receives the output
+    /// of its agents
+    GroupJoined {
+        group: String,
+        /// Agents in the group, now attached
+        agent_ids: Vec<Uuid>,
+        /// Clients in the group, this one included
+        members: Vec<String>,
+    },
+
+    /// The connection left a session group
+    GroupLeft { group: String },
+
+    /// How input sent with `broadcast_input` went for each agent
+    InputBroadcast {
+        /// One entry per target agent, in the order they were listed,
+        /// followed by the group's other agents
+        results: Vec<BroadcastResult>,
+    },
+
+    /// A client joined or left a session group this connection is in
+    GroupMembers {
+        group: String,
+        /// Clients now in the group
+        members: Vec<String>,
+    },
+
+    /// Session groups
+    GroupList { groups: Vec<GroupInfo> },
+
+    /// The connection owns agents by the claimed session token
+    SessionClaimed {
+        /// Running agents owned by the token, now attached
+        agent_ids: Vec<Uuid>,
+    },
+
+    /// Token to resume this connection's session with, should it drop
+    ResumeToken {
+        resume_token: String,
+        /// How long after the connection drops the session can be resumed
+        grace_ms: u64,
+    },
+
+    /// The session of a dropped connection was resumed; the output it
+    /// missed follows as `agent_output`
+    SessionResumed {
+        /// Agents attached again
+        agent_ids: Vec<Uuid>,
+        /// Agents whose input control was kept
+        controlled: Vec<Uuid>,
+        /// Bytes of missed output that follow
+        replayed_bytes: u64,
+        /// Whether older missed output was dropped to stay within the limit
+        #[serde(default, skip_serializing_if = "is_false")]
+        truncated: bool,
+    },
+
+    /// A task was added to the queue
+    TaskQueued {
+        /// ID of the task
+        task_id: Uuid,
+        /// Tasks ahead of it in the queue
+        position: usize,
+    },
+
+    /// Response to `list_tasks`
+    TaskList {
+        /// Running tasks, then queued tasks in the order they will start
+        tasks: Vec<TaskInfo>,
+    },
+
+    /// A queued task was removed before it started
+    TaskCancelled {
+        /// ID of the task
+        task_id: Uuid,
+    },
+
+    /// A queued task started running
+    TaskStarted {
+        /// ID of the task
+        task_id: Uuid,
+        /// Agent running it
+        agent_id: Uuid,
+        /// Project it runs in
+        project_path: String,
+    },
+
+    /// A task finished, or could not be started
+    TaskCompleted {
+        /// ID of the task
+        task_id: Uuid,
+        /// Agent that ran it, unless it could not be spawned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        agent_id: Option<Uuid>,
+        /// Whether the agent answered the prompt and exited cleanly
+        success: bool,
+        /// Exit code of the agent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+        /// What the assistant wrote, its end kept when it is long
+        output: String,
+        /// Why the task could not run
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// Cost of the run in US dollars, when reported
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        total_cost_usd: Option<f64>,
+        /// Time from start to finish in milliseconds
+        duration_ms: u64,
+    },
+
+    /// Response to `list_schedules`
+    ScheduleList { schedules: Vec<ScheduleInfo> },
+
+    /// A schedule was switched on or off
+    ScheduleUpdated { schedule: ScheduleInfo },
+
+    /// A schedule's time came and its agent was spawned, or failed to spawn
+    ScheduleFired {
+        /// Name of the schedule
+        schedule: String,
+        /// Project the agent runs in
+        project_path: String,
+        /// The agent spawned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        agent_id: Option<Uuid>,
+        /// Why the agent could not be spawned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    /// A pipeline started running
+    PipelineStarted {
+        /// ID of the run, identifying its progress messages
+        run_id: Uuid,
+        /// Name of the pipeline
+        pipeline: String,
+        /// Project it runs in
+        project_path: String,
+        /// Number of steps
+        steps: usize,
+    },
+
+    /// A step of a running pipeline started
+    PipelineStep {
+        run_id: Uuid,
+        /// Index of the step, from zero
+        step: usize,
+        /// What the step does: `spawn`, `wait`, `input` or `kill`
+        action: String,
+        /// Name the pipeline gives the agent the step acts on
+        agent: String,
+        /// The agent, once spawned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        agent_id: Option<Uuid>,
+    },
+
+    /// A pipeline ran all its steps, or stopped at one that failed
+    PipelineCompleted {
+        run_id: Uuid,
+        /// Whether every step succeeded
+        success: bool,
+        /// Index of the step that failed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failed_step: Option<usize>,
+        /// Why it failed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// Agents the pipeline spawned, by the names it gives them
+        agents: BTreeMap<String, Uuid>,
+        /// Time from start to finish in milliseconds
+        duration_ms: u64,
+    },
+
+    /// Response to `validate_spawn`: what spawning the agent would run into
+    SpawnValidation {
+        /// Project the agent would be spawned in
+        project_path: String,
+        /// Preset the agent would be spawned with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preset: Option<String>,
+        /// Whether no check failed
+        ok: bool,
+        /// Result of each check
+        checks: Vec<SpawnCheck>,
+    },
+
+    /// Response to `list_presets`
+    PresetList {
+        /// Project the presets are for
+        project_path: String,
+        /// The project's presets, then global presets it doesn't override
+        presets: Vec<PresetInfo>,
+        /// Preset agents are spawned with when none is named
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        default_preset: Option<String>,
+    },
+
+    /// A plain-language sentence about something an agent did, sent instead
+    /// of agent events in `summary` stream mode
+    EventSummary {
+        /// UUID of the agent
+        agent_id: Uuid,
+        /// The sentence, e.g. "Agent reviewer is asking: Apply this edit?"
+        text: String,
+    },
+
+    /// An agent is asking a yes/no or multiple-choice question
+    ConfirmationRequest {
+        /// UUID of the agent asking
+        agent_id: Uuid,
+        /// The question, as shown in the terminal
+        question: String,
+        /// Possible answers; reply with the index of one
+        options: Vec<String>,
+    },
+
+    /// A tool run by an agent is asking permission before it runs; answer
+    /// with `approve_action` or `deny_action`
+    ApprovalRequested {
+        /// UUID of the agent asking
+        agent_id: Uuid,
+        /// Title of the prompt, naming the tool: "Bash command", "Edit file", ...
+        tool: String,
+        /// What the tool is about to do, such as the command line or file path
+        detail: String,
+        /// The question, as shown in the terminal
+        question: String,
+        /// The prompt's options, which `confirmation_reply` also accepts
+        options: Vec<String>,
+        /// Whether `approve_action` may ask not to be prompted again
+        #[serde(default, skip_serializing_if = "is_false")]
+        can_always: bool,
+    },
+
+    /// Error response
+    Error {
+        /// Error message
+        message: String,
+        /// Error code for programmatic handling
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<ErrorCode>,
+        /// Related agent UUID if applicable
+        #[serde(skip_serializing_if = "Option::is_none")]
+        agent_id: Option<Uuid>,
+        /// Whether repeating the request later may succeed
+        #[serde(default)]
+        retryable: bool,
+        /// Machine-readable context, e.g. the invalid `field` and its `max`
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        details: BTreeMap<String, serde_json::Value>,
+        /// `request_id` of the message that caused the error
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+}
+
+/// How binary data in `agent_output`, `file_contents` and `write_file` is encoded
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputEncoding {
+    /// Base64 of the raw bytes, which need not be valid UTF-8
+    Base64,
+}
+
+/// A connected client
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// Client address
+    pub client: String,
+    /// Time the client connected, in milliseconds since the Unix epoch
+    pub connected_at_ms: u64,
+    /// Bytes sent to the client since it connected
+    pub bytes_sent: u64,
+    /// Recent bytes per second sent to the client
+    pub bytes_per_sec: u64,
+    /// Quality of the agent output the client receives
+    pub quality: QualityTier,
+}
+
+/// Whether broadcast input reached one agent
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BroadcastResult {
+    /// UUID of the agent
+    pub agent_id: Uuid,
+    /// The input was sent to the agent, or to the peer bridge hosting it
+    pub ok: bool,
+    /// Why it wasn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+}
+
+impl BroadcastResult {
+    /// The result for an agent from the response to input sent to it
+    pub fn from_response(agent_id: Uuid, response: Option<&ServerMessage>) -> Self {
+        match response {
+            Some(ServerMessage::Error { message, code, .. }) => Self {
+                agent_id,
+                ok: false,
+                error: Some(message.clone()),
+                code: *code,
+            },
+            _ => Self {
+                agent_id,
+                ok: true,
+                error: None,
+                code: None,
+            },
+        }
+    }
+}
+
+/// A session group
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GroupInfo {
+    /// Name of the group
+    pub group: String,
+    /// Agents spawned into the group that are running
+    pub agent_ids: Vec<Uuid>,
+    /// Clients in the group
+    pub members: Vec<String>,
+}
+
+/// How much agent output a connection receives, lowered for clients that
+/// can't keep up
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityTier {
+    /// Every output chunk as it is produced
+    #[default]
+    Full,
+    /// Output batched per agent a few times a second
+    Coalesced,
+    /// No terminal output, only agent events and status
+    Status,
+}
+
+/// Direction of a transfer with a git remote
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferOperation {
+    Push,
+    Pull,
+}
+
+/// Where a task is
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    /// Waiting for its project or a free slot
+    Queued,
+    /// Being worked on by an agent
+    Running,
+}
+
+/// A queued or running task
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TaskInfo {
+    /// ID of the task
+    pub task_id: Uuid,
+    /// Project the task runs in
+    pub project_path: String,
+    /// What the agent is asked to do
+    pub prompt: String,
+    /// Preset the agent is spawned with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// Where the task is
+    pub status: TaskStatus,
+    /// Agent running the task, once it started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<Uuid>,
+    /// When the task was queued, in milliseconds since the Unix epoch
+    pub queued_at_ms: u64,
+    /// Cluster node the task was queued on, if not the one answering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+}
+
+/// An agent spawned at set times
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScheduleInfo {
+    /// Name of the schedule
+    pub name: String,
+    /// Cron expression giving its times, in UTC
+    pub cron: String,
+    /// Project the agent runs in
+    pub project_path: String,
+    /// Preset the agent is spawned with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// Whether the schedule runs
+    pub enabled: bool,
+    /// When it runs next, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run_ms: Option<u64>,
+    /// When it last ran, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run_ms: Option<u64>,
+    /// Agent spawned by the last run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_agent_id: Option<Uuid>,
+}
+
+/// What a spawn check looked at
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpawnCheckKind {
+    /// The project directory exists
+    ProjectPath,
+    /// The project configuration loads and has the preset
+    Preset,
+    /// The agent's command can be found
+    Command,
+    /// The git worktree the agent would work in
+    Worktree,
+}
+
+/// Outcome of a spawn check
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    /// The agent could be spawned, but something deserves attention
+    Warning,
+    /// Spawning the agent would fail
+    Failed,
+}
+
+/// One check of a `validate_spawn`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SpawnCheck {
+    pub check: SpawnCheckKind,
+    pub status: CheckStatus,
+    /// What was found, e.g. where the command is
+    pub message: String,
+}
+
+/// Where a preset is defined
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PresetSource {
+    /// The project's `.hoc/config.toml`
+    Project,
+    /// The bridge configuration file, for every project
+    Global,
+}
+
+/// A preset agents can be spawned with
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PresetInfo {
+    /// Name to pass as `preset` in `spawn_agent`
+    pub name: String,
+    /// Where the preset is defined
+    pub source: PresetSource,
+    /// Prompt the agent starts with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_prompt: Option<String>,
+    /// Remote host the agent runs on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+/// A recording of an agent's output
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecordingInfo {
+    /// ID to replay the recording by
+    pub recording_id: String,
+    /// When recording started, in milliseconds since the Unix epoch
+    pub started_at_ms: u64,
+    /// Terminal width when recording started
+    pub cols: u16,
+    /// Terminal height when recording started
+    pub rows: u16,
+    /// Size of the recording file
+    pub size_bytes: u64,
+}
+
+/// What an agent's terminal shows, as kept by the bridge's terminal emulator
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScreenState {
+    /// Terminal width in columns
+    pub cols: u16,
+    /// Terminal height in rows
+    pub rows: u16,
+    /// Row of the cursor, from the top
+    pub cursor_row: u16,
+    /// Column of the cursor, from the left
+    pub cursor_col: u16,
+    /// Whether the application shows the cursor
+    pub cursor_visible: bool,
+    /// Whether a full-screen application switched to the alternate screen
+    pub alternate_screen: bool,
+    /// Cells of each row, top to bottom, one per column; the column covered
+    /// by the right half of a wide character holds an empty cell
+    pub cells: Vec<Vec<ScreenCell>>,
+}
+
+/// One character cell of the screen
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScreenCell {
+    /// Character in the cell, with any combining marks (empty when blank)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub text: String,
+    /// Foreground color (the terminal's default when `None`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fg: Option<ScreenColor>,
+    /// Background color (the terminal's default when `None`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg: Option<ScreenColor>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub bold: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub dim: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub italic: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub underline: bool,
+    /// Foreground and background colors swapped
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub inverse: bool,
+    /// The character is two columns wide
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub wide: bool,
+}
+
+/// A cell color
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenColor {
+    /// One of the 256 palette colors (0-15 are the themeable ANSI colors)
+    Index(u8),
+    /// A 24-bit color
+    Rgb(u8, u8, u8),
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// A recorded agent input
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InputHistoryEntry {
+    /// Input as sent, with secrets redacted
+    pub input: String,
+    /// Time the input was sent, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+}
+
+/// Information about an agent for listing
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AgentInfo {
+    /// Agent UUID
+    pub agent_id: Uuid,
+    /// Display name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Project path
+    pub project_path: String,
+    /// Current state
+    pub status: AgentState,
+    /// Terminal columns
+    pub cols: u16,
+    /// Terminal rows
+    pub rows: u16,
+    /// Name of the peer bridge hosting the agent (`None` for local agents)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Names of the input macros the agent supports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<String>,
+    /// Preset the agent was spawned with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// When the agent was spawned, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawned_at_ms: Option<u64>,
+    /// When the agent last produced output or received input, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity_ms: Option<u64>,
+    /// Git worktree containing the project path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worktree: Option<String>,
+    /// Branch checked out in the worktree
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Client controlling the agent's input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// OS process ID of the agent (local agents only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Client that spawned the agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawned_by: Option<String>,
+    /// Labels attached to the agent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Group the agent belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// What the agent is doing right now, e.g. the tool it runs (`Bash`, `Edit`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+}
+
+/// Conditions an agent must meet to be listed (all of them, when several
+/// are set)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AgentFilter {
+    /// Only agents with this tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Only agents in this state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<AgentState>,
+    /// Only agents working in this directory or below it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_path: Option<String>,
+}
+
+impl AgentFilter {
+    /// Whether an agent meets every condition
+    pub fn matches(&self, info: &AgentInfo) -> bool {
+        self.tag.as_ref().is_none_or(|tag| info.tags.contains(tag))
+            && self.status.is_none_or(|status| info.status == status)
+            && self.project_path.as_ref().is_none_or(|path| {
+                Path::new(&info.project_path).starts_with(Path::new(path))
+            })
+    }
+}
+
+/// Agent lifecycle states
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentState {
+    /// Agent is starting up
+    Starting,
+    /// Agent is running and accepting input
+    Running,
+    /// Agent is showing its prompt and ready for its first command (agents
+    /// with a ready pattern only)
+    Ready,
+    /// Agent has been quiet for a while and is waiting for a new task
+    Idle,
+    /// Agent is asking a question and cannot continue until it is answered
+    WaitingForInput,
+    /// Agent is producing output
+    Busy,
+    /// Agent's process is suspended
+    Paused,
+    /// Agent is shutting down
+    Stopping,
+    /// Agent has stopped
+    #[default]
+    Stopped,
+}
+
+impl AgentState {
+    /// Name of the state as it appears in messages
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AgentState::Starting => "starting",
+            AgentState::Running => "running",
+            AgentState::Ready => "ready",
+            AgentState::Idle => "idle",
+            AgentState::WaitingForInput => "waiting_for_input",
+            AgentState::Busy => "busy",
+            AgentState::Paused => "paused",
+            AgentState::Stopping => "stopping",
+            AgentState::Stopped => "stopped",
+        }
+    }
+
+    /// Whether the agent's process is up, whatever it is doing
+    pub fn is_alive(self) -> bool {
+        matches!(
+            self,
+            AgentState::Running
+                | AgentState::Ready
+                | AgentState::Idle
+                | AgentState::WaitingForInput
+                | AgentState::Busy
+                | AgentState::Paused
+        )
+    }
+}
+
+/// Why an agent exited
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentExitReason {
+    /// The agent's process exited on its own
+    Normal,
+    /// The agent was killed on request, or did not stop within the grace
+    /// period after being asked to
+    Killed,
+    /// The agent stopped within the grace period after being asked to
+    Terminated,
+    /// The process was terminated by a signal
+    Signalled,
+    /// The agent was stopped for exceeding a time limit
+    TimedOut,
+    /// The process went away without reporting how
+    Lost,
+}
+
+impl AgentExitReason {
+    /// Name of the reason as it appears in messages
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AgentExitReason::Normal => "normal",
+            AgentExitReason::Killed => "killed",
+            AgentExitReason::Terminated => "terminated",
+            AgentExitReason::Signalled => "signalled",
+            AgentExitReason::TimedOut => "timed_out",
+            AgentExitReason::Lost => "lost",
+        }
+    }
+}
+
+impl From<ExitReason> for AgentExitReason {
+    fn from(reason: ExitReason) -> Self {
+        match reason {
+            ExitReason::Normal => AgentExitReason::Normal,
+            ExitReason::Signal => AgentExitReason::Signalled,
+            ExitReason::Killed => AgentExitReason::Killed,
+            ExitReason::Terminated => AgentExitReason::Terminated,
+            ExitReason::TimedOut => AgentExitReason::TimedOut,
+            ExitReason::Unknown => AgentExitReason::Lost,
+        }
+    }
+}
+
+/// A group of operations a server can be started without
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Git operations on project repositories
+    Git,
+    /// Reading and writing files
+    Files,
+    /// Starting agent processes
+    Spawn,
+    /// Clipboard exchange with agents
+    Clipboard,
+}
+
+impl Capability {
+    /// Every capability, in the order they are advertised
+    pub const ALL: [Capability; 4] = [
+        Capability::Git,
+        Capability::Files,
+        Capability::Spawn,
+        Capability::Clipboard,
+    ];
+
+    /// Name of the capability as it appears in messages
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Capability::Git => "git",
+            Capability::Files => "files",
+            Capability::Spawn => "spawn",
+            Capability::Clipboard => "clipboard",
+        }
+    }
+}
+
+/// What a client may do, granted by the token it authenticates with
+///
+/// Each role may do everything the ones before it may.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// List agents and follow their output, without changing anything
+    Observer,
+    /// Spawn, drive and stop agents, and change their projects
+    Operator,
+    /// Reload the configuration and shut the server down
+    Admin,
+}
+
+impl Role {
+    /// Name of the role as it appears in messages
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Observer => "observer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Capability::ALL
+            .into_iter()
+            .find(|c| c.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "invalid capability '{}', expected git, files, spawn or clipboard",
+                    s
+                )
+            })
+    }
+}
+
+/// How an agent's claude is run
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentMode {
+    /// Claude's interactive terminal UI
+    #[default]
+    Interactive,
+    /// `claude -p` answering the initial prompt as a JSON event stream, which
+    /// clients are sent as `assistant_text`, `tool_use`, `tool_result` and
+    /// `cost` messages
+    StreamJson,
+}
+
+/// How agent events are delivered to a connection
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMode {
+    /// Terminal output and every agent event
+    #[default]
+    Terminal,
+    /// Short sentences about notable events only, for speech and
+    /// notification clients
+    Summary,
+}
+
+/// What an agent did between spawning and exiting
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RunStats {
+    /// Time from spawn to exit in milliseconds
+    pub duration_ms: u64,
+    /// Bytes written to the agent
+    pub bytes_in: u64,
+    /// Bytes of output the agent produced
+    pub bytes_out: u64,
+    /// Secrets redacted from the agent's recorded input and output
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub redactions: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// Error codes for programmatic error handling
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Invalid message format
+    InvalidMessage,
+    /// Agent not found
+    AgentNotFound,
+    /// Failed to spawn agent
+    SpawnFailed,
+    /// Authentication required
+    AuthRequired,
+    /// Authentication failed
+    AuthFailed,
+    /// Rate limited
+    RateLimited,
+    /// Internal server error
+    InternalError,
+    /// Invalid project path
+    InvalidPath,
+    /// Unsupported protocol version
+    UnsupportedVersion,
+    /// Input refused by the server's input policy
+    InputRejected,
+    /// No macro with the requested name
+    MacroNotFound,
+    /// No key sequence bound to the requested action
+    KeyNotBound,
+    /// The agent has no confirmation prompt waiting for an answer
+    NoPendingConfirmation,
+    /// Another client controls the agent's input
+    InputLocked,
+    /// The server was started with the required capability disabled
+    CapabilityDisabled,
+    /// The server or the client already runs as many agents as allowed
+    AgentLimitReached,
+    /// No recording with the requested ID
+    RecordingNotFound,
+    /// A git operation failed
+    GitFailed,
+    /// The configuration file could not be read or is invalid
+    ConfigInvalid,
+    /// The client's role does not allow the request
+    PermissionDenied,
+    /// The resume token is unknown or its grace period is over
+    SessionExpired,
+    /// No queued task has the ID; it may have started already
+    TaskNotFound,
+    /// The task queue is full
+    TaskQueueFull,
+    /// No session group has the name
+    GroupNotFound,
+    /// The project config defines no pipeline with the name
+    PipelineNotFound,
+    /// The server has no schedule with the name
+    ScheduleNotFound,
+}
+
+impl ErrorCode {
+    /// Whether the condition is temporary, so the request may succeed later
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::RateLimited
+                | ErrorCode::InternalError
+                | ErrorCode::InputLocked
+                | ErrorCode::AgentLimitReached
+                | ErrorCode::TaskQueueFull
+        )
+    }
+}
+
+impl ServerMessage {
+    /// Create a Welcome message
+    pub fn welcome() -> Self {
+        ServerMessage::Welcome {
+            version: PROTOCOL_VERSION,
+            min_version: Some(MIN_PROTOCOL_VERSION),
+            server_id: None,
+            auth_required: None,
+            capabilities: None,
+        }
+    }
+
+    /// Create a Welcome message indicating auth is required
+    pub fn welcome_auth_required() -> Self {
+        ServerMessage::Welcome {
+            version: PROTOCOL_VERSION,
+            min_version: Some(MIN_PROTOCOL_VERSION),
+            server_id: None,
+            auth_required: Some(true),
+            capabilities: None,
+        }
+    }
+
+    /// Create a Welcome message with server ID
+    pub fn welcome_with_id(server_id: impl Into<String>) -> Self {
+        ServerMessage::Welcome {
+            version: PROTOCOL_VERSION,
+            min_version: Some(MIN_PROTOCOL_VERSION),
+            server_id: Some(server_id.into()),
+            auth_required: None,
+            capabilities: None,
+        }
+    }
+
+    /// Advertise the server's enabled capabilities in a Welcome message
+    pub fn with_capabilities(mut self, enabled: Vec<Capability>) -> Self {
+        if let ServerMessage::Welcome {
+            ref mut capabilities,
+            ..
+        } = self
+        {
+            *capabilities = Some(enabled);
+        }
+        self
+    }
+
+    /// Create an AuthSuccess message for a client with the given role
+    pub fn auth_success(role: Role) -> Self {
+        ServerMessage::AuthSuccess { role: Some(role) }
+    }
+
+    /// Create a Pong message
+    pub fn pong(seq: u64) -> Self {
+        ServerMessage::Pong { seq }
+    }
+
+    /// Create an AgentSpawned message
+    pub fn agent_spawned(
+        agent_id: Uuid,
+        project_path: impl Into<String>,
+        cols: u16,
+        rows: u16,
+    ) -> Self {
+        ServerMessage::AgentSpawned {
+            agent_id,
+            project_path: project_path.into(),
+            cols,
+            rows,
+        }
+    }
+
+    /// Create an AgentOutput message
+    pub fn agent_output(agent_id: Uuid, data: impl Into<String>) -> Self {
+        ServerMessage::AgentOutput {
+            agent_id,
+            data: data.into(),
+            encoding: None,
+        }
+    }
+
+    /// Create a FileContents message, base64-encoding contents that aren't
+    /// UTF-8 text
+    pub fn file_contents(path: impl Into<String>, offset: u64, size: u64, data: Vec<u8>) -> Self {
+        use base64::Engine;
+
+        let (data, encoding) = match String::from_utf8(data) {
+            Ok(text) => (text, None),
+            Err(e) => (
+                base64::engine::general_purpose::STANDARD.encode(e.as_bytes()),
+                Some(OutputEncoding::Base64),
+            ),
+        };
+        ServerMessage::FileContents {
+            path: path.into(),
+            offset,
+            size,
+            data,
+            encoding,
+        }
+    }
+
+    /// Create an AgentOutput message carrying raw bytes, base64-encoded
+    pub fn agent_output_bytes(agent_id: Uuid, data: &[u8]) -> Self {
+        use base64::Engine;
+
+        ServerMessage::AgentOutput {
+            agent_id,
+            data: base64::engine::general_purpose::STANDARD.encode(data),
+            encoding: Some(OutputEncoding::Base64),
+        }
+    }
+
+    /// Create an AgentExited message
+    pub fn agent_exited(agent_id: Uuid, exit_code: Option<i32>) -> Self {
+        ServerMessage::AgentExited {
+            agent_id,
+            exit_code,
+            signal: None,
+            reason: None,
+            preset: None,
+            stats: None,
+        }
+    }
+
+    /// Create an AgentExited message with reason
+    pub fn agent_exited_with_reason(
+        agent_id: Uuid,
+        exit_code: Option<i32>,
+        reason: AgentExitReason,
+    ) -> Self {
+        ServerMessage::AgentExited {
+            agent_id,
+            exit_code,
+            signal: None,
+            reason: Some(reason),
+            preset: None,
+            stats: None,
+        }
+    }
+
+    /// Create an Error message
+    pub fn error(message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            message: message.into(),
+            code: None,
+            agent_id: None,
+            retryable: false,
+            details: BTreeMap::new(),
+            request_id: None,
+        }
+    }
+
+    /// Create an Error message with code
+    pub fn error_with_code(message: impl Into<String>, code: ErrorCode) -> Self {
+        ServerMessage::Error {
+            message: message.into(),
+            code: Some(code),
+            agent_id: None,
+            retryable: code.is_retryable(),
+            details: BTreeMap::new(),
+            request_id: None,
+        }
+    }
+
+    /// Create an Error message for a specific agent
+    pub fn agent_error(agent_id: Uuid, message: impl Into<String>, code: ErrorCode) -> Self {
+        ServerMessage::Error {
+            message: message.into(),
+            code: Some(code),
+            agent_id: Some(agent_id),
+            retryable: code.is_retryable(),
+            details: BTreeMap::new(),
+            request_id: None,
+        }
+    }
+
+    /// Add a detail to an Error message (other messages are returned unchanged)
+    pub fn with_detail(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Self {
+        if let ServerMessage::Error {
+            ref mut details, ..
+        } = self
+        {
+            details.insert(key.into(), value.into());
+        }
+        self
+    }
+
+    /// Tag an Error message with the request that caused it (other messages
+    /// are returned unchanged)
+    pub fn with_request_id(mut self, id: Option<String>) -> Self {
+        if let ServerMessage::Error {
+            ref mut request_id, ..
+        } = self
+        {
+            *request_id = id;
+        }
+        self
+    }
+}
+
+// ============================================================================
+// Conversion Traits
+// ============================================================================
+
+impl From<ProtocolError> for ServerMessage {
+    fn from(err: ProtocolError) -> Self {
+        let code = match &err {
+            ProtocolError::SerializationError(_) => ErrorCode::InvalidMessage,
+            ProtocolError::UnsupportedVersion(_) => ErrorCode::UnsupportedVersion,
+            ProtocolError::InvalidMessage(_) => ErrorCode::InvalidMessage,
+            ProtocolError::ValidationError(_) | ProtocolError::InvalidField { .. } => {
+                ErrorCode::InvalidMessage
+            }
+        };
+        let message = ServerMessage::error_with_code(err.to_string(), code);
+        err.details()
+            .into_iter()
+            .fold(message, |message, (key, value)| message.with_detail(key, value))
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // -------------------------------------------------------------------------
+    // Client Message Tests
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn test_ping_serialization() {
+        let msg = ClientMessage::ping(42);
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"ping\""));
+        assert!(json.contains("\"seq\":42"));
+
+        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_spawn_agent_serialization() {
+        let msg = ClientMessage::spawn_agent("/path/to/project");
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"spawn_agent\""));
+        assert!(json.contains("\"project_path\":\"/path/to/project\""));
+
+        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_spawn_agent_with_preset_serialization() {
+        let msg = ClientMessage::spawn_agent_with_preset("/path/to/project", "code-review");
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"preset\":\"code-review\""));
+
+        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_agent_input_serialization() {
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::agent_input(agent_id, "hello world");
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"agent_input\""));
+        assert!(json.contains("\"input\":\"hello world\""));
+
+        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_kill_agent_serialization() {
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::kill_agent(agent_id);
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"kill_agent\""));
+
+        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_resize_terminal_serialization() {
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::resize_terminal(agent_id, 120, 40);
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"resize_terminal\""));
+        assert!(json.contains("\"cols\":120"));
+        assert!(json.contains("\"rows\":40"));
+
+        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_list_agents_serialization() {
+        let msg = ClientMessage::list_agents();
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"list_agents"}"#);
+
+        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_client_message_agent_id() {
+        let agent_id = Uuid::new_v4();
+        assert_eq!(ClientMessage::agent_input(agent_id, "x").agent_id(), Some(agent_id));
+        assert_eq!(ClientMessage::kill_agent(agent_id).agent_id(), Some(agent_id));
+        assert_eq!(ClientMessage::list_agents().agent_id(), None);
+        assert_eq!(ClientMessage::ping(1).agent_id(), None);
+    }
+
+    // -------------------------------------------------------------------------
+    // Server Message Tests
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn test_welcome_serialization() {
+        let msg = ServerMessage::welcome();
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"welcome\""));
+        assert!(json.contains(&format!("\"version\":{}", PROTOCOL_VERSION)));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_welcome_capabilities() {
+        let msg = ServerMessage::welcome().with_capabilities(vec![Capability::Git]);
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""capabilities":["git"]"#));
+
+        assert_eq!("files".parse(), Ok(Capability::Files));
+        assert!("shell".parse::<Capability>().is_err());
+        assert_eq!(
+            ClientMessage::spawn_agent("/p").capability(),
+            Some(Capability::Spawn)
+        );
+        assert_eq!(ClientMessage::list_agents().capability(), None);
+    }
+
+    #[test]
+    fn test_pong_serialization() {
+        let msg = ServerMessage::pong(42);
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"pong\""));
+        assert!(json.contains("\"seq\":42"));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_agent_spawned_serialization() {
+        let agent_id = Uuid::new_v4();
+        let msg = ServerMessage::agent_spawned(agent_id, "/path/to/project", 80, 24);
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"agent_spawned\""));
+        assert!(json.contains("\"cols\":80"));
+        assert!(json.contains("\"rows\":24"));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_agent_output_serialization() {
+        let agent_id = Uuid::new_v4();
+        let msg = ServerMessage::agent_output(agent_id, "Hello, World!\n");
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"agent_output\""));
+        assert!(json.contains("\"data\":\"Hello, World!\\n\""));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+
+        let msg = ServerMessage::agent_output_bytes(agent_id, b"\xff\n");
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""data":"/wo=","encoding":"base64""#));
+    }
+
+    #[test]
+    fn test_agent_exited_serialization() {
+        let agent_id = Uuid::new_v4();
+        let msg = ServerMessage::agent_exited(agent_id, Some(0));
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"agent_exited\""));
+        assert!(json.contains("\"exit_code\":0"));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+
+        let msg = ServerMessage::AgentExited {
+            agent_id,
+            exit_code: None,
+            signal: Some(9),
+            reason: Some(AgentExitReason::TimedOut),
+            preset: Some("review".to_string()),
+            stats: Some(RunStats {
+                duration_ms: 1500,
+                bytes_in: 12,
+                bytes_out: 340,
+                redactions: 0,
+            }),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""signal":9,"reason":"timed_out""#));
+        assert!(json.contains(r#""stats":{"duration_ms":1500,"bytes_in":12,"bytes_out":340}"#));
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+        assert_eq!(AgentExitReason::from(ExitReason::Unknown), AgentExitReason::Lost);
+    }
+
+    #[test]
+    fn test_error_serialization() {
+        let msg = ServerMessage::error_with_code("Something went wrong", ErrorCode::InternalError);
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"error\""));
+        assert!(json.contains("\"message\":\"Something went wrong\""));
+        assert!(json.contains("\"code\":\"internal_error\""));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_agent_list_serialization() {
+        let agent_id = Uuid::new_v4();
+        let msg = ServerMessage::AgentList {
+            agents: vec![AgentInfo {
+                agent_id,
+                project_path: "/path/to/project".to_string(),
+                status: AgentState::Running,
+                cols: 80,
+                rows: 24,
+                origin: None,
+                macros: Vec::new(),
+                ..Default::default()
+            }],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"agent_list\""));
+        assert!(!json.contains("origin"));
+        assert!(json.contains("\"status\":\"running\""));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_agent_status_serialization() {
+        let msg = ServerMessage::AgentStatus {
+            info: Box::new(AgentInfo {
+                agent_id: Uuid::new_v4(),
+                project_path: "/path/to/project".to_string(),
+                status: AgentState::Idle,
+                cols: 80,
+                rows: 24,
+                preset: Some("review".to_string()),
+                spawned_at_ms: Some(1_700_000_000_000),
+                branch: Some("main".to_string()),
+                pid: Some(4242),
+                tags: vec!["review".to_string()],
+                phase: Some("Bash".to_string()),
+                ..Default::default()
+            }),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"agent_status""#));
+        assert!(json.contains(r#""status":"idle""#));
+        assert!(json.contains(r#""pid":4242"#));
+        assert!(json.contains(r#""tags":["review"]"#));
+        assert!(!json.contains("owner"));
+        assert!(!json.contains("group"));
+
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+
+        // Info from older servers has none of the optional fields
+        let info: AgentInfo = serde_json::from_str(&format!(
+            r#"{{"agent_id":"{}","project_path":"/p","status":"running","cols":80,"rows":24}}"#,
+            Uuid::new_v4()
+        ))
+        .unwrap();
+        assert!(info.tags.is_empty());
+        assert!(info.spawned_by.is_none() && info.phase.is_none());
+    }
+
+    // -------------------------------------------------------------------------
+    // Envelope Tests
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn test_client_envelope_serialization() {
+        let envelope = ClientEnvelope::new(ClientMessage::ping(1));
+        let json = envelope.to_json().unwrap();
+        assert!(json.contains(&format!("\"version\":{}", PROTOCOL_VERSION)));
+        assert!(json.contains("\"type\":\"ping\""));
+
+        let parsed = ClientEnvelope::from_json(&json).unwrap();
+        assert_eq!(parsed.version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_server_envelope_serialization() {
+        let envelope = ServerEnvelope::new(ServerMessage::pong(1));
+        let json = envelope.to_json().unwrap();
+        assert!(json.contains(&format!("\"version\":{}", PROTOCOL_VERSION)));
+        assert!(json.contains("\"type\":\"pong\""));
+
+        let parsed = ServerEnvelope::from_json(&json).unwrap();
+        assert_eq!(parsed.version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_envelope_rejects_newer_version() {
+        let json = format!(
+            r#"{{"version": {}, "type": "ping", "seq": 1}}"#,
+            PROTOCOL_VERSION + 1
+        );
+        let err = ClientEnvelope::from_json(&json).unwrap_err();
+        assert!(matches!(err, ProtocolError::UnsupportedVersion(v) if v == PROTOCOL_VERSION + 1));
+
+        let msg = ServerMessage::from(err);
+        assert!(matches!(
+            msg,
+            ServerMessage::Error {
+                code: Some(ErrorCode::UnsupportedVersion),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_envelope_version_range() {
+        // A newer client offering a range is met at the newest common version
+        let json = format!(
+            r#"{{"version": {0}, "min_version": 1, "max_version": {0}, "type": "ping", "seq": 1}}"#,
+            PROTOCOL_VERSION + 1
+        );
+        let envelope = ClientEnvelope::from_json(&json).unwrap();
+        assert_eq!(envelope.negotiated_version(), PROTOCOL_VERSION);
+
+        let old = ClientEnvelope::new(ClientMessage::ping(1)).with_version_range(1, 1);
+        assert_eq!(old.negotiated_version(), 1);
+        let unversioned = ClientEnvelope::from_json(r#"{"type": "ping", "seq": 1}"#).unwrap();
+        assert_eq!(unversioned.negotiated_version(), MIN_PROTOCOL_VERSION);
+
+        let too_new = ClientEnvelope::new(ClientMessage::ping(1))
+            .with_version_range(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2);
+        assert!(matches!(
+            too_new.validate(),
+            Err(ProtocolError::UnsupportedVersion(v)) if v == PROTOCOL_VERSION + 1
+        ));
+        let backwards = ClientEnvelope::new(ClientMessage::ping(1)).with_version_range(2, 1);
+        assert!(matches!(
+            backwards.validate(),
+            Err(ProtocolError::InvalidField { .. })
+        ));
+    }
+
+    #[test]
+    fn test_envelope_version_validation() {
+        let json = r#"{"version": 0, "type": "ping", "seq": 1}"#;
+        let result = ClientEnvelope::from_json(json);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("not supported"));
+    }
+
+    // -------------------------------------------------------------------------
+    // Validation Tests
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn test_spawn_agent_empty_path_validation() {
+        let msg = ClientMessage::SpawnAgent {
+            project_path: "".to_string(),
+            preset: None,
+            cols: None,
+            rows: None,
+            tags: Vec::new(),
+            group: None,
+            name: None,
+            mode: None,
+            prompt: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            report_echo: false,
+        };
+        let result = msg.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("cannot be empty"));
+    }
+
+    #[test]
+    fn test_spawn_agent_empty_preset_validation() {
+        let msg = ClientMessage::SpawnAgent {
+            project_path: "/valid/path".to_string(),
+            preset: Some("".to_string()),
+            cols: None,
+            rows: None,
+            tags: Vec::new(),
+            group: None,
+            name: None,
+            mode: None,
+            prompt: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            report_echo: false,
+        };
+        let result = msg.validate();
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("preset name cannot be empty"));
+    }
+
+    #[test]
+    fn test_spawn_agent_tags() {
+        // Older clients send neither field
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type":"spawn_agent","project_path":"/p"}"#).unwrap();
+        assert!(msg.validate().is_ok());
+
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"type":"spawn_agent","project_path":"/p","tags":["review"],"group":"team-a"}"#,
+        )
+        .unwrap();
+        assert!(msg.validate().is_ok());
+        let ClientMessage::SpawnAgent { tags, group, .. } = msg else {
+            panic!("Wrong message type");
+        };
+        assert_eq!(tags, vec!["review".to_string()]);
+        assert_eq!(group.as_deref(), Some("team-a"));
+
+        let msg = ClientMessage::SpawnAgent {
+            project_path: "/p".to_string(),
+            preset: None,
+            cols: None,
+            rows: None,
+            tags: vec![String::new()],
+            group: None,
+            name: None,
+            mode: None,
+            prompt: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            report_echo: false,
+        };
+        assert!(msg.validate().unwrap_err().to_string().contains("tags must be"));
+
+        let msg = ClientMessage::SpawnAgent {
+            project_path: "/p".to_string(),
+            preset: None,
+            cols: None,
+            rows: None,
+            tags: vec!["t".to_string(); MAX_TAGS + 1],
+            group: None,
+            name: None,
+            mode: None,
+            prompt: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            report_echo: false,
+        };
+        assert!(msg.validate().unwrap_err().to_string().contains("at most"));
+    }
+
+    #[test]
+    fn test_agent_names() {
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"type":"spawn_agent","project_path":"/p","name":"frontend-fixer"}"#,
+        )
+        .unwrap();
+        assert!(msg.validate().is_ok());
+
+        let agent_id = Uuid::new_v4();
+        let json = format!(r#"{{"type":"rename_agent","agent_id":"{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::RenameAgent {
+                agent_id,
+                name: None
+            }
+        );
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert!(msg.validate().is_ok());
+
+        for name in ["  ", "a\nb", &"x".repeat(MAX_AGENT_NAME_LENGTH + 1)] {
+            let msg = ClientMessage::RenameAgent {
+                agent_id,
+                name: Some(name.to_string()),
+            };
+            assert!(msg.validate().is_err(), "{:?} should be refused", name);
+        }
+    }
+
+    #[test]
+    fn test_agent_tags_and_filter() {
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"type":"list_agents","tag":"frontend","status":"idle","project_path":"/work/app"}"#,
+        )
+        .unwrap();
+        assert!(msg.validate().is_ok());
+        let ClientMessage::ListAgents { filter } = msg else {
+            panic!("expected ListAgents");
+        };
+
+        let mut info = AgentInfo {
+            project_path: "/work/app/web".to_string(),
+            status: AgentState::Idle,
+            tags: vec!["frontend".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&info));
+        assert!(AgentFilter::default().matches(&info));
+        info.status = AgentState::Busy;
+        assert!(!filter.matches(&info));
+        info.status = AgentState::Idle;
+        // A sibling directory sharing the prefix is not below the filter path
+        info.project_path = "/work/application".to_string();
+        assert!(!filter.matches(&info));
+
+        let agent_id = Uuid::new_v4();
+        let json = format!(
+            r#"{{"type":"tag_agent","agent_id":"{}","add":["review"]}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert!(msg.validate().is_ok());
+
+        let msg = ClientMessage::TagAgent {
+            agent_id,
+            add: vec![String::new()],
+            remove: Vec::new(),
+        };
+        assert!(msg.validate().is_err());
+    }
+
+    #[test]
+    fn test_resize_terminal_invalid_cols() {
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::resize_terminal(agent_id, 0, 24);
+        let result = msg.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("cols must be"));
+    }
+
+    #[test]
+    fn test_resize_terminal_invalid_rows() {
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::resize_terminal(agent_id, 80, 0);
+        let result = msg.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("rows must be"));
+    }
+
+    #[test]
+    fn test_resize_terminal_max_cols() {
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::resize_terminal(agent_id, MAX_TERMINAL_COLS + 1, 24);
+        let result = msg.validate();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_configured_terminal_limits() {
+        let agent_id = Uuid::new_v4();
+        let wide = ClientMessage::resize_terminal(agent_id, 1200, 50);
+        assert!(wide.validate().is_err());
+
+        let limits = TerminalLimits::default().with_max_size(2000, 300);
+        assert!(limits.validate().is_ok());
+        assert!(wide.validate_with_limits(&limits).is_ok());
+        let err = ClientMessage::resize_terminal(agent_id, 2001, 50)
+            .validate_with_limits(&limits)
+            .unwrap_err();
+        assert_eq!(err.details()["max"], 2000);
+
+        // Maximums are capped, and defaults must fit within them
+        assert!(limits
+            .with_max_size(TERMINAL_COLS_CEILING + 1, 300)
+            .validate()
+            .is_err());
+        assert!(limits.with_default_size(80, 301).validate().is_err());
+        assert!(TerminalLimits::default().with_max_size(60, 20).validate().is_err());
+    }
+
+    #[test]
+    fn test_kill_agent_invalid_signal() {
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::KillAgent {
+            agent_id,
+            signal: Some(100),
+        };
+        let result = msg.validate();
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("not a valid Unix signal"));
+    }
+
+    #[test]
+    fn test_pause_and_resume_agent() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(r#"{{"type":"pause_agent","agent_id":"{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg, ClientMessage::PauseAgent { agent_id });
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert!(msg.validate().is_ok());
+
+        let json = format!(r#"{{"type":"resume_agent","agent_id":"{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg, ClientMessage::ResumeAgent { agent_id });
+    }
+
+    #[test]
+    fn test_restart_agent() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(r#"{{"type":"restart_agent","agent_id":"{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::RestartAgent {
+                agent_id,
+                preserve_size: false
+            }
+        );
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert_eq!(msg.capability(), Some(Capability::Spawn));
+    }
+
+    #[test]
+    fn test_agent_input_max_length() {
+        let agent_id = Uuid::new_v4();
+        let large_input = "x".repeat(MAX_INPUT_LENGTH + 1);
+        let msg = ClientMessage::agent_input(agent_id, large_input);
+        let result = msg.validate();
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("exceeds maximum length"));
+    }
+
+    #[test]
+    fn test_valid_messages_pass_validation() {
+        let agent_id = Uuid::new_v4();
+
+        // All these should validate successfully
+        assert!(ClientMessage::ping(1).validate().is_ok());
+        assert!(ClientMessage::spawn_agent("/valid/path").validate().is_ok());
+        assert!(ClientMessage::agent_input(agent_id, "hello")
+            .validate()
+            .is_ok());
+        assert!(ClientMessage::kill_agent(agent_id).validate().is_ok());
+        assert!(ClientMessage::resize_terminal(agent_id, 80, 24)
+            .validate()
+            .is_ok());
+        assert!(ClientMessage::list_agents().validate().is_ok());
+    }
+
+    // -------------------------------------------------------------------------
+    // Error Conversion Tests
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn test_protocol_error_to_server_message() {
+        let err = ProtocolError::ValidationError("test error".to_string());
+        let msg: ServerMessage = err.into();
+
+        match msg {
+            ServerMessage::Error { message, code, .. } => {
+                assert!(message.contains("test error"));
+                assert_eq!(code, Some(ErrorCode::InvalidMessage));
+            }
+            _ => panic!("Expected Error message"),
+        }
+    }
+
+    #[test]
+    fn test_validation_error_details() {
+        let msg: ServerMessage = ClientMessage::resize_terminal(Uuid::new_v4(), 0, 24)
+            .validate()
+            .unwrap_err()
+            .into();
+        let json: serde_json::Value = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["code"], "invalid_message");
+        assert_eq!(json["retryable"], false);
+        assert_eq!(json["details"]["field"], "cols");
+        assert_eq!(json["details"]["max"], MAX_TERMINAL_COLS);
+
+        let msg = ServerMessage::error_with_code("Slow down", ErrorCode::RateLimited);
+        assert!(matches!(msg, ServerMessage::Error { retryable: true, .. }));
+    }
+
+    #[test]
+    fn test_request_id() {
+        let json = r#"{"type": "ping", "seq": 1, "request_id": "req-7"}"#;
+        let envelope = ClientEnvelope::from_json(json).unwrap();
+        assert_eq!(envelope.request_id.as_deref(), Some("req-7"));
+
+        // Recoverable even when the message itself is invalid
+        let json = r#"{"type": "spawn_agent", "project_path": "", "request_id": "req-8"}"#;
+        assert!(ClientEnvelope::from_json(json).is_err());
+        assert_eq!(ClientEnvelope::peek_request_id(json).as_deref(), Some("req-8"));
+
+        let error = ServerMessage::error("failed").with_request_id(Some("req-8".to_string()));
+        let json = serde_json::to_string(&error).unwrap();
+        assert!(json.contains(r#""request_id":"req-8""#));
+        // Only errors are tagged
+        assert_eq!(
+            ServerMessage::pong(1).with_request_id(Some("req-9".to_string())),
+            ServerMessage::pong(1)
+        );
+    }
+
+    // -------------------------------------------------------------------------
+    // JSON Compatibility Tests
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn test_parse_run_macro() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(
+            r#"{{"type": "run_macro", "agent_id": "{}", "name": "run-tests"}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert!(msg.validate().is_ok());
+
+        let msg = ClientMessage::RunMacro {
+            agent_id,
+            name: String::new(),
+        };
+        assert!(msg.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_send_key() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(
+            r#"{{"type": "send_key", "agent_id": "{}", "action": "interrupt"}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert!(msg.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse_agent_key() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(
+            r#"{{"type": "agent_key", "agent_id": "{}", "key": "ctrl+c"}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert!(msg.validate().is_ok());
+
+        let msg = ClientMessage::AgentKey {
+            agent_id,
+            key: "hyper+x".to_string(),
+        };
+        assert!(msg.validate().is_err());
+
+        let json = format!(
+            r#"{{"type": "agent_input", "agent_id": "{}", "input": "hi"}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert!(matches!(msg, ClientMessage::AgentInput { paste: false, .. }));
+    }
+
+    #[test]
+    fn test_parse_echo_messages() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(
+            r#"{{"type": "set_raw_mode", "agent_id": "{}", "raw": true}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert!(matches!(msg, ClientMessage::SetRawMode { raw: true, .. }));
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert_eq!(msg.required_role(), Role::Operator);
+
+        let msg = ClientMessage::GetEchoState { agent_id };
+        assert_eq!(msg.required_role(), Role::Observer);
+
+        let state = ServerMessage::EchoState {
+            agent_id,
+            echo: false,
+            raw: true,
+        };
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["type"], "echo_state");
+        assert_eq!(json["raw"], true);
+    }
+
+    #[test]
+    fn test_group_messages() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type": "join_group", "group": "frontend"}"#).unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.agent_id(), None);
+        assert_eq!(msg.required_role(), Role::Observer);
+        let msg = ClientMessage::CreateGroup {
+            group: String::new(),
+        };
+        assert!(msg.validate().is_err());
+        assert_eq!(msg.required_role(), Role::Operator);
+
+        let list = ServerMessage::GroupList {
+            groups: vec![GroupInfo {
+                group: "frontend".to_string(),
+                agent_ids: Vec::new(),
+                members: vec!["headset".to_string()],
+            }],
+        };
+        let json = serde_json::to_value(&list).unwrap();
+        assert_eq!(json["type"], "group_list");
+        assert_eq!(json["groups"][0]["members"][0], "headset");
+    }
+
+    #[test]
+    fn test_broadcast_input_validation() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type": "broadcast_input", "group": "a", "input": "ls\r"}"#)
+                .unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.agent_id(), None);
+
+        let none = ClientMessage::BroadcastInput {
+            agent_ids: Vec::new(),
+            group: None,
+            input: "ls\r".to_string(),
+            paste: false,
+        };
+        assert!(none.validate().is_err());
+        let many = ClientMessage::BroadcastInput {
+            agent_ids: vec![Uuid::new_v4(); MAX_BROADCAST_AGENTS + 1],
+            group: None,
+            input: "ls\r".to_string(),
+            paste: false,
+        };
+        assert!(many.validate().is_err());
+
+        let agent_id = Uuid::new_v4();
+        let error = ServerMessage::agent_error(agent_id, "Agent not found", ErrorCode::AgentNotFound);
+        let result = BroadcastResult::from_response(agent_id, Some(&error));
+        assert!(!result.ok);
+        assert_eq!(result.code, Some(ErrorCode::AgentNotFound));
+        assert!(BroadcastResult::from_response(agent_id, None).ok);
+    }
+
+    #[test]
+    fn test_get_input_history_limit() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(r#"{{"type": "get_input_history", "agent_id": "{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert!(matches!(msg, ClientMessage::GetInputHistory { limit: None, .. }));
+        assert!(msg.validate().is_ok());
+
+        let msg = ClientMessage::GetInputHistory {
+            agent_id,
+            limit: Some(MAX_HISTORY_LIMIT + 1),
+        };
+        assert!(msg.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_agent_input_raw() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(
+            r#"{{"type": "agent_input_raw", "agent_id": "{}", "data": "/xsb"}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert!(msg.validate().is_ok());
+        match msg {
+            ClientMessage::AgentInputRaw { ref data, .. } => {
+                assert_eq!(decode_raw_input(data).unwrap(), vec![0xff, 0x1b, 0x1b]);
+            }
+            _ => panic!("Expected AgentInputRaw"),
+        }
+
+        let msg = ClientMessage::AgentInputRaw {
+            agent_id,
+            data: "not base64!".to_string(),
+        };
+        assert!(msg.validate().is_err());
+    }
+
+    #[test]
+    fn test_confirmation_messages() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(
+            r#"{{"type": "confirmation_reply", "agent_id": "{}", "option": 1}}"#,
+            agent_id
+        );
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert!(matches!(msg, ClientMessage::ConfirmationReply { option: 1, .. }));
+        assert_eq!(msg.agent_id(), Some(agent_id));
+
+        let msg = ServerMessage::ConfirmationRequest {
+            agent_id,
+            question: "Overwrite?".to_string(),
+            options: vec!["Yes".to_string(), "No".to_string()],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"confirmation_request""#));
+        assert!(json.contains(r#""options":["Yes","No"]"#));
+    }
+
+    #[test]
+    fn test_stream_json_messages() {
+        let json = r#"{"type": "spawn_agent", "project_path": "/tmp", "mode": "stream_json",
+            "prompt": "summarize the diff"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::SpawnAgent {
+                mode: Some(AgentMode::StreamJson),
+                prompt: Some(_),
+                ..
+            }
+        ));
+        assert!(msg.validate().is_ok());
+
+        let msg = ServerMessage::ToolUse {
+            agent_id: Uuid::new_v4(),
+            tool_use_id: "toolu_1".to_string(),
+            name: "Bash".to_string(),
+            input: serde_json::json!({"command": "cargo test"}),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"tool_use""#));
+        assert!(json.contains(r#""input":{"command":"cargo test"}"#));
+    }
+
+    #[test]
+    fn test_approval_messages() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(r#"{{"type": "approve_action", "agent_id": "{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert!(matches!(msg, ClientMessage::ApproveAction { always: false, .. }));
+        assert_eq!(msg.required_role(), Role::Operator);
+
+        let json = format!(r#"{{"type": "deny_action", "agent_id": "{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg.agent_id(), Some(agent_id));
+
+        let msg = ServerMessage::ApprovalRequested {
+            agent_id,
+            tool: "Bash command".to_string(),
+            detail: "cargo test".to_string(),
+            question: "Do you want to proceed?".to_string(),
+            options: vec!["Yes".to_string(), "No".to_string()],
+            can_always: false,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"approval_requested""#));
+        assert!(json.contains(r#""tool":"Bash command""#));
+        assert!(!json.contains("can_always"));
+    }
+
+    #[test]
+    fn test_task_messages() {
+        let json = r#"{"type":"enqueue_task","project_path":"/srv/app","prompt":"fix the build"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.project_path(), Some("/srv/app"));
+        assert_eq!(msg.capability(), Some(Capability::Spawn));
+
+        let json = r#"{"type":"enqueue_task","project_path":"/srv/app","prompt":" "}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(msg.validate().is_err());
+        let msg: ClientMessage = serde_json::from_str(r#"{"type":"list_tasks"}"#).unwrap();
+        assert_eq!(msg.required_role(), Role::Observer);
+
+        let msg = ServerMessage::TaskCompleted {
+            task_id: Uuid::new_v4(),
+            agent_id: None,
+            success: false,
+            exit_code: None,
+            output: String::new(),
+            error: Some("Project not found".to_string()),
+            total_cost_usd: None,
+            duration_ms: 0,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"task_completed""#));
+        assert!(!json.contains("agent_id"));
+    }
+
+    #[test]
+    fn test_spawn_validation_messages() {
+        let json = r#"{"type":"validate_spawn","project_path":"/srv/app","preset":""}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(msg.validate().is_err());
+        assert_eq!(msg.capability(), Some(Capability::Spawn));
+
+        let msg = ServerMessage::SpawnValidation {
+            project_path: "/srv/app".to_string(),
+            preset: None,
+            ok: false,
+            checks: vec![SpawnCheck {
+                check: SpawnCheckKind::Command,
+                status: CheckStatus::Failed,
+                message: "claude was not found on the PATH".to_string(),
+            }],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"spawn_validation""#));
+        assert!(json.contains(r#""check":"command","status":"failed""#));
+    }
+
+    #[test]
+    fn test_preset_messages() {
+        let json = r#"{"type":"list_presets","project_path":"/srv/app"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.project_path(), Some("/srv/app"));
+        assert_eq!(msg.required_role(), Role::Observer);
+
+        let msg = ServerMessage::PresetList {
+            project_path: "/srv/app".to_string(),
+            presets: vec![PresetInfo {
+                name: "reviewer".to_string(),
+                source: PresetSource::Global,
+                initial_prompt: None,
+                host: None,
+            }],
+            default_preset: None,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"preset_list""#));
+        assert!(json.contains(r#"{"name":"reviewer","source":"global"}"#));
+    }
+
+    #[test]
+    fn test_command_finished() {
+        let msg = ServerMessage::CommandFinished {
+            agent_id: Uuid::new_v4(),
+            exit_code: Some(1),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"command_finished""#));
+        assert!(json.contains(r#""exit_code":1"#));
+    }
+
+    #[test]
+    fn test_set_stream_mode() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type":"set_stream_mode","mode":"summary"}"#).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::SetStreamMode {
+                mode: StreamMode::Summary
+            }
+        );
+        assert!(msg.validate().is_ok());
+        assert!(serde_json::from_str::<ClientMessage>(
+            r#"{"type":"set_stream_mode","mode":"speech"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_claim_session() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type":"claim_session","session_token":"tablet-1"}"#)
+                .unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.agent_id(), None);
+
+        let empty = ClientMessage::ClaimSession {
+            session_token: String::new(),
+        };
+        assert!(empty.validate().is_err());
+        let long = ClientMessage::ClaimSession {
+            session_token: "x".repeat(MAX_SESSION_TOKEN_LENGTH + 1),
+        };
+        assert!(long.validate().is_err());
+
+        let agent_id = Uuid::new_v4();
+        let msg = ClientMessage::AttachAgent { agent_id };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"attach_agent""#));
+        assert_eq!(msg.agent_id(), Some(agent_id));
+    }
+
+    #[test]
+    fn test_resume_session() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type":"resume_session","resume_token":"abc"}"#).unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.required_role(), Role::Observer);
+        let empty = ClientMessage::ResumeSession {
+            resume_token: String::new(),
+        };
+        assert!(empty.validate().is_err());
+
+        let resumed = ServerMessage::SessionResumed {
+            agent_ids: vec![],
+            controlled: vec![],
+            replayed_bytes: 0,
+            truncated: false,
+        };
+        let json = serde_json::to_string(&resumed).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"session_resumed","agent_ids":[],"controlled":[],"replayed_bytes":0}"#
+        );
+    }
+
+    #[test]
+    fn test_worktree_messages() {
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"type":"create_worktree","project_path":"/p","branch":"fix","create_branch":true}"#,
+        )
+        .unwrap();
+        assert!(msg.validate().is_ok());
+        assert!(matches!(
+            msg,
+            ClientMessage::CreateWorktree { create_branch: true, path: None, .. }
+        ));
+
+        let msg = ClientMessage::CreateWorktree {
+            project_path: "/p".to_string(),
+            branch: "--orphan".to_string(),
+            path: None,
+            create_branch: false,
+        };
+        assert!(msg.validate().is_err());
+        let msg = ClientMessage::RemoveWorktree {
+            project_path: "/p".to_string(),
+            path: String::new(),
+            force: false,
+        };
+        assert!(msg.validate().is_err());
+
+        let msg = ServerMessage::WorktreeCreated {
+            worktree: WorktreeInfo {
+                path: "/p-worktrees/agent-fix".to_string(),
+                branch: Some("agent/fix".to_string()),
+                is_main: false,
+            },
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"worktree_created""#));
+        assert!(json.contains(r#""is_main":false"#));
+    }
+
+    #[test]
+    fn test_get_diff() {
+        let agent_id = Uuid::new_v4();
+        let json = format!(r#"{{"type":"get_diff","agent_id":"{}"}}"#, agent_id);
+        let msg: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.agent_id(), Some(agent_id));
+        assert!(matches!(
+            msg,
+            ClientMessage::GetDiff { path: None, staged: false, .. }
+        ));
+
+        let msg = ClientMessage::GetDiff {
+            agent_id,
+            path: Some(String::new()),
+            staged: true,
+        };
+        assert!(msg.validate().is_err());
+    }
+
+    #[test]
+    fn test_commit_messages() {
+        let agent_id = Uuid::new_v4();
+        let stage = ClientMessage::StageFiles {
+            agent_id,
+            paths: vec![".".to_string()],
+        };
+        assert!(stage.validate().is_ok());
+        assert_eq!(stage.agent_id(), Some(agent_id));
+        let nothing = ClientMessage::UnstageFiles {
+            agent_id,
+            paths: Vec::new(),
+        };
+        assert!(nothing.validate().is_err());
+
+        let commit = |message: &str, name: Option<&str>, email: Option<&str>| {
+            ClientMessage::CommitChanges {
+                agent_id,
+                message: message.to_string(),
+                author_name: name.map(String::from),
+                author_email: email.map(String::from),
+            }
+        };
+        assert!(commit("Fix parser", None, None).validate().is_ok());
+        assert!(commit("Fix", Some("Agent"), Some("a@example.com")).validate().is_ok());
+        assert!(commit("  ", None, None).validate().is_err());
+        assert!(commit("Fix", Some("Agent"), None).validate().is_err());
+    }
+
+    #[test]
+    fn test_branch_messages() {
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"type":"create_branch","project_path":"/p","branch":"agent/x","start_point":"main~2"}"#,
+        )
+        .unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.agent_id(), None);
+
+        let msg = ClientMessage::CreateBranch {
+            project_path: "/p".to_string(),
+            branch: "x".to_string(),
+            start_point: Some("--all".to_string()),
+        };
+        assert!(msg.validate().is_err());
+        let msg = ClientMessage::DeleteBranch {
+            project_path: "/p".to_string(),
+            branch: "x".repeat(MAX_BRANCH_NAME_LENGTH + 1),
+            force: true,
+        };
+        assert!(msg.validate().is_err());
+
+        let json = serde_json::to_string(&ClientMessage::ListBranches {
+            project_path: "/p".to_string(),
+            remote: false,
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"type":"list_branches","project_path":"/p"}"#);
+    }
+
+    #[test]
+    fn test_push_and_pull_messages() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type":"push_branch","project_path":"/p"}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::PushBranch { remote: None, branch: None, force: false, .. }
+        ));
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.capability(), Some(Capability::Git));
+
+        let msg = ClientMessage::PullBranch {
+            project_path: "/p".to_string(),
+            remote: Some("--upload-pack=evil".to_string()),
+            branch: None,
+            token_secret: None,
+        };
+        assert!(msg.validate().is_err());
+
+        let json = serde_json::to_string(&ServerMessage::TransferProgress {
+            project_path: "/p".to_string(),
+            operation: TransferOperation::Push,
+            progress: TransferProgress {
+                objects: 3,
+                total_objects: 10,
+                bytes: 512,
+            },
+        })
+        .unwrap();
+        assert!(json.contains(r#""operation":"push""#));
+        assert!(json.contains(r#""progress":{"objects":3,"total_objects":10,"bytes":512}"#));
+    }
+
+    #[test]
+    fn test_replay_recording() {
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"type":"replay_recording","project_path":"/p","recording_id":"1700000000-ab12cd34"}"#,
+        )
+        .unwrap();
+        assert!(msg.validate().is_ok());
+        assert_eq!(msg.agent_id(), None);
+
+        for speed in [0.0, MAX_REPLAY_SPEED * 2.0, f64::NAN] {
+            let msg = ClientMessage::ReplayRecording {
+                project_path: "/p".to_string(),
+                recording_id: "demo".to_string(),
+                speed: Some(speed),
+            };
+            assert!(msg.validate().is_err());
+        }
+
+        let msg = ServerMessage::RecordingStopped {
+            agent_id: Uuid::new_v4(),
+            recording_id: "demo".to_string(),
+            duration_ms: 1500,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"recording_stopped""#));
+        assert!(json.contains(r#""duration_ms":1500"#));
+    }
+
+    #[test]
+    fn test_agent_state_changed() {
+        let msg = ServerMessage::AgentStateChanged {
+            agent_id: Uuid::new_v4(),
+            old_state: AgentState::Busy,
+            new_state: AgentState::WaitingForInput,
+            timestamp_ms: 1_700_000_000_000,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""type":"agent_state_changed""#));
+        assert!(json.contains(r#""timestamp_ms":1700000000000"#));
+        assert!(json.contains(r#""old_state":"busy""#));
+        assert!(json.contains(r#""new_state":"waiting_for_input""#));
+        assert_eq!(AgentState::WaitingForInput.as_str(), "waiting_for_input");
+
+        assert!(AgentState::Paused.is_alive());
+        assert!(!AgentState::Starting.is_alive());
+        assert!(!AgentState::Stopping.is_alive());
+    }
+
+    #[test]
+    fn test_validate_input_chunk() {
+        let chunk = |part, of| ClientMessage::AgentInputChunk {
+            agent_id: Uuid::new_v4(),
+            part,
+            of,
+            data: "x".to_string(),
+        };
+        assert!(chunk(0, 1).validate().is_ok());
+        assert!(chunk(2, 3).validate().is_ok());
+        assert!(chunk(3, 3).validate().is_err());
+        assert!(chunk(0, 0).validate().is_err());
+        assert!(chunk(0, MAX_PASTE_PARTS + 1).validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_adopt_session() {
+        let json = r#"{"type": "adopt_session", "multiplexer": "tmux", "target": "work:1.0"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ClientMessage::AdoptSession {
+                multiplexer,
+                ref target,
+                ref host,
+                ..
+            } => {
+                assert_eq!(multiplexer, Multiplexer::Tmux);
+                assert_eq!(target, "work:1.0");
+                assert!(host.is_none());
+            }
+            _ => panic!("Expected AdoptSession"),
+        }
+        assert!(msg.validate().is_ok());
+        assert!(msg.agent_id().is_none());
+    }
+
+    #[test]
+    fn test_attach_external_targets() {
+        let attach = |target: &str| ClientMessage::AttachExternal {
+            target: target.to_string(),
+        };
+        for target in ["tmux:work:1.0", "screen:agent", "pid:4242", "4242"] {
+            assert!(attach(target).validate().is_ok(), "{}", target);
+        }
+        for target in ["", "work", "tmux:", "tmux:-L", "pid:abc", "zellij:main"] {
+            assert!(attach(target).validate().is_err(), "{}", target);
+        }
+        assert_eq!(attach("4242").agent_id(), None);
+        assert_eq!(attach("4242").capability(), Some(Capability::Spawn));
+    }
+
+    #[test]
+    fn test_adopt_session_rejects_option_like_arguments() {
+        let msg = ClientMessage::AdoptSession {
+            multiplexer: Multiplexer::Screen,
+            target: "-X".to_string(),
+            host: None,
+            project_path: None,
+            cols: None,
+            rows: None,
+        };
+        assert!(msg.validate().is_err());
+
+        let msg = ClientMessage::AdoptSession {
+            multiplexer: Multiplexer::Tmux,
+            target: "work".to_string(),
+            host: Some("-oProxyCommand=sh".to_string()),
+            project_path: None,
+            cols: None,
+            rows: None,
+        };
+        assert!(msg.validate().is_err());
+    }
+
+    #[test]
+    fn test_spawn_agent_env() {
+        let spawn = |env: &str| {
+            let json = format!(r#"{{"type":"spawn_agent","project_path":"/srv","env":{}}}"#, env);
+            serde_json::from_str::<ClientMessage>(&json).unwrap()
+        };
+        assert!(spawn(r#"{"ANTHROPIC_MODEL":"opus","_X1":""}"#).validate().is_ok());
+        for env in [r#"{"1X":"a"}"#, r#"{"A-B":"a"}"#, r#"{"":"a"}"#, r#"{"A":"a\u0000b"}"#] {
+            assert!(spawn(env).validate().is_err(), "{} was accepted", env);
+        }
+    }
+
+    #[test]
+    fn test_spawn_agent_cwd() {
+        let spawn = |cwd: &str| {
+            let json = format!(
+                r#"{{"type":"spawn_agent","project_path":"/srv/app","cwd":"{}"}}"#,
+                cwd
+            );
+            serde_json::from_str::<ClientMessage>(&json).unwrap()
+        };
+        assert!(spawn("frontend").validate().is_ok());
+        assert!(spawn("./packages/web").validate().is_ok());
+        for cwd in ["", "/etc", "../other", "frontend/../../other"] {
+            assert!(spawn(cwd).validate().is_err(), "{} was accepted", cwd);
+        }
+    }
+
+    #[test]
+    fn test_parse_minimal_spawn_agent() {
+        // Test that we can parse a minimal spawn_agent without optional fields
+        let json = r#"{"type": "spawn_agent", "project_path": "/test"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ClientMessage::SpawnAgent {
+                project_path,
+                preset,
+                cols,
+                rows,
+                tags,
+                group,
+                name,
+                mode,
+                prompt,
+                env,
+                cwd,
+                report_echo,
+            } => {
+                assert_eq!(project_path, "/test");
+                assert!(preset.is_none());
+                assert!(cols.is_none());
+                assert!(rows.is_none());
+                assert!(tags.is_empty());
+                assert!(group.is_none());
+                assert!(name.is_none());
+                assert!(mode.is_none());
+                assert!(prompt.is_none());
+                assert!(env.is_empty());
+                assert!(cwd.is_none());
+                assert!(!report_echo);
+            }
+            _ => panic!("Expected SpawnAgent"),
+        }
+    }
+
+    #[test]
+    fn test_parse_full_spawn_agent() {
+        // Test that we can parse a full spawn_agent with all fields
+        let json = r#"{"type": "spawn_agent", "project_path": "/test", "preset": "dev", "cols": 120, "rows": 40}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ClientMessage::SpawnAgent {
+                project_path,
+                preset,
+                cols,
+                rows,
+                ..
+            } => {
+                assert_eq!(project_path, "/test");
+                assert_eq!(preset, Some("dev".to_string()));
+                assert_eq!(cols, Some(120));
+                assert_eq!(rows, Some(40));
+            }
+            _ => panic!("Expected SpawnAgent"),
+        }
+    }
+}
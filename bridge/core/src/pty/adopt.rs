@@ -0,0 +1,177 @@
+//! Adopting existing terminal sessions
+//!
+//! Brings sessions that already run in a terminal multiplexer (tmux or GNU
+//! screen) into the bridge without restarting them. The bridge runs a regular
+//! multiplexer client in its PTY, so the adopted session's screen, input and
+//! resizes flow through the normal agent protocol. Killing the adopted agent
+//! only stops that client; the session itself keeps running.
+//!
+//! A PTY owned by another process can't be read from outside, so attaching to
+//! a process by PID works when the process runs inside tmux or screen: the
+//! multiplexer it inherited from its environment is adopted instead.
+
+use serde::{Deserialize, Serialize};
+
+use super::{PtyError, PtyResult};
+
+/// Terminal multiplexer hosting an existing session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Multiplexer {
+    /// tmux; the target is a session, window or pane (e.g. "work:1.0")
+    Tmux,
+    /// GNU screen; the target is a session name or PID
+    Screen,
+}
+
+/// An existing multiplexer session to attach to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalSession {
+    /// Multiplexer hosting the session
+    pub multiplexer: Multiplexer,
+    /// Session target
+    pub target: String,
+}
+
+impl ExternalSession {
+    /// Create a reference to an existing session
+    pub fn new(multiplexer: Multiplexer, target: impl Into<String>) -> Self {
+        Self {
+            multiplexer,
+            target: target.into(),
+        }
+    }
+
+    /// Resolve an `attach_external` target: `tmux:<target>`, `screen:<target>`,
+    /// or a process as `pid:<pid>` or a bare PID
+    pub fn resolve(target: &str) -> PtyResult<Self> {
+        if let Ok(pid) = target.parse() {
+            return Self::for_process(pid);
+        }
+        match target.split_once(':') {
+            Some(("tmux", target)) => Ok(Self::new(Multiplexer::Tmux, target)),
+            Some(("screen", target)) => Ok(Self::new(Multiplexer::Screen, target)),
+            Some(("pid", pid)) => match pid.parse() {
+                Ok(pid) => Self::for_process(pid),
+                Err(_) => Err(PtyError::SystemError(format!("Invalid PID: {}", pid))),
+            },
+            _ => Err(PtyError::SystemError(format!(
+                "Unknown target {}; expected tmux:, screen: or pid:",
+                target
+            ))),
+        }
+    }
+
+    /// The multiplexer session a process runs in, from its environment
+    ///
+    /// Only works where `/proc` exposes the environment of the bridge user's
+    /// processes (Linux).
+    pub fn for_process(pid: u32) -> PtyResult<Self> {
+        let environ = std::fs::read(format!("/proc/{}/environ", pid)).map_err(|e| {
+            PtyError::SystemError(format!("Cannot inspect process {}: {}", pid, e))
+        })?;
+        Self::from_environ(&environ).ok_or_else(|| {
+            PtyError::SystemError(format!(
+                "Process {} is not running inside tmux or screen",
+                pid
+            ))
+        })
+    }
+
+    /// The session named by `TMUX_PANE` or `STY` in a NUL-separated environment
+    fn from_environ(environ: &[u8]) -> Option<Self> {
+        let var = |name: &str| {
+            let prefix = format!("{}=", name);
+            environ
+                .split(|&b| b == 0)
+                .find_map(|entry| entry.strip_prefix(prefix.as_bytes()))
+                .and_then(|value| std::str::from_utf8(value).ok())
+                .filter(|value| !value.is_empty())
+        };
+        // A process in tmux inside screen belongs to the tmux pane
+        if let Some(pane) = var("TMUX_PANE") {
+            return Some(Self::new(Multiplexer::Tmux, pane));
+        }
+        var("STY").map(|sty| Self::new(Multiplexer::Screen, sty))
+    }
+
+    /// Build the command that attaches a client to the session
+    ///
+    /// # Returns
+    /// The program to execute and its arguments
+    pub fn command(&self) -> (String, Vec<String>) {
+        match self.multiplexer {
+            Multiplexer::Tmux => (
+                "tmux".to_string(),
+                vec![
+                    "attach-session".to_string(),
+                    "-t".to_string(),
+                    self.target.clone(),
+                ],
+            ),
+            // -x attaches without detaching other displays
+            Multiplexer::Screen => (
+                "screen".to_string(),
+                vec!["-x".to_string(), self.target.clone()],
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tmux_command() {
+        let session = ExternalSession::new(Multiplexer::Tmux, "work:1.0");
+        let (command, args) = session.command();
+        assert_eq!(command, "tmux");
+        assert_eq!(args, vec!["attach-session", "-t", "work:1.0"]);
+    }
+
+    #[test]
+    fn test_screen_command() {
+        let session = ExternalSession::new(Multiplexer::Screen, "agent");
+        let (command, args) = session.command();
+        assert_eq!(command, "screen");
+        assert_eq!(args, vec!["-x", "agent"]);
+    }
+
+    #[test]
+    fn test_resolve_targets() {
+        assert_eq!(
+            ExternalSession::resolve("tmux:work:1.0").unwrap(),
+            ExternalSession::new(Multiplexer::Tmux, "work:1.0")
+        );
+        assert_eq!(
+            ExternalSession::resolve("screen:agent").unwrap(),
+            ExternalSession::new(Multiplexer::Screen, "agent")
+        );
+        assert!(ExternalSession::resolve("work").is_err());
+        assert!(ExternalSession::resolve("pid:abc").is_err());
+        assert!(ExternalSession::resolve(&u32::MAX.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_session_from_environ() {
+        let tmux = b"HOME=/home/me\0TMUX=/tmp/tmux-1000/default,42,0\0TMUX_PANE=%3\0";
+        assert_eq!(
+            ExternalSession::from_environ(tmux),
+            Some(ExternalSession::new(Multiplexer::Tmux, "%3"))
+        );
+        let screen = b"STY=1234.agent\0TERM=screen\0";
+        assert_eq!(
+            ExternalSession::from_environ(screen),
+            Some(ExternalSession::new(Multiplexer::Screen, "1234.agent"))
+        );
+        assert_eq!(ExternalSession::from_environ(b"HOME=/home/me\0STY=\0"), None);
+    }
+
+    #[test]
+    fn test_multiplexer_serde() {
+        assert_eq!(serde_json::to_string(&Multiplexer::Tmux).unwrap(), "\"tmux\"");
+        let parsed: Multiplexer = serde_json::from_str("\"screen\"").unwrap();
+        assert_eq!(parsed, Multiplexer::Screen);
+    }
+}
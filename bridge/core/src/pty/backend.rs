@@ -0,0 +1,618 @@
+//! PTY backends
+//!
+//! Agent sessions start their processes through a [`PtyBackend`] rather than
+//! calling portable-pty directly. [`NativePtyBackend`] runs real processes;
+//! [`ScriptedPtyBackend`] simulates them in memory (echoing input, replaying
+//! scripted output and exiting with programmed codes), so the agent layer can
+//! be exercised without a TTY or the `claude` binary.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{
+    EchoState, ExitReason, ProcessExit, PtyError, PtyOutput, PtyProcess, PtyResult, TerminalSize,
+    SIGHUP, SIGINT, SIGKILL, SIGTERM,
+};
+
+/// A running process attached to a terminal
+#[async_trait]
+pub trait PtyHandle: Send + Sync {
+    /// Get the process ID
+    fn id(&self) -> Uuid;
+
+    /// OS process ID, if the process runs on this host
+    fn pid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Get the current terminal size
+    async fn size(&self) -> TerminalSize;
+
+    /// Check if the process has exited
+    async fn has_exited(&self) -> bool;
+
+    /// Get exit information if the process has exited
+    async fn exit_info(&self) -> Option<ProcessExit>;
+
+    /// Write input to the process
+    async fn write(&self, data: &[u8]) -> PtyResult<()>;
+
+    /// Write a string to the process
+    async fn write_str(&self, s: &str) -> PtyResult<()> {
+        self.write(s.as_bytes()).await
+    }
+
+    /// Take the output channel, which closes once the process has exited and
+    /// its output was sent; `None` once taken
+    fn take_output(&mut self) -> Option<mpsc::Receiver<PtyOutput>>;
+
+    /// Resize the terminal
+    async fn resize(&self, cols: u16, rows: u16) -> PtyResult<()>;
+
+    /// Whether the terminal echoes input and hands it over a line at a time
+    async fn echo_state(&self) -> PtyResult<EchoState> {
+        Err(PtyError::ModesUnavailable)
+    }
+
+    /// Switch the terminal to raw mode or back to cooked mode, returning the
+    /// state it is left in
+    async fn set_raw_mode(&self, _raw: bool) -> PtyResult<EchoState> {
+        Err(PtyError::ModesUnavailable)
+    }
+
+    /// Kill the process
+    async fn kill(&self) -> PtyResult<()>;
+
+    /// Send a signal to the process without waiting for it to act on it
+    async fn signal(&self, signal: i32) -> PtyResult<()>;
+}
+
+/// Starts processes attached to a terminal
+pub trait PtyBackend: Send + Sync {
+    /// Spawn `command` in `working_dir`
+    fn spawn(
+        &self,
+        command: &str,
+        args: &[String],
+        working_dir: &Path,
+        env: Option<&HashMap<String, String>>,
+        size: TerminalSize,
+    ) -> PtyResult<Box<dyn PtyHandle>>;
+}
+
+#[async_trait]
+impl PtyHandle for PtyProcess {
+    fn id(&self) -> Uuid {
+        PtyProcess::id(self)
+    }
+
+    fn pid(&self) -> Option<u32> {
+        PtyProcess::pid(self)
+    }
+
+    async fn size(&self) -> TerminalSize {
+        PtyProcess::size(self).await
+    }
+
+    async fn has_exited(&self) -> bool {
+        PtyProcess::has_exited(self).await
+    }
+
+    async fn exit_info(&self) -> Option<ProcessExit> {
+        PtyProcess::exit_info(self).await
+    }
+
+    async fn write(&self, data: &[u8]) -> PtyResult<()> {
+        PtyProcess::write(self, data).await
+    }
+
+    fn take_output(&mut self) -> Option<mpsc::Receiver<PtyOutput>> {
+        PtyProcess::take_output(self)
+    }
+
+    async fn resize(&self, cols: u16, rows: u16) -> PtyResult<()> {
+        PtyProcess::resize(self, cols, rows).await
+    }
+
+    async fn echo_state(&self) -> PtyResult<EchoState> {
+        PtyProcess::echo_state(self).await
+    }
+
+    async fn set_raw_mode(&self, raw: bool) -> PtyResult<EchoState> {
+        PtyProcess::set_raw_mode(self, raw).await
+    }
+
+    async fn kill(&self) -> PtyResult<()> {
+        PtyProcess::kill(self).await
+    }
+
+    async fn signal(&self, signal: i32) -> PtyResult<()> {
+        PtyProcess::signal(self, signal).await
+    }
+}
+
+/// Runs real processes through portable-pty
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NativePtyBackend;
+
+impl PtyBackend for NativePtyBackend {
+    fn spawn(
+        &self,
+        command: &str,
+        args: &[String],
+        working_dir: &Path,
+        env: Option<&HashMap<String, String>>,
+        size: TerminalSize,
+    ) -> PtyResult<Box<dyn PtyHandle>> {
+        let process = PtyProcess::spawn(command, args, working_dir, env, size)?;
+        Ok(Box::new(process))
+    }
+}
+
+/// Output chunks a simulated process buffers before further output is dropped
+const SCRIPTED_OUTPUT_CHUNKS: usize = 1024;
+
+/// Output written when a line of input matches
+#[derive(Debug, Clone)]
+struct Reply {
+    line: String,
+    output: Vec<u8>,
+    exit_code: Option<i32>,
+    /// Switch the terminal to raw (`true`) or cooked mode
+    raw_mode: Option<bool>,
+}
+
+/// Behaviour of a simulated process
+///
+/// Input is split into lines; a line matching a reply writes the reply's
+/// output and, if one was programmed, exits with its code.
+#[derive(Debug, Clone, Default)]
+pub struct PtyScript {
+    /// Output written as soon as the process starts
+    output: Vec<u8>,
+    /// Whether input is echoed back, like a terminal in cooked mode; raw
+    /// mode turns it off
+    echo: bool,
+    replies: Vec<Reply>,
+    /// Exit right after the initial output
+    exit_code: Option<i32>,
+    /// Terminating signals the process ignores
+    ignored_signals: Vec<i32>,
+}
+
+impl PtyScript {
+    /// A process that prints nothing and runs until killed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A process that echoes its input
+    pub fn echo() -> Self {
+        Self::new().with_echo()
+    }
+
+    /// Echo input back as output
+    pub fn with_echo(mut self) -> Self {
+        self.echo = true;
+        self
+    }
+
+    /// Write `output` when the process starts
+    pub fn with_output(mut self, output: impl Into<Vec<u8>>) -> Self {
+        self.output.extend(output.into());
+        self
+    }
+
+    /// Write `output` whenever the input line `line` is received
+    pub fn on_input(mut self, line: impl Into<String>, output: impl Into<Vec<u8>>) -> Self {
+        self.replies.push(Reply {
+            line: line.into(),
+            output: output.into(),
+            exit_code: None,
+            raw_mode: None,
+        });
+        self
+    }
+
+    /// Switch the terminal to raw mode when the input line `line` is
+    /// received, like a program starting a full-screen interface
+    pub fn raw_on(mut self, line: impl Into<String>) -> Self {
+        self.replies.push(Reply {
+            line: line.into(),
+            output: Vec::new(),
+            exit_code: None,
+            raw_mode: Some(true),
+        });
+        self
+    }
+
+    /// Exit with `exit_code` when the input line `line` is received
+    pub fn exit_on(mut self, line: impl Into<String>, exit_code: i32) -> Self {
+        self.replies.push(Reply {
+            line: line.into(),
+            output: Vec::new(),
+            exit_code: Some(exit_code),
+            raw_mode: None,
+        });
+        self
+    }
+
+    /// Exit with `exit_code` right after the initial output
+    pub fn with_exit(mut self, exit_code: i32) -> Self {
+        self.exit_code = Some(exit_code);
+        self
+    }
+
+    /// Ignore `signal`, like a process that traps it
+    pub fn ignoring_signal(mut self, signal: i32) -> Self {
+        self.ignored_signals.push(signal);
+        self
+    }
+}
+
+/// A process started by a [`ScriptedPtyBackend`]
+#[derive(Debug, Clone)]
+pub struct ScriptedSpawn {
+    /// Command that would have run
+    pub command: String,
+    /// Its arguments
+    pub args: Vec<String>,
+    /// Directory it would have run in
+    pub working_dir: PathBuf,
+    /// Initial terminal size
+    pub size: TerminalSize,
+    input: Arc<Mutex<Vec<u8>>>,
+    signals: Arc<Mutex<Vec<i32>>>,
+}
+
+impl ScriptedSpawn {
+    /// Everything written to the process so far
+    pub fn input(&self) -> Vec<u8> {
+        self.input.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Signals sent to the process so far
+    pub fn signals(&self) -> Vec<i32> {
+        self.signals.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Simulates processes in memory according to a [`PtyScript`]
+#[derive(Debug, Default)]
+pub struct ScriptedPtyBackend {
+    script: PtyScript,
+    spawns: Mutex<Vec<ScriptedSpawn>>,
+}
+
+impl ScriptedPtyBackend {
+    /// Run every process according to `script`
+    pub fn new(script: PtyScript) -> Self {
+        Self {
+            script,
+            spawns: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Processes started so far, oldest first
+    pub fn spawns(&self) -> Vec<ScriptedSpawn> {
+        self.spawns.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl PtyBackend for ScriptedPtyBackend {
+    fn spawn(
+        &self,
+        command: &str,
+        args: &[String],
+        working_dir: &Path,
+        _env: Option<&HashMap<String, String>>,
+        size: TerminalSize,
+    ) -> PtyResult<Box<dyn PtyHandle>> {
+        if !working_dir.is_dir() {
+            return Err(PtyError::SpawnFailed(format!(
+                "No such directory: {}",
+                working_dir.display()
+            )));
+        }
+
+        let id = Uuid::new_v4();
+        let input = Arc::new(Mutex::new(Vec::new()));
+        let signals = Arc::new(Mutex::new(Vec::new()));
+        let (output_tx, output_rx) = mpsc::channel(SCRIPTED_OUTPUT_CHUNKS);
+        let mut state = ScriptState {
+            output: Some(output_tx),
+            line: Vec::new(),
+            echo: EchoState {
+                echo: self.script.echo,
+                raw: false,
+            },
+            exit: None,
+        };
+        if !self.script.output.is_empty() {
+            state.send(self.script.output.clone());
+        }
+        if let Some(code) = self.script.exit_code {
+            state.exit(id, Some(code), ExitReason::Normal);
+        }
+
+        self.spawns
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(ScriptedSpawn {
+                command: command.to_string(),
+                args: args.to_vec(),
+                working_dir: working_dir.to_path_buf(),
+                size,
+                input: Arc::clone(&input),
+                signals: Arc::clone(&signals),
+            });
+
+        Ok(Box::new(ScriptedPty {
+            id,
+            script: self.script.clone(),
+            size: Mutex::new(size),
+            state: Mutex::new(state),
+            output_rx: Some(output_rx),
+            input,
+            signals,
+        }))
+    }
+}
+
+/// Mutable state of a simulated process
+struct ScriptState {
+    /// Where output goes, until the process exits
+    output: Option<mpsc::Sender<PtyOutput>>,
+    /// Input since the last line ending
+    line: Vec<u8>,
+    echo: EchoState,
+    exit: Option<ProcessExit>,
+}
+
+impl ScriptState {
+    fn send(&mut self, data: Vec<u8>) {
+        if let Some(output) = &self.output {
+            let _ = output.try_send(PtyOutput { data });
+        }
+    }
+
+    fn set_raw_mode(&mut self, raw: bool) {
+        self.echo = EchoState { echo: !raw, raw };
+    }
+
+    /// Record the exit and close the output channel
+    fn exit(&mut self, id: Uuid, exit_code: Option<i32>, reason: ExitReason) {
+        self.output = None;
+        self.exit.get_or_insert(ProcessExit {
+            id,
+            exit_code,
+            signal: None,
+            reason,
+        });
+    }
+
+    fn signalled(&mut self, id: Uuid, signal: i32) {
+        self.output = None;
+        self.exit.get_or_insert(ProcessExit {
+            id,
+            exit_code: None,
+            signal: Some(signal),
+            reason: ExitReason::Signal,
+        });
+    }
+}
+
+/// A simulated process
+struct ScriptedPty {
+    id: Uuid,
+    script: PtyScript,
+    size: Mutex<TerminalSize>,
+    state: Mutex<ScriptState>,
+    output_rx: Option<mpsc::Receiver<PtyOutput>>,
+    input: Arc<Mutex<Vec<u8>>>,
+    signals: Arc<Mutex<Vec<i32>>>,
+}
+
+impl ScriptedPty {
+    fn state(&self) -> std::sync::MutexGuard<'_, ScriptState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run the replies for a complete input line
+    fn handle_line(&self, state: &mut ScriptState, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        for reply in self.script.replies.iter().filter(|r| r.line == line) {
+            if !reply.output.is_empty() {
+                state.send(reply.output.clone());
+            }
+            if let Some(raw) = reply.raw_mode {
+                state.set_raw_mode(raw);
+            }
+            if let Some(code) = reply.exit_code {
+                state.exit(self.id, Some(code), ExitReason::Normal);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PtyHandle for ScriptedPty {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    async fn size(&self) -> TerminalSize {
+        *self.size.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn has_exited(&self) -> bool {
+        self.state().exit.is_some()
+    }
+
+    async fn exit_info(&self) -> Option<ProcessExit> {
+        self.state().exit.clone()
+    }
+
+    async fn write(&self, data: &[u8]) -> PtyResult<()> {
+        let mut state = self.state();
+        if state.exit.is_some() {
+            return Err(PtyError::ProcessExited);
+        }
+        self.input
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(data);
+        if state.echo.echo {
+            state.send(data.to_vec());
+        }
+
+        for &byte in data {
+            if byte == b'\n' || byte == b'\r' {
+                let line = std::mem::take(&mut state.line);
+                self.handle_line(&mut state, &line);
+            } else {
+                state.line.push(byte);
+            }
+        }
+        Ok(())
+    }
+
+    fn take_output(&mut self) -> Option<mpsc::Receiver<PtyOutput>> {
+        self.output_rx.take()
+    }
+
+    async fn resize(&self, cols: u16, rows: u16) -> PtyResult<()> {
+        if self.has_exited().await {
+            return Err(PtyError::ProcessExited);
+        }
+        *self.size.lock().unwrap_or_else(|e| e.into_inner()) = TerminalSize::new(cols, rows);
+        Ok(())
+    }
+
+    async fn echo_state(&self) -> PtyResult<EchoState> {
+        Ok(self.state().echo)
+    }
+
+    async fn set_raw_mode(&self, raw: bool) -> PtyResult<EchoState> {
+        let mut state = self.state();
+        if state.exit.is_some() {
+            return Err(PtyError::ProcessExited);
+        }
+        state.set_raw_mode(raw);
+        Ok(state.echo)
+    }
+
+    async fn kill(&self) -> PtyResult<()> {
+        self.state().exit(self.id, None, ExitReason::Killed);
+        Ok(())
+    }
+
+    /// Terminating signals end the process; others are only recorded
+    async fn signal(&self, signal: i32) -> PtyResult<()> {
+        let mut state = self.state();
+        if state.exit.is_some() {
+            return Err(PtyError::ProcessExited);
+        }
+        self.signals
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(signal);
+        let ignored = signal != SIGKILL && self.script.ignored_signals.contains(&signal);
+        if matches!(signal, SIGHUP | SIGINT | SIGKILL | SIGTERM) && !ignored {
+            state.signalled(self.id, signal);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(output_rx: &mut mpsc::Receiver<PtyOutput>) -> Vec<u8> {
+        let mut output = Vec::new();
+        while let Ok(chunk) = output_rx.try_recv() {
+            output.extend(chunk.data);
+        }
+        output
+    }
+
+    #[tokio::test]
+    async fn test_scripted_replies_and_exit() {
+        let backend = ScriptedPtyBackend::new(
+            PtyScript::echo()
+                .with_output("ready> ")
+                .on_input("status", "all good\r\n")
+                .exit_on("quit", 3),
+        );
+        let mut process = backend
+            .spawn("claude", &[], &std::env::temp_dir(), None, TerminalSize::default())
+            .unwrap();
+        let mut output_rx = process.take_output().unwrap();
+        assert!(process.take_output().is_none());
+
+        assert_eq!(drain(&mut output_rx), b"ready> ");
+        process.write_str("status\r").await.unwrap();
+        assert_eq!(drain(&mut output_rx), b"status\rall good\r\n");
+        assert!(!process.has_exited().await);
+
+        // The channel closes after the output before the exit
+        process.write_str("quit\n").await.unwrap();
+        assert_eq!(output_rx.recv().await.unwrap().data, b"quit\n");
+        assert!(output_rx.recv().await.is_none());
+        let exit = process.exit_info().await.unwrap();
+        assert_eq!(exit.exit_code, Some(3));
+        assert_eq!(exit.reason, ExitReason::Normal);
+        assert!(matches!(
+            process.write_str("more\n").await,
+            Err(PtyError::ProcessExited)
+        ));
+
+        let spawns = backend.spawns();
+        assert_eq!(spawns.len(), 1);
+        assert_eq!(spawns[0].command, "claude");
+        assert_eq!(spawns[0].input(), b"status\rquit\n");
+    }
+
+    #[tokio::test]
+    async fn test_scripted_kill_and_resize() {
+        let backend = ScriptedPtyBackend::new(PtyScript::new());
+        let process = backend
+            .spawn("claude", &[], &std::env::temp_dir(), None, TerminalSize::default())
+            .unwrap();
+
+        process.resize(120, 40).await.unwrap();
+        assert_eq!(process.size().await.cols, 120);
+        process.kill().await.unwrap();
+        assert_eq!(process.exit_info().await.unwrap().reason, ExitReason::Killed);
+        assert!(matches!(
+            process.signal(SIGTERM).await,
+            Err(PtyError::ProcessExited)
+        ));
+        assert!(backend
+            .spawn("claude", &[], Path::new("/nonexistent"), None, TerminalSize::default())
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scripted_signals() {
+        let backend = ScriptedPtyBackend::new(PtyScript::new());
+        let process = backend
+            .spawn("claude", &[], &std::env::temp_dir(), None, TerminalSize::default())
+            .unwrap();
+
+        // Signals that don't terminate are only recorded
+        process.signal(18).await.unwrap();
+        assert!(!process.has_exited().await);
+        process.signal(SIGTERM).await.unwrap();
+        let exit = process.exit_info().await.unwrap();
+        assert_eq!(exit.signal, Some(SIGTERM));
+        assert_eq!(exit.reason, ExitReason::Signal);
+        assert_eq!(backend.spawns()[0].signals(), vec![18, SIGTERM]);
+    }
+}
@@ -0,0 +1,159 @@
+//! Named keys and bracketed paste
+//!
+//! Clients press keys by name, like `enter`, `esc`, `ctrl+c` or `up`, and
+//! the bridge writes the bytes a terminal would send for them, so clients
+//! don't encode escape sequences themselves. Pasted text can be wrapped in
+//! bracketed-paste markers, which tell an application that it was pasted
+//! rather than typed, so the newlines in it don't submit anything. Both
+//! depend on modes the application switched on, which the bridge follows
+//! from its output.
+
+/// Start of a bracketed paste
+pub const PASTE_START: &str = "\x1b[200~";
+
+/// End of a bracketed paste
+pub const PASTE_END: &str = "\x1b[201~";
+
+/// Input modes a terminal application has switched on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputModes {
+    /// Cursor keys send SS3 rather than CSI sequences (DECCKM)
+    pub application_cursor: bool,
+    /// Pastes are expected between markers (mode 2004)
+    pub bracketed_paste: bool,
+}
+
+/// Bytes a terminal sends for a named key, or `None` for unknown names
+///
+/// Names are case-insensitive: `enter`, `tab`, `shift+tab`, `esc`,
+/// `backspace`, `delete`, `insert`, `space`, `up`, `down`, `left`, `right`,
+/// `home`, `end`, `pageup`, `pagedown`, `f1` to `f12`, `ctrl+` a letter or
+/// one of `@[\]^_?`, and `alt+` a key or character.
+pub fn key_sequence(name: &str, modes: InputModes) -> Option<String> {
+    let name = name.trim().to_ascii_lowercase();
+    if let Some(key) = name.strip_prefix("alt+") {
+        // Alt sends Esc ahead of the key
+        let key = match key_sequence(key, modes) {
+            Some(sequence) => sequence,
+            None if key.chars().count() == 1 => key.to_string(),
+            None => return None,
+        };
+        return Some(format!("\x1b{}", key));
+    }
+    if let Some(key) = name.strip_prefix("ctrl+") {
+        return control(key).map(String::from);
+    }
+
+    let cursor = |key: char| {
+        let intro = if modes.application_cursor { 'O' } else { '[' };
+        Some(format!("\x1b{}{}", intro, key))
+    };
+    let sequence = match name.as_str() {
+        "enter" | "return" => "\r",
+        "tab" => "\t",
+        "shift+tab" => "\x1b[Z",
+        "esc" | "escape" => "\x1b",
+        "backspace" => "\x7f",
+        "delete" => "\x1b[3~",
+        "insert" => "\x1b[2~",
+        "space" => " ",
+        "up" => return cursor('A'),
+        "down" => return cursor('B'),
+        "right" => return cursor('C'),
+        "left" => return cursor('D'),
+        "home" => return cursor('H'),
+        "end" => return cursor('F'),
+        "pageup" => "\x1b[5~",
+        "pagedown" => "\x1b[6~",
+        "f1" => "\x1bOP",
+        "f2" => "\x1bOQ",
+        "f3" => "\x1bOR",
+        "f4" => "\x1bOS",
+        "f5" => "\x1b[15~",
+        "f6" => "\x1b[17~",
+        "f7" => "\x1b[18~",
+        "f8" => "\x1b[19~",
+        "f9" => "\x1b[20~",
+        "f10" => "\x1b[21~",
+        "f11" => "\x1b[23~",
+        "f12" => "\x1b[24~",
+        _ => return None,
+    };
+    Some(sequence.to_string())
+}
+
+/// Control character typed with Ctrl and `key`
+fn control(key: &str) -> Option<char> {
+    let code = match key {
+        "@" | "space" => 0x00,
+        "[" => 0x1b,
+        "\\" => 0x1c,
+        "]" => 0x1d,
+        "^" => 0x1e,
+        "_" => 0x1f,
+        "?" => 0x7f,
+        _ => match key.as_bytes() {
+            &[letter @ b'a'..=b'z'] => letter - b'a' + 1,
+            _ => return None,
+        },
+    };
+    Some(char::from(code))
+}
+
+/// Wrap pasted text in bracketed-paste markers
+///
+/// End markers in the text are removed, so it can't end the paste early and
+/// have the rest taken as typed.
+pub fn bracketed_paste(text: &str) -> String {
+    format!(
+        "{}{}{}",
+        PASTE_START,
+        text.replace(PASTE_END, ""),
+        PASTE_END
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Option<String> {
+        key_sequence(name, InputModes::default())
+    }
+
+    #[test]
+    fn test_named_keys() {
+        assert_eq!(key("Enter").as_deref(), Some("\r"));
+        assert_eq!(key("esc").as_deref(), Some("\x1b"));
+        assert_eq!(key("shift+tab").as_deref(), Some("\x1b[Z"));
+        assert_eq!(key("up").as_deref(), Some("\x1b[A"));
+        assert_eq!(key("f12").as_deref(), Some("\x1b[24~"));
+        assert_eq!(key("ctrl+c").as_deref(), Some("\x03"));
+        assert_eq!(key("Ctrl+[").as_deref(), Some("\x1b"));
+        assert_eq!(key("alt+b").as_deref(), Some("\x1bb"));
+        assert_eq!(key("alt+enter").as_deref(), Some("\x1b\r"));
+        for unknown in ["", "ctrl+", "ctrl+cc", "ctrl+1", "alt+", "f13", "hyper+x"] {
+            assert_eq!(key(unknown), None, "{}", unknown);
+        }
+
+        let modes = InputModes {
+            application_cursor: true,
+            ..Default::default()
+        };
+        assert_eq!(key_sequence("left", modes).as_deref(), Some("\x1bOD"));
+        assert_eq!(key_sequence("pageup", modes).as_deref(), Some("\x1b[5~"));
+    }
+
+    #[test]
+    fn test_bracketed_paste() {
+        assert_eq!(
+            bracketed_paste("line 1\nline 2"),
+            "\x1b[200~line 1\nline 2\x1b[201~"
+        );
+        // The text can't close the paste itself
+        assert_eq!(
+            bracketed_paste("a\x1b[201~rm -rf ~\r"),
+            "\x1b[200~arm -rf ~\r\x1b[201~"
+        );
+    }
+}
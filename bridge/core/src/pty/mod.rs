@@ -3,8 +3,20 @@
 //! Provides PTY terminal emulation for running processes with full terminal support.
 //! Uses portable-pty for cross-platform compatibility.
 
+mod adopt;
+mod backend;
+mod keys;
 #[allow(unused_imports)]
 mod process;
+mod screen;
+mod ssh;
+mod tmux;
 
+pub use adopt::*;
+pub use backend::*;
+pub use keys::*;
 #[allow(unused_imports)]
 pub use process::*;
+pub use screen::*;
+pub use ssh::*;
+pub use tmux::*;
@@ -0,0 +1,1188 @@
+//! PTY process management
+//!
+//! Handles spawning processes with PTY terminal emulation, including:
+//! - Configurable terminal size
+//! - Stdin/stdout streaming
+//! - Terminal resize support
+//! - Proper cleanup on exit
+
+#![allow(dead_code)]
+
+use portable_pty::{native_pty_system, Child, CommandBuilder, ExitStatus, MasterPty, PtySize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
+use uuid::Uuid;
+
+/// Errors that can occur during PTY operations
+#[derive(Error, Debug)]
+pub enum PtyError {
+    #[error("Failed to open PTY: {0}")]
+    OpenFailed(String),
+
+    #[error("Failed to spawn process: {0}")]
+    SpawnFailed(String),
+
+    #[error("Failed to write to PTY: {0}")]
+    WriteFailed(String),
+
+    #[error("Failed to read from PTY: {0}")]
+    ReadFailed(String),
+
+    #[error("Failed to resize PTY: {0}")]
+    ResizeFailed(String),
+
+    #[error("Process not found: {0}")]
+    ProcessNotFound(Uuid),
+
+    #[error("Process already exited")]
+    ProcessExited,
+
+    #[error("Terminal modes are not available on this platform")]
+    ModesUnavailable,
+
+    #[error("PTY system error: {0}")]
+    SystemError(String),
+}
+
+/// Result type for PTY operations
+pub type PtyResult<T> = Result<T, PtyError>;
+
+/// Terminal size configuration
+#[derive(Debug, Clone, Copy)]
+pub struct TerminalSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Default for TerminalSize {
+    fn default() -> Self {
+        Self { cols: 80, rows: 24 }
+    }
+}
+
+impl TerminalSize {
+    /// Create a new terminal size
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Self { cols, rows }
+    }
+
+    /// Convert to portable-pty PtySize
+    fn to_pty_size(self) -> PtySize {
+        PtySize {
+            rows: self.rows,
+            cols: self.cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+}
+
+/// Echo and line settings of a terminal, from its termios
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EchoState {
+    /// The terminal echoes typed characters back (ECHO)
+    pub echo: bool,
+    /// Input reaches the application as it is typed rather than a line at a
+    /// time (ICANON off)
+    pub raw: bool,
+}
+
+/// Output data from the PTY
+#[derive(Debug, Clone)]
+pub struct PtyOutput {
+    /// The output data
+    pub data: Vec<u8>,
+}
+
+/// Event emitted when a process exits
+#[derive(Debug, Clone)]
+pub struct ProcessExit {
+    /// The process ID
+    pub id: Uuid,
+    /// Exit code if available
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the process, on Unix
+    pub signal: Option<i32>,
+    /// Exit reason
+    pub reason: ExitReason,
+}
+
+impl ProcessExit {
+    /// Describe how a reaped child exited
+    fn from_status(id: Uuid, status: &ExitStatus) -> Self {
+        match exit_signal(status) {
+            Some(signal) => Self {
+                id,
+                exit_code: None,
+                signal: Some(signal),
+                reason: ExitReason::Signal,
+            },
+            None => Self {
+                id,
+                exit_code: Some(status.exit_code() as i32),
+                signal: None,
+                reason: ExitReason::Normal,
+            },
+        }
+    }
+
+    fn unknown(id: Uuid) -> Self {
+        Self {
+            id,
+            exit_code: None,
+            signal: None,
+            reason: ExitReason::Unknown,
+        }
+    }
+}
+
+/// Number of the signal that terminated a child
+///
+/// portable-pty only keeps the `strsignal` description of the signal, so it
+/// is matched back to its number.
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    let status = status.to_string();
+    let description = status.strip_prefix("Terminated by ")?;
+    if let Some(number) = description.strip_prefix("Signal ") {
+        return number.parse().ok();
+    }
+    (1..=31).find(|&signal| {
+        // SAFETY: strsignal returns null or a NUL-terminated string that
+        // stays valid until the next call on this thread
+        let name = unsafe { libc::strsignal(signal) };
+        !name.is_null()
+            && unsafe { std::ffi::CStr::from_ptr(name) }.to_bytes() == description.as_bytes()
+    })
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Read the echo state of the terminal behind `master`
+#[cfg(unix)]
+fn read_echo_state(master: &dyn MasterPty) -> PtyResult<EchoState> {
+    let termios = get_termios(master)?;
+    Ok(EchoState {
+        echo: termios.c_lflag & libc::ECHO != 0,
+        raw: termios.c_lflag & libc::ICANON == 0,
+    })
+}
+
+/// Switch the terminal behind `master` to raw mode or back to cooked mode
+///
+/// Raw mode turns off echo, line editing, signal keys and CR translation of
+/// input, like `stty raw -echo`; output processing is left alone so the
+/// screen still renders the same.
+#[cfg(unix)]
+fn write_raw_mode(master: &dyn MasterPty, raw: bool) -> PtyResult<()> {
+    const LOCAL: libc::tcflag_t = libc::ECHO | libc::ICANON | libc::ISIG | libc::IEXTEN;
+    const INPUT: libc::tcflag_t = libc::ICRNL | libc::IXON;
+
+    let mut termios = get_termios(master)?;
+    if raw {
+        termios.c_lflag &= !LOCAL;
+        termios.c_iflag &= !INPUT;
+    } else {
+        termios.c_lflag |= LOCAL;
+        termios.c_iflag |= INPUT;
+    }
+    let fd = master.as_raw_fd().ok_or(PtyError::ModesUnavailable)?;
+    // SAFETY: termios is a valid, initialised struct
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(PtyError::SystemError(format!(
+            "Failed to set terminal modes: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn get_termios(master: &dyn MasterPty) -> PtyResult<libc::termios> {
+    let fd = master.as_raw_fd().ok_or(PtyError::ModesUnavailable)?;
+    // SAFETY: termios is plain data, filled in by tcgetattr before it is read
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(PtyError::SystemError(format!(
+            "Failed to read terminal modes: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(termios)
+}
+
+/// ConPTY keeps no termios; the console modes are the child's own
+#[cfg(not(unix))]
+fn read_echo_state(_master: &dyn MasterPty) -> PtyResult<EchoState> {
+    Err(PtyError::ModesUnavailable)
+}
+
+#[cfg(not(unix))]
+fn write_raw_mode(_master: &dyn MasterPty, _raw: bool) -> PtyResult<()> {
+    Err(PtyError::ModesUnavailable)
+}
+
+/// Reason for process exit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Process exited normally
+    Normal,
+    /// Process was killed by signal
+    Signal,
+    /// Process was killed by request
+    Killed,
+    /// Process exited after being asked to terminate
+    Terminated,
+    /// Process was stopped for exceeding a time limit
+    TimedOut,
+    /// Unknown exit reason
+    Unknown,
+}
+
+/// Hangup, the signal a closing terminal sends
+pub const SIGHUP: i32 = 1;
+/// Interrupt, as sent by Ctrl+C
+pub const SIGINT: i32 = 2;
+/// Kill, which cannot be caught or ignored
+pub const SIGKILL: i32 = 9;
+/// Polite request to terminate
+pub const SIGTERM: i32 = 15;
+/// Stop, which suspends a process until it is continued
+#[cfg(unix)]
+pub const SIGSTOP: i32 = libc::SIGSTOP;
+/// Continue a stopped process
+#[cfg(unix)]
+pub const SIGCONT: i32 = libc::SIGCONT;
+
+/// Console input that ConPTY delivers as a CTRL_C_EVENT
+const CTRL_C: u8 = 0x03;
+
+/// Time a Windows console process gets to exit after Ctrl+C before it is terminated
+const CONSOLE_CTRL_GRACE: Duration = Duration::from_secs(2);
+
+/// Time a child gets to exit once its terminal has closed before it is
+/// reported as lost
+const REAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether `signal` can be sent to a process on this platform
+///
+/// Windows has no signals: a console process can only be sent Ctrl+C (SIGINT)
+/// or terminated (SIGHUP, SIGKILL, SIGTERM), so only those numbers are accepted.
+pub fn is_supported_signal(signal: i32) -> bool {
+    if cfg!(windows) {
+        matches!(signal, 1 | 2 | 9 | 15)
+    } else {
+        (1..=31).contains(&signal)
+    }
+}
+
+/// Handle to a running PTY process
+pub struct PtyProcess {
+    /// Unique identifier
+    id: Uuid,
+    /// The master PTY handle, taken once a Windows pseudoconsole is closed
+    master: Arc<Mutex<Option<Box<dyn MasterPty + Send>>>>,
+    /// Current terminal size
+    size: Arc<RwLock<TerminalSize>>,
+    /// Writer for sending input
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Channel for receiving output, until it is taken
+    output_rx: Option<mpsc::Receiver<PtyOutput>>,
+    /// Channel for signaling shutdown
+    shutdown_tx: broadcast::Sender<()>,
+    /// Flag indicating if process has exited
+    exited: Arc<RwLock<bool>>,
+    /// Exit information
+    exit_info: Arc<RwLock<Option<ProcessExit>>>,
+    /// OS process ID of the child, if known
+    pid: Option<u32>,
+    /// The child process, kept so it can be terminated and reaped
+    child: Arc<std::sync::Mutex<Box<dyn Child + Send + Sync>>>,
+}
+
+impl PtyProcess {
+    /// Spawn a new process with PTY
+    ///
+    /// # Arguments
+    /// * `command` - The command to run
+    /// * `args` - Command arguments
+    /// * `working_dir` - Working directory for the process
+    /// * `env` - Environment variables (optional)
+    /// * `size` - Initial terminal size
+    ///
+    /// # Returns
+    /// A new `PtyProcess` handle
+    pub fn spawn(
+        command: &str,
+        args: &[String],
+        working_dir: &Path,
+        env: Option<&HashMap<String, String>>,
+        size: TerminalSize,
+    ) -> PtyResult<Self> {
+        let id = Uuid::new_v4();
+
+        // Get the native PTY system
+        let pty_system = native_pty_system();
+
+        // Open a new PTY with the specified size
+        let pair = pty_system
+            .openpty(size.to_pty_size())
+            .map_err(|e| PtyError::OpenFailed(e.to_string()))?;
+
+        // Build the command
+        let mut cmd = CommandBuilder::new(command);
+        cmd.args(args);
+        cmd.cwd(working_dir);
+
+        // Set environment variables if provided
+        if let Some(env_vars) = env {
+            for (key, value) in env_vars {
+                cmd.env(key, value);
+            }
+        }
+
+        // Spawn the process
+        let child = pair
+            .slave
+            .spawn_command(cmd)
+            .map_err(|e| PtyError::SpawnFailed(e.to_string()))?;
+        let pid = child.process_id();
+
+        // Drop the slave - we only need the master
+        drop(pair.slave);
+
+        // Get reader and writer from the master
+        let reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(|e| PtyError::SystemError(e.to_string()))?;
+        let writer = pair
+            .master
+            .take_writer()
+            .map_err(|e| PtyError::SystemError(e.to_string()))?;
+
+        // Create channels
+        let (output_tx, output_rx) = mpsc::channel(1024);
+        let (shutdown_tx, _) = broadcast::channel(1);
+
+        let exited = Arc::new(RwLock::new(false));
+        let exit_info = Arc::new(RwLock::new(None));
+
+        // Spawn the reader task
+        let exited_clone = Arc::clone(&exited);
+        let exit_info_clone = Arc::clone(&exit_info);
+        let shutdown_rx = shutdown_tx.subscribe();
+        let id_clone = id;
+        let child = Arc::new(std::sync::Mutex::new(child));
+        let child_clone = Arc::clone(&child);
+        let master = Arc::new(Mutex::new(Some(pair.master)));
+
+        std::thread::spawn(move || {
+            Self::reader_loop(
+                reader,
+                output_tx,
+                shutdown_rx,
+                exited_clone,
+                exit_info_clone,
+                child_clone,
+                id_clone,
+            );
+        });
+
+        // ConPTY keeps the output pipe open after the child exits, until the
+        // pseudoconsole is closed, so the reader would never see EOF
+        if cfg!(windows) {
+            let child = Arc::clone(&child);
+            let master = Arc::clone(&master);
+            std::thread::spawn(move || Self::close_on_exit(&child, &master));
+        }
+
+        Ok(Self {
+            id,
+            master,
+            size: Arc::new(RwLock::new(size)),
+            writer: Arc::new(Mutex::new(writer)),
+            output_rx: Some(output_rx),
+            shutdown_tx,
+            exited,
+            exit_info,
+            pid,
+            child,
+        })
+    }
+
+    /// Reader loop that runs in a separate thread
+    fn reader_loop(
+        mut reader: Box<dyn Read + Send>,
+        output_tx: mpsc::Sender<PtyOutput>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+        exited: Arc<RwLock<bool>>,
+        exit_info: Arc<RwLock<Option<ProcessExit>>>,
+        child: Arc<std::sync::Mutex<Box<dyn Child + Send + Sync>>>,
+        id: Uuid,
+    ) {
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            // Check for shutdown signal (non-blocking)
+            match shutdown_rx.try_recv() {
+                Ok(_) | Err(broadcast::error::TryRecvError::Closed) => {
+                    break;
+                }
+                Err(broadcast::error::TryRecvError::Empty) => {}
+                Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+            }
+
+            // Read from PTY with timeout-like behavior
+            match reader.read(&mut buffer) {
+                Ok(0) => {
+                    // EOF - process has exited
+                    Self::record_exit(&child, &exited, &exit_info, id);
+                    break;
+                }
+                Ok(n) => {
+                    let output = PtyOutput {
+                        data: buffer[..n].to_vec(),
+                    };
+                    // Try to send output, ignore if receiver is dropped
+                    if output_tx.blocking_send(output).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    // Check if it's a "would block" error on Unix
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                        continue;
+                    }
+                    // Other errors indicate process exit or PTY closed
+                    Self::record_exit(&child, &exited, &exit_info, id);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Wait for the child to exit, then close its pseudoconsole so the
+    /// reader drains the remaining output and reaches EOF
+    fn close_on_exit(
+        child: &std::sync::Mutex<Box<dyn Child + Send + Sync>>,
+        master: &Mutex<Option<Box<dyn MasterPty + Send>>>,
+    ) {
+        loop {
+            let status = child.lock().unwrap_or_else(|e| e.into_inner()).try_wait();
+            if !matches!(status, Ok(None)) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        drop(master.blocking_lock().take());
+    }
+
+    /// Reap the child once its terminal has closed and record how it exited
+    ///
+    /// The child is polled rather than waited on so that `kill` can still
+    /// take the handle meanwhile.
+    fn record_exit(
+        child: &std::sync::Mutex<Box<dyn Child + Send + Sync>>,
+        exited: &RwLock<bool>,
+        exit_info: &RwLock<Option<ProcessExit>>,
+        id: Uuid,
+    ) {
+        let deadline = Instant::now() + REAP_TIMEOUT;
+        let exit = loop {
+            let status = child.lock().unwrap_or_else(|e| e.into_inner()).try_wait();
+            match status {
+                Ok(Some(status)) => break ProcessExit::from_status(id, &status),
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                _ => break ProcessExit::unknown(id),
+            }
+        };
+        // A kill records its own exit
+        exit_info.blocking_write().get_or_insert(exit);
+        *exited.blocking_write() = true;
+    }
+
+    /// Get the process ID
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Get the OS process ID of the child, if known
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// Get the current terminal size
+    pub async fn size(&self) -> TerminalSize {
+        *self.size.read().await
+    }
+
+    /// Check if the process has exited
+    pub async fn has_exited(&self) -> bool {
+        *self.exited.read().await
+    }
+
+    /// Get exit information if process has exited
+    pub async fn exit_info(&self) -> Option<ProcessExit> {
+        self.exit_info.read().await.clone()
+    }
+
+    /// Write input to the PTY (stdin)
+    pub async fn write(&self, data: &[u8]) -> PtyResult<()> {
+        if self.has_exited().await {
+            return Err(PtyError::ProcessExited);
+        }
+
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(data)
+            .map_err(|e| PtyError::WriteFailed(e.to_string()))?;
+        writer
+            .flush()
+            .map_err(|e| PtyError::WriteFailed(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Write a string to the PTY
+    pub async fn write_str(&self, s: &str) -> PtyResult<()> {
+        self.write(s.as_bytes()).await
+    }
+
+    /// Receive output from the PTY
+    ///
+    /// Returns `None` if the process has exited and all output has been consumed,
+    /// or the output channel was taken
+    pub async fn recv(&mut self) -> Option<PtyOutput> {
+        self.output_rx.as_mut()?.recv().await
+    }
+
+    /// Try to receive output without blocking
+    pub fn try_recv(&mut self) -> Option<PtyOutput> {
+        self.output_rx.as_mut()?.try_recv().ok()
+    }
+
+    /// Take the output channel, so output can be awaited without holding the
+    /// process
+    ///
+    /// The channel closes once the process has exited, after its remaining
+    /// output, by which time its exit information is recorded.
+    pub fn take_output(&mut self) -> Option<mpsc::Receiver<PtyOutput>> {
+        self.output_rx.take()
+    }
+
+    /// Resize the terminal
+    pub async fn resize(&self, cols: u16, rows: u16) -> PtyResult<()> {
+        if self.has_exited().await {
+            return Err(PtyError::ProcessExited);
+        }
+
+        let new_size = TerminalSize::new(cols, rows);
+        let master = self.master.lock().await;
+        let master = master.as_ref().ok_or(PtyError::ProcessExited)?;
+
+        master
+            .resize(new_size.to_pty_size())
+            .map_err(|e| PtyError::ResizeFailed(e.to_string()))?;
+
+        *self.size.write().await = new_size;
+
+        Ok(())
+    }
+
+    /// Whether the terminal echoes input and hands it over a line at a time
+    pub async fn echo_state(&self) -> PtyResult<EchoState> {
+        let master = self.master.lock().await;
+        let master = master.as_ref().ok_or(PtyError::ProcessExited)?;
+        read_echo_state(master.as_ref())
+    }
+
+    /// Switch the terminal to raw mode or back to cooked mode
+    ///
+    /// The application may switch it again itself, as interactive programs
+    /// do when they start and exit.
+    pub async fn set_raw_mode(&self, raw: bool) -> PtyResult<EchoState> {
+        if self.has_exited().await {
+            return Err(PtyError::ProcessExited);
+        }
+        let master = self.master.lock().await;
+        let master = master.as_ref().ok_or(PtyError::ProcessExited)?;
+        write_raw_mode(master.as_ref(), raw)?;
+        read_echo_state(master.as_ref())
+    }
+
+    /// Kill the process
+    ///
+    /// On Unix the child gets SIGHUP, then SIGKILL if it is still running.
+    /// `GenerateConsoleCtrlEvent` only reaches processes attached to the
+    /// bridge's own console, so on Windows Ctrl+C is written to the
+    /// pseudoconsole instead and the child is terminated if it has not exited
+    /// after a grace period.
+    pub async fn kill(&self) -> PtyResult<()> {
+        let mut status = None;
+        if !self.has_exited().await {
+            if cfg!(windows) {
+                self.interrupt(CONSOLE_CTRL_GRACE).await;
+            }
+            let child = Arc::clone(&self.child);
+            let reaped = tokio::task::spawn_blocking(move || {
+                let mut child = child.lock().unwrap_or_else(|e| e.into_inner());
+                match child.try_wait()? {
+                    Some(status) => Ok(status),
+                    None => {
+                        child.kill()?;
+                        child.wait()
+                    }
+                }
+            })
+            .await
+            .map_err(|e| PtyError::SystemError(e.to_string()))?
+            .map_err(|e| PtyError::SystemError(format!("Failed to kill process: {}", e)))?;
+            status = Some(reaped);
+        }
+
+        // Mark as exited before the reader closes the output channel
+        if let Some(status) = status {
+            *self.exit_info.write().await = Some(ProcessExit {
+                reason: ExitReason::Killed,
+                ..ProcessExit::from_status(self.id, &status)
+            });
+        }
+        *self.exited.write().await = true;
+
+        // Signal shutdown to the reader thread
+        let _ = self.shutdown_tx.send(());
+
+        Ok(())
+    }
+
+    /// Send `signal` to the child
+    ///
+    /// Unlike [`kill`](Self::kill) this does not wait for the child, which is
+    /// reported as exited once its terminal closes. Windows has no signals, so
+    /// SIGINT is sent as Ctrl+C and the other supported signals terminate the
+    /// process.
+    pub async fn signal(&self, signal: i32) -> PtyResult<()> {
+        if self.has_exited().await {
+            return Err(PtyError::ProcessExited);
+        }
+
+        #[cfg(unix)]
+        {
+            let pid = self
+                .pid
+                .ok_or_else(|| PtyError::SystemError("Process ID unknown".to_string()))?;
+            // SAFETY: kill has no memory safety requirements
+            if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+                return Err(PtyError::SystemError(format!(
+                    "Failed to send signal {}: {}",
+                    signal,
+                    std::io::Error::last_os_error()
+                )));
+            }
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        {
+            if signal == SIGINT {
+                return self.write(&[CTRL_C]).await;
+            }
+            let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+            child
+                .kill()
+                .map_err(|e| PtyError::SystemError(format!("Failed to kill process: {}", e)))
+        }
+    }
+
+    /// Send Ctrl+C and give the child up to `grace` to exit
+    async fn interrupt(&self, grace: Duration) {
+        if self.write(&[CTRL_C]).await.is_err() {
+            return;
+        }
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            let exited = {
+                let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+                !matches!(child.try_wait(), Ok(None))
+            };
+            if exited {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+impl Drop for PtyProcess {
+    fn drop(&mut self) {
+        // Signal shutdown to reader thread
+        let _ = self.shutdown_tx.send(());
+    }
+}
+
+/// Callback type for output streaming
+pub type OutputCallback = Box<dyn Fn(Uuid, &[u8]) + Send + Sync>;
+
+/// Callback type for process exit
+pub type ExitCallback = Box<dyn Fn(ProcessExit) + Send + Sync>;
+
+/// PTY process with callback-based output streaming
+pub struct PtyProcessWithCallbacks {
+    /// The underlying PTY process
+    process: Arc<Mutex<PtyProcess>>,
+    /// Process ID
+    id: Uuid,
+    /// Terminal size
+    size: Arc<RwLock<TerminalSize>>,
+    /// Flag indicating if callbacks are running
+    running: Arc<RwLock<bool>>,
+    /// Shutdown signal
+    shutdown_tx: broadcast::Sender<()>,
+}
+
+impl PtyProcessWithCallbacks {
+    /// Spawn a new process with callback-based output streaming
+    pub fn spawn<F, E>(
+        command: &str,
+        args: &[String],
+        working_dir: &Path,
+        env: Option<&HashMap<String, String>>,
+        size: TerminalSize,
+        output_callback: F,
+        exit_callback: E,
+    ) -> PtyResult<Self>
+    where
+        F: Fn(Uuid, &[u8]) + Send + Sync + 'static,
+        E: Fn(ProcessExit) + Send + Sync + 'static,
+    {
+        let process = PtyProcess::spawn(command, args, working_dir, env, size)?;
+        let id = process.id();
+        let process_size = Arc::new(RwLock::new(size));
+
+        let (shutdown_tx, mut shutdown_rx) = broadcast::channel(1);
+        let running = Arc::new(RwLock::new(true));
+        let running_clone = Arc::clone(&running);
+
+        let process = Arc::new(Mutex::new(process));
+        let process_clone = Arc::clone(&process);
+
+        // Spawn the output forwarding task
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown_rx.recv() => {
+                        break;
+                    }
+                    output = async {
+                        let mut proc = process_clone.lock().await;
+                        proc.recv().await
+                    } => {
+                        match output {
+                            Some(out) => {
+                                output_callback(id, &out.data);
+                            }
+                            None => {
+                                // Process exited
+                                let proc = process_clone.lock().await;
+                                if let Some(exit_info) = proc.exit_info().await {
+                                    exit_callback(exit_info);
+                                } else {
+                                    exit_callback(ProcessExit::unknown(id));
+                                }
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            *running_clone.write().await = false;
+        });
+
+        Ok(Self {
+            process,
+            id,
+            size: process_size,
+            running,
+            shutdown_tx,
+        })
+    }
+
+    /// Get the process ID
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Get the current terminal size
+    pub async fn size(&self) -> TerminalSize {
+        *self.size.read().await
+    }
+
+    /// Check if the process is still running
+    pub async fn is_running(&self) -> bool {
+        *self.running.read().await
+    }
+
+    /// Check if the process has exited
+    pub async fn has_exited(&self) -> bool {
+        self.process.lock().await.has_exited().await
+    }
+
+    /// Write input to the process
+    pub async fn write(&self, data: &[u8]) -> PtyResult<()> {
+        self.process.lock().await.write(data).await
+    }
+
+    /// Write a string to the process
+    pub async fn write_str(&self, s: &str) -> PtyResult<()> {
+        self.process.lock().await.write_str(s).await
+    }
+
+    /// Resize the terminal
+    pub async fn resize(&self, cols: u16, rows: u16) -> PtyResult<()> {
+        self.process.lock().await.resize(cols, rows).await?;
+        *self.size.write().await = TerminalSize::new(cols, rows);
+        Ok(())
+    }
+
+    /// Kill the process
+    pub async fn kill(&self) -> PtyResult<()> {
+        let _ = self.shutdown_tx.send(());
+        self.process.lock().await.kill().await
+    }
+}
+
+impl Drop for PtyProcessWithCallbacks {
+    fn drop(&mut self) {
+        let _ = self.shutdown_tx.send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// A working directory every platform has
+    fn work_dir() -> PathBuf {
+        std::env::temp_dir()
+    }
+
+    /// Run `script` with the platform's shell
+    fn shell(script: &str) -> (&'static str, Vec<String>) {
+        if cfg!(windows) {
+            ("cmd", vec!["/C".to_string(), script.to_string()])
+        } else {
+            ("sh", vec!["-c".to_string(), script.to_string()])
+        }
+    }
+
+    /// A process that waits for input until it is stopped
+    fn spawn_reader() -> PtyResult<PtyProcess> {
+        let command = if cfg!(windows) { "cmd" } else { "cat" };
+        PtyProcess::spawn(command, &[], &work_dir(), None, TerminalSize::default())
+    }
+
+    /// A process that runs for a while without reading input
+    fn sleeper() -> (&'static str, Vec<String>) {
+        if cfg!(windows) {
+            let args = ["-NoProfile", "-Command", "Start-Sleep -Seconds 30"];
+            ("powershell", args.iter().map(|a| a.to_string()).collect())
+        } else {
+            ("sleep", vec!["30".to_string()])
+        }
+    }
+
+    /// Read output until it contains `text`, which ConPTY may precede with
+    /// escape sequences of its own
+    async fn read_until(process: &mut PtyProcess, text: &str) -> bool {
+        let mut output = Vec::new();
+        timeout(Duration::from_secs(5), async {
+            while let Some(chunk) = process.recv().await {
+                output.extend(chunk.data);
+                if String::from_utf8_lossy(&output).contains(text) {
+                    return true;
+                }
+            }
+            false
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    async fn wait_exit(process: &PtyProcess) -> ProcessExit {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(exit) = process.exit_info().await {
+                    return exit;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_terminal_size_default() {
+        let size = TerminalSize::default();
+        assert_eq!(size.cols, 80);
+        assert_eq!(size.rows, 24);
+    }
+
+    #[tokio::test]
+    async fn test_terminal_size_custom() {
+        let size = TerminalSize::new(120, 40);
+        assert_eq!(size.cols, 120);
+        assert_eq!(size.rows, 40);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_process() {
+        let (command, args) = shell("echo hello");
+        let process = PtyProcess::spawn(command, &args, &work_dir(), None, TerminalSize::default());
+
+        assert!(process.is_ok());
+        let mut process = process.unwrap();
+
+        assert!(read_until(&mut process, "hello").await);
+    }
+
+    #[tokio::test]
+    async fn test_process_write() {
+        let process = spawn_reader();
+
+        assert!(process.is_ok());
+        let mut process = process.unwrap();
+
+        // Write to the process
+        let write_result = process.write_str("test input\r").await;
+        assert!(write_result.is_ok());
+
+        // Read the echoed output
+        assert!(read_until(&mut process, "test input").await);
+    }
+
+    #[tokio::test]
+    async fn test_process_resize() {
+        let process = spawn_reader();
+
+        assert!(process.is_ok());
+        let process = process.unwrap();
+
+        // Check initial size
+        let size = process.size().await;
+        assert_eq!(size.cols, 80);
+        assert_eq!(size.rows, 24);
+
+        // Resize
+        let resize_result = process.resize(120, 40).await;
+        assert!(resize_result.is_ok());
+
+        // Check new size
+        let size = process.size().await;
+        assert_eq!(size.cols, 120);
+        assert_eq!(size.rows, 40);
+    }
+
+    #[tokio::test]
+    async fn test_process_kill() {
+        let process = spawn_reader();
+
+        assert!(process.is_ok());
+        let process = process.unwrap();
+
+        // Kill the process
+        let kill_result = process.kill().await;
+        assert!(kill_result.is_ok());
+
+        // Should be marked as exited
+        assert!(process.has_exited().await);
+        let exit = process.exit_info().await.unwrap();
+        assert_eq!(exit.reason, ExitReason::Killed);
+        // Windows processes are terminated with an exit code instead
+        assert_eq!(exit.signal, cfg!(unix).then_some(SIGHUP));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_kill_escalates_when_hangup_ignored() {
+        let (command, args) = shell("trap '' HUP; echo ready; sleep 30");
+        let mut process =
+            PtyProcess::spawn(command, &args, &work_dir(), None, TerminalSize::default()).unwrap();
+        let pid = process.pid().unwrap();
+        timeout(Duration::from_secs(2), process.recv())
+            .await
+            .unwrap();
+
+        process.kill().await.unwrap();
+        let alive = std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap()
+            .success();
+        assert!(!alive);
+    }
+
+    #[tokio::test]
+    async fn test_exit_status() {
+        let run = |script: &str| {
+            let (command, args) = shell(script);
+            PtyProcess::spawn(command, &args, &work_dir(), None, TerminalSize::default()).unwrap()
+        };
+
+        let exit = wait_exit(&run("exit 3")).await;
+        assert_eq!(exit.exit_code, Some(3));
+        assert_eq!(exit.signal, None);
+        assert_eq!(exit.reason, ExitReason::Normal);
+
+        #[cfg(unix)]
+        {
+            let exit = wait_exit(&run("kill -TERM $$")).await;
+            assert_eq!(exit.exit_code, None);
+            assert_eq!(exit.signal, Some(15));
+            assert_eq!(exit.reason, ExitReason::Signal);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_signal() {
+        let (command, args) = sleeper();
+        let process =
+            PtyProcess::spawn(command, &args, &work_dir(), None, TerminalSize::default()).unwrap();
+
+        process.signal(SIGTERM).await.unwrap();
+        let exit = wait_exit(&process).await;
+        // Windows has no signals, SIGTERM terminates the process
+        assert_eq!(exit.signal, cfg!(unix).then_some(SIGTERM));
+        assert!(matches!(
+            process.signal(SIGTERM).await,
+            Err(PtyError::ProcessExited)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_echo_state() {
+        let (command, args) = shell("stty -echo; echo ready; sleep 30");
+        let mut process =
+            PtyProcess::spawn(command, &args, &work_dir(), None, TerminalSize::default()).unwrap();
+        assert!(read_until(&mut process, "ready").await);
+
+        let cooked = EchoState {
+            echo: true,
+            raw: false,
+        };
+        let state = process.echo_state().await.unwrap();
+        assert_eq!(
+            state,
+            EchoState {
+                echo: false,
+                ..cooked
+            }
+        );
+        let raw = process.set_raw_mode(true).await.unwrap();
+        assert_eq!(
+            raw,
+            EchoState {
+                echo: false,
+                raw: true
+            }
+        );
+        assert_eq!(process.set_raw_mode(false).await.unwrap(), cooked);
+        assert_eq!(process.echo_state().await.unwrap(), cooked);
+        process.kill().await.unwrap();
+    }
+
+    #[test]
+    fn test_supported_signals() {
+        for signal in [1, 2, 9, 15] {
+            assert!(is_supported_signal(signal));
+        }
+        assert!(!is_supported_signal(0));
+        assert!(!is_supported_signal(100));
+        assert_eq!(is_supported_signal(19), cfg!(unix));
+    }
+
+    #[tokio::test]
+    async fn test_exit_reason() {
+        assert_eq!(ExitReason::Normal, ExitReason::Normal);
+        assert_ne!(ExitReason::Normal, ExitReason::Killed);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_callbacks() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let output_received = Arc::new(AtomicBool::new(false));
+        let output_received_clone = Arc::clone(&output_received);
+
+        let exit_received = Arc::new(AtomicBool::new(false));
+        let exit_received_clone = Arc::clone(&exit_received);
+
+        let (command, args) = shell("echo callback test");
+        let process = PtyProcessWithCallbacks::spawn(
+            command,
+            &args,
+            &work_dir(),
+            None,
+            TerminalSize::default(),
+            move |_id, _data| {
+                output_received_clone.store(true, Ordering::SeqCst);
+            },
+            move |_exit| {
+                exit_received_clone.store(true, Ordering::SeqCst);
+            },
+        );
+
+        assert!(process.is_ok());
+        let _process = process.unwrap();
+
+        // Wait for output and exit
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        assert!(output_received.load(Ordering::SeqCst));
+
+        // Process should have exited (echo completes quickly)
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(exit_received.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_env() {
+        let mut env = HashMap::new();
+        env.insert("TEST_VAR".to_string(), "test_value".to_string());
+
+        let (command, args) = shell(if cfg!(windows) {
+            "echo %TEST_VAR%"
+        } else {
+            "echo $TEST_VAR"
+        });
+        let process = PtyProcess::spawn(
+            command,
+            &args,
+            &work_dir(),
+            Some(&env),
+            TerminalSize::default(),
+        );
+
+        assert!(process.is_ok());
+        let mut process = process.unwrap();
+
+        assert!(read_until(&mut process, "test_value").await);
+    }
+}
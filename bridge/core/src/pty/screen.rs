@@ -0,0 +1,143 @@
+//! Server-side terminal emulation
+//!
+//! Agent output is fed through a VT100 emulator so the bridge knows what each
+//! agent's terminal shows. Clients can ask for the resulting grid of cells
+//! instead of interpreting escape sequences themselves.
+
+use vt100::{Color, Parser};
+
+use super::InputModes;
+use crate::protocol::{ScreenCell, ScreenColor, ScreenState};
+
+/// The screen of one terminal, kept up to date from its output
+pub struct TerminalScreen {
+    parser: Parser,
+}
+
+impl TerminalScreen {
+    /// Create a blank screen of the given size
+    pub fn new(cols: u16, rows: u16) -> Self {
+        // Scrolled-off lines are not kept; clients have the raw output for that
+        Self {
+            parser: Parser::new(rows, cols, 0),
+        }
+    }
+
+    /// Apply terminal output
+    pub fn feed(&mut self, output: &[u8]) {
+        self.parser.process(output);
+    }
+
+    /// Resize the screen, as the application's terminal was resized
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.parser.screen_mut().set_size(rows, cols);
+    }
+
+    /// Input modes the application has switched on
+    pub fn input_modes(&self) -> InputModes {
+        let screen = self.parser.screen();
+        InputModes {
+            application_cursor: screen.application_cursor(),
+            bracketed_paste: screen.bracketed_paste(),
+        }
+    }
+
+    /// Current cursor and cells
+    pub fn state(&self) -> ScreenState {
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        let (cursor_row, cursor_col) = screen.cursor_position();
+        let cells = (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| screen.cell(row, col).map(cell).unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+
+        ScreenState {
+            cols,
+            rows,
+            cursor_row,
+            cursor_col,
+            cursor_visible: !screen.hide_cursor(),
+            alternate_screen: screen.alternate_screen(),
+            cells,
+        }
+    }
+}
+
+impl std::fmt::Debug for TerminalScreen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (rows, cols) = self.parser.screen().size();
+        f.debug_struct("TerminalScreen")
+            .field("cols", &cols)
+            .field("rows", &rows)
+            .finish()
+    }
+}
+
+fn cell(cell: &vt100::Cell) -> ScreenCell {
+    ScreenCell {
+        text: cell.contents().to_string(),
+        fg: color(cell.fgcolor()),
+        bg: color(cell.bgcolor()),
+        bold: cell.bold(),
+        dim: cell.dim(),
+        italic: cell.italic(),
+        underline: cell.underline(),
+        inverse: cell.inverse(),
+        wide: cell.is_wide(),
+    }
+}
+
+fn color(color: Color) -> Option<ScreenColor> {
+    match color {
+        Color::Default => None,
+        Color::Idx(index) => Some(ScreenColor::Index(index)),
+        Color::Rgb(r, g, b) => Some(ScreenColor::Rgb(r, g, b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_state() {
+        let mut screen = TerminalScreen::new(10, 3);
+        screen.feed(b"\x1b[1;31mok\x1b[0m \x1b[48;2;0;0;255mblue\x1b[0m\r\nline 2");
+
+        let state = screen.state();
+        assert_eq!((state.cols, state.rows), (10, 3));
+        assert_eq!((state.cursor_row, state.cursor_col), (1, 6));
+        assert!(state.cursor_visible);
+        assert_eq!(state.cells.len(), 3);
+        assert!(state.cells.iter().all(|row| row.len() == 10));
+
+        let ok = &state.cells[0][0];
+        assert_eq!(ok.text, "o");
+        assert_eq!(ok.fg, Some(ScreenColor::Index(1)));
+        assert!(ok.bold);
+        let blank = &state.cells[0][8];
+        assert_eq!(blank, &ScreenCell::default());
+        assert_eq!(state.cells[0][3].bg, Some(ScreenColor::Rgb(0, 0, 255)));
+        assert_eq!(state.cells[1][0].text, "l");
+    }
+
+    #[test]
+    fn test_alternate_screen_and_resize() {
+        let mut screen = TerminalScreen::new(80, 24);
+        screen.feed(b"\x1b[?1049h\x1b[?25l\x1b[5;10H\xe6\x97\xa5");
+        screen.resize(100, 30);
+
+        let state = screen.state();
+        assert!(state.alternate_screen);
+        assert!(!state.cursor_visible);
+        assert_eq!((state.cols, state.rows), (100, 30));
+        let wide = &state.cells[4][9];
+        assert_eq!(wide.text, "日");
+        assert!(wide.wide);
+        assert_eq!(state.cells[4][10].text, "");
+    }
+}
@@ -0,0 +1,128 @@
+//! SSH execution backend
+//!
+//! Runs agent commands on a remote host by wrapping them in an `ssh -tt`
+//! invocation. The local PTY is forwarded by ssh, so output, input and
+//! terminal resizes (SIGWINCH) behave exactly like a local agent.
+
+/// Remote host an agent should be executed on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshTarget {
+    /// Host name or ssh config alias (e.g. "build-box")
+    pub host: String,
+    /// Working directory on the remote host (defaults to the project path)
+    pub remote_dir: Option<String>,
+}
+
+impl SshTarget {
+    /// Create a new SSH target for the given host
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            remote_dir: None,
+        }
+    }
+
+    /// Set the remote working directory
+    pub fn with_remote_dir(mut self, dir: impl Into<String>) -> Self {
+        self.remote_dir = Some(dir.into());
+        self
+    }
+
+    /// Build the local `ssh` command line that runs `command` remotely
+    ///
+    /// `default_dir` is used as the remote working directory when no explicit
+    /// `remote_dir` is configured.
+    ///
+    /// # Returns
+    /// The program to execute locally and its arguments
+    pub fn command(&self, command: &str, args: &[String], default_dir: &str) -> (String, Vec<String>) {
+        let dir = self.remote_dir.as_deref().unwrap_or(default_dir);
+        self.ssh_command(Some(dir), command, args)
+    }
+
+    /// Build the local `ssh` command line that runs `command` remotely
+    ///
+    /// Runs from the configured `remote_dir`, or the login directory when
+    /// there is none.
+    pub fn command_in_home(&self, command: &str, args: &[String]) -> (String, Vec<String>) {
+        self.ssh_command(self.remote_dir.as_deref(), command, args)
+    }
+
+    fn ssh_command(&self, dir: Option<&str>, command: &str, args: &[String]) -> (String, Vec<String>) {
+        let mut remote = match dir {
+            Some(dir) => format!("cd {} && exec {}", shell_quote(dir), shell_quote(command)),
+            None => format!("exec {}", shell_quote(command)),
+        };
+        for arg in args {
+            remote.push(' ');
+            remote.push_str(&shell_quote(arg));
+        }
+
+        let ssh_args = vec![
+            // Force remote PTY allocation even though stdin is already a PTY
+            "-tt".to_string(),
+            // Never block on interactive password prompts nobody can see
+            "-o".to_string(),
+            "BatchMode=yes".to_string(),
+            self.host.clone(),
+            "--".to_string(),
+            remote,
+        ];
+
+        ("ssh".to_string(), ssh_args)
+    }
+}
+
+/// Quote a string for a POSIX shell
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty()
+        && s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("claude"), "claude");
+        assert_eq!(shell_quote("/home/me/project"), "/home/me/project");
+        assert_eq!(shell_quote("two words"), "'two words'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_ssh_command_default_dir() {
+        let target = SshTarget::new("build-box");
+        let (program, args) = target.command("claude", &["--verbose".to_string()], "/srv/app");
+        assert_eq!(program, "ssh");
+        assert_eq!(args[0], "-tt");
+        assert!(args.contains(&"build-box".to_string()));
+        assert_eq!(args.last().unwrap(), "cd /srv/app && exec claude --verbose");
+    }
+
+    #[test]
+    fn test_ssh_command_in_home() {
+        let target = SshTarget::new("build-box");
+        let args = vec!["attach-session".to_string(), "-t".to_string(), "work".to_string()];
+        let (_, ssh_args) = target.command_in_home("tmux", &args);
+        assert_eq!(ssh_args.last().unwrap(), "exec tmux attach-session -t work");
+    }
+
+    #[test]
+    fn test_ssh_command_remote_dir() {
+        let target = SshTarget::new("gpu").with_remote_dir("/data/my project");
+        let (_, args) = target.command("claude", &["-p".to_string(), "fix it".to_string()], "/local");
+        assert_eq!(
+            args.last().unwrap(),
+            "cd '/data/my project' && exec claude -p 'fix it'"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Managed tmux sessions
+//!
+//! With session persistence enabled, each local agent runs inside a detached
+//! tmux session named after its agent ID, and the bridge's PTY only hosts a
+//! tmux client attached to it. The agent process therefore outlives the
+//! bridge; on startup the bridge finds the surviving sessions and re-attaches
+//! them under their original IDs.
+
+use tokio::process::Command;
+use uuid::Uuid;
+
+use super::{ExternalSession, Multiplexer, PtyError, PtyResult, TerminalSize};
+
+/// Prefix of tmux session names owned by the bridge
+pub const MANAGED_SESSION_PREFIX: &str = "hoc-";
+
+/// A bridge-managed tmux session found on startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedSession {
+    /// Agent the session belongs to
+    pub agent_id: Uuid,
+    /// Directory the session was started in
+    pub project_path: String,
+}
+
+/// Name of the managed tmux session for an agent
+pub fn managed_session_name(agent_id: Uuid) -> String {
+    format!("{}{}", MANAGED_SESSION_PREFIX, agent_id)
+}
+
+/// The session to attach to for a managed agent
+pub fn managed_session(agent_id: Uuid) -> ExternalSession {
+    ExternalSession::new(Multiplexer::Tmux, managed_session_name(agent_id))
+}
+
+/// Start a detached managed session running `command`, unless it already exists
+pub async fn ensure_managed_session(
+    agent_id: Uuid,
+    project_path: &str,
+    command: &str,
+    args: &[String],
+    size: TerminalSize,
+) -> PtyResult<()> {
+    let name = managed_session_name(agent_id);
+    if tmux(&["has-session", "-t", &name]).await.is_ok() {
+        return Ok(());
+    }
+
+    let cols = size.cols.to_string();
+    let rows = size.rows.to_string();
+    let mut tmux_args = vec![
+        "new-session", "-d", "-s", &name, "-c", project_path, "-x", &cols, "-y", &rows, "--",
+        command,
+    ];
+    tmux_args.extend(args.iter().map(String::as_str));
+    tmux(&tmux_args).await.map_err(PtyError::SpawnFailed)?;
+
+    // The client only forwards the agent's screen; hide tmux's own status line
+    tmux(&["set-option", "-t", &name, "status", "off"])
+        .await
+        .map_err(PtyError::SpawnFailed)?;
+    Ok(())
+}
+
+/// Kill an agent's managed session, if it still exists
+pub async fn kill_managed_session(agent_id: Uuid) -> PtyResult<()> {
+    let name = managed_session_name(agent_id);
+    if tmux(&["has-session", "-t", &name]).await.is_err() {
+        return Ok(());
+    }
+    tmux(&["kill-session", "-t", &name])
+        .await
+        .map_err(PtyError::SystemError)?;
+    Ok(())
+}
+
+/// List the managed sessions currently running
+///
+/// Returns an empty list when tmux is not installed or no server is running.
+pub async fn list_managed_sessions() -> Vec<ManagedSession> {
+    match tmux(&["list-sessions", "-F", "#{session_name}\t#{session_path}"]).await {
+        Ok(output) => parse_managed_sessions(&output),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Parse `list-sessions` output, keeping only bridge-managed sessions
+fn parse_managed_sessions(output: &str) -> Vec<ManagedSession> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, path) = line.split_once('\t')?;
+            let agent_id = Uuid::parse_str(name.strip_prefix(MANAGED_SESSION_PREFIX)?).ok()?;
+            Some(ManagedSession {
+                agent_id,
+                project_path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Run a tmux command, returning its stdout or an error description
+async fn tmux(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("tmux")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("failed to run tmux: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "tmux {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_managed_session_name() {
+        let id = Uuid::new_v4();
+        let session = managed_session(id);
+        assert_eq!(session.target, format!("hoc-{}", id));
+        assert_eq!(session.multiplexer, Multiplexer::Tmux);
+    }
+
+    #[test]
+    fn test_parse_managed_sessions() {
+        let id = Uuid::new_v4();
+        let output = format!(
+            "work\t/home/me\nhoc-{}\t/srv/my project\nhoc-not-a-uuid\t/tmp\n",
+            id
+        );
+        let sessions = parse_managed_sessions(&output);
+        assert_eq!(
+            sessions,
+            vec![ManagedSession {
+                agent_id: id,
+                project_path: "/srv/my project".to_string(),
+            }]
+        );
+    }
+}
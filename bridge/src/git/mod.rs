@@ -1,9 +0,0 @@
-//! Git operations module
-//!
-//! Provides git repository detection and worktree management.
-
-#[allow(dead_code)]
-mod worktree;
-
-#[allow(unused_imports)]
-pub use worktree::*;
@@ -3,20 +3,28 @@
 //! WebSocket bridge for VR agent orchestration. Manages PTY sessions for Claude Code
 //! agents and streams output to Godot clients over WebSocket.
 
-mod agent;
-mod config;
-mod git;
-mod pty;
 mod server;
 
-use std::sync::Arc;
+use hoc_bridge_core::{agent, config, fs, git, pty};
 
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
 use clap::Parser;
 use tokio::signal;
-use tracing::{info, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
-use server::{ServerConfig, WebSocketServer};
+use config::{BridgeConfig, LoggingConfig, SecretSource, SecretStore, TranscriptConfig};
+use server::{
+    AgentLimits, AuditLog, Capability, ClusterConfig, ConfigSource, ConnectionLimits, ControlPolicy,
+    DiscoveryConfig, EnvPolicy, HeartbeatConfig, InputPolicy, OriginPolicy, PeerConfig, QuicConfig,
+    ReloadableConfig, ServerConfig, TerminalLimits, TlsConfig, WebSocketServer,
+    DEFAULT_MAX_RUNNING_TASKS, DEFAULT_MESSAGE_RATE,
+};
 
 /// Halls of Creation Bridge Server
 ///
@@ -25,9 +33,13 @@ use server::{ServerConfig, WebSocketServer};
 #[command(name = "hoc-bridge")]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Port to listen on
-    #[arg(short, long, default_value_t = 9000)]
-    port: u16,
+    /// Configuration file (default ~/.config/hoc/bridge.toml, if it exists); flags override it
+    #[arg(long, value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+
+    /// Port to listen on [default: 9000]
+    #[arg(short, long)]
+    port: Option<u16>,
 
     /// Enable verbose logging
     #[arg(short, long)]
@@ -37,31 +49,249 @@ struct Args {
     #[arg(long)]
     token: Option<String>,
 
-    /// Bind address
-    #[arg(long, default_value = "127.0.0.1")]
-    bind: String,
+    /// Bind address [default: 127.0.0.1]
+    #[arg(long)]
+    bind: Option<String>,
+
+    /// PEM certificate chain to serve wss:// with
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    tls_cert: Option<std::path::PathBuf>,
+
+    /// PEM private key for the certificate
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    tls_key: Option<std::path::PathBuf>,
+
+    /// Upstream peer bridge to federate, as NAME=URL (repeatable)
+    #[arg(long = "peer", value_name = "NAME=URL", value_parser = PeerConfig::parse)]
+    peers: Vec<PeerConfig>,
+
+    /// Authentication token for a peer bridge, as NAME=TOKEN (repeatable)
+    #[arg(long = "peer-token", value_name = "NAME=TOKEN")]
+    peer_tokens: Vec<String>,
+
+    /// Relay endpoint to dial out to, serving clients through it (reverse-tunnel mode)
+    #[arg(long, value_name = "URL")]
+    relay: Option<String>,
+
+    /// Only accept WebSocket upgrades on this URL path (e.g. /ws)
+    #[arg(long, value_name = "PATH")]
+    path: Option<String>,
+
+    /// Reverse proxy address whose X-Forwarded-For/-Proto headers are trusted (repeatable)
+    #[arg(long = "trusted-proxy", value_name = "IP")]
+    trusted_proxies: Vec<std::net::IpAddr>,
+
+    /// Origin of a browser page allowed to connect, e.g. https://app.example.com, or * for
+    /// any (repeatable)
+    #[arg(long = "allowed-origin", value_name = "ORIGIN")]
+    allowed_origins: Vec<String>,
+
+    /// Host name browsers may reach the bridge by, besides IP addresses and localhost
+    /// (repeatable)
+    #[arg(long = "allowed-host", value_name = "HOST")]
+    allowed_hosts: Vec<String>,
+
+    /// Serve a read-only web dashboard on this HTTP port
+    #[arg(long, value_name = "PORT")]
+    dashboard_port: Option<u16>,
+
+    /// Serve the gRPC API on this port
+    #[arg(long, value_name = "PORT")]
+    grpc_port: Option<u16>,
+
+    /// Join a cluster whose nodes share state through this directory (e.g. a shared volume)
+    #[arg(long, value_name = "DIR", requires = "advertise_url")]
+    cluster_dir: Option<std::path::PathBuf>,
+
+    /// WebSocket URL other cluster nodes use to reach this instance
+    #[arg(long, value_name = "URL")]
+    advertise_url: Option<String>,
+
+    /// Cluster node ID (random if not set)
+    #[arg(long, value_name = "ID")]
+    node_id: Option<String>,
+
+    /// Also serve the protocol over QUIC and WebTransport on this UDP port (experimental)
+    #[arg(long, value_name = "PORT", requires_all = ["quic_cert", "quic_key"])]
+    quic_port: Option<u16>,
+
+    /// PEM certificate chain for the QUIC listener
+    #[arg(long, value_name = "FILE")]
+    quic_cert: Option<std::path::PathBuf>,
+
+    /// PEM private key for the QUIC listener
+    #[arg(long, value_name = "FILE")]
+    quic_key: Option<std::path::PathBuf>,
+
+    /// Announce the bridge on the local network over mDNS (_hoc-bridge._tcp) so headsets can
+    /// find it
+    #[arg(long)]
+    mdns: bool,
+
+    /// Name to announce the bridge under [default: the host name]
+    #[arg(long, value_name = "NAME", requires = "mdns")]
+    mdns_name: Option<String>,
+
+    /// Run agents in managed tmux sessions that survive bridge restarts
+    #[arg(long)]
+    tmux_sessions: bool,
+
+    /// Largest terminal clients may request, as COLSxROWS [default: 500x200]
+    #[arg(long, value_name = "COLSxROWS", value_parser = parse_size)]
+    max_terminal_size: Option<(u16, u16)>,
+
+    /// Terminal size for clients that don't request one, as COLSxROWS [default: 80x24]
+    #[arg(long, value_name = "COLSxROWS", value_parser = parse_size)]
+    default_terminal_size: Option<(u16, u16)>,
+
+    /// Simulate agents that echo their input instead of running claude (for client development)
+    #[arg(long, conflicts_with = "tmux_sessions")]
+    simulate: bool,
+
+    /// Handling of terminal control strings (OSC, DCS, ...) in agent input: allow, strip or reject
+    #[arg(long, value_name = "POLICY", default_value = "strip")]
+    input_control: ControlPolicy,
+
+    /// Maximum input bytes per second per agent
+    #[arg(long, value_name = "BYTES")]
+    input_rate_limit: Option<u32>,
+    /// Maximum messages per second per connection (0 for unlimited)
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MESSAGE_RATE)]
+    message_rate_limit: u32,
+    /// Maximum agent input bytes per second per connection, over all its agents
+    #[arg(long, value_name = "BYTES")]
+    connection_input_rate_limit: Option<u32>,
+    /// Regular expression redacted from recorded input history (repeatable)
+    #[arg(long = "redact-input", value_name = "REGEX", value_parser = regex::Regex::new)]
+    input_redactions: Vec<regex::Regex>,
+    /// Also redact secrets from agent output streamed to clients
+    #[arg(long)]
+    redact_output: bool,
+
+    /// Maximum agents running at once
+    #[arg(long, value_name = "N")]
+    max_agents: Option<usize>,
+
+    /// Maximum agents running at once for each client
+    #[arg(long, value_name = "N")]
+    max_agents_per_client: Option<usize>,
+
+    /// File of NAME=value lines holding secrets presets can request
+    #[arg(long, value_name = "FILE")]
+    secrets_file: Option<std::path::PathBuf>,
+
+    /// Look up secrets in the OS keyring under this service name
+    #[arg(long, value_name = "SERVICE")]
+    secrets_keyring: Option<String>,
+
+    /// Command printing the secret named by its last argument (e.g. "pass show")
+    #[arg(long, value_name = "COMMAND")]
+    secrets_command: Option<String>,
+
+    /// Only let presets and clients set these environment variables for agents (repeatable,
+    /// `NAME*` matches a prefix)
+    #[arg(long = "allow-env", value_name = "NAME")]
+    allowed_env: Vec<String>,
+
+    /// Refuse this environment variable for agents, besides PATH, LD_* and the like (repeatable)
+    #[arg(long = "deny-env", value_name = "NAME")]
+    denied_env: Vec<String>,
+
+    /// Refuse a group of operations: git, files, spawn or clipboard (repeatable)
+    #[arg(long = "disable", value_name = "CAPABILITY")]
+    disabled_capabilities: Vec<Capability>,
+
+    /// Milliseconds agent output is held so small writes go out as one message (0 disables)
+    #[arg(long, value_name = "MS", default_value_t = 16)]
+    coalesce_ms: u64,
+
+    /// Seconds between pings to WebSocket clients (0 disables)
+    #[arg(long, value_name = "SECS", default_value_t = 20)]
+    heartbeat_secs: u64,
+
+    /// Unanswered pings in a row after which a WebSocket connection is closed
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    heartbeat_misses: u32,
+
+    /// Seconds the session of a lost connection can be resumed in (0 disables)
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    resume_grace_secs: u64,
+
+    /// Seconds a killed agent gets to exit after SIGTERM before it is sent SIGKILL
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    kill_grace_secs: u64,
+
+    /// Minutes without input or output after which an agent is stopped (presets may override)
+    #[arg(long, value_name = "MINS")]
+    idle_timeout_mins: Option<u64>,
+
+    /// Queued tasks run at once, one per project at most
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_RUNNING_TASKS)]
+    max_running_tasks: usize,
+
+    /// Directory clients may browse files under (repeatable; defaults to agents' projects)
+    #[arg(long = "allowed-root", value_name = "DIR")]
+    allowed_roots: Vec<std::path::PathBuf>,
+
+    /// Log each agent's output to .hoc/logs/<agent-id>.log in its project, with rotation
+    #[arg(long)]
+    transcripts: bool,
+
+    /// Also log the input sent to agents to .hoc/logs/<agent-id>.input.log, unredacted
+    #[arg(long)]
+    transcript_input: bool,
+
+    /// Append a JSON line for every connection, spawn, input, kill and config change to this file
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<std::path::PathBuf>,
+
+    /// Serve the launching process over stdin/stdout instead of listening on a port, logging
+    /// to stderr
+    #[arg(long, conflicts_with_all = ["relay", "quic_port", "cluster_dir"])]
+    stdio: bool,
+
+    /// Serve the launching process as a Model Context Protocol server over stdin/stdout instead
+    /// of listening on a port, logging to stderr
+    #[arg(long, conflicts_with_all = ["stdio", "relay", "quic_port", "cluster_dir"])]
+    mcp: bool,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-
-    // Initialize logging
-    let log_level = if args.verbose {
-        Level::DEBUG
-    } else {
-        Level::INFO
-    };
-
-    FmtSubscriber::builder()
-        .with_max_level(log_level)
-        .with_target(false)
-        .compact()
-        .init();
+    let (config_path, file) = load_config(args.config.as_deref())?;
+    init_logging(args.verbose, &file.logging, args.stdio || args.mcp)?;
 
     info!("Halls of Creation Bridge v{}", env!("CARGO_PKG_VERSION"));
+    if let Some(ref path) = config_path {
+        info!("Loaded configuration from {}", path.display());
+    }
+
+    // Flags override the configuration file, also when it is reloaded
+    let flags = ReloadableConfig {
+        token: args.token,
+        tokens: Vec::new(),
+        allowed_roots: args.allowed_roots,
+        agent_limits: AgentLimits::default()
+            .with_max_agents(args.max_agents)
+            .with_max_per_client(args.max_agents_per_client),
+    };
+    let reloadable = ReloadableConfig::merge(&flags, &file);
+    let config_source = config_path
+        .or_else(BridgeConfig::default_path)
+        .map(|path| ConfigSource::new(path, flags));
+    let max_terminal_size = match args.max_terminal_size {
+        Some(size) => size,
+        None => parse_size(file.max_terminal_size.as_deref().unwrap_or("500x200"))
+            .map_err(anyhow::Error::msg)?,
+    };
+    let default_terminal_size = match args.default_terminal_size {
+        Some(size) => size,
+        None => parse_size(file.default_terminal_size.as_deref().unwrap_or("80x24"))
+            .map_err(anyhow::Error::msg)?,
+    };
 
-    if let Some(ref token) = args.token {
+    if let Some(ref token) = reloadable.token {
         info!("Token authentication enabled");
         // Only show a hint of the token for verification, not the full value
         let hint = if token.len() > 8 {
@@ -71,9 +301,160 @@ async fn main() -> anyhow::Result<()> {
         };
         info!("Auth token configured (hint: {})", hint);
     }
+    for entry in &reloadable.tokens {
+        let name = entry.name.as_deref().unwrap_or("unnamed");
+        info!("Token for {} configured with the {} role", name, entry.role.as_str());
+    }
+
+    // Attach tokens to their peers
+    let mut peers = args.peers;
+    for spec in &args.peer_tokens {
+        let Some((name, token)) = spec.split_once('=') else {
+            anyhow::bail!("invalid peer token '{}', expected NAME=TOKEN", spec);
+        };
+        match peers.iter_mut().find(|p| p.name == name) {
+            Some(peer) => peer.token = Some(token.to_string()),
+            None => anyhow::bail!("peer token given for unknown peer '{}'", name),
+        }
+    }
+    for peer in &peers {
+        info!("Federating with peer bridge {} at {}", peer.name, peer.url);
+    }
+
+    let tls = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => Some(TlsConfig::new(cert, key)),
+        _ => None,
+    };
+
+    let quic = match (args.quic_port, args.quic_cert, args.quic_key) {
+        (Some(port), Some(cert), Some(key)) => Some(QuicConfig::new(port, cert, key)),
+        _ => None,
+    };
+
+    let cluster = match (args.cluster_dir, args.advertise_url) {
+        (Some(dir), Some(url)) => {
+            let node_id = args
+                .node_id
+                .unwrap_or_else(|| format!("node-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]));
+            Some(ClusterConfig::new(node_id, url, dir))
+        }
+        _ => None,
+    };
+
+    let mdns = match args.mdns_name {
+        Some(name) => Some(DiscoveryConfig::new(name)),
+        None if args.mdns => Some(DiscoveryConfig::from_hostname()),
+        None => None,
+    };
+
+    let (max_cols, max_rows) = max_terminal_size;
+    let (default_cols, default_rows) = default_terminal_size;
+    let terminal = TerminalLimits::default()
+        .with_max_size(max_cols, max_rows)
+        .with_default_size(default_cols, default_rows);
+    terminal.validate().map_err(anyhow::Error::msg)?;
+
+    // Secret sources, consulted in this order
+    let mut secrets = SecretStore::default();
+    if let Some(path) = args.secrets_file {
+        secrets = secrets.with_source(SecretSource::EnvFile(path));
+    }
+    if let Some(service) = args.secrets_keyring {
+        secrets = secrets.with_source(SecretSource::Keyring(service));
+    }
+    if let Some(command) = args.secrets_command {
+        secrets = secrets.with_source(SecretSource::Command(command));
+    }
+
+    let transcript = if args.transcripts {
+        Some(file.transcript.unwrap_or_default())
+    } else {
+        file.transcript
+    }
+    .map(|transcript| TranscriptConfig {
+        input: transcript.input || args.transcript_input,
+        ..transcript
+    });
+
+    let audit_log = match args.audit_log.or(file.audit_log) {
+        Some(path) => {
+            let log = AuditLog::open(&path)
+                .with_context(|| format!("failed to open audit log {}", path.display()))?;
+            info!("Writing audit log to {}", path.display());
+            Some(log)
+        }
+        None => None,
+    };
+
+    if cfg!(windows) && args.tmux_sessions {
+        anyhow::bail!("--tmux-sessions needs tmux, which is not available on Windows");
+    }
 
     // Create server configuration
-    let config = ServerConfig::new(args.bind, args.port).with_token(args.token);
+    let bind = args.bind.or(file.bind).unwrap_or_else(|| "127.0.0.1".to_string());
+    let port = args.port.or(file.port).unwrap_or(9000);
+    let config = ServerConfig::new(bind, port)
+        .with_token(reloadable.token)
+        .with_tokens(reloadable.tokens)
+        .with_tls(tls)
+        .with_peers(peers)
+        .with_relay(args.relay)
+        .with_ws_path(args.path)
+        .with_trusted_proxies(args.trusted_proxies)
+        .with_origin_policy(
+            OriginPolicy::default()
+                .with_allowed_origins(or_file(args.allowed_origins, file.allowed_origins))
+                .with_allowed_hosts(or_file(args.allowed_hosts, file.allowed_hosts)),
+        )
+        .with_dashboard_port(args.dashboard_port)
+        .with_quic(quic)
+        .with_grpc_port(args.grpc_port)
+        .with_cluster(cluster)
+        .with_mdns(mdns)
+        .with_persistent_sessions(args.tmux_sessions)
+        .with_input_policy(
+            InputPolicy::default()
+                .with_control(args.input_control)
+                .with_rate_limit(args.input_rate_limit),
+        )
+        .with_input_redactions(args.input_redactions)
+        .with_output_redaction(args.redact_output)
+        .with_simulation(args.simulate)
+        .with_terminal_limits(terminal)
+        .with_disabled_capabilities(args.disabled_capabilities)
+        .with_transcript(transcript)
+        .with_allowed_roots(reloadable.allowed_roots)
+        .with_secrets(secrets)
+        .with_env_policy(
+            EnvPolicy::default()
+                .with_allowed(args.allowed_env)
+                .with_denied(args.denied_env),
+        )
+        .with_coalesce_window(Duration::from_millis(args.coalesce_ms))
+        .with_heartbeat(
+            HeartbeatConfig::default()
+                .with_interval(Some(Duration::from_secs(args.heartbeat_secs)))
+                .with_max_missed(args.heartbeat_misses),
+        )
+        .with_resume_grace(Some(Duration::from_secs(args.resume_grace_secs)))
+        .with_kill_grace(Duration::from_secs(args.kill_grace_secs))
+        .with_idle_timeout(
+            args.idle_timeout_mins
+                .filter(|&mins| mins > 0)
+                .map(|mins| Duration::from_secs(mins * 60)),
+        )
+        .with_connection_limits(
+            ConnectionLimits::default()
+                .with_messages_per_sec(Some(args.message_rate_limit).filter(|&n| n > 0))
+                .with_input_bytes_per_sec(args.connection_input_rate_limit),
+        )
+        .with_agent_limits(reloadable.agent_limits)
+        .with_max_running_tasks(args.max_running_tasks)
+        .with_presets(file.presets)
+        .with_schedules(file.schedules)
+        .with_webhooks(file.webhooks)
+        .with_config_source(config_source)
+        .with_audit_log(audit_log);
 
     // Create and start the WebSocket server
     let server = Arc::new(WebSocketServer::new(config));
@@ -86,13 +467,104 @@ async fn main() -> anyhow::Result<()> {
         server_handle.shutdown();
     });
 
+    // Reload the configuration file on SIGHUP
+    #[cfg(unix)]
+    {
+        let server_handle = Arc::clone(&server);
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+            .context("failed to install SIGHUP handler")?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading configuration");
+                // The outcome is logged; a failed reload keeps the settings
+                let _ = server_handle.reload_config();
+            }
+        });
+    }
+
     // Run the server
-    server.run().await?;
+    if args.mcp {
+        server.run_mcp().await?;
+    } else if args.stdio {
+        server.run_stdio().await?;
+    } else {
+        server.run().await?;
+    }
 
     info!("Server shutdown complete");
     Ok(())
 }
 
+/// Load the configuration file given on the command line, else the default
+/// one if it exists, returning where it was read from
+fn load_config(path: Option<&Path>) -> anyhow::Result<(Option<PathBuf>, BridgeConfig)> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match BridgeConfig::default_path().filter(|path| path.exists()) {
+            Some(path) => path,
+            None => return Ok((None, BridgeConfig::default())),
+        },
+    };
+    let config = BridgeConfig::load(&path)
+        .with_context(|| format!("failed to load configuration from {}", path.display()))?;
+    Ok((Some(path), config))
+}
+
+/// Log to standard output, or the configured file, at the configured level
+/// (`debug` with `--verbose`)
+///
+/// With `stdio` standard output carries the protocol (or MCP), so logs go to standard
+/// error instead.
+fn init_logging(verbose: bool, logging: &LoggingConfig, stdio: bool) -> anyhow::Result<()> {
+    let level = match (verbose, &logging.level) {
+        (true, _) => "debug",
+        (false, Some(level)) => level.as_str(),
+        (false, None) => "info",
+    };
+    let filter = EnvFilter::try_new(level)
+        .with_context(|| format!("invalid log level '{}'", level))?;
+    let writer = match &logging.file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open log file {}", path.display()))?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None if stdio => BoxMakeWriter::new(std::io::stderr),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+
+    FmtSubscriber::builder()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(logging.file.is_none())
+        .with_target(false)
+        .compact()
+        .init();
+    Ok(())
+}
+
+/// Values given as flags, else those of the configuration file
+fn or_file<T>(flags: Vec<T>, file: Vec<T>) -> Vec<T> {
+    if flags.is_empty() {
+        file
+    } else {
+        flags
+    }
+}
+
+/// Parse a terminal size given as COLSxROWS
+fn parse_size(s: &str) -> Result<(u16, u16), String> {
+    let invalid = || format!("invalid terminal size '{}', expected COLSxROWS", s);
+    let (cols, rows) = s.split_once('x').ok_or_else(invalid)?;
+    Ok((
+        cols.parse().map_err(|_| invalid())?,
+        rows.parse().map_err(|_| invalid())?,
+    ))
+}
+
 /// Wait for shutdown signal (SIGTERM or SIGINT)
 async fn shutdown_signal() {
     let ctrl_c = async {
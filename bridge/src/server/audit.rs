@@ -0,0 +1,336 @@
+//! Audit log
+//!
+//! Teams sharing a bridge can have it append one JSON line to a file for
+//! every authenticated connection, spawn, input, kill and configuration
+//! change, saying when it happened and which client did it. Input is
+//! recorded by length only, so nothing typed into an agent ends up in the
+//! log.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::protocol::{ClientMessage, Role, ServerMessage};
+
+/// Something worth auditing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A client connected, authenticating if the server requires it
+    Connected,
+    /// A client failed to authenticate
+    AuthFailed,
+    /// A connected client went away
+    Disconnected,
+    Spawn {
+        project_path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preset: Option<String>,
+        /// The spawned agent, unless spawning failed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        agent_id: Option<Uuid>,
+    },
+    /// Input sent to an agent, of which only the length is kept
+    Input { agent_id: Uuid, length: usize },
+    /// A named key sent to an agent, such as `ctrl+c`
+    Key { agent_id: Uuid, key: String },
+    /// Input sent to several agents at once, of which only the length is kept
+    BroadcastInput {
+        /// Agents the input was sent to
+        agent_ids: Vec<Uuid>,
+        length: usize,
+    },
+    Kill {
+        agent_id: Uuid,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signal: Option<i32>,
+    },
+    ReloadConfig {
+        /// Settings that changed
+        #[serde(default)]
+        changed: Vec<String>,
+    },
+    Shutdown,
+}
+
+impl AuditEvent {
+    /// The event a request stands for, `None` if it isn't audited
+    pub(super) fn for_request(message: &ClientMessage) -> Option<Self> {
+        match message {
+            ClientMessage::SpawnAgent {
+                project_path,
+                preset,
+                ..
+            } => Some(Self::Spawn {
+                project_path: project_path.clone(),
+                preset: preset.clone(),
+                agent_id: None,
+            }),
+            ClientMessage::AgentInput {
+                agent_id,
+                input: data,
+                ..
+            }
+            | ClientMessage::AgentInputRaw { agent_id, data }
+            | ClientMessage::AgentInputChunk { agent_id, data, .. } => Some(Self::Input {
+                agent_id: *agent_id,
+                length: data.len(),
+            }),
+            ClientMessage::AgentKey { agent_id, key } => Some(Self::Key {
+                agent_id: *agent_id,
+                key: key.clone(),
+            }),
+            // The targets are known once the input is sent
+            ClientMessage::BroadcastInput { input, .. } => Some(Self::BroadcastInput {
+                agent_ids: Vec::new(),
+                length: input.len(),
+            }),
+            ClientMessage::KillAgent { agent_id, signal } => Some(Self::Kill {
+                agent_id: *agent_id,
+                signal: *signal,
+            }),
+            ClientMessage::ReloadConfig => Some(Self::ReloadConfig {
+                changed: Vec::new(),
+            }),
+            ClientMessage::Shutdown => Some(Self::Shutdown),
+            _ => None,
+        }
+    }
+
+    /// Complete the event with what the request's response says, returning
+    /// the error the request failed with, if any
+    pub(super) fn complete(&mut self, response: Option<&ServerMessage>) -> Option<String> {
+        match (self, response) {
+            (_, Some(ServerMessage::Error { message, .. })) => Some(message.clone()),
+            (
+                Self::Spawn { agent_id, .. },
+                Some(ServerMessage::AgentSpawned { agent_id: id, .. }),
+            ) => {
+                *agent_id = Some(*id);
+                None
+            }
+            (
+                Self::BroadcastInput { agent_ids, .. },
+                Some(ServerMessage::InputBroadcast { results }),
+            ) => {
+                agent_ids.extend(results.iter().filter(|r| r.ok).map(|r| r.agent_id));
+                None
+            }
+            (
+                Self::ReloadConfig { changed },
+                Some(ServerMessage::ConfigReloaded { changed: reloaded }),
+            ) => {
+                changed.clone_from(reloaded);
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// When it happened, in milliseconds since the Unix epoch
+    pub ts_ms: u64,
+    /// Client address, or `local` for changes made on the bridge's host,
+    /// such as a reload on SIGHUP
+    pub client: String,
+    /// Name of the token the client authenticated with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Role the client had
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    /// Why the request failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    /// A record of an event happening now
+    pub fn new(client: impl Into<String>, event: AuditEvent) -> Self {
+        Self {
+            ts_ms: now_ms(),
+            client: client.into(),
+            token: None,
+            role: None,
+            event,
+            error: None,
+        }
+    }
+
+    /// Record the role and token name the client authenticated with
+    pub fn with_identity(mut self, role: Role, token: Option<&str>) -> Self {
+        self.role = Some(role);
+        self.token = token.map(str::to_string);
+        self
+    }
+
+    /// Record why the request failed
+    pub fn with_error(mut self, error: Option<String>) -> Self {
+        self.error = error;
+        self
+    }
+}
+
+/// Audit log file, appended to by every connection
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl AuditLog {
+    /// Open a log file for appending, creating it and its directory if needed
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Append a record; failures are logged rather than failing the request
+    pub fn write(&self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to encode audit record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(&line) {
+            warn!("Failed to write audit log {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Records the end of a connection when dropped, however it ends
+pub(super) struct DisconnectRecord {
+    log: Option<AuditLog>,
+    record: AuditRecord,
+}
+
+impl DisconnectRecord {
+    pub(super) fn new(log: Option<AuditLog>, record: AuditRecord) -> Self {
+        Self { log, record }
+    }
+}
+
+impl Drop for DisconnectRecord {
+    fn drop(&mut self) {
+        if let Some(log) = &self.log {
+            self.record.ts_ms = now_ms();
+            log.write(&self.record);
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_request_events() {
+        let agent_id = Uuid::new_v4();
+        let input = ClientMessage::AgentInput {
+            agent_id,
+            input: "password\n".to_string(),
+            paste: false,
+        };
+        assert_eq!(
+            AuditEvent::for_request(&input),
+            Some(AuditEvent::Input {
+                agent_id,
+                length: 9
+            })
+        );
+        let key = ClientMessage::AgentKey {
+            agent_id,
+            key: "ctrl+c".to_string(),
+        };
+        assert_eq!(
+            AuditEvent::for_request(&key),
+            Some(AuditEvent::Key {
+                agent_id,
+                key: "ctrl+c".to_string()
+            })
+        );
+        assert_eq!(AuditEvent::for_request(&ClientMessage::Ping { seq: 1 }), None);
+
+        let spawn = ClientMessage::SpawnAgent {
+            project_path: "/srv/app".to_string(),
+            preset: None,
+            cols: None,
+            rows: None,
+            tags: Vec::new(),
+            group: None,
+            name: None,
+            mode: None,
+            prompt: None,
+            env: BTreeMap::new(),
+            cwd: None,
+            report_echo: false,
+        };
+        let mut event = AuditEvent::for_request(&spawn).unwrap();
+        let spawned = ServerMessage::AgentSpawned {
+            agent_id,
+            project_path: "/srv/app".to_string(),
+            cols: 80,
+            rows: 24,
+        };
+        assert_eq!(event.complete(Some(&spawned)), None);
+        assert!(matches!(event, AuditEvent::Spawn { agent_id: Some(id), .. } if id == agent_id));
+
+        let mut event = AuditEvent::for_request(&ClientMessage::Shutdown).unwrap();
+        let refused = ServerMessage::error("Not now");
+        assert_eq!(event.complete(Some(&refused)).as_deref(), Some("Not now"));
+    }
+
+    #[test]
+    fn test_write_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit/bridge.jsonl");
+        let log = AuditLog::open(&path).unwrap();
+        log.write(
+            &AuditRecord::new("10.0.0.5:51234", AuditEvent::Connected)
+                .with_identity(Role::Operator, Some("alice")),
+        );
+        log.write(&AuditRecord::new("local", AuditEvent::ReloadConfig { changed: vec![] }));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<AuditRecord> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].token.as_deref(), Some("alice"));
+        assert_eq!(records[0].event, AuditEvent::Connected);
+        assert_eq!(records[1].role, None);
+        assert!(content.starts_with(r#"{"ts_ms":"#));
+        assert!(content.contains(r#""event":"connected""#));
+    }
+}
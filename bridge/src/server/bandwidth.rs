@@ -0,0 +1,307 @@
+//! Per-client bandwidth and adaptive output quality
+//!
+//! Every connection counts the bytes sent to it, so `list_clients` can show
+//! who is using the link. A client that falls behind the agent event stream
+//! (its receiver lags, or sends to it stall) is stepped down a quality tier:
+//! from every output chunk, to output coalesced per agent, to status events
+//! only. After a quiet period it is stepped back up, one tier at a time.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use super::protocol::{ClientInfo, QualityTier};
+use super::transport::TransportSender;
+
+/// Period over which the send rate is measured
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// A send taking longer than this counts as congestion
+pub(super) const SLOW_SEND: Duration = Duration::from_millis(250);
+
+/// Interval at which coalesced output is flushed
+pub(super) const COALESCE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Minimum time between two steps down, so one burst of lag costs one tier
+const STEP_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Time without congestion before quality is stepped back up
+const RECOVERY_AFTER: Duration = Duration::from_secs(30);
+
+/// Bytes sent to a client per window
+#[derive(Debug)]
+struct RateMeter {
+    window_start: Instant,
+    window_bytes: u64,
+    /// Rate over the last complete window
+    rate: u64,
+}
+
+impl RateMeter {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            window_bytes: 0,
+            rate: 0,
+        }
+    }
+
+    fn record(&mut self, bytes: usize, now: Instant) {
+        if now.duration_since(self.window_start) >= RATE_WINDOW {
+            self.rate = self.bytes_per_sec(now);
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes as u64;
+    }
+
+    fn bytes_per_sec(&self, now: Instant) -> u64 {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < RATE_WINDOW {
+            return self.rate;
+        }
+        // The window is overdue, so the client has been quiet since
+        self.window_bytes * 1000 / elapsed.as_millis().max(1) as u64
+    }
+}
+
+/// Traffic and quality of one connection
+#[derive(Debug)]
+pub(super) struct ClientStats {
+    client: String,
+    connected_at_ms: u64,
+    bytes_sent: u64,
+    meter: RateMeter,
+    quality: QualityTier,
+}
+
+impl ClientStats {
+    fn info(&self, now: Instant) -> ClientInfo {
+        ClientInfo {
+            client: self.client.clone(),
+            connected_at_ms: self.connected_at_ms,
+            bytes_sent: self.bytes_sent,
+            bytes_per_sec: self.meter.bytes_per_sec(now),
+            quality: self.quality,
+        }
+    }
+}
+
+type SharedStats = Arc<Mutex<ClientStats>>;
+
+/// Connected clients and their traffic
+#[derive(Default)]
+pub(super) struct ClientRegistry {
+    clients: Mutex<HashMap<Uuid, SharedStats>>,
+}
+
+impl ClientRegistry {
+    /// Start tracking a connection until the returned registration is dropped
+    pub(super) fn register(&self, client: &str) -> Registration<'_> {
+        let id = Uuid::new_v4();
+        let stats = Arc::new(Mutex::new(ClientStats {
+            client: client.to_string(),
+            connected_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            bytes_sent: 0,
+            meter: RateMeter::new(Instant::now()),
+            quality: QualityTier::Full,
+        }));
+        self.lock().insert(id, Arc::clone(&stats));
+        Registration {
+            registry: self,
+            id,
+            stats,
+        }
+    }
+
+    /// Connected clients, in order of connection
+    pub(super) fn list(&self) -> Vec<ClientInfo> {
+        let now = Instant::now();
+        let mut clients: Vec<ClientInfo> = self
+            .lock()
+            .values()
+            .map(|stats| stats.lock().unwrap_or_else(|e| e.into_inner()).info(now))
+            .collect();
+        clients.sort_by_key(|c| c.connected_at_ms);
+        clients
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, SharedStats>> {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A tracked connection
+pub(super) struct Registration<'a> {
+    registry: &'a ClientRegistry,
+    id: Uuid,
+    stats: SharedStats,
+}
+
+impl Registration<'_> {
+    /// Wrap the connection's sender so it counts what it sends
+    pub(super) fn meter<T: TransportSender>(&self, sender: T) -> MeteredSender<T> {
+        MeteredSender {
+            inner: sender,
+            stats: Arc::clone(&self.stats),
+        }
+    }
+
+    /// Record the quality the connection now receives
+    pub(super) fn set_quality(&self, quality: QualityTier) {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).quality = quality;
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.id);
+    }
+}
+
+/// A sender that counts the bytes it sends
+pub(super) struct MeteredSender<T> {
+    inner: T,
+    stats: SharedStats,
+}
+
+impl<T: TransportSender> TransportSender for MeteredSender<T> {
+    async fn send_text(&mut self, text: String) -> anyhow::Result<()> {
+        let bytes = text.len();
+        self.inner.send_text(text).await?;
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.bytes_sent += bytes as u64;
+        stats.meter.record(bytes, Instant::now());
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.inner.close().await
+    }
+
+    fn supports_ping(&self) -> bool {
+        self.inner.supports_ping()
+    }
+
+    async fn send_ping(&mut self) -> anyhow::Result<()> {
+        self.inner.send_ping().await
+    }
+}
+
+/// Picks the quality tier of a connection from signs of congestion
+#[derive(Debug)]
+pub(super) struct AdaptiveQuality {
+    tier: QualityTier,
+    last_change: Instant,
+    last_congestion: Option<Instant>,
+}
+
+impl AdaptiveQuality {
+    pub(super) fn new(now: Instant) -> Self {
+        Self {
+            tier: QualityTier::Full,
+            last_change: now,
+            last_congestion: None,
+        }
+    }
+
+    /// Quality currently in effect
+    pub(super) fn tier(&self) -> QualityTier {
+        self.tier
+    }
+
+    /// Note that the client fell behind, returning the new tier if this
+    /// steps it down
+    pub(super) fn congested(&mut self, now: Instant) -> Option<QualityTier> {
+        self.last_congestion = Some(now);
+        let lower = match self.tier {
+            QualityTier::Full => QualityTier::Coalesced,
+            QualityTier::Coalesced | QualityTier::Status => QualityTier::Status,
+        };
+        if lower == self.tier || now.duration_since(self.last_change) < STEP_INTERVAL {
+            return None;
+        }
+        self.tier = lower;
+        self.last_change = now;
+        Some(lower)
+    }
+
+    /// Step quality back up once the client has kept up for a while,
+    /// returning the new tier if it changed
+    pub(super) fn recover(&mut self, now: Instant) -> Option<QualityTier> {
+        let higher = match self.tier {
+            QualityTier::Full | QualityTier::Coalesced => QualityTier::Full,
+            QualityTier::Status => QualityTier::Coalesced,
+        };
+        let calm_since = self.last_congestion.map_or(self.last_change, |t| t.max(self.last_change));
+        if higher == self.tier || now.duration_since(calm_since) < RECOVERY_AFTER {
+            return None;
+        }
+        self.tier = higher;
+        self.last_change = now;
+        Some(higher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_steps_down_and_recovers() {
+        let start = Instant::now();
+        let mut quality = AdaptiveQuality::new(start);
+
+        // Lag right after connecting, then again within the step interval
+        let t = start + STEP_INTERVAL;
+        assert_eq!(quality.congested(t), Some(QualityTier::Coalesced));
+        assert_eq!(quality.congested(t + Duration::from_millis(500)), None);
+        let t = t + STEP_INTERVAL;
+        assert_eq!(quality.congested(t), Some(QualityTier::Status));
+        assert_eq!(quality.congested(t + STEP_INTERVAL), None);
+        assert_eq!(quality.tier(), QualityTier::Status);
+
+        let t = t + STEP_INTERVAL;
+        assert_eq!(quality.recover(t + Duration::from_secs(10)), None);
+        let t = t + RECOVERY_AFTER;
+        assert_eq!(quality.recover(t), Some(QualityTier::Coalesced));
+        assert_eq!(quality.recover(t + Duration::from_secs(1)), None);
+        assert_eq!(quality.recover(t + RECOVERY_AFTER), Some(QualityTier::Full));
+    }
+
+    #[test]
+    fn test_rate_meter() {
+        let start = Instant::now();
+        let mut meter = RateMeter::new(start);
+        meter.record(3000, start);
+        meter.record(1000, start + Duration::from_secs(1));
+        assert_eq!(meter.bytes_per_sec(start + Duration::from_secs(1)), 0);
+
+        // Completed windows report their average
+        meter.record(500, start + RATE_WINDOW);
+        assert_eq!(meter.bytes_per_sec(start + RATE_WINDOW), 2000);
+        // Quiet clients decay towards zero
+        assert_eq!(meter.bytes_per_sec(start + RATE_WINDOW * 6), 50);
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = ClientRegistry::default();
+        let a = registry.register("10.0.0.1:5000");
+        {
+            let _b = registry.register("10.0.0.2:5000");
+            assert_eq!(registry.list().len(), 2);
+        }
+        a.set_quality(QualityTier::Status);
+        let clients = registry.list();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client, "10.0.0.1:5000");
+        assert_eq!(clients[0].quality, QualityTier::Status);
+        assert_eq!(clients[0].bytes_sent, 0);
+    }
+}
@@ -0,0 +1,100 @@
+//! Output batching
+//!
+//! Agents often write in many small bursts (a spinner frame, a few tokens),
+//! and each read becomes its own `agent_output`. A connection holds output
+//! for a short window and then sends what each agent wrote in it as one
+//! message. Output never waits longer than the window, and a batch that
+//! grows large is sent at once.
+
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+/// Window output is held for by default
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(16);
+
+/// Bytes held before a batch is sent without waiting for the window
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// Output held for a connection, per agent in the order agents first wrote
+#[derive(Debug)]
+pub(super) struct OutputBatch {
+    window: Duration,
+    pending: Vec<(Uuid, Vec<u8>)>,
+    bytes: usize,
+    /// When the oldest held output must go out
+    deadline: Option<Instant>,
+}
+
+impl OutputBatch {
+    /// Batch output over `window` (a zero window sends every chunk as is)
+    pub(super) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: Vec::new(),
+            bytes: 0,
+            deadline: None,
+        }
+    }
+
+    /// Hold output, returning whether the batch should be sent now
+    pub(super) fn push(&mut self, agent_id: Uuid, data: Vec<u8>, now: Instant) -> bool {
+        self.bytes += data.len();
+        match self.pending.iter_mut().find(|(id, _)| *id == agent_id) {
+            Some((_, held)) => held.extend(data),
+            None => self.pending.push((agent_id, data)),
+        }
+        self.deadline.get_or_insert(now + self.window);
+        self.window.is_zero() || self.bytes >= MAX_BATCH_BYTES
+    }
+
+    /// When the held output must be sent, if any is held
+    pub(super) fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Take the held output, one chunk per agent
+    pub(super) fn take(&mut self) -> Vec<(Uuid, Vec<u8>)> {
+        self.bytes = 0;
+        self.deadline = None;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merges_output_per_agent() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let now = Instant::now();
+        let mut batch = OutputBatch::new(DEFAULT_COALESCE_WINDOW);
+        assert_eq!(batch.deadline(), None);
+
+        assert!(!batch.push(a, b"one ".to_vec(), now));
+        assert!(!batch.push(b, b"other".to_vec(), now + Duration::from_millis(5)));
+        assert!(!batch.push(a, b"two".to_vec(), now + Duration::from_millis(10)));
+        // The window runs from the first output held
+        assert_eq!(batch.deadline(), Some(now + DEFAULT_COALESCE_WINDOW));
+        assert_eq!(
+            batch.take(),
+            vec![(a, b"one two".to_vec()), (b, b"other".to_vec())]
+        );
+        assert_eq!(batch.deadline(), None);
+        assert!(batch.take().is_empty());
+    }
+
+    #[test]
+    fn test_sends_early() {
+        let agent_id = Uuid::new_v4();
+        let now = Instant::now();
+        let mut batch = OutputBatch::new(DEFAULT_COALESCE_WINDOW);
+        assert!(!batch.push(agent_id, vec![b'x'; MAX_BATCH_BYTES - 1], now));
+        assert!(batch.push(agent_id, vec![b'x'; 1], now));
+        assert_eq!(batch.take()[0].1.len(), MAX_BATCH_BYTES);
+
+        let mut unbatched = OutputBatch::new(Duration::ZERO);
+        assert!(unbatched.push(agent_id, b"x".to_vec(), now));
+    }
+}
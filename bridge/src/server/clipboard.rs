@@ -0,0 +1,100 @@
+//! Agent clipboards
+//!
+//! Every agent has a clipboard on the bridge. A client copies text selected
+//! in one agent's terminal panel to it with `set_clipboard`, and any client
+//! can fetch it with `get_clipboard` and paste it into another agent, without
+//! going through the host's clipboard, which a headset doesn't share.
+//! Clipboards are only kept in memory and are dropped when their agent exits.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use super::protocol::ServerMessage;
+
+/// Text copied to an agent's clipboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ClipboardEntry {
+    pub(super) text: String,
+    /// Client that copied the text
+    pub(super) set_by: Option<String>,
+    /// When it was copied, in Unix milliseconds
+    pub(super) set_at_ms: u64,
+}
+
+/// The clipboards of all local agents
+#[derive(Debug, Default)]
+pub(super) struct Clipboards {
+    entries: Mutex<HashMap<Uuid, ClipboardEntry>>,
+}
+
+impl Clipboards {
+    /// Replace an agent's clipboard
+    pub(super) fn set(&self, agent_id: Uuid, text: String, client: Option<&str>) -> ClipboardEntry {
+        let entry = ClipboardEntry {
+            text,
+            set_by: client.map(str::to_string),
+            set_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(agent_id, entry.clone());
+        entry
+    }
+
+    /// What an agent's clipboard holds, if anything was copied to it
+    pub(super) fn get(&self, agent_id: Uuid) -> Option<ClipboardEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(&agent_id).cloned()
+    }
+
+    /// Forget the clipboard of an agent that exited
+    pub(super) fn remove_agent(&self, agent_id: Uuid) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(&agent_id);
+    }
+}
+
+/// The message telling a client what an agent's clipboard holds
+pub(super) fn clipboard_message(agent_id: Uuid, entry: Option<ClipboardEntry>) -> ServerMessage {
+    match entry {
+        Some(entry) => ServerMessage::Clipboard {
+            agent_id,
+            text: entry.text,
+            set_by: entry.set_by,
+            set_at_ms: Some(entry.set_at_ms),
+        },
+        None => ServerMessage::Clipboard {
+            agent_id,
+            text: String::new(),
+            set_by: None,
+            set_at_ms: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboards_per_agent() {
+        let clipboards = Clipboards::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(clipboards.get(first), None);
+
+        clipboards.set(first, "cargo test".to_string(), Some("headset"));
+        clipboards.set(second, "git status".to_string(), None);
+        let entry = clipboards.set(first, "cargo build".to_string(), Some("tablet"));
+        assert_eq!(clipboards.get(first), Some(entry.clone()));
+        assert_eq!(entry.text, "cargo build");
+        assert_eq!(entry.set_by.as_deref(), Some("tablet"));
+
+        clipboards.remove_agent(first);
+        assert_eq!(clipboards.get(first), None);
+        assert_eq!(clipboards.get(second).unwrap().text, "git status");
+    }
+}
@@ -0,0 +1,346 @@
+//! Shared-state clustering
+//!
+//! Lets several bridge instances run behind a load balancer as one cluster.
+//! Every node publishes a heartbeat record (its ID, advertised URL, hosted
+//! agents and queued tasks) to a shared [`ClusterStore`] and reads the
+//! records of the others. Live nodes are connected as cluster peers through
+//! [`Federation`], so a client on any node sees every agent in the cluster
+//! and input for an agent is routed to the node hosting it. The task queues
+//! of the other nodes are replicated from their records, while task updates
+//! travel over the peer connections as they happen. Nodes whose heartbeat
+//! goes stale are dropped again.
+//!
+//! [`Federation`]: super::federation::Federation
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::federation::PeerConfig;
+use super::protocol::TaskInfo;
+use super::websocket::ServerState;
+
+/// Interval between heartbeats
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A node whose last heartbeat is older than this is considered down
+const NODE_TTL: Duration = Duration::from_secs(10);
+
+/// Configuration for cluster mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterConfig {
+    /// Unique ID of this node within the cluster
+    pub node_id: String,
+    /// WebSocket URL other nodes use to reach this one
+    pub advertise_url: String,
+    /// Directory shared by all nodes holding their heartbeat records
+    pub store_dir: PathBuf,
+}
+
+impl ClusterConfig {
+    /// Create a new cluster configuration
+    pub fn new(
+        node_id: impl Into<String>,
+        advertise_url: impl Into<String>,
+        store_dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            node_id: node_id.into(),
+            advertise_url: advertise_url.into(),
+            store_dir: store_dir.into(),
+        }
+    }
+}
+
+/// Heartbeat record published by each node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    /// Node ID
+    pub node_id: String,
+    /// Advertised WebSocket URL
+    pub url: String,
+    /// Agents hosted by the node
+    pub agents: Vec<Uuid>,
+    /// Tasks queued or running on the node
+    #[serde(default)]
+    pub tasks: Vec<TaskInfo>,
+    /// Time of the heartbeat, in milliseconds since the Unix epoch
+    pub updated_ms: u64,
+}
+
+impl NodeRecord {
+    /// Whether the node's heartbeat is recent enough at `now_ms`
+    pub fn is_live(&self, now_ms: u64) -> bool {
+        now_ms.saturating_sub(self.updated_ms) <= NODE_TTL.as_millis() as u64
+    }
+}
+
+/// Storage backend for cluster state shared between nodes
+pub trait ClusterStore: Send + Sync {
+    /// Publish (create or replace) a node's record
+    fn publish(&self, record: &NodeRecord) -> std::io::Result<()>;
+
+    /// Remove a node's record
+    fn remove(&self, node_id: &str) -> std::io::Result<()>;
+
+    /// Read all node records
+    fn nodes(&self) -> std::io::Result<Vec<NodeRecord>>;
+}
+
+/// Cluster store backed by a shared directory (e.g. an NFS or volume mount)
+///
+/// Each node owns one `<node_id>.json` file, written atomically via rename.
+pub struct DirectoryStore {
+    dir: PathBuf,
+}
+
+impl DirectoryStore {
+    /// Use the given directory, creating it if needed
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn record_path(&self, node_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", node_id))
+    }
+}
+
+impl ClusterStore for DirectoryStore {
+    fn publish(&self, record: &NodeRecord) -> std::io::Result<()> {
+        let tmp = self.dir.join(format!(".{}.json.tmp", record.node_id));
+        std::fs::write(&tmp, serde_json::to_vec(record)?)?;
+        std::fs::rename(tmp, self.record_path(&record.node_id))
+    }
+
+    fn remove(&self, node_id: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(self.record_path(node_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn nodes(&self) -> std::io::Result<Vec<NodeRecord>> {
+        let mut nodes = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            // Records may be mid-replacement or foreign files; skip bad ones
+            match std::fs::read(&path).map(|data| serde_json::from_slice::<NodeRecord>(&data)) {
+                Ok(Ok(record)) => nodes.push(record),
+                _ => debug!("Skipping unreadable cluster record {}", path.display()),
+            }
+        }
+        Ok(nodes)
+    }
+}
+
+/// Publish heartbeats and keep cluster peers in sync until shutdown
+pub(super) async fn run_cluster(
+    config: ClusterConfig,
+    store: Arc<dyn ClusterStore>,
+    state: Arc<ServerState>,
+    shutdown_tx: broadcast::Sender<()>,
+) {
+    info!(
+        "Cluster mode enabled: node {} advertised at {}",
+        config.node_id, config.advertise_url
+    );
+
+    let mut members: HashSet<String> = HashSet::new();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut shutdown_rx = shutdown_tx.subscribe();
+
+    loop {
+        tokio::select! {
+            _ = heartbeat.tick() => {}
+            _ = shutdown_rx.recv() => break,
+        }
+
+        let now = now_ms();
+        let record = NodeRecord {
+            node_id: config.node_id.clone(),
+            url: config.advertise_url.clone(),
+            agents: state
+                .agent_manager
+                .list_agents()
+                .await
+                .into_iter()
+                .map(|a| a.agent_id)
+                .collect(),
+            tasks: state.tasks.list(),
+            updated_ms: now,
+        };
+        if let Err(e) = store.publish(&record) {
+            warn!("Failed to publish cluster heartbeat: {}", e);
+        }
+
+        let nodes = match store.nodes() {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                warn!("Failed to read cluster state: {}", e);
+                continue;
+            }
+        };
+
+        let live: Vec<NodeRecord> = nodes
+            .into_iter()
+            .filter(|n| n.node_id != config.node_id && n.is_live(now))
+            .collect();
+
+        for node in &live {
+            state.tasks.set_remote(&node.node_id, node.tasks.clone());
+            if members.insert(node.node_id.clone()) {
+                info!("Cluster node {} joined ({})", node.node_id, node.url);
+                let mut peer = PeerConfig::new(node.node_id.clone(), node.url.clone());
+                // Nodes of one cluster share the client token
+                peer.token = state.live.admin_token();
+                peer.cluster_node = Some(config.node_id.clone());
+                state.federation.add_peer(peer, &shutdown_tx).await;
+            } else if !state
+                .federation
+                .peer_knows_agents(&node.node_id, &node.agents)
+                .await
+            {
+                // New agents on that node; make them routable right away
+                state.federation.refresh_peer(&node.node_id).await;
+            }
+        }
+
+        let gone: Vec<String> = members
+            .iter()
+            .filter(|id| !live.iter().any(|n| &n.node_id == *id))
+            .cloned()
+            .collect();
+        for node_id in gone {
+            info!("Cluster node {} left", node_id);
+            members.remove(&node_id);
+            state.tasks.remove_remote(&node_id);
+            state.federation.remove_peer(&node_id).await;
+        }
+    }
+
+    if let Err(e) = store.remove(&config.node_id) {
+        warn!("Failed to remove cluster record: {}", e);
+    }
+    info!("Left cluster");
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::federation::Federation;
+    use crate::server::ServerConfig;
+
+    fn record(node_id: &str, updated_ms: u64) -> NodeRecord {
+        NodeRecord {
+            node_id: node_id.to_string(),
+            url: format!("ws://{}:9000", node_id),
+            agents: vec![Uuid::new_v4()],
+            tasks: Vec::new(),
+            updated_ms,
+        }
+    }
+
+    #[test]
+    fn test_node_liveness() {
+        let node = record("a", 100_000);
+        assert!(node.is_live(100_000));
+        assert!(node.is_live(100_000 + NODE_TTL.as_millis() as u64));
+        assert!(!node.is_live(100_001 + NODE_TTL.as_millis() as u64));
+        // Clock skew into the future counts as live
+        assert!(node.is_live(50_000));
+    }
+
+    #[test]
+    fn test_directory_store_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(dir.path().join("cluster")).unwrap();
+
+        store.publish(&record("a", 1)).unwrap();
+        store.publish(&record("b", 2)).unwrap();
+        store.publish(&record("a", 3)).unwrap();
+        std::fs::write(dir.path().join("cluster/garbage.json"), "not json").unwrap();
+
+        let mut nodes = store.nodes().unwrap();
+        nodes.sort_by(|x, y| x.node_id.cmp(&y.node_id));
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].updated_ms, 3);
+
+        store.remove("a").unwrap();
+        store.remove("a").unwrap();
+        assert_eq!(store.nodes().unwrap().len(), 1);
+
+        // Records of nodes predating task replication still parse
+        std::fs::write(
+            dir.path().join("cluster/c.json"),
+            r#"{"node_id":"c","url":"ws://c:9000","agents":[],"updated_ms":4}"#,
+        )
+        .unwrap();
+        assert_eq!(store.nodes().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_replicated_between_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn ClusterStore> = Arc::new(DirectoryStore::new(dir.path()).unwrap());
+        let state = |port| {
+            let config = ServerConfig::new("127.0.0.1".to_string(), port).with_simulation(true);
+            Arc::new(ServerState::new(config, Federation::new()))
+        };
+
+        let node_a = state(9001);
+        let (task_id, _) = node_a
+            .tasks
+            .enqueue("/srv/app".to_string(), "fix the tests".to_string(), None, None)
+            .unwrap();
+        let mut record = record("a", now_ms());
+        record.tasks = node_a.tasks.list();
+        store.publish(&record).unwrap();
+
+        let node_b = state(9002);
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let config = ClusterConfig::new("b", "ws://127.0.0.1:9002", dir.path());
+        let cluster = tokio::spawn(run_cluster(
+            config,
+            Arc::clone(&store),
+            Arc::clone(&node_b),
+            shutdown_tx.clone(),
+        ));
+
+        let mut tasks = Vec::new();
+        for _ in 0..50 {
+            tasks = node_b.tasks.list_cluster();
+            if !tasks.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].task_id, task_id);
+        assert_eq!(tasks[0].node.as_deref(), Some("a"));
+        assert_eq!(node_b.tasks.remote_node(task_id).as_deref(), Some("a"));
+
+        shutdown_tx.send(()).unwrap();
+        cluster.await.unwrap();
+        let nodes = store.nodes().unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].node_id, "a");
+    }
+}
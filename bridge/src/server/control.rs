@@ -0,0 +1,370 @@
+//! Agent input control
+//!
+//! Only one client at a time may type into an agent: the first client to send
+//! input takes control, and input from everyone else is refused with
+//! `input_locked` until the owner grants a `request_control` or releases the
+//! agent. Released control passes to the longest-waiting requester, so
+//! clients queued behind the owner don't have to race for it. Without this,
+//! two clients typing at once interleave their bytes and corrupt each other's
+//! commands. Control is tracked per WebSocket/QUIC connection and dropped
+//! when the connection closes, or, for a lost connection, when its session is
+//! no longer resumable.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use thiserror::Error;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// A connection that holds or wants control
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Holder {
+    connection_id: Uuid,
+    client: String,
+}
+
+/// Control of one agent
+#[derive(Debug)]
+struct Ownership {
+    owner: Holder,
+    /// Clients waiting for the owner to hand over control
+    requests: Vec<Holder>,
+}
+
+/// Changes relayed to connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum ControlEvent {
+    /// An agent got a new controlling client, or none
+    Changed {
+        agent_id: Uuid,
+        owner: Option<String>,
+    },
+    /// A client asked the owner (`owner_connection`) for control
+    Requested {
+        agent_id: Uuid,
+        owner_connection: Uuid,
+        client: String,
+    },
+}
+
+/// Reasons a control operation is refused
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(super) enum ControlError {
+    #[error("Agent input is controlled by {0}")]
+    Locked(String),
+
+    #[error("Only the controlling client can grant control")]
+    NotOwner,
+
+    #[error("{0} has not requested control")]
+    NoRequest(String),
+}
+
+/// Tracks which connection controls each agent's input
+pub(super) struct InputControl {
+    owners: Mutex<HashMap<Uuid, Ownership>>,
+    events: broadcast::Sender<ControlEvent>,
+}
+
+impl InputControl {
+    pub(super) fn new() -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            owners: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    /// Subscribe to control changes and requests
+    pub(super) fn subscribe(&self) -> broadcast::Receiver<ControlEvent> {
+        self.events.subscribe()
+    }
+
+    /// Client currently controlling an agent's input
+    pub(super) fn owner(&self, agent_id: Uuid) -> Option<String> {
+        let owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        owners.get(&agent_id).map(|o| o.owner.client.clone())
+    }
+
+    /// Check that a connection may send input, taking control of an
+    /// uncontrolled agent
+    pub(super) fn claim(
+        &self,
+        agent_id: Uuid,
+        connection_id: Uuid,
+        client: &str,
+    ) -> Result<(), ControlError> {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        match owners.get(&agent_id) {
+            Some(ownership) if ownership.owner.connection_id == connection_id => Ok(()),
+            Some(ownership) => Err(ControlError::Locked(ownership.owner.client.clone())),
+            None => {
+                owners.insert(
+                    agent_id,
+                    Ownership {
+                        owner: Holder {
+                            connection_id,
+                            client: client.to_string(),
+                        },
+                        requests: Vec::new(),
+                    },
+                );
+                self.changed(agent_id, Some(client));
+                Ok(())
+            }
+        }
+    }
+
+    /// Ask for control, which is granted at once if nobody holds it
+    ///
+    /// Returns whether the connection now controls the agent.
+    pub(super) fn request(&self, agent_id: Uuid, connection_id: Uuid, client: &str) -> bool {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(ownership) = owners.get_mut(&agent_id) else {
+            drop(owners);
+            return self.claim(agent_id, connection_id, client).is_ok();
+        };
+        if ownership.owner.connection_id == connection_id {
+            return true;
+        }
+
+        let requester = Holder {
+            connection_id,
+            client: client.to_string(),
+        };
+        if !ownership.requests.contains(&requester) {
+            ownership.requests.push(requester);
+        }
+        let _ = self.events.send(ControlEvent::Requested {
+            agent_id,
+            owner_connection: ownership.owner.connection_id,
+            client: client.to_string(),
+        });
+        false
+    }
+
+    /// Hand control to a client that requested it
+    pub(super) fn grant(
+        &self,
+        agent_id: Uuid,
+        connection_id: Uuid,
+        to_client: &str,
+    ) -> Result<(), ControlError> {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        let ownership = owners
+            .get_mut(&agent_id)
+            .filter(|o| o.owner.connection_id == connection_id)
+            .ok_or(ControlError::NotOwner)?;
+        let index = ownership
+            .requests
+            .iter()
+            .position(|r| r.client == to_client)
+            .ok_or_else(|| ControlError::NoRequest(to_client.to_string()))?;
+
+        ownership.owner = ownership.requests.remove(index);
+        self.changed(agent_id, Some(to_client));
+        Ok(())
+    }
+
+    /// Give up control of an agent, handing it to the first requester
+    pub(super) fn release(&self, agent_id: Uuid, connection_id: Uuid) {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ownership) = owners
+            .get_mut(&agent_id)
+            .filter(|o| o.owner.connection_id == connection_id)
+        {
+            if !self.hand_over(agent_id, ownership) {
+                owners.remove(&agent_id);
+            }
+        }
+    }
+
+    /// Move what one connection held and asked for to another, returning the
+    /// agents it controls
+    pub(super) fn transfer(&self, from: Uuid, to: Uuid, client: &str) -> Vec<Uuid> {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        let mut controlled = Vec::new();
+        for (&agent_id, ownership) in owners.iter_mut() {
+            for request in &mut ownership.requests {
+                if request.connection_id == from {
+                    request.connection_id = to;
+                    request.client = client.to_string();
+                }
+            }
+            if ownership.owner.connection_id == from {
+                ownership.owner = Holder {
+                    connection_id: to,
+                    client: client.to_string(),
+                };
+                controlled.push(agent_id);
+                self.changed(agent_id, Some(client));
+            }
+        }
+        controlled.sort();
+        controlled
+    }
+
+    /// Drop everything a closed connection held or asked for
+    pub(super) fn release_all(&self, connection_id: Uuid) {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        owners.retain(|&agent_id, ownership| {
+            ownership.requests.retain(|r| r.connection_id != connection_id);
+            if ownership.owner.connection_id != connection_id {
+                return true;
+            }
+            self.hand_over(agent_id, ownership)
+        });
+    }
+
+    /// Pass control to the longest-waiting requester, returning whether
+    /// there was one
+    fn hand_over(&self, agent_id: Uuid, ownership: &mut Ownership) -> bool {
+        if ownership.requests.is_empty() {
+            self.changed(agent_id, None);
+            return false;
+        }
+        ownership.owner = ownership.requests.remove(0);
+        self.changed(agent_id, Some(&ownership.owner.client));
+        true
+    }
+
+    /// Forget an agent that exited
+    pub(super) fn remove_agent(&self, agent_id: Uuid) {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        if owners.remove(&agent_id).is_some() {
+            self.changed(agent_id, None);
+        }
+    }
+
+    fn changed(&self, agent_id: Uuid, owner: Option<&str>) {
+        let _ = self.events.send(ControlEvent::Changed {
+            agent_id,
+            owner: owner.map(str::to_string),
+        });
+    }
+}
+
+/// Releases a connection's control when it goes away
+pub(super) struct ControlRelease<'a> {
+    pub(super) control: &'a InputControl,
+    pub(super) connection_id: Uuid,
+}
+
+impl ControlRelease<'_> {
+    /// Leave the connection's control in place, for a session that may be
+    /// resumed
+    pub(super) fn keep(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for ControlRelease<'_> {
+    fn drop(&mut self) {
+        self.control.release_all(self.connection_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_input_takes_control() {
+        let control = InputControl::new();
+        let agent = Uuid::new_v4();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(control.claim(agent, a, "10.0.0.1:5000").is_ok());
+        assert!(control.claim(agent, a, "10.0.0.1:5000").is_ok());
+        assert_eq!(
+            control.claim(agent, b, "10.0.0.2:5000"),
+            Err(ControlError::Locked("10.0.0.1:5000".to_string()))
+        );
+        // Other agents are unaffected
+        assert!(control.claim(Uuid::new_v4(), b, "10.0.0.2:5000").is_ok());
+    }
+
+    #[test]
+    fn test_request_and_grant() {
+        let control = InputControl::new();
+        let mut events = control.subscribe();
+        let agent = Uuid::new_v4();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(control.request(agent, a, "a"));
+        assert!(!control.request(agent, b, "b"));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ControlEvent::Changed {
+                agent_id: agent,
+                owner: Some("a".to_string())
+            }
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ControlEvent::Requested {
+                agent_id: agent,
+                owner_connection: a,
+                client: "b".to_string()
+            }
+        );
+
+        // Only the owner can grant, and only to a requester
+        assert_eq!(control.grant(agent, b, "b"), Err(ControlError::NotOwner));
+        assert_eq!(
+            control.grant(agent, a, "c"),
+            Err(ControlError::NoRequest("c".to_string()))
+        );
+        assert!(control.grant(agent, a, "b").is_ok());
+        assert_eq!(control.owner(agent), Some("b".to_string()));
+        assert!(control.claim(agent, a, "a").is_err());
+    }
+
+    #[test]
+    fn test_release_on_disconnect() {
+        let control = InputControl::new();
+        let agent = Uuid::new_v4();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        control.claim(agent, a, "a").unwrap();
+        {
+            let _release = ControlRelease {
+                control: &control,
+                connection_id: a,
+            };
+        }
+        assert_eq!(control.owner(agent), None);
+        assert!(control.claim(agent, b, "b").is_ok());
+
+        control.release(agent, a);
+        assert_eq!(control.owner(agent), Some("b".to_string()));
+        control.release(agent, b);
+        assert_eq!(control.owner(agent), None);
+    }
+
+    #[test]
+    fn test_release_hands_over_to_requesters() {
+        let control = InputControl::new();
+        let agent = Uuid::new_v4();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        control.claim(agent, a, "a").unwrap();
+        control.request(agent, b, "b");
+        control.request(agent, c, "c");
+        let mut events = control.subscribe();
+
+        // Requesters get control in the order they asked
+        control.release(agent, a);
+        assert_eq!(control.owner(agent), Some("b".to_string()));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ControlEvent::Changed {
+                agent_id: agent,
+                owner: Some("b".to_string())
+            }
+        );
+        control.release_all(b);
+        assert_eq!(control.owner(agent), Some("c".to_string()));
+        control.release(agent, c);
+        assert_eq!(control.owner(agent), None);
+    }
+}
@@ -0,0 +1,438 @@
+//! Built-in web dashboard
+//!
+//! Serves a minimal, read-only HTML dashboard on a separate HTTP port so
+//! operators without a headset can see what the bridge is doing. The page
+//! polls a small JSON API for the agent list and recent lifecycle events, and
+//! live-tails agent output by connecting to the regular WebSocket endpoint.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+use super::http::{read_request, HttpRequest, HttpResponse};
+use super::websocket::ServerState;
+use crate::agent::{AgentEvent, StreamEvent};
+
+/// Dashboard page, embedded at compile time
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// Number of lifecycle events kept for the dashboard
+const EVENT_LOG_CAPACITY: usize = 200;
+
+/// A lifecycle event shown on the dashboard
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DashboardEvent {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// Agent the event relates to
+    pub agent_id: Uuid,
+    /// Event kind ("spawned", "exited", "resized")
+    pub kind: String,
+    /// Human-readable details
+    pub detail: String,
+}
+
+/// Bounded log of recent lifecycle events
+pub struct EventLog {
+    events: Mutex<VecDeque<DashboardEvent>>,
+}
+
+impl EventLog {
+    /// Create an empty event log
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new(VecDeque::with_capacity(EVENT_LOG_CAPACITY)),
+        }
+    }
+
+    /// Record an agent event (output events are ignored)
+    pub async fn record(&self, event: &AgentEvent) {
+        let (agent_id, kind, detail) = match event {
+            AgentEvent::Spawned {
+                agent_id,
+                project_path,
+                ..
+            } => (*agent_id, "spawned", project_path.clone()),
+            AgentEvent::Tagged { agent_id, tags } => (*agent_id, "tagged", tags.join(", ")),
+            AgentEvent::Renamed { agent_id, name } => (
+                *agent_id,
+                "renamed",
+                name.clone().unwrap_or_else(|| "(no name)".to_string()),
+            ),
+            AgentEvent::Restarted {
+                agent_id,
+                cols,
+                rows,
+            } => (*agent_id, "restarted", format!("{}x{}", cols, rows)),
+            AgentEvent::IdleWarning {
+                agent_id,
+                stop_in_ms,
+                ..
+            } => (
+                *agent_id,
+                "idle_warning",
+                format!("stopping in {}s", stop_in_ms / 1000),
+            ),
+            AgentEvent::Exited {
+                agent_id,
+                exit_code,
+                signal,
+                reason,
+                stats,
+                ..
+            } => (
+                *agent_id,
+                "exited",
+                match (exit_code, signal) {
+                    (Some(code), _) => format!(
+                        "{} after {}s (exit code {})",
+                        reason.as_str(),
+                        stats.duration_ms / 1000,
+                        code
+                    ),
+                    (None, Some(signal)) => format!(
+                        "{} after {}s (signal {})",
+                        reason.as_str(),
+                        stats.duration_ms / 1000,
+                        signal
+                    ),
+                    (None, None) => {
+                        format!("{} after {}s", reason.as_str(), stats.duration_ms / 1000)
+                    }
+                },
+            ),
+            AgentEvent::Resized {
+                agent_id,
+                cols,
+                rows,
+            } => (*agent_id, "resized", format!("{}x{}", cols, rows)),
+            AgentEvent::ConfirmationRequested {
+                agent_id, question, ..
+            } => (*agent_id, "confirmation", question.clone()),
+            AgentEvent::ApprovalRequested {
+                agent_id,
+                tool,
+                detail,
+                ..
+            } => (
+                *agent_id,
+                "approval",
+                format!("{}: {}", tool, detail.lines().next().unwrap_or_default()),
+            ),
+            AgentEvent::StateChanged {
+                agent_id,
+                old_state,
+                new_state,
+                ..
+            } => (
+                *agent_id,
+                "state",
+                format!("{} -> {}", old_state.as_str(), new_state.as_str()),
+            ),
+            AgentEvent::CommandStarted { agent_id } => {
+                (*agent_id, "command", "started".to_string())
+            }
+            AgentEvent::CommandFinished {
+                agent_id,
+                exit_code,
+            } => (
+                *agent_id,
+                "command",
+                match exit_code {
+                    Some(code) => format!("finished (exit code {})", code),
+                    None => "finished".to_string(),
+                },
+            ),
+            AgentEvent::TriggerFired {
+                agent_id, trigger, ..
+            } => (*agent_id, "trigger", trigger.clone()),
+            AgentEvent::EchoChanged { agent_id, echo, raw } => (
+                *agent_id,
+                "echo",
+                format!(
+                    "{}, {}",
+                    if *echo { "echo on" } else { "echo off" },
+                    if *raw { "raw" } else { "cooked" }
+                ),
+            ),
+            AgentEvent::InitialPromptDelivered {
+                agent_id,
+                waited_ms,
+                timed_out,
+            } => (
+                *agent_id,
+                "initial_prompt",
+                if *timed_out {
+                    format!("sent after {}ms without the ready pattern", waited_ms)
+                } else {
+                    format!("sent after {}ms", waited_ms)
+                },
+            ),
+            AgentEvent::Stream { agent_id, event } => match event {
+                StreamEvent::ToolUse { name, .. } => (*agent_id, "tool", name.clone()),
+                StreamEvent::Cost {
+                    total_cost_usd,
+                    num_turns,
+                    ..
+                } => (
+                    *agent_id,
+                    "cost",
+                    format!("${:.4} over {} turns", total_cost_usd, num_turns),
+                ),
+                StreamEvent::AssistantText { .. } | StreamEvent::ToolResult { .. } => return,
+            },
+            AgentEvent::Output { .. } => return,
+        };
+
+        let mut events = self.events.lock().await;
+        if events.len() == EVENT_LOG_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(DashboardEvent {
+            timestamp_ms: now_ms(),
+            agent_id,
+            kind: kind.to_string(),
+            detail,
+        });
+    }
+
+    /// Get recent events, newest last
+    pub async fn recent(&self) -> Vec<DashboardEvent> {
+        self.events.lock().await.iter().cloned().collect()
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Connection details the dashboard page needs to open the WebSocket
+#[derive(Debug, Serialize)]
+struct DashboardInfo {
+    version: &'static str,
+    ws_port: u16,
+    ws_path: String,
+    auth_required: bool,
+}
+
+/// Serve the dashboard until shutdown
+pub(super) async fn run_dashboard(
+    port: u16,
+    state: Arc<ServerState>,
+    shutdown_tx: broadcast::Sender<()>,
+) -> anyhow::Result<()> {
+    let addr = format!("{}:{}", state.config.bind, port);
+    let listener = TcpListener::bind(&addr).await?;
+    info!("Dashboard available at http://{}/", addr);
+
+    // Record lifecycle events for the dashboard
+    let events = Arc::new(EventLog::new());
+    {
+        let events = Arc::clone(&events);
+        let mut agent_event_rx = state.agent_manager.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match agent_event_rx.recv().await {
+                    Ok(event) => events.record(&event).await,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                let (mut stream, peer_addr) = match result {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Failed to accept dashboard connection: {}", e);
+                        continue;
+                    }
+                };
+                let state = Arc::clone(&state);
+                let events = Arc::clone(&events);
+                tokio::spawn(async move {
+                    let response = match read_request(&mut stream).await {
+                        Ok(request) => route(&request, &state, &events).await,
+                        Err(e) => {
+                            debug!("Bad dashboard request from {}: {}", peer_addr, e);
+                            HttpResponse::text(400, "Bad Request")
+                        }
+                    };
+                    let _ = response.write_to(&mut stream).await;
+                });
+            }
+            _ = shutdown_rx.recv() => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Route a dashboard request
+async fn route(request: &HttpRequest, state: &ServerState, events: &EventLog) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::method_not_allowed();
+    }
+
+    match request.path.as_str() {
+        "/" | "/index.html" => HttpResponse::html(DASHBOARD_HTML),
+        "/api/info" => HttpResponse::json(
+            200,
+            &DashboardInfo {
+                version: env!("CARGO_PKG_VERSION"),
+                ws_port: state.config.port,
+                ws_path: state.config.ws_path.clone().unwrap_or_else(|| "/".to_string()),
+                auth_required: state.live.auth_required(),
+            },
+        ),
+        "/api/agents" if state.live.auth_required() => {
+            // Agent details are only exposed over the authenticated WebSocket
+            HttpResponse::text(401, "Authentication required")
+        }
+        "/api/agents" => {
+            let mut agents = state.agent_manager.list_agents().await;
+            agents.extend(state.federation.list_agents().await);
+            HttpResponse::json(200, &agents)
+        }
+        "/api/events" if state.live.auth_required() => {
+            HttpResponse::text(401, "Authentication required")
+        }
+        "/api/events" => {
+            let mut recent = events.recent().await;
+            if let Some(limit) = request.query_param("limit").and_then(|l| l.parse().ok()) {
+                let skip = recent.len().saturating_sub(limit);
+                recent.drain(..skip);
+            }
+            HttpResponse::json(200, &recent)
+        }
+        _ => HttpResponse::not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::federation::Federation;
+    use crate::server::protocol::{AgentExitReason, RunStats};
+    use crate::server::ServerConfig;
+
+    fn get(path: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: None,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn state(token: Option<String>) -> ServerState {
+        ServerState::new(
+            ServerConfig::new("127.0.0.1".to_string(), 9000).with_token(token),
+            Federation::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_event_log_records_lifecycle_only() {
+        let log = EventLog::new();
+        let agent_id = Uuid::new_v4();
+
+        log.record(&AgentEvent::Output {
+            agent_id,
+            data: b"hello".to_vec(),
+        })
+        .await;
+        log.record(&AgentEvent::Exited {
+            agent_id,
+            exit_code: Some(1),
+            signal: None,
+            reason: AgentExitReason::Normal,
+            preset: None,
+            stats: RunStats {
+                duration_ms: 4200,
+                ..Default::default()
+            },
+        })
+        .await;
+
+        let events = log.recent().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, "exited");
+        assert_eq!(events[0].detail, "normal after 4s (exit code 1)");
+    }
+
+    #[tokio::test]
+    async fn test_event_log_is_bounded() {
+        let log = EventLog::new();
+        for _ in 0..EVENT_LOG_CAPACITY + 10 {
+            log.record(&AgentEvent::Resized {
+                agent_id: Uuid::new_v4(),
+                cols: 80,
+                rows: 24,
+            })
+            .await;
+        }
+        assert_eq!(log.recent().await.len(), EVENT_LOG_CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let state = state(None);
+        let events = EventLog::new();
+
+        let page = route(&get("/"), &state, &events).await;
+        assert_eq!(page.status, 200);
+        assert!(page.content_type.starts_with("text/html"));
+
+        let agents = route(&get("/api/agents"), &state, &events).await;
+        assert_eq!(agents.status, 200);
+        assert_eq!(agents.body, b"[]");
+
+        assert_eq!(route(&get("/nope"), &state, &events).await.status, 404);
+
+        for _ in 0..3 {
+            events
+                .record(&AgentEvent::Resized {
+                    agent_id: Uuid::new_v4(),
+                    cols: 80,
+                    rows: 24,
+                })
+                .await;
+        }
+        let mut limited = get("/api/events");
+        limited.query = Some("limit=2".to_string());
+        let response = route(&limited, &state, &events).await;
+        let parsed: Vec<serde_json::Value> = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(parsed.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_api_requires_websocket_auth_when_token_set() {
+        let state = state(Some("secret".to_string()));
+        let events = EventLog::new();
+
+        assert_eq!(route(&get("/api/agents"), &state, &events).await.status, 401);
+        assert_eq!(route(&get("/api/events"), &state, &events).await.status, 401);
+        assert_eq!(route(&get("/api/info"), &state, &events).await.status, 200);
+    }
+}
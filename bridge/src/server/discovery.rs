@@ -0,0 +1,175 @@
+//! LAN discovery over mDNS
+//!
+//! With `--mdns` the bridge announces itself as a `_hoc-bridge._tcp` DNS-SD
+//! service on the local network, so standalone headsets can list the bridges
+//! around them instead of having their IP address typed in. The TXT record
+//! tells clients what to expect before they connect: the protocol versions
+//! spoken, whether a token is needed, whether to use `wss://` and the
+//! WebSocket path.
+
+use std::collections::HashMap;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use tracing::{debug, info, warn};
+
+use super::protocol::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/// DNS-SD service type the bridge is announced under
+pub const SERVICE_TYPE: &str = "_hoc-bridge._tcp.local.";
+
+/// Configuration for the mDNS announcement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryConfig {
+    /// Name clients list the bridge under
+    pub instance_name: String,
+}
+
+impl DiscoveryConfig {
+    /// Announce the bridge under this name
+    pub fn new(instance_name: impl Into<String>) -> Self {
+        Self {
+            instance_name: instance_name.into(),
+        }
+    }
+
+    /// Announce the bridge under the machine's host name
+    pub fn from_hostname() -> Self {
+        Self::new(hostname().unwrap_or_else(|| "hoc-bridge".to_string()))
+    }
+}
+
+/// What the announcement tells clients about the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Announcement {
+    pub(super) port: u16,
+    pub(super) auth_required: bool,
+    pub(super) tls: bool,
+    pub(super) ws_path: Option<String>,
+}
+
+impl Announcement {
+    /// TXT record properties
+    fn properties(&self) -> HashMap<String, String> {
+        let mut properties = HashMap::from([
+            ("version".to_string(), PROTOCOL_VERSION.to_string()),
+            ("min_version".to_string(), MIN_PROTOCOL_VERSION.to_string()),
+            ("bridge".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+            ("auth".to_string(), yes_no(self.auth_required).to_string()),
+            ("tls".to_string(), yes_no(self.tls).to_string()),
+        ]);
+        if let Some(path) = &self.ws_path {
+            properties.insert("path".to_string(), path.clone());
+        }
+        properties
+    }
+}
+
+/// Keeps the bridge announced until dropped
+pub(super) struct Announcer {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Announcer {
+    /// Start answering mDNS queries for the bridge on every interface
+    pub(super) fn start(
+        config: &DiscoveryConfig,
+        announcement: &Announcement,
+    ) -> anyhow::Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let host_name = format!("{}.local.", host_label(&config.instance_name));
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &config.instance_name,
+            &host_name,
+            "",
+            announcement.port,
+            announcement.properties(),
+        )?
+        .enable_addr_auto();
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service)?;
+        info!("Announcing {} over mDNS", fullname);
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Announcer {
+    fn drop(&mut self) {
+        // Say goodbye so clients drop the bridge from their lists right away
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            debug!("Failed to withdraw mDNS announcement: {}", e);
+        }
+        if let Err(e) = self.daemon.shutdown() {
+            warn!("Failed to stop mDNS responder: {}", e);
+        }
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// A DNS label for the bridge's address records, made from its name
+fn host_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let label = label.trim_matches('-');
+    if label.is_empty() {
+        "hoc-bridge".to_string()
+    } else {
+        label.chars().take(63).collect()
+    }
+}
+
+/// The machine's host name, without any domain
+fn hostname() -> Option<String> {
+    let name = std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())?;
+    let name = name.trim().split('.').next().unwrap_or_default();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement_properties() {
+        let announcement = Announcement {
+            port: 9000,
+            auth_required: true,
+            tls: false,
+            ws_path: Some("/ws".to_string()),
+        };
+        let properties = announcement.properties();
+        assert_eq!(properties["version"], PROTOCOL_VERSION.to_string());
+        assert_eq!(properties["auth"], "yes");
+        assert_eq!(properties["tls"], "no");
+        assert_eq!(properties["path"], "/ws");
+
+        let open = Announcement {
+            auth_required: false,
+            ws_path: None,
+            ..announcement
+        };
+        assert_eq!(open.properties()["auth"], "no");
+        assert!(!open.properties().contains_key("path"));
+    }
+
+    #[test]
+    fn test_host_label() {
+        assert_eq!(host_label("Studio PC"), "studio-pc");
+        assert_eq!(host_label("dev_box.lan"), "dev-box-lan");
+        assert_eq!(host_label("__"), "hoc-bridge");
+        assert_eq!(host_label(&"x".repeat(80)).len(), 63);
+    }
+}
@@ -0,0 +1,140 @@
+//! Agent environment policy
+//!
+//! Presets and `spawn_agent` can set environment variables for an agent,
+//! such as the model to use or the locale. Some variables change which code
+//! programs load, like `LD_PRELOAD` or `PATH`, and would let whoever sets
+//! them run anything as the bridge's user, so they are refused by default.
+//! The bridge can refuse further variables, or only accept those on an
+//! allow list. Names ending in `*` match every variable with that prefix.
+
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+/// Variables refused unless the policy says otherwise
+pub const DEFAULT_DENIED_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "SHELL",
+    "LD_*",
+    "DYLD_*",
+    "BASH_ENV",
+    "ENV",
+    "PROMPT_COMMAND",
+    "IFS",
+    "NODE_OPTIONS",
+    "PYTHONPATH",
+    "PYTHONSTARTUP",
+    "PERL5LIB",
+    "PERL5OPT",
+    "RUBYOPT",
+    "GIT_SSH_COMMAND",
+    "GIT_EXEC_PATH",
+    "GIT_CONFIG_*",
+];
+
+/// Which environment variables agents may be given
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvPolicy {
+    /// Only these variables are accepted (any when empty)
+    pub allowed: Vec<String>,
+    /// These variables are refused, even when allowed
+    pub denied: Vec<String>,
+}
+
+impl Default for EnvPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: Vec::new(),
+            denied: DEFAULT_DENIED_ENV
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl EnvPolicy {
+    /// Only accept these variables
+    pub fn with_allowed(mut self, allowed: Vec<String>) -> Self {
+        self.allowed = allowed;
+        self
+    }
+
+    /// Refuse these variables as well as the default ones
+    pub fn with_denied(mut self, denied: impl IntoIterator<Item = String>) -> Self {
+        self.denied.extend(denied);
+        self
+    }
+
+    /// Check the variables to be set for an agent
+    pub fn check(&self, env: &BTreeMap<String, String>) -> Result<(), EnvRejected> {
+        let refused: Vec<String> = env
+            .keys()
+            .filter(|name| !self.accepts(name))
+            .cloned()
+            .collect();
+        if refused.is_empty() {
+            Ok(())
+        } else {
+            Err(EnvRejected(refused))
+        }
+    }
+
+    fn accepts(&self, name: &str) -> bool {
+        let allowed = self.allowed.is_empty() || self.allowed.iter().any(|p| matches(p, name));
+        allowed && !self.denied.iter().any(|p| matches(p, name))
+    }
+}
+
+/// Variables the policy refused
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Environment variables not allowed: {}", .0.join(", "))]
+pub struct EnvRejected(pub Vec<String>);
+
+/// Whether a name matches a pattern, ignoring case since Windows does
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix)),
+        None => name.eq_ignore_ascii_case(pattern),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(names: &[&str]) -> BTreeMap<String, String> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), "1".to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = EnvPolicy::default();
+        assert!(policy.check(&env(&["ANTHROPIC_MODEL", "LANG"])).is_ok());
+        assert_eq!(
+            policy.check(&env(&["LANG", "ld_preload", "Path"])),
+            Err(EnvRejected(vec![
+                "Path".to_string(),
+                "ld_preload".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let policy = EnvPolicy::default()
+            .with_allowed(vec!["ANTHROPIC_*".to_string(), "LANG".to_string()])
+            .with_denied(vec!["ANTHROPIC_API_KEY".to_string()]);
+        assert!(policy.check(&env(&["ANTHROPIC_MODEL", "LANG"])).is_ok());
+        assert!(policy.check(&env(&["ANTHROPIC_API_KEY"])).is_err());
+        assert!(policy.check(&env(&["LC_ALL"])).is_err());
+        // Patterns may be longer than the name
+        assert!(policy.check(&env(&["ANTH"])).is_err());
+    }
+}
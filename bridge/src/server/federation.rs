@@ -0,0 +1,565 @@
+//! Bridge federation
+//!
+//! Lets one bridge connect to other bridges as upstream peers. Agents hosted by
+//! peers are merged into `ListAgents` (tagged with the peer name as their origin),
+//! and input/kill/resize requests for those agents are proxied to the owning peer.
+//! Output and lifecycle messages received from peers are re-broadcast to local clients.
+//!
+//! Peers can be added and removed at runtime, which cluster mode uses to track
+//! the other instances of a cluster.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::protocol::{AgentInfo, AgentState, ClientMessage, ServerMessage};
+
+/// Delay between reconnection attempts to an unreachable peer
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Interval at which peer agent lists are refreshed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Handshake header identifying a connection as a cluster link
+pub const CLUSTER_NODE_HEADER: &str = "x-hoc-cluster-node";
+
+/// Configuration for an upstream peer bridge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerConfig {
+    /// Name used to tag agents originating from this peer
+    pub name: String,
+    /// WebSocket URL of the peer (e.g. `ws://server:9000/ws`)
+    pub url: String,
+    /// Authentication token for the peer, if it requires one
+    pub token: Option<String>,
+    /// Local cluster node ID, set when the peer is another node of our cluster
+    ///
+    /// Cluster links only mirror agents the peer hosts itself, which keeps a
+    /// full mesh of nodes from echoing each other's agents back.
+    pub cluster_node: Option<String>,
+}
+
+impl PeerConfig {
+    /// Create a new peer configuration
+    pub fn new(name: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            url: url.into(),
+            token: None,
+            cluster_node: None,
+        }
+    }
+
+    /// Parse a `NAME=URL` peer specification
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once('=') {
+            Some((name, url)) if !name.is_empty() && !url.is_empty() => Ok(Self::new(name, url)),
+            _ => Err(format!("invalid peer '{}', expected NAME=URL", spec)),
+        }
+    }
+}
+
+/// Connection to a single peer
+struct Peer {
+    /// Outgoing messages to the peer
+    tx: mpsc::Sender<ClientMessage>,
+    /// Agents currently hosted by the peer
+    agents: Arc<RwLock<Vec<AgentInfo>>>,
+    /// Connection task
+    task: JoinHandle<()>,
+}
+
+/// Registry of upstream peer bridges
+pub struct Federation {
+    peers: RwLock<HashMap<String, Peer>>,
+    event_tx: broadcast::Sender<ServerMessage>,
+}
+
+impl Federation {
+    /// Create an empty federation (no peers)
+    pub fn new() -> Self {
+        let (event_tx, _) = broadcast::channel(1024);
+        Self {
+            peers: RwLock::new(HashMap::new()),
+            event_tx,
+        }
+    }
+
+    /// Start connections to all configured peers
+    ///
+    /// Each peer gets a background task that keeps reconnecting until the
+    /// shutdown signal fires.
+    pub fn start(peers: Vec<PeerConfig>, shutdown_tx: &broadcast::Sender<()>) -> Self {
+        let federation = Self::new();
+        {
+            let mut map = federation.peers.try_write().expect("new federation is unshared");
+            for config in peers {
+                let name = config.name.clone();
+                map.insert(name, Peer::spawn(config, &federation.event_tx, shutdown_tx));
+            }
+        }
+        federation
+    }
+
+    /// Connect to an additional peer, replacing any peer with the same name
+    pub async fn add_peer(&self, config: PeerConfig, shutdown_tx: &broadcast::Sender<()>) {
+        let name = config.name.clone();
+        let peer = Peer::spawn(config, &self.event_tx, shutdown_tx);
+        if let Some(old) = self.peers.write().await.insert(name, peer) {
+            old.task.abort();
+        }
+    }
+
+    /// Disconnect from a peer, forgetting its agents
+    ///
+    /// Returns `false` if no peer with that name exists.
+    pub async fn remove_peer(&self, name: &str) -> bool {
+        match self.peers.write().await.remove(name) {
+            Some(peer) => {
+                peer.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the agent cache of a peer contains all the given agents
+    pub async fn peer_knows_agents(&self, name: &str, agent_ids: &[Uuid]) -> bool {
+        let Some(agents) = self.peers.read().await.get(name).map(|p| Arc::clone(&p.agents)) else {
+            return false;
+        };
+        let cached = agents.read().await;
+        agent_ids.iter().all(|id| cached.iter().any(|a| a.agent_id == *id))
+    }
+
+    /// Ask a peer for its agent list now instead of at the next refresh
+    pub async fn refresh_peer(&self, name: &str) {
+        let tx = match self.peers.read().await.get(name) {
+            Some(peer) => peer.tx.clone(),
+            None => return,
+        };
+        // Never wait on a peer that is down and not draining its queue
+        let _ = tx.try_send(ClientMessage::list_agents());
+    }
+
+    /// Number of configured peers
+    pub async fn peer_count(&self) -> usize {
+        self.peers.read().await.len()
+    }
+
+    /// Subscribe to messages relayed from peers
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerMessage> {
+        self.event_tx.subscribe()
+    }
+
+    /// List all agents hosted by peers, tagged with their origin
+    pub async fn list_agents(&self) -> Vec<AgentInfo> {
+        let mut agents = Vec::new();
+        for peer in self.peers.read().await.values() {
+            agents.extend(peer.agents.read().await.iter().cloned());
+        }
+        agents
+    }
+
+    /// Find the peer hosting an agent
+    pub async fn peer_for_agent(&self, agent_id: Uuid) -> Option<String> {
+        for (name, peer) in self.peers.read().await.iter() {
+            if peer.agents.read().await.iter().any(|a| a.agent_id == agent_id) {
+                return Some(name.clone());
+            }
+        }
+        None
+    }
+
+    /// Proxy a message to the peer hosting the given agent
+    ///
+    /// Returns `false` if no peer hosts the agent. Responses from the peer are
+    /// relayed asynchronously through [`Federation::subscribe`].
+    pub async fn forward(&self, agent_id: Uuid, message: ClientMessage) -> bool {
+        let Some(name) = self.peer_for_agent(agent_id).await else {
+            return false;
+        };
+        self.send(&name, message).await
+    }
+
+    /// Send a message to the named peer
+    ///
+    /// Returns `false` if there is no such peer.
+    pub async fn send(&self, name: &str, message: ClientMessage) -> bool {
+        let tx = match self.peers.read().await.get(name) {
+            Some(peer) => peer.tx.clone(),
+            None => return false,
+        };
+        tx.send(message).await.is_ok()
+    }
+}
+
+impl Peer {
+    /// Start the background connection task for a peer
+    fn spawn(
+        config: PeerConfig,
+        event_tx: &broadcast::Sender<ServerMessage>,
+        shutdown_tx: &broadcast::Sender<()>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(256);
+        let agents = Arc::new(RwLock::new(Vec::new()));
+        let task = tokio::spawn(run_peer(
+            config,
+            rx,
+            Arc::clone(&agents),
+            event_tx.clone(),
+            shutdown_tx.subscribe(),
+        ));
+        Self { tx, agents, task }
+    }
+}
+
+impl Default for Federation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keep a connection to one peer alive until shutdown
+async fn run_peer(
+    config: PeerConfig,
+    mut rx: mpsc::Receiver<ClientMessage>,
+    agents: Arc<RwLock<Vec<AgentInfo>>>,
+    event_tx: broadcast::Sender<ServerMessage>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            result = peer_session(&config, &mut rx, &agents, &event_tx) => {
+                if let Err(e) = result {
+                    warn!("Peer {} ({}) disconnected: {}", config.name, config.url, e);
+                }
+            }
+            _ = shutdown_rx.recv() => break,
+        }
+
+        // Agents of a disconnected peer are unreachable until it reconnects
+        agents.write().await.clear();
+
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+            _ = shutdown_rx.recv() => break,
+        }
+    }
+}
+
+/// Run a single connection to a peer until it drops
+async fn peer_session(
+    config: &PeerConfig,
+    rx: &mut mpsc::Receiver<ClientMessage>,
+    agents: &RwLock<Vec<AgentInfo>>,
+    event_tx: &broadcast::Sender<ServerMessage>,
+) -> anyhow::Result<()> {
+    let mut request = config.url.as_str().into_client_request()?;
+    if let Some(ref node) = config.cluster_node {
+        request
+            .headers_mut()
+            .insert(CLUSTER_NODE_HEADER, HeaderValue::from_str(node)?);
+    }
+    let (ws_stream, _) = connect_async(request).await?;
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    info!("Connected to peer {} at {}", config.name, config.url);
+
+    if let Some(ref token) = config.token {
+        let auth = ClientMessage::Authenticate {
+            token: token.clone(),
+        };
+        ws_sender
+            .send(Message::Text(serde_json::to_string(&auth)?))
+            .await?;
+    }
+
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                let list = serde_json::to_string(&ClientMessage::list_agents())?;
+                ws_sender.send(Message::Text(list)).await?;
+            }
+            outgoing = rx.recv() => {
+                let Some(message) = outgoing else {
+                    return Ok(());
+                };
+                ws_sender.send(Message::Text(serde_json::to_string(&message)?)).await?;
+            }
+            incoming = ws_receiver.next() => {
+                match incoming {
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<ServerMessage>(&text) {
+                            Ok(message) => {
+                                let hosted_only = config.cluster_node.is_some();
+                                handle_peer_message(&config.name, hosted_only, message, agents, event_tx).await
+                            }
+                            Err(e) => debug!("Ignoring unparseable message from peer {}: {}", config.name, e),
+                        }
+                    }
+                    Some(Ok(Message::Ping(data))) => {
+                        ws_sender.send(Message::Pong(data)).await?;
+                    }
+                    Some(Ok(Message::Close(_))) | None => {
+                        return Err(anyhow::anyhow!("connection closed"));
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.into()),
+                }
+            }
+        }
+    }
+}
+
+/// Update the peer's agent cache and relay the message to local clients
+///
+/// With `hosted_only`, agents the peer itself federates from elsewhere are
+/// left out of the cache.
+async fn handle_peer_message(
+    peer_name: &str,
+    hosted_only: bool,
+    message: ServerMessage,
+    agents: &RwLock<Vec<AgentInfo>>,
+    event_tx: &broadcast::Sender<ServerMessage>,
+) {
+    match message {
+        ServerMessage::AgentList { agents: list } => {
+            *agents.write().await = list
+                .into_iter()
+                .filter(|info| !hosted_only || info.origin.is_none())
+                .map(|mut info| {
+                    info.origin = Some(peer_name.to_string());
+                    info
+                })
+                .collect();
+        }
+        ServerMessage::AgentSpawned {
+            agent_id,
+            ref project_path,
+            cols,
+            rows,
+        } => {
+            agents.write().await.push(AgentInfo {
+                agent_id,
+                project_path: project_path.clone(),
+                status: AgentState::Running,
+                cols,
+                rows,
+                origin: Some(peer_name.to_string()),
+                macros: Vec::new(),
+                ..Default::default()
+            });
+            let _ = event_tx.send(message);
+        }
+        ServerMessage::AgentExited { agent_id, .. } => {
+            agents.write().await.retain(|a| a.agent_id != agent_id);
+            let _ = event_tx.send(message);
+        }
+        ServerMessage::AgentResized {
+            agent_id,
+            cols,
+            rows,
+        } => {
+            if let Some(info) = agents
+                .write()
+                .await
+                .iter_mut()
+                .find(|a| a.agent_id == agent_id)
+            {
+                info.cols = cols;
+                info.rows = rows;
+            }
+            let _ = event_tx.send(message);
+        }
+        ServerMessage::AgentStateChanged {
+            agent_id,
+            new_state,
+            ..
+        } => {
+            if let Some(info) = agents
+                .write()
+                .await
+                .iter_mut()
+                .find(|a| a.agent_id == agent_id)
+            {
+                info.status = new_state;
+            }
+            let _ = event_tx.send(message);
+        }
+        ServerMessage::AgentOutput { .. }
+        | ServerMessage::AgentStatus { .. }
+        | ServerMessage::InputHistory { .. }
+        | ServerMessage::InputChunkAck { .. }
+        | ServerMessage::PasteWritten { .. }
+        | ServerMessage::ConfirmationRequest { .. } => {
+            let _ = event_tx.send(message);
+        }
+        // Task updates of another cluster node reach clients of every node
+        ServerMessage::TaskStarted { .. }
+        | ServerMessage::TaskCompleted { .. }
+        | ServerMessage::TaskCancelled { .. }
+            if hosted_only =>
+        {
+            let _ = event_tx.send(message);
+        }
+        ServerMessage::Error { agent_id: Some(_), .. } => {
+            let _ = event_tx.send(message);
+        }
+        ServerMessage::Error { ref message, .. } => {
+            warn!("Peer {} reported error: {}", peer_name, message);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_config_parse() {
+        let peer = PeerConfig::parse("server=ws://10.0.0.2:9000/ws").unwrap();
+        assert_eq!(peer.name, "server");
+        assert_eq!(peer.url, "ws://10.0.0.2:9000/ws");
+        assert!(peer.token.is_none());
+
+        assert!(PeerConfig::parse("no-url").is_err());
+        assert!(PeerConfig::parse("=ws://x").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peer_agent_list_tagged_with_origin() {
+        let agents = RwLock::new(Vec::new());
+        let (event_tx, _) = broadcast::channel(16);
+        let agent_id = Uuid::new_v4();
+
+        let list = ServerMessage::AgentList {
+            agents: vec![AgentInfo {
+                agent_id,
+                project_path: "/srv/app".to_string(),
+                status: AgentState::Running,
+                cols: 80,
+                rows: 24,
+                origin: None,
+                macros: Vec::new(),
+                ..Default::default()
+            }],
+        };
+        handle_peer_message("server", false, list, &agents, &event_tx).await;
+
+        let cached = agents.read().await;
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].origin, Some("server".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_peer_exit_removes_agent_and_relays() {
+        let agent_id = Uuid::new_v4();
+        let agents = RwLock::new(vec![AgentInfo {
+            agent_id,
+            project_path: "/srv/app".to_string(),
+            status: AgentState::Running,
+            cols: 80,
+            rows: 24,
+            origin: Some("server".to_string()),
+            macros: Vec::new(),
+            ..Default::default()
+        }]);
+        let (event_tx, mut event_rx) = broadcast::channel(16);
+
+        handle_peer_message(
+            "server",
+            false,
+            ServerMessage::agent_exited(agent_id, Some(0)),
+            &agents,
+            &event_tx,
+        )
+        .await;
+
+        assert!(agents.read().await.is_empty());
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(ServerMessage::AgentExited { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_hosted_only_skips_federated_agents() {
+        let agents = RwLock::new(Vec::new());
+        let (event_tx, _) = broadcast::channel(16);
+        let info = |origin: Option<&str>| AgentInfo {
+            agent_id: Uuid::new_v4(),
+            project_path: "/srv/app".to_string(),
+            status: AgentState::Running,
+            cols: 80,
+            rows: 24,
+            origin: origin.map(str::to_string),
+            macros: Vec::new(),
+            ..Default::default()
+        };
+
+        let list = ServerMessage::AgentList {
+            agents: vec![info(None), info(Some("node-a"))],
+        };
+        handle_peer_message("node-b", true, list, &agents, &event_tx).await;
+
+        let cached = agents.read().await;
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].origin, Some("node-b".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_task_updates_relayed_from_cluster_nodes_only() {
+        let agents = RwLock::new(Vec::new());
+        let (event_tx, mut event_rx) = broadcast::channel(16);
+        let cancelled = || ServerMessage::TaskCancelled {
+            task_id: Uuid::new_v4(),
+        };
+
+        handle_peer_message("server", false, cancelled(), &agents, &event_tx).await;
+        assert!(event_rx.try_recv().is_err());
+
+        handle_peer_message("node-b", true, cancelled(), &agents, &event_tx).await;
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(ServerMessage::TaskCancelled { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_peer() {
+        let federation = Federation::new();
+        let (shutdown_tx, _) = broadcast::channel(1);
+
+        federation
+            .add_peer(PeerConfig::new("node-b", "ws://127.0.0.1:1"), &shutdown_tx)
+            .await;
+        assert_eq!(federation.peer_count().await, 1);
+        assert!(!federation.peer_knows_agents("node-b", &[Uuid::new_v4()]).await);
+        assert!(federation.peer_knows_agents("node-b", &[]).await);
+
+        assert!(federation.remove_peer("node-b").await);
+        assert!(!federation.remove_peer("node-b").await);
+        assert_eq!(federation.peer_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_forward_unknown_agent() {
+        let federation = Federation::new();
+        assert_eq!(federation.peer_count().await, 0);
+        assert!(federation.list_agents().await.is_empty());
+        assert!(!federation.forward(Uuid::new_v4(), ClientMessage::list_agents()).await);
+        assert!(!federation.send("node-b", ClientMessage::list_agents()).await);
+    }
+}
@@ -0,0 +1,154 @@
+//! Session groups
+//!
+//! A group gathers the agents spawned into it with `spawn_agent`'s `group`,
+//! like the agents working on one project. Clients create a group and join
+//! it to receive the output of all its agents, including those spawned into
+//! it later, so several people can watch the same fleet of agents together,
+//! as in a shared VR room. The members of a group are told when others join
+//! or leave it. Membership lasts as long as the connection; a group no
+//! client is in is forgotten unless agents still belong to it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::protocol::ServerMessage;
+
+/// A client in a group
+#[derive(Debug)]
+struct Member {
+    client: String,
+    notice_tx: mpsc::UnboundedSender<ServerMessage>,
+}
+
+/// Clients in each group, by connection ID
+#[derive(Debug, Default)]
+pub(super) struct Groups {
+    groups: Mutex<BTreeMap<String, HashMap<Uuid, Member>>>,
+}
+
+impl Groups {
+    /// Whether a client is in the group
+    pub(super) fn contains(&self, group: &str) -> bool {
+        let groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        groups.contains_key(group)
+    }
+
+    /// Add a connection to a group, creating it if needed, and tell the
+    /// other members; returns the clients now in the group
+    pub(super) fn join(
+        &self,
+        group: &str,
+        connection_id: Uuid,
+        client: &str,
+        notice_tx: mpsc::UnboundedSender<ServerMessage>,
+    ) -> Vec<String> {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        let members = groups.entry(group.to_string()).or_default();
+        let member = Member {
+            client: client.to_string(),
+            notice_tx,
+        };
+        if members.insert(connection_id, member).is_none() {
+            notify(group, members, connection_id);
+        }
+        clients(members)
+    }
+
+    /// Remove a connection from a group, returning whether it was in it
+    pub(super) fn leave(&self, group: &str, connection_id: Uuid) -> bool {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(members) = groups.get_mut(group) else {
+            return false;
+        };
+        if members.remove(&connection_id).is_none() {
+            return false;
+        }
+        if members.is_empty() {
+            groups.remove(group);
+        } else {
+            notify(group, members, connection_id);
+        }
+        true
+    }
+
+    /// Remove a connection that went away from every group it was in
+    pub(super) fn leave_all(&self, connection_id: Uuid) {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        groups.retain(|group, members| {
+            if members.remove(&connection_id).is_some() {
+                notify(group, members, connection_id);
+            }
+            !members.is_empty()
+        });
+    }
+
+    /// The clients in each group, by group name
+    pub(super) fn members(&self) -> BTreeMap<String, Vec<String>> {
+        let groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        groups
+            .iter()
+            .map(|(group, members)| (group.clone(), clients(members)))
+            .collect()
+    }
+}
+
+/// Names of the clients in a group, sorted
+fn clients(members: &HashMap<Uuid, Member>) -> Vec<String> {
+    let mut clients: Vec<String> = members.values().map(|m| m.client.clone()).collect();
+    clients.sort();
+    clients
+}
+
+/// Tell the members of a group other than the one that joined or left who
+/// is in it now
+fn notify(group: &str, members: &HashMap<Uuid, Member>, changed: Uuid) {
+    let msg = ServerMessage::GroupMembers {
+        group: group.to_string(),
+        members: clients(members),
+    };
+    for (id, member) in members {
+        if *id != changed {
+            let _ = member.notice_tx.send(msg.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_membership() {
+        let groups = Groups::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let (a_tx, mut a_rx) = mpsc::unbounded_channel();
+        let (b_tx, mut b_rx) = mpsc::unbounded_channel();
+
+        assert_eq!(groups.join("frontend", a, "headset", a_tx), ["headset"]);
+        assert!(groups.contains("frontend"));
+        assert_eq!(
+            groups.join("frontend", b, "laptop", b_tx.clone()),
+            ["headset", "laptop"]
+        );
+        // Members already in the group hear of the one that joined
+        assert_eq!(
+            a_rx.try_recv().unwrap(),
+            ServerMessage::GroupMembers {
+                group: "frontend".to_string(),
+                members: vec!["headset".to_string(), "laptop".to_string()],
+            }
+        );
+        assert!(b_rx.try_recv().is_err());
+        groups.join("backend", b, "laptop", b_tx);
+
+        assert!(groups.leave("frontend", a));
+        assert!(!groups.leave("frontend", a));
+        assert!(b_rx.try_recv().is_ok());
+        groups.leave_all(b);
+        assert!(!groups.contains("frontend"));
+        assert!(groups.members().is_empty());
+    }
+}
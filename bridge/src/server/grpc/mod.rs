@@ -0,0 +1,677 @@
+//! gRPC API
+//!
+//! Exposes agent management and output streaming as a gRPC service (see
+//! `proto/hoc_bridge.proto`) next to the WebSocket server, for tooling that
+//! cannot speak the JSON protocol. Requests are translated into protocol
+//! messages and go through the same handler as WebSocket clients, so
+//! validation, presets and federation behave identically.
+
+// `tonic::Status` is the error type the generated service trait requires
+#![allow(clippy::result_large_err)]
+
+pub mod proto;
+
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use self::proto::hoc_bridge_server::{HocBridge, HocBridgeServer};
+use super::protocol::{
+    self, AgentFilter, AgentMode, AgentState, ClientMessage, ErrorCode, Role, ServerMessage,
+};
+use super::websocket::{dispatch_request, ServerState};
+use crate::agent::{AgentEvent, StreamEvent};
+
+/// Buffered events per streaming client before backpressure applies
+const EVENT_STREAM_BUFFER: usize = 256;
+
+/// Serve the gRPC API until shutdown
+pub(super) async fn run_grpc(
+    listener: TcpListener,
+    state: Arc<ServerState>,
+    shutdown_tx: broadcast::Sender<()>,
+) -> anyhow::Result<()> {
+    if let Ok(addr) = listener.local_addr() {
+        info!("gRPC API listening on {}", addr);
+    }
+
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    tonic::transport::Server::builder()
+        .add_service(HocBridgeServer::new(GrpcService::new(state)))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+            let _ = shutdown_rx.recv().await;
+        })
+        .await?;
+
+    info!("gRPC API stopped");
+    Ok(())
+}
+
+/// gRPC service implementation
+pub(super) struct GrpcService {
+    state: Arc<ServerState>,
+}
+
+impl GrpcService {
+    /// Create a service backed by the shared server state
+    pub(super) fn new(state: Arc<ServerState>) -> Self {
+        Self { state }
+    }
+
+    /// Check the bearer token when authentication is enabled, returning the
+    /// role it grants
+    fn authorize<T>(&self, request: &Request<T>) -> Result<Role, Status> {
+        if !self.state.live.auth_required() {
+            return Ok(Role::Admin);
+        }
+        let provided = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match provided {
+            Some(token) => self
+                .state
+                .live
+                .role_for(token)
+                .ok_or_else(|| Status::unauthenticated("Invalid authentication token")),
+            None => Err(Status::unauthenticated("Authentication required")),
+        }
+    }
+
+    /// Validate and handle a protocol message, mapping errors to statuses
+    async fn dispatch(
+        &self,
+        role: Role,
+        message: ClientMessage,
+    ) -> Result<Option<ServerMessage>, Status> {
+        self.dispatch_from(role, None, message).await
+    }
+
+    /// [`dispatch`](Self::dispatch) on behalf of a known client
+    async fn dispatch_from(
+        &self,
+        role: Role,
+        client: Option<&str>,
+        message: ClientMessage,
+    ) -> Result<Option<ServerMessage>, Status> {
+        let required = message.required_role();
+        if role < required {
+            return Err(Status::permission_denied(format!(
+                "This request needs the {} role",
+                required.as_str()
+            )));
+        }
+        let audit_client = client.unwrap_or("grpc");
+        match dispatch_request(&self.state, message, client, audit_client, role).await {
+            Ok(Some(ServerMessage::Error { message, code, .. })) => Err(error_status(message, code)),
+            Ok(response) => Ok(response),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl HocBridge for GrpcService {
+    type StreamEventsStream = ReceiverStream<Result<proto::AgentEvent, Status>>;
+
+    async fn list_agents(
+        &self,
+        request: Request<proto::ListAgentsRequest>,
+    ) -> Result<Response<proto::ListAgentsResponse>, Status> {
+        let role = self.authorize(&request)?;
+        let request = request.into_inner();
+        let status = match request.status {
+            Some(status) => proto::AgentState::try_from(status)
+                .ok()
+                .and_then(agent_state)
+                .map(Some)
+                .ok_or_else(|| Status::invalid_argument("Unknown agent status"))?,
+            None => None,
+        };
+        let filter = AgentFilter {
+            tag: request.tag,
+            status,
+            project_path: request.project_path,
+        };
+        match self.dispatch(role, ClientMessage::ListAgents { filter }).await? {
+            Some(ServerMessage::AgentList { agents }) => Ok(Response::new(proto::ListAgentsResponse {
+                agents: agents.into_iter().map(Into::into).collect(),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_agent_status(
+        &self,
+        request: Request<proto::AgentRequest>,
+    ) -> Result<Response<proto::AgentInfo>, Status> {
+        let role = self.authorize(&request)?;
+        let agent_id = parse_agent_id(&request.get_ref().agent_id)?;
+
+        // Agents on peer bridges are answered from the federation cache
+        if !self.state.agent_manager.agent_exists(agent_id).await {
+            if let Some(info) = self
+                .state
+                .federation
+                .list_agents()
+                .await
+                .into_iter()
+                .find(|a| a.agent_id == agent_id)
+            {
+                return Ok(Response::new(info.into()));
+            }
+        }
+
+        match self.dispatch(role, ClientMessage::GetAgentStatus { agent_id }).await? {
+            Some(ServerMessage::AgentStatus { info }) => Ok(Response::new((*info).into())),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn spawn_agent(
+        &self,
+        request: Request<proto::SpawnAgentRequest>,
+    ) -> Result<Response<proto::AgentInfo>, Status> {
+        let role = self.authorize(&request)?;
+        let client = request.remote_addr().map(|addr| addr.to_string());
+        let request = request.into_inner();
+        let message = ClientMessage::SpawnAgent {
+            project_path: request.project_path,
+            preset: request.preset,
+            cols: request.cols.map(to_u16).transpose()?,
+            rows: request.rows.map(to_u16).transpose()?,
+            tags: request.tags,
+            group: request.group,
+            name: request.name,
+            mode: request.stream_json.then_some(AgentMode::StreamJson),
+            prompt: request.prompt,
+            env: request.env.into_iter().collect(),
+            cwd: request.cwd,
+            report_echo: request.report_echo,
+        };
+
+        match self.dispatch_from(role, client.as_deref(), message).await? {
+            Some(ServerMessage::AgentSpawned {
+                agent_id,
+                project_path,
+                cols,
+                rows,
+            }) => {
+                let info = match self.state.agent_manager.get_agent_status(agent_id).await {
+                    Ok(info) => info,
+                    // Already exited again; report what was spawned
+                    Err(_) => protocol::AgentInfo {
+                        agent_id,
+                        project_path,
+                        status: AgentState::Stopped,
+                        cols,
+                        rows,
+                        origin: None,
+                        macros: Vec::new(),
+                        ..Default::default()
+                    },
+                };
+                Ok(Response::new(info.into()))
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn send_input(
+        &self,
+        request: Request<proto::SendInputRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let role = self.authorize(&request)?;
+        let client = request.remote_addr().map(|addr| addr.to_string());
+        let request = request.into_inner();
+        let message = ClientMessage::AgentInput {
+            agent_id: parse_agent_id(&request.agent_id)?,
+            input: request.input,
+            paste: request.paste,
+        };
+        self.dispatch_from(role, client.as_deref(), message).await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn resize_terminal(
+        &self,
+        request: Request<proto::ResizeTerminalRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let role = self.authorize(&request)?;
+        let request = request.into_inner();
+        let message = ClientMessage::ResizeTerminal {
+            agent_id: parse_agent_id(&request.agent_id)?,
+            cols: to_u16(request.cols)?,
+            rows: to_u16(request.rows)?,
+        };
+        self.dispatch(role, message).await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn kill_agent(
+        &self,
+        request: Request<proto::KillAgentRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let role = self.authorize(&request)?;
+        let client = request.remote_addr().map(|addr| addr.to_string());
+        let request = request.into_inner();
+        let message = ClientMessage::KillAgent {
+            agent_id: parse_agent_id(&request.agent_id)?,
+            signal: request.signal,
+        };
+        self.dispatch_from(role, client.as_deref(), message).await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn stream_events(
+        &self,
+        request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        self.authorize(&request)?;
+        let filter = request
+            .into_inner()
+            .agent_id
+            .map(|id| parse_agent_id(&id))
+            .transpose()?;
+
+        let mut agent_event_rx = self.state.agent_manager.subscribe();
+        let (tx, rx) = mpsc::channel(EVENT_STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                match agent_event_rx.recv().await {
+                    Ok(event) => {
+                        if filter.is_some_and(|id| id != event.agent_id()) {
+                            continue;
+                        }
+                        if tx.send(Ok(event.into())).await.is_err() {
+                            debug!("gRPC event stream closed by client");
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("gRPC event stream lagged by {} agent events", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Parse an agent ID string
+fn parse_agent_id(agent_id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(agent_id).map_err(|_| Status::invalid_argument(format!("Invalid agent ID: {}", agent_id)))
+}
+
+/// Narrow a terminal dimension to the protocol's integer type
+fn to_u16(value: u32) -> Result<u16, Status> {
+    u16::try_from(value).map_err(|_| Status::invalid_argument(format!("Terminal dimension out of range: {}", value)))
+}
+
+/// Map a protocol error to a gRPC status
+fn error_status(message: String, code: Option<ErrorCode>) -> Status {
+    match code {
+        Some(ErrorCode::AgentNotFound
+            | ErrorCode::MacroNotFound
+            | ErrorCode::KeyNotBound
+            | ErrorCode::RecordingNotFound
+            | ErrorCode::SessionExpired
+            | ErrorCode::TaskNotFound
+            | ErrorCode::GroupNotFound
+            | ErrorCode::PipelineNotFound
+            | ErrorCode::ScheduleNotFound) => Status::not_found(message),
+        Some(ErrorCode::CapabilityDisabled | ErrorCode::PermissionDenied) => {
+            Status::permission_denied(message)
+        }
+        Some(ErrorCode::NoPendingConfirmation
+            | ErrorCode::InputLocked
+            | ErrorCode::GitFailed
+            | ErrorCode::ConfigInvalid) => Status::failed_precondition(message),
+        Some(ErrorCode::InvalidMessage
+            | ErrorCode::InvalidPath
+            | ErrorCode::UnsupportedVersion
+            | ErrorCode::InputRejected) => {
+            Status::invalid_argument(message)
+        }
+        Some(ErrorCode::AuthRequired | ErrorCode::AuthFailed) => Status::unauthenticated(message),
+        Some(ErrorCode::RateLimited | ErrorCode::AgentLimitReached | ErrorCode::TaskQueueFull) => {
+            Status::resource_exhausted(message)
+        }
+        Some(ErrorCode::SpawnFailed | ErrorCode::InternalError) | None => Status::internal(message),
+    }
+}
+
+/// Status for a response the handler should never produce for a request
+fn unexpected(response: Option<ServerMessage>) -> Status {
+    match response {
+        // The request was forwarded to a peer bridge, which answers asynchronously
+        None => Status::unavailable("Request was forwarded to a peer bridge"),
+        Some(other) => Status::internal(format!("Unexpected response: {:?}", other)),
+    }
+}
+
+impl From<AgentState> for proto::AgentState {
+    fn from(state: AgentState) -> Self {
+        match state {
+            AgentState::Starting => proto::AgentState::Starting,
+            AgentState::Running => proto::AgentState::Running,
+            AgentState::Ready => proto::AgentState::Ready,
+            AgentState::Idle => proto::AgentState::Idle,
+            AgentState::WaitingForInput => proto::AgentState::WaitingForInput,
+            AgentState::Busy => proto::AgentState::Busy,
+            AgentState::Paused => proto::AgentState::Paused,
+            AgentState::Stopping => proto::AgentState::Stopping,
+            AgentState::Stopped => proto::AgentState::Stopped,
+        }
+    }
+}
+
+/// Core state for a requested gRPC state (`None` for `Unspecified`)
+fn agent_state(state: proto::AgentState) -> Option<AgentState> {
+    match state {
+        proto::AgentState::Unspecified => None,
+        proto::AgentState::Starting => Some(AgentState::Starting),
+        proto::AgentState::Running => Some(AgentState::Running),
+        proto::AgentState::Idle => Some(AgentState::Idle),
+        proto::AgentState::WaitingForInput => Some(AgentState::WaitingForInput),
+        proto::AgentState::Busy => Some(AgentState::Busy),
+        proto::AgentState::Paused => Some(AgentState::Paused),
+        proto::AgentState::Ready => Some(AgentState::Ready),
+        proto::AgentState::Stopping => Some(AgentState::Stopping),
+        proto::AgentState::Stopped => Some(AgentState::Stopped),
+    }
+}
+
+impl From<protocol::AgentInfo> for proto::AgentInfo {
+    fn from(info: protocol::AgentInfo) -> Self {
+        Self {
+            agent_id: info.agent_id.to_string(),
+            project_path: info.project_path,
+            status: proto::AgentState::from(info.status) as i32,
+            cols: info.cols.into(),
+            rows: info.rows.into(),
+            origin: info.origin,
+            preset: info.preset,
+            spawned_at_ms: info.spawned_at_ms,
+            last_activity_ms: info.last_activity_ms,
+            worktree: info.worktree,
+            branch: info.branch,
+            owner: info.owner,
+            pid: info.pid,
+            spawned_by: info.spawned_by,
+            tags: info.tags,
+            group: info.group,
+            phase: info.phase,
+            name: info.name,
+        }
+    }
+}
+
+impl From<AgentEvent> for proto::AgentEvent {
+    fn from(event: AgentEvent) -> Self {
+        use proto::agent_event::Event;
+
+        let agent_id = event.agent_id().to_string();
+        let event = match event {
+            AgentEvent::Spawned {
+                project_path,
+                cols,
+                rows,
+                ..
+            } => Event::Spawned(proto::AgentSpawned {
+                project_path,
+                cols: cols.into(),
+                rows: rows.into(),
+            }),
+            AgentEvent::Renamed { name, .. } => Event::Renamed(proto::AgentRenamed { name }),
+            AgentEvent::Tagged { tags, .. } => Event::Tagged(proto::AgentTagged { tags }),
+            AgentEvent::Restarted { cols, rows, .. } => Event::Restarted(proto::AgentRestarted {
+                cols: cols.into(),
+                rows: rows.into(),
+            }),
+            AgentEvent::IdleWarning {
+                idle_ms,
+                stop_in_ms,
+                ..
+            } => Event::IdleWarning(proto::AgentIdleWarning {
+                idle_ms,
+                stop_in_ms,
+            }),
+            AgentEvent::Output { data, .. } => Event::Output(proto::AgentOutput { data }),
+            AgentEvent::Exited {
+                exit_code,
+                signal,
+                reason,
+                preset,
+                stats,
+                ..
+            } => Event::Exited(proto::AgentExited {
+                exit_code,
+                signal,
+                reason: reason.as_str().to_string(),
+                preset,
+                duration_ms: stats.duration_ms,
+                bytes_in: stats.bytes_in,
+                bytes_out: stats.bytes_out,
+                redactions: stats.redactions,
+            }),
+            AgentEvent::Resized { cols, rows, .. } => Event::Resized(proto::AgentResized {
+                cols: cols.into(),
+                rows: rows.into(),
+            }),
+            AgentEvent::ConfirmationRequested {
+                question, options, ..
+            } => Event::ConfirmationRequested(proto::ConfirmationRequested { question, options }),
+            AgentEvent::ApprovalRequested {
+                tool,
+                detail,
+                question,
+                options,
+                can_always,
+                ..
+            } => Event::ApprovalRequested(proto::ApprovalRequested {
+                tool,
+                detail,
+                question,
+                options,
+                can_always,
+            }),
+            AgentEvent::StateChanged {
+                old_state,
+                new_state,
+                timestamp_ms,
+                ..
+            } => Event::StateChanged(proto::AgentStateChanged {
+                old_state: proto::AgentState::from(old_state) as i32,
+                new_state: proto::AgentState::from(new_state) as i32,
+                timestamp_ms,
+            }),
+            AgentEvent::CommandStarted { .. } => Event::CommandStarted(proto::CommandStarted {}),
+            AgentEvent::CommandFinished { exit_code, .. } => {
+                Event::CommandFinished(proto::CommandFinished { exit_code })
+            }
+            AgentEvent::TriggerFired {
+                trigger, captures, ..
+            } => Event::TriggerFired(proto::TriggerFired { trigger, captures }),
+            AgentEvent::Stream { event, .. } => stream_event(event),
+            AgentEvent::EchoChanged { echo, raw, .. } => {
+                Event::EchoChanged(proto::EchoChanged { echo, raw })
+            }
+            AgentEvent::InitialPromptDelivered {
+                waited_ms,
+                timed_out,
+                ..
+            } => Event::InitialPromptDelivered(proto::InitialPromptDelivered {
+                waited_ms,
+                timed_out,
+            }),
+        };
+        Self {
+            agent_id,
+            event: Some(event),
+        }
+    }
+}
+
+/// The gRPC event for an event of a `stream_json` agent
+fn stream_event(event: StreamEvent) -> proto::agent_event::Event {
+    use proto::agent_event::Event;
+
+    match event {
+        StreamEvent::AssistantText { text } => Event::AssistantText(proto::AssistantText { text }),
+        StreamEvent::ToolUse { id, name, input } => Event::ToolUse(proto::ToolUse {
+            tool_use_id: id,
+            name,
+            input_json: input.to_string(),
+        }),
+        StreamEvent::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+        } => Event::ToolResult(proto::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+        }),
+        StreamEvent::Cost {
+            total_cost_usd,
+            duration_ms,
+            num_turns,
+            input_tokens,
+            output_tokens,
+        } => Event::Cost(proto::Cost {
+            total_cost_usd,
+            duration_ms,
+            num_turns,
+            input_tokens,
+            output_tokens,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::federation::Federation;
+    use crate::server::ServerConfig;
+    use proto::hoc_bridge_client::HocBridgeClient;
+    use protocol::{AgentExitReason, RunStats};
+
+    async fn start(token: Option<String>) -> (String, broadcast::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(ServerState::new(
+            ServerConfig::new("127.0.0.1".to_string(), 0).with_token(token),
+            Federation::new(),
+        ));
+        let (shutdown_tx, _) = broadcast::channel(1);
+        tokio::spawn(run_grpc(listener, state, shutdown_tx.clone()));
+        (format!("http://{}", addr), shutdown_tx)
+    }
+
+    #[tokio::test]
+    async fn test_list_and_errors_over_grpc() {
+        let (url, shutdown_tx) = start(None).await;
+        let mut client = HocBridgeClient::connect(url).await.unwrap();
+
+        let agents = client
+            .list_agents(proto::ListAgentsRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(agents.agents.is_empty());
+
+        let status = client
+            .get_agent_status(proto::AgentRequest {
+                agent_id: Uuid::new_v4().to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let status = client
+            .spawn_agent(proto::SpawnAgentRequest {
+                project_path: "/nonexistent/path".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_grpc_requires_bearer_token() {
+        let (url, shutdown_tx) = start(Some("secret".to_string())).await;
+        let mut client = HocBridgeClient::connect(url).await.unwrap();
+
+        let status = client
+            .list_agents(proto::ListAgentsRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let mut request = Request::new(proto::ListAgentsRequest::default());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        assert!(client.list_agents(request).await.is_ok());
+
+        let _ = shutdown_tx.send(());
+    }
+
+    #[test]
+    fn test_event_conversion() {
+        let agent_id = Uuid::new_v4();
+        let event: proto::AgentEvent = AgentEvent::Exited {
+            agent_id,
+            exit_code: None,
+            signal: Some(15),
+            reason: AgentExitReason::Signalled,
+            preset: Some("review".to_string()),
+            stats: RunStats {
+                duration_ms: 1500,
+                bytes_in: 12,
+                bytes_out: 340,
+                redactions: 1,
+            },
+        }
+        .into();
+        assert_eq!(event.agent_id, agent_id.to_string());
+        assert_eq!(
+            event.event,
+            Some(proto::agent_event::Event::Exited(proto::AgentExited {
+                exit_code: None,
+                signal: Some(15),
+                reason: "signalled".to_string(),
+                preset: Some("review".to_string()),
+                duration_ms: 1500,
+                bytes_in: 12,
+                bytes_out: 340,
+                redactions: 1,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_error_status_mapping() {
+        assert_eq!(
+            error_status("x".to_string(), Some(ErrorCode::AgentNotFound)).code(),
+            tonic::Code::NotFound
+        );
+        assert_eq!(
+            error_status("x".to_string(), Some(ErrorCode::InvalidPath)).code(),
+            tonic::Code::InvalidArgument
+        );
+        assert_eq!(error_status("x".to_string(), None).code(), tonic::Code::Internal);
+    }
+}
@@ -0,0 +1,388 @@
+//! gRPC message types
+//!
+//! Hand-written `prost` equivalents of the messages in
+//! `proto/hoc_bridge.proto`; field tags must match the `.proto` file.
+
+/// Agent lifecycle states
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum AgentState {
+    Unspecified = 0,
+    Starting = 1,
+    Running = 2,
+    Stopping = 3,
+    Stopped = 4,
+    Idle = 5,
+    WaitingForInput = 6,
+    Busy = 7,
+    Paused = 8,
+    Ready = 9,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Empty {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentInfo {
+    #[prost(string, tag = "1")]
+    pub agent_id: String,
+    #[prost(string, tag = "2")]
+    pub project_path: String,
+    #[prost(enumeration = "AgentState", tag = "3")]
+    pub status: i32,
+    #[prost(uint32, tag = "4")]
+    pub cols: u32,
+    #[prost(uint32, tag = "5")]
+    pub rows: u32,
+    #[prost(string, optional, tag = "6")]
+    pub origin: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub preset: Option<String>,
+    #[prost(uint64, optional, tag = "8")]
+    pub spawned_at_ms: Option<u64>,
+    #[prost(uint64, optional, tag = "9")]
+    pub last_activity_ms: Option<u64>,
+    #[prost(string, optional, tag = "10")]
+    pub worktree: Option<String>,
+    #[prost(string, optional, tag = "11")]
+    pub branch: Option<String>,
+    #[prost(string, optional, tag = "12")]
+    pub owner: Option<String>,
+    #[prost(uint32, optional, tag = "13")]
+    pub pid: Option<u32>,
+    #[prost(string, optional, tag = "14")]
+    pub spawned_by: Option<String>,
+    #[prost(string, repeated, tag = "15")]
+    pub tags: Vec<String>,
+    #[prost(string, optional, tag = "16")]
+    pub group: Option<String>,
+    #[prost(string, optional, tag = "17")]
+    pub phase: Option<String>,
+    #[prost(string, optional, tag = "18")]
+    pub name: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListAgentsRequest {
+    #[prost(string, optional, tag = "1")]
+    pub tag: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub project_path: Option<String>,
+    #[prost(enumeration = "AgentState", optional, tag = "3")]
+    pub status: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListAgentsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub agents: Vec<AgentInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentRequest {
+    #[prost(string, tag = "1")]
+    pub agent_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SpawnAgentRequest {
+    #[prost(string, tag = "1")]
+    pub project_path: String,
+    #[prost(string, optional, tag = "2")]
+    pub preset: Option<String>,
+    #[prost(uint32, optional, tag = "3")]
+    pub cols: Option<u32>,
+    #[prost(uint32, optional, tag = "4")]
+    pub rows: Option<u32>,
+    #[prost(string, repeated, tag = "5")]
+    pub tags: Vec<String>,
+    #[prost(string, optional, tag = "6")]
+    pub group: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub prompt: Option<String>,
+    #[prost(bool, tag = "9")]
+    pub stream_json: bool,
+    #[prost(map = "string, string", tag = "10")]
+    pub env: std::collections::HashMap<String, String>,
+    #[prost(string, optional, tag = "11")]
+    pub cwd: Option<String>,
+    #[prost(bool, tag = "12")]
+    pub report_echo: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SendInputRequest {
+    #[prost(string, tag = "1")]
+    pub agent_id: String,
+    #[prost(string, tag = "2")]
+    pub input: String,
+    #[prost(bool, tag = "3")]
+    pub paste: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResizeTerminalRequest {
+    #[prost(string, tag = "1")]
+    pub agent_id: String,
+    #[prost(uint32, tag = "2")]
+    pub cols: u32,
+    #[prost(uint32, tag = "3")]
+    pub rows: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KillAgentRequest {
+    #[prost(string, tag = "1")]
+    pub agent_id: String,
+    #[prost(int32, optional, tag = "2")]
+    pub signal: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamEventsRequest {
+    #[prost(string, optional, tag = "1")]
+    pub agent_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentEvent {
+    #[prost(string, tag = "1")]
+    pub agent_id: String,
+    #[prost(
+        oneof = "agent_event::Event",
+        tags = "2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20"
+    )]
+    pub event: Option<agent_event::Event>,
+}
+
+pub mod agent_event {
+    /// Event payload
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Event {
+        #[prost(message, tag = "2")]
+        Spawned(super::AgentSpawned),
+        #[prost(message, tag = "3")]
+        Output(super::AgentOutput),
+        #[prost(message, tag = "4")]
+        Exited(super::AgentExited),
+        #[prost(message, tag = "5")]
+        Resized(super::AgentResized),
+        #[prost(message, tag = "6")]
+        ConfirmationRequested(super::ConfirmationRequested),
+        #[prost(message, tag = "7")]
+        StateChanged(super::AgentStateChanged),
+        #[prost(message, tag = "8")]
+        CommandStarted(super::CommandStarted),
+        #[prost(message, tag = "9")]
+        CommandFinished(super::CommandFinished),
+        #[prost(message, tag = "10")]
+        Restarted(super::AgentRestarted),
+        #[prost(message, tag = "11")]
+        IdleWarning(super::AgentIdleWarning),
+        #[prost(message, tag = "12")]
+        Renamed(super::AgentRenamed),
+        #[prost(message, tag = "13")]
+        Tagged(super::AgentTagged),
+        #[prost(message, tag = "14")]
+        InitialPromptDelivered(super::InitialPromptDelivered),
+        #[prost(message, tag = "15")]
+        TriggerFired(super::TriggerFired),
+        #[prost(message, tag = "16")]
+        ApprovalRequested(super::ApprovalRequested),
+        #[prost(message, tag = "17")]
+        AssistantText(super::AssistantText),
+        #[prost(message, tag = "18")]
+        ToolUse(super::ToolUse),
+        #[prost(message, tag = "19")]
+        ToolResult(super::ToolResult),
+        #[prost(message, tag = "20")]
+        Cost(super::Cost),
+        #[prost(message, tag = "21")]
+        EchoChanged(super::EchoChanged),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentRestarted {
+    #[prost(uint32, tag = "1")]
+    pub cols: u32,
+    #[prost(uint32, tag = "2")]
+    pub rows: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentRenamed {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentTagged {
+    #[prost(string, repeated, tag = "1")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentIdleWarning {
+    #[prost(uint64, tag = "1")]
+    pub idle_ms: u64,
+    #[prost(uint64, tag = "2")]
+    pub stop_in_ms: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TriggerFired {
+    #[prost(string, tag = "1")]
+    pub trigger: String,
+    #[prost(btree_map = "string, string", tag = "2")]
+    pub captures: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InitialPromptDelivered {
+    #[prost(uint64, tag = "1")]
+    pub waited_ms: u64,
+    #[prost(bool, tag = "2")]
+    pub timed_out: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentSpawned {
+    #[prost(string, tag = "1")]
+    pub project_path: String,
+    #[prost(uint32, tag = "2")]
+    pub cols: u32,
+    #[prost(uint32, tag = "3")]
+    pub rows: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentOutput {
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentExited {
+    #[prost(int32, optional, tag = "1")]
+    pub exit_code: Option<i32>,
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    #[prost(string, optional, tag = "3")]
+    pub preset: Option<String>,
+    #[prost(uint64, tag = "4")]
+    pub duration_ms: u64,
+    #[prost(uint64, tag = "5")]
+    pub bytes_in: u64,
+    #[prost(uint64, tag = "6")]
+    pub bytes_out: u64,
+    #[prost(uint64, tag = "7")]
+    pub redactions: u64,
+    #[prost(int32, optional, tag = "8")]
+    pub signal: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentResized {
+    #[prost(uint32, tag = "1")]
+    pub cols: u32,
+    #[prost(uint32, tag = "2")]
+    pub rows: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConfirmationRequested {
+    #[prost(string, tag = "1")]
+    pub question: String,
+    #[prost(string, repeated, tag = "2")]
+    pub options: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AssistantText {
+    #[prost(string, tag = "1")]
+    pub text: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ToolUse {
+    #[prost(string, tag = "1")]
+    pub tool_use_id: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub input_json: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ToolResult {
+    #[prost(string, tag = "1")]
+    pub tool_use_id: String,
+    #[prost(string, tag = "2")]
+    pub content: String,
+    #[prost(bool, tag = "3")]
+    pub is_error: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Cost {
+    #[prost(double, tag = "1")]
+    pub total_cost_usd: f64,
+    #[prost(uint64, tag = "2")]
+    pub duration_ms: u64,
+    #[prost(uint32, tag = "3")]
+    pub num_turns: u32,
+    #[prost(uint64, tag = "4")]
+    pub input_tokens: u64,
+    #[prost(uint64, tag = "5")]
+    pub output_tokens: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EchoChanged {
+    #[prost(bool, tag = "1")]
+    pub echo: bool,
+    #[prost(bool, tag = "2")]
+    pub raw: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ApprovalRequested {
+    #[prost(string, tag = "1")]
+    pub tool: String,
+    #[prost(string, tag = "2")]
+    pub detail: String,
+    #[prost(string, tag = "3")]
+    pub question: String,
+    #[prost(string, repeated, tag = "4")]
+    pub options: Vec<String>,
+    #[prost(bool, tag = "5")]
+    pub can_always: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentStateChanged {
+    #[prost(enumeration = "AgentState", tag = "1")]
+    pub old_state: i32,
+    #[prost(enumeration = "AgentState", tag = "2")]
+    pub new_state: i32,
+    #[prost(uint64, tag = "3")]
+    pub timestamp_ms: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CommandStarted {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CommandFinished {
+    #[prost(int32, optional, tag = "1")]
+    pub exit_code: Option<i32>,
+}
+
+#[allow(clippy::all)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/hoc.bridge.v1.HocBridge.rs"));
+}
+pub use generated::*;
@@ -0,0 +1,170 @@
+//! Health and readiness probes
+//!
+//! `GET /healthz` and `GET /readyz` are answered on the WebSocket port
+//! itself, so orchestrators can probe the bridge without speaking the
+//! protocol. The bridge is healthy while it serves requests at all; it is
+//! ready once it could start an agent, i.e. the `claude` binary is on the
+//! `PATH` (or agents are simulated). Probes need no token and only reveal
+//! how many agents are running.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::http::{HttpRequest, HttpResponse};
+use super::websocket::ServerState;
+
+/// Program started for local agents
+pub(super) const AGENT_PROGRAM: &str = "claude";
+
+/// Body of a `/healthz` response
+#[derive(Debug, Serialize)]
+struct Health {
+    status: &'static str,
+    version: &'static str,
+    uptime_secs: u64,
+    agents: usize,
+}
+
+/// Body of a `/readyz` response
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    agents: usize,
+    /// Where `claude` was found, if it was
+    claude: Option<PathBuf>,
+    simulated: bool,
+}
+
+/// Whether a request is a probe rather than a WebSocket upgrade
+pub(super) fn is_probe(request: &HttpRequest) -> bool {
+    matches!(request.path.as_str(), "/healthz" | "/readyz") && request.header("upgrade").is_none()
+}
+
+/// Answer a probe
+pub(super) async fn probe(request: &HttpRequest, state: &ServerState) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::method_not_allowed();
+    }
+    let agents = state.agent_manager.list_agents().await.len();
+    match request.path.as_str() {
+        "/healthz" => HttpResponse::json(
+            200,
+            &Health {
+                status: "ok",
+                version: env!("CARGO_PKG_VERSION"),
+                uptime_secs: state.started.elapsed().as_secs(),
+                agents,
+            },
+        ),
+        "/readyz" => {
+            let simulated = state.config.simulate;
+            let claude = find_program(AGENT_PROGRAM, std::env::var_os("PATH"));
+            let ready = simulated || claude.is_some();
+            HttpResponse::json(
+                if ready { 200 } else { 503 },
+                &Readiness {
+                    ready,
+                    agents,
+                    claude,
+                    simulated,
+                },
+            )
+        }
+        _ => HttpResponse::not_found(),
+    }
+}
+
+/// Look a program up in a `PATH`-style list of directories
+pub(super) fn find_program(name: &str, path: Option<std::ffi::OsString>) -> Option<PathBuf> {
+    let path = path?;
+    std::env::split_paths(&path).find_map(|dir| {
+        candidates(&dir, name)
+            .into_iter()
+            .find(|candidate| is_executable(candidate))
+    })
+}
+
+#[cfg(windows)]
+fn candidates(dir: &Path, name: &str) -> Vec<PathBuf> {
+    ["exe", "cmd", "bat"]
+        .iter()
+        .map(|ext| dir.join(name).with_extension(ext))
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn candidates(dir: &Path, name: &str) -> Vec<PathBuf> {
+    vec![dir.join(name)]
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::federation::Federation;
+    use crate::server::ServerConfig;
+
+    fn get(path: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: None,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probes() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000).with_simulation(true);
+        let state = ServerState::new(config, Federation::new());
+
+        let health = probe(&get("/healthz"), &state).await;
+        assert_eq!(health.status, 200);
+        let body: serde_json::Value = serde_json::from_slice(&health.body).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["agents"], 0);
+
+        // Simulated agents don't need claude
+        let ready = probe(&get("/readyz"), &state).await;
+        assert_eq!(ready.status, 200);
+        let body: serde_json::Value = serde_json::from_slice(&ready.body).unwrap();
+        assert_eq!(body["ready"], true);
+
+        let mut upgrade = get("/healthz");
+        upgrade
+            .headers
+            .push(("upgrade".to_string(), "websocket".to_string()));
+        assert!(is_probe(&get("/readyz")));
+        assert!(!is_probe(&upgrade));
+        assert!(!is_probe(&get("/")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_program() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("claude");
+        std::fs::write(&program, "#!/bin/sh\n").unwrap();
+        let path = std::env::join_paths([Path::new("/nonexistent"), dir.path()]).unwrap();
+        assert_eq!(find_program("claude", Some(path.clone())), None);
+
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(find_program("claude", Some(path)), Some(program));
+        assert_eq!(find_program("claude", None), None);
+    }
+}
@@ -0,0 +1,102 @@
+//! Dead connection detection
+//!
+//! A headset that drops off Wi-Fi or goes to sleep rarely closes its
+//! WebSocket, leaving the bridge holding the connection, and any input
+//! control it had, indefinitely. The server pings every WebSocket client on
+//! an interval and closes connections that stay silent, answering neither a
+//! ping nor sending anything else, for too many intervals in a row. Closing
+//! them releases their input control and other per-connection state.
+//!
+//! Transports without pings (QUIC, which has its own keep-alive, and stdio)
+//! are not checked.
+
+use std::time::Duration;
+
+/// Default interval between pings
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Default number of unanswered pings after which a connection is closed
+pub const DEFAULT_MAX_MISSED: u32 = 3;
+
+/// How often clients are pinged and how many pings they may miss
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Interval between pings (no pings when `None`)
+    pub interval: Option<Duration>,
+    /// Unanswered pings in a row after which the connection is closed
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Some(DEFAULT_HEARTBEAT_INTERVAL),
+            max_missed: DEFAULT_MAX_MISSED,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// Ping on this interval, or not at all
+    pub fn with_interval(mut self, interval: Option<Duration>) -> Self {
+        self.interval = interval.filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// Close connections after this many unanswered pings
+    pub fn with_max_missed(mut self, max_missed: u32) -> Self {
+        self.max_missed = max_missed.max(1);
+        self
+    }
+}
+
+/// Counts the pings a connection has left unanswered
+#[derive(Debug)]
+pub(super) struct Heartbeat {
+    max_missed: u32,
+    missed: u32,
+}
+
+impl Heartbeat {
+    pub(super) fn new(config: HeartbeatConfig) -> Self {
+        Self {
+            max_missed: config.max_missed.max(1),
+            missed: 0,
+        }
+    }
+
+    /// Note the end of an interval, in which the client was heard from or
+    /// not; returns whether the connection is to be considered dead
+    pub(super) fn tick(&mut self, heard_from: bool) -> bool {
+        if heard_from {
+            self.missed = 0;
+        } else {
+            self.missed += 1;
+        }
+        self.missed >= self.max_missed
+    }
+
+    /// Pings left unanswered in a row
+    pub(super) fn missed(&self) -> u32 {
+        self.missed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missed_pings() {
+        let config = HeartbeatConfig::default().with_max_missed(2);
+        let mut heartbeat = Heartbeat::new(config);
+        assert!(!heartbeat.tick(false));
+        // Any sign of life resets the count
+        assert!(!heartbeat.tick(true));
+        assert!(!heartbeat.tick(false));
+        assert_eq!(heartbeat.missed(), 1);
+        assert!(heartbeat.tick(false));
+
+        assert_eq!(HeartbeatConfig::default().with_max_missed(0).max_missed, 1);
+    }
+}
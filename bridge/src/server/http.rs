@@ -0,0 +1,370 @@
+//! Minimal HTTP/1.1 support
+//!
+//! Just enough HTTP to serve small auxiliary endpoints (dashboard, JSON APIs)
+//! next to the WebSocket server without pulling in a full web framework.
+//! Every response closes the connection.
+
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Maximum size of the request line and headers
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Maximum size of a request body
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// A parsed HTTP request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    /// Request method (e.g. "GET")
+    pub method: String,
+    /// Request path without the query string
+    pub path: String,
+    /// Raw query string, if any
+    pub query: Option<String>,
+    /// Request headers (names lowercased)
+    pub headers: Vec<(String, String)>,
+    /// Request body
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Get a header value by (case-insensitive) name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get a query parameter by name (no percent-decoding)
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.as_deref()?.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key == name).then_some(value)
+        })
+    }
+}
+
+/// Read and parse an HTTP request from a stream
+pub async fn read_request<S>(stream: &mut S) -> std::io::Result<HttpRequest>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    let head_end = read_head(stream, &mut buffer).await?;
+    let mut request = parse_head(&buffer[..head_end])?;
+
+    // Read the body, if any
+    let content_length: usize = request
+        .header("content-length")
+        .map(|v| v.parse().map_err(|_| invalid("invalid content-length")))
+        .transpose()?
+        .unwrap_or(0);
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("request body too large"));
+    }
+
+    let mut body = buffer[head_end + 4..].to_vec();
+    while body.len() < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(invalid("connection closed before end of request body"));
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+    request.body = body;
+
+    Ok(request)
+}
+
+/// Read a request head without consuming it
+///
+/// Returns the parsed head (without a body) and a stream that replays
+/// everything read so far, so the request can still be handed on, e.g. to
+/// the WebSocket handshake.
+pub async fn peek_request<S>(mut stream: S) -> std::io::Result<(HttpRequest, Replay<S>)>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::with_capacity(1024);
+    let head_end = read_head(&mut stream, &mut buffer).await?;
+    let request = parse_head(&buffer[..head_end])?;
+    Ok((request, Replay::new(buffer, stream)))
+}
+
+/// Read into `buffer` until it holds the end of the request head, returning
+/// where the head ends
+async fn read_head<S>(stream: &mut S, buffer: &mut Vec<u8>) -> std::io::Result<usize>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut chunk = [0u8; 1024];
+    loop {
+        if let Some(pos) = find_head_end(buffer) {
+            return Ok(pos);
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            return Err(invalid("request head too large"));
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(invalid("connection closed before end of request head"));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// A stream that yields already-read bytes before reading on
+#[derive(Debug)]
+pub struct Replay<S> {
+    prefix: Vec<u8>,
+    pos: usize,
+    inner: S,
+}
+
+impl<S> Replay<S> {
+    fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self {
+            prefix,
+            pos: 0,
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Replay<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if this.pos < this.prefix.len() {
+            let rest = &this.prefix[this.pos..];
+            let n = rest.len().min(buf.remaining());
+            buf.put_slice(&rest[..n]);
+            this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Replay<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+fn parse_head(head: &[u8]) -> std::io::Result<HttpRequest> {
+    let head = std::str::from_utf8(head).map_err(|_| invalid("request head is not UTF-8"))?;
+    let mut lines = head.split("\r\n");
+
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+
+    Ok(HttpRequest {
+        method: method.to_string(),
+        path,
+        query,
+        headers,
+        body: Vec::new(),
+    })
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// An HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    /// Status code
+    pub status: u16,
+    /// Content-Type header value
+    pub content_type: &'static str,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Create a response with the given status and plain-text body
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into().into_bytes(),
+        }
+    }
+
+    /// Create a 200 HTML response
+    pub fn html(body: impl Into<String>) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: body.into().into_bytes(),
+        }
+    }
+
+    /// Create a JSON response with the given status
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::text(500, format!("Failed to serialize response: {}", e)),
+        }
+    }
+
+    /// Create a 404 response
+    pub fn not_found() -> Self {
+        Self::text(404, "Not Found")
+    }
+
+    /// Create a 405 response
+    pub fn method_not_allowed() -> Self {
+        Self::text(405, "Method Not Allowed")
+    }
+
+    /// Write the response to a stream
+    pub async fn write_to<S>(&self, stream: &mut S) -> std::io::Result<()>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason_phrase(self.status),
+            self.content_type,
+            self.body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.flush().await
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_get_request() {
+        let raw = b"GET /api/agents?status=running HTTP/1.1\r\nHost: localhost\r\nX-Test: yes\r\n\r\n";
+        let request = read_request(&mut &raw[..]).await.unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/api/agents");
+        assert_eq!(request.query_param("status"), Some("running"));
+        assert_eq!(request.header("x-test"), Some("yes"));
+        assert_eq!(request.header("Host"), Some("localhost"));
+        assert!(request.body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_request_with_body() {
+        let raw = b"POST /agents HTTP/1.1\r\nContent-Length: 13\r\n\r\n{\"a\": \"b\"}xyz";
+        let request = read_request(&mut &raw[..]).await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.body, b"{\"a\": \"b\"}xyz");
+    }
+
+    #[tokio::test]
+    async fn test_peek_request_replays_head() {
+        let raw = b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\n\r\nrest";
+        let (request, mut replay) = peek_request(&raw[..]).await.unwrap();
+        assert_eq!(request.path, "/ws");
+        assert_eq!(request.header("upgrade"), Some("websocket"));
+
+        let mut replayed = Vec::new();
+        replay.read_to_end(&mut replayed).await.unwrap();
+        assert_eq!(replayed, raw);
+    }
+
+    #[tokio::test]
+    async fn test_read_malformed_request() {
+        let raw = b"garbage\r\n\r\n";
+        assert!(read_request(&mut &raw[..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_response() {
+        let mut out = Vec::new();
+        HttpResponse::json(200, &serde_json::json!({"ok": true}))
+            .write_to(&mut out)
+            .await
+            .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("Content-Type: application/json"));
+        assert!(text.ends_with("{\"ok\":true}"));
+    }
+}
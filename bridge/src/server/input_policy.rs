@@ -0,0 +1,306 @@
+//! Agent input policy
+//!
+//! Screens `agent_input` before it reaches a PTY. Terminal string sequences
+//! (OSC, DCS, APC, PM and SOS) never come from a keyboard but can reprogram
+//! the terminal or trigger clipboard and title reports, so they are stripped
+//! or rejected depending on the policy. An optional per-agent byte-rate cap
+//! protects the host from clients flooding a PTY.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use super::protocol::ErrorCode;
+
+/// Idle rate-limit buckets are pruned once this many agents are tracked
+const MAX_TRACKED_AGENTS: usize = 1024;
+
+/// What to do with terminal control strings found in input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlPolicy {
+    /// Pass input through unchanged
+    Allow,
+    /// Remove control strings and forward the rest
+    #[default]
+    Strip,
+    /// Refuse input containing control strings
+    Reject,
+}
+
+impl FromStr for ControlPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "strip" => Ok(Self::Strip),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!(
+                "invalid control policy '{}', expected allow, strip or reject",
+                s
+            )),
+        }
+    }
+}
+
+/// Policy applied to agent input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputPolicy {
+    /// Handling of terminal control strings
+    pub control: ControlPolicy,
+    /// Maximum input bytes per second per agent (unlimited when `None`)
+    pub rate_limit: Option<u32>,
+}
+
+impl InputPolicy {
+    /// Set the control string handling
+    pub fn with_control(mut self, control: ControlPolicy) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// Cap input per agent at `bytes_per_sec`
+    pub fn with_rate_limit(mut self, bytes_per_sec: Option<u32>) -> Self {
+        self.rate_limit = bytes_per_sec;
+        self
+    }
+}
+
+/// Reasons input is refused
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InputRejected {
+    #[error("Input contains a terminal control sequence")]
+    ControlSequence,
+
+    #[error("Input rate limit exceeded")]
+    RateLimited,
+}
+
+impl InputRejected {
+    /// Protocol error code reported to the client
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            InputRejected::ControlSequence => ErrorCode::InputRejected,
+            InputRejected::RateLimited => ErrorCode::RateLimited,
+        }
+    }
+}
+
+/// Token bucket refilled at the configured rate, holding one second of input
+pub(super) struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// A full bucket for `rate` per second
+    pub(super) fn new(rate: f64, now: Instant) -> Self {
+        Self {
+            tokens: rate,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.updated = now;
+    }
+
+    /// Take `amount` tokens if the bucket holds them
+    pub(super) fn take(&mut self, amount: usize, rate: f64, now: Instant) -> bool {
+        self.refill(rate, now);
+        if (amount as f64) > self.tokens {
+            return false;
+        }
+        self.tokens -= amount as f64;
+        true
+    }
+}
+
+/// Applies an [`InputPolicy`], tracking per-agent input rates
+pub(super) struct InputFilter {
+    policy: InputPolicy,
+    buckets: Mutex<HashMap<Uuid, Bucket>>,
+}
+
+impl InputFilter {
+    /// Create a filter for the given policy
+    pub(super) fn new(policy: InputPolicy) -> Self {
+        Self {
+            policy,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check input for an agent, returning what should be written to its PTY
+    pub(super) fn apply(&self, agent_id: Uuid, input: String) -> Result<String, InputRejected> {
+        let bytes = self.apply_bytes(agent_id, input.into_bytes())?;
+        // Only whole sequences are removed, so the input stays valid UTF-8
+        Ok(String::from_utf8(bytes)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+    }
+
+    /// Check raw input bytes for an agent
+    pub(super) fn apply_bytes(
+        &self,
+        agent_id: Uuid,
+        input: Vec<u8>,
+    ) -> Result<Vec<u8>, InputRejected> {
+        let input = match self.policy.control {
+            ControlPolicy::Allow => input,
+            ControlPolicy::Strip => strip_control_strings(&input),
+            ControlPolicy::Reject if contains_control_string(&input) => {
+                return Err(InputRejected::ControlSequence)
+            }
+            ControlPolicy::Reject => input,
+        };
+
+        if let Some(limit) = self.policy.rate_limit {
+            self.take(agent_id, input.len(), limit as f64, Instant::now())?;
+        }
+        Ok(input)
+    }
+
+    fn take(&self, agent_id: Uuid, len: usize, rate: f64, now: Instant) -> Result<(), InputRejected> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_AGENTS && !buckets.contains_key(&agent_id) {
+            // Full buckets carry no state worth keeping
+            buckets.retain(|_, bucket| {
+                bucket.refill(rate, now);
+                bucket.tokens < rate
+            });
+        }
+
+        let bucket = buckets.entry(agent_id).or_insert_with(|| Bucket::new(rate, now));
+        if !bucket.take(len, rate, now) {
+            return Err(InputRejected::RateLimited);
+        }
+        Ok(())
+    }
+}
+
+/// Length of the control string introducer at the start of `input`, if any
+///
+/// Recognizes ESC followed by `]`, `P`, `_`, `^` or `X`, and the UTF-8
+/// encoded C1 controls OSC, DCS, APC, PM and SOS.
+fn string_introducer_len(input: &[u8]) -> Option<usize> {
+    match input {
+        [0x1b, b']' | b'P' | b'_' | b'^' | b'X', ..] => Some(2),
+        [0xc2, 0x9d | 0x90 | 0x9f | 0x9e | 0x98, ..] => Some(2),
+        _ => None,
+    }
+}
+
+/// Length of the string terminator (BEL, ESC \ or C1 ST) at the start of `input`, if any
+fn string_terminator_len(input: &[u8]) -> Option<usize> {
+    match input {
+        [0x07, ..] => Some(1),
+        [0x1b, b'\\', ..] | [0xc2, 0x9c, ..] => Some(2),
+        _ => None,
+    }
+}
+
+/// Remove OSC/DCS/APC/PM/SOS strings, including their terminators
+///
+/// Unterminated strings are removed up to the end of the input.
+fn strip_control_strings(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < input.len() {
+        let Some(len) = string_introducer_len(&input[i..]) else {
+            out.push(input[i]);
+            i += 1;
+            continue;
+        };
+
+        // Skip to the string terminator
+        i += len;
+        while i < input.len() {
+            if let Some(len) = string_terminator_len(&input[i..]) {
+                i += len;
+                break;
+            }
+            i += 1;
+        }
+    }
+
+    out
+}
+
+/// Whether the input contains any OSC/DCS/APC/PM/SOS string
+fn contains_control_string(input: &[u8]) -> bool {
+    (0..input.len()).any(|i| string_introducer_len(&input[i..]).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_strip_control_strings() {
+        // Title change terminated by BEL, clipboard write terminated by ST
+        let input = "ls\x1b]0;pwned\x07 -la\x1b]52;c;ZWNobyBoaQ==\x1b\\\r";
+        assert_eq!(strip_control_strings(input.as_bytes()), b"ls -la\r");
+        // DCS and 8-bit OSC
+        let input = "a\x1bPq#0\x1b\\b\u{9d}2;x\u{9c}c";
+        assert_eq!(strip_control_strings(input.as_bytes()), b"abc");
+        // Unterminated strings swallow the rest
+        assert_eq!(strip_control_strings(b"ok\x1b]0;never ends"), b"ok");
+    }
+
+    #[test]
+    fn test_keyboard_sequences_pass_through() {
+        // Arrow keys, bracketed paste, Alt+x, Ctrl-C
+        let input = "\x1b[A\x1b[200~paste\x1b[201~\x1bx\x03 caf\u{e9}";
+        assert_eq!(strip_control_strings(input.as_bytes()), input.as_bytes());
+        assert!(!contains_control_string(input.as_bytes()));
+    }
+
+    #[test]
+    fn test_reject_policy() {
+        let filter = InputFilter::new(InputPolicy::default().with_control(ControlPolicy::Reject));
+        let agent = Uuid::new_v4();
+        assert_eq!(
+            filter.apply(agent, "\x1b]0;x\x07".to_string()),
+            Err(InputRejected::ControlSequence)
+        );
+        assert_eq!(filter.apply(agent, "hello".to_string()), Ok("hello".to_string()));
+    }
+
+    #[test]
+    fn test_raw_bytes() {
+        let filter = InputFilter::new(InputPolicy::default());
+        // Invalid UTF-8 passes through; control strings are still removed
+        assert_eq!(
+            filter.apply_bytes(Uuid::new_v4(), b"\xff\x1b]0;t\x07\x1bOP".to_vec()),
+            Ok(b"\xff\x1bOP".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_per_agent() {
+        let filter = InputFilter::new(InputPolicy::default().with_rate_limit(Some(10)));
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+
+        assert!(filter.take(a, 8, 10.0, start).is_ok());
+        assert_eq!(filter.take(a, 8, 10.0, start), Err(InputRejected::RateLimited));
+        // Other agents have their own budget
+        assert!(filter.take(b, 8, 10.0, start).is_ok());
+        // The budget refills over time
+        assert!(filter.take(a, 8, 10.0, start + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_parse_control_policy() {
+        assert_eq!("reject".parse::<ControlPolicy>(), Ok(ControlPolicy::Reject));
+        assert!("block".parse::<ControlPolicy>().is_err());
+    }
+}
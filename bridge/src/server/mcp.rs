@@ -0,0 +1,640 @@
+//! Model Context Protocol server
+//!
+//! With `--mcp` the bridge serves the process that launched it, typically an
+//! AI tool, as an MCP server over its standard input and output: JSON-RPC
+//! messages, one per line. Its tools list, spawn, drive and kill agents and
+//! read what they print, so one agent can orchestrate others through the
+//! bridge. Tool calls are translated into protocol messages and go through the
+//! same handler as WebSocket clients. Log messages go to standard error in
+//! this mode.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::protocol::{AgentFilter, ClientMessage, Role, ServerMessage};
+use super::websocket::{dispatch_request, ServerState};
+use crate::agent::{push_window, AgentEvent};
+
+/// Client address MCP tool calls are logged and audited under
+const MCP_CLIENT: &str = "mcp";
+
+/// MCP revisions spoken, newest first
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Output text kept per agent for `read_output`
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// Lines `read_output` returns unless asked for another number
+const DEFAULT_OUTPUT_LINES: usize = 100;
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// A JSON-RPC request or notification (without an `id`)
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// A JSON-RPC error
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Escape-free output of each agent, as seen since the session started
+#[derive(Debug, Default)]
+struct Outputs {
+    agents: Mutex<HashMap<Uuid, String>>,
+}
+
+impl Outputs {
+    fn push(&self, agent_id: Uuid, data: &[u8]) {
+        let mut agents = self.agents.lock().unwrap_or_else(|e| e.into_inner());
+        push_window(agents.entry(agent_id).or_default(), data, MAX_OUTPUT_BYTES);
+    }
+
+    /// The last `count` lines an agent printed, as a terminal would show
+    /// them after carriage returns
+    fn tail(&self, agent_id: Uuid, count: usize) -> Option<String> {
+        let agents = self.agents.lock().unwrap_or_else(|e| e.into_inner());
+        let output = agents.get(&agent_id)?;
+        let lines: Vec<&str> = output
+            .split('\n')
+            .map(|line| {
+                line.trim_end_matches('\r')
+                    .rsplit('\r')
+                    .next()
+                    .unwrap_or("")
+            })
+            .collect();
+        let start = lines.len().saturating_sub(count);
+        Some(lines[start..].join("\n"))
+    }
+}
+
+/// Serve one MCP session over a reader and writer until the client closes
+/// the reader or the server shuts down
+pub(super) async fn serve_mcp<R, W>(
+    reader: R,
+    mut writer: W,
+    state: Arc<ServerState>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    info!("Serving MCP over standard input and output");
+    let outputs = Arc::new(Outputs::default());
+    let capture = tokio::spawn({
+        let mut events = state.agent_manager.subscribe();
+        let outputs = Arc::clone(&outputs);
+        async move {
+            loop {
+                match events.recv().await {
+                    Ok(AgentEvent::Output { agent_id, data }) => outputs.push(agent_id, &data),
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("MCP output capture missed {} agent event(s)", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(reader).lines();
+    let result = loop {
+        let line = tokio::select! {
+            _ = shutdown_rx.recv() => break Ok(()),
+            line = lines.next_line() => line,
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(response) = handle_line(&line, &state, &outputs).await else {
+            continue;
+        };
+        let mut json = response.to_string();
+        json.push('\n');
+        if let Err(e) = writer.write_all(json.as_bytes()).await {
+            break Err(e.into());
+        }
+        if let Err(e) = writer.flush().await {
+            break Err(e.into());
+        }
+    };
+    capture.abort();
+    info!("MCP session ended");
+    result
+}
+
+/// Answer one JSON-RPC message; notifications get no answer
+async fn handle_line(line: &str, state: &ServerState, outputs: &Outputs) -> Option<Value> {
+    let request = match serde_json::from_str::<Value>(line) {
+        Ok(value) => match serde_json::from_value::<Request>(value) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(INVALID_REQUEST, e.to_string()),
+                ))
+            }
+        },
+        Err(e) => {
+            return Some(error_response(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    let Some(id) = request.id else {
+        debug!("MCP notification {}", request.method);
+        return None;
+    };
+    let result = match request.method.as_str() {
+        "initialize" => Ok(initialize(&request.params)),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => call_tool(&request.params, state, outputs).await,
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {}", method),
+        )),
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    })
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+/// Agree on the client's MCP revision if it is spoken, else offer the newest
+fn initialize(params: &Value) -> Value {
+    let requested = params.get("protocolVersion").and_then(Value::as_str);
+    let version = requested
+        .filter(|v| PROTOCOL_VERSIONS.contains(v))
+        .unwrap_or(PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": {} },
+        "serverInfo": { "name": "hoc-bridge", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Agents are terminal sessions (usually Claude Code) run by the bridge. Spawn one in a project, send it input, and read its output to follow what it does.",
+    })
+}
+
+/// The tools offered, with the JSON schemas of their arguments
+fn tools() -> Value {
+    let agent_id = json!({ "type": "string", "description": "ID of the agent" });
+    json!([
+        {
+            "name": "list_agents",
+            "description": "List the bridge's agents with their status, project and name.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "tag": { "type": "string", "description": "Only agents with this tag" },
+                    "project_path": { "type": "string", "description": "Only agents working in this directory or below it" },
+                },
+            },
+        },
+        {
+            "name": "spawn_agent",
+            "description": "Start an agent in a project directory, optionally with a preset and an initial prompt.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "project_path": { "type": "string", "description": "Directory the agent works in" },
+                    "preset": { "type": "string", "description": "Preset to spawn the agent with" },
+                    "prompt": { "type": "string", "description": "Initial prompt, sent once the agent is ready" },
+                    "name": { "type": "string", "description": "Display name of the agent" },
+                },
+                "required": ["project_path"],
+            },
+        },
+        {
+            "name": "send_input",
+            "description": "Type text into an agent's terminal, pressing Enter after it unless submit is false.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "agent_id": agent_id,
+                    "input": { "type": "string", "description": "Text to type" },
+                    "submit": { "type": "boolean", "description": "Press Enter after the text (default true)" },
+                },
+                "required": ["agent_id", "input"],
+            },
+        },
+        {
+            "name": "read_output",
+            "description": "Read the last lines an agent printed, without terminal escape sequences.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "agent_id": agent_id,
+                    "lines": { "type": "integer", "minimum": 1, "description": "Number of lines (default 100)" },
+                },
+                "required": ["agent_id"],
+            },
+        },
+        {
+            "name": "read_screen",
+            "description": "Read what an agent's terminal shows right now, as text.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "agent_id": agent_id },
+                "required": ["agent_id"],
+            },
+        },
+        {
+            "name": "kill_agent",
+            "description": "Stop an agent.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "agent_id": agent_id },
+                "required": ["agent_id"],
+            },
+        },
+    ])
+}
+
+/// Arguments of the tools, all optional so that missing ones are reported
+/// by name
+#[derive(Debug, Default, Deserialize)]
+struct ToolArguments {
+    agent_id: Option<Uuid>,
+    project_path: Option<String>,
+    preset: Option<String>,
+    prompt: Option<String>,
+    name: Option<String>,
+    tag: Option<String>,
+    input: Option<String>,
+    submit: Option<bool>,
+    lines: Option<usize>,
+}
+
+/// Run a tool; failures of the tool itself are results flagged `isError`,
+/// so the model sees them
+async fn call_tool(
+    params: &Value,
+    state: &ServerState,
+    outputs: &Outputs,
+) -> Result<Value, RpcError> {
+    let name = params
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing tool name"))?;
+    let arguments = params.get("arguments").cloned().unwrap_or(Value::Null);
+    let args: ToolArguments = if arguments.is_null() {
+        ToolArguments::default()
+    } else {
+        serde_json::from_value(arguments)
+            .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid arguments: {}", e)))?
+    };
+    debug!("MCP tool call {}", name);
+    let outcome = match name {
+        "list_agents" => list_agents(args, state).await,
+        "spawn_agent" => spawn_agent(args, state).await,
+        "send_input" => send_input(args, state).await,
+        "read_output" => read_output(args, outputs),
+        "read_screen" => read_screen(args, state).await,
+        "kill_agent" => kill_agent(args, state).await,
+        _ => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("Unknown tool {}", name),
+            ))
+        }
+    };
+    let (text, is_error) = match outcome {
+        Ok(text) => (text, false),
+        Err(error) => (error, true),
+    };
+    Ok(json!({
+        "content": [{ "type": "text", "text": text }],
+        "isError": is_error,
+    }))
+}
+
+async fn list_agents(args: ToolArguments, state: &ServerState) -> Result<String, String> {
+    let filter = AgentFilter {
+        tag: args.tag,
+        status: None,
+        project_path: args.project_path,
+    };
+    match dispatch(state, ClientMessage::ListAgents { filter }).await? {
+        Some(ServerMessage::AgentList { agents }) => to_json(&agents),
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn spawn_agent(args: ToolArguments, state: &ServerState) -> Result<String, String> {
+    let message = ClientMessage::SpawnAgent {
+        project_path: required(args.project_path, "project_path")?,
+        preset: args.preset,
+        cols: None,
+        rows: None,
+        tags: Vec::new(),
+        group: None,
+        name: args.name,
+        mode: None,
+        prompt: args.prompt,
+        env: Default::default(),
+        cwd: None,
+        report_echo: false,
+    };
+    match dispatch(state, message).await? {
+        Some(ServerMessage::AgentSpawned { agent_id, .. }) => {
+            match state.agent_manager.get_agent_status(agent_id).await {
+                Ok(info) => to_json(&info),
+                Err(_) => Ok(format!(
+                    "Agent {} was spawned and has already exited",
+                    agent_id
+                )),
+            }
+        }
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn send_input(args: ToolArguments, state: &ServerState) -> Result<String, String> {
+    let agent_id = required(args.agent_id, "agent_id")?;
+    let mut input = required(args.input, "input")?;
+    if args.submit.unwrap_or(true) {
+        input.push('\r');
+    }
+    let message = ClientMessage::AgentInput {
+        agent_id,
+        input,
+        paste: false,
+    };
+    dispatch(state, message).await?;
+    Ok(format!("Sent the input to agent {}", agent_id))
+}
+
+fn read_output(args: ToolArguments, outputs: &Outputs) -> Result<String, String> {
+    let agent_id = required(args.agent_id, "agent_id")?;
+    let lines = args.lines.unwrap_or(DEFAULT_OUTPUT_LINES).max(1);
+    outputs.tail(agent_id, lines).ok_or_else(|| {
+        format!(
+            "Agent {} has printed nothing since the session started",
+            agent_id
+        )
+    })
+}
+
+async fn read_screen(args: ToolArguments, state: &ServerState) -> Result<String, String> {
+    let agent_id = required(args.agent_id, "agent_id")?;
+    match dispatch(state, ClientMessage::GetScreenState { agent_id }).await? {
+        Some(ServerMessage::ScreenState { screen, .. }) => {
+            let rows: Vec<String> = screen
+                .cells
+                .iter()
+                .map(|row| {
+                    let text: String = row
+                        .iter()
+                        .map(|cell| match cell.text.as_str() {
+                            "" => " ",
+                            text => text,
+                        })
+                        .collect();
+                    text.trim_end().to_string()
+                })
+                .collect();
+            Ok(rows.join("\n").trim_end().to_string())
+        }
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn kill_agent(args: ToolArguments, state: &ServerState) -> Result<String, String> {
+    let agent_id = required(args.agent_id, "agent_id")?;
+    let message = ClientMessage::KillAgent {
+        agent_id,
+        signal: None,
+    };
+    dispatch(state, message).await?;
+    Ok(format!("Agent {} was stopped", agent_id))
+}
+
+/// Validate and handle a protocol message, turning errors into tool errors;
+/// messages without a reply, like input, give `None`
+async fn dispatch(
+    state: &ServerState,
+    message: ClientMessage,
+) -> Result<Option<ServerMessage>, String> {
+    match dispatch_request(state, message, Some(MCP_CLIENT), MCP_CLIENT, Role::Admin).await {
+        Ok(Some(ServerMessage::Error { message, .. })) => Err(message),
+        Ok(response) => Ok(response),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn required<T>(value: Option<T>, name: &str) -> Result<T, String> {
+    value.ok_or_else(|| format!("Missing argument {}", name))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string_pretty(value).map_err(|e| e.to_string())
+}
+
+fn unexpected(response: Option<ServerMessage>) -> String {
+    match response {
+        Some(response) => format!("Unexpected response: {:?}", response),
+        // Requests for agents of peer bridges are answered asynchronously
+        None => "The request was forwarded to a peer bridge".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentManager;
+    use crate::pty::{PtyScript, ScriptedPtyBackend};
+    use crate::server::federation::Federation;
+    use crate::server::ServerConfig;
+    use tokio::io::{AsyncWriteExt, DuplexStream, Lines};
+
+    async fn call(
+        client_in: &mut DuplexStream,
+        lines: &mut Lines<BufReader<DuplexStream>>,
+        request: Value,
+    ) -> Value {
+        let mut line = request.to_string();
+        line.push('\n');
+        client_in.write_all(line.as_bytes()).await.unwrap();
+        let response = lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    fn tool_call(id: u64, name: &str, arguments: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "tools/call",
+            "params": { "name": name, "arguments": arguments },
+        })
+    }
+
+    #[test]
+    fn test_output_tail() {
+        let outputs = Outputs::default();
+        let agent_id = Uuid::new_v4();
+        assert_eq!(outputs.tail(agent_id, 10), None);
+        outputs.push(agent_id, b"one\r\n\x1b[32mtwo\x1b[0m\r\n10%\r50%\r100%\r\n");
+        assert_eq!(outputs.tail(agent_id, 10).unwrap(), "one\ntwo\n100%\n");
+        assert_eq!(outputs.tail(agent_id, 2).unwrap(), "100%\n");
+    }
+
+    #[tokio::test]
+    async fn test_mcp_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = PtyScript::echo();
+        let manager = Arc::new(
+            AgentManager::new().with_pty_backend(Arc::new(ScriptedPtyBackend::new(script))),
+        );
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let state = Arc::new(ServerState::with_backend(
+            ServerConfig::new("127.0.0.1".to_string(), 0),
+            Federation::new(),
+            manager.clone(),
+            manager,
+            shutdown_tx.clone(),
+        ));
+        let (mut client_in, bridge_in) = tokio::io::duplex(64 * 1024);
+        let (bridge_out, client_out) = tokio::io::duplex(64 * 1024);
+        let session = tokio::spawn(serve_mcp(
+            bridge_in,
+            bridge_out,
+            state,
+            shutdown_tx.subscribe(),
+        ));
+        let mut lines = BufReader::new(client_out).lines();
+
+        let init = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": { "protocolVersion": "2025-03-26", "capabilities": {} },
+        });
+        let response = call(&mut client_in, &mut lines, init).await;
+        assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(response["result"]["serverInfo"]["name"], "hoc-bridge");
+        // Notifications are not answered
+        client_in
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"notifications/initialized\"}\n")
+            .await
+            .unwrap();
+
+        let listed = call(
+            &mut client_in,
+            &mut lines,
+            json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"}),
+        )
+        .await;
+        let names: Vec<_> = listed["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "list_agents",
+                "spawn_agent",
+                "send_input",
+                "read_output",
+                "read_screen",
+                "kill_agent"
+            ]
+        );
+
+        let spawn = tool_call(3, "spawn_agent", json!({"project_path": dir.path()}));
+        let spawned = call(&mut client_in, &mut lines, spawn).await;
+        assert_eq!(spawned["result"]["isError"], false);
+        let text = spawned["result"]["content"][0]["text"].as_str().unwrap();
+        let agent: Value = serde_json::from_str(text).unwrap();
+        let agent_id = agent["agent_id"].as_str().unwrap().to_string();
+
+        let input = tool_call(
+            4,
+            "send_input",
+            json!({"agent_id": agent_id, "input": "hello"}),
+        );
+        let sent = call(&mut client_in, &mut lines, input).await;
+        assert_eq!(sent["result"]["isError"], false, "{}", sent);
+        // The scripted terminal echoes the input back
+        let mut output = String::new();
+        for id in 5..50 {
+            let read = tool_call(id, "read_output", json!({"agent_id": agent_id}));
+            let read = call(&mut client_in, &mut lines, read).await;
+            if read["result"]["isError"] == false {
+                output = read["result"]["content"][0]["text"]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
+                if output.contains("hello") {
+                    break;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(output.contains("hello"), "{:?}", output);
+
+        let missing = tool_call(50, "kill_agent", json!({}));
+        let missing = call(&mut client_in, &mut lines, missing).await;
+        assert_eq!(missing["result"]["isError"], true);
+        assert_eq!(
+            missing["result"]["content"][0]["text"],
+            "Missing argument agent_id"
+        );
+        let unknown = call(
+            &mut client_in,
+            &mut lines,
+            json!({"jsonrpc": "2.0", "id": 51, "method": "resources/list"}),
+        )
+        .await;
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+
+        drop(client_in);
+        session.await.unwrap().unwrap();
+    }
+}
@@ -3,14 +3,67 @@
 //! Handles WebSocket connections from Godot clients and routes messages
 //! to the appropriate handlers.
 
+mod audit;
+mod bandwidth;
+mod batch;
+mod clipboard;
+mod cluster;
+mod control;
+mod dashboard;
+mod discovery;
+mod env_policy;
+mod federation;
+mod groups;
+mod grpc;
 #[allow(dead_code)]
 mod handler;
-#[allow(dead_code)]
+mod health;
+mod heartbeat;
+mod http;
+mod input_policy;
+mod mcp;
+mod origin;
+mod paste;
+mod pipelines;
+mod preflight;
 mod protocol;
+mod proxy;
+mod quic;
+mod quota;
+mod rate_limit;
+mod relay;
+mod reload;
+mod replay;
+mod resume;
+mod rest;
+mod schedules;
+mod stdio;
+mod summary;
+mod tasks;
+mod tls;
+mod transfer;
+mod transport;
+mod watch;
+mod webhooks;
 mod websocket;
 
 #[allow(unused_imports)]
 pub use protocol::{
-    AgentInfo, AgentState, ClientMessage, ErrorCode, ServerMessage, PROTOCOL_VERSION,
+    AgentInfo, AgentState, Capability, ClientMessage, ErrorCode, ServerMessage, TerminalLimits,
+    PROTOCOL_VERSION,
 };
+pub use audit::AuditLog;
+pub use cluster::ClusterConfig;
+pub use discovery::DiscoveryConfig;
+pub use env_policy::EnvPolicy;
+pub use federation::PeerConfig;
+pub use heartbeat::HeartbeatConfig;
+pub use input_policy::{ControlPolicy, InputPolicy};
+pub use origin::OriginPolicy;
+pub use quic::QuicConfig;
+pub use quota::AgentLimits;
+pub use rate_limit::{ConnectionLimits, DEFAULT_MESSAGE_RATE};
+pub use reload::{ConfigSource, ReloadableConfig};
+pub use tasks::DEFAULT_MAX_RUNNING_TASKS;
+pub use tls::TlsConfig;
 pub use websocket::{ServerConfig, WebSocketServer};
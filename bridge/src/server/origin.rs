@@ -0,0 +1,157 @@
+//! Origin and Host checks for WebSocket upgrades
+//!
+//! Browsers let any web page open a WebSocket to the bridge, even on
+//! localhost, and tell the server which page it is through the `Origin`
+//! header. Native clients send no `Origin`, so only upgrades that carry one
+//! are checked: the origin must be allowed, or be the dashboard served by
+//! this bridge. The `Host` header of such upgrades must name the bridge by IP
+//! address, `localhost` or an allowed host name, so a page cannot reach it
+//! through a domain it rebinds to 127.0.0.1.
+
+use std::net::IpAddr;
+
+use tokio_tungstenite::tungstenite::http::HeaderMap;
+
+/// Which browser pages may connect
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OriginPolicy {
+    /// Origins allowed to connect, such as `https://app.example.com`; `*`
+    /// allows every origin
+    pub allowed_origins: Vec<String>,
+    /// Host names browsers may reach the bridge by, besides IP addresses and
+    /// `localhost`
+    pub allowed_hosts: Vec<String>,
+}
+
+impl OriginPolicy {
+    /// Allow these origins
+    pub fn with_allowed_origins(mut self, origins: Vec<String>) -> Self {
+        self.allowed_origins = origins;
+        self
+    }
+
+    /// Allow browsers to reach the bridge by these host names
+    pub fn with_allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.allowed_hosts = hosts;
+        self
+    }
+
+    /// Check an upgrade request's headers, returning why it is refused
+    ///
+    /// `dashboard_port` is the port the bridge serves its dashboard on, whose
+    /// pages may connect.
+    pub fn check(&self, headers: &HeaderMap, dashboard_port: Option<u16>) -> Result<(), String> {
+        let Some(origin) = headers.get("origin") else {
+            return Ok(());
+        };
+        let origin = origin
+            .to_str()
+            .map_err(|_| "Malformed Origin header".to_string())?
+            .trim_end_matches('/')
+            .to_ascii_lowercase();
+        if self.allowed_origins.iter().any(|allowed| {
+            allowed == "*" || allowed.trim_end_matches('/').eq_ignore_ascii_case(&origin)
+        }) {
+            return Ok(());
+        }
+
+        let host = headers
+            .get("host")
+            .and_then(|v| v.to_str().ok())
+            .map(host_name)
+            .ok_or_else(|| "Missing Host header".to_string())?
+            .to_ascii_lowercase();
+        if !self.host_allowed(&host) {
+            return Err(format!("Host {} is not allowed", host));
+        }
+        let is_dashboard = dashboard_port.is_some_and(|port| {
+            ["http", "https"]
+                .iter()
+                .any(|scheme| origin == format!("{}://{}:{}", scheme, host, port))
+        });
+        if is_dashboard {
+            Ok(())
+        } else {
+            Err(format!("Origin {} is not allowed", origin))
+        }
+    }
+
+    /// Whether browsers may reach the bridge by this host name
+    fn host_allowed(&self, host: &str) -> bool {
+        host == "localhost"
+            || host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok()
+            || self
+                .allowed_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+}
+
+/// Host name of a `Host` header value, without the port
+fn host_name(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 literal, e.g. [::1]:9000
+        return host.split_inclusive(']').next().unwrap_or(host);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::http::HeaderValue;
+
+    fn headers(origin: Option<&'static str>, host: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", HeaderValue::from_static(host));
+        if let Some(origin) = origin {
+            headers.insert("origin", HeaderValue::from_static(origin));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_native_clients_pass() {
+        let policy = OriginPolicy::default();
+        assert!(policy
+            .check(&headers(None, "bridge.lan:9000"), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_browser_origins() {
+        let policy = OriginPolicy::default()
+            .with_allowed_origins(vec!["https://app.example.com/".to_string()]);
+        let evil = headers(Some("https://evil.example"), "localhost:9000");
+        assert!(policy.check(&evil, Some(9001)).is_err());
+        let app = headers(Some("https://APP.example.com"), "bridge.lan:9000");
+        assert!(policy.check(&app, None).is_ok());
+
+        // Pages of the bridge's own dashboard may connect
+        let dashboard = headers(Some("http://127.0.0.1:9001"), "127.0.0.1:9000");
+        assert!(policy.check(&dashboard, Some(9001)).is_ok());
+        assert!(policy.check(&dashboard, None).is_err());
+
+        let any = OriginPolicy::default().with_allowed_origins(vec!["*".to_string()]);
+        assert!(any.check(&evil, None).is_ok());
+    }
+
+    #[test]
+    fn test_rebound_host_is_refused() {
+        let policy = OriginPolicy::default();
+        let rebound = headers(Some("http://rebind.example:9001"), "rebind.example:9000");
+        assert_eq!(
+            policy.check(&rebound, Some(9001)),
+            Err("Host rebind.example is not allowed".to_string())
+        );
+        let policy = policy.with_allowed_hosts(vec!["rebind.example".to_string()]);
+        assert!(policy.check(&rebound, Some(9001)).is_ok());
+
+        let ipv6 = headers(Some("http://[::1]:9001"), "[::1]:9000");
+        assert!(OriginPolicy::default().check(&ipv6, Some(9001)).is_ok());
+    }
+}
@@ -0,0 +1,196 @@
+//! Chunked paste input
+//!
+//! Large pastes arrive as numbered `agent_input_chunk` messages. Each
+//! connection assembles them per agent, and the finished paste is written to
+//! the PTY in small slices with a pause in between, so the application reads
+//! it at its own pace instead of having its input buffer flooded.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::agent::{AgentBackend, ManagerResult};
+
+/// Maximum size of an assembled paste (16MB)
+pub const MAX_PASTE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Bytes written to the PTY at a time
+const WRITE_SLICE: usize = 4096;
+
+/// Pause between slices
+const WRITE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Problems with a chunk sequence; the pending paste is discarded
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PasteError {
+    #[error("Expected chunk {expected} but got {got}")]
+    OutOfOrder { expected: u32, got: u32 },
+
+    #[error("Chunk count changed from {expected} to {got}")]
+    CountMismatch { expected: u32, got: u32 },
+
+    #[error("Paste exceeds maximum length of {} bytes", MAX_PASTE_LENGTH)]
+    TooLarge,
+}
+
+/// A paste still being received
+struct PendingPaste {
+    next_part: u32,
+    of: u32,
+    data: String,
+}
+
+/// Assembles chunked pastes for one connection
+#[derive(Default)]
+pub(super) struct PasteAssembler {
+    pending: HashMap<Uuid, PendingPaste>,
+}
+
+impl PasteAssembler {
+    /// Add a chunk, returning the whole paste once its last chunk arrived
+    ///
+    /// Part 0 always starts a new paste, discarding any unfinished one for
+    /// the same agent.
+    pub(super) fn add(
+        &mut self,
+        agent_id: Uuid,
+        part: u32,
+        of: u32,
+        data: &str,
+    ) -> Result<Option<String>, PasteError> {
+        if part == 0 {
+            self.pending.insert(
+                agent_id,
+                PendingPaste {
+                    next_part: 0,
+                    of,
+                    data: String::new(),
+                },
+            );
+        }
+
+        let Some(paste) = self.pending.get_mut(&agent_id) else {
+            return Err(PasteError::OutOfOrder {
+                expected: 0,
+                got: part,
+            });
+        };
+        let result = if paste.of != of {
+            Err(PasteError::CountMismatch {
+                expected: paste.of,
+                got: of,
+            })
+        } else if paste.next_part != part {
+            Err(PasteError::OutOfOrder {
+                expected: paste.next_part,
+                got: part,
+            })
+        } else if paste.data.len() + data.len() > MAX_PASTE_LENGTH {
+            Err(PasteError::TooLarge)
+        } else {
+            paste.data.push_str(data);
+            paste.next_part += 1;
+            Ok(())
+        };
+
+        if let Err(e) = result {
+            self.pending.remove(&agent_id);
+            return Err(e);
+        }
+        if part + 1 == of {
+            return Ok(self.pending.remove(&agent_id).map(|paste| paste.data));
+        }
+        Ok(None)
+    }
+}
+
+/// Write input to an agent in slices, pausing between them
+pub(super) async fn write_paced(
+    agent_manager: Arc<dyn AgentBackend>,
+    agent_id: Uuid,
+    input: &str,
+) -> ManagerResult<()> {
+    let mut rest = input;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(WRITE_SLICE);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (slice, tail) = rest.split_at(end);
+        agent_manager.send_input(agent_id, slice).await?;
+        rest = tail;
+        if !rest.is_empty() {
+            tokio::time::sleep(WRITE_INTERVAL).await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_in_order() {
+        let mut assembler = PasteAssembler::default();
+        let agent = Uuid::new_v4();
+        assert_eq!(assembler.add(agent, 0, 3, "foo"), Ok(None));
+        assert_eq!(assembler.add(agent, 1, 3, "bar"), Ok(None));
+        assert_eq!(assembler.add(agent, 2, 3, "baz"), Ok(Some("foobarbaz".to_string())));
+        // Nothing pending afterwards
+        assert!(assembler.add(agent, 1, 3, "x").is_err());
+    }
+
+    #[test]
+    fn test_single_chunk_paste() {
+        let mut assembler = PasteAssembler::default();
+        assert_eq!(
+            assembler.add(Uuid::new_v4(), 0, 1, "all"),
+            Ok(Some("all".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_out_of_order_discards_paste() {
+        let mut assembler = PasteAssembler::default();
+        let agent = Uuid::new_v4();
+        assembler.add(agent, 0, 3, "a").unwrap();
+        assert_eq!(
+            assembler.add(agent, 2, 3, "c"),
+            Err(PasteError::OutOfOrder {
+                expected: 1,
+                got: 2
+            })
+        );
+        assert!(assembler.add(agent, 1, 3, "b").is_err());
+        // A new paste can start over
+        assert_eq!(assembler.add(agent, 0, 1, "z"), Ok(Some("z".to_string())));
+    }
+
+    #[test]
+    fn test_count_mismatch() {
+        let mut assembler = PasteAssembler::default();
+        let agent = Uuid::new_v4();
+        assembler.add(agent, 0, 3, "a").unwrap();
+        assert_eq!(
+            assembler.add(agent, 1, 4, "b"),
+            Err(PasteError::CountMismatch {
+                expected: 3,
+                got: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_pastes_are_tracked_per_agent() {
+        let mut assembler = PasteAssembler::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assembler.add(a, 0, 2, "a1").unwrap();
+        assembler.add(b, 0, 2, "b1").unwrap();
+        assert_eq!(assembler.add(a, 1, 2, "a2"), Ok(Some("a1a2".to_string())));
+        assert_eq!(assembler.add(b, 1, 2, "b2"), Ok(Some("b1b2".to_string())));
+    }
+}
@@ -0,0 +1,421 @@
+//! Pipeline runner
+//!
+//! Runs the pipelines a project's config defines when a client sends
+//! `run_pipeline`. Each run works through its steps in a task of its own,
+//! spawning agents, creating worktrees and sending input through the same
+//! handler client requests go through, so capabilities, allowed roots and
+//! agent limits apply as usual. Every client is sent `pipeline_step` as a
+//! step starts and `pipeline_completed` once the run is over.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{broadcast, Notify};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::protocol::{AgentState, ClientMessage, ServerMessage};
+use super::websocket::{handle_client_message, ServerState};
+use crate::agent::AgentEvent;
+use crate::config::{PipelineConfig, PipelineStep, WaitUntil};
+
+/// A pipeline waiting to start
+struct PipelineRun {
+    run_id: Uuid,
+    project_path: String,
+    pipeline: PipelineConfig,
+    /// Client that started the run, which its agents are spawned for
+    client: Option<String>,
+    /// Session token the run's agents are owned by
+    owner: Option<String>,
+}
+
+/// Pipelines started by clients
+pub(super) struct Pipelines {
+    pending: Mutex<Vec<PipelineRun>>,
+    events: broadcast::Sender<ServerMessage>,
+    /// Notified when a run was started
+    wake: Notify,
+}
+
+impl Default for Pipelines {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            pending: Mutex::new(Vec::new()),
+            events,
+            wake: Notify::new(),
+        }
+    }
+}
+
+impl Pipelines {
+    /// Subscribe to pipeline steps starting and runs completing
+    pub(super) fn subscribe(&self) -> broadcast::Receiver<ServerMessage> {
+        self.events.subscribe()
+    }
+
+    /// Start running a pipeline, returning the ID of the run
+    pub(super) fn start(
+        &self,
+        project_path: String,
+        pipeline: PipelineConfig,
+        client: Option<String>,
+        owner: Option<String>,
+    ) -> Uuid {
+        let run_id = Uuid::new_v4();
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(PipelineRun {
+                run_id,
+                project_path,
+                pipeline,
+                client,
+                owner,
+            });
+        self.wake.notify_one();
+        run_id
+    }
+
+    fn take_pending(&self) -> Vec<PipelineRun> {
+        std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Run started pipelines until shutdown
+pub(super) async fn run_pipelines(state: Arc<ServerState>, shutdown_tx: broadcast::Sender<()>) {
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.recv() => break,
+            _ = state.pipelines.wake.notified() => {
+                for run in state.pipelines.take_pending() {
+                    tokio::spawn(run_pipeline(Arc::clone(&state), run));
+                }
+            }
+        }
+    }
+}
+
+/// What a run has seen of an agent it spawned
+#[derive(Debug, Default)]
+struct AgentProgress {
+    /// The agent showed its ready prompt
+    ready: bool,
+    /// Triggers fired and not yet waited for
+    triggers: Vec<String>,
+    /// The agent exited, with its exit code if it had one
+    exit: Option<Option<i32>>,
+}
+
+impl AgentProgress {
+    fn observe(&mut self, event: AgentEvent) {
+        match event {
+            AgentEvent::StateChanged {
+                new_state: AgentState::Ready,
+                ..
+            } => self.ready = true,
+            AgentEvent::TriggerFired { trigger, .. } => self.triggers.push(trigger),
+            AgentEvent::Exited { exit_code, .. } => self.exit = Some(exit_code),
+            _ => {}
+        }
+    }
+
+    /// How a wait for `agent` ends, or `None` while what it waits for may
+    /// still happen. A trigger is only waited for once per time it fired.
+    fn check(
+        &mut self,
+        agent: &str,
+        trigger: Option<&str>,
+        until: WaitUntil,
+    ) -> Option<Result<(), String>> {
+        if let Some(trigger) = trigger {
+            if let Some(index) = self.triggers.iter().position(|t| t == trigger) {
+                self.triggers.remove(index);
+                return Some(Ok(()));
+            }
+            return self.exit.map(|_| {
+                Err(format!(
+                    "Agent {} exited before trigger {} fired",
+                    agent, trigger
+                ))
+            });
+        }
+        match (until, self.exit) {
+            (WaitUntil::Ready, _) if self.ready => Some(Ok(())),
+            (WaitUntil::Ready, Some(_)) => {
+                Some(Err(format!("Agent {} exited before it was ready", agent)))
+            }
+            (WaitUntil::Exit, Some(Some(0))) => Some(Ok(())),
+            (WaitUntil::Exit, Some(Some(code))) => {
+                Some(Err(format!("Agent {} exited with code {}", agent, code)))
+            }
+            (WaitUntil::Exit, Some(None)) => {
+                Some(Err(format!("Agent {} exited without an exit code", agent)))
+            }
+            (_, None) => None,
+        }
+    }
+}
+
+/// Work through a run's steps and report how it went
+async fn run_pipeline(state: Arc<ServerState>, run: PipelineRun) {
+    let started = Instant::now();
+    info!(
+        "Running pipeline {} in {} ({})",
+        run.pipeline.name, run.project_path, run.run_id
+    );
+    let mut runner = Runner {
+        // Subscribed before the first spawn so that none of the agents'
+        // events are missed
+        events: state.agent_manager.subscribe(),
+        state: &state,
+        run: &run,
+        agents: BTreeMap::new(),
+        progress: HashMap::new(),
+    };
+    let mut failed = None;
+    for (index, step) in run.pipeline.steps.iter().enumerate() {
+        let _ = state.pipelines.events.send(ServerMessage::PipelineStep {
+            run_id: run.run_id,
+            step: index,
+            action: step.action().to_string(),
+            agent: step.agent().to_string(),
+            agent_id: runner.agents.get(step.agent()).copied(),
+        });
+        if let Err(error) = runner.step(step).await {
+            info!(
+                "Pipeline {} failed at step {}: {}",
+                run.pipeline.name, index, error
+            );
+            failed = Some((index, error));
+            break;
+        }
+    }
+
+    let (failed_step, error) = failed.unzip();
+    let _ = state.pipelines.events.send(ServerMessage::PipelineCompleted {
+        run_id: run.run_id,
+        success: failed_step.is_none(),
+        failed_step,
+        error,
+        agents: runner.agents,
+        duration_ms: started.elapsed().as_millis() as u64,
+    });
+}
+
+/// A pipeline run in progress
+struct Runner<'a> {
+    state: &'a ServerState,
+    run: &'a PipelineRun,
+    events: broadcast::Receiver<AgentEvent>,
+    /// Agents spawned so far, by the names the pipeline gives them
+    agents: BTreeMap<String, Uuid>,
+    progress: HashMap<Uuid, AgentProgress>,
+}
+
+impl Runner<'_> {
+    /// Run a step, returning why it failed
+    async fn step(&mut self, step: &PipelineStep) -> Result<(), String> {
+        match step {
+            PipelineStep::Spawn {
+                agent,
+                preset,
+                prompt,
+                worktree,
+            } => {
+                let project_path = match worktree {
+                    Some(branch) => self.worktree(branch).await?,
+                    None => self.run.project_path.clone(),
+                };
+                let spawn = ClientMessage::SpawnAgent {
+                    project_path,
+                    preset: preset.clone(),
+                    cols: None,
+                    rows: None,
+                    tags: Vec::new(),
+                    group: None,
+                    name: Some(agent.clone()),
+                    mode: None,
+                    prompt: prompt.clone(),
+                    env: BTreeMap::new(),
+                    cwd: None,
+                    report_echo: false,
+                };
+                match self.request(spawn).await? {
+                    Some(ServerMessage::AgentSpawned { agent_id, .. }) => {
+                        debug!("Pipeline agent {} is {}", agent, agent_id);
+                        self.agents.insert(agent.clone(), agent_id);
+                        self.progress.insert(agent_id, AgentProgress::default());
+                        Ok(())
+                    }
+                    _ => Err(format!("Agent {} could not be spawned", agent)),
+                }
+            }
+            PipelineStep::Wait {
+                agent,
+                trigger,
+                until,
+                timeout_secs,
+            } => {
+                let deadline = timeout_secs.map(|secs| Instant::now() + Duration::from_secs(secs));
+                self.wait(agent, trigger.as_deref(), *until, deadline).await
+            }
+            PipelineStep::Input {
+                agent,
+                input,
+                submit,
+            } => {
+                let mut input = input.clone();
+                if *submit {
+                    input.push('\r');
+                }
+                let agent_id = self.agent_id(agent)?;
+                let message = ClientMessage::AgentInput {
+                    agent_id,
+                    input,
+                    paste: false,
+                };
+                self.request(message).await.map(drop)
+            }
+            PipelineStep::Kill { agent } => {
+                let agent_id = self.agent_id(agent)?;
+                self.request(ClientMessage::kill_agent(agent_id)).await.map(drop)
+            }
+        }
+    }
+
+    fn agent_id(&self, agent: &str) -> Result<Uuid, String> {
+        self.agents
+            .get(agent)
+            .copied()
+            .ok_or_else(|| format!("Agent {} was not spawned", agent))
+    }
+
+    /// Create a worktree of the project on a new branch, returning its path
+    async fn worktree(&self, branch: &str) -> Result<String, String> {
+        let create = ClientMessage::CreateWorktree {
+            project_path: self.run.project_path.clone(),
+            branch: branch.to_string(),
+            path: None,
+            create_branch: true,
+        };
+        match self.request(create).await? {
+            Some(ServerMessage::WorktreeCreated { worktree }) => Ok(worktree.path),
+            _ => Err(format!("No worktree was created for branch {}", branch)),
+        }
+    }
+
+    /// Wait for an agent to fire a trigger, become ready or exit
+    async fn wait(
+        &mut self,
+        agent: &str,
+        trigger: Option<&str>,
+        until: WaitUntil,
+        deadline: Option<Instant>,
+    ) -> Result<(), String> {
+        let agent_id = self.agent_id(agent)?;
+        loop {
+            let progress = self.progress.entry(agent_id).or_default();
+            if let Some(outcome) = progress.check(agent, trigger, until) {
+                return outcome;
+            }
+            let event = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), self.events.recv())
+                    .await
+                    .map_err(|_| format!("Timed out waiting for agent {}", agent))?,
+                None => self.events.recv().await,
+            };
+            match event {
+                Ok(event) => {
+                    if let Some(progress) = self.progress.get_mut(&event.agent_id()) {
+                        progress.observe(event);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Pipeline {} lagged by {} agent events", self.run.run_id, n);
+                    self.refresh(agent_id).await;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err("The agent manager stopped".to_string());
+                }
+            }
+        }
+    }
+
+    /// Catch up on an agent after missing some of its events; triggers that
+    /// fired meanwhile are lost
+    async fn refresh(&mut self, agent_id: Uuid) {
+        let status = self.state.agent_manager.get_agent_status(agent_id).await;
+        let progress = self.progress.entry(agent_id).or_default();
+        match status.map(|info| info.status) {
+            Ok(AgentState::Ready) => progress.ready = true,
+            Ok(AgentState::Stopped) | Err(_) => {
+                progress.exit.get_or_insert(None);
+            }
+            Ok(_) => {}
+        }
+    }
+
+    /// Handle a request as if the client that started the run sent it,
+    /// returning the error it was answered with
+    async fn request(&self, message: ClientMessage) -> Result<Option<ServerMessage>, String> {
+        message.validate().map_err(|e| e.to_string())?;
+        let response = handle_client_message(
+            message,
+            self.state,
+            self.run.client.as_deref(),
+            self.run.owner.as_deref(),
+        )
+        .await;
+        match response {
+            Ok(Some(ServerMessage::Error { message, .. })) => Err(message),
+            Ok(response) => Ok(response),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger_waited_for_once() {
+        let mut progress = AgentProgress::default();
+        let agent_id = Uuid::new_v4();
+        progress.observe(AgentEvent::TriggerFired {
+            agent_id,
+            trigger: "tests-passed".to_string(),
+            captures: BTreeMap::new(),
+        });
+        let check = |progress: &mut AgentProgress| {
+            progress.check("fixer", Some("tests-passed"), WaitUntil::Exit)
+        };
+        assert_eq!(check(&mut progress), Some(Ok(())));
+        assert_eq!(check(&mut progress), None);
+
+        // An agent that exits won't fire it any more
+        progress.exit = Some(Some(0));
+        assert!(matches!(check(&mut progress), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_wait_until() {
+        let mut progress = AgentProgress::default();
+        assert_eq!(progress.check("fixer", None, WaitUntil::Ready), None);
+        assert_eq!(progress.check("fixer", None, WaitUntil::Exit), None);
+
+        progress.ready = true;
+        assert_eq!(progress.check("fixer", None, WaitUntil::Ready), Some(Ok(())));
+        progress.exit = Some(Some(1));
+        assert_eq!(
+            progress.check("fixer", None, WaitUntil::Exit),
+            Some(Err("Agent fixer exited with code 1".to_string()))
+        );
+        progress.exit = Some(Some(0));
+        assert_eq!(progress.check("fixer", None, WaitUntil::Exit), Some(Ok(())));
+    }
+}
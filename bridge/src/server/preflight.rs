@@ -0,0 +1,257 @@
+//! Spawn pre-flight checks
+//!
+//! `validate_spawn` looks at what a `spawn_agent` would run into without
+//! starting anything: whether the project directory exists, whether its
+//! configuration loads and has the preset, whether the agent's command can
+//! be found and which git worktree the agent would share with others. A
+//! client can show the report before the user commits to spawning.
+
+use std::path::Path;
+
+use super::health::{find_program, AGENT_PROGRAM};
+use super::protocol::{AgentState, CheckStatus, ServerMessage, SpawnCheck, SpawnCheckKind};
+use super::env_policy::EnvPolicy;
+use super::websocket::{ServerConfig, ServerState};
+use crate::agent::{AgentBackend, Expecter, ReadyDetector, Triggers};
+use crate::config::{AgentPreset, ProjectConfig};
+use crate::git::{worktree_for, GitError};
+
+/// Check whether an agent could be spawned in `project_path` with `preset`
+pub(super) async fn validate_spawn(
+    state: &ServerState,
+    project_path: String,
+    preset: Option<String>,
+) -> ServerMessage {
+    let path = Path::new(&project_path);
+    let mut checks = vec![check_project_path(path)];
+    let mut effective = preset.clone();
+    // Nothing else can be told about a directory that isn't there
+    if checks[0].status != CheckStatus::Failed {
+        let (check, resolved) = check_preset(path, preset.as_deref(), &state.config);
+        checks.push(check);
+        if preset.is_none() {
+            effective = resolved.as_ref().map(|p| p.name.clone());
+        }
+        let host = resolved.as_ref().and_then(|p| p.host.as_deref());
+        checks.push(check_command(state, host));
+        checks.push(check_worktree(path, state.agent_manager.as_ref()).await);
+    }
+
+    ServerMessage::SpawnValidation {
+        project_path,
+        preset: effective,
+        ok: checks.iter().all(|c| c.status != CheckStatus::Failed),
+        checks,
+    }
+}
+
+fn check(check: SpawnCheckKind, status: CheckStatus, message: impl Into<String>) -> SpawnCheck {
+    SpawnCheck {
+        check,
+        status,
+        message: message.into(),
+    }
+}
+
+fn check_project_path(path: &Path) -> SpawnCheck {
+    let (status, message) = if path.is_dir() {
+        (CheckStatus::Passed, "The project directory exists")
+    } else if path.exists() {
+        (CheckStatus::Failed, "The project path is not a directory")
+    } else {
+        (CheckStatus::Failed, "The project path does not exist")
+    };
+    check(SpawnCheckKind::ProjectPath, status, message)
+}
+
+/// Check the project configuration and the preset, returning the preset
+/// when it is defined (the default preset when none is named)
+fn check_preset(
+    path: &Path,
+    preset: Option<&str>,
+    server: &ServerConfig,
+) -> (SpawnCheck, Option<AgentPreset>) {
+    let config = match ProjectConfig::load(path) {
+        Ok(config) => config.with_global_presets(&server.presets),
+        Err(e) => {
+            let message = format!("The project configuration does not load: {}", e);
+            return (
+                check(SpawnCheckKind::Preset, CheckStatus::Failed, message),
+                None,
+            );
+        }
+    };
+    let resolved = match preset {
+        Some(name) => config.get_preset(name),
+        None => config.default_preset(),
+    };
+    let result = match (preset, resolved) {
+        (_, Some(found)) => match preset_error(found, &server.env_policy) {
+            Some(error) => check(SpawnCheckKind::Preset, CheckStatus::Failed, error),
+            None => check(
+                SpawnCheckKind::Preset,
+                CheckStatus::Passed,
+                format!("Preset {} is defined", found.name),
+            ),
+        },
+        // Spawning goes ahead under the name, without any preset settings
+        (Some(name), None) => check(
+            SpawnCheckKind::Preset,
+            CheckStatus::Warning,
+            format!(
+                "No preset named {}; the agent would start without preset settings",
+                name
+            ),
+        ),
+        (None, None) => check(
+            SpawnCheckKind::Preset,
+            CheckStatus::Passed,
+            "No preset is used",
+        ),
+    };
+    (result, resolved.cloned())
+}
+
+/// Why a preset's patterns or environment would make the spawn fail, if
+/// they would
+fn preset_error(preset: &AgentPreset, policy: &EnvPolicy) -> Option<String> {
+    if let Err(e) = policy.check(&preset.env) {
+        return Some(e.to_string());
+    }
+    if let Err(e) = Expecter::new(&preset.expect) {
+        return Some(format!("Invalid expect pattern: {}", e));
+    }
+    if let Err(e) = Triggers::new(&preset.triggers) {
+        return Some(format!("Invalid trigger pattern: {}", e));
+    }
+    let ready = preset.ready_pattern.as_deref().map(ReadyDetector::new);
+    if let Some(Err(e)) = ready {
+        return Some(format!("Invalid ready pattern: {}", e));
+    }
+    None
+}
+
+/// Check that the programs the agent is started with can be found: `claude`,
+/// or `ssh` for remote agents, and `tmux` for persistent sessions
+fn check_command(state: &ServerState, host: Option<&str>) -> SpawnCheck {
+    if state.config.simulate {
+        return check(
+            SpawnCheckKind::Command,
+            CheckStatus::Passed,
+            "Agents are simulated",
+        );
+    }
+    let path = std::env::var_os("PATH");
+    let program = if host.is_some() { "ssh" } else { AGENT_PROGRAM };
+    let mut programs = vec![program];
+    if state.config.persistent_sessions && host.is_none() {
+        programs.push("tmux");
+    }
+    let mut found = Vec::new();
+    for program in programs {
+        match find_program(program, path.clone()) {
+            Some(location) => found.push(format!("{} at {}", program, location.display())),
+            None => {
+                return check(
+                    SpawnCheckKind::Command,
+                    CheckStatus::Failed,
+                    format!("{} was not found on the PATH", program),
+                )
+            }
+        }
+    }
+    let mut message = format!("Found {}", found.join(" and "));
+    if let Some(host) = host {
+        message.push_str(&format!(
+            "; the agent runs on {}, which is not checked",
+            host
+        ));
+    }
+    check(SpawnCheckKind::Command, CheckStatus::Passed, message)
+}
+
+/// Check the worktree the agent would work in and whether other agents
+/// already work there
+async fn check_worktree(path: &Path, agents: &dyn AgentBackend) -> SpawnCheck {
+    let worktree = match worktree_for(path) {
+        Ok(worktree) => worktree,
+        Err(GitError::NotARepository(_)) => {
+            let message = "The project is not in a git repository";
+            return check(SpawnCheckKind::Worktree, CheckStatus::Passed, message);
+        }
+        Err(e) => {
+            let message = format!("The git repository could not be read: {}", e);
+            return check(SpawnCheckKind::Worktree, CheckStatus::Warning, message);
+        }
+    };
+    let branch = worktree.branch.as_deref().unwrap_or("a detached HEAD");
+    let sharing = agents
+        .list_agents()
+        .await
+        .iter()
+        .filter(|a| a.status != AgentState::Stopped)
+        .filter(|a| a.worktree.as_deref() == Some(worktree.path.as_str()))
+        .count();
+    if sharing > 0 {
+        check(
+            SpawnCheckKind::Worktree,
+            CheckStatus::Warning,
+            format!(
+                "{} agent(s) already work in {} on {}",
+                sharing, worktree.path, branch
+            ),
+        )
+    } else {
+        check(
+            SpawnCheckKind::Worktree,
+            CheckStatus::Passed,
+            format!("No other agent works in {} on {}", worktree.path, branch),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::federation::Federation;
+
+    fn statuses(message: ServerMessage) -> (bool, Vec<(SpawnCheckKind, CheckStatus)>) {
+        let ServerMessage::SpawnValidation { ok, checks, .. } = message else {
+            panic!("expected a spawn validation, got {:?}", message);
+        };
+        (ok, checks.iter().map(|c| (c.check, c.status)).collect())
+    }
+
+    #[tokio::test]
+    async fn test_validate_spawn() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".hoc")).unwrap();
+        let config = "[[presets]]\nname = \"broken\"\nready_pattern = \"(\"\n";
+        std::fs::write(dir.path().join(".hoc/config.toml"), config).unwrap();
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000).with_simulation(true);
+        let state = ServerState::new(config, Federation::new());
+        let project = dir.path().display().to_string();
+
+        let (ok, checks) = statuses(validate_spawn(&state, project.clone(), None).await);
+        assert!(ok);
+        assert_eq!(checks.len(), 4);
+        assert!(checks
+            .iter()
+            .all(|&(_, status)| status == CheckStatus::Passed));
+
+        let missing = Some("missing".to_string());
+        let (ok, checks) = statuses(validate_spawn(&state, project.clone(), missing).await);
+        assert!(ok);
+        assert_eq!(checks[1], (SpawnCheckKind::Preset, CheckStatus::Warning));
+
+        let broken = Some("broken".to_string());
+        let (ok, checks) = statuses(validate_spawn(&state, project, broken).await);
+        assert!(!ok);
+        assert_eq!(checks[1], (SpawnCheckKind::Preset, CheckStatus::Failed));
+
+        let gone = dir.path().join("gone").display().to_string();
+        let (ok, checks) = statuses(validate_spawn(&state, gone, None).await);
+        assert!(!ok);
+        assert_eq!(checks, [(SpawnCheckKind::ProjectPath, CheckStatus::Failed)]);
+    }
+}
@@ -1,1051 +1,209 @@
-//! Protocol message definitions
-//!
-//! Defines the message types exchanged between Godot clients and the bridge server.
-//! All messages are JSON-encoded and include version information for compatibility.
-
-use serde::{Deserialize, Serialize};
-use thiserror::Error;
-use uuid::Uuid;
-
-/// Current protocol version
-/// Increment when making breaking changes to message format
-pub const PROTOCOL_VERSION: u32 = 1;
-
-/// Minimum supported protocol version
-pub const MIN_PROTOCOL_VERSION: u32 = 1;
-
-/// Maximum terminal dimensions
-pub const MAX_TERMINAL_COLS: u16 = 500;
-pub const MAX_TERMINAL_ROWS: u16 = 200;
-
-/// Default terminal dimensions
-pub const DEFAULT_TERMINAL_COLS: u16 = 80;
-pub const DEFAULT_TERMINAL_ROWS: u16 = 24;
-
-/// Maximum input length (1MB)
-pub const MAX_INPUT_LENGTH: usize = 1024 * 1024;
-
-/// Maximum path length
-pub const MAX_PATH_LENGTH: usize = 4096;
-
-/// Maximum preset name length
-pub const MAX_PRESET_NAME_LENGTH: usize = 256;
-
-// ============================================================================
-// Error Types
-// ============================================================================
-
-/// Protocol-related errors
-#[derive(Debug, Error)]
-pub enum ProtocolError {
-    #[error("JSON serialization error: {0}")]
-    SerializationError(#[from] serde_json::Error),
-
-    #[error("Protocol version {0} not supported (min: {MIN_PROTOCOL_VERSION}, current: {PROTOCOL_VERSION})")]
-    UnsupportedVersion(u32),
-
-    #[error("Invalid message: {0}")]
-    InvalidMessage(String),
-
-    #[error("Validation error: {0}")]
-    ValidationError(String),
-}
-
-/// Result type for protocol operations
-pub type ProtocolResult<T> = Result<T, ProtocolError>;
-
-// ============================================================================
-// Message Envelope
-// ============================================================================
-
-/// Protocol envelope wrapping all client messages
-/// Includes version for compatibility checking
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ClientEnvelope {
-    /// Protocol version used by the client
-    #[serde(default = "default_version")]
-    pub version: u32,
-    /// The actual message payload
-    #[serde(flatten)]
-    pub message: ClientMessage,
-}
-
-/// Protocol envelope wrapping all server messages
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServerEnvelope {
-    /// Protocol version used by the server
-    pub version: u32,
-    /// The actual message payload
-    #[serde(flatten)]
-    pub message: ServerMessage,
-}
-
-fn default_version() -> u32 {
-    PROTOCOL_VERSION
-}
-
-impl ClientEnvelope {
-    /// Create a new client envelope with the current protocol version
-    pub fn new(message: ClientMessage) -> Self {
-        Self {
-            version: PROTOCOL_VERSION,
-            message,
-        }
-    }
-
-    /// Parse and validate a client envelope from JSON
-    pub fn from_json(json: &str) -> ProtocolResult<Self> {
-        let envelope: Self = serde_json::from_str(json)?;
-        envelope.validate()?;
-        Ok(envelope)
-    }
-
-    /// Validate the envelope and its contents
-    pub fn validate(&self) -> ProtocolResult<()> {
-        // Check protocol version
-        if self.version < MIN_PROTOCOL_VERSION {
-            return Err(ProtocolError::UnsupportedVersion(self.version));
-        }
-
-        // Validate the message contents
-        self.message.validate()
-    }
-
-    /// Serialize the envelope to JSON
-    pub fn to_json(&self) -> ProtocolResult<String> {
-        Ok(serde_json::to_string(self)?)
-    }
+//! Protocol message definitions, shared with embedders via `hoc-bridge-core`,
+//! and the wire format of each protocol version
+
+pub use hoc_bridge_core::protocol::*;
+
+use std::borrow::Cow;
+
+use base64::Engine;
+
+/// Wire format of one protocol version
+///
+/// Connections are handled in terms of the current message model whatever
+/// version the client speaks; the codec translates at the edge, so older
+/// clients keep working across breaking changes. Client messages are the
+/// same in both versions (a message without a `version` is a version 1
+/// message). Server messages for version 1 clients differ in that:
+///
+/// - `agent_output` carries text, with invalid UTF-8 replaced, instead of
+///   base64-encoded bytes
+/// - only errors carry the `request_id` of the message they answer
+/// - fields added since, listed in [`NEWER_FIELDS`], are left out
+/// - tool permission prompts are sent as plain `confirmation_request`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    V1,
+    V2,
 }
 
-impl ServerEnvelope {
-    /// Create a new server envelope with the current protocol version
-    pub fn new(message: ServerMessage) -> Self {
-        Self {
-            version: PROTOCOL_VERSION,
-            message,
+impl Codec {
+    /// Codec for a supported protocol version
+    pub fn for_version(version: u32) -> Self {
+        if version >= 2 {
+            Codec::V2
+        } else {
+            Codec::V1
         }
     }
 
-    /// Serialize the envelope to JSON
-    pub fn to_json(&self) -> ProtocolResult<String> {
-        Ok(serde_json::to_string(self)?)
-    }
-
-    /// Parse a server envelope from JSON (primarily for testing)
-    pub fn from_json(json: &str) -> ProtocolResult<Self> {
-        Ok(serde_json::from_str(json)?)
-    }
-}
-
-// ============================================================================
-// Client Messages
-// ============================================================================
-
-/// Messages sent from client (Godot) to server
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum ClientMessage {
-    /// Authentication message - must be sent first when token is required
-    Authenticate {
-        /// The authentication token
-        token: String,
-    },
-
-    /// Connection keepalive ping
-    Ping {
-        /// Sequence number for tracking round-trip time
-        seq: u64,
-    },
-
-    /// Request to spawn a new agent session
-    SpawnAgent {
-        /// Path to the project directory
-        project_path: String,
-        /// Optional preset name from project config
-        #[serde(skip_serializing_if = "Option::is_none")]
-        preset: Option<String>,
-        /// Optional initial terminal columns
-        #[serde(skip_serializing_if = "Option::is_none")]
-        cols: Option<u16>,
-        /// Optional initial terminal rows
-        #[serde(skip_serializing_if = "Option::is_none")]
-        rows: Option<u16>,
-    },
-
-    /// Send input to an existing agent
-    AgentInput {
-        /// UUID of the target agent
-        agent_id: Uuid,
-        /// Input data to send to the agent's stdin
-        input: String,
-    },
-
-    /// Request to terminate an agent
-    KillAgent {
-        /// UUID of the agent to terminate
-        agent_id: Uuid,
-        /// Optional signal to send (default: SIGTERM)
-        #[serde(skip_serializing_if = "Option::is_none")]
-        signal: Option<i32>,
-    },
-
-    /// Resize an agent's terminal
-    ResizeTerminal {
-        /// UUID of the target agent
-        agent_id: Uuid,
-        /// New terminal width in columns
-        cols: u16,
-        /// New terminal height in rows
-        rows: u16,
-    },
-
-    /// List all active agents
-    ListAgents,
-
-    /// Request agent status
-    GetAgentStatus {
-        /// UUID of the agent to query
-        agent_id: Uuid,
-    },
-}
-
-impl ClientMessage {
-    /// Validate message contents
-    pub fn validate(&self) -> ProtocolResult<()> {
+    /// Protocol version the codec speaks
+    pub fn version(self) -> u32 {
         match self {
-            ClientMessage::Authenticate { token } => {
-                if token.is_empty() {
-                    return Err(ProtocolError::ValidationError(
-                        "token cannot be empty".to_string(),
-                    ));
+            Codec::V1 => 1,
+            Codec::V2 => 2,
+        }
+    }
+
+    /// Encode a server message, answering the message with `request_id` if any
+    pub fn encode(
+        self,
+        message: &ServerMessage,
+        request_id: Option<&str>,
+    ) -> serde_json::Result<String> {
+        let mut value = match self {
+            Codec::V1 => serde_json::to_value(downgrade(message))?,
+            Codec::V2 => {
+                let mut value = serde_json::to_value(message)?;
+                if let (Some(request_id), Some(object)) = (request_id, value.as_object_mut()) {
+                    object.insert("request_id".to_string(), request_id.into());
                 }
-                Ok(())
+                value
             }
-
-            ClientMessage::Ping { .. } => Ok(()),
-
-            ClientMessage::SpawnAgent {
-                project_path,
-                preset,
-                cols,
-                rows,
-            } => {
-                // Validate project path
-                if project_path.is_empty() {
-                    return Err(ProtocolError::ValidationError(
-                        "project_path cannot be empty".to_string(),
-                    ));
-                }
-                if project_path.len() > MAX_PATH_LENGTH {
-                    return Err(ProtocolError::ValidationError(format!(
-                        "project_path exceeds maximum length of {} characters",
-                        MAX_PATH_LENGTH
-                    )));
-                }
-
-                // Validate preset name
-                if let Some(p) = preset {
-                    if p.is_empty() {
-                        return Err(ProtocolError::ValidationError(
-                            "preset name cannot be empty when specified".to_string(),
-                        ));
-                    }
-                    if p.len() > MAX_PRESET_NAME_LENGTH {
-                        return Err(ProtocolError::ValidationError(format!(
-                            "preset name exceeds maximum length of {} characters",
-                            MAX_PRESET_NAME_LENGTH
-                        )));
-                    }
-                }
-
-                // Validate terminal dimensions
-                if let Some(c) = cols {
-                    if *c == 0 || *c > MAX_TERMINAL_COLS {
-                        return Err(ProtocolError::ValidationError(format!(
-                            "cols must be between 1 and {}",
-                            MAX_TERMINAL_COLS
-                        )));
-                    }
-                }
-                if let Some(r) = rows {
-                    if *r == 0 || *r > MAX_TERMINAL_ROWS {
-                        return Err(ProtocolError::ValidationError(format!(
-                            "rows must be between 1 and {}",
-                            MAX_TERMINAL_ROWS
-                        )));
-                    }
-                }
-
-                Ok(())
-            }
-
-            ClientMessage::AgentInput { input, .. } => {
-                if input.len() > MAX_INPUT_LENGTH {
-                    return Err(ProtocolError::ValidationError(format!(
-                        "input exceeds maximum length of {} bytes",
-                        MAX_INPUT_LENGTH
-                    )));
-                }
-                Ok(())
-            }
-
-            ClientMessage::KillAgent { signal, .. } => {
-                // Validate signal is reasonable (common Unix signals)
-                if let Some(sig) = signal {
-                    if *sig < 1 || *sig > 31 {
-                        return Err(ProtocolError::ValidationError(format!(
-                            "signal {} is not a valid Unix signal (1-31)",
-                            sig
-                        )));
-                    }
-                }
-                Ok(())
-            }
-
-            ClientMessage::ResizeTerminal { cols, rows, .. } => {
-                if *cols == 0 || *cols > MAX_TERMINAL_COLS {
-                    return Err(ProtocolError::ValidationError(format!(
-                        "cols must be between 1 and {}",
-                        MAX_TERMINAL_COLS
-                    )));
-                }
-                if *rows == 0 || *rows > MAX_TERMINAL_ROWS {
-                    return Err(ProtocolError::ValidationError(format!(
-                        "rows must be between 1 and {}",
-                        MAX_TERMINAL_ROWS
-                    )));
-                }
-                Ok(())
+        };
+        let message_type = value
+            .get("type")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .to_string();
+        for (since, newer_type, path) in NEWER_FIELDS {
+            if *since > self.version() && *newer_type == message_type {
+                remove_field(&mut value, path);
             }
-
-            ClientMessage::ListAgents => Ok(()),
-
-            ClientMessage::GetAgentStatus { .. } => Ok(()),
-        }
-    }
-
-    /// Create a Ping message
-    pub fn ping(seq: u64) -> Self {
-        ClientMessage::Ping { seq }
-    }
-
-    /// Create a SpawnAgent message
-    pub fn spawn_agent(project_path: impl Into<String>) -> Self {
-        ClientMessage::SpawnAgent {
-            project_path: project_path.into(),
-            preset: None,
-            cols: None,
-            rows: None,
-        }
-    }
-
-    /// Create a SpawnAgent message with preset
-    pub fn spawn_agent_with_preset(
-        project_path: impl Into<String>,
-        preset: impl Into<String>,
-    ) -> Self {
-        ClientMessage::SpawnAgent {
-            project_path: project_path.into(),
-            preset: Some(preset.into()),
-            cols: None,
-            rows: None,
-        }
-    }
-
-    /// Create an AgentInput message
-    pub fn agent_input(agent_id: Uuid, input: impl Into<String>) -> Self {
-        ClientMessage::AgentInput {
-            agent_id,
-            input: input.into(),
-        }
-    }
-
-    /// Create a KillAgent message
-    pub fn kill_agent(agent_id: Uuid) -> Self {
-        ClientMessage::KillAgent {
-            agent_id,
-            signal: None,
-        }
-    }
-
-    /// Create a ResizeTerminal message
-    pub fn resize_terminal(agent_id: Uuid, cols: u16, rows: u16) -> Self {
-        ClientMessage::ResizeTerminal {
-            agent_id,
-            cols,
-            rows,
         }
+        serde_json::to_string(&value)
     }
 }
 
-// ============================================================================
-// Server Messages
-// ============================================================================
-
-/// Messages sent from server to client (Godot)
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum ServerMessage {
-    /// Welcome message sent on connection
-    Welcome {
-        /// Server protocol version
-        version: u32,
-        /// Server identifier/name
-        #[serde(skip_serializing_if = "Option::is_none")]
-        server_id: Option<String>,
-        /// Whether authentication is required
-        #[serde(skip_serializing_if = "Option::is_none")]
-        auth_required: Option<bool>,
-    },
-
-    /// Authentication successful
-    AuthSuccess,
-
-    /// Response to Ping
-    Pong {
-        /// Echo back the sequence number
-        seq: u64,
-    },
-
-    /// Agent successfully spawned
-    AgentSpawned {
-        /// UUID of the new agent
-        agent_id: Uuid,
-        /// Confirmed project path
-        project_path: String,
-        /// Terminal columns
-        cols: u16,
-        /// Terminal rows
-        rows: u16,
-    },
-
-    /// Output data from an agent
-    AgentOutput {
-        /// UUID of the source agent
-        agent_id: Uuid,
-        /// Output data (may contain ANSI escape sequences)
-        data: String,
-    },
-
-    /// Agent process exited
-    AgentExited {
-        /// UUID of the exited agent
-        agent_id: Uuid,
-        /// Exit code if available
-        #[serde(skip_serializing_if = "Option::is_none")]
-        exit_code: Option<i32>,
-        /// Exit reason description
-        #[serde(skip_serializing_if = "Option::is_none")]
-        reason: Option<String>,
-    },
-
-    /// Agent terminal resized
-    AgentResized {
-        /// UUID of the agent
-        agent_id: Uuid,
-        /// New terminal columns
-        cols: u16,
-        /// New terminal rows
-        rows: u16,
-    },
-
-    /// List of active agents
-    AgentList {
-        /// List of agent information
-        agents: Vec<AgentInfo>,
-    },
-
-    /// Status of a specific agent
-    AgentStatus {
-        /// UUID of the agent
-        agent_id: Uuid,
-        /// Current status
-        status: AgentState,
-        /// Project path
-        project_path: String,
-        /// Terminal columns
-        cols: u16,
-        /// Terminal rows
-        rows: u16,
-    },
-
-    /// Error response
-    Error {
-        /// Error message
-        message: String,
-        /// Error code for programmatic handling
-        #[serde(skip_serializing_if = "Option::is_none")]
-        code: Option<ErrorCode>,
-        /// Related agent UUID if applicable
-        #[serde(skip_serializing_if = "Option::is_none")]
-        agent_id: Option<Uuid>,
-    },
-}
-
-/// Information about an agent for listing
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct AgentInfo {
-    /// Agent UUID
-    pub agent_id: Uuid,
-    /// Project path
-    pub project_path: String,
-    /// Current state
-    pub status: AgentState,
-    /// Terminal columns
-    pub cols: u16,
-    /// Terminal rows
-    pub rows: u16,
-}
-
-/// Agent lifecycle states
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub enum AgentState {
-    /// Agent is starting up
-    Starting,
-    /// Agent is running and accepting input
-    Running,
-    /// Agent is shutting down
-    Stopping,
-    /// Agent has stopped
-    Stopped,
-}
-
-/// Error codes for programmatic error handling
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub enum ErrorCode {
-    /// Invalid message format
-    InvalidMessage,
-    /// Agent not found
-    AgentNotFound,
-    /// Failed to spawn agent
-    SpawnFailed,
-    /// Authentication required
-    AuthRequired,
-    /// Authentication failed
-    AuthFailed,
-    /// Rate limited
-    RateLimited,
-    /// Internal server error
-    InternalError,
-    /// Invalid project path
-    InvalidPath,
-    /// Unsupported protocol version
-    UnsupportedVersion,
-}
-
-impl ServerMessage {
-    /// Create a Welcome message
-    pub fn welcome() -> Self {
-        ServerMessage::Welcome {
-            version: PROTOCOL_VERSION,
-            server_id: None,
-            auth_required: None,
-        }
-    }
-
-    /// Create a Welcome message indicating auth is required
-    pub fn welcome_auth_required() -> Self {
-        ServerMessage::Welcome {
-            version: PROTOCOL_VERSION,
-            server_id: None,
-            auth_required: Some(true),
-        }
-    }
-
-    /// Create a Welcome message with server ID
-    pub fn welcome_with_id(server_id: impl Into<String>) -> Self {
-        ServerMessage::Welcome {
-            version: PROTOCOL_VERSION,
-            server_id: Some(server_id.into()),
-            auth_required: None,
+/// Fields of server messages added after the messages themselves, with the
+/// protocol version that added them: clients of older versions don't get
+/// them. `*` stands for every element of an array.
+pub const NEWER_FIELDS: &[(u32, &str, &[&str])] = &[
+    (2, "auth_success", &["role"]),
+    (2, "agent_exited", &["signal"]),
+    (2, "agent_state_changed", &["timestamp_ms"]),
+    (2, "agent_list", &["agents", "*", "name"]),
+    (2, "agent_status", &["name"]),
+];
+
+/// Remove the field at `path` from a message
+fn remove_field(value: &mut serde_json::Value, path: &[&str]) {
+    match (path, value) {
+        ([field], serde_json::Value::Object(object)) => {
+            object.remove(*field);
+        }
+        (["*", rest @ ..], serde_json::Value::Array(items)) => {
+            for item in items {
+                remove_field(item, rest);
+            }
         }
-    }
-
-    /// Create an AuthSuccess message
-    pub fn auth_success() -> Self {
-        ServerMessage::AuthSuccess
-    }
-
-    /// Create a Pong message
-    pub fn pong(seq: u64) -> Self {
-        ServerMessage::Pong { seq }
-    }
-
-    /// Create an AgentSpawned message
-    pub fn agent_spawned(
-        agent_id: Uuid,
-        project_path: impl Into<String>,
-        cols: u16,
-        rows: u16,
-    ) -> Self {
-        ServerMessage::AgentSpawned {
-            agent_id,
-            project_path: project_path.into(),
-            cols,
-            rows,
+        ([field, rest @ ..], serde_json::Value::Object(object)) => {
+            if let Some(inner) = object.get_mut(*field) {
+                remove_field(inner, rest);
+            }
         }
+        _ => {}
     }
+}
 
-    /// Create an AgentOutput message
-    pub fn agent_output(agent_id: Uuid, data: impl Into<String>) -> Self {
+/// Translate a message to its version 1 form
+fn downgrade(message: &ServerMessage) -> Cow<'_, ServerMessage> {
+    match message {
         ServerMessage::AgentOutput {
             agent_id,
-            data: data.into(),
-        }
-    }
-
-    /// Create an AgentExited message
-    pub fn agent_exited(agent_id: Uuid, exit_code: Option<i32>) -> Self {
-        ServerMessage::AgentExited {
-            agent_id,
-            exit_code,
-            reason: None,
-        }
-    }
-
-    /// Create an AgentExited message with reason
-    pub fn agent_exited_with_reason(
-        agent_id: Uuid,
-        exit_code: Option<i32>,
-        reason: impl Into<String>,
-    ) -> Self {
-        ServerMessage::AgentExited {
+            data,
+            encoding: Some(OutputEncoding::Base64),
+        } => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .unwrap_or_default();
+            Cow::Owned(ServerMessage::agent_output(
+                *agent_id,
+                String::from_utf8_lossy(&bytes),
+            ))
+        }
+        ServerMessage::ApprovalRequested {
             agent_id,
-            exit_code,
-            reason: Some(reason.into()),
-        }
-    }
-
-    /// Create an Error message
-    pub fn error(message: impl Into<String>) -> Self {
-        ServerMessage::Error {
-            message: message.into(),
-            code: None,
-            agent_id: None,
-        }
-    }
-
-    /// Create an Error message with code
-    pub fn error_with_code(message: impl Into<String>, code: ErrorCode) -> Self {
-        ServerMessage::Error {
-            message: message.into(),
-            code: Some(code),
-            agent_id: None,
-        }
-    }
-
-    /// Create an Error message for a specific agent
-    pub fn agent_error(agent_id: Uuid, message: impl Into<String>, code: ErrorCode) -> Self {
-        ServerMessage::Error {
-            message: message.into(),
-            code: Some(code),
-            agent_id: Some(agent_id),
-        }
-    }
-}
-
-// ============================================================================
-// Conversion Traits
-// ============================================================================
-
-impl From<ProtocolError> for ServerMessage {
-    fn from(err: ProtocolError) -> Self {
-        let code = match &err {
-            ProtocolError::SerializationError(_) => ErrorCode::InvalidMessage,
-            ProtocolError::UnsupportedVersion(_) => ErrorCode::UnsupportedVersion,
-            ProtocolError::InvalidMessage(_) => ErrorCode::InvalidMessage,
-            ProtocolError::ValidationError(_) => ErrorCode::InvalidMessage,
-        };
-        ServerMessage::error_with_code(err.to_string(), code)
+            question,
+            options,
+            ..
+        } => Cow::Owned(ServerMessage::ConfirmationRequest {
+            agent_id: *agent_id,
+            question: question.clone(),
+            options: options.clone(),
+        }),
+        _ => Cow::Borrowed(message),
     }
 }
 
-// ============================================================================
-// Tests
-// ============================================================================
-
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    // -------------------------------------------------------------------------
-    // Client Message Tests
-    // -------------------------------------------------------------------------
-
-    #[test]
-    fn test_ping_serialization() {
-        let msg = ClientMessage::ping(42);
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"ping\""));
-        assert!(json.contains("\"seq\":42"));
-
-        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_spawn_agent_serialization() {
-        let msg = ClientMessage::spawn_agent("/path/to/project");
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"spawn_agent\""));
-        assert!(json.contains("\"project_path\":\"/path/to/project\""));
-
-        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_spawn_agent_with_preset_serialization() {
-        let msg = ClientMessage::spawn_agent_with_preset("/path/to/project", "code-review");
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"preset\":\"code-review\""));
-
-        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_agent_input_serialization() {
-        let agent_id = Uuid::new_v4();
-        let msg = ClientMessage::agent_input(agent_id, "hello world");
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"agent_input\""));
-        assert!(json.contains("\"input\":\"hello world\""));
-
-        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
+    use uuid::Uuid;
 
     #[test]
-    fn test_kill_agent_serialization() {
+    fn test_output_for_each_version() {
         let agent_id = Uuid::new_v4();
-        let msg = ClientMessage::kill_agent(agent_id);
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"kill_agent\""));
+        let output = ServerMessage::agent_output_bytes(agent_id, b"ok \xff");
 
-        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
+        let v1 = Codec::for_version(1).encode(&output, Some("req-1")).unwrap();
+        assert!(v1.contains(r#""data":"ok �""#));
+        assert!(!v1.contains("encoding"));
+        assert!(!v1.contains("req-1"));
 
-    #[test]
-    fn test_resize_terminal_serialization() {
-        let agent_id = Uuid::new_v4();
-        let msg = ClientMessage::resize_terminal(agent_id, 120, 40);
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"resize_terminal\""));
-        assert!(json.contains("\"cols\":120"));
-        assert!(json.contains("\"rows\":40"));
-
-        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
+        let v2 = Codec::for_version(2).encode(&output, Some("req-1")).unwrap();
+        assert!(v2.contains(r#""encoding":"base64""#));
+        assert!(v2.contains(r#""request_id":"req-1""#));
+        let parsed: ServerMessage = serde_json::from_str(&v2).unwrap();
+        assert_eq!(parsed, output);
     }
 
     #[test]
-    fn test_list_agents_serialization() {
-        let msg = ClientMessage::ListAgents;
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"list_agents\""));
-
-        let parsed: ClientMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    // -------------------------------------------------------------------------
-    // Server Message Tests
-    // -------------------------------------------------------------------------
-
-    #[test]
-    fn test_welcome_serialization() {
-        let msg = ServerMessage::welcome();
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"welcome\""));
-        assert!(json.contains(&format!("\"version\":{}", PROTOCOL_VERSION)));
-
-        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_pong_serialization() {
-        let msg = ServerMessage::pong(42);
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"pong\""));
-        assert!(json.contains("\"seq\":42"));
-
-        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_agent_spawned_serialization() {
-        let agent_id = Uuid::new_v4();
-        let msg = ServerMessage::agent_spawned(agent_id, "/path/to/project", 80, 24);
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"agent_spawned\""));
-        assert!(json.contains("\"cols\":80"));
-        assert!(json.contains("\"rows\":24"));
-
-        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_agent_output_serialization() {
-        let agent_id = Uuid::new_v4();
-        let msg = ServerMessage::agent_output(agent_id, "Hello, World!\n");
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"agent_output\""));
-        assert!(json.contains("\"data\":\"Hello, World!\\n\""));
-
-        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_agent_exited_serialization() {
-        let agent_id = Uuid::new_v4();
-        let msg = ServerMessage::agent_exited(agent_id, Some(0));
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"agent_exited\""));
-        assert!(json.contains("\"exit_code\":0"));
-
-        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_error_serialization() {
-        let msg = ServerMessage::error_with_code("Something went wrong", ErrorCode::InternalError);
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"error\""));
-        assert!(json.contains("\"message\":\"Something went wrong\""));
-        assert!(json.contains("\"code\":\"internal_error\""));
-
-        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    #[test]
-    fn test_agent_list_serialization() {
-        let agent_id = Uuid::new_v4();
-        let msg = ServerMessage::AgentList {
-            agents: vec![AgentInfo {
-                agent_id,
-                project_path: "/path/to/project".to_string(),
-                status: AgentState::Running,
-                cols: 80,
-                rows: 24,
-            }],
+    fn test_newer_fields_left_out() {
+        let info = AgentInfo {
+            agent_id: Uuid::new_v4(),
+            name: Some("fixer".to_string()),
+            ..Default::default()
         };
-        let json = serde_json::to_string(&msg).unwrap();
-        assert!(json.contains("\"type\":\"agent_list\""));
-        assert!(json.contains("\"status\":\"running\""));
-
-        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, msg);
-    }
-
-    // -------------------------------------------------------------------------
-    // Envelope Tests
-    // -------------------------------------------------------------------------
-
-    #[test]
-    fn test_client_envelope_serialization() {
-        let envelope = ClientEnvelope::new(ClientMessage::ping(1));
-        let json = envelope.to_json().unwrap();
-        assert!(json.contains(&format!("\"version\":{}", PROTOCOL_VERSION)));
-        assert!(json.contains("\"type\":\"ping\""));
-
-        let parsed = ClientEnvelope::from_json(&json).unwrap();
-        assert_eq!(parsed.version, PROTOCOL_VERSION);
-    }
-
-    #[test]
-    fn test_server_envelope_serialization() {
-        let envelope = ServerEnvelope::new(ServerMessage::pong(1));
-        let json = envelope.to_json().unwrap();
-        assert!(json.contains(&format!("\"version\":{}", PROTOCOL_VERSION)));
-        assert!(json.contains("\"type\":\"pong\""));
-
-        let parsed = ServerEnvelope::from_json(&json).unwrap();
-        assert_eq!(parsed.version, PROTOCOL_VERSION);
-    }
-
-    #[test]
-    fn test_envelope_version_validation() {
-        let json = r#"{"version": 0, "type": "ping", "seq": 1}"#;
-        let result = ClientEnvelope::from_json(json);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("not supported"));
-    }
-
-    // -------------------------------------------------------------------------
-    // Validation Tests
-    // -------------------------------------------------------------------------
-
-    #[test]
-    fn test_spawn_agent_empty_path_validation() {
-        let msg = ClientMessage::SpawnAgent {
-            project_path: "".to_string(),
-            preset: None,
-            cols: None,
-            rows: None,
+        let list = ServerMessage::AgentList {
+            agents: vec![info.clone()],
         };
-        let result = msg.validate();
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("cannot be empty"));
-    }
+        let v1 = Codec::V1.encode(&list, None).unwrap();
+        assert!(v1.contains("project_path"));
+        assert!(!v1.contains("fixer"));
+        assert!(Codec::V2.encode(&list, None).unwrap().contains("fixer"));
 
-    #[test]
-    fn test_spawn_agent_empty_preset_validation() {
-        let msg = ClientMessage::SpawnAgent {
-            project_path: "/valid/path".to_string(),
-            preset: Some("".to_string()),
-            cols: None,
-            rows: None,
+        let status = ServerMessage::AgentStatus {
+            info: Box::new(info),
         };
-        let result = msg.validate();
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("preset name cannot be empty"));
-    }
-
-    #[test]
-    fn test_resize_terminal_invalid_cols() {
-        let agent_id = Uuid::new_v4();
-        let msg = ClientMessage::resize_terminal(agent_id, 0, 24);
-        let result = msg.validate();
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("cols must be"));
-    }
-
-    #[test]
-    fn test_resize_terminal_invalid_rows() {
-        let agent_id = Uuid::new_v4();
-        let msg = ClientMessage::resize_terminal(agent_id, 80, 0);
-        let result = msg.validate();
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("rows must be"));
-    }
-
-    #[test]
-    fn test_resize_terminal_max_cols() {
-        let agent_id = Uuid::new_v4();
-        let msg = ClientMessage::resize_terminal(agent_id, MAX_TERMINAL_COLS + 1, 24);
-        let result = msg.validate();
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn test_kill_agent_invalid_signal() {
-        let agent_id = Uuid::new_v4();
-        let msg = ClientMessage::KillAgent {
-            agent_id,
-            signal: Some(100),
+        assert!(!Codec::V1.encode(&status, None).unwrap().contains("fixer"));
+        let auth = Codec::V1
+            .encode(&ServerMessage::auth_success(Role::Admin), None)
+            .unwrap();
+        assert_eq!(auth, r#"{"type":"auth_success"}"#);
+    }
+
+    #[test]
+    fn test_approval_as_confirmation_for_v1() {
+        let approval = ServerMessage::ApprovalRequested {
+            agent_id: Uuid::new_v4(),
+            tool: "Bash command".to_string(),
+            detail: "cargo test".to_string(),
+            question: "Do you want to proceed?".to_string(),
+            options: vec!["Yes".to_string(), "No".to_string()],
+            can_always: false,
         };
-        let result = msg.validate();
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("not a valid Unix signal"));
-    }
-
-    #[test]
-    fn test_agent_input_max_length() {
-        let agent_id = Uuid::new_v4();
-        let large_input = "x".repeat(MAX_INPUT_LENGTH + 1);
-        let msg = ClientMessage::agent_input(agent_id, large_input);
-        let result = msg.validate();
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("exceeds maximum length"));
-    }
-
-    #[test]
-    fn test_valid_messages_pass_validation() {
-        let agent_id = Uuid::new_v4();
-
-        // All these should validate successfully
-        assert!(ClientMessage::ping(1).validate().is_ok());
-        assert!(ClientMessage::spawn_agent("/valid/path").validate().is_ok());
-        assert!(ClientMessage::agent_input(agent_id, "hello")
-            .validate()
-            .is_ok());
-        assert!(ClientMessage::kill_agent(agent_id).validate().is_ok());
-        assert!(ClientMessage::resize_terminal(agent_id, 80, 24)
-            .validate()
-            .is_ok());
-        assert!(ClientMessage::ListAgents.validate().is_ok());
-    }
-
-    // -------------------------------------------------------------------------
-    // Error Conversion Tests
-    // -------------------------------------------------------------------------
-
-    #[test]
-    fn test_protocol_error_to_server_message() {
-        let err = ProtocolError::ValidationError("test error".to_string());
-        let msg: ServerMessage = err.into();
-
-        match msg {
-            ServerMessage::Error { message, code, .. } => {
-                assert!(message.contains("test error"));
-                assert_eq!(code, Some(ErrorCode::InvalidMessage));
-            }
-            _ => panic!("Expected Error message"),
-        }
-    }
-
-    // -------------------------------------------------------------------------
-    // JSON Compatibility Tests
-    // -------------------------------------------------------------------------
-
-    #[test]
-    fn test_parse_minimal_spawn_agent() {
-        // Test that we can parse a minimal spawn_agent without optional fields
-        let json = r#"{"type": "spawn_agent", "project_path": "/test"}"#;
-        let msg: ClientMessage = serde_json::from_str(json).unwrap();
-        match msg {
-            ClientMessage::SpawnAgent {
-                project_path,
-                preset,
-                cols,
-                rows,
-            } => {
-                assert_eq!(project_path, "/test");
-                assert!(preset.is_none());
-                assert!(cols.is_none());
-                assert!(rows.is_none());
-            }
-            _ => panic!("Expected SpawnAgent"),
-        }
+        let v1 = Codec::V1.encode(&approval, None).unwrap();
+        assert!(v1.contains(r#""type":"confirmation_request""#));
+        assert!(!v1.contains("cargo test"));
+        assert!(Codec::V2.encode(&approval, None).unwrap().contains("approval_requested"));
     }
 
     #[test]
-    fn test_parse_full_spawn_agent() {
-        // Test that we can parse a full spawn_agent with all fields
-        let json = r#"{"type": "spawn_agent", "project_path": "/test", "preset": "dev", "cols": 120, "rows": 40}"#;
-        let msg: ClientMessage = serde_json::from_str(json).unwrap();
-        match msg {
-            ClientMessage::SpawnAgent {
-                project_path,
-                preset,
-                cols,
-                rows,
-            } => {
-                assert_eq!(project_path, "/test");
-                assert_eq!(preset, Some("dev".to_string()));
-                assert_eq!(cols, Some(120));
-                assert_eq!(rows, Some(40));
-            }
-            _ => panic!("Expected SpawnAgent"),
+    fn test_errors_echo_request_id_in_every_version() {
+        let error = ServerMessage::error("failed").with_request_id(Some("req-2".to_string()));
+        for codec in [Codec::V1, Codec::V2] {
+            let json = codec.encode(&error, Some("req-2")).unwrap();
+            assert_eq!(json.matches("req-2").count(), 1);
         }
     }
 }
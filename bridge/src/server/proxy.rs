@@ -0,0 +1,168 @@
+//! Reverse-proxy support
+//!
+//! Helpers for running the bridge behind nginx, traefik and other reverse
+//! proxies: URL path matching for the WebSocket endpoint and resolution of the
+//! real client address from `X-Forwarded-For` / `X-Forwarded-Proto` headers
+//! sent by trusted proxies.
+
+use std::net::{IpAddr, SocketAddr};
+
+use tokio_tungstenite::tungstenite::http::HeaderMap;
+
+/// Client connection details after applying forwarding headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardedInfo {
+    /// Address of the actual client (the proxy's address if not forwarded)
+    pub client_addr: String,
+    /// Protocol the client used to reach the proxy (e.g. "https")
+    pub proto: Option<String>,
+    /// Whether the connection arrived through a trusted proxy
+    pub via_proxy: bool,
+}
+
+impl ForwardedInfo {
+    /// Connection details for a direct (non-proxied) connection
+    pub fn direct(peer_addr: SocketAddr) -> Self {
+        Self {
+            client_addr: peer_addr.to_string(),
+            proto: None,
+            via_proxy: false,
+        }
+    }
+
+    /// Whether the client reached the proxy over TLS
+    pub fn is_secure(&self) -> bool {
+        matches!(self.proto.as_deref(), Some("https") | Some("wss"))
+    }
+}
+
+/// Check whether a request path matches the configured WebSocket path
+///
+/// A trailing slash is ignored and any query string is stripped. When no
+/// path is configured every path is accepted.
+pub fn path_matches(configured: Option<&str>, request_path: &str) -> bool {
+    let Some(configured) = configured else {
+        return true;
+    };
+    let request_path = request_path.split('?').next().unwrap_or("");
+    normalize_path(configured) == normalize_path(request_path)
+}
+
+fn normalize_path(path: &str) -> &str {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/"
+    } else {
+        trimmed
+    }
+}
+
+/// Resolve the real client address from forwarding headers
+///
+/// Headers are only honoured when the direct peer is a trusted proxy. The
+/// client is the rightmost `X-Forwarded-For` entry that is not itself a trusted
+/// proxy, which prevents clients from spoofing their address by sending their
+/// own header through the proxy chain.
+pub fn resolve_client(peer_addr: SocketAddr, headers: &HeaderMap, trusted: &[IpAddr]) -> ForwardedInfo {
+    if !trusted.contains(&peer_addr.ip()) {
+        return ForwardedInfo::direct(peer_addr);
+    }
+
+    let forwarded_for: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|entry| entry.trim().parse().ok())
+        .collect();
+
+    let client_ip = forwarded_for
+        .iter()
+        .rev()
+        .find(|ip| !trusted.contains(ip))
+        .or_else(|| forwarded_for.first());
+
+    let proto = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty());
+
+    ForwardedInfo {
+        client_addr: client_ip
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| peer_addr.to_string()),
+        proto,
+        via_proxy: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::http::HeaderValue;
+
+    fn proxy_addr() -> SocketAddr {
+        "10.0.0.1:45000".parse().unwrap()
+    }
+
+    fn trusted() -> Vec<IpAddr> {
+        vec!["10.0.0.1".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches(None, "/anything"));
+        assert!(path_matches(Some("/ws"), "/ws"));
+        assert!(path_matches(Some("/ws"), "/ws/"));
+        assert!(path_matches(Some("/ws/"), "/ws?client=godot"));
+        assert!(path_matches(Some("/"), "/"));
+        assert!(!path_matches(Some("/ws"), "/"));
+        assert!(!path_matches(Some("/bridge/ws"), "/ws"));
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("1.2.3.4"));
+        let peer: SocketAddr = "192.168.1.5:5000".parse().unwrap();
+
+        let info = resolve_client(peer, &headers, &trusted());
+        assert_eq!(info.client_addr, "192.168.1.5:5000");
+        assert!(!info.via_proxy);
+    }
+
+    #[test]
+    fn test_trusted_proxy_uses_forwarded_for() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("203.0.113.9"));
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+
+        let info = resolve_client(proxy_addr(), &headers, &trusted());
+        assert_eq!(info.client_addr, "203.0.113.9");
+        assert!(info.via_proxy);
+        assert!(info.is_secure());
+    }
+
+    #[test]
+    fn test_spoofed_forwarded_for_is_skipped() {
+        let mut headers = HeaderMap::new();
+        // Client sent its own header, proxy appended the real address
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("6.6.6.6, 203.0.113.9"),
+        );
+
+        let info = resolve_client(proxy_addr(), &headers, &trusted());
+        assert_eq!(info.client_addr, "203.0.113.9");
+    }
+
+    #[test]
+    fn test_trusted_proxy_without_header() {
+        let info = resolve_client(proxy_addr(), &HeaderMap::new(), &trusted());
+        assert_eq!(info.client_addr, "10.0.0.1:45000");
+        assert!(info.proto.is_none());
+        assert!(!info.is_secure());
+    }
+}
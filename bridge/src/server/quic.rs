@@ -0,0 +1,418 @@
+//! Experimental QUIC and WebTransport transport
+//!
+//! Serves the bridge protocol over QUIC next to the WebSocket server. QUIC
+//! survives Wi-Fi roaming (connection migration) and avoids TCP head-of-line
+//! blocking on lossy headset links.
+//!
+//! Native clients negotiate the [`ALPN_PROTOCOL`] ALPN identifier: each
+//! connection carries one session, on a bidirectional stream the bridge opens
+//! as soon as the handshake completes, and the usual protocol (welcome, auth,
+//! ...) runs over it as newline-delimited JSON.
+//!
+//! Browsers negotiate HTTP/3 (`h3`) and open a WebTransport session with an
+//! extended CONNECT request, which is subject to the same origin checks as
+//! WebSocket upgrades. Every bidirectional stream the browser then opens in
+//! the session carries a protocol session of its own, also as
+//! newline-delimited JSON.
+
+use std::future::poll_fn;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use h3::ext::Protocol;
+use h3::frame::FrameStream;
+use h3::proto::frame::Frame;
+use h3::proto::varint::VarInt;
+use h3::server::RequestStream;
+use h3::stream::BufRecvStream;
+use h3::webtransport::SessionId;
+use http::{HeaderValue, Method, Request, Response, StatusCode};
+use quinn::crypto::rustls::{HandshakeData, QuicServerConfig};
+use quinn::rustls;
+use quinn::{Endpoint, Incoming};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
+
+use super::tls::load_cert_and_key;
+use super::transport::{LineReceiver, LineSender};
+use super::websocket::{serve_client, ServerState, SessionOptions};
+
+/// ALPN protocol identifier native clients must offer
+pub const ALPN_PROTOCOL: &[u8] = b"hoc-bridge/1";
+
+/// ALPN protocol identifier of HTTP/3, which WebTransport runs over
+const H3_ALPN: &[u8] = b"h3";
+
+/// Close a connection after this long without any traffic
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keep-alive interval, well below the idle timeout
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Configuration for the QUIC listener
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuicConfig {
+    /// UDP port to listen on
+    pub port: u16,
+    /// PEM file with the certificate chain
+    pub cert_path: PathBuf,
+    /// PEM file with the private key
+    pub key_path: PathBuf,
+}
+
+impl QuicConfig {
+    /// Create a new QUIC configuration
+    pub fn new(port: u16, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            port,
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Build the QUIC server configuration from the certificate files
+    fn server_config(&self) -> anyhow::Result<quinn::ServerConfig> {
+        let (certs, key) = load_cert_and_key(&self.cert_path, &self.key_path)?;
+        let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec(), H3_ALPN.to_vec()];
+
+        let mut transport = quinn::TransportConfig::default();
+        transport
+            .max_idle_timeout(Some(IDLE_TIMEOUT.try_into()?))
+            .keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls)?));
+        config.transport_config(Arc::new(transport));
+        Ok(config)
+    }
+}
+
+/// Bind the QUIC endpoint
+pub(super) fn bind(config: &QuicConfig, bind: &str) -> anyhow::Result<Endpoint> {
+    let addr: SocketAddr = format!("{}:{}", bind, config.port).parse()?;
+    Ok(Endpoint::server(config.server_config()?, addr)?)
+}
+
+/// Serve QUIC clients until shutdown
+pub(super) async fn run_quic(endpoint: Endpoint, state: Arc<ServerState>, shutdown_tx: broadcast::Sender<()>) {
+    if let Ok(addr) = endpoint.local_addr() {
+        info!("QUIC and WebTransport listening on {} (experimental)", addr);
+    }
+
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    loop {
+        tokio::select! {
+            incoming = endpoint.accept() => {
+                let Some(incoming) = incoming else { break };
+                let state = Arc::clone(&state);
+                let shutdown_rx = shutdown_tx.subscribe();
+                tokio::spawn(async move {
+                    let remote = incoming.remote_address();
+                    if let Err(e) = handle_connection(incoming, state, shutdown_rx).await {
+                        error!("QUIC connection error from {}: {}", remote, e);
+                    }
+                });
+            }
+            _ = shutdown_rx.recv() => break,
+        }
+    }
+
+    endpoint.close(0u32.into(), b"shutdown");
+    info!("QUIC transport stopped");
+}
+
+/// Complete the handshake and serve one session over a server-opened stream,
+/// or WebTransport sessions to a browser
+async fn handle_connection(
+    incoming: Incoming,
+    state: Arc<ServerState>,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let connection = incoming.await?;
+    let alpn = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<HandshakeData>().ok())
+        .and_then(|data| data.protocol);
+    if alpn.as_deref() == Some(H3_ALPN) {
+        let peer_addr = format!("webtransport:{}", connection.remote_address());
+        info!("New connection from {}", peer_addr);
+        let result = serve_webtransport(connection.clone(), &peer_addr, state, shutdown_rx).await;
+        connection.close(0u32.into(), b"bye");
+        debug!("WebTransport connection {} closed", peer_addr);
+        return result;
+    }
+    let peer_addr = format!("quic:{}", connection.remote_address());
+    info!("New connection from {}", peer_addr);
+
+    let (send, recv) = connection.open_bi().await?;
+    let result = serve_client(
+        LineSender::new(send),
+        LineReceiver::new(recv),
+        peer_addr.clone(),
+        state,
+        SessionOptions::default(),
+        shutdown_rx,
+    )
+    .await;
+
+    connection.close(0u32.into(), b"bye");
+    debug!("QUIC connection {} closed", peer_addr);
+    result
+}
+
+/// Accept WebTransport sessions on an HTTP/3 connection, serving every
+/// bidirectional stream opened in one until the connection closes
+async fn serve_webtransport(
+    connection: quinn::Connection,
+    peer_addr: &str,
+    state: Arc<ServerState>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let mut h3 = h3::server::builder()
+        .enable_webtransport(true)
+        .enable_extended_connect(true)
+        .enable_datagram(true)
+        .max_webtransport_sessions(1)
+        .build::<_, Bytes>(h3_quinn::Connection::new(connection))
+        .await?;
+    // CONNECT streams of the accepted sessions, kept open as closing one
+    // ends its session
+    let mut sessions: Vec<(SessionId, RequestStream<_, Bytes>)> = Vec::new();
+    loop {
+        let stream = tokio::select! {
+            stream = poll_fn(|cx| h3.poll_accept_request_stream(cx)) => stream?,
+            _ = shutdown_rx.recv() => break,
+        };
+        let Some(stream) = stream else { break };
+        let mut frames = FrameStream::new(BufRecvStream::new(stream));
+        let frame = poll_fn(|cx| frames.poll_next(cx)).await;
+
+        // A stream of a session, which starts with the session's ID
+        if let Ok(Some(Frame::WebTransportStream(session_id))) = frame {
+            if !sessions.iter().any(|(id, _)| *id == session_id) {
+                debug!("Ignoring a stream of unknown WebTransport session {:?}", session_id);
+                continue;
+            }
+            let (recv, send) = tokio::io::split(frames.into_inner());
+            let peer_addr = peer_addr.to_string();
+            let state = Arc::clone(&state);
+            let shutdown_rx = shutdown_rx.resubscribe();
+            tokio::spawn(async move {
+                if let Err(e) = serve_client(
+                    LineSender::new(send),
+                    LineReceiver::new(recv),
+                    peer_addr.clone(),
+                    state,
+                    SessionOptions::default(),
+                    shutdown_rx,
+                )
+                .await
+                {
+                    error!("WebTransport session error from {}: {}", peer_addr, e);
+                }
+            });
+            continue;
+        }
+
+        // Otherwise a request, which must open a session
+        let resolved = match h3.create_resolver(frames).accept_with_frame(frame) {
+            Ok(resolved) => resolved.resolve().await,
+            Err(e) => Err(e),
+        };
+        let (request, mut stream) = match resolved {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                debug!("Invalid HTTP/3 request from {}: {}", peer_addr, e);
+                continue;
+            }
+        };
+        let status = match session_error(&request, &state) {
+            Some((status, reason)) => {
+                warn!("Refused WebTransport session from {}: {}", peer_addr, reason);
+                status
+            }
+            None => StatusCode::OK,
+        };
+        let response = Response::builder()
+            .status(status)
+            // Chrome expects the draft the session follows
+            .header("sec-webtransport-http3-draft", "draft02")
+            .body(())?;
+        if let Err(e) = stream.send_response(response).await {
+            debug!("Failed to answer HTTP/3 request from {}: {}", peer_addr, e);
+            continue;
+        }
+        if status != StatusCode::OK {
+            let _ = stream.finish().await;
+            continue;
+        }
+        // Session IDs are the CONNECT stream's ID
+        let session_id = SessionId::try_from(VarInt::from(stream.id()).into_inner())
+            .map_err(|_| anyhow::anyhow!("Invalid stream ID {}", stream.id()))?;
+        debug!("WebTransport session {:?} opened by {}", session_id, peer_addr);
+        sessions.push((session_id, stream));
+    }
+    Ok(())
+}
+
+/// Why a request may not open a WebTransport session, with the status to
+/// answer it with
+fn session_error(request: &Request<()>, state: &ServerState) -> Option<(StatusCode, String)> {
+    if request.method() != Method::CONNECT
+        || request.extensions().get::<Protocol>() != Some(&Protocol::WEB_TRANSPORT)
+    {
+        return Some((
+            StatusCode::NOT_FOUND,
+            format!("{} {} is not a WebTransport session", request.method(), request.uri()),
+        ));
+    }
+    // HTTP/3 carries the host in the :authority pseudo-header
+    let mut headers = request.headers().clone();
+    if let Some(authority) = request.uri().authority() {
+        if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+            headers.entry("host").or_insert(host);
+        }
+    }
+    state
+        .config
+        .origin_policy
+        .check(&headers, state.config.dashboard_port)
+        .err()
+        .map(|reason| (StatusCode::FORBIDDEN, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::federation::Federation;
+    use crate::server::origin::OriginPolicy;
+    use crate::server::ServerConfig;
+    use quinn::crypto::rustls::QuicClientConfig;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    fn write_cert(dir: &std::path::Path) -> (QuicConfig, rustls::pki_types::CertificateDer<'static>) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        (QuicConfig::new(0, cert_path, key_path), certified.cert.der().clone())
+    }
+
+    #[test]
+    fn test_missing_certificate() {
+        let config = QuicConfig::new(0, "/nonexistent/cert.pem", "/nonexistent/key.pem");
+        let err = config.server_config().unwrap_err();
+        assert!(err.to_string().contains("Failed to read certificate"));
+    }
+
+    /// Bind a bridge's QUIC listener and a client endpoint trusting it,
+    /// negotiating `alpn`
+    fn start(dir: &std::path::Path, alpn: &[u8]) -> (Endpoint, SocketAddr, broadcast::Sender<()>) {
+        let (config, cert) = write_cert(dir);
+        let endpoint = bind(&config, "127.0.0.1").unwrap();
+        let addr = endpoint.local_addr().unwrap();
+        let policy = OriginPolicy::default().with_allowed_origins(vec!["https://hoc.example".into()]);
+        let state = Arc::new(ServerState::new(
+            ServerConfig::new("127.0.0.1".to_string(), 0).with_origin_policy(policy),
+            Federation::new(),
+        ));
+        let (shutdown_tx, _) = broadcast::channel(1);
+        tokio::spawn(run_quic(endpoint, state, shutdown_tx.clone()));
+
+        // Client trusting the self-signed certificate
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        tls.alpn_protocols = vec![alpn.to_vec()];
+        let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(tls).unwrap(),
+        )));
+        (client, addr, shutdown_tx)
+    }
+
+    #[tokio::test]
+    async fn test_quic_session_welcome_and_ping() {
+        let dir = tempfile::tempdir().unwrap();
+        let (client, addr, shutdown_tx) = start(dir.path(), ALPN_PROTOCOL);
+
+        let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+        let (mut send, recv) = connection.accept_bi().await.unwrap();
+        let mut lines = BufReader::new(recv).lines();
+
+        let welcome = lines.next_line().await.unwrap().unwrap();
+        assert!(welcome.contains(r#""type":"welcome""#));
+
+        send.write_all(b"{\"type\":\"ping\",\"seq\":7}\n").await.unwrap();
+        let negotiated = lines.next_line().await.unwrap().unwrap();
+        assert!(negotiated.contains(r#""type":"version_negotiated""#));
+        let pong = lines.next_line().await.unwrap().unwrap();
+        assert!(pong.contains(r#""type":"pong""#));
+        assert!(pong.contains(r#""seq":7"#));
+
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_webtransport_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let (client, addr, shutdown_tx) = start(dir.path(), H3_ALPN);
+        let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+        let (mut driver, mut h3) = h3::client::builder()
+            .enable_extended_connect(true)
+            .enable_datagram(true)
+            .build::<_, _, Bytes>(h3_quinn::Connection::new(connection.clone()))
+            .await
+            .unwrap();
+        tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+        let connect = |origin: &str| {
+            Request::builder()
+                .method(Method::CONNECT)
+                .uri("https://localhost/")
+                .header("origin", origin)
+                .extension(Protocol::WEB_TRANSPORT)
+                .body(())
+                .unwrap()
+        };
+
+        // Pages of other sites may not open sessions
+        let mut refused = h3.send_request(connect("https://evil.example")).await.unwrap();
+        assert_eq!(refused.recv_response().await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        let mut session = h3.send_request(connect("https://hoc.example")).await.unwrap();
+        assert_eq!(session.recv_response().await.unwrap().status(), StatusCode::OK);
+        let session_id = VarInt::from(session.id()).into_inner();
+
+        // A stream of the session: the WebTransport stream type and the
+        // session ID, then the protocol
+        let (mut send, recv) = connection.open_bi().await.unwrap();
+        let mut header = vec![0x40, 0x41];
+        header.push(u8::try_from(session_id).unwrap());
+        send.write_all(&header).await.unwrap();
+        send.write_all(b"{\"type\":\"ping\",\"seq\":3}\n").await.unwrap();
+        let mut lines = BufReader::new(recv).lines();
+        let welcome = lines.next_line().await.unwrap().unwrap();
+        assert!(welcome.contains(r#""type":"welcome""#));
+        let negotiated = lines.next_line().await.unwrap().unwrap();
+        assert!(negotiated.contains(r#""type":"version_negotiated""#));
+        let pong = lines.next_line().await.unwrap().unwrap();
+        assert!(pong.contains(r#""seq":3"#));
+
+        let _ = shutdown_tx.send(());
+    }
+}
@@ -0,0 +1,219 @@
+//! Agent quotas
+//!
+//! Caps how many local agents run at once, both on the whole server and per
+//! client, so one user of a shared bridge cannot take every slot. Usage is
+//! counted from the running agents, which record the client that spawned
+//! them, plus spawns still in progress.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use thiserror::Error;
+
+use super::protocol::{AgentInfo, ErrorCode, ServerMessage};
+
+/// Agent limits (unlimited when `None`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AgentLimits {
+    /// Maximum agents on the server
+    pub max_agents: Option<usize>,
+    /// Maximum agents spawned by one client
+    pub max_per_client: Option<usize>,
+}
+
+impl AgentLimits {
+    /// Cap the number of agents on the server
+    pub fn with_max_agents(mut self, max_agents: Option<usize>) -> Self {
+        self.max_agents = max_agents;
+        self
+    }
+
+    /// Cap the number of agents each client may spawn
+    pub fn with_max_per_client(mut self, max_per_client: Option<usize>) -> Self {
+        self.max_per_client = max_per_client;
+        self
+    }
+}
+
+/// A spawn refused because a limit is reached
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(super) enum LimitReached {
+    #[error("Agent limit reached: {current} of {limit} agents are running")]
+    Server { current: usize, limit: usize },
+
+    #[error("Agent limit reached: {client} is running {current} of {limit} agents")]
+    Client {
+        client: String,
+        current: usize,
+        limit: usize,
+    },
+}
+
+impl LimitReached {
+    /// Error reported to the client, with the usage in its details
+    pub(super) fn to_message(&self) -> ServerMessage {
+        let (scope, current, limit) = match *self {
+            LimitReached::Server { current, limit } => ("server", current, limit),
+            LimitReached::Client { current, limit, .. } => ("client", current, limit),
+        };
+        ServerMessage::error_with_code(self.to_string(), ErrorCode::AgentLimitReached)
+            .with_detail("scope", scope)
+            .with_detail("current", current)
+            .with_detail("limit", limit)
+    }
+}
+
+/// Spawns in progress, which count against the limits until they finish
+#[derive(Default)]
+struct Pending {
+    total: usize,
+    clients: HashMap<String, usize>,
+}
+
+/// Enforces [`AgentLimits`] on spawns
+pub(super) struct AgentQuota {
+    limits: Mutex<AgentLimits>,
+    pending: Mutex<Pending>,
+}
+
+impl AgentQuota {
+    pub(super) fn new(limits: AgentLimits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            pending: Mutex::new(Pending::default()),
+        }
+    }
+
+    /// Change the limits; agents already running over them are left alone
+    pub(super) fn set_limits(&self, limits: AgentLimits) {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner()) = limits;
+    }
+
+    /// Reserve a slot for a spawn by `client`, given the agents now running
+    ///
+    /// The slot is held until the returned reservation is dropped, by which
+    /// time the spawned agent is among the running ones.
+    pub(super) fn reserve(
+        &self,
+        client: Option<&str>,
+        running: &[AgentInfo],
+    ) -> Result<Reservation<'_>, LimitReached> {
+        let limits = *self.limits.lock().unwrap_or_else(|e| e.into_inner());
+        let local = running.iter().filter(|a| a.origin.is_none());
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(limit) = limits.max_agents {
+            let current = local.clone().count() + pending.total;
+            if current >= limit {
+                return Err(LimitReached::Server { current, limit });
+            }
+        }
+        if let (Some(limit), Some(client)) = (limits.max_per_client, client) {
+            let current = local
+                .filter(|a| a.spawned_by.as_deref() == Some(client))
+                .count()
+                + pending.clients.get(client).copied().unwrap_or(0);
+            if current >= limit {
+                return Err(LimitReached::Client {
+                    client: client.to_string(),
+                    current,
+                    limit,
+                });
+            }
+        }
+
+        pending.total += 1;
+        if let Some(client) = client {
+            *pending.clients.entry(client.to_string()).or_default() += 1;
+        }
+        Ok(Reservation {
+            quota: self,
+            client: client.map(str::to_string),
+        })
+    }
+}
+
+/// A slot held for a spawn in progress
+pub(super) struct Reservation<'a> {
+    quota: &'a AgentQuota,
+    client: Option<String>,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.quota.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.total -= 1;
+        if let Some(ref client) = self.client {
+            if let Some(count) = pending.clients.get_mut(client) {
+                *count -= 1;
+                if *count == 0 {
+                    pending.clients.remove(client);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn agent(spawned_by: &str) -> AgentInfo {
+        AgentInfo {
+            agent_id: Uuid::new_v4(),
+            spawned_by: Some(spawned_by.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_per_client_limit() {
+        let quota = AgentQuota::new(AgentLimits::default().with_max_per_client(Some(2)));
+        let running = vec![agent("a"), agent("b")];
+
+        let reservation = quota.reserve(Some("a"), &running).unwrap();
+        assert_eq!(
+            quota.reserve(Some("a"), &running).err(),
+            Some(LimitReached::Client {
+                client: "a".to_string(),
+                current: 2,
+                limit: 2
+            })
+        );
+        // Other clients and unidentified ones are unaffected
+        assert!(quota.reserve(Some("b"), &running).is_ok());
+        assert!(quota.reserve(None, &running).is_ok());
+
+        drop(reservation);
+        assert!(quota.reserve(Some("a"), &running).is_ok());
+    }
+
+    #[test]
+    fn test_server_limit() {
+        let quota = AgentQuota::new(
+            AgentLimits::default()
+                .with_max_agents(Some(2))
+                .with_max_per_client(Some(5)),
+        );
+        let mut running = vec![agent("a"), agent("b")];
+        let error = quota.reserve(Some("c"), &running).err().unwrap();
+        assert_eq!(error, LimitReached::Server { current: 2, limit: 2 });
+
+        let ServerMessage::Error { code, details, .. } = error.to_message() else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(code, Some(ErrorCode::AgentLimitReached));
+        assert_eq!(details["scope"], "server");
+        assert_eq!(details["current"], 2);
+
+        // Agents hosted by peer bridges don't count
+        running[0].origin = Some("peer".to_string());
+        assert!(quota.reserve(Some("c"), &running).is_ok());
+
+        // Lowered limits apply to the next spawn
+        quota.set_limits(AgentLimits::default().with_max_agents(Some(1)));
+        let error = quota.reserve(Some("c"), &running).err().unwrap();
+        assert_eq!(error, LimitReached::Server { current: 1, limit: 1 });
+    }
+}
@@ -0,0 +1,219 @@
+//! Per-connection rate limiting
+//!
+//! Caps how many messages a connection may send per second, and how many
+//! bytes of agent input, so one client cannot flood the bridge or the agents
+//! behind it. Messages over a limit are refused with `rate_limited`; a client
+//! that keeps sending regardless is disconnected.
+
+use std::time::{Duration, Instant};
+
+use super::input_policy::Bucket;
+use super::protocol::{ClientMessage, ErrorCode, ServerMessage};
+
+/// Messages per second a connection may send by default
+pub const DEFAULT_MESSAGE_RATE: u32 = 200;
+
+/// Refused messages within [`STRIKE_WINDOW`] after which a client is
+/// disconnected
+const MAX_STRIKES: u32 = 100;
+
+/// Period refused messages are counted over
+const STRIKE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Rate limits for each connection (unlimited when `None`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Maximum messages per second
+    pub messages_per_sec: Option<u32>,
+    /// Maximum agent input bytes per second, over all agents
+    pub input_bytes_per_sec: Option<u32>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            messages_per_sec: Some(DEFAULT_MESSAGE_RATE),
+            input_bytes_per_sec: None,
+        }
+    }
+}
+
+impl ConnectionLimits {
+    /// Cap the messages a connection may send per second
+    pub fn with_messages_per_sec(mut self, messages_per_sec: Option<u32>) -> Self {
+        self.messages_per_sec = messages_per_sec;
+        self
+    }
+
+    /// Cap the agent input bytes a connection may send per second
+    pub fn with_input_bytes_per_sec(mut self, input_bytes_per_sec: Option<u32>) -> Self {
+        self.input_bytes_per_sec = input_bytes_per_sec;
+        self
+    }
+}
+
+/// Applies [`ConnectionLimits`] to one connection
+pub(super) struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    messages: Bucket,
+    input: Bucket,
+    strikes: u32,
+    strikes_since: Option<Instant>,
+}
+
+impl ConnectionLimiter {
+    pub(super) fn new(limits: ConnectionLimits, now: Instant) -> Self {
+        Self {
+            limits,
+            messages: Bucket::new(limits.messages_per_sec.unwrap_or(0) as f64, now),
+            input: Bucket::new(limits.input_bytes_per_sec.unwrap_or(0) as f64, now),
+            strikes: 0,
+            strikes_since: None,
+        }
+    }
+
+    /// Count a received message, returning the error to answer it with if it
+    /// is over the message rate
+    pub(super) fn message(&mut self, now: Instant) -> Option<ServerMessage> {
+        let rate = self.limits.messages_per_sec? as f64;
+        if self.messages.take(1, rate, now) {
+            return None;
+        }
+        self.strike(now);
+        Some(ServerMessage::error_with_code(
+            "Too many messages, slow down",
+            ErrorCode::RateLimited,
+        ))
+    }
+
+    /// Count the agent input a message carries, returning the error to answer
+    /// it with if it is over the input rate
+    ///
+    /// Broadcast input is counted as the `agent_input` sent to each target.
+    pub(super) fn input(&mut self, message: &ClientMessage, now: Instant) -> Option<ServerMessage> {
+        let rate = self.limits.input_bytes_per_sec? as f64;
+        let len = match message {
+            ClientMessage::AgentInput { input, .. } => input.len(),
+            ClientMessage::AgentInputRaw { data, .. }
+            | ClientMessage::AgentInputChunk { data, .. } => data.len(),
+            _ => return None,
+        };
+        if self.input.take(len, rate, now) {
+            return None;
+        }
+        self.strike(now);
+        Some(ServerMessage::error_with_code(
+            "Too much agent input, slow down",
+            ErrorCode::RateLimited,
+        ))
+    }
+
+    /// Whether the client kept sending over its limits and should be dropped
+    pub(super) fn is_abusive(&self) -> bool {
+        self.strikes >= MAX_STRIKES
+    }
+
+    fn strike(&mut self, now: Instant) {
+        match self.strikes_since {
+            Some(since) if now.duration_since(since) < STRIKE_WINDOW => self.strikes += 1,
+            _ => {
+                self.strikes = 1;
+                self.strikes_since = Some(now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_message_rate() {
+        let start = Instant::now();
+        let limits = ConnectionLimits::default().with_messages_per_sec(Some(5));
+        let mut limiter = ConnectionLimiter::new(limits, start);
+
+        for _ in 0..5 {
+            assert!(limiter.message(start).is_none());
+        }
+        assert!(matches!(
+            limiter.message(start),
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::RateLimited),
+                ..
+            })
+        ));
+        assert!(limiter.message(start + Duration::from_secs(1)).is_none());
+
+        let mut unlimited = ConnectionLimiter::new(limits.with_messages_per_sec(None), start);
+        assert!((0..1000).all(|_| unlimited.message(start).is_none()));
+    }
+
+    #[test]
+    fn test_input_rate() {
+        let start = Instant::now();
+        let limits = ConnectionLimits::default().with_input_bytes_per_sec(Some(10));
+        let mut limiter = ConnectionLimiter::new(limits, start);
+        let input = |text: &str| ClientMessage::AgentInput {
+            agent_id: Uuid::new_v4(),
+            input: text.to_string(),
+            paste: false,
+        };
+
+        assert!(limiter.input(&input("12345678"), start).is_none());
+        // The budget is shared by every agent
+        assert!(limiter.input(&input("12345678"), start).is_some());
+        assert!(limiter.input(&ClientMessage::list_agents(), start).is_none());
+    }
+
+    #[test]
+    fn test_broadcast_input_rate() {
+        let start = Instant::now();
+        let limits = ConnectionLimits::default().with_input_bytes_per_sec(Some(10));
+        let mut limiter = ConnectionLimiter::new(limits, start);
+        let agent_ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let broadcast = ClientMessage::BroadcastInput {
+            agent_ids: agent_ids.clone(),
+            group: None,
+            input: "1234".to_string(),
+            paste: false,
+        };
+
+        assert!(limiter.input(&broadcast, start).is_none());
+        // Each target is charged the input, so three copies don't fit
+        let refused: Vec<bool> = agent_ids
+            .into_iter()
+            .map(|agent_id| {
+                let input = ClientMessage::AgentInput {
+                    agent_id,
+                    input: "1234".to_string(),
+                    paste: false,
+                };
+                limiter.input(&input, start).is_some()
+            })
+            .collect();
+        assert_eq!(refused, [false, false, true]);
+    }
+
+    #[test]
+    fn test_disconnects_persistent_floods() {
+        let start = Instant::now();
+        let limits = ConnectionLimits::default().with_messages_per_sec(Some(1));
+        let mut limiter = ConnectionLimiter::new(limits, start);
+        limiter.message(start);
+
+        for _ in 0..MAX_STRIKES - 1 {
+            limiter.message(start);
+        }
+        assert!(!limiter.is_abusive());
+        // Strikes expire, so an occasional burst is forgiven
+        limiter.message(start + STRIKE_WINDOW);
+        assert!(!limiter.is_abusive());
+        for _ in 0..MAX_STRIKES {
+            limiter.message(start + STRIKE_WINDOW);
+        }
+        assert!(limiter.is_abusive());
+    }
+}
@@ -0,0 +1,149 @@
+//! Relay (reverse-tunnel) mode
+//!
+//! Instead of waiting for clients to connect, the bridge dials out to a relay
+//! endpoint and serves clients through it. This lets headsets reach a bridge
+//! running behind NAT without port forwarding.
+//!
+//! The relay protocol is intentionally minimal:
+//! 1. The bridge opens a WebSocket to the relay URL and waits, idle.
+//! 2. When a client is paired with that connection, the relay sends a single
+//!    `{"type": "relay_paired", "client": "<addr>"}` text frame.
+//! 3. From then on the relay pipes frames verbatim between client and bridge,
+//!    and the bridge speaks the normal protocol (welcome, auth, ...).
+//!
+//! The bridge always keeps exactly one idle connection open, dialing a new one
+//! as soon as the previous one is paired.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+use tokio_tungstenite::{connect_async, tungstenite::Message, WebSocketStream};
+use tracing::{debug, error, info, warn};
+
+use super::websocket::{serve_websocket, ServerState, SessionOptions};
+
+/// Initial delay before retrying a failed relay connection
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximum delay between relay connection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Control messages sent by the relay before it starts piping client frames
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RelayControl {
+    /// A client has been paired with this connection
+    RelayPaired {
+        /// Client address as seen by the relay
+        #[serde(default)]
+        client: Option<String>,
+    },
+}
+
+/// Serve clients through a relay until shutdown
+pub(super) async fn run_relay(
+    url: String,
+    state: Arc<ServerState>,
+    shutdown_tx: broadcast::Sender<()>,
+) {
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    let mut backoff = INITIAL_BACKOFF;
+
+    info!("Relay mode enabled, dialing {}", url);
+
+    loop {
+        let attempt = async {
+            let (mut ws_stream, _) = connect_async(url.as_str()).await?;
+            debug!("Idle relay connection open, waiting for a client");
+            let client = wait_for_pairing(&mut ws_stream).await?;
+            Ok::<_, anyhow::Error>((ws_stream, client))
+        };
+
+        let result = tokio::select! {
+            result = attempt => result,
+            _ = shutdown_rx.recv() => break,
+        };
+
+        match result {
+            Ok((ws_stream, client)) => {
+                backoff = INITIAL_BACKOFF;
+                let peer_addr = format!("relay:{}", client.as_deref().unwrap_or("unknown"));
+                info!("Relay paired client {}", peer_addr);
+
+                let state = Arc::clone(&state);
+                let shutdown_rx = shutdown_tx.subscribe();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_websocket(ws_stream, peer_addr.clone(), state, SessionOptions::default(), shutdown_rx).await {
+                        error!("Connection error from {}: {}", peer_addr, e);
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("Relay connection to {} failed: {} (retrying in {:?})", url, e, backoff);
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = shutdown_rx.recv() => break,
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    info!("Relay mode stopped");
+}
+
+/// Wait on an idle relay connection until the relay pairs a client
+///
+/// Returns the client address reported by the relay.
+async fn wait_for_pairing<S>(ws_stream: &mut WebSocketStream<S>) -> anyhow::Result<Option<String>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(msg) = ws_stream.next().await {
+        match msg? {
+            Message::Text(text) => {
+                let RelayControl::RelayPaired { client } = parse_control(&text)?;
+                return Ok(client);
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    Err(anyhow::anyhow!("relay closed the connection before pairing"))
+}
+
+/// Parse a relay control frame
+fn parse_control(text: &str) -> anyhow::Result<RelayControl> {
+    serde_json::from_str(text).map_err(|e| anyhow::anyhow!("invalid relay control message: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_paired() {
+        let control = parse_control(r#"{"type": "relay_paired", "client": "203.0.113.7:5123"}"#).unwrap();
+        assert_eq!(
+            control,
+            RelayControl::RelayPaired {
+                client: Some("203.0.113.7:5123".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_paired_without_client() {
+        let control = parse_control(r#"{"type": "relay_paired"}"#).unwrap();
+        assert_eq!(control, RelayControl::RelayPaired { client: None });
+    }
+
+    #[test]
+    fn test_parse_unknown_control() {
+        assert!(parse_control(r#"{"type": "ping", "seq": 1}"#).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Configuration reloads
+//!
+//! The authentication tokens, agent limits and allowed roots can change
+//! without a restart: on SIGHUP or a `reload_config` message the
+//! configuration file is read again. New values apply to connections and
+//! requests from then on; open connections and running agents are left
+//! alone.
+
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use thiserror::Error;
+
+use super::protocol::Role;
+use super::quota::AgentLimits;
+use crate::config::{BridgeConfig, ConfigError, TokenConfig};
+
+/// Settings that can be reloaded while the server runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadableConfig {
+    /// Authentication token for remote connections, granting the admin role
+    pub token: Option<String>,
+    /// Further tokens, each with its own role
+    pub tokens: Vec<TokenConfig>,
+    /// Directories clients may access files under
+    pub allowed_roots: Vec<PathBuf>,
+    /// Caps on running agents, overall and per client
+    pub agent_limits: AgentLimits,
+}
+
+impl ReloadableConfig {
+    /// Settings from `file`, except those `flags` set, which override it
+    pub fn merge(flags: &ReloadableConfig, file: &BridgeConfig) -> Self {
+        let allowed_roots = if flags.allowed_roots.is_empty() {
+            file.allowed_roots.clone()
+        } else {
+            flags.allowed_roots.clone()
+        };
+        let tokens = if flags.tokens.is_empty() {
+            file.tokens.clone()
+        } else {
+            flags.tokens.clone()
+        };
+        Self {
+            token: flags.token.clone().or_else(|| file.token.clone()),
+            tokens,
+            allowed_roots,
+            agent_limits: AgentLimits::default()
+                .with_max_agents(flags.agent_limits.max_agents.or(file.max_agents))
+                .with_max_per_client(
+                    flags
+                        .agent_limits
+                        .max_per_client
+                        .or(file.max_agents_per_client),
+                ),
+        }
+    }
+
+    /// Whether clients must authenticate
+    pub(super) fn auth_required(&self) -> bool {
+        self.token.is_some() || !self.tokens.is_empty()
+    }
+
+    /// Role granted by a token, `None` if the token is unknown
+    pub(super) fn role_for(&self, token: &str) -> Option<Role> {
+        if self.token.as_deref() == Some(token) {
+            return Some(Role::Admin);
+        }
+        self.tokens
+            .iter()
+            .find(|entry| entry.token == token)
+            .map(|entry| entry.role)
+    }
+
+    /// Name a token was issued under, for the audit log
+    pub(super) fn token_name(&self, token: &str) -> Option<String> {
+        self.tokens
+            .iter()
+            .find(|entry| entry.token == token)
+            .and_then(|entry| entry.name.clone())
+    }
+
+    /// A token granting the admin role, which cluster nodes link with
+    pub(super) fn admin_token(&self) -> Option<String> {
+        self.token.clone().or_else(|| {
+            self.tokens
+                .iter()
+                .find(|entry| entry.role == Role::Admin)
+                .map(|entry| entry.token.clone())
+        })
+    }
+
+    /// Names of the settings that differ from `other`'s
+    pub(super) fn changes(&self, other: &ReloadableConfig) -> Vec<String> {
+        let mut changed = Vec::new();
+        if self.token != other.token {
+            changed.push("token".to_string());
+        }
+        if self.tokens != other.tokens {
+            changed.push("tokens".to_string());
+        }
+        if self.allowed_roots != other.allowed_roots {
+            changed.push("allowed_roots".to_string());
+        }
+        if self.agent_limits.max_agents != other.agent_limits.max_agents {
+            changed.push("max_agents".to_string());
+        }
+        if self.agent_limits.max_per_client != other.agent_limits.max_per_client {
+            changed.push("max_agents_per_client".to_string());
+        }
+        changed
+    }
+}
+
+/// Where reloaded settings come from
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    /// Configuration file, which need not exist until a reload
+    pub path: PathBuf,
+    /// Settings given on the command line, which keep overriding the file's
+    pub flags: ReloadableConfig,
+}
+
+impl ConfigSource {
+    /// Read settings from `path`, overridden by `flags`
+    pub fn new(path: PathBuf, flags: ReloadableConfig) -> Self {
+        Self { path, flags }
+    }
+
+    /// Read the file again
+    pub fn load(&self) -> Result<ReloadableConfig, ReloadError> {
+        let file = BridgeConfig::load(&self.path).map_err(|source| ReloadError::Config {
+            path: self.path.display().to_string(),
+            source,
+        })?;
+        Ok(ReloadableConfig::merge(&self.flags, &file))
+    }
+}
+
+/// A failed reload, which leaves the settings as they were
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error("No configuration file to reload")]
+    NoSource,
+
+    #[error("Failed to reload {path}: {source}")]
+    Config { path: String, source: ConfigError },
+}
+
+/// Settings in effect, and where to reload them from
+#[derive(Debug)]
+pub(super) struct LiveConfig {
+    source: Option<ConfigSource>,
+    current: RwLock<ReloadableConfig>,
+}
+
+impl LiveConfig {
+    pub(super) fn new(initial: ReloadableConfig, source: Option<ConfigSource>) -> Self {
+        Self {
+            source,
+            current: RwLock::new(initial),
+        }
+    }
+
+    /// Settings in effect
+    pub(super) fn get(&self) -> ReloadableConfig {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether clients must authenticate
+    pub(super) fn auth_required(&self) -> bool {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .auth_required()
+    }
+
+    /// Role granted by a token in effect, `None` if the token is unknown
+    pub(super) fn role_for(&self, token: &str) -> Option<Role> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .role_for(token)
+    }
+
+    /// Name a token in effect was issued under
+    pub(super) fn token_name(&self, token: &str) -> Option<String> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .token_name(token)
+    }
+
+    /// A token in effect that grants the admin role
+    pub(super) fn admin_token(&self) -> Option<String> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .admin_token()
+    }
+
+    /// Read the configuration again and put it into effect, returning the
+    /// new settings and the names of those that changed
+    pub(super) fn reload(&self) -> Result<(ReloadableConfig, Vec<String>), ReloadError> {
+        let source = self.source.as_ref().ok_or(ReloadError::NoSource)?;
+        let reloaded = source.load()?;
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        let changed = reloaded.changes(&current);
+        *current = reloaded.clone();
+        Ok((reloaded, changed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_file() {
+        let file = BridgeConfig::parse(
+            r#"
+token = "file"
+allowed_roots = ["/srv"]
+max_agents = 4
+max_agents_per_client = 2
+"#,
+        )
+        .unwrap();
+        let flags = ReloadableConfig {
+            token: Some("flag".to_string()),
+            agent_limits: AgentLimits::default().with_max_agents(Some(8)),
+            ..Default::default()
+        };
+        let merged = ReloadableConfig::merge(&flags, &file);
+        assert_eq!(merged.token.as_deref(), Some("flag"));
+        assert_eq!(merged.allowed_roots, [PathBuf::from("/srv")]);
+        assert_eq!(merged.agent_limits.max_agents, Some(8));
+        assert_eq!(merged.agent_limits.max_per_client, Some(2));
+
+        let initial = ReloadableConfig::merge(&ReloadableConfig::default(), &file);
+        assert_eq!(merged.changes(&initial), ["token", "max_agents"]);
+    }
+
+    #[test]
+    fn test_token_roles() {
+        let file = BridgeConfig::parse(
+            r#"
+[[tokens]]
+token = "ops"
+role = "operator"
+name = "ci"
+
+[[tokens]]
+token = "root"
+role = "admin"
+"#,
+        )
+        .unwrap();
+        let config = ReloadableConfig::merge(&ReloadableConfig::default(), &file);
+        assert!(config.auth_required());
+        assert_eq!(config.role_for("ops"), Some(Role::Operator));
+        assert_eq!(config.role_for("nope"), None);
+        assert_eq!(config.token_name("ops").as_deref(), Some("ci"));
+        assert_eq!(config.token_name("root"), None);
+        assert_eq!(config.admin_token().as_deref(), Some("root"));
+
+        // The single token is an admin token
+        let flags = ReloadableConfig {
+            token: Some("flag".to_string()),
+            ..Default::default()
+        };
+        let config = ReloadableConfig::merge(&flags, &file);
+        assert_eq!(config.role_for("flag"), Some(Role::Admin));
+        assert_eq!(config.admin_token().as_deref(), Some("flag"));
+        assert!(!ReloadableConfig::default().auth_required());
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bridge.toml");
+        let source = ConfigSource::new(path.clone(), ReloadableConfig::default());
+        let live = LiveConfig::new(ReloadableConfig::default(), Some(source));
+
+        // A missing or invalid file leaves the settings alone
+        assert!(matches!(live.reload(), Err(ReloadError::Config { .. })));
+        std::fs::write(&path, "max_agents = \"many\"").unwrap();
+        assert!(live.reload().is_err());
+        assert_eq!(live.get(), ReloadableConfig::default());
+
+        std::fs::write(&path, "token = \"secret\"\nmax_agents = 3").unwrap();
+        let (reloaded, changed) = live.reload().unwrap();
+        assert_eq!(changed, ["token", "max_agents"]);
+        assert_eq!(reloaded.agent_limits.max_agents, Some(3));
+        assert_eq!(live.role_for("secret"), Some(Role::Admin));
+        assert!(live.reload().unwrap().1.is_empty());
+
+        let fixed = LiveConfig::new(ReloadableConfig::default(), None);
+        assert!(matches!(fixed.reload(), Err(ReloadError::NoSource)));
+    }
+}
@@ -0,0 +1,125 @@
+//! Playback of agent recordings
+//!
+//! A recording is streamed to the connection that asked for it with the
+//! timing it was recorded with, scaled by the requested speed. Long quiet
+//! stretches (an agent waiting on its user) are shortened so reviewing a run
+//! doesn't mean sitting through them.
+
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use super::protocol::ServerMessage;
+use crate::agent::{Cast, CastEvent};
+
+/// Longest pause between two replayed events
+const MAX_REPLAY_PAUSE: Duration = Duration::from_secs(2);
+
+/// Pause before each event of a recording played at `speed`
+fn pauses(events: &[CastEvent], speed: f64) -> Vec<Duration> {
+    let mut previous = Duration::ZERO;
+    events
+        .iter()
+        .map(|event| {
+            let gap = event.at().saturating_sub(previous);
+            previous = event.at();
+            gap.div_f64(speed).min(MAX_REPLAY_PAUSE)
+        })
+        .collect()
+}
+
+/// Play a recording to a client, stopping early if the client goes away
+pub(super) async fn replay(
+    recording_id: String,
+    cast: Cast,
+    speed: f64,
+    tx: mpsc::UnboundedSender<ServerMessage>,
+) {
+    let started = ServerMessage::ReplayStarted {
+        recording_id: recording_id.clone(),
+        cols: cast.header.width,
+        rows: cast.header.height,
+    };
+    if tx.send(started).is_err() {
+        return;
+    }
+
+    for (pause, event) in pauses(&cast.events, speed).into_iter().zip(cast.events) {
+        tokio::time::sleep(pause).await;
+        let msg = match event {
+            CastEvent::Output { data, .. } => ServerMessage::ReplayOutput {
+                recording_id: recording_id.clone(),
+                data,
+            },
+            CastEvent::Resize { cols, rows, .. } => ServerMessage::ReplayResized {
+                recording_id: recording_id.clone(),
+                cols,
+                rows,
+            },
+        };
+        if tx.send(msg).is_err() {
+            return;
+        }
+    }
+    let _ = tx.send(ServerMessage::ReplayFinished { recording_id });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::CastHeader;
+
+    fn output(at_ms: u64) -> CastEvent {
+        CastEvent::Output {
+            at: Duration::from_millis(at_ms),
+            data: "x".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_pauses() {
+        let events = [output(500), output(1500), output(60_000)];
+        assert_eq!(
+            pauses(&events, 1.0),
+            vec![
+                Duration::from_millis(500),
+                Duration::from_millis(1000),
+                MAX_REPLAY_PAUSE
+            ]
+        );
+        assert_eq!(pauses(&events, 4.0)[1], Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn test_replay_messages() {
+        let cast = Cast {
+            header: CastHeader {
+                version: 2,
+                width: 80,
+                height: 24,
+                timestamp: 0,
+                title: None,
+            },
+            events: vec![
+                output(100),
+                CastEvent::Resize {
+                    at: Duration::from_millis(200),
+                    cols: 100,
+                    rows: 30,
+                },
+            ],
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        replay("rec".to_string(), cast, 1.0, tx).await;
+
+        let mut messages = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            messages.push(msg);
+        }
+        assert_eq!(messages.len(), 4);
+        assert!(matches!(messages[0], ServerMessage::ReplayStarted { cols: 80, rows: 24, .. }));
+        assert!(matches!(&messages[1], ServerMessage::ReplayOutput { data, .. } if data == "x"));
+        assert!(matches!(messages[2], ServerMessage::ReplayResized { cols: 100, rows: 30, .. }));
+        assert!(matches!(messages[3], ServerMessage::ReplayFinished { .. }));
+    }
+}
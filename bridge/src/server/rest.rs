@@ -0,0 +1,359 @@
+//! REST API
+//!
+//! `GET /agents`, `POST /agents`, `GET /agents/{id}` and `DELETE /agents/{id}`
+//! are answered on the WebSocket port, so scripts and CI jobs can drive the
+//! bridge with plain HTTP instead of the protocol. Requests are translated
+//! into protocol messages and go through the same handler as WebSocket
+//! clients. They authenticate with the same tokens, sent as
+//! `Authorization: Bearer <token>`, and requests from browser pages pass the
+//! same origin checks as WebSocket upgrades.
+
+use std::net::SocketAddr;
+
+use serde::Serialize;
+use tokio_tungstenite::tungstenite::http::{HeaderMap, HeaderName, HeaderValue};
+use tracing::debug;
+use uuid::Uuid;
+
+use super::http::{HttpRequest, HttpResponse};
+use super::protocol::{
+    AgentFilter, AgentInfo, AgentState, ClientMessage, ErrorCode, Role, ServerMessage,
+};
+use super::proxy::resolve_client;
+use super::websocket::{dispatch_request, role_error, ServerState};
+
+/// Path the API is served under
+const AGENTS_PATH: &str = "/agents";
+
+/// Body of an error response
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+}
+
+/// Whether a request is for the REST API rather than a WebSocket upgrade
+pub(super) fn is_rest(request: &HttpRequest) -> bool {
+    let under_agents = request
+        .path
+        .strip_prefix(AGENTS_PATH)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+    under_agents && request.header("upgrade").is_none()
+}
+
+/// Answer a REST request from `peer_addr`
+pub(super) async fn handle(
+    request: &HttpRequest,
+    peer_addr: SocketAddr,
+    state: &ServerState,
+) -> HttpResponse {
+    let headers = header_map(request);
+    if let Err(reason) = state
+        .config
+        .origin_policy
+        .check(&headers, state.config.dashboard_port)
+    {
+        return error(403, reason, None);
+    }
+    let role = match authorize(request, state) {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+    let client = resolve_client(peer_addr, &headers, &state.config.trusted_proxies).client_addr;
+
+    let agent_id = match request.path[AGENTS_PATH.len()..].trim_matches('/') {
+        "" => None,
+        id => match Uuid::parse_str(id) {
+            Ok(agent_id) => Some(agent_id),
+            Err(_) => return error(404, format!("No agent {}", id), None),
+        },
+    };
+    let message = match (request.method.as_str(), agent_id) {
+        ("GET", None) => match filter(request) {
+            Ok(filter) => ClientMessage::ListAgents { filter },
+            Err(response) => return response,
+        },
+        ("POST", None) => match spawn_message(&request.body) {
+            Ok(message) => message,
+            Err(reason) => return error(400, reason, Some(ErrorCode::InvalidMessage)),
+        },
+        ("GET", Some(agent_id)) => ClientMessage::GetAgentStatus { agent_id },
+        ("DELETE", Some(agent_id)) => ClientMessage::KillAgent {
+            agent_id,
+            signal: None,
+        },
+        _ => return HttpResponse::method_not_allowed(),
+    };
+    debug!("REST {} {} from {}", request.method, request.path, client);
+
+    match dispatch(state, role, &client, message).await {
+        Ok(ServerMessage::AgentList { agents }) => HttpResponse::json(200, &agents),
+        Ok(ServerMessage::AgentStatus { info }) => HttpResponse::json(200, &info),
+        Ok(ServerMessage::AgentSpawned {
+            agent_id,
+            project_path,
+            cols,
+            rows,
+        }) => {
+            let info = match state.agent_manager.get_agent_status(agent_id).await {
+                Ok(info) => info,
+                // Already exited again; report what was spawned
+                Err(_) => AgentInfo {
+                    agent_id,
+                    project_path,
+                    status: AgentState::Stopped,
+                    cols,
+                    rows,
+                    ..Default::default()
+                },
+            };
+            HttpResponse::json(201, &info)
+        }
+        Ok(ServerMessage::AgentExited { .. }) => HttpResponse::text(204, ""),
+        Ok(other) => error(500, format!("Unexpected response: {:?}", other), None),
+        Err(response) => response,
+    }
+}
+
+/// Check the bearer token when authentication is enabled, returning the role
+/// it grants
+fn authorize(request: &HttpRequest, state: &ServerState) -> Result<Role, HttpResponse> {
+    if !state.live.auth_required() {
+        return Ok(Role::Admin);
+    }
+    let provided = request
+        .header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match provided {
+        Some(token) => state.live.role_for(token).ok_or_else(|| {
+            error(
+                401,
+                "Invalid authentication token",
+                Some(ErrorCode::AuthFailed),
+            )
+        }),
+        None => Err(error(
+            401,
+            "Authentication required",
+            Some(ErrorCode::AuthRequired),
+        )),
+    }
+}
+
+/// Validate and handle a protocol message, turning errors into responses
+async fn dispatch(
+    state: &ServerState,
+    role: Role,
+    client: &str,
+    message: ClientMessage,
+) -> Result<ServerMessage, HttpResponse> {
+    if let Some(ServerMessage::Error { message, code, .. }) = role_error(role, &message) {
+        return Err(error(403, message, code));
+    }
+    match dispatch_request(state, message, Some(client), client, role).await {
+        Ok(Some(ServerMessage::Error { message, code, .. })) => {
+            Err(error(error_status(code), message, code))
+        }
+        Ok(Some(response)) => Ok(response),
+        // The request was forwarded to a peer bridge, which answers asynchronously
+        Ok(None) => Err(error(503, "Request was forwarded to a peer bridge", None)),
+        Err(e) => Err(error(500, e.to_string(), Some(ErrorCode::InternalError))),
+    }
+}
+
+/// The `spawn_agent` message for a `POST /agents` body, which takes the
+/// message's fields
+fn spawn_message(body: &[u8]) -> Result<ClientMessage, String> {
+    if body.is_empty() {
+        return Err("Expected a JSON object with the agent's project_path".to_string());
+    }
+    let mut fields: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(body).map_err(|e| format!("Invalid JSON body: {}", e))?;
+    fields.insert("type".to_string(), "spawn_agent".into());
+    serde_json::from_value(fields.into()).map_err(|e| format!("Invalid agent: {}", e))
+}
+
+/// Agent filter from the `tag`, `status` and `project_path` query parameters
+fn filter(request: &HttpRequest) -> Result<AgentFilter, HttpResponse> {
+    let status = request
+        .query_param("status")
+        .map(|status| {
+            serde_json::from_value(status.into()).map_err(|_| {
+                error(
+                    400,
+                    format!("Unknown agent status {}", status),
+                    Some(ErrorCode::InvalidMessage),
+                )
+            })
+        })
+        .transpose()?;
+    Ok(AgentFilter {
+        tag: request.query_param("tag").map(str::to_string),
+        status,
+        project_path: request.query_param("project_path").map(str::to_string),
+    })
+}
+
+/// The request's headers, for the origin and proxy checks shared with
+/// WebSocket upgrades
+fn header_map(request: &HttpRequest) -> HeaderMap {
+    request
+        .headers
+        .iter()
+        .filter_map(|(name, value)| {
+            Some((
+                HeaderName::from_bytes(name.as_bytes()).ok()?,
+                HeaderValue::from_str(value).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// HTTP status for an error response
+fn error_status(code: Option<ErrorCode>) -> u16 {
+    match code {
+        Some(ErrorCode::AgentNotFound
+            | ErrorCode::MacroNotFound
+            | ErrorCode::KeyNotBound
+            | ErrorCode::RecordingNotFound
+            | ErrorCode::SessionExpired
+            | ErrorCode::TaskNotFound
+            | ErrorCode::GroupNotFound
+            | ErrorCode::PipelineNotFound
+            | ErrorCode::ScheduleNotFound) => 404,
+        Some(ErrorCode::CapabilityDisabled | ErrorCode::PermissionDenied) => 403,
+        Some(ErrorCode::NoPendingConfirmation
+            | ErrorCode::InputLocked
+            | ErrorCode::GitFailed
+            | ErrorCode::ConfigInvalid) => 409,
+        Some(ErrorCode::InvalidMessage
+            | ErrorCode::InvalidPath
+            | ErrorCode::UnsupportedVersion
+            | ErrorCode::InputRejected) => 400,
+        Some(ErrorCode::AuthRequired | ErrorCode::AuthFailed) => 401,
+        Some(ErrorCode::RateLimited | ErrorCode::AgentLimitReached | ErrorCode::TaskQueueFull) => {
+            429
+        }
+        Some(ErrorCode::SpawnFailed | ErrorCode::InternalError) | None => 500,
+    }
+}
+
+fn error(status: u16, message: impl Into<String>, code: Option<ErrorCode>) -> HttpResponse {
+    HttpResponse::json(
+        status,
+        &ErrorBody {
+            error: message.into(),
+            code,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::agent::AgentManager;
+    use crate::pty::{PtyScript, ScriptedPtyBackend};
+    use crate::server::federation::Federation;
+    use crate::server::websocket::ServerConfig;
+
+    fn request(method: &str, path: &str, body: &str) -> HttpRequest {
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (path.to_string(), None),
+        };
+        HttpRequest {
+            method: method.to_string(),
+            path,
+            query,
+            headers: vec![("host".to_string(), "127.0.0.1:9000".to_string())],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn peer() -> SocketAddr {
+        "127.0.0.1:50000".parse().unwrap()
+    }
+
+    fn json(response: &HttpResponse) -> serde_json::Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_is_rest() {
+        assert!(is_rest(&request("GET", "/agents", "")));
+        assert!(is_rest(&request("DELETE", "/agents/abc", "")));
+        assert!(!is_rest(&request("GET", "/agentsx", "")));
+        assert!(!is_rest(&request("GET", "/", "")));
+        let mut upgrade = request("GET", "/agents", "");
+        upgrade.headers.push(("upgrade".to_string(), "websocket".to_string()));
+        assert!(!is_rest(&upgrade));
+    }
+
+    #[tokio::test]
+    async fn test_agents() {
+        let dir = tempfile::tempdir().unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty.clone()));
+        let (shutdown_tx, _) = tokio::sync::broadcast::channel(1);
+        let state = &ServerState::with_backend(
+            ServerConfig::new("127.0.0.1".to_string(), 9000),
+            Federation::new(),
+            manager.clone(),
+            manager,
+            shutdown_tx,
+        );
+
+        let body = serde_json::json!({"project_path": dir.path(), "tags": ["ci"]}).to_string();
+        let spawned = handle(&request("POST", "/agents", &body), peer(), state).await;
+        assert_eq!(spawned.status, 201);
+        let agent_id = json(&spawned)["agent_id"].as_str().unwrap().to_string();
+        assert_eq!(pty.spawns().len(), 1);
+
+        let listed = handle(&request("GET", "/agents?tag=ci", ""), peer(), state).await;
+        assert_eq!(listed.status, 200);
+        assert_eq!(json(&listed)[0]["agent_id"], agent_id.as_str());
+        let listed = handle(&request("GET", "/agents?tag=other", ""), peer(), state).await;
+        assert_eq!(json(&listed), serde_json::json!([]));
+        let status = handle(&request("GET", "/agents?status=sleepy", ""), peer(), state).await;
+        assert_eq!(status.status, 400);
+
+        let path = format!("/agents/{}", agent_id);
+        let status = handle(&request("GET", &path, ""), peer(), state).await;
+        assert_eq!(json(&status)["project_path"], dir.path().display().to_string());
+        let killed = handle(&request("DELETE", &path, ""), peer(), state).await;
+        assert_eq!(killed.status, 204);
+
+        let missing = format!("/agents/{}", Uuid::new_v4());
+        let missing = handle(&request("GET", &missing, ""), peer(), state).await;
+        assert_eq!(missing.status, 404);
+        assert_eq!(json(&missing)["code"], "agent_not_found");
+        let invalid = handle(&request("POST", "/agents", "{\"cols\": 80}"), peer(), state).await;
+        assert_eq!(invalid.status, 400);
+        let method = handle(&request("PUT", "/agents", ""), peer(), state).await;
+        assert_eq!(method.status, 405);
+    }
+
+    #[tokio::test]
+    async fn test_auth_and_origin() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_token(Some("secret".to_string()));
+        let state = &ServerState::new(config, Federation::new());
+
+        let anonymous = handle(&request("GET", "/agents", ""), peer(), state).await;
+        assert_eq!(anonymous.status, 401);
+        let mut authorized = request("GET", "/agents", "");
+        authorized
+            .headers
+            .push(("authorization".to_string(), "Bearer secret".to_string()));
+        assert_eq!(handle(&authorized, peer(), state).await.status, 200);
+
+        // Pages on other sites can't drive the bridge with a visitor's browser
+        authorized
+            .headers
+            .push(("origin".to_string(), "https://evil.example".to_string()));
+        assert_eq!(handle(&authorized, peer(), state).await.status, 403);
+    }
+}
@@ -0,0 +1,415 @@
+//! Session resumption
+//!
+//! Headsets drop off Wi-Fi for a few seconds at a time. So that such a drop
+//! doesn't reset the client's workspace, every connection is given a resume
+//! token (`resume_token`, sent to version 2 clients). When the connection
+//! is lost rather than closed, its session is kept for a grace period: the
+//! agents it was attached to, the input control it held and the output
+//! those agents printed meanwhile. A new connection presenting the token
+//! with `resume_session` picks all of that up. If the old connection hasn't
+//! been found dead yet, it is closed in favour of the new one.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::{broadcast, Notify};
+use tracing::info;
+use uuid::Uuid;
+
+use super::protocol::{Role, StreamMode};
+use super::websocket::ServerState;
+use crate::agent::AgentEvent;
+
+/// Default time a dropped connection's session is kept
+pub const DEFAULT_RESUME_GRACE: Duration = Duration::from_secs(60);
+
+/// Most missed output kept for a session; older output is dropped
+const MAX_BACKLOG_BYTES: usize = 1024 * 1024;
+
+/// Longest a resuming client waits for the connection it replaces to close
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A connection's resume token and how long its session outlives it
+#[derive(Debug, Clone)]
+pub(super) struct ResumeTicket {
+    pub(super) token: String,
+    pub(super) grace: Duration,
+    /// Notified when a resuming client takes the session over
+    pub(super) takeover: Arc<Notify>,
+}
+
+/// Who a session belongs to; only the same role and token may resume it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Identity {
+    pub(super) role: Role,
+    pub(super) token_name: Option<String>,
+}
+
+/// Output missed while the client was away
+#[derive(Debug, Default)]
+pub(super) struct Backlog {
+    chunks: VecDeque<(Uuid, Vec<u8>)>,
+    bytes: usize,
+    truncated: bool,
+}
+
+impl Backlog {
+    fn push(&mut self, agent_id: Uuid, data: &[u8]) {
+        match self.chunks.back_mut() {
+            Some((last, chunk)) if *last == agent_id => chunk.extend_from_slice(data),
+            _ => self.chunks.push_back((agent_id, data.to_vec())),
+        }
+        self.bytes += data.len();
+        while self.bytes > MAX_BACKLOG_BYTES {
+            let Some((_, oldest)) = self.chunks.front_mut() else {
+                break;
+            };
+            let excess = self.bytes - MAX_BACKLOG_BYTES;
+            if oldest.len() > excess {
+                oldest.drain(..excess);
+                self.bytes -= excess;
+            } else {
+                self.bytes -= oldest.len();
+                self.chunks.pop_front();
+            }
+            self.truncated = true;
+        }
+    }
+
+    /// Bytes of output held
+    pub(super) fn len(&self) -> usize {
+        self.bytes
+    }
+
+    /// Whether older output was dropped
+    pub(super) fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Output in the order it was printed, by agent
+    pub(super) fn into_chunks(self) -> Vec<(Uuid, Vec<u8>)> {
+        self.chunks.into()
+    }
+}
+
+/// What a lost connection leaves to be resumed
+#[derive(Debug)]
+pub(super) struct SuspendedSession {
+    /// The lost connection, which still holds its input control
+    pub(super) connection_id: Uuid,
+    pub(super) identity: Identity,
+    /// Key the connection's agents are owned by
+    pub(super) owner: String,
+    /// Agents whose output the connection received (every agent when `None`)
+    pub(super) attached: Option<HashSet<Uuid>>,
+    pub(super) stream_mode: StreamMode,
+    pub(super) backlog: Backlog,
+}
+
+impl SuspendedSession {
+    fn receives_output(&self, agent_id: Uuid) -> bool {
+        self.stream_mode != StreamMode::Summary
+            && self
+                .attached
+                .as_ref()
+                .is_none_or(|attached| attached.contains(&agent_id))
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// The session's connection is open
+    Live {
+        identity: Identity,
+        takeover: Arc<Notify>,
+    },
+    /// The connection was lost and the session awaits resumption
+    Suspended(SuspendedSession),
+}
+
+/// Reasons a session cannot be resumed
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(super) enum ResumeError {
+    #[error("Unknown or expired resume token")]
+    Expired,
+
+    #[error("The session belongs to another role or token")]
+    WrongIdentity,
+
+    #[error("The session's connection did not close in time")]
+    TakeoverTimedOut,
+}
+
+/// Sessions that can be resumed, by resume token
+#[derive(Debug, Default)]
+pub(super) struct ResumeRegistry {
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Notified whenever a session is suspended
+    suspended: Notify,
+}
+
+impl ResumeRegistry {
+    /// Issue a token for a new connection
+    pub(super) fn register(&self, identity: Identity, grace: Duration) -> ResumeTicket {
+        let ticket = ResumeTicket {
+            token: Uuid::new_v4().simple().to_string(),
+            grace,
+            takeover: Arc::new(Notify::new()),
+        };
+        self.lock().insert(
+            ticket.token.clone(),
+            Entry::Live {
+                identity,
+                takeover: Arc::clone(&ticket.takeover),
+            },
+        );
+        ticket
+    }
+
+    /// Forget a session whose connection was closed for good
+    pub(super) fn remove(&self, token: &str) {
+        self.lock().remove(token);
+    }
+
+    /// Take over a suspended session, closing its connection first if it is
+    /// still open
+    ///
+    /// On success the token belongs to the resuming connection, `ticket`,
+    /// from then on.
+    pub(super) async fn resume(
+        &self,
+        token: &str,
+        identity: &Identity,
+        ticket: &ResumeTicket,
+    ) -> Result<SuspendedSession, ResumeError> {
+        let deadline = tokio::time::Instant::now() + TAKEOVER_TIMEOUT;
+        loop {
+            let suspended = self.suspended.notified();
+            tokio::pin!(suspended);
+            suspended.as_mut().enable();
+            {
+                let mut entries = self.lock();
+                let entry = entries.remove(token).ok_or(ResumeError::Expired)?;
+                let owner = match &entry {
+                    Entry::Live { identity, .. } => identity,
+                    Entry::Suspended(session) => &session.identity,
+                };
+                if owner != identity {
+                    entries.insert(token.to_string(), entry);
+                    return Err(ResumeError::WrongIdentity);
+                }
+                match entry {
+                    Entry::Live { identity, takeover } => {
+                        takeover.notify_one();
+                        entries.insert(token.to_string(), Entry::Live { identity, takeover });
+                    }
+                    Entry::Suspended(session) => {
+                        let live = Entry::Live {
+                            identity: identity.clone(),
+                            takeover: Arc::clone(&ticket.takeover),
+                        };
+                        entries.insert(token.to_string(), live);
+                        return Ok(session);
+                    }
+                }
+            }
+            if tokio::time::timeout_at(deadline, suspended).await.is_err() {
+                return Err(ResumeError::TakeoverTimedOut);
+            }
+        }
+    }
+
+    fn suspend(&self, token: String, session: SuspendedSession) {
+        self.lock().insert(token, Entry::Suspended(session));
+        self.suspended.notify_waiters();
+    }
+
+    /// Add output to a session suspended by `connection_id`, returning
+    /// whether it is still suspended
+    fn record_output(&self, token: &str, connection_id: Uuid, agent_id: Uuid, data: &[u8]) -> bool {
+        match self.lock().get_mut(token) {
+            Some(Entry::Suspended(session)) if session.connection_id == connection_id => {
+                if session.receives_output(agent_id) {
+                    session.backlog.push(agent_id, data);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Note that output was lost, returning whether the session is still
+    /// suspended
+    fn record_loss(&self, token: &str, connection_id: Uuid) -> bool {
+        match self.lock().get_mut(token) {
+            Some(Entry::Suspended(session)) if session.connection_id == connection_id => {
+                session.backlog.truncated = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drop a session that was not resumed in time, returning whether it was
+    /// still waiting
+    fn expire(&self, token: &str, connection_id: Uuid) -> bool {
+        let mut entries = self.lock();
+        match entries.get(token) {
+            Some(Entry::Suspended(session)) if session.connection_id == connection_id => {
+                entries.remove(token);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Keep a lost connection's session for its grace period, collecting the
+/// output it misses, then release its input control unless it was resumed
+pub(super) fn suspend(state: &Arc<ServerState>, ticket: &ResumeTicket, session: SuspendedSession) {
+    let mut events = state.agent_manager.subscribe();
+    let connection_id = session.connection_id;
+    let token = ticket.token.clone();
+    state.resumable.suspend(token.clone(), session);
+
+    let state = Arc::clone(state);
+    let expiry = tokio::time::sleep(ticket.grace);
+    tokio::spawn(async move {
+        tokio::pin!(expiry);
+        let mut receiving = true;
+        loop {
+            tokio::select! {
+                _ = &mut expiry => {
+                    if state.resumable.expire(&token, connection_id) {
+                        info!("Session of connection {} was not resumed in time", connection_id);
+                        state.input_control.release_all(connection_id);
+                    }
+                    break;
+                }
+                event = events.recv(), if receiving => {
+                    let suspended = match event {
+                        Ok(AgentEvent::Output { agent_id, data }) => state
+                            .resumable
+                            .record_output(&token, connection_id, agent_id, &data),
+                        Ok(_) => true,
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            state.resumable.record_loss(&token, connection_id)
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            receiving = false;
+                            true
+                        }
+                    };
+                    // Resumed: the new connection holds the control now
+                    if !suspended {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(role: Role) -> Identity {
+        Identity {
+            role,
+            token_name: None,
+        }
+    }
+
+    fn session(connection_id: Uuid) -> SuspendedSession {
+        SuspendedSession {
+            connection_id,
+            identity: identity(Role::Operator),
+            owner: connection_id.to_string(),
+            attached: Some(HashSet::new()),
+            stream_mode: StreamMode::Terminal,
+            backlog: Backlog::default(),
+        }
+    }
+
+    #[test]
+    fn test_backlog_keeps_latest_output() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut backlog = Backlog::default();
+        backlog.push(a, b"one ");
+        backlog.push(a, b"two ");
+        backlog.push(b, b"three");
+        assert_eq!(backlog.len(), 13);
+        assert!(!backlog.truncated());
+
+        backlog.push(b, &vec![b'x'; MAX_BACKLOG_BYTES - 9]);
+        assert_eq!(backlog.len(), MAX_BACKLOG_BYTES);
+        assert!(backlog.truncated());
+        let chunks = backlog.into_chunks();
+        assert_eq!(chunks[0], (a, b"two ".to_vec()));
+        assert_eq!(chunks[1].0, b);
+        assert!(chunks[1].1.starts_with(b"three"));
+    }
+
+    #[tokio::test]
+    async fn test_resume_suspended_session() {
+        let registry = ResumeRegistry::default();
+        let old = registry.register(identity(Role::Operator), DEFAULT_RESUME_GRACE);
+        let connection_id = Uuid::new_v4();
+        registry.suspend(old.token.clone(), session(connection_id));
+        let agent_id = Uuid::new_v4();
+        assert!(registry.record_output(&old.token, connection_id, agent_id, b"ignored"));
+
+        let new = registry.register(identity(Role::Observer), DEFAULT_RESUME_GRACE);
+        let wrong = registry.resume(&old.token, &identity(Role::Observer), &new).await;
+        assert_eq!(wrong.unwrap_err(), ResumeError::WrongIdentity);
+
+        let resumed = registry
+            .resume(&old.token, &identity(Role::Operator), &new)
+            .await
+            .unwrap();
+        assert_eq!(resumed.connection_id, connection_id);
+        // Output of agents the connection wasn't attached to is not kept
+        assert_eq!(resumed.backlog.len(), 0);
+        // The token now belongs to the resuming connection
+        assert!(!registry.record_output(&old.token, connection_id, agent_id, b"late"));
+        assert!(!registry.expire(&old.token, connection_id));
+
+        registry.remove(&old.token);
+        let expired = registry.resume(&old.token, &identity(Role::Operator), &new).await;
+        assert_eq!(expired.unwrap_err(), ResumeError::Expired);
+    }
+
+    #[tokio::test]
+    async fn test_takeover_of_live_connection() {
+        let registry = Arc::new(ResumeRegistry::default());
+        let old = registry.register(identity(Role::Admin), DEFAULT_RESUME_GRACE);
+        let connection_id = Uuid::new_v4();
+
+        // The old connection suspends its session when told it is taken over
+        let takeover = {
+            let registry = Arc::clone(&registry);
+            let old = old.clone();
+            tokio::spawn(async move {
+                old.takeover.notified().await;
+                let mut session = session(connection_id);
+                session.identity = identity(Role::Admin);
+                registry.suspend(old.token.clone(), session);
+            })
+        };
+
+        let new = registry.register(identity(Role::Admin), DEFAULT_RESUME_GRACE);
+        let resumed = registry
+            .resume(&old.token, &identity(Role::Admin), &new)
+            .await
+            .unwrap();
+        assert_eq!(resumed.connection_id, connection_id);
+        takeover.await.unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+//! Scheduled spawns
+//!
+//! Spawns the agents of the schedules in the bridge configuration when their
+//! cron expressions come due, and tells every client with `schedule_fired`.
+//! Clients can list the schedules and switch them on and off; the switch
+//! lasts until the bridge restarts. Runs missed while the bridge was down
+//! are not made up for.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+use tokio::sync::{broadcast, Notify};
+use tracing::info;
+use uuid::Uuid;
+
+use super::protocol::{ClientMessage, ScheduleInfo, ServerMessage};
+use super::websocket::{handle_client_message, ServerState};
+use crate::config::ScheduleConfig;
+
+/// Longest the scheduler sleeps, so that changes of the system clock are
+/// noticed
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Reasons a schedule operation is refused
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(super) enum ScheduleError {
+    #[error("No schedule named {0}")]
+    NotFound(String),
+}
+
+/// A schedule and when it runs
+#[derive(Debug)]
+struct Schedule {
+    config: ScheduleConfig,
+    enabled: bool,
+    /// Next run, in seconds since the Unix epoch
+    next_run: Option<u64>,
+    last_run: Option<u64>,
+    last_agent_id: Option<Uuid>,
+}
+
+impl Schedule {
+    fn info(&self) -> ScheduleInfo {
+        ScheduleInfo {
+            name: self.config.name.clone(),
+            cron: self.config.cron.to_string(),
+            project_path: self.config.project_path.clone(),
+            preset: self.config.preset.clone(),
+            enabled: self.enabled,
+            next_run_ms: self.next_run.filter(|_| self.enabled).map(|secs| secs * 1000),
+            last_run_ms: self.last_run.map(|secs| secs * 1000),
+            last_agent_id: self.last_agent_id,
+        }
+    }
+}
+
+/// The server's schedules
+pub(super) struct Schedules {
+    schedules: Mutex<Vec<Schedule>>,
+    events: broadcast::Sender<ServerMessage>,
+    /// Notified when a schedule was switched on or off
+    wake: Notify,
+}
+
+impl Schedules {
+    /// Schedules from the configuration, their next runs counted from `now`
+    pub(super) fn new(configs: Vec<ScheduleConfig>, now: u64) -> Self {
+        let (events, _) = broadcast::channel(64);
+        let schedules = configs
+            .into_iter()
+            .map(|config| Schedule {
+                enabled: config.enabled,
+                next_run: config.cron.next_after(now),
+                last_run: None,
+                last_agent_id: None,
+                config,
+            })
+            .collect();
+        Self {
+            schedules: Mutex::new(schedules),
+            events,
+            wake: Notify::new(),
+        }
+    }
+
+    /// Subscribe to schedules spawning agents
+    pub(super) fn subscribe(&self) -> broadcast::Receiver<ServerMessage> {
+        self.events.subscribe()
+    }
+
+    /// Every schedule, in the order of the configuration
+    pub(super) fn list(&self) -> Vec<ScheduleInfo> {
+        let schedules = self.schedules.lock().unwrap_or_else(|e| e.into_inner());
+        schedules.iter().map(Schedule::info).collect()
+    }
+
+    /// Switch a schedule on or off; switched on, it next runs at its first
+    /// time after `now`
+    pub(super) fn enable(
+        &self,
+        name: &str,
+        enabled: bool,
+        now: u64,
+    ) -> Result<ScheduleInfo, ScheduleError> {
+        let mut schedules = self.schedules.lock().unwrap_or_else(|e| e.into_inner());
+        let schedule = schedules
+            .iter_mut()
+            .find(|s| s.config.name == name)
+            .ok_or_else(|| ScheduleError::NotFound(name.to_string()))?;
+        if enabled && !schedule.enabled {
+            schedule.next_run = schedule.config.cron.next_after(now);
+        }
+        schedule.enabled = enabled;
+        let info = schedule.info();
+        drop(schedules);
+        self.wake.notify_one();
+        Ok(info)
+    }
+
+    /// When the next enabled schedule is due
+    fn next_due(&self) -> Option<u64> {
+        let schedules = self.schedules.lock().unwrap_or_else(|e| e.into_inner());
+        schedules
+            .iter()
+            .filter(|s| s.enabled)
+            .filter_map(|s| s.next_run)
+            .min()
+    }
+
+    /// Mark the enabled schedules due at `now` as run and return them
+    fn take_due(&self, now: u64) -> Vec<ScheduleConfig> {
+        let mut schedules = self.schedules.lock().unwrap_or_else(|e| e.into_inner());
+        let mut due = Vec::new();
+        for schedule in schedules.iter_mut() {
+            if schedule.enabled && schedule.next_run.is_some_and(|next| next <= now) {
+                schedule.last_run = Some(now);
+                schedule.next_run = schedule.config.cron.next_after(now);
+                due.push(schedule.config.clone());
+            }
+        }
+        due
+    }
+
+    /// Note the agent a schedule's last run spawned
+    fn spawned(&self, name: &str, agent_id: Uuid) {
+        let mut schedules = self.schedules.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(schedule) = schedules.iter_mut().find(|s| s.config.name == name) {
+            schedule.last_agent_id = Some(agent_id);
+        }
+    }
+}
+
+/// Spawn the agents of schedules as they come due, until shutdown
+pub(super) async fn run_schedules(state: Arc<ServerState>, shutdown_tx: broadcast::Sender<()>) {
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    loop {
+        let now = now_secs();
+        for schedule in state.schedules.take_due(now) {
+            tokio::spawn(fire(Arc::clone(&state), schedule));
+        }
+        let wait = state.schedules.next_due().map_or(MAX_SLEEP, |next| {
+            Duration::from_secs(next.saturating_sub(now)).min(MAX_SLEEP)
+        });
+        tokio::select! {
+            _ = shutdown_rx.recv() => break,
+            _ = state.schedules.wake.notified() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+/// Spawn a schedule's agent and tell clients how it went
+pub(super) async fn fire(state: Arc<ServerState>, schedule: ScheduleConfig) {
+    info!(
+        "Schedule {} spawning an agent in {}",
+        schedule.name, schedule.project_path
+    );
+    let spawn = ClientMessage::SpawnAgent {
+        project_path: schedule.project_path.clone(),
+        preset: schedule.preset.clone(),
+        cols: None,
+        rows: None,
+        tags: Vec::new(),
+        group: None,
+        name: Some(schedule.name.clone()),
+        mode: None,
+        prompt: schedule.prompt.clone(),
+        env: BTreeMap::new(),
+        cwd: None,
+        report_echo: false,
+    };
+    let client = format!("schedule {}", schedule.name);
+    let response = match spawn.validate() {
+        Ok(()) => handle_client_message(spawn, &state, Some(&client), None)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let (agent_id, error) = match response {
+        Ok(Some(ServerMessage::AgentSpawned { agent_id, .. })) => (Some(agent_id), None),
+        Ok(Some(ServerMessage::Error { message, .. })) | Err(message) => (None, Some(message)),
+        Ok(_) => (None, Some("The agent could not be spawned".to_string())),
+    };
+    match (agent_id, &error) {
+        (Some(agent_id), _) => state.schedules.spawned(&schedule.name, agent_id),
+        (None, Some(error)) => info!("Schedule {} failed: {}", schedule.name, error),
+        (None, None) => {}
+    }
+    let _ = state.schedules.events.send(ServerMessage::ScheduleFired {
+        schedule: schedule.name,
+        project_path: schedule.project_path,
+        agent_id,
+        error,
+    });
+}
+
+/// Seconds since the Unix epoch
+pub(super) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CronSchedule;
+
+    fn schedule(name: &str, cron: &str) -> ScheduleConfig {
+        ScheduleConfig {
+            name: name.to_string(),
+            cron: CronSchedule::parse(cron).unwrap(),
+            project_path: "/srv/app".to_string(),
+            preset: None,
+            prompt: None,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_take_due() {
+        let schedules = Schedules::new(
+            vec![schedule("hourly", "@hourly"), schedule("daily", "@daily")],
+            0,
+        );
+        assert_eq!(schedules.next_due(), Some(3600));
+        assert!(schedules.take_due(3599).is_empty());
+
+        let due = schedules.take_due(3600);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].name, "hourly");
+        assert!(schedules.take_due(3600).is_empty());
+        let hourly = &schedules.list()[0];
+        assert_eq!(hourly.last_run_ms, Some(3_600_000));
+        assert_eq!(hourly.next_run_ms, Some(7_200_000));
+    }
+
+    #[test]
+    fn test_enable() {
+        let schedules = Schedules::new(vec![schedule("hourly", "@hourly")], 0);
+        let info = schedules.enable("hourly", false, 0).unwrap();
+        assert!(!info.enabled);
+        assert_eq!(info.next_run_ms, None);
+        assert_eq!(schedules.next_due(), None);
+        assert!(schedules.take_due(3600).is_empty());
+
+        // Switched back on, it doesn't make up for the runs it missed
+        let info = schedules.enable("hourly", true, 5000).unwrap();
+        assert_eq!(info.next_run_ms, Some(7_200_000));
+        assert_eq!(
+            schedules.enable("nightly", true, 0),
+            Err(ScheduleError::NotFound("nightly".to_string()))
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Standard input/output transport
+//!
+//! With `--stdio` the bridge serves a single client, the process that
+//! launched it, over its standard input and output instead of listening on a
+//! port. Messages are the usual JSON, one per line. The session ends when
+//! the client closes the bridge's standard input, and the bridge with it, so
+//! an embedding client needn't pick a free port or find a running bridge.
+//! Log messages go to standard error in this mode, keeping standard output
+//! for the protocol.
+
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+use tracing::info;
+
+use super::transport::{LineReceiver, LineSender};
+use super::websocket::{serve_client, ServerState, SessionOptions};
+
+/// Client address the stdio session is logged and audited under
+const STDIO_CLIENT: &str = "stdio";
+
+/// Serve one session over a reader and writer until the client closes the
+/// reader or the server shuts down
+pub(super) async fn serve_stdio<R, W>(
+    reader: R,
+    writer: W,
+    state: Arc<ServerState>,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    info!("Serving a client over standard input and output");
+    let result = serve_client(
+        LineSender::new(writer),
+        LineReceiver::new(reader),
+        STDIO_CLIENT.to_string(),
+        state,
+        SessionOptions::default(),
+        shutdown_rx,
+    )
+    .await;
+    info!("Stdio session ended");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::federation::Federation;
+    use crate::server::ServerConfig;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    #[tokio::test]
+    async fn test_stdio_session() {
+        let state = Arc::new(ServerState::new(
+            ServerConfig::new("127.0.0.1".to_string(), 0),
+            Federation::new(),
+        ));
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let (client_in, bridge_in) = tokio::io::duplex(4096);
+        let (bridge_out, client_out) = tokio::io::duplex(4096);
+        let session = tokio::spawn(serve_stdio(
+            bridge_in,
+            bridge_out,
+            state,
+            shutdown_tx.subscribe(),
+        ));
+
+        let mut lines = BufReader::new(client_out).lines();
+        let welcome = lines.next_line().await.unwrap().unwrap();
+        assert!(welcome.contains(r#""type":"welcome""#));
+
+        let mut client_in = client_in;
+        client_in
+            .write_all(b"{\"type\":\"ping\",\"seq\":3}\n")
+            .await
+            .unwrap();
+        let negotiated = lines.next_line().await.unwrap().unwrap();
+        assert!(negotiated.contains(r#""type":"version_negotiated""#));
+        let pong = lines.next_line().await.unwrap().unwrap();
+        assert!(pong.contains(r#""seq":3"#));
+
+        // Closing standard input ends the session
+        drop(client_in);
+        session.await.unwrap().unwrap();
+    }
+}
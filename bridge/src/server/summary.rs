@@ -0,0 +1,202 @@
+//! Plain-language event summaries
+//!
+//! Connections in `summary` stream mode get one short sentence per notable
+//! agent event instead of terminal output, for text-to-speech and
+//! notification clients. Output, resizes and the busy/idle churn of a working
+//! agent are left out.
+
+use std::path::Path;
+
+use uuid::Uuid;
+
+use super::protocol::{AgentInfo, AgentState, ServerMessage};
+use crate::agent::{AgentEvent, StreamEvent};
+
+/// Name an agent is called by in summaries: its display name, else its
+/// preset, else its project directory, else the start of its ID
+pub(super) fn agent_label(agent_id: Uuid, info: Option<&AgentInfo>) -> String {
+    info.and_then(|info| {
+        info.name.clone().or_else(|| info.preset.clone()).or_else(|| {
+            Path::new(&info.project_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+    })
+    .unwrap_or_else(|| agent_id.to_string()[..8].to_string())
+}
+
+/// Summarize an event about the agent called `label`, if it is worth telling
+pub(super) fn summarize(event: &AgentEvent, label: &str) -> Option<String> {
+    let text = match event {
+        AgentEvent::Spawned { project_path, .. } => {
+            format!("Agent {} started in {}", label, project_path)
+        }
+        AgentEvent::Exited {
+            exit_code,
+            signal,
+            stats,
+            ..
+        } => {
+            let seconds = stats.duration_ms / 1000;
+            match (exit_code, signal) {
+                (Some(0), _) => format!("Agent {} finished after {} seconds", label, seconds),
+                (Some(code), _) => format!(
+                    "Agent {} exited with code {} after {} seconds",
+                    label, code, seconds
+                ),
+                (None, Some(signal)) => format!(
+                    "Agent {} was terminated by signal {} after {} seconds",
+                    label, signal, seconds
+                ),
+                (None, None) => format!("Agent {} stopped after {} seconds", label, seconds),
+            }
+        }
+        AgentEvent::Restarted { .. } => format!("Agent {} restarted", label),
+        AgentEvent::IdleWarning { stop_in_ms, .. } => format!(
+            "Agent {} is idle and will be stopped in {}s",
+            label,
+            stop_in_ms / 1000
+        ),
+        AgentEvent::ConfirmationRequested { question, .. } => {
+            format!("Agent {} is asking: {}", label, question)
+        }
+        AgentEvent::ApprovalRequested { tool, detail, .. } => {
+            let detail = detail.lines().next().unwrap_or_default();
+            format!("Agent {} wants permission for {}: {}", label, tool, detail)
+        }
+        AgentEvent::StateChanged {
+            old_state: AgentState::Busy,
+            new_state: AgentState::Idle,
+            ..
+        } => format!("Agent {} is done working", label),
+        AgentEvent::StateChanged {
+            new_state: AgentState::Paused,
+            ..
+        } => format!("Agent {} is paused", label),
+        AgentEvent::StateChanged {
+            new_state: AgentState::Ready,
+            ..
+        } => format!("Agent {} is ready for a command", label),
+        AgentEvent::CommandFinished { exit_code, .. } => match exit_code {
+            Some(0) | None => format!("Agent {} finished a command", label),
+            Some(code) => format!("Agent {} had a command fail with code {}", label, code),
+        },
+        AgentEvent::Stream {
+            event: StreamEvent::ToolUse { name, .. },
+            ..
+        } => format!("Agent {} is using {}", label, name),
+        AgentEvent::Stream {
+            event: StreamEvent::Cost { total_cost_usd, .. },
+            ..
+        } => format!("Agent {} finished, costing ${:.2}", label, total_cost_usd),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// The `event_summary` message for an event, if it is worth telling
+pub(super) fn summary_message(
+    event: &AgentEvent,
+    info: Option<&AgentInfo>,
+) -> Option<ServerMessage> {
+    let agent_id = event.agent_id();
+    let label = match event {
+        // Exited agents are gone from the registry, but the event names the preset
+        AgentEvent::Exited {
+            preset: Some(preset),
+            ..
+        } if info.is_none() => preset.clone(),
+        _ => agent_label(agent_id, info),
+    };
+    Some(ServerMessage::EventSummary {
+        agent_id,
+        text: summarize(event, &label)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::protocol::{AgentExitReason, RunStats};
+
+    #[test]
+    fn test_summaries() {
+        let agent_id = Uuid::new_v4();
+        let info = AgentInfo {
+            agent_id,
+            project_path: "/home/dev/webapp".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(agent_label(agent_id, Some(&info)), "webapp");
+        assert_eq!(agent_label(agent_id, None), agent_id.to_string()[..8]);
+
+        let exited = AgentEvent::Exited {
+            agent_id,
+            exit_code: Some(3),
+            signal: None,
+            reason: AgentExitReason::Normal,
+            preset: Some("reviewer".to_string()),
+            stats: RunStats {
+                duration_ms: 42_000,
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            summary_message(&exited, None),
+            Some(ServerMessage::EventSummary {
+                agent_id,
+                text: "Agent reviewer exited with code 3 after 42 seconds".to_string()
+            })
+        );
+
+        let question = AgentEvent::ConfirmationRequested {
+            agent_id,
+            question: "Apply this edit?".to_string(),
+            options: vec!["Yes".to_string(), "No".to_string()],
+        };
+        assert_eq!(
+            summarize(&question, "webapp").as_deref(),
+            Some("Agent webapp is asking: Apply this edit?")
+        );
+
+        let approval = AgentEvent::ApprovalRequested {
+            agent_id,
+            tool: "Bash command".to_string(),
+            detail: "cargo test\nRun the tests".to_string(),
+            question: "Do you want to proceed?".to_string(),
+            options: vec!["Yes".to_string(), "No".to_string()],
+            can_always: false,
+        };
+        assert_eq!(
+            summarize(&approval, "webapp").as_deref(),
+            Some("Agent webapp wants permission for Bash command: cargo test")
+        );
+
+        let tool_use = AgentEvent::Stream {
+            agent_id,
+            event: StreamEvent::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "Bash".to_string(),
+                input: serde_json::json!({"command": "cargo test"}),
+            },
+        };
+        assert_eq!(
+            summarize(&tool_use, "webapp").as_deref(),
+            Some("Agent webapp is using Bash")
+        );
+
+        // Output and busy churn are left out
+        let output = AgentEvent::Output {
+            agent_id,
+            data: b"hello".to_vec(),
+        };
+        assert_eq!(summarize(&output, "webapp"), None);
+        let busy = AgentEvent::StateChanged {
+            agent_id,
+            old_state: AgentState::Idle,
+            new_state: AgentState::Busy,
+            timestamp_ms: 0,
+        };
+        assert_eq!(summarize(&busy, "webapp"), None);
+    }
+}
@@ -0,0 +1,456 @@
+//! Task queue
+//!
+//! Clients can queue prompts for their projects and put the headset down:
+//! each task is run by a `stream_json` agent of its own, and clients are sent
+//! `task_started` when it begins and `task_completed`, with what the
+//! assistant wrote, once the agent exits. Tasks of one project run one at a
+//! time in the order they were queued, so two agents never edit the same
+//! tree at once; tasks of different projects run side by side, up to a
+//! limit.
+//!
+//! In cluster mode the queue also holds the tasks of the other nodes, as
+//! published in their heartbeats, so every node can list the whole cluster's
+//! tasks. Those are only shown; each node runs its own.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+use tokio::sync::{broadcast, Notify};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use super::protocol::{AgentMode, ClientMessage, ServerMessage, TaskInfo, TaskStatus};
+use super::websocket::{handle_client_message, ServerState};
+use crate::agent::{AgentEvent, StreamEvent};
+
+/// Default number of tasks run at the same time
+pub const DEFAULT_MAX_RUNNING_TASKS: usize = 2;
+
+/// Most tasks waiting in the queue
+const MAX_QUEUED_TASKS: usize = 256;
+
+/// Most assistant output kept for `task_completed`; the start of longer
+/// output is dropped
+const MAX_TASK_OUTPUT: usize = 64 * 1024;
+
+/// Reasons a queue operation is refused
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(super) enum TaskError {
+    #[error("The task queue is full ({0} tasks)")]
+    QueueFull(usize),
+
+    #[error("No queued task {0}")]
+    NotFound(Uuid),
+}
+
+/// A task and who queued it
+#[derive(Debug, Clone)]
+struct Task {
+    info: TaskInfo,
+    /// Client that queued the task, which its agent is spawned for
+    client: Option<String>,
+}
+
+/// Queued and running tasks
+pub(super) struct TaskQueue {
+    max_running: usize,
+    tasks: Mutex<VecDeque<Task>>,
+    /// Tasks of other cluster nodes, by node ID
+    remote: Mutex<BTreeMap<String, Vec<TaskInfo>>>,
+    events: broadcast::Sender<ServerMessage>,
+    /// Notified when a task may be able to start
+    wake: Notify,
+}
+
+impl TaskQueue {
+    /// Run at most `max_running` tasks at the same time
+    pub(super) fn new(max_running: usize) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            max_running: max_running.max(1),
+            tasks: Mutex::new(VecDeque::new()),
+            remote: Mutex::new(BTreeMap::new()),
+            events,
+            wake: Notify::new(),
+        }
+    }
+
+    /// Subscribe to tasks starting and completing
+    pub(super) fn subscribe(&self) -> broadcast::Receiver<ServerMessage> {
+        self.events.subscribe()
+    }
+
+    /// Queue a task, returning its ID and the number of tasks ahead of it
+    pub(super) fn enqueue(
+        &self,
+        project_path: String,
+        prompt: String,
+        preset: Option<String>,
+        client: Option<String>,
+    ) -> Result<(Uuid, usize), TaskError> {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let queued = tasks
+            .iter()
+            .filter(|t| t.info.status == TaskStatus::Queued)
+            .count();
+        if queued >= MAX_QUEUED_TASKS {
+            return Err(TaskError::QueueFull(MAX_QUEUED_TASKS));
+        }
+
+        let task_id = Uuid::new_v4();
+        tasks.push_back(Task {
+            info: TaskInfo {
+                task_id,
+                project_path,
+                prompt,
+                preset,
+                status: TaskStatus::Queued,
+                agent_id: None,
+                queued_at_ms: now_ms(),
+                node: None,
+            },
+            client,
+        });
+        drop(tasks);
+        self.wake.notify_one();
+        Ok((task_id, queued))
+    }
+
+    /// Remove a task that hasn't started
+    pub(super) fn cancel(&self, task_id: Uuid) -> Result<(), TaskError> {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let index = tasks
+            .iter()
+            .position(|t| t.info.task_id == task_id && t.info.status == TaskStatus::Queued)
+            .ok_or(TaskError::NotFound(task_id))?;
+        tasks.remove(index);
+        Ok(())
+    }
+
+    /// Running tasks, then queued tasks in the order they will start
+    pub(super) fn list(&self) -> Vec<TaskInfo> {
+        let tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut running, queued): (Vec<_>, Vec<_>) = tasks
+            .iter()
+            .map(|t| t.info.clone())
+            .partition(|info| info.status == TaskStatus::Running);
+        running.extend(queued);
+        running
+    }
+
+    /// This node's tasks followed by those of the other cluster nodes
+    pub(super) fn list_cluster(&self) -> Vec<TaskInfo> {
+        let mut tasks = self.list();
+        let remote = self.remote.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.extend(remote.values().flatten().cloned());
+        tasks
+    }
+
+    /// Replace the tasks known for another cluster node
+    pub(super) fn set_remote(&self, node_id: &str, tasks: Vec<TaskInfo>) {
+        let tasks = tasks
+            .into_iter()
+            .map(|mut info| {
+                info.node = Some(node_id.to_string());
+                info
+            })
+            .collect();
+        self.remote
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(node_id.to_string(), tasks);
+    }
+
+    /// Forget the tasks of a node that left the cluster
+    pub(super) fn remove_remote(&self, node_id: &str) {
+        self.remote
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(node_id);
+    }
+
+    /// Cluster node holding a task queued elsewhere
+    pub(super) fn remote_node(&self, task_id: Uuid) -> Option<String> {
+        let remote = self.remote.lock().unwrap_or_else(|e| e.into_inner());
+        remote
+            .iter()
+            .find(|(_, tasks)| tasks.iter().any(|t| t.task_id == task_id))
+            .map(|(node_id, _)| node_id.clone())
+    }
+
+    /// Mark the tasks that can start now as running and return them: the
+    /// first queued task of each project without a running one, while there
+    /// are free slots
+    fn take_startable(&self) -> Vec<Task> {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut busy: Vec<String> = tasks
+            .iter()
+            .filter(|t| t.info.status == TaskStatus::Running)
+            .map(|t| t.info.project_path.clone())
+            .collect();
+        let mut startable = Vec::new();
+        for task in tasks.iter_mut() {
+            if busy.len() >= self.max_running {
+                break;
+            }
+            if task.info.status == TaskStatus::Queued && !busy.contains(&task.info.project_path) {
+                task.info.status = TaskStatus::Running;
+                busy.push(task.info.project_path.clone());
+                startable.push(task.clone());
+            }
+        }
+        startable
+    }
+
+    /// Note the agent a task runs in
+    fn started(&self, task_id: Uuid, agent_id: Uuid) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(task) = tasks.iter_mut().find(|t| t.info.task_id == task_id) {
+            task.info.agent_id = Some(agent_id);
+            let _ = self.events.send(ServerMessage::TaskStarted {
+                task_id,
+                agent_id,
+                project_path: task.info.project_path.clone(),
+            });
+        }
+    }
+
+    /// Remove a finished task, letting the next ones start
+    fn finished(&self, task_id: Uuid, completed: ServerMessage) {
+        self.tasks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|t| t.info.task_id != task_id);
+        let _ = self.events.send(completed);
+        self.wake.notify_one();
+    }
+}
+
+/// Start queued tasks as projects and slots free up, until shutdown
+pub(super) async fn run_tasks(state: Arc<ServerState>, shutdown_tx: broadcast::Sender<()>) {
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.recv() => break,
+            _ = state.tasks.wake.notified() => {
+                for task in state.tasks.take_startable() {
+                    tokio::spawn(run_task(Arc::clone(&state), task));
+                }
+            }
+        }
+    }
+}
+
+/// What a task's agent reported until it exited
+#[derive(Debug, Default)]
+struct TaskOutcome {
+    output: String,
+    exit_code: Option<i32>,
+    total_cost_usd: Option<f64>,
+}
+
+impl TaskOutcome {
+    /// Keep text the assistant wrote, dropping the oldest beyond the limit
+    fn push_text(&mut self, text: &str) {
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+        self.output.push_str(text);
+        if self.output.len() > MAX_TASK_OUTPUT {
+            let mut cut = self.output.len() - MAX_TASK_OUTPUT;
+            while !self.output.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.output.drain(..cut);
+        }
+    }
+}
+
+/// Run a task in an agent of its own and report how it went
+async fn run_task(state: Arc<ServerState>, task: Task) {
+    let task_id = task.info.task_id;
+    let started = Instant::now();
+    // Subscribed before spawning so that none of the agent's events are missed
+    let mut events = state.agent_manager.subscribe();
+    let spawn = ClientMessage::SpawnAgent {
+        project_path: task.info.project_path.clone(),
+        preset: task.info.preset.clone(),
+        cols: None,
+        rows: None,
+        tags: Vec::new(),
+        group: None,
+        name: None,
+        mode: Some(AgentMode::StreamJson),
+        prompt: Some(task.info.prompt.clone()),
+        env: BTreeMap::new(),
+        cwd: None,
+        report_echo: false,
+    };
+    let response = handle_client_message(spawn, &state, task.client.as_deref(), None).await;
+    let agent_id = match response {
+        Ok(Some(ServerMessage::AgentSpawned { agent_id, .. })) => agent_id,
+        other => {
+            let error = match other {
+                Ok(Some(ServerMessage::Error { message, .. })) => message,
+                Err(e) => e.to_string(),
+                Ok(_) => "The agent could not be spawned".to_string(),
+            };
+            info!("Task {} could not start: {}", task_id, error);
+            state.tasks.finished(
+                task_id,
+                ServerMessage::TaskCompleted {
+                    task_id,
+                    agent_id: None,
+                    success: false,
+                    exit_code: None,
+                    output: String::new(),
+                    error: Some(error),
+                    total_cost_usd: None,
+                    duration_ms: 0,
+                },
+            );
+            return;
+        }
+    };
+    debug!("Task {} running in agent {}", task_id, agent_id);
+    state.tasks.started(task_id, agent_id);
+
+    let mut outcome = TaskOutcome::default();
+    loop {
+        match events.recv().await {
+            Ok(event) if event.agent_id() != agent_id => {}
+            Ok(AgentEvent::Stream { event, .. }) => match event {
+                StreamEvent::AssistantText { text } => outcome.push_text(&text),
+                StreamEvent::Cost { total_cost_usd, .. } => {
+                    outcome.total_cost_usd = Some(total_cost_usd);
+                }
+                StreamEvent::ToolUse { .. } | StreamEvent::ToolResult { .. } => {}
+            },
+            Ok(AgentEvent::Exited { exit_code, .. }) => {
+                outcome.exit_code = exit_code;
+                break;
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+
+    info!(
+        "Task {} finished with exit code {:?}",
+        task_id, outcome.exit_code
+    );
+    state.tasks.finished(
+        task_id,
+        ServerMessage::TaskCompleted {
+            task_id,
+            agent_id: Some(agent_id),
+            success: outcome.exit_code == Some(0),
+            exit_code: outcome.exit_code,
+            output: outcome.output,
+            error: None,
+            total_cost_usd: outcome.total_cost_usd,
+            duration_ms: started.elapsed().as_millis() as u64,
+        },
+    );
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enqueue(queue: &TaskQueue, project: &str) -> Uuid {
+        let prompt = "fix the tests".to_string();
+        queue
+            .enqueue(project.to_string(), prompt, None, None)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_one_task_per_project() {
+        let queue = TaskQueue::new(2);
+        let first = enqueue(&queue, "/srv/app");
+        let second = enqueue(&queue, "/srv/app");
+        let other = enqueue(&queue, "/srv/site");
+        let last = enqueue(&queue, "/srv/docs");
+
+        // The second task of a project waits for the first; the slots are full
+        let started: Vec<_> = queue
+            .take_startable()
+            .iter()
+            .map(|t| t.info.task_id)
+            .collect();
+        assert_eq!(started, vec![first, other]);
+        assert!(queue.take_startable().is_empty());
+        let statuses: Vec<_> = queue.list().iter().map(|t| t.status).collect();
+        assert_eq!(statuses[..2], [TaskStatus::Running, TaskStatus::Running]);
+
+        queue.finished(first, ServerMessage::TaskCancelled { task_id: first });
+        let started: Vec<_> = queue
+            .take_startable()
+            .iter()
+            .map(|t| t.info.task_id)
+            .collect();
+        assert_eq!(started, vec![second]);
+        assert_eq!(queue.list().last().unwrap().task_id, last);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let queue = TaskQueue::new(1);
+        let running = enqueue(&queue, "/srv/app");
+        let queued = enqueue(&queue, "/srv/app");
+        queue.take_startable();
+
+        // Running tasks are stopped by killing their agent instead
+        assert_eq!(queue.cancel(running), Err(TaskError::NotFound(running)));
+        assert_eq!(queue.cancel(queued), Ok(()));
+        assert_eq!(queue.list().len(), 1);
+    }
+
+    #[test]
+    fn test_remote_tasks() {
+        let queue = TaskQueue::new(1);
+        let local = enqueue(&queue, "/srv/app");
+
+        let other = TaskQueue::new(1);
+        let remote = enqueue(&other, "/srv/site");
+        queue.set_remote("b", other.list());
+
+        // Other nodes' tasks are listed after ours, tagged with their node
+        let tasks = queue.list_cluster();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!((tasks[0].task_id, tasks[0].node.as_deref()), (local, None));
+        assert_eq!((tasks[1].task_id, tasks[1].node.as_deref()), (remote, Some("b")));
+        assert_eq!(queue.list().len(), 1);
+        assert_eq!(queue.remote_node(remote).as_deref(), Some("b"));
+        assert_eq!(queue.remote_node(local), None);
+
+        // They are never started here
+        assert_eq!(queue.take_startable().len(), 1);
+        assert!(queue.take_startable().is_empty());
+
+        queue.remove_remote("b");
+        assert_eq!(queue.list_cluster().len(), 1);
+        assert_eq!(queue.remote_node(remote), None);
+    }
+
+    #[test]
+    fn test_output_keeps_its_end() {
+        let mut outcome = TaskOutcome::default();
+        outcome.push_text("first");
+        outcome.push_text(&"é".repeat(MAX_TASK_OUTPUT));
+        assert!(outcome.output.len() <= MAX_TASK_OUTPUT);
+        assert!(outcome.output.ends_with('é'));
+        assert!(!outcome.output.contains("first"));
+    }
+}
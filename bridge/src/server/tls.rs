@@ -0,0 +1,89 @@
+//! TLS for the WebSocket listener
+//!
+//! With a certificate configured, accepted TCP connections are wrapped in
+//! TLS before the WebSocket upgrade, so remote clients can connect over
+//! `wss://` without a reverse proxy in front of the bridge.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+
+/// Give up on clients that don't finish the TLS handshake in this time
+pub(super) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate and key the WebSocket listener serves TLS with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain
+    pub cert_path: PathBuf,
+    /// PEM file with the private key
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Create a new TLS configuration
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Build the acceptor that wraps accepted connections
+    pub(super) fn acceptor(&self) -> anyhow::Result<TlsAcceptor> {
+        let (certs, key) = load_cert_and_key(&self.cert_path, &self.key_path)?;
+        let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+        tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(tls)))
+    }
+}
+
+/// Read a PEM certificate chain and private key
+pub(super) fn load_cert_and_key(
+    cert_path: &Path,
+    key_path: &Path,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert_pem = std::fs::read(cert_path).map_err(|e| {
+        anyhow::anyhow!("Failed to read certificate {}: {}", cert_path.display(), e)
+    })?;
+    let key_pem = std::fs::read(key_path).map_err(|e| {
+        anyhow::anyhow!("Failed to read private key {}: {}", key_path.display(), e)
+    })?;
+
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice()).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", cert_path.display());
+    }
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", key_path.display()))?;
+    Ok((certs, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_without_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+        // A key file given as the certificate holds no certificates
+        let err = match TlsConfig::new(&key_path, &key_path).acceptor() {
+            Ok(_) => panic!("Expected an error"),
+            Err(e) => e,
+        };
+        assert!(err.to_string().contains("No certificates found"));
+    }
+}
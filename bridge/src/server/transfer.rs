@@ -0,0 +1,97 @@
+//! Pushes and pulls
+//!
+//! Transfers with a remote can take a while, so they run on a blocking
+//! thread and report to the connection that asked for them as notices:
+//! `transfer_progress` while objects move, then `branch_pushed` or
+//! `branch_pulled`, or an error.
+
+use std::path::Path;
+
+use tokio::sync::mpsc;
+use tracing::info;
+
+use super::protocol::{ServerMessage, TransferOperation};
+use super::websocket::git_error;
+use crate::git::{
+    open_repository, pull_branch, push_branch, GitError, TransferProgress, DEFAULT_REMOTE,
+};
+
+/// A push or pull requested by a client
+#[derive(Debug)]
+pub(super) struct Transfer {
+    pub operation: TransferOperation,
+    pub project_path: String,
+    pub remote: Option<String>,
+    pub branch: Option<String>,
+    pub force: bool,
+    /// Token for HTTPS remotes, already looked up
+    pub token: Option<String>,
+}
+
+/// Run a transfer, sending its progress and outcome to `tx`
+pub(super) async fn transfer(transfer: Transfer, tx: mpsc::UnboundedSender<ServerMessage>) {
+    let progress_tx = tx.clone();
+    let result = tokio::task::spawn_blocking(move || run(transfer, progress_tx)).await;
+    let msg = match result {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => git_error(e),
+        Err(e) => ServerMessage::error(format!("Transfer failed: {}", e)),
+    };
+    let _ = tx.send(msg);
+}
+
+fn run(
+    transfer: Transfer,
+    tx: mpsc::UnboundedSender<ServerMessage>,
+) -> Result<ServerMessage, GitError> {
+    let Transfer {
+        operation,
+        project_path,
+        remote,
+        branch,
+        force,
+        token,
+    } = transfer;
+    let repo = open_repository(Path::new(&project_path))?;
+    let remote = remote.unwrap_or_else(|| DEFAULT_REMOTE.to_string());
+    let branch = match branch {
+        Some(branch) => branch,
+        None => {
+            let head = repo.head()?;
+            match head.shorthand() {
+                Some(name) if head.is_branch() => name.to_string(),
+                _ => return Err(GitError::BranchNotFound("HEAD".to_string())),
+            }
+        }
+    };
+
+    let report = |progress: TransferProgress| {
+        let _ = tx.send(ServerMessage::TransferProgress {
+            project_path: project_path.clone(),
+            operation,
+            progress,
+        });
+    };
+    match operation {
+        TransferOperation::Push => {
+            push_branch(&repo, &remote, &branch, force, token.as_deref(), report)?;
+            info!("Pushed branch {} to {}", branch, remote);
+            Ok(ServerMessage::BranchPushed {
+                project_path,
+                remote,
+                branch,
+            })
+        }
+        TransferOperation::Pull => {
+            let outcome = pull_branch(&repo, &remote, &branch, token.as_deref(), report)?;
+            info!("Pulled branch {} from {} at {}", branch, remote, outcome.commit_id);
+            Ok(ServerMessage::BranchPulled {
+                project_path,
+                remote,
+                branch,
+                commit_id: outcome.commit_id,
+                updated: outcome.updated,
+            })
+        }
+    }
+}
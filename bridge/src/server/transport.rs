@@ -0,0 +1,243 @@
+//! Client transports
+//!
+//! The bridge protocol is an ordered exchange of JSON text messages, so a
+//! transport only has to carry whole messages in both directions. Session
+//! handling is written against [`TransportSender`] and [`TransportReceiver`],
+//! which lets the same code serve WebSocket clients and byte-stream transports
+//! such as QUIC streams.
+
+use std::future::Future;
+
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tracing::debug;
+
+/// Maximum size of a single newline-delimited message
+///
+/// Comfortably above the largest valid client message (`MAX_INPUT_LENGTH` of
+/// input plus JSON escaping and envelope).
+pub const MAX_LINE_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Sending half of a client transport
+pub trait TransportSender: Send {
+    /// Send a text message
+    fn send_text(&mut self, text: String) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Close the transport, ignoring errors
+    fn close(&mut self) -> impl Future<Output = ()> + Send;
+
+    /// Whether the transport can ping the client to check it is still there
+    fn supports_ping(&self) -> bool {
+        false
+    }
+
+    /// Ping the client; the answer is seen through
+    /// [`TransportReceiver::take_pong`]
+    fn send_ping(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Receiving half of a client transport
+pub trait TransportReceiver: Send {
+    /// Receive the next text message
+    ///
+    /// Returns `None` once the client has closed the transport. Must be
+    /// cancel-safe, as it is polled inside `tokio::select!`.
+    fn recv_text(&mut self) -> impl Future<Output = Option<anyhow::Result<String>>> + Send;
+
+    /// Whether a pong has arrived since the last call
+    fn take_pong(&mut self) -> bool {
+        false
+    }
+}
+
+impl<S> TransportSender for SplitSink<WebSocketStream<S>, Message>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn send_text(&mut self, text: String) -> anyhow::Result<()> {
+        self.send(Message::Text(text)).await?;
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        let _ = self.send(Message::Close(None)).await;
+    }
+
+    fn supports_ping(&self) -> bool {
+        true
+    }
+
+    async fn send_ping(&mut self) -> anyhow::Result<()> {
+        self.send(Message::Ping(Vec::new())).await?;
+        Ok(())
+    }
+}
+
+/// Receives text messages from a WebSocket, noting pongs
+pub struct WebSocketReceiver<S> {
+    stream: SplitStream<WebSocketStream<S>>,
+    pong: bool,
+}
+
+impl<S> WebSocketReceiver<S> {
+    /// Wrap the receiving half of a WebSocket
+    pub fn new(stream: SplitStream<WebSocketStream<S>>) -> Self {
+        Self {
+            stream,
+            pong: false,
+        }
+    }
+}
+
+impl<S> TransportReceiver for WebSocketReceiver<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn recv_text(&mut self) -> Option<anyhow::Result<String>> {
+        // Pings are answered by tungstenite itself; only text carries protocol
+        loop {
+            match self.stream.next().await? {
+                Ok(Message::Text(text)) => return Some(Ok(text)),
+                Ok(Message::Binary(data)) => {
+                    debug!("Ignoring binary WebSocket message ({} bytes)", data.len());
+                }
+                Ok(Message::Pong(_)) => self.pong = true,
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+
+    fn take_pong(&mut self) -> bool {
+        std::mem::take(&mut self.pong)
+    }
+}
+
+/// Sends newline-delimited messages over a byte stream
+pub struct LineSender<W> {
+    writer: W,
+}
+
+impl<W> LineSender<W> {
+    /// Wrap a byte stream writer
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W> TransportSender for LineSender<W>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    async fn send_text(&mut self, mut text: String) -> anyhow::Result<()> {
+        // Serialized JSON never contains a raw newline, so it can delimit
+        text.push('\n');
+        self.writer.write_all(text.as_bytes()).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        let _ = self.writer.shutdown().await;
+    }
+}
+
+/// Receives newline-delimited messages from a byte stream
+pub struct LineReceiver<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R> LineReceiver<R> {
+    /// Wrap a byte stream reader
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Take the next complete line out of the buffer
+    fn next_line(&mut self) -> Option<anyhow::Result<String>> {
+        let pos = self.buffer.iter().position(|&b| b == b'\n')?;
+        let mut line: Vec<u8> = self.buffer.drain(..=pos).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some(String::from_utf8(line).map_err(|_| anyhow::anyhow!("message is not valid UTF-8")))
+    }
+}
+
+impl<R> TransportReceiver for LineReceiver<R>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    async fn recv_text(&mut self) -> Option<anyhow::Result<String>> {
+        let mut chunk = [0u8; 8192];
+        loop {
+            match self.next_line() {
+                // Skip blank keep-alive lines
+                Some(Ok(line)) if line.trim().is_empty() => continue,
+                Some(result) => return Some(result),
+                None => {}
+            }
+            if self.buffer.len() > MAX_LINE_LENGTH {
+                return Some(Err(anyhow::anyhow!("message exceeds {} bytes", MAX_LINE_LENGTH)));
+            }
+            // Reading into a local chunk keeps this cancel-safe: bytes only
+            // enter the buffer once a read has completed
+            match self.reader.read(&mut chunk).await {
+                Ok(0) => return None,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_line_transport_roundtrip() {
+        let (client, server) = tokio::io::duplex(64);
+        let (server_read, _server_write) = tokio::io::split(server);
+        let (_client_read, client_write) = tokio::io::split(client);
+
+        let mut sender = LineSender::new(client_write);
+        let mut receiver = LineReceiver::new(server_read);
+
+        sender.send_text(r#"{"type":"ping","seq":1}"#.to_string()).await.unwrap();
+        sender.send_text(r#"{"type":"list_agents"}"#.to_string()).await.unwrap();
+
+        assert_eq!(receiver.recv_text().await.unwrap().unwrap(), r#"{"type":"ping","seq":1}"#);
+        assert_eq!(receiver.recv_text().await.unwrap().unwrap(), r#"{"type":"list_agents"}"#);
+
+        sender.close().await;
+        drop(sender);
+        assert!(receiver.recv_text().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_line_receiver_handles_crlf_and_blank_lines() {
+        let raw: &[u8] = b"\r\n{\"a\":1}\r\n\n{\"b\":2}\n";
+        let mut receiver = LineReceiver::new(raw);
+        assert_eq!(receiver.recv_text().await.unwrap().unwrap(), r#"{"a":1}"#);
+        assert_eq!(receiver.recv_text().await.unwrap().unwrap(), r#"{"b":2}"#);
+        assert!(receiver.recv_text().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_line_receiver_rejects_invalid_utf8() {
+        let raw: &[u8] = b"\xff\xfe\n";
+        let mut receiver = LineReceiver::new(raw);
+        assert!(receiver.recv_text().await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! File watches
+//!
+//! Connections watch directories in agents' projects. Changes are gathered
+//! for a short moment, so an editor saving a file doesn't flood the client,
+//! and sent to the connection as `file_changed` notices with paths relative
+//! to the project directory.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::protocol::ServerMessage;
+use crate::fs::{FileChange, FileWatcher, FsError, FsResult, Sandbox};
+
+/// Most paths one connection can watch at once
+pub(super) const MAX_WATCHES: usize = 64;
+
+/// How long changes are gathered before they are sent
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// A connection's file watches, keyed by agent and watched path
+#[derive(Debug, Default)]
+pub(super) struct Watches {
+    watches: HashMap<(Uuid, Option<String>), FileWatcher>,
+}
+
+impl Watches {
+    /// Number of watched paths
+    pub fn len(&self) -> usize {
+        self.watches.len()
+    }
+
+    /// Watch a directory below an agent's project, replacing an earlier
+    /// watch of the same path
+    pub fn watch(
+        &mut self,
+        agent_id: Uuid,
+        path: Option<String>,
+        project_path: &str,
+        notice_tx: mpsc::UnboundedSender<ServerMessage>,
+    ) -> FsResult<()> {
+        let path = normalize(path);
+        let (sandbox, project) = Sandbox::project(project_path)?;
+        let dir = match &path {
+            Some(path) => sandbox.resolve(&project.join(path).to_string_lossy())?,
+            None => project.clone(),
+        };
+        if !dir.is_dir() {
+            return Err(FsError::NotADirectory(dir.display().to_string()));
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        let watcher = FileWatcher::new(&dir, tx)?;
+        tokio::spawn(forward(agent_id, project, rx, notice_tx));
+        self.watches.insert((agent_id, path), watcher);
+        Ok(())
+    }
+
+    /// Stop watching a path, or every path of the agent when `path` is
+    /// `None`, returning whether anything was watched
+    pub fn unwatch(&mut self, agent_id: Uuid, path: Option<String>) -> bool {
+        let before = self.watches.len();
+        match normalize(path) {
+            Some(path) => {
+                self.watches.remove(&(agent_id, Some(path)));
+            }
+            None => self.remove_agent(agent_id),
+        }
+        self.watches.len() < before
+    }
+
+    /// Stop every watch of an agent
+    pub fn remove_agent(&mut self, agent_id: Uuid) {
+        self.watches.retain(|(id, _), _| *id != agent_id);
+    }
+}
+
+/// Watched path without trailing slashes, `None` for the project directory
+fn normalize(path: Option<String>) -> Option<String> {
+    let path = path?;
+    let path = path.trim_end_matches('/');
+    (!path.is_empty() && path != ".").then(|| path.to_string())
+}
+
+/// Send changes to the connection until the watcher is dropped
+async fn forward(
+    agent_id: Uuid,
+    project: PathBuf,
+    mut rx: mpsc::UnboundedReceiver<FileChange>,
+    notice_tx: mpsc::UnboundedSender<ServerMessage>,
+) {
+    while let Some(first) = rx.recv().await {
+        tokio::time::sleep(DEBOUNCE).await;
+        let mut seen = HashSet::new();
+        let mut changes = vec![first];
+        while let Ok(change) = rx.try_recv() {
+            changes.push(change);
+        }
+        for change in changes {
+            if !seen.insert(change.clone()) {
+                continue;
+            }
+            let Ok(path) = change.path.strip_prefix(&project) else {
+                continue;
+            };
+            let msg = ServerMessage::FileChanged {
+                agent_id,
+                path: path.display().to_string(),
+                change: change.kind,
+            };
+            if notice_tx.send(msg).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::ChangeKind;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(None), None);
+        assert_eq!(normalize(Some(".".to_string())), None);
+        assert_eq!(normalize(Some("src/".to_string())).as_deref(), Some("src"));
+    }
+
+    #[tokio::test]
+    async fn test_watch_and_unwatch() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        let project_path = dir.path().display().to_string();
+        let agent_id = Uuid::new_v4();
+        let (notice_tx, mut notice_rx) = mpsc::unbounded_channel();
+
+        let mut watches = Watches::default();
+        watches
+            .watch(
+                agent_id,
+                Some("src/".to_string()),
+                &project_path,
+                notice_tx.clone(),
+            )
+            .unwrap();
+        assert!(matches!(
+            watches.watch(
+                agent_id,
+                Some("..".to_string()),
+                &project_path,
+                notice_tx.clone()
+            ),
+            Err(FsError::OutsideRoots(_))
+        ));
+        assert_eq!(watches.len(), 1);
+
+        std::fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        let notice = tokio::time::timeout(Duration::from_secs(5), notice_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let ServerMessage::FileChanged { path, change, .. } = notice else {
+            panic!("Expected file_changed, got {:?}", notice);
+        };
+        assert_eq!(path, "src/lib.rs");
+        assert_eq!(change, ChangeKind::Created);
+
+        assert!(watches.unwatch(agent_id, Some("src".to_string())));
+        assert!(!watches.unwatch(agent_id, None));
+        assert_eq!(watches.len(), 0);
+    }
+}
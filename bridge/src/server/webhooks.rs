@@ -0,0 +1,465 @@
+//! Webhooks
+//!
+//! POSTs agent spawns, restarts, exits and trigger matches as JSON to the
+//! webhooks in the bridge configuration. Each webhook has a queue of its own,
+//! so a slow endpoint only holds up its own events; a full queue drops new
+//! events with a warning. Deliveries that fail are retried a few times and
+//! then given up on.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use thiserror::Error;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::protocol::{AgentInfo, ServerMessage};
+use super::summary::{agent_label, summarize};
+use super::websocket::ServerState;
+use crate::agent::AgentEvent;
+use crate::config::{WebhookConfig, WebhookEvent, WebhookUrl};
+
+/// Events waiting to be sent to a webhook before new ones are dropped
+const QUEUE_SIZE: usize = 256;
+
+/// Give up on a delivery attempt after this long
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Attempts made to deliver an event
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for each further one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest status line read from a webhook's response
+const MAX_STATUS_LINE: u64 = 1024;
+
+/// Reasons a delivery fails
+#[derive(Debug, Error)]
+pub(super) enum WebhookError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid host name {0}")]
+    HostName(String),
+
+    #[error("Invalid HTTP response")]
+    InvalidResponse,
+
+    #[error("HTTP status {0}")]
+    Status(u16),
+
+    #[error("No response within {0:?}")]
+    Timeout(Duration),
+}
+
+/// What a webhook is sent for an event
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct WebhookPayload {
+    pub(super) event: WebhookEvent,
+    pub(super) agent_id: Uuid,
+    /// When the bridge saw the event, in Unix milliseconds
+    pub(super) timestamp_ms: u64,
+    /// The event in a sentence; chat services like Slack show it as the
+    /// message
+    pub(super) text: String,
+    /// The agent, when still known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) agent: Option<AgentInfo>,
+    /// The message clients are sent for the event
+    pub(super) message: ServerMessage,
+}
+
+impl WebhookPayload {
+    /// The payload for an event, if webhooks are sent it
+    pub(super) fn new(event: &AgentEvent, info: Option<&AgentInfo>) -> Option<Self> {
+        let agent_id = event.agent_id();
+        let label = match event {
+            // Exited agents may be gone, but the event names the preset
+            AgentEvent::Exited {
+                preset: Some(preset),
+                ..
+            } if info.is_none() => preset.clone(),
+            _ => agent_label(agent_id, info),
+        };
+        let (kind, message) = match event.clone() {
+            AgentEvent::Spawned {
+                agent_id,
+                project_path,
+                cols,
+                rows,
+            } => (
+                WebhookEvent::AgentSpawned,
+                ServerMessage::AgentSpawned {
+                    agent_id,
+                    project_path,
+                    cols,
+                    rows,
+                },
+            ),
+            AgentEvent::Restarted {
+                agent_id,
+                cols,
+                rows,
+            } => (
+                WebhookEvent::AgentRestarted,
+                ServerMessage::AgentRestarted {
+                    agent_id,
+                    cols,
+                    rows,
+                },
+            ),
+            AgentEvent::Exited {
+                agent_id,
+                exit_code,
+                signal,
+                reason,
+                preset,
+                stats,
+            } => (
+                WebhookEvent::AgentExited,
+                ServerMessage::AgentExited {
+                    agent_id,
+                    exit_code,
+                    signal,
+                    reason: Some(reason),
+                    preset,
+                    stats: Some(stats),
+                },
+            ),
+            AgentEvent::TriggerFired {
+                agent_id,
+                trigger,
+                captures,
+            } => (
+                WebhookEvent::TriggerFired,
+                ServerMessage::TriggerFired {
+                    agent_id,
+                    trigger,
+                    captures,
+                },
+            ),
+            _ => return None,
+        };
+        let text = match event {
+            AgentEvent::TriggerFired { trigger, .. } => {
+                format!("Agent {} fired trigger {}", label, trigger)
+            }
+            _ => summarize(event, &label)?,
+        };
+        Some(Self {
+            event: kind,
+            agent_id,
+            timestamp_ms: now_ms(),
+            text,
+            agent: info.cloned(),
+            message,
+        })
+    }
+
+    /// Whether a webhook wants the payload
+    fn wanted_by(&self, webhook: &WebhookConfig) -> bool {
+        webhook.events.contains(&self.event)
+            && (webhook.projects.is_empty()
+                || self.agent.as_ref().is_some_and(|agent| {
+                    webhook.projects.contains(&agent.project_path)
+                }))
+    }
+}
+
+/// POST agent events to the configured webhooks until shutdown
+pub(super) async fn run_webhooks(state: Arc<ServerState>, shutdown_tx: broadcast::Sender<()>) {
+    if state.config.webhooks.is_empty() {
+        return;
+    }
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    let mut events = state.agent_manager.subscribe();
+    let connector = tls_connector();
+    let queues: Vec<_> = state
+        .config
+        .webhooks
+        .iter()
+        .map(|webhook| {
+            let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+            tokio::spawn(deliver(webhook.clone(), rx, connector.clone()));
+            (webhook, tx)
+        })
+        .collect();
+    info!("Sending agent events to {} webhook(s)", queues.len());
+    // Agents as last seen, for the events that outlive them
+    let mut agents: HashMap<Uuid, AgentInfo> = HashMap::new();
+    loop {
+        let event = tokio::select! {
+            _ = shutdown_rx.recv() => break,
+            event = events.recv() => event,
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Webhooks missed {} agent event(s)", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if !matches!(
+            event,
+            AgentEvent::Spawned { .. }
+                | AgentEvent::Restarted { .. }
+                | AgentEvent::Exited { .. }
+                | AgentEvent::TriggerFired { .. }
+        ) {
+            continue;
+        }
+        let agent_id = event.agent_id();
+        if let Ok(info) = state.agent_manager.get_agent_status(agent_id).await {
+            agents.insert(agent_id, info);
+        }
+        let info = match event {
+            AgentEvent::Exited { .. } => agents.remove(&agent_id),
+            _ => agents.get(&agent_id).cloned(),
+        };
+        let Some(payload) = WebhookPayload::new(&event, info.as_ref()) else {
+            continue;
+        };
+        for (webhook, queue) in &queues {
+            if payload.wanted_by(webhook) && queue.try_send(payload.clone()).is_err() {
+                warn!("Webhook {} is falling behind; dropped an event", webhook.url);
+            }
+        }
+    }
+}
+
+/// Send a webhook its queued events, one at a time
+async fn deliver(
+    webhook: WebhookConfig,
+    mut queue: mpsc::Receiver<WebhookPayload>,
+    connector: TlsConnector,
+) {
+    while let Some(payload) = queue.recv().await {
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to encode webhook event: {}", e);
+                continue;
+            }
+        };
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let sent = tokio::time::timeout(
+                REQUEST_TIMEOUT,
+                post(&webhook.url, &webhook.headers, &body, &connector),
+            )
+            .await
+            .unwrap_or(Err(WebhookError::Timeout(REQUEST_TIMEOUT)));
+            match sent {
+                Ok(()) => {
+                    debug!("Sent {:?} to webhook {}", payload.event, webhook.url);
+                    break;
+                }
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    debug!("Webhook {} failed, retrying: {}", webhook.url, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => warn!(
+                    "Gave up sending {:?} to webhook {}: {}",
+                    payload.event, webhook.url, e
+                ),
+            }
+        }
+    }
+}
+
+/// POST a JSON body to a URL, expecting a 2xx response
+pub(super) async fn post(
+    url: &WebhookUrl,
+    headers: &BTreeMap<String, String>,
+    body: &[u8],
+    connector: &TlsConnector,
+) -> Result<(), WebhookError> {
+    let stream = TcpStream::connect((url.host(), url.port())).await?;
+    let status = if url.tls() {
+        let name = ServerName::try_from(url.host().to_string())
+            .map_err(|_| WebhookError::HostName(url.host().to_string()))?;
+        let stream = connector.connect(name, stream).await?;
+        exchange(stream, url, headers, body).await?
+    } else {
+        exchange(stream, url, headers, body).await?
+    };
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(WebhookError::Status(status))
+    }
+}
+
+/// Write the request and read the response's status code
+async fn exchange<S>(
+    mut stream: S,
+    url: &WebhookUrl,
+    headers: &BTreeMap<String, String>,
+    body: &[u8],
+) -> Result<u16, WebhookError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let host = if url.host().contains(':') {
+        format!("[{}]", url.host())
+    } else {
+        url.host().to_string()
+    };
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: hoc-bridge\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path(),
+        host,
+        url.port(),
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream.take(MAX_STATUS_LINE))
+        .read_line(&mut status_line)
+        .await?;
+    // e.g. `HTTP/1.1 204 No Content`
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .filter(|_| status_line.starts_with("HTTP/"))
+        .and_then(|status| status.parse().ok())
+        .ok_or(WebhookError::InvalidResponse)
+}
+
+/// TLS client trusting the common web root certificates
+fn tls_connector() -> TlsConnector {
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("the ring provider supports the default protocol versions")
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    use crate::server::protocol::{AgentExitReason, RunStats};
+
+    /// Accept one request, answer it with `status` and return it
+    async fn serve_once(listener: &TcpListener, status: &str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = WebhookUrl::parse(&format!("http://127.0.0.1:{}/hook?key=1", port)).unwrap();
+        let headers = BTreeMap::from([("Authorization".to_string(), "Bearer t".to_string())]);
+        let connector = tls_connector();
+
+        let (sent, request) = tokio::join!(
+            post(&url, &headers, b"{\"ok\":true}", &connector),
+            serve_once(&listener, "204 No Content"),
+        );
+        assert!(sent.is_ok());
+        assert!(request.starts_with("POST /hook?key=1 HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nAuthorization: Bearer t\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"ok\":true}"));
+
+        let (sent, _) = tokio::join!(
+            post(&url, &headers, b"{}", &connector),
+            serve_once(&listener, "500 Internal Server Error"),
+        );
+        assert!(matches!(sent, Err(WebhookError::Status(500))));
+    }
+
+    #[test]
+    fn test_payload() {
+        let agent_id = Uuid::new_v4();
+        let exited = AgentEvent::Exited {
+            agent_id,
+            exit_code: Some(0),
+            signal: None,
+            reason: AgentExitReason::Normal,
+            preset: Some("reviewer".to_string()),
+            stats: RunStats {
+                duration_ms: 90_000,
+                ..RunStats::default()
+            },
+        };
+        let payload = WebhookPayload::new(&exited, None).unwrap();
+        assert_eq!(payload.event, WebhookEvent::AgentExited);
+        assert_eq!(payload.text, "Agent reviewer finished after 90 seconds");
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["event"], "agent_exited");
+        assert_eq!(json["message"]["type"], "agent_exited");
+        assert!(json.get("agent").is_none());
+
+        let resized = AgentEvent::Resized {
+            agent_id,
+            cols: 80,
+            rows: 24,
+        };
+        assert!(WebhookPayload::new(&resized, None).is_none());
+
+        // Without the agent, a project filter can't be matched
+        let webhook: WebhookConfig =
+            toml::from_str("url = \"http://localhost\"\nprojects = [\"/srv/app\"]\n").unwrap();
+        assert!(!payload.wanted_by(&webhook));
+        let webhook: WebhookConfig =
+            toml::from_str("url = \"http://localhost\"\nevents = [\"agent_exited\"]\n").unwrap();
+        assert!(payload.wanted_by(&webhook));
+    }
+}
@@ -3,21 +3,90 @@
 //! Provides a WebSocket server that listens on a configurable port and handles
 //! connections from Godot clients.
 
-use std::net::SocketAddr;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use futures_util::{SinkExt, StreamExt};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use regex::Regex;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::broadcast;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{accept_hdr_async, WebSocketStream};
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
+use super::bandwidth::{
+    AdaptiveQuality, ClientRegistry, Registration, COALESCE_INTERVAL, SLOW_SEND,
+};
+use super::audit::{AuditEvent, AuditLog, AuditRecord, DisconnectRecord};
+use super::batch::{OutputBatch, DEFAULT_COALESCE_WINDOW};
+use super::clipboard::{clipboard_message, Clipboards};
+use super::cluster::{ClusterConfig, DirectoryStore};
+use super::control::{ControlError, ControlEvent, ControlRelease, InputControl};
+use super::discovery::{Announcement, Announcer, DiscoveryConfig};
+use super::env_policy::EnvPolicy;
+use super::federation::{Federation, PeerConfig, CLUSTER_NODE_HEADER};
+use super::groups::Groups;
+use super::health;
+use super::rest;
+use super::heartbeat::{Heartbeat, HeartbeatConfig, DEFAULT_HEARTBEAT_INTERVAL};
+use super::http::{peek_request, read_request, HttpResponse};
+use super::input_policy::{InputFilter, InputPolicy};
+use super::origin::OriginPolicy;
+use super::paste::{write_paced, PasteAssembler};
+use super::pipelines::{self, Pipelines};
+use super::preflight;
+use super::quic::QuicConfig;
+use super::quota::{AgentLimits, AgentQuota};
+use super::rate_limit::{ConnectionLimiter, ConnectionLimits};
+use super::reload::{ConfigSource, LiveConfig, ReloadError, ReloadableConfig};
+use super::replay::replay;
+use super::schedules::{self, Schedules};
+use super::resume::{
+    self, Backlog, Identity, ResumeError, ResumeRegistry, ResumeTicket, SuspendedSession,
+    DEFAULT_RESUME_GRACE,
+};
+use super::transfer::{transfer, Transfer};
+use super::summary;
+use super::tasks::{self, TaskQueue, DEFAULT_MAX_RUNNING_TASKS};
+use super::tls::{TlsConfig, HANDSHAKE_TIMEOUT};
+use super::proxy::{path_matches, resolve_client, ForwardedInfo};
+use super::transport::{TransportReceiver, TransportSender, WebSocketReceiver};
+use super::watch::{Watches, MAX_WATCHES};
+use super::webhooks;
 use super::protocol::{
-    ClientEnvelope, ClientMessage, ErrorCode, ServerMessage, DEFAULT_TERMINAL_COLS, DEFAULT_TERMINAL_ROWS,
+    decode_file_content, decode_raw_input, AgentState, BroadcastResult, Capability,
+    ClientEnvelope, ClientMessage, Codec, ErrorCode, GroupInfo, InputHistoryEntry, PresetInfo,
+    PresetSource, QualityTier, Role, ServerMessage, StreamMode, TerminalLimits, TransferOperation,
+    DEFAULT_HISTORY_LIMIT, MIN_PROTOCOL_VERSION,
+};
+use crate::agent::{
+    list_recordings, recording_path, AgentBackend, AgentManager, AgentSpawner, ApprovalDecision,
+    Cast, ManagerError, Redactor, SessionError, SpawnConfig, StreamEvent, DEFAULT_KILL_GRACE,
+};
+use crate::config::{
+    AgentPreset, ProjectConfig, ScheduleConfig, SecretStore, TokenConfig, TranscriptConfig,
+    WebhookConfig,
+};
+use crate::fs::{
+    list_directory, read_file, stat_path, write_file, FsError, Sandbox, DEFAULT_READ_BYTES,
+};
+use crate::git::{
+    add_worktree, apply_patch, checkout_branch, commit_staged, create_branch, delete_branch,
+    diff_worktree, list_branches, list_worktrees, open_repository, remove_worktree, stage_file,
+    stage_paths, unstage_paths, worktree_for, GitError,
+};
+use crate::pty::{
+    bracketed_paste, key_sequence, EchoState, ExternalSession, PtyScript, ScriptedPtyBackend,
+    SshTarget,
 };
-use crate::agent::{AgentManager, SpawnConfig};
-use crate::config::ProjectConfig;
 
 /// Configuration for the WebSocket server
 #[derive(Debug, Clone)]
@@ -26,8 +95,86 @@ pub struct ServerConfig {
     pub bind: String,
     /// Port to listen on
     pub port: u16,
-    /// Optional authentication token
+    /// Optional authentication token, granting the admin role
     pub token: Option<String>,
+    /// Further authentication tokens, each with its own role
+    pub tokens: Vec<TokenConfig>,
+    /// Certificate to serve `wss://` with (plain `ws://` when `None`)
+    pub tls: Option<TlsConfig>,
+    /// Upstream peer bridges whose agents are federated into this one
+    pub peers: Vec<PeerConfig>,
+    /// Relay endpoint to dial out to (reverse-tunnel mode)
+    pub relay_url: Option<String>,
+    /// URL path the WebSocket endpoint is served on (any path when `None`)
+    pub ws_path: Option<String>,
+    /// Reverse proxies whose `X-Forwarded-*` headers are trusted
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Browser pages allowed to connect
+    pub origin_policy: OriginPolicy,
+    /// Port for the read-only web dashboard (disabled when `None`)
+    pub dashboard_port: Option<u16>,
+    /// Experimental QUIC listener (disabled when `None`)
+    pub quic: Option<QuicConfig>,
+    /// Port for the gRPC API (disabled when `None`)
+    pub grpc_port: Option<u16>,
+    /// Cluster membership (standalone when `None`)
+    pub cluster: Option<ClusterConfig>,
+    /// mDNS announcement on the local network (not announced when `None`)
+    pub mdns: Option<DiscoveryConfig>,
+    /// Run local agents in managed tmux sessions that survive restarts
+    pub persistent_sessions: bool,
+    /// Sanitization and rate limiting applied to agent input
+    pub input_policy: InputPolicy,
+    /// Extra patterns redacted from recorded input history
+    pub input_redactions: Vec<Regex>,
+    /// Whether the redaction patterns also apply to streamed agent output
+    pub redact_output: bool,
+    /// Simulate agents in memory instead of running `claude`
+    pub simulate: bool,
+    /// Terminal sizes clients may request, and the default size
+    pub terminal: TerminalLimits,
+    /// Capability groups refused by this server
+    pub disabled_capabilities: Vec<Capability>,
+    /// Caps on running agents, overall and per client
+    pub agent_limits: AgentLimits,
+    /// Where secrets named by presets are looked up
+    pub secrets: SecretStore,
+    /// Environment variables presets and clients may set for agents
+    pub env_policy: EnvPolicy,
+    /// Window agent output is held for so small writes go out as one message
+    pub coalesce_window: Duration,
+    /// Message and input rates each connection is held to
+    pub connection_limits: ConnectionLimits,
+    /// Pings that close WebSocket connections whose client has gone away
+    pub heartbeat: HeartbeatConfig,
+    /// Time a lost connection's session can be resumed in (sessions are not
+    /// kept when `None`)
+    pub resume_grace: Option<Duration>,
+    /// Time a killed agent gets to exit after SIGTERM before it is sent SIGKILL
+    pub kill_grace: Duration,
+    /// Time without input or output after which agents are stopped, unless
+    /// their preset sets its own
+    pub idle_timeout: Option<Duration>,
+    /// Transcript logging of agents' terminals, unless their preset sets its
+    /// own (disabled when `None`)
+    pub transcript: Option<TranscriptConfig>,
+    /// Directories clients may browse and spawn agents in (any project,
+    /// and the local agents' project directories for browsing, when empty)
+    pub allowed_roots: Vec<PathBuf>,
+    /// Where the token, agent limits and allowed roots are reloaded from
+    /// (not reloadable when `None`)
+    pub config_source: Option<ConfigSource>,
+    /// Where connections, spawns, input, kills and configuration changes
+    /// are recorded (not recorded when `None`)
+    pub audit_log: Option<AuditLog>,
+    /// Queued tasks run at the same time
+    pub max_running_tasks: usize,
+    /// Presets available in every project, besides the project's own
+    pub presets: Vec<AgentPreset>,
+    /// Agents spawned on a schedule
+    pub schedules: Vec<ScheduleConfig>,
+    /// URLs agent events are POSTed to
+    pub webhooks: Vec<WebhookConfig>,
 }
 
 impl ServerConfig {
@@ -37,6 +184,42 @@ impl ServerConfig {
             bind,
             port,
             token: None,
+            tokens: Vec::new(),
+            tls: None,
+            peers: Vec::new(),
+            relay_url: None,
+            ws_path: None,
+            trusted_proxies: Vec::new(),
+            origin_policy: OriginPolicy::default(),
+            dashboard_port: None,
+            quic: None,
+            grpc_port: None,
+            cluster: None,
+            mdns: None,
+            persistent_sessions: false,
+            input_policy: InputPolicy::default(),
+            input_redactions: Vec::new(),
+            redact_output: false,
+            simulate: false,
+            terminal: TerminalLimits::default(),
+            disabled_capabilities: Vec::new(),
+            agent_limits: AgentLimits::default(),
+            secrets: SecretStore::default(),
+            env_policy: EnvPolicy::default(),
+            coalesce_window: DEFAULT_COALESCE_WINDOW,
+            connection_limits: ConnectionLimits::default(),
+            heartbeat: HeartbeatConfig::default(),
+            resume_grace: Some(DEFAULT_RESUME_GRACE),
+            kill_grace: DEFAULT_KILL_GRACE,
+            idle_timeout: None,
+            transcript: None,
+            allowed_roots: Vec::new(),
+            config_source: None,
+            audit_log: None,
+            max_running_tasks: DEFAULT_MAX_RUNNING_TASKS,
+            presets: Vec::new(),
+            schedules: Vec::new(),
+            webhooks: Vec::new(),
         }
     }
 
@@ -46,28 +229,631 @@ impl ServerConfig {
         self
     }
 
+    /// Accept these tokens as well, with their roles
+    pub fn with_tokens(mut self, tokens: Vec<TokenConfig>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Serve WebSocket connections over TLS
+    pub fn with_tls(mut self, tls: Option<TlsConfig>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Set the upstream peer bridges
+    pub fn with_peers(mut self, peers: Vec<PeerConfig>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    /// Set the relay endpoint to serve clients through
+    pub fn with_relay(mut self, relay_url: Option<String>) -> Self {
+        self.relay_url = relay_url;
+        self
+    }
+
+    /// Restrict the WebSocket endpoint to a URL path
+    pub fn with_ws_path(mut self, ws_path: Option<String>) -> Self {
+        self.ws_path = ws_path;
+        self
+    }
+
+    /// Set the reverse proxies whose forwarding headers are trusted
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Set which browser pages may connect
+    pub fn with_origin_policy(mut self, policy: OriginPolicy) -> Self {
+        self.origin_policy = policy;
+        self
+    }
+
+    /// Serve the web dashboard on the given port
+    pub fn with_dashboard_port(mut self, dashboard_port: Option<u16>) -> Self {
+        self.dashboard_port = dashboard_port;
+        self
+    }
+
+    /// Serve the protocol over QUIC as well
+    pub fn with_quic(mut self, quic: Option<QuicConfig>) -> Self {
+        self.quic = quic;
+        self
+    }
+
+    /// Serve the gRPC API on the given port
+    pub fn with_grpc_port(mut self, grpc_port: Option<u16>) -> Self {
+        self.grpc_port = grpc_port;
+        self
+    }
+
+    /// Join a cluster of bridge instances sharing agent state
+    pub fn with_cluster(mut self, cluster: Option<ClusterConfig>) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
+    /// Announce the bridge on the local network over mDNS
+    pub fn with_mdns(mut self, mdns: Option<DiscoveryConfig>) -> Self {
+        self.mdns = mdns;
+        self
+    }
+
+    /// Run local agents in managed tmux sessions that survive bridge restarts
+    pub fn with_persistent_sessions(mut self, persistent: bool) -> Self {
+        self.persistent_sessions = persistent;
+        self
+    }
+
+    /// Set the policy applied to agent input
+    pub fn with_input_policy(mut self, policy: InputPolicy) -> Self {
+        self.input_policy = policy;
+        self
+    }
+
+    /// Redact these patterns from recorded input history, in addition to the built-in ones
+    pub fn with_input_redactions(mut self, redactions: Vec<Regex>) -> Self {
+        self.input_redactions = redactions;
+        self
+    }
+
+    /// Redact secrets from the output streamed to clients as well as from history
+    pub fn with_output_redaction(mut self, enabled: bool) -> Self {
+        self.redact_output = enabled;
+        self
+    }
+
+    /// Simulate agents that echo their input instead of running `claude`
+    pub fn with_simulation(mut self, simulate: bool) -> Self {
+        self.simulate = simulate;
+        self
+    }
+
+    /// Set the terminal size limits and defaults
+    pub fn with_terminal_limits(mut self, terminal: TerminalLimits) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// Refuse the operations of these capability groups
+    pub fn with_disabled_capabilities(mut self, disabled: Vec<Capability>) -> Self {
+        self.disabled_capabilities = disabled;
+        self
+    }
+
+    /// Cap the number of running agents
+    pub fn with_agent_limits(mut self, limits: AgentLimits) -> Self {
+        self.agent_limits = limits;
+        self
+    }
+
+    /// Look up secrets named by presets in these sources
+    pub fn with_secrets(mut self, secrets: SecretStore) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Limit the environment variables agents can be given
+    pub fn with_env_policy(mut self, policy: EnvPolicy) -> Self {
+        self.env_policy = policy;
+        self
+    }
+
+    /// Set the output coalescing window (zero sends every read as it is)
+    pub fn with_coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    /// Set the rate limits applied to each connection
+    pub fn with_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.connection_limits = limits;
+        self
+    }
+
+    /// Set how often clients are pinged and how many pings they may miss
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Keep the sessions of lost connections this long for clients to resume,
+    /// or not at all
+    pub fn with_resume_grace(mut self, grace: Option<Duration>) -> Self {
+        self.resume_grace = grace.filter(|grace| !grace.is_zero());
+        self
+    }
+
+    /// Set how long killed agents get to exit before they are sent SIGKILL
+    pub fn with_kill_grace(mut self, grace: Duration) -> Self {
+        self.kill_grace = grace;
+        self
+    }
+
+    /// Stop agents after `timeout` without input or output
+    pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Log every agent's terminal to its project's `.hoc/logs`
+    pub fn with_transcript(mut self, transcript: Option<TranscriptConfig>) -> Self {
+        self.transcript = transcript;
+        self
+    }
+
+    /// Confine file access to these directories
+    pub fn with_allowed_roots(mut self, roots: Vec<PathBuf>) -> Self {
+        self.allowed_roots = roots;
+        self
+    }
+
+    /// Reload the token, agent limits and allowed roots from this source on
+    /// request
+    pub fn with_config_source(mut self, source: Option<ConfigSource>) -> Self {
+        self.config_source = source;
+        self
+    }
+
+    /// Append a record of every connection, spawn, input, kill and
+    /// configuration change to this log
+    pub fn with_audit_log(mut self, audit_log: Option<AuditLog>) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Run at most this many queued tasks at the same time
+    pub fn with_max_running_tasks(mut self, max: usize) -> Self {
+        self.max_running_tasks = max;
+        self
+    }
+
+    /// Offer these presets in every project, unless the project defines a
+    /// preset of the same name
+    pub fn with_presets(mut self, presets: Vec<AgentPreset>) -> Self {
+        self.presets = presets;
+        self
+    }
+
+    /// Spawn agents on these schedules
+    pub fn with_schedules(mut self, schedules: Vec<ScheduleConfig>) -> Self {
+        self.schedules = schedules;
+        self
+    }
+
+    /// POST agent events to these webhooks
+    pub fn with_webhooks(mut self, webhooks: Vec<WebhookConfig>) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Settings that can be reloaded, as configured at startup
+    fn reloadable(&self) -> ReloadableConfig {
+        ReloadableConfig {
+            token: self.token.clone(),
+            tokens: self.tokens.clone(),
+            allowed_roots: self.allowed_roots.clone(),
+            agent_limits: self.agent_limits,
+        }
+    }
+
+    /// Capabilities that are not disabled
+    pub fn capabilities(&self) -> Vec<Capability> {
+        Capability::ALL
+            .into_iter()
+            .filter(|c| !self.disabled_capabilities.contains(c))
+            .collect()
+    }
+
     /// Get the socket address to bind to
     pub fn socket_addr(&self) -> String {
         format!("{}:{}", self.bind, self.port)
     }
 }
 
+/// Minimum interval between typing notifications for one client and agent
+const TYPING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Input activity of one client, relayed to the other clients
+#[derive(Debug, Clone)]
+pub(super) struct TypingEvent {
+    /// Connection the input came from
+    connection_id: Uuid,
+    /// Agent receiving the input
+    agent_id: Uuid,
+    /// Client address
+    client: String,
+}
+
+/// Publishes a connection's input activity, throttled per agent
+struct TypingNotifier {
+    connection_id: Uuid,
+    client: String,
+    last_sent: HashMap<Uuid, Instant>,
+}
+
+impl TypingNotifier {
+    fn new(connection_id: Uuid, client: String) -> Self {
+        Self {
+            connection_id,
+            client,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Announce input for `agent_id` unless it was announced recently
+    fn notify(&mut self, state: &ServerState, agent_id: Uuid, now: Instant) {
+        if let Some(last) = self.last_sent.get(&agent_id) {
+            if now.duration_since(*last) < TYPING_INTERVAL {
+                return;
+            }
+        }
+        self.last_sent.insert(agent_id, now);
+        let _ = state.typing_tx.send(TypingEvent {
+            connection_id: self.connection_id,
+            agent_id,
+            client: self.client.clone(),
+        });
+    }
+}
+
+/// Per-connection state used while handling client messages
+struct Connection {
+    /// Identifies the connection to other connections
+    id: Uuid,
+    /// Client address
+    client: String,
+    /// Typing notifications for this client's input
+    typing: TypingNotifier,
+    /// Chunked pastes being received
+    pastes: PasteAssembler,
+    /// Messages for this client produced outside of request handling
+    notice_tx: mpsc::UnboundedSender<ServerMessage>,
+    /// Protocol version the client speaks, once known
+    version: Option<u32>,
+    /// How agent events are delivered
+    stream_mode: StreamMode,
+    /// Key agents spawned on this connection are owned by: the claimed
+    /// session token, else the connection ID
+    owner: String,
+    /// Agents whose output the connection receives (every agent when `None`)
+    attached: Option<HashSet<Uuid>>,
+    /// Session groups the connection joined
+    groups: HashSet<String>,
+    /// Message and input rates the client is held to
+    limiter: ConnectionLimiter,
+    /// Directories the client receives `file_changed` for
+    watches: Watches,
+    /// What the client's token lets it do
+    role: Role,
+    /// Name the client's token was issued under
+    token_name: Option<String>,
+    /// Token the client can resume this connection's session with
+    resume: Option<ResumeTicket>,
+    /// Output the client missed while away, sent after the response that
+    /// resumes its session
+    replay: Vec<(Uuid, Vec<u8>)>,
+}
+
+impl Connection {
+    fn new(client: String) -> (Self, mpsc::UnboundedReceiver<ServerMessage>) {
+        let (notice_tx, notice_rx) = mpsc::unbounded_channel();
+        let id = Uuid::new_v4();
+        let connection = Self {
+            id,
+            typing: TypingNotifier::new(id, client.clone()),
+            client,
+            pastes: PasteAssembler::default(),
+            notice_tx,
+            version: None,
+            stream_mode: StreamMode::default(),
+            owner: id.to_string(),
+            attached: None,
+            groups: HashSet::new(),
+            limiter: ConnectionLimiter::new(ConnectionLimits::default(), Instant::now()),
+            watches: Watches::default(),
+            role: Role::Admin,
+            token_name: None,
+            resume: None,
+            replay: Vec::new(),
+        };
+        (connection, notice_rx)
+    }
+
+    /// Audit record of an event caused by this client
+    fn audit_record(&self, event: AuditEvent) -> AuditRecord {
+        AuditRecord::new(&self.client, event).with_identity(self.role, self.token_name.as_deref())
+    }
+
+    /// Record a request in the audit log, if one is kept, with the outcome
+    /// its response tells
+    fn audit(&self, state: &ServerState, mut event: AuditEvent, response: Option<&ServerMessage>) {
+        if let Some(log) = &state.config.audit_log {
+            let error = event.complete(response);
+            log.write(&self.audit_record(event).with_error(error));
+        }
+    }
+
+    /// Wire format for the connection's protocol version
+    fn codec(&self) -> Codec {
+        Codec::for_version(self.version.unwrap_or(MIN_PROTOCOL_VERSION))
+    }
+
+    /// Whether the connection receives an agent's output
+    fn receives_output(&self, agent_id: Uuid) -> bool {
+        self.attached
+            .as_ref()
+            .is_none_or(|attached| attached.contains(&agent_id))
+    }
+
+    /// Fix the connection's protocol version from the client's first message,
+    /// echoing it to the client
+    ///
+    /// Version 1 clients receive the output of every agent until they attach
+    /// or detach one; later versions only that of agents they attach or spawn.
+    fn negotiate(&mut self, version: u32) {
+        if self.version.is_none() {
+            self.version = Some(version);
+            if version >= 2 {
+                self.attached = Some(HashSet::new());
+            }
+            let _ = self
+                .notice_tx
+                .send(ServerMessage::VersionNegotiated { version });
+            if let Some(ticket) = self.resume.as_ref().filter(|_| version >= 2) {
+                let _ = self.notice_tx.send(ServerMessage::ResumeToken {
+                    resume_token: ticket.token.clone(),
+                    grace_ms: ticket.grace.as_millis() as u64,
+                });
+            }
+        }
+    }
+
+    /// What is kept of the session once the connection is lost
+    fn suspend(&mut self) -> SuspendedSession {
+        SuspendedSession {
+            connection_id: self.id,
+            identity: Identity {
+                role: self.role,
+                token_name: self.token_name.clone(),
+            },
+            owner: self.owner.clone(),
+            attached: self.attached.take(),
+            stream_mode: self.stream_mode,
+            backlog: Backlog::default(),
+        }
+    }
+}
+
+/// State shared by all client connections
+pub(super) struct ServerState {
+    /// Server configuration
+    pub(super) config: ServerConfig,
+    /// Local agent sessions
+    pub(super) agent_manager: Arc<dyn AgentBackend>,
+    /// Starts local agents
+    pub(super) spawner: Arc<dyn AgentSpawner>,
+    /// Upstream peer bridges
+    pub(super) federation: Federation,
+    /// Input sanitization and per-agent rate limits
+    pub(super) input_filter: InputFilter,
+    /// Typing activity relayed between clients
+    pub(super) typing_tx: broadcast::Sender<TypingEvent>,
+    /// Which client controls each agent's input
+    pub(super) input_control: InputControl,
+    /// Text copied to each agent's clipboard
+    pub(super) clipboards: Clipboards,
+    /// Clients in each session group
+    pub(super) groups: Groups,
+    /// Agent limits, overall and per client
+    pub(super) agent_quota: AgentQuota,
+    /// Sessions of connections that can be resumed
+    pub(super) resumable: ResumeRegistry,
+    /// Token, agent limits and allowed roots in effect, which may have been
+    /// reloaded since startup
+    pub(super) live: LiveConfig,
+    /// Connected clients and their traffic
+    pub(super) clients: ClientRegistry,
+    /// Prompts queued to run as agents
+    pub(super) tasks: TaskQueue,
+    /// Pipelines started by clients
+    pub(super) pipelines: Pipelines,
+    /// Agents spawned on a schedule
+    pub(super) schedules: Schedules,
+    /// When the server was created
+    pub(super) started: Instant,
+    /// Stops the server and everything it runs
+    pub(super) shutdown_tx: broadcast::Sender<()>,
+}
+
+impl ServerState {
+    /// Create server state with a fresh agent manager
+    #[cfg(test)]
+    pub(super) fn new(config: ServerConfig, federation: Federation) -> Self {
+        let manager = Arc::new(default_manager(&config));
+        let (shutdown_tx, _) = broadcast::channel(1);
+        Self::with_backend(config, federation, manager.clone(), manager, shutdown_tx)
+    }
+
+    /// Create server state driving agents through the given backend
+    pub(super) fn with_backend(
+        config: ServerConfig,
+        federation: Federation,
+        agent_manager: Arc<dyn AgentBackend>,
+        spawner: Arc<dyn AgentSpawner>,
+        shutdown_tx: broadcast::Sender<()>,
+    ) -> Self {
+        let (typing_tx, _) = broadcast::channel(256);
+        Self {
+            typing_tx,
+            input_control: InputControl::new(),
+            clipboards: Clipboards::default(),
+            groups: Groups::default(),
+            agent_quota: AgentQuota::new(config.agent_limits),
+            resumable: ResumeRegistry::default(),
+            live: LiveConfig::new(config.reloadable(), config.config_source.clone()),
+            clients: ClientRegistry::default(),
+            tasks: TaskQueue::new(config.max_running_tasks),
+            pipelines: Pipelines::default(),
+            schedules: Schedules::new(config.schedules.clone(), schedules::now_secs()),
+            started: Instant::now(),
+            shutdown_tx,
+            input_filter: InputFilter::new(config.input_policy),
+            config,
+            agent_manager,
+            spawner,
+            federation,
+        }
+    }
+}
+
+impl ServerState {
+    /// Read the configuration file again and apply its token, agent limits
+    /// and allowed roots, returning the names of the settings that changed
+    pub(super) fn reload_config(&self) -> Result<Vec<String>, ReloadError> {
+        match self.live.reload() {
+            Ok((reloaded, changed)) => {
+                self.agent_quota.set_limits(reloaded.agent_limits);
+                if changed.is_empty() {
+                    info!("Configuration reloaded; nothing changed");
+                } else {
+                    info!("Configuration reloaded; changed {}", changed.join(", "));
+                }
+                Ok(changed)
+            }
+            Err(e) => {
+                warn!("{}; keeping the current configuration", e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Agent manager configured from the server config
+fn default_manager(config: &ServerConfig) -> AgentManager {
+    let redactor = Redactor::default().with_rules(config.input_redactions.iter().cloned());
+    let manager = AgentManager::new()
+        .with_redactor(redactor)
+        .with_output_redaction(config.redact_output);
+    if config.simulate {
+        let script = PtyScript::echo().with_output("Simulated agent\r\n");
+        manager.with_pty_backend(Arc::new(ScriptedPtyBackend::new(script)))
+    } else {
+        manager
+    }
+}
+
+/// Builder for a [`WebSocketServer`]
+///
+/// Without a manager, agents run in an [`AgentManager`] configured from the
+/// server config.
+#[derive(Default)]
+pub struct WebSocketServerBuilder {
+    config: Option<ServerConfig>,
+    manager: Option<Arc<dyn AgentBackend>>,
+    spawner: Option<Arc<dyn AgentSpawner>>,
+}
+
+impl WebSocketServerBuilder {
+    /// Set the server configuration (defaults to 127.0.0.1:9000)
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Drive agents through a custom manager, which also spawns them
+    ///
+    /// `input_redactions` and `redact_output` from the config only apply to the
+    /// default manager.
+    #[allow(dead_code)]
+    pub fn with_manager<M>(mut self, manager: Arc<M>) -> Self
+    where
+        M: AgentBackend + AgentSpawner + 'static,
+    {
+        self.manager = Some(manager.clone());
+        self.spawner = Some(manager);
+        self
+    }
+
+    /// Start agents with a custom spawner instead of the manager
+    #[allow(dead_code)]
+    pub fn with_spawner(mut self, spawner: Arc<dyn AgentSpawner>) -> Self {
+        self.spawner = Some(spawner);
+        self
+    }
+
+    /// Build the server
+    ///
+    /// Connections to configured peer bridges are started immediately.
+    pub fn build(self) -> WebSocketServer {
+        let config = self
+            .config
+            .unwrap_or_else(|| ServerConfig::new("127.0.0.1".to_string(), 9000));
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let federation = Federation::start(config.peers.clone(), &shutdown_tx);
+
+        let (manager, spawner) = match (self.manager, self.spawner) {
+            (Some(manager), Some(spawner)) => (manager, spawner),
+            (manager, spawner) => {
+                let default = Arc::new(default_manager(&config));
+                (
+                    manager.unwrap_or_else(|| default.clone()),
+                    spawner.unwrap_or(default),
+                )
+            }
+        };
+        WebSocketServer {
+            state: Arc::new(ServerState::with_backend(
+                config,
+                federation,
+                manager,
+                spawner,
+                shutdown_tx.clone(),
+            )),
+            shutdown_tx,
+        }
+    }
+}
+
 /// WebSocket server for handling Godot client connections
 pub struct WebSocketServer {
-    config: ServerConfig,
-    agent_manager: Arc<AgentManager>,
+    state: Arc<ServerState>,
     shutdown_tx: broadcast::Sender<()>,
 }
 
 impl WebSocketServer {
     /// Create a new WebSocket server
+    ///
+    /// Connections to configured peer bridges are started immediately.
     pub fn new(config: ServerConfig) -> Self {
-        let (shutdown_tx, _) = broadcast::channel(1);
-        Self {
-            config,
-            agent_manager: Arc::new(AgentManager::new()),
-            shutdown_tx,
-        }
+        Self::builder().with_config(config).build()
+    }
+
+    /// Start building a server, e.g. with a custom agent backend
+    pub fn builder() -> WebSocketServerBuilder {
+        WebSocketServerBuilder::default()
     }
 
     /// Get a shutdown signal receiver (for external components to listen for shutdown)
@@ -81,14 +867,117 @@ impl WebSocketServer {
         let _ = self.shutdown_tx.send(());
     }
 
+    /// Reload the token, agent limits and allowed roots from the
+    /// configuration file, keeping connections and agents
+    pub fn reload_config(&self) -> Result<Vec<String>, ReloadError> {
+        let result = self.state.reload_config();
+        if let Some(log) = &self.state.config.audit_log {
+            let (changed, error) = match &result {
+                Ok(changed) => (changed.clone(), None),
+                Err(e) => (Vec::new(), Some(e.to_string())),
+            };
+            let event = AuditEvent::ReloadConfig { changed };
+            log.write(&AuditRecord::new("local", event).with_error(error));
+        }
+        result
+    }
+
     /// Run the WebSocket server
     ///
     /// This will listen for incoming connections and handle them concurrently.
     /// The server will shut down gracefully when a shutdown signal is received.
     pub async fn run(&self) -> anyhow::Result<()> {
-        let addr = self.config.socket_addr();
+        let addr = self.state.config.socket_addr();
+        let tls = match self.state.config.tls {
+            Some(ref tls) => Some(tls.acceptor()?),
+            None => None,
+        };
         let listener = TcpListener::bind(&addr).await?;
-        info!("WebSocket server listening on ws://{}/ws", addr);
+        info!(
+            "WebSocket server listening on {}://{}{}",
+            if tls.is_some() { "wss" } else { "ws" },
+            addr,
+            self.state.config.ws_path.as_deref().unwrap_or("")
+        );
+        // Withdrawn when the server stops and the announcer is dropped
+        let _announcer = match self.state.config.mdns {
+            Some(ref mdns) => self.announce(mdns, listener.local_addr()?.port()),
+            None => None,
+        };
+        let peer_count = self.state.federation.peer_count().await;
+        if peer_count > 0 {
+            info!("Federating agents from {} peer bridge(s)", peer_count);
+        }
+
+        if self.state.config.persistent_sessions {
+            let count = self.state.spawner.reattach_persistent().await;
+            info!("Persistent sessions enabled; re-attached {} agent(s)", count);
+        }
+
+        tokio::spawn(tasks::run_tasks(
+            Arc::clone(&self.state),
+            self.shutdown_tx.clone(),
+        ));
+        tokio::spawn(pipelines::run_pipelines(
+            Arc::clone(&self.state),
+            self.shutdown_tx.clone(),
+        ));
+        tokio::spawn(schedules::run_schedules(
+            Arc::clone(&self.state),
+            self.shutdown_tx.clone(),
+        ));
+        tokio::spawn(webhooks::run_webhooks(
+            Arc::clone(&self.state),
+            self.shutdown_tx.clone(),
+        ));
+
+        if let Some(ref relay_url) = self.state.config.relay_url {
+            tokio::spawn(super::relay::run_relay(
+                relay_url.clone(),
+                Arc::clone(&self.state),
+                self.shutdown_tx.clone(),
+            ));
+        }
+
+        if let Some(ref cluster) = self.state.config.cluster {
+            let store = Arc::new(DirectoryStore::new(&cluster.store_dir)?);
+            tokio::spawn(super::cluster::run_cluster(
+                cluster.clone(),
+                store,
+                Arc::clone(&self.state),
+                self.shutdown_tx.clone(),
+            ));
+        }
+
+        if let Some(ref quic) = self.state.config.quic {
+            let endpoint = super::quic::bind(quic, &self.state.config.bind)?;
+            tokio::spawn(super::quic::run_quic(
+                endpoint,
+                Arc::clone(&self.state),
+                self.shutdown_tx.clone(),
+            ));
+        }
+
+        if let Some(port) = self.state.config.grpc_port {
+            let listener = TcpListener::bind(format!("{}:{}", self.state.config.bind, port)).await?;
+            let state = Arc::clone(&self.state);
+            let shutdown_tx = self.shutdown_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = super::grpc::run_grpc(listener, state, shutdown_tx).await {
+                    error!("gRPC server failed: {}", e);
+                }
+            });
+        }
+
+        if let Some(port) = self.state.config.dashboard_port {
+            let state = Arc::clone(&self.state);
+            let shutdown_tx = self.shutdown_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = super::dashboard::run_dashboard(port, state, shutdown_tx).await {
+                    error!("Dashboard server failed: {}", e);
+                }
+            });
+        }
 
         let mut shutdown_rx = self.shutdown_tx.subscribe();
 
@@ -98,12 +987,12 @@ impl WebSocketServer {
                 result = listener.accept() => {
                     match result {
                         Ok((stream, peer_addr)) => {
-                            let agent_manager = Arc::clone(&self.agent_manager);
+                            let state = Arc::clone(&self.state);
                             let shutdown_rx = self.shutdown_tx.subscribe();
-                            let token = self.config.token.clone();
+                            let tls = tls.clone();
 
                             tokio::spawn(async move {
-                                if let Err(e) = handle_connection(stream, peer_addr, agent_manager, shutdown_rx, token).await {
+                                if let Err(e) = accept_connection(stream, peer_addr, tls, state, shutdown_rx).await {
                                     error!("Connection error from {}: {}", peer_addr, e);
                                 }
                             });
@@ -122,314 +1011,2681 @@ impl WebSocketServer {
         }
 
         // Wait for active connections to finish
-        let session_count = self.agent_manager.session_count().await;
+        let session_count = self.state.agent_manager.session_count().await;
         if session_count > 0 {
             info!("Waiting for {} active sessions to close...", session_count);
         }
 
         Ok(())
     }
-}
 
-/// Handle a single WebSocket connection
-async fn handle_connection(
-    stream: TcpStream,
-    peer_addr: SocketAddr,
-    agent_manager: Arc<AgentManager>,
-    mut shutdown_rx: broadcast::Receiver<()>,
-    token: Option<String>,
-) -> anyhow::Result<()> {
-    use crate::agent::AgentEvent;
+    /// Announce the server on the local network, logging rather than
+    /// failing if mDNS is unavailable
+    fn announce(&self, mdns: &DiscoveryConfig, port: u16) -> Option<Announcer> {
+        let config = &self.state.config;
+        let loopback = config.bind == "localhost"
+            || config.bind.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        if loopback {
+            warn!(
+                "Announcing over mDNS, but bound to {}: other devices can't connect (use --bind)",
+                config.bind
+            );
+        }
+        let announcement = Announcement {
+            port,
+            auth_required: self.state.live.auth_required(),
+            tls: config.tls.is_some(),
+            ws_path: config.ws_path.clone(),
+        };
+        match Announcer::start(mdns, &announcement) {
+            Ok(announcer) => Some(announcer),
+            Err(e) => {
+                warn!("Failed to announce over mDNS: {}", e);
+                None
+            }
+        }
+    }
 
-    info!("New connection from {}", peer_addr);
+    /// Serve the process that launched the bridge over standard input and
+    /// output instead of listening for connections
+    ///
+    /// Returns once the client closes standard input or the server is shut
+    /// down.
+    pub async fn run_stdio(&self) -> anyhow::Result<()> {
+        if self.state.config.persistent_sessions {
+            let count = self.state.spawner.reattach_persistent().await;
+            info!("Persistent sessions enabled; re-attached {} agent(s)", count);
+        }
 
-    // Upgrade to WebSocket
-    let ws_stream = accept_async(stream).await?;
-    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        let result = super::stdio::serve_stdio(
+            tokio::io::stdin(),
+            tokio::io::stdout(),
+            Arc::clone(&self.state),
+            self.shutdown_tx.subscribe(),
+        )
+        .await;
+        // Stop anything still running for the session
+        self.shutdown();
+        result
+    }
 
-    // Send welcome message, indicating if auth is required
-    let welcome = if token.is_some() {
-        ServerMessage::welcome_auth_required()
+    /// Serve the process that launched the bridge as a Model Context Protocol
+    /// server over standard input and output
+    ///
+    /// Returns once the client closes standard input or the server is shut
+    /// down.
+    pub async fn run_mcp(&self) -> anyhow::Result<()> {
+        if self.state.config.persistent_sessions {
+            let count = self.state.spawner.reattach_persistent().await;
+            info!("Persistent sessions enabled; re-attached {} agent(s)", count);
+        }
+
+        let result = super::mcp::serve_mcp(
+            tokio::io::stdin(),
+            tokio::io::stdout(),
+            Arc::clone(&self.state),
+            self.shutdown_tx.subscribe(),
+        )
+        .await;
+        // Stop anything still running for the session
+        self.shutdown();
+        result
+    }
+}
+
+/// Complete the TLS handshake, if TLS is configured, and handle the connection
+async fn accept_connection(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    state: Arc<ServerState>,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let Some(tls) = tls else {
+        return handle_connection(stream, peer_addr, state, shutdown_rx).await;
+    };
+    let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream))
+        .await
+        .map_err(|_| anyhow::anyhow!("TLS handshake timed out"))??;
+    handle_connection(stream, peer_addr, state, shutdown_rx).await
+}
+
+/// Handle a single WebSocket connection
+async fn handle_connection<S>(
+    stream: S,
+    peer_addr: SocketAddr,
+    state: Arc<ServerState>,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    info!("New connection from {}", peer_addr);
+
+    // Health probes and the REST API share the port; everything else is a
+    // WebSocket handshake
+    let (request, mut stream) = tokio::time::timeout(HANDSHAKE_TIMEOUT, peek_request(stream))
+        .await
+        .map_err(|_| anyhow::anyhow!("Request head timed out"))??;
+    if health::is_probe(&request) {
+        debug!("Health probe {} from {}", request.path, peer_addr);
+        health::probe(&request, &state).await.write_to(&mut stream).await?;
+        return Ok(());
+    }
+    if rest::is_rest(&request) {
+        // The stream replays the head, so the request is read again with its body
+        let response = match tokio::time::timeout(HANDSHAKE_TIMEOUT, read_request(&mut stream)).await {
+            Ok(Ok(request)) => rest::handle(&request, peer_addr, &state).await,
+            Ok(Err(e)) => HttpResponse::text(400, e.to_string()),
+            Err(_) => HttpResponse::text(408, "Request body timed out"),
+        };
+        response.write_to(&mut stream).await?;
+        return Ok(());
+    }
+
+    // Upgrade to WebSocket, checking the request path, origin and forwarding
+    // headers
+    let ws_path = state.config.ws_path.clone();
+    let mut forwarded = ForwardedInfo::direct(peer_addr);
+    let mut cluster_node = None;
+    // The handshake callback signature is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        if !path_matches(ws_path.as_deref(), request.uri().path()) {
+            debug!("Rejecting upgrade from {} for path {}", peer_addr, request.uri().path());
+            let mut error = ErrorResponse::new(Some("Not Found".to_string()));
+            *error.status_mut() = StatusCode::NOT_FOUND;
+            return Err(error);
+        }
+        let origin = state
+            .config
+            .origin_policy
+            .check(request.headers(), state.config.dashboard_port);
+        if let Err(reason) = origin {
+            warn!("Rejecting upgrade from {}: {}", peer_addr, reason);
+            let mut error = ErrorResponse::new(Some(reason));
+            *error.status_mut() = StatusCode::FORBIDDEN;
+            return Err(error);
+        }
+        forwarded = resolve_client(peer_addr, request.headers(), &state.config.trusted_proxies);
+        cluster_node = request
+            .headers()
+            .get(CLUSTER_NODE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        Ok(response)
+    };
+    let ws_stream = accept_hdr_async(stream, callback).await?;
+
+    if forwarded.via_proxy {
+        info!(
+            "Connection {} is client {} via proxy (proto: {})",
+            peer_addr,
+            forwarded.client_addr,
+            forwarded.proto.as_deref().unwrap_or("unknown")
+        );
+        if state.live.auth_required() && !forwarded.is_secure() {
+            warn!(
+                "Client {} reached the proxy without TLS; its auth token is sent in clear text",
+                forwarded.client_addr
+            );
+        }
+    }
+
+    if let Some(ref node) = cluster_node {
+        info!("Connection {} is a cluster link from node {}", forwarded.client_addr, node);
+    }
+
+    let options = SessionOptions {
+        relay_peer_events: cluster_node.is_none(),
+    };
+    serve_websocket(ws_stream, forwarded.client_addr, state, options, shutdown_rx).await
+}
+
+/// Per-connection session behaviour
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SessionOptions {
+    /// Forward messages relayed from peer bridges to the client
+    ///
+    /// Disabled for cluster links so nodes don't echo each other's agents.
+    pub(super) relay_peer_events: bool,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            relay_peer_events: true,
+        }
+    }
+}
+
+/// Serve the bridge protocol to a client over an established WebSocket
+///
+/// Used for directly accepted connections as well as connections paired
+/// through a relay.
+pub(super) async fn serve_websocket<S>(
+    ws_stream: WebSocketStream<S>,
+    peer_addr: String,
+    state: Arc<ServerState>,
+    options: SessionOptions,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let (sender, receiver) = ws_stream.split();
+    let receiver = WebSocketReceiver::new(receiver);
+    serve_client(sender, receiver, peer_addr, state, options, shutdown_rx).await
+}
+
+/// Serve the bridge protocol to a client over any transport
+pub(super) async fn serve_client<T, R>(
+    sender: T,
+    mut receiver: R,
+    peer_addr: String,
+    state: Arc<ServerState>,
+    options: SessionOptions,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<()>
+where
+    T: TransportSender,
+    R: TransportReceiver,
+{
+    use crate::agent::AgentEvent;
+
+    // Count what the client is sent, for list_clients and quality adaptation
+    let registration = state.clients.register(&peer_addr);
+    let mut sender = registration.meter(sender);
+
+    // Send welcome message, indicating if auth is required
+    let auth_required = state.live.auth_required();
+    let welcome = if auth_required {
+        ServerMessage::welcome_auth_required()
     } else {
         ServerMessage::welcome()
-    };
+    }
+    .with_capabilities(state.config.capabilities());
     let welcome_json = serde_json::to_string(&welcome)?;
-    ws_sender.send(Message::Text(welcome_json)).await?;
+    sender.send_text(welcome_json).await?;
     debug!("Sent welcome message to {}", peer_addr);
 
-    // Handle authentication if token is required
-    if let Some(ref expected_token) = token {
+    // Handle authentication if token is required; without tokens every
+    // client is an admin
+    let mut auth_version = None;
+    let mut role = Role::Admin;
+    let mut token_name = None;
+    if auth_required {
         debug!("Waiting for authentication from {}", peer_addr);
 
         // Wait for the first message which should be authentication
         let auth_result = tokio::time::timeout(
             std::time::Duration::from_secs(30),
-            wait_for_auth(&mut ws_receiver, expected_token),
+            wait_for_auth(&mut receiver, &state.live),
         )
         .await;
 
         match auth_result {
-            Ok(Ok(())) => {
-                info!("Client {} authenticated successfully", peer_addr);
-                let success = ServerMessage::auth_success();
-                let success_json = serde_json::to_string(&success)?;
-                ws_sender.send(Message::Text(success_json)).await?;
-            }
-            Ok(Err(e)) => {
-                warn!("Authentication failed for {}: {}", peer_addr, e);
-                let error = ServerMessage::error_with_code(e.to_string(), ErrorCode::AuthFailed);
+            Ok(Ok((version, token_role, name))) => {
+                info!("Client {} authenticated as {}", peer_addr, token_role.as_str());
+                let success = ServerMessage::auth_success(token_role);
+                let success_json = Codec::for_version(version).encode(&success, None)?;
+                sender.send_text(success_json).await?;
+                auth_version = Some(version);
+                role = token_role;
+                token_name = name;
+            }
+            Ok(Err(error)) => {
+                if let ServerMessage::Error { ref message, .. } = error {
+                    warn!("Authentication failed for {}: {}", peer_addr, message);
+                    if let Some(log) = &state.config.audit_log {
+                        let record = AuditRecord::new(&peer_addr, AuditEvent::AuthFailed)
+                            .with_error(Some(message.clone()));
+                        log.write(&record);
+                    }
+                }
                 let error_json = serde_json::to_string(&error)?;
-                ws_sender.send(Message::Text(error_json)).await?;
-                let _ = ws_sender.send(Message::Close(None)).await;
+                sender.send_text(error_json).await?;
+                sender.close().await;
                 return Ok(());
             }
             Err(_) => {
                 warn!("Authentication timeout for {}", peer_addr);
+                if let Some(log) = &state.config.audit_log {
+                    let record = AuditRecord::new(&peer_addr, AuditEvent::AuthFailed)
+                        .with_error(Some("Authentication timeout".to_string()));
+                    log.write(&record);
+                }
                 let error =
                     ServerMessage::error_with_code("Authentication timeout", ErrorCode::AuthFailed);
                 let error_json = serde_json::to_string(&error)?;
-                ws_sender.send(Message::Text(error_json)).await?;
-                let _ = ws_sender.send(Message::Close(None)).await;
+                sender.send_text(error_json).await?;
+                sender.close().await;
                 return Ok(());
             }
         }
     }
 
-    // Subscribe to agent events
-    let mut agent_event_rx = agent_manager.subscribe();
+    // Subscribe to agent events, both local and relayed from peer bridges
+    let mut agent_event_rx = state.agent_manager.subscribe();
+    let mut peer_event_rx = state.federation.subscribe();
+    let mut typing_rx = state.typing_tx.subscribe();
+    let (mut connection, mut notice_rx) = Connection::new(peer_addr.clone());
+    connection.limiter = ConnectionLimiter::new(state.config.connection_limits, Instant::now());
+    connection.role = role;
+    connection.token_name = token_name;
+    if let Some(log) = &state.config.audit_log {
+        log.write(&connection.audit_record(AuditEvent::Connected));
+    }
+    let _disconnect_record = DisconnectRecord::new(
+        state.config.audit_log.clone(),
+        connection.audit_record(AuditEvent::Disconnected),
+    );
+    if let Some(grace) = state.config.resume_grace {
+        let identity = Identity {
+            role: connection.role,
+            token_name: connection.token_name.clone(),
+        };
+        connection.resume = Some(state.resumable.register(identity, grace));
+    }
+    if let Some(version) = auth_version {
+        connection.negotiate(version);
+    }
+    // A connection without a resume token is never taken over
+    let takeover = connection
+        .resume
+        .as_ref()
+        .map_or_else(|| Arc::new(Notify::new()), |ticket| Arc::clone(&ticket.takeover));
+    let mut control_rx = state.input_control.subscribe();
+    let mut task_rx = state.tasks.subscribe();
+    let mut pipeline_rx = state.pipelines.subscribe();
+    let mut schedule_rx = state.schedules.subscribe();
+    let control_release = ControlRelease {
+        control: &state.input_control,
+        connection_id: connection.id,
+    };
+    let mut quality = AdaptiveQuality::new(Instant::now());
+    let mut batch = OutputBatch::new(state.config.coalesce_window);
+    let mut coalesced: HashMap<Uuid, Vec<u8>> = HashMap::new();
+    let mut flush = tokio::time::interval(COALESCE_INTERVAL);
+    flush.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let ping_interval = state.config.heartbeat.interval.filter(|_| sender.supports_ping());
+    let mut heartbeat = Heartbeat::new(state.config.heartbeat);
+    // A client that just connected counts as heard from
+    let mut heard_from = true;
+    let mut ping = tokio::time::interval(ping_interval.unwrap_or(DEFAULT_HEARTBEAT_INTERVAL));
+    ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ping.reset();
 
-    // Message handling loop
-    loop {
-        tokio::select! {
-            // Receive messages from client
-            msg = ws_receiver.next() => {
-                match msg {
-                    Some(Ok(Message::Text(text))) => {
-                        debug!("Received message from {}: {}", peer_addr, text);
+    // Message handling loop, until the client leaves or its connection is lost
+    let end: anyhow::Result<SessionEnd> = async {
+        loop {
+            tokio::select! {
+                // Receive messages from client
+                msg = receiver.recv_text() => {
+                    match msg {
+                        Some(Ok(text)) => {
+                            debug!("Received message from {}: {}", peer_addr, text);
+                            heard_from = true;
 
-                        match handle_message(&text, &agent_manager).await {
-                            Ok(Some(response)) => {
-                                let response_json = serde_json::to_string(&response)?;
-                                ws_sender.send(Message::Text(response_json)).await?;
+                            let response = handle_message(&text, &state, &mut connection).await;
+                            // Notices raised while handling (e.g. the negotiated
+                            // version) go out before the response
+                            while let Ok(notice) = notice_rx.try_recv() {
+                                let json = connection.codec().encode(&notice, None)?;
+                                sender.send_text(json).await?;
+                            }
+                            // No response is needed for e.g. agent input forwarded successfully
+                            if let Some(response) = response {
+                                let request_id = ClientEnvelope::peek_request_id(&text);
+                                let response_json = connection
+                                    .codec()
+                                    .encode(&response, request_id.as_deref())?;
+                                sender.send_text(response_json).await?;
                             }
-                            Ok(None) => {
-                                // No response needed (e.g., agent input forwarded successfully)
+                            // Output missed by a client resuming its session
+                            for (agent_id, data) in std::mem::take(&mut connection.replay) {
+                                let codec = connection.codec();
+                                send_output(&mut sender, codec, agent_id, &data).await?;
                             }
-                            Err(e) => {
-                                let error_msg = ServerMessage::error_with_code(
-                                    e.to_string(),
-                                    ErrorCode::InternalError,
-                                );
-                                let error_json = serde_json::to_string(&error_msg)?;
-                                ws_sender.send(Message::Text(error_json)).await?;
+                            if connection.limiter.is_abusive() {
+                                warn!("Disconnecting {} for exceeding its rate limits", peer_addr);
+                                sender.close().await;
+                                break Ok(SessionEnd::Closed);
                             }
                         }
+                        Some(Err(e)) => {
+                            error!("Transport error from {}: {}", peer_addr, e);
+                            break Ok(SessionEnd::Lost);
+                        }
+                        None => {
+                            info!("Connection closed by {}", peer_addr);
+                            break Ok(SessionEnd::Closed);
+                        }
                     }
-                    Some(Ok(Message::Binary(data))) => {
-                        warn!("Received binary message from {} ({} bytes), ignoring", peer_addr, data.len());
-                    }
-                    Some(Ok(Message::Ping(data))) => {
-                        ws_sender.send(Message::Pong(data)).await?;
+                }
+                // Forward agent events to client
+                event = agent_event_rx.recv() => {
+                    let codec = connection.codec();
+                    if let Ok(AgentEvent::Exited { agent_id, .. }) = event {
+                        state.input_control.remove_agent(agent_id);
+                        state.clipboards.remove_agent(agent_id);
+                        connection.watches.remove_agent(agent_id);
                     }
-                    Some(Ok(Message::Pong(_))) => {
-                        // Ignore pong messages
+                    // Held output goes out before any other event, keeping the order
+                    let held = batch.deadline().is_some();
+                    if held && !matches!(event, Ok(AgentEvent::Output { .. })) {
+                        send_batch(&mut sender, codec, &mut batch, &mut quality, &registration)
+                            .await?;
                     }
-                    Some(Ok(Message::Close(_))) => {
-                        info!("Client {} requested close", peer_addr);
-                        break;
+                    match event {
+                        Ok(event) if connection.stream_mode == StreamMode::Summary => {
+                            let info = state
+                                .agent_manager
+                                .get_agent_status(event.agent_id())
+                                .await
+                                .ok();
+                            if let Some(msg) = summary::summary_message(&event, info.as_ref()) {
+                                let json = codec.encode(&msg, None)?;
+                                sender.send_text(json).await?;
+                            }
+                        }
+                        Ok(AgentEvent::Output { agent_id, .. })
+                            if !connection.receives_output(agent_id) => {}
+                        Ok(AgentEvent::Output { agent_id, data }) => match quality.tier() {
+                            QualityTier::Full => {
+                                if batch.push(agent_id, data, Instant::now()) {
+                                    let batch = &mut batch;
+                                    let quality = &mut quality;
+                                    send_batch(&mut sender, codec, batch, quality, &registration)
+                                        .await?;
+                                }
+                            }
+                            QualityTier::Coalesced => {
+                                coalesced.entry(agent_id).or_default().extend(data);
+                            }
+                            QualityTier::Status => {}
+                        },
+                        Ok(AgentEvent::Exited {
+                            agent_id,
+                            exit_code,
+                            signal,
+                            reason,
+                            preset,
+                            stats,
+                        }) => {
+                            let msg = ServerMessage::AgentExited {
+                                agent_id,
+                                exit_code,
+                                signal,
+                                reason: Some(reason),
+                                preset,
+                                stats: Some(stats),
+                            };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::Resized { agent_id, cols, rows }) => {
+                            let msg = ServerMessage::AgentResized { agent_id, cols, rows };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::ConfirmationRequested { agent_id, question, options }) => {
+                            let msg = ServerMessage::ConfirmationRequest {
+                                agent_id,
+                                question,
+                                options,
+                            };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::ApprovalRequested {
+                            agent_id,
+                            tool,
+                            detail,
+                            question,
+                            options,
+                            can_always,
+                        }) => {
+                            let msg = ServerMessage::ApprovalRequested {
+                                agent_id,
+                                tool,
+                                detail,
+                                question,
+                                options,
+                                can_always,
+                            };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::StateChanged {
+                            agent_id,
+                            old_state,
+                            new_state,
+                            timestamp_ms,
+                        }) => {
+                            let msg = ServerMessage::AgentStateChanged {
+                                agent_id,
+                                old_state,
+                                new_state,
+                                timestamp_ms,
+                            };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                            if new_state == AgentState::Ready {
+                                let msg = ServerMessage::AgentReady { agent_id };
+                                sender.send_text(codec.encode(&msg, None)?).await?;
+                            }
+                        }
+                        Ok(AgentEvent::CommandStarted { agent_id }) => {
+                            let msg = ServerMessage::CommandStarted { agent_id };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::CommandFinished { agent_id, exit_code }) => {
+                            let msg = ServerMessage::CommandFinished { agent_id, exit_code };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::Tagged { agent_id, tags }) => {
+                            let msg = ServerMessage::AgentTagged { agent_id, tags };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::Renamed { agent_id, name }) => {
+                            let msg = ServerMessage::AgentRenamed { agent_id, name };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::Restarted { agent_id, cols, rows }) => {
+                            let msg = ServerMessage::AgentRestarted { agent_id, cols, rows };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::IdleWarning { agent_id, idle_ms, stop_in_ms }) => {
+                            let msg = ServerMessage::AgentIdleWarning {
+                                agent_id,
+                                idle_ms,
+                                stop_in_ms,
+                            };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::TriggerFired {
+                            agent_id,
+                            trigger,
+                            captures,
+                        }) => {
+                            let msg = ServerMessage::TriggerFired {
+                                agent_id,
+                                trigger,
+                                captures,
+                            };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::Stream { agent_id, event }) => {
+                            let msg = stream_message(agent_id, event);
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::EchoChanged { agent_id, echo, raw }) => {
+                            let msg = ServerMessage::EchoState { agent_id, echo, raw };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::InitialPromptDelivered {
+                            agent_id,
+                            waited_ms,
+                            timed_out,
+                        }) => {
+                            let msg = ServerMessage::InitialPromptDelivered {
+                                agent_id,
+                                waited_ms,
+                                timed_out,
+                            };
+                            let json = codec.encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(AgentEvent::Spawned { agent_id, .. }) => {
+                            // Spawn is handled by the direct response to SpawnAgent message;
+                            // members of the agent's group are attached to it
+                            if !connection.groups.is_empty() {
+                                let group = state
+                                    .agent_manager
+                                    .get_agent_status(agent_id)
+                                    .await
+                                    .ok()
+                                    .and_then(|info| info.group);
+                                if let (Some(group), Some(attached)) =
+                                    (group, &mut connection.attached)
+                                {
+                                    if connection.groups.contains(&group) {
+                                        attached.insert(agent_id);
+                                    }
+                                }
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Client {} lagged by {} agent events", peer_addr, n);
+                            if let Some(tier) = quality.congested(Instant::now()) {
+                                info!("Lowering output quality for {} to {:?}", peer_addr, tier);
+                                change_quality(&mut sender, codec, &registration, tier).await?;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            info!("Agent event channel closed");
+                            break Ok(SessionEnd::Closed);
+                        }
                     }
-                    Some(Ok(Message::Frame(_))) => {
-                        // Raw frame, ignore
+                }
+                // Forward messages relayed from peer bridges
+                event = peer_event_rx.recv(), if options.relay_peer_events => {
+                    match event {
+                        Ok(ServerMessage::AgentOutput { agent_id, .. })
+                            if !connection.receives_output(agent_id) => {}
+                        Ok(msg) => {
+                            let json = connection.codec().encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Client {} lagged by {} peer events", peer_addr, n);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {}
                     }
-                    Some(Err(e)) => {
-                        error!("WebSocket error from {}: {}", peer_addr, e);
-                        break;
+                }
+                // Deliver messages produced by background work for this client
+                Some(msg) = notice_rx.recv() => {
+                    let json = connection.codec().encode(&msg, None)?;
+                    sender.send_text(json).await?;
+                }
+                // Let the client know when others are typing to an agent
+                event = typing_rx.recv() => {
+                    match event {
+                        Ok(event) if event.connection_id != connection.typing.connection_id => {
+                            let msg = ServerMessage::ClientTyping {
+                                agent_id: event.agent_id,
+                                client: event.client,
+                            };
+                            let json = connection.codec().encode(&msg, None)?;
+                            sender.send_text(json).await?;
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => {}
                     }
-                    None => {
-                        info!("Connection closed by {}", peer_addr);
-                        break;
+                }
+                // Relay changes of input control, and requests to this client as owner
+                event = control_rx.recv() => {
+                    let msg = match event {
+                        Ok(ControlEvent::Changed { agent_id, owner }) => {
+                            Some(ServerMessage::ControlChanged { agent_id, owner })
+                        }
+                        Ok(ControlEvent::Requested { agent_id, owner_connection, client })
+                            if owner_connection == connection.id =>
+                        {
+                            Some(ServerMessage::ControlRequested { agent_id, client })
+                        }
+                        Ok(_) | Err(_) => None,
+                    };
+                    if let Some(msg) = msg {
+                        let json = connection.codec().encode(&msg, None)?;
+                        sender.send_text(json).await?;
                     }
                 }
-            }
-            // Forward agent events to client
-            event = agent_event_rx.recv() => {
-                match event {
-                    Ok(AgentEvent::Output { agent_id, data }) => {
-                        let output_str = String::from_utf8_lossy(&data).to_string();
-                        let msg = ServerMessage::agent_output(agent_id, output_str);
-                        let json = serde_json::to_string(&msg)?;
-                        ws_sender.send(Message::Text(json)).await?;
+                // Report queued tasks starting and completing
+                event = task_rx.recv() => {
+                    if let Ok(msg) = event {
+                        let json = connection.codec().encode(&msg, None)?;
+                        sender.send_text(json).await?;
                     }
-                    Ok(AgentEvent::Exited { agent_id, exit_code, reason }) => {
-                        let msg = ServerMessage::agent_exited_with_reason(agent_id, exit_code, reason);
-                        let json = serde_json::to_string(&msg)?;
-                        ws_sender.send(Message::Text(json)).await?;
+                }
+                // Report pipeline progress
+                event = pipeline_rx.recv() => {
+                    if let Ok(msg) = event {
+                        let json = connection.codec().encode(&msg, None)?;
+                        sender.send_text(json).await?;
                     }
-                    Ok(AgentEvent::Resized { agent_id, cols, rows }) => {
-                        let msg = ServerMessage::AgentResized { agent_id, cols, rows };
-                        let json = serde_json::to_string(&msg)?;
-                        ws_sender.send(Message::Text(json)).await?;
+                }
+                // Announce agents spawned on a schedule
+                event = schedule_rx.recv() => {
+                    if let Ok(msg) = event {
+                        let json = connection.codec().encode(&msg, None)?;
+                        sender.send_text(json).await?;
                     }
-                    Ok(AgentEvent::Spawned { .. }) => {
-                        // Spawn is handled by the direct response to SpawnAgent message
+                }
+                // Send held output once its window is over
+                _ = tokio::time::sleep_until(batch.deadline().unwrap_or_else(Instant::now).into()),
+                    if batch.deadline().is_some() =>
+                {
+                    let codec = connection.codec();
+                    send_batch(&mut sender, codec, &mut batch, &mut quality, &registration).await?;
+                }
+                // Send coalesced output and restore quality once the client keeps up
+                _ = flush.tick() => {
+                    let codec = connection.codec();
+                    let mut slow = false;
+                    for (agent_id, data) in coalesced.drain() {
+                        slow |= send_output(&mut sender, codec, agent_id, &data).await?;
                     }
-                    Err(broadcast::error::RecvError::Lagged(n)) => {
-                        warn!("Client {} lagged by {} agent events", peer_addr, n);
+                    let now = Instant::now();
+                    let change = if slow {
+                        quality.congested(now)
+                    } else {
+                        quality.recover(now)
+                    };
+                    if let Some(tier) = change {
+                        change_quality(&mut sender, codec, &registration, tier).await?;
                     }
-                    Err(broadcast::error::RecvError::Closed) => {
-                        info!("Agent event channel closed");
-                        break;
+                }
+                // Ping the client, closing the connection once it stops answering
+                _ = ping.tick(), if ping_interval.is_some() => {
+                    let alive = std::mem::take(&mut heard_from) | receiver.take_pong();
+                    if heartbeat.tick(alive) {
+                        warn!(
+                            "Closing connection to {}: {} pings went unanswered",
+                            peer_addr,
+                            heartbeat.missed()
+                        );
+                        sender.close().await;
+                        break Ok(SessionEnd::Lost);
                     }
+                    sender.send_ping().await?;
+                }
+                // Hand the session to a client resuming it on a new connection
+                _ = takeover.notified() => {
+                    info!("Connection to {} replaced by a resuming client", peer_addr);
+                    sender.close().await;
+                    break Ok(SessionEnd::Lost);
+                }
+                // Handle shutdown signal
+                _ = shutdown_rx.recv() => {
+                    info!("Shutdown signal received, closing connection to {}", peer_addr);
+                    sender.close().await;
+                    break Ok(SessionEnd::Closed);
                 }
             }
-            // Handle shutdown signal
-            _ = shutdown_rx.recv() => {
-                info!("Shutdown signal received, closing connection to {}", peer_addr);
-                let _ = ws_sender.send(Message::Close(None)).await;
-                break;
-            }
         }
     }
+    .await;
+    state.groups.leave_all(connection.id);
+
+    // Keep a lost connection's session for a client that comes back
+    match connection.resume.take() {
+        Some(ticket) if !matches!(end, Ok(SessionEnd::Closed)) => {
+            info!(
+                "Keeping the session of {} for {}s to be resumed",
+                peer_addr,
+                ticket.grace.as_secs()
+            );
+            control_release.keep();
+            let session = connection.suspend();
+            resume::suspend(&state, &ticket, session);
+        }
+        Some(ticket) => state.resumable.remove(&ticket.token),
+        None => {}
+    }
 
     info!("Connection from {} closed", peer_addr);
+    end.map(|_| ())
+}
+
+/// How a client's session ended
+enum SessionEnd {
+    /// The client closed the connection, or was sent away
+    Closed,
+    /// The connection was lost, or taken over by a client resuming it
+    Lost,
+}
+
+/// Send agent output, returning whether the send was slow enough to count as
+/// congestion
+async fn send_output<T: TransportSender>(
+    sender: &mut T,
+    codec: Codec,
+    agent_id: Uuid,
+    data: &[u8],
+) -> anyhow::Result<bool> {
+    let started = Instant::now();
+    let msg = ServerMessage::agent_output_bytes(agent_id, data);
+    sender.send_text(codec.encode(&msg, None)?).await?;
+    Ok(started.elapsed() >= SLOW_SEND)
+}
+
+/// Send the output held in a batch, lowering quality if the client is slow to
+/// take it
+async fn send_batch<T: TransportSender>(
+    sender: &mut T,
+    codec: Codec,
+    batch: &mut OutputBatch,
+    quality: &mut AdaptiveQuality,
+    registration: &Registration<'_>,
+) -> anyhow::Result<()> {
+    let mut slow = false;
+    for (agent_id, data) in batch.take() {
+        slow |= send_output(sender, codec, agent_id, &data).await?;
+    }
+    if slow {
+        if let Some(tier) = quality.congested(Instant::now()) {
+            change_quality(sender, codec, registration, tier).await?;
+        }
+    }
     Ok(())
 }
 
+/// Switch a connection to another quality tier and tell the client
+async fn change_quality<T: TransportSender>(
+    sender: &mut T,
+    codec: Codec,
+    registration: &Registration<'_>,
+    quality: QualityTier,
+) -> anyhow::Result<()> {
+    registration.set_quality(quality);
+    let msg = ServerMessage::QualityChanged { quality };
+    sender.send_text(codec.encode(&msg, None)?).await
+}
+
 /// Handle a client message and return an optional response
 ///
-/// Returns `Ok(None)` when no response is needed (e.g., agent input).
-async fn handle_message(text: &str, agent_manager: &AgentManager) -> anyhow::Result<Option<ServerMessage>> {
-    let envelope = ClientEnvelope::from_json(text).map_err(|e| {
-        debug!("Invalid client message: {}", e);
-        anyhow::anyhow!("{}", e)
-    })?;
-    let message = envelope.message;
+/// Returns `None` when no response is needed (e.g., agent input). Errors carry
+/// the message's `request_id`.
+async fn handle_message(
+    text: &str,
+    state: &ServerState,
+    connection: &mut Connection,
+) -> Option<ServerMessage> {
+    let now = Instant::now();
+    if let Some(error) = connection.limiter.message(now) {
+        return Some(error.with_request_id(ClientEnvelope::peek_request_id(text)));
+    }
+    let envelope = match ClientEnvelope::from_json_with_limits(text, &state.config.terminal) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            debug!("Invalid client message: {}", e);
+            let request_id = ClientEnvelope::peek_request_id(text);
+            return Some(ServerMessage::from(e).with_request_id(request_id));
+        }
+    };
+    if let Some(error) = connection.limiter.input(&envelope.message, now) {
+        return Some(error.with_request_id(envelope.request_id));
+    }
 
-    match message {
-        ClientMessage::Authenticate { .. } => {
-            warn!("Received unexpected Authenticate message after connection established");
-            Ok(Some(ServerMessage::error_with_code(
-                "Already authenticated",
-                ErrorCode::InvalidMessage,
-            )))
+    let audit = AuditEvent::for_request(&envelope.message);
+    let request_id = envelope.request_id.clone();
+    let response = if let Some(error) = role_error(connection.role, &envelope.message) {
+        Some(error)
+    } else {
+        connection.negotiate(envelope.negotiated_version());
+        match handle_envelope(envelope, state, connection).await {
+            Ok(response) => response,
+            Err(e) => Some(ServerMessage::error_with_code(
+                e.to_string(),
+                ErrorCode::InternalError,
+            )),
         }
-        ClientMessage::Ping { seq } => {
-            debug!("Received ping with seq {}", seq);
-            Ok(Some(ServerMessage::Pong { seq }))
+    };
+    if let Some(event) = audit {
+        connection.audit(state, event, response.as_ref());
+    }
+    response.map(|response| response.with_request_id(request_id))
+}
+
+/// Handle a parsed client message
+async fn handle_envelope(
+    envelope: ClientEnvelope,
+    state: &ServerState,
+    connection: &mut Connection,
+) -> anyhow::Result<Option<ServerMessage>> {
+    // Broadcast input goes to each agent as an agent_input of its own, so
+    // input control is claimed and the input rate charged agent by agent
+    if let ClientMessage::BroadcastInput {
+        agent_ids,
+        group,
+        input,
+        paste,
+    } = envelope.message
+    {
+        return broadcast_input(
+            state,
+            connection,
+            agent_ids,
+            group.as_deref(),
+            input,
+            paste,
+            send_envelope_input,
+        )
+        .await;
+    }
+
+    if let ClientMessage::AgentInput { agent_id, .. }
+    | ClientMessage::AgentInputRaw { agent_id, .. }
+    | ClientMessage::RunMacro { agent_id, .. }
+    | ClientMessage::AgentInputChunk { agent_id, .. }
+    | ClientMessage::AgentKey { agent_id, .. }
+    | ClientMessage::SetRawMode { agent_id, .. }
+    | ClientMessage::SendKey { agent_id, .. }
+    | ClientMessage::ConfirmationReply { agent_id, .. }
+    | ClientMessage::ApproveAction { agent_id, .. }
+    | ClientMessage::DenyAction { agent_id } = envelope.message
+    {
+        if agent_known(state, agent_id).await {
+            if let Err(e) = state
+                .input_control
+                .claim(agent_id, connection.id, &connection.client)
+            {
+                let mut error =
+                    ServerMessage::agent_error(agent_id, e.to_string(), ErrorCode::InputLocked);
+                if let ControlError::Locked(owner) = e {
+                    error = error.with_detail("owner", owner);
+                }
+                return Ok(Some(error));
+            }
         }
-        ClientMessage::SpawnAgent {
-            project_path,
-            preset,
-            cols,
-            rows,
-        } => {
-            debug!(
-                "SpawnAgent request: project={}, preset={:?}",
-                project_path, preset
-            );
+        connection.typing.notify(state, agent_id, Instant::now());
+    }
 
-            // Validate project path exists
-            let path = Path::new(&project_path);
-            if !path.exists() {
-                return Ok(Some(ServerMessage::error_with_code(
-                    format!("Project path does not exist: {}", project_path),
-                    ErrorCode::InvalidPath,
+    match envelope.message {
+        ClientMessage::RequestControl { agent_id } => {
+            if !agent_known(state, agent_id).await {
+                return Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    "Agent not found",
+                    ErrorCode::AgentNotFound,
                 )));
             }
-            if !path.is_dir() {
+            // The outcome arrives as control_changed, once the owner grants it
+            state
+                .input_control
+                .request(agent_id, connection.id, &connection.client);
+            return Ok(None);
+        }
+        ClientMessage::GrantControl {
+            agent_id,
+            ref client,
+        } => {
+            return Ok(state
+                .input_control
+                .grant(agent_id, connection.id, client)
+                .err()
+                .map(|e| {
+                    let code = match e {
+                        ControlError::NoRequest(_) => ErrorCode::InvalidMessage,
+                        ControlError::Locked(_) | ControlError::NotOwner => ErrorCode::InputLocked,
+                    };
+                    ServerMessage::agent_error(agent_id, e.to_string(), code)
+                }));
+        }
+        ClientMessage::ReleaseControl { agent_id } => {
+            state.input_control.release(agent_id, connection.id);
+            return Ok(None);
+        }
+        ClientMessage::SetStreamMode { mode } => {
+            connection.stream_mode = mode;
+            return Ok(Some(ServerMessage::StreamModeSet { mode }));
+        }
+        ClientMessage::AttachAgent { agent_id } => {
+            if !agent_known(state, agent_id).await {
+                return Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    "Agent not found",
+                    ErrorCode::AgentNotFound,
+                )));
+            }
+            connection
+                .attached
+                .get_or_insert_with(HashSet::new)
+                .insert(agent_id);
+            return Ok(Some(ServerMessage::AgentAttached { agent_id }));
+        }
+        ClientMessage::DetachAgent { agent_id } => {
+            let attached = match connection.attached.take() {
+                Some(attached) => attached,
+                // Scope a connection receiving everything to every other agent
+                None => all_agent_ids(state).await,
+            };
+            let attached = connection.attached.insert(attached);
+            attached.remove(&agent_id);
+            return Ok(Some(ServerMessage::AgentDetached { agent_id }));
+        }
+        ClientMessage::CreateGroup { ref group } | ClientMessage::JoinGroup { ref group } => {
+            let create = matches!(envelope.message, ClientMessage::CreateGroup { .. });
+            let agent_ids = group_agent_ids(state, group).await;
+            if !create && agent_ids.is_empty() && !state.groups.contains(group) {
                 return Ok(Some(ServerMessage::error_with_code(
-                    format!("Project path is not a directory: {}", project_path),
-                    ErrorCode::InvalidPath,
+                    format!("No group named {}", group),
+                    ErrorCode::GroupNotFound,
                 )));
             }
-
-            // Load project config to get preset settings
-            let project_config = ProjectConfig::load(path).unwrap_or_default();
-
-            // Build spawn config with preset args and initial prompt
-            let mut spawn_config = SpawnConfig::new(&project_path).with_size(
-                cols.unwrap_or(DEFAULT_TERMINAL_COLS),
-                rows.unwrap_or(DEFAULT_TERMINAL_ROWS),
+            let members = state.groups.join(
+                group,
+                connection.id,
+                &connection.client,
+                connection.notice_tx.clone(),
             );
-
-            // Apply preset if specified
-            if let Some(preset_name) = &preset {
-                spawn_config = spawn_config.with_preset(preset_name.clone());
-
-                if let Some(preset_config) = project_config.get_preset(preset_name) {
-                    if !preset_config.args.is_empty() {
-                        spawn_config = spawn_config.with_args(preset_config.args.clone());
-                    }
-                    if let Some(ref prompt) = preset_config.initial_prompt {
-                        spawn_config = spawn_config.with_initial_prompt(prompt.as_str());
-                    }
-                }
-            } else if let Some(default_preset) = project_config.default_preset() {
-                spawn_config = spawn_config.with_preset(&default_preset.name);
-                if !default_preset.args.is_empty() {
-                    spawn_config = spawn_config.with_args(default_preset.args.clone());
-                }
-                if let Some(ref prompt) = default_preset.initial_prompt {
-                    spawn_config = spawn_config.with_initial_prompt(prompt.as_str());
-                }
+            connection.groups.insert(group.clone());
+            if let Some(attached) = &mut connection.attached {
+                attached.extend(agent_ids.iter().copied());
             }
-
-            match agent_manager.spawn_agent(spawn_config).await {
-                Ok(agent_id) => {
-                    info!("Agent spawned: {} for project {}", agent_id, project_path);
-                    Ok(Some(ServerMessage::agent_spawned(
-                        agent_id,
-                        project_path,
-                        cols.unwrap_or(DEFAULT_TERMINAL_COLS),
-                        rows.unwrap_or(DEFAULT_TERMINAL_ROWS),
-                    )))
-                }
-                Err(e) => {
-                    error!("Failed to spawn agent: {}", e);
-                    Ok(Some(ServerMessage::error_with_code(
-                        format!("Failed to spawn agent: {}", e),
-                        ErrorCode::SpawnFailed,
-                    )))
+            info!(
+                "Client {} joined group {} of {} agents",
+                connection.client,
+                group,
+                agent_ids.len()
+            );
+            return Ok(Some(ServerMessage::GroupJoined {
+                group: group.clone(),
+                agent_ids,
+                members,
+            }));
+        }
+        ClientMessage::LeaveGroup { ref group } => {
+            if !connection.groups.remove(group) {
+                return Ok(Some(ServerMessage::error_with_code(
+                    format!("Not in group {}", group),
+                    ErrorCode::GroupNotFound,
+                )));
+            }
+            state.groups.leave(group, connection.id);
+            if let Some(attached) = &mut connection.attached {
+                for agent_id in group_agent_ids(state, group).await {
+                    attached.remove(&agent_id);
                 }
             }
+            return Ok(Some(ServerMessage::GroupLeft {
+                group: group.clone(),
+            }));
         }
-        ClientMessage::AgentInput { agent_id, input } => {
-            debug!(
-                "AgentInput request: agent={}, input_len={}",
-                agent_id,
-                input.len()
+        ClientMessage::ClaimSession { ref session_token } => {
+            connection.owner = session_token.clone();
+            let agent_ids = state.agent_manager.agents_owned_by(session_token).await;
+            if let Some(attached) = &mut connection.attached {
+                attached.extend(agent_ids.iter().copied());
+            }
+            info!(
+                "Client {} claimed a session owning {} agents",
+                connection.client,
+                agent_ids.len()
             );
-            match agent_manager.send_input(agent_id, &input).await {
-                Ok(()) => Ok(None),
-                Err(e) => Ok(Some(ServerMessage::agent_error(
+            return Ok(Some(ServerMessage::SessionClaimed { agent_ids }));
+        }
+        ClientMessage::ResumeSession { ref resume_token } => {
+            return Ok(Some(resume_session(state, connection, resume_token).await));
+        }
+        ClientMessage::WatchPath { agent_id, ref path } => {
+            if let Some(error) = capability_error(state, &envelope.message) {
+                return Ok(Some(error));
+            }
+            let project_path = match agent_project_path(state, agent_id).await {
+                Ok(project_path) => project_path,
+                Err(error) => return Ok(Some(error)),
+            };
+            if connection.watches.len() >= MAX_WATCHES {
+                return Ok(Some(ServerMessage::agent_error(
                     agent_id,
-                    format!("Failed to send input: {}", e),
-                    ErrorCode::InternalError,
-                ))),
+                    format!("At most {} paths can be watched at once", MAX_WATCHES),
+                    ErrorCode::InvalidMessage,
+                )));
             }
+            let notice_tx = connection.notice_tx.clone();
+            let watched = connection
+                .watches
+                .watch(agent_id, path.clone(), &project_path, notice_tx);
+            return Ok(Some(match watched {
+                Ok(()) => ServerMessage::PathWatched {
+                    agent_id,
+                    path: path.clone(),
+                },
+                Err(e) => agent_fs_error(agent_id, e),
+            }));
         }
-        ClientMessage::KillAgent { agent_id, signal, .. } => {
-            // Note: `signal` is accepted by the protocol but not forwarded to the PTY layer
-            // because portable-pty only supports kill(), not arbitrary signal delivery.
-            if signal.is_some() {
-                debug!("KillAgent request: agent={} (signal={:?} ignored, using kill)", agent_id, signal);
-            } else {
-                debug!("KillAgent request: agent={}", agent_id);
+        ClientMessage::UnwatchPath { agent_id, ref path } => {
+            connection.watches.unwatch(agent_id, path.clone());
+            return Ok(Some(ServerMessage::PathUnwatched {
+                agent_id,
+                path: path.clone(),
+            }));
+        }
+        ClientMessage::ReplayRecording {
+            ref project_path,
+            ref recording_id,
+            speed,
+        } => {
+            if let Some(error) = allowed_root_error(state, &envelope.message) {
+                return Ok(Some(error));
             }
-            match agent_manager.kill_agent(agent_id).await {
-                Ok(()) => {
-                    info!("Agent killed: {}", agent_id);
-                    Ok(Some(ServerMessage::agent_exited(agent_id, None)))
+            let path = recording_path(Path::new(project_path), recording_id);
+            let cast = match path.map(|path| Cast::read(&path)) {
+                Some(Ok(cast)) => cast,
+                Some(Err(e)) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Ok(Some(ServerMessage::error_with_code(
+                        format!("Failed to read recording {}: {}", recording_id, e),
+                        ErrorCode::InternalError,
+                    )));
                 }
-                Err(e) => Ok(Some(ServerMessage::agent_error(
-                    agent_id,
-                    format!("Failed to kill agent: {}", e),
-                    ErrorCode::InternalError,
-                ))),
+                _ => {
+                    return Ok(Some(ServerMessage::error_with_code(
+                        format!("Recording not found: {}", recording_id),
+                        ErrorCode::RecordingNotFound,
+                    )));
+                }
+            };
+            // Playback arrives as notices, starting with replay_started
+            let speed = speed.unwrap_or(1.0);
+            tokio::spawn(replay(recording_id.clone(), cast, speed, connection.notice_tx.clone()));
+            return Ok(None);
+        }
+        ClientMessage::PushBranch {
+            ref project_path,
+            ref remote,
+            ref branch,
+            force,
+            ref token_secret,
+        } => {
+            if let Some(error) = transfer_error(state, &envelope.message) {
+                return Ok(Some(error));
             }
+            let request = Transfer {
+                operation: TransferOperation::Push,
+                project_path: project_path.clone(),
+                remote: remote.clone(),
+                branch: branch.clone(),
+                force,
+                token: None,
+            };
+            return Ok(start_transfer(state, connection, request, token_secret.clone()).await);
         }
-        ClientMessage::ResizeTerminal {
-            agent_id,
+        ClientMessage::PullBranch {
+            ref project_path,
+            ref remote,
+            ref branch,
+            ref token_secret,
+        } => {
+            if let Some(error) = transfer_error(state, &envelope.message) {
+                return Ok(Some(error));
+            }
+            let request = Transfer {
+                operation: TransferOperation::Pull,
+                project_path: project_path.clone(),
+                remote: remote.clone(),
+                branch: branch.clone(),
+                force: false,
+                token: None,
+            };
+            return Ok(start_transfer(state, connection, request, token_secret.clone()).await);
+        }
+        _ => {}
+    }
+
+    // Pastes to local agents are assembled per connection; chunks for
+    // federated agents are forwarded like any other message
+    if let ClientMessage::AgentInputChunk {
+        agent_id,
+        part,
+        of,
+        ref data,
+    } = envelope.message
+    {
+        if state.agent_manager.agent_exists(agent_id).await {
+            return Ok(Some(handle_input_chunk(state, connection, agent_id, part, of, data).await));
+        }
+    }
+    let response = handle_client_message(
+        envelope.message,
+        state,
+        Some(&connection.client),
+        Some(&connection.owner),
+    )
+    .await?;
+    // Clients receive the output of agents they spawn
+    if let (Some(ServerMessage::AgentSpawned { agent_id, .. }), Some(attached)) =
+        (&response, &mut connection.attached)
+    {
+        attached.insert(*agent_id);
+    }
+    Ok(response)
+}
+
+/// Take over the session of a lost connection: its attachments, input
+/// control and the output it missed
+async fn resume_session(
+    state: &ServerState,
+    connection: &mut Connection,
+    resume_token: &str,
+) -> ServerMessage {
+    let Some(ticket) = connection.resume.clone() else {
+        return ServerMessage::error_with_code(
+            "Sessions cannot be resumed on this server",
+            ErrorCode::InvalidMessage,
+        );
+    };
+    if ticket.token == resume_token {
+        return ServerMessage::error_with_code(
+            "The session is this connection's own",
+            ErrorCode::InvalidMessage,
+        );
+    }
+    let identity = Identity {
+        role: connection.role,
+        token_name: connection.token_name.clone(),
+    };
+    let session = match state.resumable.resume(resume_token, &identity, &ticket).await {
+        Ok(session) => session,
+        Err(e) => {
+            let code = match e {
+                ResumeError::Expired => ErrorCode::SessionExpired,
+                ResumeError::WrongIdentity => ErrorCode::PermissionDenied,
+                ResumeError::TakeoverTimedOut => ErrorCode::InternalError,
+            };
+            return ServerMessage::error_with_code(e.to_string(), code);
+        }
+    };
+
+    // The resumed session's token replaces the one this connection was given
+    state.resumable.remove(&ticket.token);
+    connection.resume = Some(ResumeTicket {
+        token: resume_token.to_string(),
+        ..ticket
+    });
+    let controlled = state.input_control.transfer(
+        session.connection_id,
+        connection.id,
+        &connection.client,
+    );
+    let running = all_agent_ids(state).await;
+    connection.attached = session
+        .attached
+        .map(|attached| attached.intersection(&running).copied().collect());
+    connection.owner = session.owner;
+    connection.stream_mode = session.stream_mode;
+    let mut agent_ids: Vec<Uuid> = match &connection.attached {
+        Some(attached) => attached.iter().copied().collect(),
+        None => running.into_iter().collect(),
+    };
+    agent_ids.sort();
+
+    let replayed_bytes = session.backlog.len() as u64;
+    let truncated = session.backlog.truncated();
+    connection.replay = session.backlog.into_chunks();
+    info!(
+        "Client {} resumed a session with {} agents and {} bytes of missed output",
+        connection.client,
+        agent_ids.len(),
+        replayed_bytes
+    );
+    ServerMessage::SessionResumed {
+        agent_ids,
+        controlled,
+        replayed_bytes,
+        truncated,
+    }
+}
+
+/// IDs of every local and federated agent
+async fn all_agent_ids(state: &ServerState) -> HashSet<Uuid> {
+    let local = state.agent_manager.list_agents().await;
+    let federated = state.federation.list_agents().await;
+    local
+        .into_iter()
+        .chain(federated)
+        .map(|info| info.agent_id)
+        .collect()
+}
+
+/// IDs of the local and federated agents spawned into a group
+async fn group_agent_ids(state: &ServerState, group: &str) -> Vec<Uuid> {
+    let local = state.agent_manager.list_agents().await;
+    let federated = state.federation.list_agents().await;
+    local
+        .into_iter()
+        .chain(federated)
+        .filter(|info| info.group.as_deref() == Some(group))
+        .map(|info| info.agent_id)
+        .collect()
+}
+
+/// Send broadcast input to each of its targets as an `agent_input` message
+/// passed to `send`, answering with the result for every agent
+async fn broadcast_input<C: Send>(
+    state: &ServerState,
+    context: &mut C,
+    agent_ids: Vec<Uuid>,
+    group: Option<&str>,
+    input: String,
+    paste: bool,
+    send: impl for<'a> Fn(
+        &'a ServerState,
+        &'a mut C,
+        ClientMessage,
+    ) -> BoxFuture<'a, anyhow::Result<Option<ServerMessage>>>,
+) -> anyhow::Result<Option<ServerMessage>> {
+    let agent_ids = match broadcast_targets(state, agent_ids, group).await {
+        Ok(agent_ids) => agent_ids,
+        Err(error) => return Ok(Some(error)),
+    };
+    debug!(
+        "BroadcastInput request: agents={}, input_len={}",
+        agent_ids.len(),
+        input.len()
+    );
+    let mut results = Vec::with_capacity(agent_ids.len());
+    for agent_id in agent_ids {
+        let response = if agent_known(state, agent_id).await {
+            let message = ClientMessage::AgentInput {
+                agent_id,
+                input: input.clone(),
+                paste,
+            };
+            send(state, context, message).await?
+        } else {
+            Some(ServerMessage::agent_error(
+                agent_id,
+                "Agent not found",
+                ErrorCode::AgentNotFound,
+            ))
+        };
+        results.push(BroadcastResult::from_response(agent_id, response.as_ref()));
+    }
+    Ok(Some(ServerMessage::InputBroadcast { results }))
+}
+
+/// Send one agent of a connection's broadcast its input, charging it to the
+/// connection's input rate
+fn send_envelope_input<'a>(
+    state: &'a ServerState,
+    connection: &'a mut Connection,
+    message: ClientMessage,
+) -> BoxFuture<'a, anyhow::Result<Option<ServerMessage>>> {
+    Box::pin(async move {
+        if let Some(error) = connection.limiter.input(&message, Instant::now()) {
+            return Ok(Some(error));
+        }
+        handle_envelope(ClientEnvelope::new(message), state, connection).await
+    })
+}
+
+/// Send one agent of a broadcast its input on behalf of a client and owner
+fn send_client_input<'a>(
+    state: &'a ServerState,
+    (client, owner): &'a mut (Option<&str>, Option<&str>),
+    message: ClientMessage,
+) -> BoxFuture<'a, anyhow::Result<Option<ServerMessage>>> {
+    Box::pin(handle_client_message(message, state, *client, *owner))
+}
+
+/// The agents broadcast input goes to: those listed, then the group's
+/// others, or the error to answer with if the group doesn't exist
+async fn broadcast_targets(
+    state: &ServerState,
+    agent_ids: Vec<Uuid>,
+    group: Option<&str>,
+) -> Result<Vec<Uuid>, ServerMessage> {
+    let mut targets = Vec::with_capacity(agent_ids.len());
+    let mut seen = HashSet::new();
+    let listed = agent_ids.into_iter();
+    let grouped = match group {
+        Some(group) => {
+            let grouped = group_agent_ids(state, group).await;
+            if grouped.is_empty() && !state.groups.contains(group) {
+                return Err(ServerMessage::error_with_code(
+                    format!("No group named {}", group),
+                    ErrorCode::GroupNotFound,
+                ));
+            }
+            grouped
+        }
+        None => Vec::new(),
+    };
+    for agent_id in listed.chain(grouped) {
+        if seen.insert(agent_id) {
+            targets.push(agent_id);
+        }
+    }
+    Ok(targets)
+}
+
+/// Groups that have members or agents, by name
+async fn list_groups(state: &ServerState) -> Vec<GroupInfo> {
+    let mut groups: BTreeMap<String, GroupInfo> = state
+        .groups
+        .members()
+        .into_iter()
+        .map(|(group, members)| {
+            let info = GroupInfo {
+                group: group.clone(),
+                agent_ids: Vec::new(),
+                members,
+            };
+            (group, info)
+        })
+        .collect();
+    let local = state.agent_manager.list_agents().await;
+    let federated = state.federation.list_agents().await;
+    for agent in local.into_iter().chain(federated) {
+        let Some(group) = agent.group else {
+            continue;
+        };
+        let info = groups.entry(group.clone()).or_insert_with(|| GroupInfo {
+            group,
+            agent_ids: Vec::new(),
+            members: Vec::new(),
+        });
+        info.agent_ids.push(agent.agent_id);
+    }
+    groups.into_values().collect()
+}
+
+/// Whether an agent is hosted locally or by a peer bridge
+async fn agent_known(state: &ServerState, agent_id: Uuid) -> bool {
+    state.agent_manager.agent_exists(agent_id).await
+        || state.federation.peer_for_agent(agent_id).await.is_some()
+}
+
+/// Add a paste chunk, starting the paced write once the paste is complete
+async fn handle_input_chunk(
+    state: &ServerState,
+    connection: &mut Connection,
+    agent_id: Uuid,
+    part: u32,
+    of: u32,
+    data: &str,
+) -> ServerMessage {
+    let paste = match connection.pastes.add(agent_id, part, of, data) {
+        Ok(None) => return ServerMessage::InputChunkAck { agent_id, part, of },
+        Ok(Some(paste)) => paste,
+        Err(e) => {
+            return ServerMessage::agent_error(agent_id, e.to_string(), ErrorCode::InvalidMessage)
+        }
+    };
+
+    let paste = match state.input_filter.apply(agent_id, paste) {
+        Ok(paste) => paste,
+        Err(e) => {
+            warn!("Refused paste for agent {}: {}", agent_id, e);
+            return ServerMessage::agent_error(agent_id, e.to_string(), e.code());
+        }
+    };
+    debug!("Writing {} byte paste to agent {}", paste.len(), agent_id);
+    let _ = state.agent_manager.record_input(agent_id, &paste).await;
+
+    let agent_manager = Arc::clone(&state.agent_manager);
+    let notice_tx = connection.notice_tx.clone();
+    tokio::spawn(async move {
+        let notice = match write_paced(agent_manager, agent_id, &paste).await {
+            Ok(()) => ServerMessage::PasteWritten {
+                agent_id,
+                bytes: paste.len(),
+            },
+            Err(e) => ServerMessage::agent_error(
+                agent_id,
+                format!("Failed to write paste: {}", e),
+                ErrorCode::InternalError,
+            ),
+        };
+        let _ = notice_tx.send(notice);
+    });
+
+    ServerMessage::InputChunkAck { agent_id, part, of }
+}
+
+/// Error for a message needing a capability the server has disabled
+fn capability_error(state: &ServerState, message: &ClientMessage) -> Option<ServerMessage> {
+    let capability = message.capability()?;
+    state
+        .config
+        .disabled_capabilities
+        .contains(&capability)
+        .then(|| {
+            ServerMessage::error_with_code(
+                format!("The {} capability is disabled on this server", capability.as_str()),
+                ErrorCode::CapabilityDisabled,
+            )
+        })
+}
+
+/// Error for a message the client's role does not allow
+pub(super) fn role_error(role: Role, message: &ClientMessage) -> Option<ServerMessage> {
+    let required = message.required_role();
+    (role < required).then(|| {
+        ServerMessage::error_with_code(
+            format!(
+                "This request needs the {} role; the client is {}",
+                required.as_str(),
+                role.as_str()
+            ),
+            ErrorCode::PermissionDenied,
+        )
+    })
+}
+
+/// Error for a message naming a project path outside the allowed roots
+///
+/// Without configured roots every path is accepted. New worktrees must be
+/// created below a root as well.
+fn allowed_root_error(state: &ServerState, message: &ClientMessage) -> Option<ServerMessage> {
+    let project_path = message.project_path()?;
+    let allowed_roots = state.live.get().allowed_roots;
+    if allowed_roots.is_empty() {
+        return None;
+    }
+    let sandbox = Sandbox::new(&allowed_roots);
+    let resolved = match message {
+        ClientMessage::CreateWorktree {
+            path: Some(path), ..
+        } => sandbox
+            .resolve(project_path)
+            .and_then(|_| sandbox.resolve_new(path)),
+        _ => sandbox.resolve(project_path),
+    };
+    resolved.err().map(fs_error)
+}
+
+/// Validate and handle a request from a front end without connections of
+/// its own, such as gRPC or REST, writing it to the audit log as made by
+/// `audit_client` with `role`
+///
+/// Invalid requests are answered with an error message, like responses.
+pub(super) async fn dispatch_request(
+    state: &ServerState,
+    message: ClientMessage,
+    client: Option<&str>,
+    audit_client: &str,
+    role: Role,
+) -> anyhow::Result<Option<ServerMessage>> {
+    if let Err(e) = message.validate_with_limits(&state.config.terminal) {
+        return Ok(Some(ServerMessage::from(e)));
+    }
+    let audit = AuditEvent::for_request(&message);
+    let result = handle_client_message(message, state, client, None).await;
+    if let (Some(mut event), Some(log)) = (audit, &state.config.audit_log) {
+        let error = match &result {
+            Ok(response) => event.complete(response.as_ref()),
+            Err(e) => Some(e.to_string()),
+        };
+        let record = AuditRecord::new(audit_client, event)
+            .with_identity(role, None)
+            .with_error(error);
+        log.write(&record);
+    }
+    result
+}
+
+/// Handle an already validated client message
+///
+/// Shared by every front end (WebSocket, QUIC, gRPC). Agents spawned are owned
+/// by `owner` when given. Returns `Ok(None)` when no response is needed or the
+/// request was forwarded to a peer bridge.
+pub(super) async fn handle_client_message(
+    message: ClientMessage,
+    state: &ServerState,
+    client: Option<&str>,
+    owner: Option<&str>,
+) -> anyhow::Result<Option<ServerMessage>> {
+    let agent_manager = &state.agent_manager;
+
+    if let Some(error) = capability_error(state, &message) {
+        return Ok(Some(error));
+    }
+    if let Some(error) = allowed_root_error(state, &message) {
+        return Ok(Some(error));
+    }
+
+    // Proxy requests for agents hosted by peer bridges; their responses are
+    // relayed back asynchronously through the federation event channel
+    if let Some(agent_id) = message.agent_id() {
+        if !agent_manager.agent_exists(agent_id).await
+            && state.federation.forward(agent_id, message.clone()).await
+        {
+            debug!("Forwarded request for agent {} to peer bridge", agent_id);
+            return Ok(None);
+        }
+    }
+
+    match message {
+        ClientMessage::Authenticate { .. } => {
+            warn!("Received unexpected Authenticate message after connection established");
+            Ok(Some(ServerMessage::error_with_code(
+                "Already authenticated",
+                ErrorCode::InvalidMessage,
+            )))
+        }
+        ClientMessage::Ping { seq } => {
+            debug!("Received ping with seq {}", seq);
+            Ok(Some(ServerMessage::Pong { seq }))
+        }
+        ClientMessage::SpawnAgent {
+            project_path,
+            preset,
+            cols,
+            rows,
+            tags,
+            group,
+            name,
+            mode,
+            prompt,
+            env,
+            cwd,
+            report_echo,
+        } => {
+            debug!(
+                "SpawnAgent request: project={}, preset={:?}, mode={:?}",
+                project_path, preset, mode
+            );
+
+            // Validate project path exists
+            let path = Path::new(&project_path);
+            if !path.exists() {
+                return Ok(Some(ServerMessage::error_with_code(
+                    format!("Project path does not exist: {}", project_path),
+                    ErrorCode::InvalidPath,
+                )));
+            }
+            if !path.is_dir() {
+                return Ok(Some(ServerMessage::error_with_code(
+                    format!("Project path is not a directory: {}", project_path),
+                    ErrorCode::InvalidPath,
+                )));
+            }
+
+            // Load project config to get preset settings
+            let project_config = ProjectConfig::load(path)
+                .unwrap_or_default()
+                .with_global_presets(&state.config.presets);
+
+            // Build spawn config with preset args and initial prompt
+            let mut spawn_config = SpawnConfig::new(&project_path)
+                .with_size(
+                    cols.unwrap_or(state.config.terminal.default_cols),
+                    rows.unwrap_or(state.config.terminal.default_rows),
+                )
+                .with_macros(project_config.macros.iter().cloned())
+                .with_tags(tags);
+            if let Some(client) = client {
+                spawn_config = spawn_config.with_spawned_by(client);
+            }
+            if let Some(owner) = owner {
+                spawn_config = spawn_config.with_owner(owner);
+            }
+            if let Some(group) = group {
+                spawn_config = spawn_config.with_group(group);
+            }
+            if let Some(name) = name {
+                spawn_config = spawn_config.with_name(name);
+            }
+            if let Some(timeout) = state.config.idle_timeout {
+                spawn_config = spawn_config.with_idle_timeout(timeout);
+            }
+            if let Some(transcript) = state.config.transcript {
+                spawn_config = spawn_config.with_transcript(transcript);
+            }
+
+            // Apply preset if specified, falling back to the project's default preset
+            if let Some(preset_name) = &preset {
+                spawn_config = match project_config.get_preset(preset_name) {
+                    Some(preset_config) => spawn_config.apply_preset(preset_config),
+                    None => spawn_config.with_preset(preset_name.clone()),
+                };
+            } else if let Some(default_preset) = project_config.default_preset() {
+                spawn_config = spawn_config.apply_preset(default_preset);
+            }
+            if let Some(mode) = mode {
+                spawn_config = spawn_config.with_mode(mode);
+            }
+            if let Some(prompt) = prompt {
+                spawn_config = spawn_config.with_initial_prompt(prompt);
+            }
+            spawn_config = spawn_config.with_env(env);
+            if let Some(cwd) = cwd {
+                if !path.join(&cwd).is_dir() {
+                    return Ok(Some(ServerMessage::error_with_code(
+                        format!("Working directory is not a directory: {}", cwd),
+                        ErrorCode::InvalidPath,
+                    )));
+                }
+                spawn_config = spawn_config.with_cwd(cwd);
+            }
+            if report_echo {
+                spawn_config = spawn_config.with_echo_reports();
+            }
+            if let Err(e) = state.config.env_policy.check(&spawn_config.env) {
+                return Ok(Some(ServerMessage::error_with_code(
+                    e.to_string(),
+                    ErrorCode::PermissionDenied,
+                )));
+            }
+
+            // Resolve the keybinding profile the effective preset selects
+            let profile = spawn_config
+                .preset
+                .as_deref()
+                .and_then(|name| project_config.get_preset(name))
+                .and_then(|preset| preset.keybindings.as_deref());
+            match project_config.keybindings(profile) {
+                Some(keybindings) => spawn_config = spawn_config.with_keybindings(keybindings),
+                None => warn!("Unknown keybinding profile {:?}, using default", profile),
+            }
+
+            if state.config.persistent_sessions {
+                spawn_config = spawn_config.with_persistence();
+            }
+
+            if !spawn_config.secrets.is_empty() {
+                match state.config.secrets.resolve(&spawn_config.secrets).await {
+                    Ok(env) => spawn_config = spawn_config.with_secret_env(env),
+                    Err(e) => {
+                        error!("Failed to look up secrets: {}", e);
+                        return Ok(Some(ServerMessage::error_with_code(
+                            format!("Failed to spawn agent: {}", e),
+                            ErrorCode::SpawnFailed,
+                        )));
+                    }
+                }
+            }
+
+            let running = agent_manager.list_agents().await;
+            let _reservation = match state.agent_quota.reserve(client, &running) {
+                Ok(reservation) => reservation,
+                Err(e) => return Ok(Some(e.to_message())),
+            };
+            match state.spawner.spawn_agent(spawn_config).await {
+                Ok(agent_id) => {
+                    info!("Agent spawned: {} for project {}", agent_id, project_path);
+                    Ok(Some(ServerMessage::agent_spawned(
+                        agent_id,
+                        project_path,
+                        cols.unwrap_or(state.config.terminal.default_cols),
+                        rows.unwrap_or(state.config.terminal.default_rows),
+                    )))
+                }
+                Err(e) => {
+                    error!("Failed to spawn agent: {}", e);
+                    Ok(Some(ServerMessage::error_with_code(
+                        format!("Failed to spawn agent: {}", e),
+                        ErrorCode::SpawnFailed,
+                    )))
+                }
+            }
+        }
+        ClientMessage::AdoptSession {
+            multiplexer,
+            target,
+            host,
+            project_path,
+            cols,
+            rows,
+        } => {
+            debug!(
+                "AdoptSession request: {:?} target={} host={:?}",
+                multiplexer, target, host
+            );
+
+            // The adopted session has no project of its own; report the home directory
+            let project_path = project_path
+                .or_else(|| std::env::var("HOME").ok())
+                .unwrap_or_else(|| "/".to_string());
+            let cols = cols.unwrap_or(state.config.terminal.default_cols);
+            let rows = rows.unwrap_or(state.config.terminal.default_rows);
+
+            let mut spawn_config = SpawnConfig::new(&project_path)
+                .with_size(cols, rows)
+                .with_adopt(ExternalSession::new(multiplexer, target.clone()));
+            if let Some(host) = host {
+                spawn_config = spawn_config.with_remote(SshTarget::new(host));
+            }
+            if let Some(client) = client {
+                spawn_config = spawn_config.with_spawned_by(client);
+            }
+            if let Some(owner) = owner {
+                spawn_config = spawn_config.with_owner(owner);
+            }
+
+            let running = agent_manager.list_agents().await;
+            let _reservation = match state.agent_quota.reserve(client, &running) {
+                Ok(reservation) => reservation,
+                Err(e) => return Ok(Some(e.to_message())),
+            };
+            match state.spawner.spawn_agent(spawn_config).await {
+                Ok(agent_id) => {
+                    info!("Agent {} adopted session {}", agent_id, target);
+                    Ok(Some(ServerMessage::agent_spawned(
+                        agent_id,
+                        project_path,
+                        cols,
+                        rows,
+                    )))
+                }
+                Err(e) => {
+                    error!("Failed to adopt session {}: {}", target, e);
+                    Ok(Some(ServerMessage::error_with_code(
+                        format!("Failed to adopt session: {}", e),
+                        ErrorCode::SpawnFailed,
+                    )))
+                }
+            }
+        }
+        ClientMessage::AttachExternal { target } => {
+            debug!("AttachExternal request: target={}", target);
+            let session = match ExternalSession::resolve(&target) {
+                Ok(session) => session,
+                Err(e) => {
+                    return Ok(Some(ServerMessage::error_with_code(
+                        format!("Failed to attach to {}: {}", target, e),
+                        ErrorCode::SpawnFailed,
+                    )))
+                }
+            };
+            // Attached like any adopted session
+            let adopt = ClientMessage::AdoptSession {
+                multiplexer: session.multiplexer,
+                target: session.target,
+                host: None,
+                project_path: None,
+                cols: None,
+                rows: None,
+            };
+            Box::pin(handle_client_message(adopt, state, client, owner)).await
+        }
+        ClientMessage::AgentInput {
+            agent_id,
+            input,
+            paste,
+        } => {
+            debug!(
+                "AgentInput request: agent={}, input_len={}",
+                agent_id,
+                input.len()
+            );
+            let modes = agent_manager.input_modes(agent_id).await.unwrap_or_default();
+            let input = if paste && modes.bracketed_paste {
+                bracketed_paste(&input)
+            } else {
+                input
+            };
+            Ok(send_agent_input(state, agent_id, input, true).await)
+        }
+        ClientMessage::BroadcastInput {
+            agent_ids,
+            group,
+            input,
+            paste,
+        } => {
+            broadcast_input(
+                state,
+                &mut (client, owner),
+                agent_ids,
+                group.as_deref(),
+                input,
+                paste,
+                send_client_input,
+            )
+            .await
+        }
+        ClientMessage::AgentKey { agent_id, key } => {
+            debug!("AgentKey request: agent={}, key={}", agent_id, key);
+            let modes = agent_manager.input_modes(agent_id).await.unwrap_or_default();
+            match key_sequence(&key, modes) {
+                Some(input) => Ok(send_agent_input(state, agent_id, input, false).await),
+                None => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Unknown key: {}", key),
+                    ErrorCode::InvalidMessage,
+                ))),
+            }
+        }
+        ClientMessage::SetClipboard { agent_id, text } => {
+            debug!("SetClipboard request: agent={}, text_len={}", agent_id, text.len());
+            if !agent_manager.agent_exists(agent_id).await {
+                return Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Agent not found: {}", agent_id),
+                    ErrorCode::AgentNotFound,
+                )));
+            }
+            let entry = state.clipboards.set(agent_id, text, client);
+            Ok(Some(clipboard_message(agent_id, Some(entry))))
+        }
+        ClientMessage::GetClipboard { agent_id } => {
+            debug!("GetClipboard request: agent={}", agent_id);
+            if !agent_manager.agent_exists(agent_id).await {
+                return Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Agent not found: {}", agent_id),
+                    ErrorCode::AgentNotFound,
+                )));
+            }
+            let entry = state.clipboards.get(agent_id);
+            Ok(Some(clipboard_message(agent_id, entry)))
+        }
+        ClientMessage::GetEchoState { agent_id } => {
+            debug!("GetEchoState request: agent={}", agent_id);
+            match agent_manager.echo_state(agent_id).await {
+                Ok(EchoState { echo, raw }) => Ok(Some(ServerMessage::EchoState {
+                    agent_id,
+                    echo,
+                    raw,
+                })),
+                Err(e) => Ok(Some(echo_error(agent_id, e))),
+            }
+        }
+        ClientMessage::SetRawMode { agent_id, raw } => {
+            debug!("SetRawMode request: agent={}, raw={}", agent_id, raw);
+            match agent_manager.set_raw_mode(agent_id, raw).await {
+                Ok(EchoState { echo, raw }) => Ok(Some(ServerMessage::EchoState {
+                    agent_id,
+                    echo,
+                    raw,
+                })),
+                Err(e) => Ok(Some(echo_error(agent_id, e))),
+            }
+        }
+        ClientMessage::AgentInputRaw { agent_id, data } => {
+            let input = match decode_raw_input(&data) {
+                Ok(input) => input,
+                Err(e) => return Ok(Some(ServerMessage::from(e))),
+            };
+            debug!(
+                "AgentInputRaw request: agent={}, input_len={}",
+                agent_id,
+                input.len()
+            );
+            let input = match state.input_filter.apply_bytes(agent_id, input) {
+                Ok(input) => input,
+                Err(e) => {
+                    warn!("Refused input for agent {}: {}", agent_id, e);
+                    return Ok(Some(ServerMessage::agent_error(agent_id, e.to_string(), e.code())));
+                }
+            };
+            match agent_manager.send_bytes(agent_id, &input).await {
+                Ok(()) => {
+                    // Only text is kept in the history
+                    if let Ok(text) = std::str::from_utf8(&input) {
+                        let _ = agent_manager.record_input(agent_id, text).await;
+                    }
+                    Ok(None)
+                }
+                Err(e) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Failed to send input: {}", e),
+                    ErrorCode::InternalError,
+                ))),
+            }
+        }
+        ClientMessage::RequestControl { agent_id }
+        | ClientMessage::GrantControl { agent_id, .. }
+        | ClientMessage::ReleaseControl { agent_id } => Ok(Some(ServerMessage::agent_error(
+            agent_id,
+            "Input control requires a streaming connection",
+            ErrorCode::InvalidMessage,
+        ))),
+        ClientMessage::AgentInputChunk { agent_id, .. } => Ok(Some(ServerMessage::agent_error(
+            agent_id,
+            "Chunked input requires a streaming connection",
+            ErrorCode::InvalidMessage,
+        ))),
+        ClientMessage::SetStreamMode { .. } => Ok(Some(ServerMessage::error_with_code(
+            "Stream modes require a streaming connection",
+            ErrorCode::InvalidMessage,
+        ))),
+        ClientMessage::AttachAgent { agent_id } | ClientMessage::DetachAgent { agent_id } => {
+            Ok(Some(ServerMessage::agent_error(
+                agent_id,
+                "Attaching agents requires a streaming connection",
+                ErrorCode::InvalidMessage,
+            )))
+        }
+        ClientMessage::ClaimSession { .. } | ClientMessage::ResumeSession { .. } => {
+            Ok(Some(ServerMessage::error_with_code(
+                "Sessions require a streaming connection",
+                ErrorCode::InvalidMessage,
+            )))
+        }
+        ClientMessage::CreateGroup { .. }
+        | ClientMessage::JoinGroup { .. }
+        | ClientMessage::LeaveGroup { .. } => Ok(Some(ServerMessage::error_with_code(
+            "Session groups require a streaming connection",
+            ErrorCode::InvalidMessage,
+        ))),
+        ClientMessage::ListGroups => Ok(Some(ServerMessage::GroupList {
+            groups: list_groups(state).await,
+        })),
+        ClientMessage::WatchPath { .. } | ClientMessage::UnwatchPath { .. } => {
+            Ok(Some(ServerMessage::error_with_code(
+                "Watching paths requires a streaming connection",
+                ErrorCode::InvalidMessage,
+            )))
+        }
+        ClientMessage::ReplayRecording { .. } => Ok(Some(ServerMessage::error_with_code(
+            "Replaying recordings requires a streaming connection",
+            ErrorCode::InvalidMessage,
+        ))),
+        ClientMessage::PushBranch { .. } | ClientMessage::PullBranch { .. } => {
+            Ok(Some(ServerMessage::error_with_code(
+                "Pushing and pulling require a streaming connection",
+                ErrorCode::InvalidMessage,
+            )))
+        }
+        ClientMessage::ListWorktrees { project_path } => {
+            debug!("ListWorktrees request: project={}", project_path);
+            let worktrees = open_repository(Path::new(&project_path))
+                .and_then(|repo| list_worktrees(&repo));
+            match worktrees {
+                Ok(worktrees) => Ok(Some(ServerMessage::WorktreeList { worktrees })),
+                Err(e) => Ok(Some(git_error(e))),
+            }
+        }
+        ClientMessage::CreateWorktree {
+            project_path,
+            branch,
+            path,
+            create_branch,
+        } => {
+            debug!(
+                "CreateWorktree request: project={}, branch={}, path={:?}",
+                project_path, branch, path
+            );
+            let worktree = open_repository(Path::new(&project_path)).and_then(|repo| {
+                add_worktree(&repo, &branch, path.as_deref().map(Path::new), create_branch)
+            });
+            match worktree {
+                Ok(worktree) => {
+                    info!("Created worktree {} for branch {}", worktree.path, branch);
+                    Ok(Some(ServerMessage::WorktreeCreated { worktree }))
+                }
+                Err(e) => Ok(Some(git_error(e))),
+            }
+        }
+        ClientMessage::RemoveWorktree {
+            project_path,
+            path,
+            force,
+        } => {
+            debug!("RemoveWorktree request: project={}, path={}", project_path, path);
+            // Pulling the directory from under a running agent would break it
+            let target = std::fs::canonicalize(&path).ok();
+            let in_use = agent_manager.list_agents().await.into_iter().find(|info| {
+                let worktree = info.worktree.as_ref().and_then(|w| std::fs::canonicalize(w).ok());
+                target.is_some() && worktree == target
+            });
+            if let Some(info) = in_use {
+                return Ok(Some(ServerMessage::error_with_code(
+                    format!("Worktree {} is in use by agent {}", path, info.agent_id),
+                    ErrorCode::GitFailed,
+                )));
+            }
+            let removed = open_repository(Path::new(&project_path))
+                .and_then(|repo| remove_worktree(&repo, Path::new(&path), force));
+            match removed {
+                Ok(()) => {
+                    info!("Removed worktree {}", path);
+                    Ok(Some(ServerMessage::WorktreeRemoved { path }))
+                }
+                Err(e) => Ok(Some(git_error(e))),
+            }
+        }
+        ClientMessage::ListBranches {
+            project_path,
+            remote,
+        } => {
+            debug!("ListBranches request: project={}, remote={}", project_path, remote);
+            let branches = open_repository(Path::new(&project_path))
+                .and_then(|repo| list_branches(&repo, remote));
+            match branches {
+                Ok(branches) => Ok(Some(ServerMessage::BranchList { branches })),
+                Err(e) => Ok(Some(git_error(e))),
+            }
+        }
+        ClientMessage::CreateBranch {
+            project_path,
+            branch,
+            start_point,
+        } => {
+            debug!(
+                "CreateBranch request: project={}, branch={}, start_point={:?}",
+                project_path, branch, start_point
+            );
+            let created = open_repository(Path::new(&project_path))
+                .and_then(|repo| create_branch(&repo, &branch, start_point.as_deref()));
+            match created {
+                Ok(branch) => {
+                    info!("Created branch {} at {}", branch.name, branch.commit_id);
+                    Ok(Some(ServerMessage::BranchCreated { branch }))
+                }
+                Err(e) => Ok(Some(git_error(e))),
+            }
+        }
+        ClientMessage::CheckoutBranch {
+            project_path,
+            branch,
+        } => {
+            debug!("CheckoutBranch request: project={}, branch={}", project_path, branch);
+            let checked_out = open_repository(Path::new(&project_path))
+                .and_then(|repo| checkout_branch(&repo, &branch));
+            match checked_out {
+                Ok(()) => {
+                    info!("Checked out branch {} in {}", branch, project_path);
+                    Ok(Some(ServerMessage::BranchCheckedOut {
+                        project_path,
+                        branch,
+                    }))
+                }
+                Err(e) => Ok(Some(git_error(e))),
+            }
+        }
+        ClientMessage::DeleteBranch {
+            project_path,
+            branch,
+            force,
+        } => {
+            debug!(
+                "DeleteBranch request: project={}, branch={}, force={}",
+                project_path, branch, force
+            );
+            let deleted = open_repository(Path::new(&project_path))
+                .and_then(|repo| delete_branch(&repo, &branch, force));
+            match deleted {
+                Ok(()) => {
+                    info!("Deleted branch {}", branch);
+                    Ok(Some(ServerMessage::BranchDeleted { branch }))
+                }
+                Err(e) => Ok(Some(git_error(e))),
+            }
+        }
+        ClientMessage::GetDiff {
+            agent_id,
+            path,
+            staged,
+        } => {
+            debug!(
+                "GetDiff request: agent={}, path={:?}, staged={}",
+                agent_id, path, staged
+            );
+            let project_path = match agent_project_path(state, agent_id).await {
+                Ok(project_path) => project_path,
+                Err(error) => return Ok(Some(error)),
+            };
+            let diff = open_repository(Path::new(&project_path))
+                .and_then(|repo| diff_worktree(&repo, path.as_deref(), staged));
+            match diff {
+                Ok(diff) => Ok(Some(ServerMessage::Diff {
+                    agent_id,
+                    staged,
+                    files: diff.files,
+                    truncated: diff.truncated,
+                })),
+                Err(e) => Ok(Some(agent_git_error(agent_id, e))),
+            }
+        }
+        ClientMessage::StageFiles { agent_id, paths } => {
+            debug!("StageFiles request: agent={}, paths={:?}", agent_id, paths);
+            let project_path = match agent_project_path(state, agent_id).await {
+                Ok(project_path) => project_path,
+                Err(error) => return Ok(Some(error)),
+            };
+            match open_repository(Path::new(&project_path)).and_then(|r| stage_paths(&r, &paths)) {
+                Ok(()) => Ok(Some(ServerMessage::FilesStaged { agent_id, paths })),
+                Err(e) => Ok(Some(agent_git_error(agent_id, e))),
+            }
+        }
+        ClientMessage::UnstageFiles { agent_id, paths } => {
+            debug!("UnstageFiles request: agent={}, paths={:?}", agent_id, paths);
+            let project_path = match agent_project_path(state, agent_id).await {
+                Ok(project_path) => project_path,
+                Err(error) => return Ok(Some(error)),
+            };
+            let unstaged = open_repository(Path::new(&project_path))
+                .and_then(|repo| unstage_paths(&repo, &paths));
+            match unstaged {
+                Ok(()) => Ok(Some(ServerMessage::FilesUnstaged { agent_id, paths })),
+                Err(e) => Ok(Some(agent_git_error(agent_id, e))),
+            }
+        }
+        ClientMessage::CommitChanges {
+            agent_id,
+            message,
+            author_name,
+            author_email,
+        } => {
+            debug!("CommitChanges request: agent={}", agent_id);
+            let project_path = match agent_project_path(state, agent_id).await {
+                Ok(project_path) => project_path,
+                Err(error) => return Ok(Some(error)),
+            };
+            let author = author_name.as_deref().zip(author_email.as_deref());
+            let committed = open_repository(Path::new(&project_path))
+                .and_then(|repo| commit_staged(&repo, &message, author));
+            match committed {
+                Ok(commit_id) => {
+                    info!("Committed {} for agent {}", commit_id, agent_id);
+                    let branch = worktree_for(Path::new(&project_path))
+                        .ok()
+                        .and_then(|w| w.branch);
+                    Ok(Some(ServerMessage::ChangesCommitted {
+                        agent_id,
+                        commit_id,
+                        branch,
+                    }))
+                }
+                Err(e) => Ok(Some(agent_git_error(agent_id, e))),
+            }
+        }
+        ClientMessage::ListDirectory { path, hidden } => {
+            debug!("ListDirectory request: path={}", path);
+            let listing = file_sandbox(state)
+                .await
+                .resolve(&path)
+                .and_then(|dir| Ok((list_directory(&dir, hidden)?, dir)));
+            match listing {
+                Ok((listing, dir)) => Ok(Some(ServerMessage::DirectoryListing {
+                    path: dir.display().to_string(),
+                    entries: listing.entries,
+                    truncated: listing.truncated,
+                })),
+                Err(e) => Ok(Some(fs_error(e))),
+            }
+        }
+        ClientMessage::ReadFile {
+            path,
+            offset,
+            limit,
+        } => {
+            debug!("ReadFile request: path={}, offset={:?}, limit={:?}", path, offset, limit);
+            let offset = offset.unwrap_or(0);
+            let limit = limit.unwrap_or(DEFAULT_READ_BYTES);
+            let chunk = file_sandbox(state)
+                .await
+                .resolve(&path)
+                .and_then(|file| Ok((read_file(&file, offset, limit)?, file)));
+            match chunk {
+                Ok((chunk, file)) => Ok(Some(ServerMessage::file_contents(
+                    file.display().to_string(),
+                    offset,
+                    chunk.size,
+                    chunk.data,
+                ))),
+                Err(e) => Ok(Some(fs_error(e))),
+            }
+        }
+        ClientMessage::StatPath { path } => {
+            debug!("StatPath request: path={}", path);
+            let entry = file_sandbox(state)
+                .await
+                .resolve(&path)
+                .and_then(|path| Ok((stat_path(&path)?, path)));
+            match entry {
+                Ok((entry, path)) => Ok(Some(ServerMessage::PathStat {
+                    path: path.display().to_string(),
+                    entry,
+                })),
+                Err(e) => Ok(Some(fs_error(e))),
+            }
+        }
+        ClientMessage::WriteFile {
+            agent_id,
+            path,
+            content,
+            encoding,
+            stage,
+        } => {
+            debug!("WriteFile request: agent={}, path={}, stage={}", agent_id, path, stage);
+            let project_path = match agent_project_path(state, agent_id).await {
+                Ok(project_path) => project_path,
+                Err(error) => return Ok(Some(error)),
+            };
+            let data = match decode_file_content(&content, encoding) {
+                Ok(data) => data,
+                Err(e) => {
+                    return Ok(Some(ServerMessage::agent_error(
+                        agent_id,
+                        e.to_string(),
+                        ErrorCode::InvalidMessage,
+                    )))
+                }
+            };
+            let written = Sandbox::project(&project_path).and_then(|(sandbox, project)| {
+                let file = sandbox.resolve_new(&project.join(&path).to_string_lossy())?;
+                write_file(&file, &data)?;
+                Ok(file)
+            });
+            let file = match written {
+                Ok(file) => file,
+                Err(e) => return Ok(Some(agent_fs_error(agent_id, e))),
+            };
+            info!("Client wrote {} in the project of agent {}", path, agent_id);
+            if stage {
+                if let Err(e) = open_repository(&file).and_then(|repo| stage_file(&repo, &file)) {
+                    return Ok(Some(agent_git_error(agent_id, e)));
+                }
+            }
+            Ok(Some(ServerMessage::FileWritten {
+                agent_id,
+                path,
+                size: data.len() as u64,
+                staged: stage,
+            }))
+        }
+        ClientMessage::ApplyPatch {
+            agent_id,
+            patch,
+            stage,
+        } => {
+            debug!("ApplyPatch request: agent={}, stage={}", agent_id, stage);
+            let project_path = match agent_project_path(state, agent_id).await {
+                Ok(project_path) => project_path,
+                Err(error) => return Ok(Some(error)),
+            };
+            let applied = open_repository(Path::new(&project_path))
+                .and_then(|repo| apply_patch(&repo, &patch, stage));
+            match applied {
+                Ok(paths) => {
+                    info!("Client patched {:?} in the repository of agent {}", paths, agent_id);
+                    Ok(Some(ServerMessage::PatchApplied {
+                        agent_id,
+                        paths,
+                        staged: stage,
+                    }))
+                }
+                Err(e) => Ok(Some(agent_git_error(agent_id, e))),
+            }
+        }
+        ClientMessage::StartRecording { agent_id } => {
+            debug!("StartRecording request: agent={}", agent_id);
+            match agent_manager.start_recording(agent_id).await {
+                Ok(recording_id) => Ok(Some(ServerMessage::RecordingStarted {
+                    agent_id,
+                    recording_id,
+                })),
+                Err(e) => Ok(Some(recording_error(agent_id, e))),
+            }
+        }
+        ClientMessage::StopRecording { agent_id } => {
+            debug!("StopRecording request: agent={}", agent_id);
+            match agent_manager.stop_recording(agent_id).await {
+                Ok((recording_id, duration)) => Ok(Some(ServerMessage::RecordingStopped {
+                    agent_id,
+                    recording_id,
+                    duration_ms: duration.as_millis() as u64,
+                })),
+                Err(e) => Ok(Some(recording_error(agent_id, e))),
+            }
+        }
+        ClientMessage::ListRecordings { project_path } => {
+            debug!("ListRecordings request: project={}", project_path);
+            match list_recordings(Path::new(&project_path)) {
+                Ok(recordings) => Ok(Some(ServerMessage::RecordingList { recordings })),
+                Err(e) => Ok(Some(ServerMessage::error_with_code(
+                    format!("Failed to list recordings: {}", e),
+                    ErrorCode::InternalError,
+                ))),
+            }
+        }
+        ClientMessage::RunMacro { agent_id, name } => {
+            debug!("RunMacro request: agent={}, macro={}", agent_id, name);
+            match agent_manager.expand_macro(agent_id, &name).await {
+                Ok(input) => Ok(send_agent_input(state, agent_id, input, true).await),
+                Err(ManagerError::MacroNotFound(_)) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Unknown macro: {}", name),
+                    ErrorCode::MacroNotFound,
+                ))),
+                Err(e) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Failed to run macro: {}", e),
+                    ErrorCode::AgentNotFound,
+                ))),
+            }
+        }
+        ClientMessage::SendKey { agent_id, action } => {
+            debug!("SendKey request: agent={}, action={}", agent_id, action);
+            match agent_manager.key_sequence(agent_id, &action).await {
+                Ok(input) => Ok(send_agent_input(state, agent_id, input, false).await),
+                Err(ManagerError::KeyNotBound(_)) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("No key bound to action: {}", action),
+                    ErrorCode::KeyNotBound,
+                ))),
+                Err(e) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Failed to send key: {}", e),
+                    ErrorCode::AgentNotFound,
+                ))),
+            }
+        }
+        ClientMessage::ConfirmationReply { agent_id, option } => {
+            debug!("ConfirmationReply request: agent={}, option={}", agent_id, option);
+            match agent_manager.answer_confirmation(agent_id, option).await {
+                Ok(()) => Ok(None),
+                Err(e @ ManagerError::NoPendingConfirmation(_)) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    e.to_string(),
+                    ErrorCode::NoPendingConfirmation,
+                ))),
+                Err(e @ ManagerError::InvalidOption(_)) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    e.to_string(),
+                    ErrorCode::InvalidMessage,
+                ))),
+                Err(e) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Failed to answer confirmation: {}", e),
+                    ErrorCode::AgentNotFound,
+                ))),
+            }
+        }
+        ClientMessage::ApproveAction { agent_id, always } => {
+            debug!("ApproveAction request: agent={}, always={}", agent_id, always);
+            let decision = if always {
+                ApprovalDecision::AlwaysApprove
+            } else {
+                ApprovalDecision::Approve
+            };
+            match agent_manager.answer_approval(agent_id, decision).await {
+                Ok(()) => Ok(None),
+                Err(e) => Ok(Some(approval_error(agent_id, e))),
+            }
+        }
+        ClientMessage::DenyAction { agent_id } => {
+            debug!("DenyAction request: agent={}", agent_id);
+            match agent_manager.answer_approval(agent_id, ApprovalDecision::Deny).await {
+                Ok(()) => Ok(None),
+                Err(e) => Ok(Some(approval_error(agent_id, e))),
+            }
+        }
+        ClientMessage::EnqueueTask {
+            project_path,
+            prompt,
+            preset,
+        } => {
+            debug!("EnqueueTask request: project={}", project_path);
+            let client = client.map(str::to_string);
+            match state.tasks.enqueue(project_path, prompt, preset, client) {
+                Ok((task_id, position)) => {
+                    Ok(Some(ServerMessage::TaskQueued { task_id, position }))
+                }
+                Err(e) => Ok(Some(ServerMessage::error_with_code(
+                    e.to_string(),
+                    ErrorCode::TaskQueueFull,
+                ))),
+            }
+        }
+        ClientMessage::ValidateSpawn {
+            project_path,
+            preset,
+        } => {
+            debug!("ValidateSpawn request: project={}, preset={:?}", project_path, preset);
+            Ok(Some(preflight::validate_spawn(state, project_path, preset).await))
+        }
+        ClientMessage::ListPresets { project_path } => {
+            debug!("ListPresets request: project={}", project_path);
+            let path = Path::new(&project_path);
+            if !path.is_dir() {
+                return Ok(Some(ServerMessage::error_with_code(
+                    format!("Project path is not a directory: {}", project_path),
+                    ErrorCode::InvalidPath,
+                )));
+            }
+            let project_config = match ProjectConfig::load(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    return Ok(Some(ServerMessage::error_with_code(
+                        format!("Failed to load project config: {}", e),
+                        ErrorCode::InvalidMessage,
+                    )))
+                }
+            };
+            // Global presets follow the project's own
+            let own = project_config.presets.len();
+            let project_config = project_config.with_global_presets(&state.config.presets);
+            let presets = project_config
+                .presets
+                .iter()
+                .enumerate()
+                .map(|(i, preset)| PresetInfo {
+                    name: preset.name.clone(),
+                    source: if i < own {
+                        PresetSource::Project
+                    } else {
+                        PresetSource::Global
+                    },
+                    initial_prompt: preset.initial_prompt.clone(),
+                    host: preset.host.clone(),
+                })
+                .collect();
+            Ok(Some(ServerMessage::PresetList {
+                project_path,
+                presets,
+                default_preset: project_config.default_preset,
+            }))
+        }
+        ClientMessage::ListTasks => Ok(Some(ServerMessage::TaskList {
+            tasks: state.tasks.list_cluster(),
+        })),
+        ClientMessage::CancelTask { task_id } => {
+            debug!("CancelTask request: task={}", task_id);
+            match state.tasks.cancel(task_id) {
+                Ok(()) => Ok(Some(ServerMessage::TaskCancelled { task_id })),
+                // Tasks queued on another cluster node are cancelled there;
+                // its `task_cancelled` is relayed back to clients
+                Err(_) if forward_cancel(state, task_id).await => Ok(None),
+                Err(e) => Ok(Some(ServerMessage::error_with_code(
+                    e.to_string(),
+                    ErrorCode::TaskNotFound,
+                ))),
+            }
+        }
+        ClientMessage::RunPipeline {
+            project_path,
+            pipeline,
+        } => {
+            debug!(
+                "RunPipeline request: project={}, pipeline={}",
+                project_path, pipeline
+            );
+            let config = match ProjectConfig::load(Path::new(&project_path)) {
+                Ok(config) => config,
+                Err(e) => {
+                    return Ok(Some(ServerMessage::error_with_code(
+                        format!("Failed to load project config: {}", e),
+                        ErrorCode::ConfigInvalid,
+                    )));
+                }
+            };
+            let Some(pipeline) = config.get_pipeline(&pipeline).cloned() else {
+                return Ok(Some(ServerMessage::error_with_code(
+                    format!("No pipeline named {}", pipeline),
+                    ErrorCode::PipelineNotFound,
+                )));
+            };
+            if let Err(e) = pipeline.validate() {
+                return Ok(Some(ServerMessage::error_with_code(
+                    e.to_string(),
+                    ErrorCode::ConfigInvalid,
+                )));
+            }
+            let name = pipeline.name.clone();
+            let steps = pipeline.steps.len();
+            let run_id = state.pipelines.start(
+                project_path.clone(),
+                pipeline,
+                client.map(str::to_string),
+                owner.map(str::to_string),
+            );
+            Ok(Some(ServerMessage::PipelineStarted {
+                run_id,
+                pipeline: name,
+                project_path,
+                steps,
+            }))
+        }
+        ClientMessage::ListSchedules => {
+            debug!("ListSchedules request");
+            Ok(Some(ServerMessage::ScheduleList {
+                schedules: state.schedules.list(),
+            }))
+        }
+        ClientMessage::EnableSchedule { schedule, enabled } => {
+            debug!(
+                "EnableSchedule request: schedule={}, enabled={}",
+                schedule, enabled
+            );
+            match state
+                .schedules
+                .enable(&schedule, enabled, schedules::now_secs())
+            {
+                Ok(schedule) => Ok(Some(ServerMessage::ScheduleUpdated { schedule })),
+                Err(e) => Ok(Some(ServerMessage::error_with_code(
+                    e.to_string(),
+                    ErrorCode::ScheduleNotFound,
+                ))),
+            }
+        }
+        ClientMessage::KillAgent {
+            agent_id,
+            signal: Some(signal),
+        } => {
+            debug!("KillAgent request: agent={}, signal={}", agent_id, signal);
+            match agent_manager.signal_agent(agent_id, signal).await {
+                Ok(()) => Ok(Some(ServerMessage::AgentSignalled { agent_id, signal })),
+                Err(e) => Ok(Some(signal_error(agent_id, "signal", e))),
+            }
+        }
+        ClientMessage::TagAgent {
+            agent_id,
+            add,
+            remove,
+        } => {
+            debug!("TagAgent request: agent={}, add={:?}, remove={:?}", agent_id, add, remove);
+            match agent_manager.tag_agent(agent_id, add, remove).await {
+                Ok(_) => Ok(None),
+                Err(ManagerError::AgentNotFound(_)) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Agent not found: {}", agent_id),
+                    ErrorCode::AgentNotFound,
+                ))),
+                Err(e) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    e.to_string(),
+                    ErrorCode::InvalidMessage,
+                ))),
+            }
+        }
+        ClientMessage::RenameAgent { agent_id, name } => {
+            debug!("RenameAgent request: agent={}, name={:?}", agent_id, name);
+            match agent_manager.rename_agent(agent_id, name).await {
+                Ok(()) => Ok(None),
+                Err(_) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Agent not found: {}", agent_id),
+                    ErrorCode::AgentNotFound,
+                ))),
+            }
+        }
+        ClientMessage::RestartAgent {
+            agent_id,
+            preserve_size,
+        } => {
+            debug!("RestartAgent request: agent={}, preserve_size={}", agent_id, preserve_size);
+            match agent_manager.restart_agent(agent_id, preserve_size).await {
+                Ok(()) => Ok(None),
+                Err(ManagerError::AgentNotFound(_)) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Agent not found: {}", agent_id),
+                    ErrorCode::AgentNotFound,
+                ))),
+                Err(e) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Failed to restart agent: {}", e),
+                    ErrorCode::SpawnFailed,
+                ))),
+            }
+        }
+        ClientMessage::PauseAgent { agent_id } => {
+            debug!("PauseAgent request: agent={}", agent_id);
+            match agent_manager.pause_agent(agent_id).await {
+                Ok(()) => Ok(None),
+                Err(e) => Ok(Some(signal_error(agent_id, "pause", e))),
+            }
+        }
+        ClientMessage::ResumeAgent { agent_id } => {
+            debug!("ResumeAgent request: agent={}", agent_id);
+            match agent_manager.resume_agent(agent_id).await {
+                Ok(()) => Ok(None),
+                Err(e) => Ok(Some(signal_error(agent_id, "resume", e))),
+            }
+        }
+        ClientMessage::KillAgent { agent_id, .. } => {
+            debug!("KillAgent request: agent={}", agent_id);
+            let grace = state.config.kill_grace;
+            match agent_manager.kill_agent_with_timeout(agent_id, grace).await {
+                Ok(()) => {
+                    info!("Agent killed: {}", agent_id);
+                    Ok(Some(ServerMessage::agent_exited(agent_id, None)))
+                }
+                Err(e) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    format!("Failed to kill agent: {}", e),
+                    ErrorCode::InternalError,
+                ))),
+            }
+        }
+        ClientMessage::ResizeTerminal {
+            agent_id,
             cols,
             rows,
         } => {
@@ -450,103 +3706,1989 @@ async fn handle_message(text: &str, agent_manager: &AgentManager) -> anyhow::Res
                 ))),
             }
         }
-        ClientMessage::ListAgents => {
-            debug!("ListAgents request");
-            let agents = agent_manager.list_agents().await;
-            Ok(Some(ServerMessage::AgentList { agents }))
+        ClientMessage::ListClients => Ok(Some(ServerMessage::ClientList {
+            clients: state.clients.list(),
+        })),
+        ClientMessage::Shutdown => {
+            info!("Shutdown requested by {}", client.unwrap_or("a client"));
+            let _ = state.shutdown_tx.send(());
+            Ok(Some(ServerMessage::ShuttingDown))
+        }
+        ClientMessage::ReloadConfig => match state.reload_config() {
+            Ok(changed) => Ok(Some(ServerMessage::ConfigReloaded { changed })),
+            Err(e) => Ok(Some(ServerMessage::error_with_code(
+                e.to_string(),
+                ErrorCode::ConfigInvalid,
+            ))),
+        },
+        ClientMessage::ListAgents { filter } => {
+            debug!("ListAgents request: {:?}", filter);
+            let mut agents = agent_manager.list_agents().await;
+            for info in &mut agents {
+                info.owner = state.input_control.owner(info.agent_id);
+            }
+            agents.extend(state.federation.list_agents().await);
+            agents.retain(|info| filter.matches(info));
+            Ok(Some(ServerMessage::AgentList { agents }))
+        }
+        ClientMessage::GetAgentStatus { agent_id } => {
+            debug!("GetAgentStatus request: agent={}", agent_id);
+            match agent_manager.get_agent_status(agent_id).await {
+                Ok(mut info) => {
+                    info.owner = state.input_control.owner(agent_id);
+                    Ok(Some(ServerMessage::AgentStatus {
+                        info: Box::new(info),
+                    }))
+                }
+                Err(_) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    "Agent not found",
+                    ErrorCode::AgentNotFound,
+                ))),
+            }
+        }
+        ClientMessage::GetScreenState { agent_id } => {
+            debug!("GetScreenState request: agent={}", agent_id);
+            match agent_manager.screen_state(agent_id).await {
+                Ok(screen) => Ok(Some(ServerMessage::ScreenState { agent_id, screen })),
+                Err(_) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    "Agent not found",
+                    ErrorCode::AgentNotFound,
+                ))),
+            }
+        }
+        ClientMessage::GetInputHistory { agent_id, limit } => {
+            debug!("GetInputHistory request: agent={}, limit={:?}", agent_id, limit);
+            let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT) as usize;
+            match agent_manager.input_history(agent_id, limit).await {
+                Ok(entries) => Ok(Some(ServerMessage::InputHistory {
+                    agent_id,
+                    entries: entries
+                        .into_iter()
+                        .map(|entry| InputHistoryEntry {
+                            input: entry.input,
+                            timestamp_ms: entry.timestamp_ms,
+                        })
+                        .collect(),
+                })),
+                Err(_) => Ok(Some(ServerMessage::agent_error(
+                    agent_id,
+                    "Agent not found",
+                    ErrorCode::AgentNotFound,
+                ))),
+            }
+        }
+    }
+}
+
+/// Why a push or pull may not be started: git disabled or a project outside
+/// the allowed roots
+fn transfer_error(state: &ServerState, message: &ClientMessage) -> Option<ServerMessage> {
+    capability_error(state, message).or_else(|| allowed_root_error(state, message))
+}
+
+/// Start a push or pull whose progress and outcome arrive as notices,
+/// looking up the token it authenticates with first
+async fn start_transfer(
+    state: &ServerState,
+    connection: &Connection,
+    mut request: Transfer,
+    token_secret: Option<String>,
+) -> Option<ServerMessage> {
+    debug!(
+        "{:?} request: project={}, remote={:?}, branch={:?}",
+        request.operation, request.project_path, request.remote, request.branch
+    );
+    if let Some(name) = token_secret {
+        match state.config.secrets.resolve(std::slice::from_ref(&name)).await {
+            Ok(env) => request.token = env.vars().remove(&name),
+            Err(e) => {
+                error!("Failed to look up secrets: {}", e);
+                return Some(ServerMessage::error_with_code(
+                    format!("Failed to look up token: {}", e),
+                    ErrorCode::GitFailed,
+                ));
+            }
+        }
+    }
+    tokio::spawn(transfer(request, connection.notice_tx.clone()));
+    None
+}
+
+/// Send a `cancel_task` to the cluster node holding the task, if any
+async fn forward_cancel(state: &ServerState, task_id: Uuid) -> bool {
+    match state.tasks.remote_node(task_id) {
+        Some(node_id) => {
+            debug!("Forwarding cancellation of task {} to node {}", task_id, node_id);
+            let cancel = ClientMessage::CancelTask { task_id };
+            state.federation.send(&node_id, cancel).await
+        }
+        None => false,
+    }
+}
+
+/// Error for a failed git operation
+pub(super) fn git_error(error: GitError) -> ServerMessage {
+    ServerMessage::error_with_code(error.to_string(), git_error_code(&error))
+}
+
+/// Error for a failed git operation in an agent's repository
+fn agent_git_error(agent_id: Uuid, error: GitError) -> ServerMessage {
+    ServerMessage::agent_error(agent_id, error.to_string(), git_error_code(&error))
+}
+
+/// Project path of a local agent, or the error to send for an unknown one
+async fn agent_project_path(state: &ServerState, agent_id: Uuid) -> Result<String, ServerMessage> {
+    match state.agent_manager.get_agent_status(agent_id).await {
+        Ok(info) => Ok(info.project_path),
+        Err(_) => Err(ServerMessage::agent_error(
+            agent_id,
+            "Agent not found",
+            ErrorCode::AgentNotFound,
+        )),
+    }
+}
+
+/// Error code for a failed git operation
+fn git_error_code(error: &GitError) -> ErrorCode {
+    match error {
+        GitError::NotARepository(_) | GitError::InvalidPath(_) => ErrorCode::InvalidPath,
+        _ => ErrorCode::GitFailed,
+    }
+}
+
+/// Directories clients may access files under: the configured roots, or
+/// else the project directories of the local agents
+async fn file_sandbox(state: &ServerState) -> Sandbox {
+    let allowed_roots = state.live.get().allowed_roots;
+    if !allowed_roots.is_empty() {
+        return Sandbox::new(&allowed_roots);
+    }
+    let agents = state.agent_manager.list_agents().await;
+    Sandbox::new(agents.iter().map(|info| &info.project_path))
+}
+
+/// Error for a failed filesystem operation
+fn fs_error(error: FsError) -> ServerMessage {
+    ServerMessage::error_with_code(error.to_string(), fs_error_code(&error))
+}
+
+/// Error for a failed filesystem operation in an agent's project
+fn agent_fs_error(agent_id: Uuid, error: FsError) -> ServerMessage {
+    ServerMessage::agent_error(agent_id, error.to_string(), fs_error_code(&error))
+}
+
+/// Error code for a failed filesystem operation
+fn fs_error_code(error: &FsError) -> ErrorCode {
+    match error {
+        FsError::Io(_) | FsError::Watch(_) => ErrorCode::InternalError,
+        _ => ErrorCode::InvalidPath,
+    }
+}
+
+/// Error for a failed start or stop of a recording
+fn recording_error(agent_id: Uuid, error: ManagerError) -> ServerMessage {
+    let code = match error {
+        ManagerError::AgentNotFound(_) => ErrorCode::AgentNotFound,
+        ManagerError::SessionError(SessionError::RecordingFailed(_)) => ErrorCode::InternalError,
+        _ => ErrorCode::InvalidMessage,
+    };
+    ServerMessage::agent_error(agent_id, error.to_string(), code)
+}
+
+/// The message for an event of a `stream_json` agent
+fn stream_message(agent_id: Uuid, event: StreamEvent) -> ServerMessage {
+    match event {
+        StreamEvent::AssistantText { text } => ServerMessage::AssistantText { agent_id, text },
+        StreamEvent::ToolUse { id, name, input } => ServerMessage::ToolUse {
+            agent_id,
+            tool_use_id: id,
+            name,
+            input,
+        },
+        StreamEvent::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+        } => ServerMessage::ToolResult {
+            agent_id,
+            tool_use_id,
+            content,
+            is_error,
+        },
+        StreamEvent::Cost {
+            total_cost_usd,
+            duration_ms,
+            num_turns,
+            input_tokens,
+            output_tokens,
+        } => ServerMessage::Cost {
+            agent_id,
+            total_cost_usd,
+            duration_ms,
+            num_turns,
+            input_tokens,
+            output_tokens,
+        },
+    }
+}
+
+/// Error for a permission prompt that could not be answered
+fn approval_error(agent_id: Uuid, error: ManagerError) -> ServerMessage {
+    let code = match error {
+        ManagerError::NoPendingApproval(_) => ErrorCode::NoPendingConfirmation,
+        ManagerError::AlwaysApproveUnavailable(_) => ErrorCode::InvalidMessage,
+        _ => ErrorCode::AgentNotFound,
+    };
+    ServerMessage::agent_error(agent_id, error.to_string(), code)
+}
+
+/// Error for terminal modes that could not be read or set
+fn echo_error(agent_id: Uuid, error: ManagerError) -> ServerMessage {
+    let code = match error {
+        ManagerError::AgentNotFound(_) => ErrorCode::AgentNotFound,
+        ManagerError::SessionError(SessionError::PtyError(_)) => ErrorCode::InternalError,
+        _ => ErrorCode::InvalidMessage,
+    };
+    ServerMessage::agent_error(agent_id, error.to_string(), code)
+}
+
+/// Error for a signal that could not be sent to an agent
+fn signal_error(agent_id: Uuid, action: &str, error: ManagerError) -> ServerMessage {
+    let code = match error {
+        ManagerError::AgentNotFound(_) => ErrorCode::AgentNotFound,
+        ManagerError::SessionError(SessionError::PtyError(_)) => ErrorCode::InternalError,
+        _ => ErrorCode::InvalidMessage,
+    };
+    ServerMessage::agent_error(agent_id, format!("Failed to {} agent: {}", action, error), code)
+}
+
+/// Apply the input policy and write input to a local agent
+///
+/// With `record`, input that was sent is added to the agent's history.
+/// Returns an error message for the client if the input was refused or could not be sent.
+async fn send_agent_input(
+    state: &ServerState,
+    agent_id: Uuid,
+    input: String,
+    record: bool,
+) -> Option<ServerMessage> {
+    let input = match state.input_filter.apply(agent_id, input) {
+        Ok(input) => input,
+        Err(e) => {
+            warn!("Refused input for agent {}: {}", agent_id, e);
+            return Some(ServerMessage::agent_error(agent_id, e.to_string(), e.code()));
+        }
+    };
+    match state.agent_manager.send_input(agent_id, &input).await {
+        Ok(()) => {
+            if record {
+                let _ = state.agent_manager.record_input(agent_id, &input).await;
+            }
+            None
+        }
+        Err(e) => Some(ServerMessage::agent_error(
+            agent_id,
+            format!("Failed to send input: {}", e),
+            ErrorCode::InternalError,
+        )),
+    }
+}
+
+/// Wait for an authentication message from the client
+///
+/// Returns the protocol version the client uses, the role its token grants
+/// and the name it was issued under, or the error to send it.
+async fn wait_for_auth<R: TransportReceiver>(
+    receiver: &mut R,
+    live: &LiveConfig,
+) -> Result<(u32, Role, Option<String>), ServerMessage> {
+    let failed = |message: String| ServerMessage::error_with_code(message, ErrorCode::AuthFailed);
+
+    match receiver.recv_text().await {
+        Some(Ok(text)) => {
+            let envelope = ClientEnvelope::from_json(&text).map_err(|e| {
+                ServerMessage::from(e).with_request_id(ClientEnvelope::peek_request_id(&text))
+            })?;
+            let version = envelope.negotiated_version();
+            match envelope.message {
+                ClientMessage::Authenticate { token } => match live.role_for(&token) {
+                    Some(role) => Ok((version, role, live.token_name(&token))),
+                    None => Err(failed("Invalid authentication token".to_string())),
+                },
+                _ => Err(failed("Authentication required before other messages".to_string())),
+            }
+        }
+        Some(Err(e)) => Err(failed(format!("Transport error during authentication: {}", e))),
+        None => Err(failed("Connection closed before authentication".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BridgeConfig;
+    use crate::server::protocol::OutputEncoding;
+
+    #[test]
+    fn test_server_config() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000);
+        assert_eq!(config.socket_addr(), "127.0.0.1:9000");
+    }
+
+    #[test]
+    fn test_server_config_with_token() {
+        let config =
+            ServerConfig::new("0.0.0.0".to_string(), 8080).with_token(Some("secret".to_string()));
+        assert_eq!(config.token, Some("secret".to_string()));
+    }
+
+    fn test_state() -> ServerState {
+        ServerState::new(ServerConfig::new("127.0.0.1".to_string(), 9000), Federation::new())
+    }
+
+    /// Handle a JSON message on a connection
+    async fn request(
+        state: &ServerState,
+        connection: &mut Connection,
+        message: serde_json::Value,
+    ) -> Option<ServerMessage> {
+        handle_message(&message.to_string(), state, connection).await
+    }
+
+    #[test]
+    fn test_server_config_with_peers() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_peers(vec![PeerConfig::new("server", "ws://10.0.0.2:9000/ws")]);
+        assert_eq!(config.peers.len(), 1);
+        assert_eq!(config.peers[0].name, "server");
+    }
+
+    #[test]
+    fn test_server_config_with_relay() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_relay(Some("wss://relay.example.com/bridge/abc".to_string()));
+        assert_eq!(
+            config.relay_url.as_deref(),
+            Some("wss://relay.example.com/bridge/abc")
+        );
+    }
+
+    #[test]
+    fn test_server_config_proxy_settings() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_ws_path(Some("/ws".to_string()))
+            .with_trusted_proxies(vec!["10.0.0.1".parse().unwrap()]);
+        assert_eq!(config.ws_path.as_deref(), Some("/ws"));
+        assert_eq!(config.trusted_proxies.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handle_ping_message() {
+        let state = test_state();
+        let msg = r#"{"type": "ping", "seq": 42}"#;
+        let (mut connection, _) = Connection::new("test".to_string());
+        let response = handle_message(msg, &state, &mut connection).await;
+
+        match response {
+            Some(ServerMessage::Pong { seq }) => assert_eq!(seq, 42),
+            _ => panic!("Expected Some(Pong) response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_input_unknown_agent_without_peers() {
+        let state = test_state();
+        let msg = format!(
+            r#"{{"type": "agent_input", "agent_id": "{}", "input": "hi"}}"#,
+            uuid::Uuid::new_v4()
+        );
+        let (mut connection, _) = Connection::new("test".to_string());
+        let response = handle_message(&msg, &state, &mut connection).await;
+        assert!(matches!(response, Some(ServerMessage::Error { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_message_error_details() {
+        let state = test_state();
+        let msg = r#"{"type": "resize_terminal", "agent_id": "00000000-0000-0000-0000-000000000000", "cols": 0, "rows": 24, "request_id": "r1"}"#;
+        let (mut connection, _) = Connection::new("test".to_string());
+
+        match handle_message(msg, &state, &mut connection).await {
+            Some(ServerMessage::Error {
+                code,
+                retryable,
+                details,
+                request_id,
+                ..
+            }) => {
+                assert_eq!(code, Some(ErrorCode::InvalidMessage));
+                assert!(!retryable);
+                assert_eq!(details["field"], "cols");
+                assert_eq!(request_id.as_deref(), Some("r1"));
+            }
+            other => panic!("Expected Error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_version_negotiation() {
+        use super::super::protocol::PROTOCOL_VERSION;
+
+        let state = test_state();
+        let (mut connection, mut notices) = Connection::new("test".to_string());
+
+        let newer = format!(
+            r#"{{"version": {}, "type": "ping", "seq": 1}}"#,
+            PROTOCOL_VERSION + 1
+        );
+        assert!(matches!(
+            handle_message(&newer, &state, &mut connection).await,
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::UnsupportedVersion),
+                ..
+            })
+        ));
+        assert!(notices.try_recv().is_err());
+
+        // Messages without a version come from version 1 clients
+        let ping = r#"{"type": "ping", "seq": 2}"#;
+        handle_message(ping, &state, &mut connection).await;
+        handle_message(ping, &state, &mut connection).await;
+        assert_eq!(connection.version, Some(MIN_PROTOCOL_VERSION));
+        assert_eq!(connection.codec(), Codec::V1);
+        assert_eq!(
+            notices.try_recv().unwrap(),
+            ServerMessage::VersionNegotiated {
+                version: MIN_PROTOCOL_VERSION
+            }
+        );
+        // Only echoed once
+        assert!(notices.try_recv().is_err());
+
+        let (mut connection, _notices) = Connection::new("test".to_string());
+        let ping = format!(r#"{{"version": {}, "type": "ping", "seq": 3}}"#, PROTOCOL_VERSION);
+        handle_message(&ping, &state, &mut connection).await;
+        assert_eq!(connection.codec(), Codec::V2);
+
+        // Clients offering a range get the newest version the server speaks
+        let (mut connection, mut notices) = Connection::new("test".to_string());
+        let ping = format!(
+            r#"{{"version": {0}, "min_version": 1, "max_version": {0}, "type": "ping", "seq": 4}}"#,
+            PROTOCOL_VERSION + 1
+        );
+        assert!(matches!(
+            handle_message(&ping, &state, &mut connection).await,
+            Some(ServerMessage::Pong { seq: 4 })
+        ));
+        assert_eq!(connection.version, Some(PROTOCOL_VERSION));
+        assert_eq!(
+            notices.try_recv().unwrap(),
+            ServerMessage::VersionNegotiated {
+                version: PROTOCOL_VERSION
+            }
+        );
+    }
+
+    /// Backend that records input instead of running agents
+    #[derive(Default)]
+    struct MockBackend {
+        agents: std::sync::Mutex<Vec<Uuid>>,
+        input: std::sync::Mutex<Vec<(Uuid, String)>>,
+        events: Option<broadcast::Sender<crate::agent::AgentEvent>>,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentSpawner for MockBackend {
+        async fn spawn_agent(&self, _config: SpawnConfig) -> crate::agent::ManagerResult<Uuid> {
+            let agent_id = Uuid::new_v4();
+            self.agents.lock().unwrap().push(agent_id);
+            Ok(agent_id)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AgentBackend for MockBackend {
+        fn subscribe(&self) -> broadcast::Receiver<crate::agent::AgentEvent> {
+            match self.events {
+                Some(ref events) => events.subscribe(),
+                None => broadcast::channel(1).1,
+            }
+        }
+
+        async fn session_count(&self) -> usize {
+            self.agents.lock().unwrap().len()
+        }
+
+        async fn list_agents(&self) -> Vec<super::super::AgentInfo> {
+            Vec::new()
+        }
+
+        async fn agent_exists(&self, agent_id: Uuid) -> bool {
+            self.agents.lock().unwrap().contains(&agent_id)
+        }
+
+        async fn get_agent_status(
+            &self,
+            agent_id: Uuid,
+        ) -> crate::agent::ManagerResult<super::super::AgentInfo> {
+            Err(ManagerError::AgentNotFound(agent_id))
+        }
+
+        async fn kill_agent(&self, agent_id: Uuid) -> crate::agent::ManagerResult<()> {
+            self.agents.lock().unwrap().retain(|id| *id != agent_id);
+            Ok(())
+        }
+
+        async fn send_input(&self, agent_id: Uuid, input: &str) -> crate::agent::ManagerResult<()> {
+            self.input.lock().unwrap().push((agent_id, input.to_string()));
+            Ok(())
+        }
+
+        async fn send_bytes(&self, agent_id: Uuid, input: &[u8]) -> crate::agent::ManagerResult<()> {
+            self.send_input(agent_id, &String::from_utf8_lossy(input)).await
+        }
+
+        async fn resize_agent(&self, _agent_id: Uuid, _cols: u16, _rows: u16) -> crate::agent::ManagerResult<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_with_mock_backend() {
+        let backend = Arc::new(MockBackend::default());
+        let server = WebSocketServer::builder()
+            .with_manager(Arc::clone(&backend))
+            .build();
+        let state = &server.state;
+        let (mut first, _) = Connection::new("10.0.0.1:5000".to_string());
+        let (mut second, _) = Connection::new("10.0.0.2:5000".to_string());
+
+        let dir = tempfile::tempdir().unwrap();
+        let msg = format!(
+            r#"{{"type": "spawn_agent", "project_path": "{}"}}"#,
+            dir.path().display()
+        );
+        let agent_id = match handle_message(&msg, state, &mut first).await {
+            Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_id,
+            other => panic!("Expected AgentSpawned, got {:?}", other),
+        };
+
+        let input = format!(
+            r#"{{"type": "agent_input", "agent_id": "{}", "input": "ls\n"}}"#,
+            agent_id
+        );
+        assert!(handle_message(&input, state, &mut first).await.is_none());
+        // The first client now controls the agent's input
+        assert!(matches!(
+            handle_message(&input, state, &mut second).await,
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::InputLocked),
+                ..
+            })
+        ));
+        assert_eq!(
+            *backend.input.lock().unwrap(),
+            vec![(agent_id, "ls\n".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_input() {
+        let backend = Arc::new(MockBackend::default());
+        let server = WebSocketServer::builder()
+            .with_manager(Arc::clone(&backend))
+            .build();
+        let state = &server.state;
+        let (mut first, _) = Connection::new("10.0.0.1:5000".to_string());
+        let (mut second, _) = Connection::new("10.0.0.2:5000".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let spawn = serde_json::json!({"type": "spawn_agent", "project_path": dir.path()});
+        let mut agent_ids = Vec::new();
+        for _ in 0..2 {
+            match request(state, &mut first, spawn.clone()).await {
+                Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_ids.push(agent_id),
+                other => panic!("Expected AgentSpawned, got {:?}", other),
+            }
+        }
+        let input = |agent_id: Uuid| {
+            serde_json::json!({"type": "agent_input", "agent_id": agent_id, "input": "ls\n"})
+        };
+        assert!(request(state, &mut second, input(agent_ids[1])).await.is_none());
+
+        let unknown = Uuid::new_v4();
+        let broadcast = serde_json::json!({
+            "type": "broadcast_input",
+            "agent_ids": [agent_ids[0], agent_ids[1], unknown, agent_ids[0]],
+            "input": "cargo test\n",
+        });
+        let Some(ServerMessage::InputBroadcast { results }) =
+            request(state, &mut first, broadcast).await
+        else {
+            panic!("expected the broadcast results");
+        };
+        // Each agent is sent the input once; the second is controlled by
+        // the other client
+        assert_eq!(results.len(), 3);
+        assert!(results[0].ok);
+        assert_eq!(results[1].code, Some(ErrorCode::InputLocked));
+        assert_eq!(results[2].agent_id, unknown);
+        assert_eq!(results[2].code, Some(ErrorCode::AgentNotFound));
+        assert_eq!(
+            *backend.input.lock().unwrap(),
+            vec![
+                (agent_ids[1], "ls\n".to_string()),
+                (agent_ids[0], "cargo test\n".to_string()),
+            ]
+        );
+
+        // The input rate is charged for every agent the input goes to
+        let limits = ConnectionLimits::default().with_input_bytes_per_sec(Some(30));
+        first.limiter = ConnectionLimiter::new(limits, Instant::now());
+        let broadcast = serde_json::json!({
+            "type": "broadcast_input",
+            "agent_ids": agent_ids,
+            "input": "cargo build --release\n",
+        });
+        let Some(ServerMessage::InputBroadcast { results }) =
+            request(state, &mut first, broadcast).await
+        else {
+            panic!("expected the broadcast results");
+        };
+        assert!(results[0].ok);
+        assert_eq!(results[1].code, Some(ErrorCode::RateLimited));
+
+        let group = serde_json::json!({"type": "broadcast_input", "group": "none", "input": "ls"});
+        assert!(matches!(
+            request(state, &mut first, group).await,
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::GroupNotFound),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_capability() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_disabled_capabilities(vec![Capability::Spawn]);
+        assert!(!config.capabilities().contains(&Capability::Spawn));
+        let server = WebSocketServer::builder()
+            .with_config(config)
+            .with_manager(Arc::new(MockBackend::default()))
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let dir = tempfile::tempdir().unwrap();
+
+        let msg = format!(
+            r#"{{"type": "spawn_agent", "project_path": "{}"}}"#,
+            dir.path().display()
+        );
+        assert!(matches!(
+            handle_message(&msg, &server.state, &mut connection).await,
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::CapabilityDisabled),
+                ..
+            })
+        ));
+        assert!(matches!(
+            handle_message(r#"{"type": "list_agents"}"#, &server.state, &mut connection).await,
+            Some(ServerMessage::AgentList { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_clipboard() {
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_disabled_capabilities(vec![Capability::Clipboard]);
+        let server = WebSocketServer::builder()
+            .with_config(config)
+            .with_manager(Arc::new(MockBackend::default()))
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let agent_id = Uuid::new_v4();
+
+        for msg in [
+            format!(
+                r#"{{"type": "set_clipboard", "agent_id": "{}", "text": "copied"}}"#,
+                agent_id
+            ),
+            format!(r#"{{"type": "get_clipboard", "agent_id": "{}"}}"#, agent_id),
+        ] {
+            assert!(matches!(
+                handle_message(&msg, &server.state, &mut connection).await,
+                Some(ServerMessage::Error {
+                    code: Some(ErrorCode::CapabilityDisabled),
+                    ..
+                })
+            ));
         }
-        ClientMessage::GetAgentStatus { agent_id } => {
-            debug!("GetAgentStatus request: agent={}", agent_id);
-            match agent_manager.get_agent_status(agent_id).await {
-                Ok(info) => Ok(Some(ServerMessage::AgentStatus {
-                    agent_id: info.agent_id,
-                    status: info.status,
-                    project_path: info.project_path,
-                    cols: info.cols,
-                    rows: info.rows,
-                })),
-                Err(_) => Ok(Some(ServerMessage::agent_error(
-                    agent_id,
-                    "Agent not found",
-                    ErrorCode::AgentNotFound,
-                ))),
+    }
+
+    #[tokio::test]
+    async fn test_configured_terminal_size() {
+        let terminal = TerminalLimits::default()
+            .with_max_size(1000, 200)
+            .with_default_size(160, 48);
+        let config =
+            ServerConfig::new("127.0.0.1".to_string(), 9000).with_terminal_limits(terminal);
+        let server = WebSocketServer::builder()
+            .with_config(config)
+            .with_manager(Arc::new(MockBackend::default()))
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let dir = tempfile::tempdir().unwrap();
+
+        let msg = format!(
+            r#"{{"type": "spawn_agent", "project_path": "{}"}}"#,
+            dir.path().display()
+        );
+        assert!(matches!(
+            handle_message(&msg, &server.state, &mut connection).await,
+            Some(ServerMessage::AgentSpawned { cols: 160, rows: 48, .. })
+        ));
+
+        let msg = format!(
+            r#"{{"type": "spawn_agent", "project_path": "{}", "cols": 900}}"#,
+            dir.path().display()
+        );
+        assert!(matches!(
+            handle_message(&msg, &server.state, &mut connection).await,
+            Some(ServerMessage::AgentSpawned { cols: 900, rows: 48, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_agent_attachments() {
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::echo()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_manager(Arc::clone(&manager))
+            .build();
+        let state = &server.state;
+        let dir = tempfile::tempdir().unwrap();
+        let spawn = format!(
+            r#"{{"type": "spawn_agent", "project_path": "{}", "version": 2}}"#,
+            dir.path().display()
+        );
+        let claim = r#"{"type": "claim_session", "session_token": "tablet", "version": 2}"#;
+
+        // Version 2 clients receive the output of agents they spawn
+        let (mut first, _) = Connection::new("10.0.0.1:5000".to_string());
+        handle_message(claim, state, &mut first).await;
+        let agent_id = match handle_message(&spawn, state, &mut first).await {
+            Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_id,
+            other => panic!("Expected AgentSpawned, got {:?}", other),
+        };
+        assert!(first.receives_output(agent_id));
+
+        // Claiming the same session from a new connection attaches its agents
+        let (mut second, _) = Connection::new("10.0.0.1:5001".to_string());
+        assert_eq!(
+            handle_message(claim, state, &mut second).await,
+            Some(ServerMessage::SessionClaimed {
+                agent_ids: vec![agent_id]
+            })
+        );
+        assert!(second.receives_output(agent_id));
+        let detach = format!(
+            r#"{{"type": "detach_agent", "agent_id": "{}", "version": 2}}"#,
+            agent_id
+        );
+        handle_message(&detach, state, &mut second).await;
+        assert!(!second.receives_output(agent_id));
+
+        // Version 1 clients receive everything until they scope themselves
+        let (mut legacy, _) = Connection::new("10.0.0.2:5000".to_string());
+        handle_message(r#"{"type": "ping", "seq": 1}"#, state, &mut legacy).await;
+        assert!(legacy.receives_output(agent_id));
+        let other = Uuid::new_v4();
+        let attach = format!(r#"{{"type": "attach_agent", "agent_id": "{}"}}"#, other);
+        assert!(matches!(
+            handle_message(&attach, state, &mut legacy).await,
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::AgentNotFound),
+                ..
+            })
+        ));
+        let detach = format!(r#"{{"type": "detach_agent", "agent_id": "{}"}}"#, agent_id);
+        assert_eq!(
+            handle_message(&detach, state, &mut legacy).await,
+            Some(ServerMessage::AgentDetached { agent_id })
+        );
+        assert!(!legacy.receives_output(agent_id));
+        assert_eq!(legacy.attached, Some(HashSet::new()));
+    }
+
+    #[tokio::test]
+    async fn test_resume_lost_session() {
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::echo()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_manager(Arc::clone(&manager))
+            .build();
+        let state = &server.state;
+        let dir = tempfile::tempdir().unwrap();
+        let identity = || Identity {
+            role: Role::Admin,
+            token_name: None,
+        };
+        let grace = Duration::from_secs(60);
+
+        let (mut lost, _) = Connection::new("10.0.0.1:5000".to_string());
+        lost.resume = Some(state.resumable.register(identity(), grace));
+        let agent_id = match request(
+            state,
+            &mut lost,
+            serde_json::json!({"type": "spawn_agent", "project_path": dir.path(), "version": 2}),
+        )
+        .await
+        {
+            Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_id,
+            other => panic!("Expected AgentSpawned, got {:?}", other),
+        };
+        let input =
+            serde_json::json!({"type": "agent_input", "agent_id": agent_id, "input": "ls\n"});
+        request(state, &mut lost, input.clone()).await;
+        let ticket = lost.resume.clone().unwrap();
+        resume::suspend(state, &ticket, lost.suspend());
+
+        // A new connection picks up the attachments and input control
+        let (mut resumed, _) = Connection::new("10.0.0.1:5001".to_string());
+        resumed.resume = Some(state.resumable.register(identity(), grace));
+        let resume = serde_json::json!({"type": "resume_session", "resume_token": ticket.token});
+        assert!(matches!(
+            request(state, &mut resumed, resume).await,
+            Some(ServerMessage::SessionResumed { agent_ids, controlled, .. })
+                if agent_ids == vec![agent_id] && controlled == vec![agent_id]
+        ));
+        assert!(resumed.receives_output(agent_id));
+        assert_eq!(resumed.resume.as_ref().unwrap().token, ticket.token);
+        assert_eq!(request(state, &mut resumed, input).await, None);
+
+        // Tokens of sessions that are gone are refused
+        let (mut late, _) = Connection::new("10.0.0.1:5002".to_string());
+        late.resume = Some(state.resumable.register(identity(), grace));
+        let unknown = serde_json::json!({"type": "resume_session", "resume_token": "gone"});
+        assert!(matches!(
+            request(state, &mut late, unknown).await,
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::SessionExpired),
+                ..
+            })
+        ));
+        manager.kill_agent(agent_id).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_worktree_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        let repo = git2::Repository::init(&project).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "Initial", &tree, &[])
+            .unwrap();
+
+        let state = test_state();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let project_path = project.display().to_string();
+
+        let created = request(
+            &state,
+            &mut connection,
+            serde_json::json!({
+                "type": "create_worktree",
+                "project_path": project_path,
+                "branch": "agent-1",
+                "create_branch": true,
+            }),
+        )
+        .await;
+        let Some(ServerMessage::WorktreeCreated { worktree }) = created else {
+            panic!("Expected worktree_created, got {:?}", created);
+        };
+        assert!(worktree.path.ends_with("project-worktrees/agent-1"));
+
+        let listed = request(
+            &state,
+            &mut connection,
+            serde_json::json!({
+                "type": "list_worktrees",
+                "project_path": project_path,
+            }),
+        )
+        .await;
+        let Some(ServerMessage::WorktreeList { worktrees }) = listed else {
+            panic!("Expected worktree_list, got {:?}", listed);
+        };
+        assert_eq!(worktrees.len(), 2);
+        assert!(worktrees[0].is_main);
+        assert_eq!(worktrees[1].branch.as_deref(), Some("agent-1"));
+
+        let removed = request(
+            &state,
+            &mut connection,
+            serde_json::json!({
+                "type": "remove_worktree",
+                "project_path": project_path,
+                "path": worktree.path,
+            }),
+        )
+        .await;
+        assert!(matches!(removed, Some(ServerMessage::WorktreeRemoved { .. })));
+
+        let not_a_repo = request(
+            &state,
+            &mut connection,
+            serde_json::json!({
+                "type": "list_worktrees",
+                "project_path": dir.path().display().to_string(),
+            }),
+        )
+        .await;
+        assert!(matches!(
+            not_a_repo,
+            Some(ServerMessage::Error { code: Some(ErrorCode::InvalidPath), .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_file_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(root.join("logo.bin"), [0xff, 0x00]).unwrap();
+        std::fs::write(dir.path().join("secret"), "x").unwrap();
+
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_allowed_roots(vec![root.clone()]);
+        let state = ServerState::new(config, Federation::new());
+        let (mut connection, _) = Connection::new("test".to_string());
+        let root_path = root.display().to_string();
+
+        let listed = request(
+            &state,
+            &mut connection,
+            serde_json::json!({"type": "list_directory", "path": root_path}),
+        )
+        .await;
+        let Some(ServerMessage::DirectoryListing { entries, .. }) = listed else {
+            panic!("Expected directory_listing, got {:?}", listed);
+        };
+        assert_eq!(entries.len(), 2);
+
+        let read = request(
+            &state,
+            &mut connection,
+            serde_json::json!({
+                "type": "read_file",
+                "path": root.join("main.rs"),
+                "offset": 3,
+                "limit": 4,
+            }),
+        )
+        .await;
+        let Some(ServerMessage::FileContents {
+            data,
+            size,
+            encoding,
+            ..
+        }) = read
+        else {
+            panic!("Expected file_contents, got {:?}", read);
+        };
+        assert_eq!((data.as_str(), size, encoding), ("main", 13, None));
+
+        let binary = request(
+            &state,
+            &mut connection,
+            serde_json::json!({"type": "read_file", "path": root.join("logo.bin")}),
+        )
+        .await;
+        assert!(matches!(
+            binary,
+            Some(ServerMessage::FileContents { encoding: Some(OutputEncoding::Base64), .. })
+        ));
+
+        let stat = request(
+            &state,
+            &mut connection,
+            serde_json::json!({"type": "stat_path", "path": root.join("main.rs")}),
+        )
+        .await;
+        assert!(matches!(stat, Some(ServerMessage::PathStat { entry, .. }) if entry.size == 13));
+
+        // Paths outside the roots are refused, even through `..`
+        let escape = request(
+            &state,
+            &mut connection,
+            serde_json::json!({"type": "read_file", "path": format!("{}/../secret", root_path)}),
+        )
+        .await;
+        assert!(matches!(
+            escape,
+            Some(ServerMessage::Error { code: Some(ErrorCode::InvalidPath), .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bridge.toml");
+        std::fs::write(&path, "max_agents = 2").unwrap();
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000)
+            .with_agent_limits(AgentLimits::default().with_max_agents(Some(2)))
+            .with_config_source(Some(ConfigSource::new(path.clone(), Default::default())));
+        let state = ServerState::new(config, Federation::new());
+        let (mut connection, _) = Connection::new("test".to_string());
+        let reload = serde_json::json!({"type": "reload_config"});
+
+        std::fs::write(&path, format!("token = \"secret\"\nallowed_roots = [{:?}]", dir.path()))
+            .unwrap();
+        let reloaded = request(&state, &mut connection, reload.clone()).await;
+        let Some(ServerMessage::ConfigReloaded { changed }) = reloaded else {
+            panic!("Expected config_reloaded, got {:?}", reloaded);
+        };
+        assert_eq!(changed, ["token", "allowed_roots", "max_agents"]);
+        assert_eq!(state.live.role_for("secret"), Some(Role::Admin));
+        assert!(file_sandbox(&state).await.contains(&dir.path().canonicalize().unwrap()));
+
+        // A broken file is reported and changes nothing
+        std::fs::write(&path, "token = ").unwrap();
+        let failed = request(&state, &mut connection, reload).await;
+        assert!(matches!(
+            failed,
+            Some(ServerMessage::Error { code: Some(ErrorCode::ConfigInvalid), .. })
+        ));
+        assert_eq!(state.live.role_for("secret"), Some(Role::Admin));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_config(
+                ServerConfig::new("127.0.0.1".to_string(), 9000)
+                    .with_audit_log(Some(AuditLog::open(&path).unwrap())),
+            )
+            .with_manager(manager)
+            .build();
+        let (mut connection, _) = Connection::new("10.0.0.5:51234".to_string());
+        connection.role = Role::Operator;
+        connection.token_name = Some("alice".to_string());
+
+        let spawned = request(
+            &server.state,
+            &mut connection,
+            serde_json::json!({"type": "spawn_agent", "project_path": dir.path()}),
+        )
+        .await;
+        let Some(ServerMessage::AgentSpawned { agent_id, .. }) = spawned else {
+            panic!("Expected agent_spawned, got {:?}", spawned);
+        };
+        for message in [
+            serde_json::json!({"type": "agent_input", "agent_id": agent_id, "input": "secret\n"}),
+            serde_json::json!({
+                "type": "broadcast_input",
+                "agent_ids": [agent_id, Uuid::new_v4()],
+                "input": "secret\n",
+            }),
+            serde_json::json!({"type": "list_agents"}),
+            serde_json::json!({"type": "kill_agent", "agent_id": agent_id}),
+            serde_json::json!({"type": "shutdown"}),
+        ] {
+            request(&server.state, &mut connection, message).await;
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("secret"));
+        let records: Vec<AuditRecord> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().all(|record| record.client == "10.0.0.5:51234"
+            && record.token.as_deref() == Some("alice")
+            && record.role == Some(Role::Operator)));
+        assert!(matches!(
+            records[0].event,
+            AuditEvent::Spawn { agent_id: Some(id), .. } if id == agent_id
+        ));
+        assert_eq!(records[1].event, AuditEvent::Input { agent_id, length: 7 });
+        // Broadcasts record the agents the input reached
+        assert_eq!(
+            records[2].event,
+            AuditEvent::BroadcastInput {
+                agent_ids: vec![agent_id],
+                length: 7
             }
+        );
+        assert_eq!(records[3].event, AuditEvent::Kill { agent_id, signal: None });
+        assert_eq!(records[3].error, None);
+        // Refused requests are recorded with the reason
+        assert_eq!(records[4].event, AuditEvent::Shutdown);
+        assert!(records[4].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_confined_to_allowed_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("projects");
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_config(
+                ServerConfig::new("127.0.0.1".to_string(), 9000)
+                    .with_allowed_roots(vec![root.clone()]),
+            )
+            .with_manager(manager)
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+
+        let spawned = request(
+            &server.state,
+            &mut connection,
+            serde_json::json!({"type": "spawn_agent", "project_path": root.join("app")}),
+        )
+        .await;
+        assert!(matches!(spawned, Some(ServerMessage::AgentSpawned { .. })));
+
+        for message in [
+            serde_json::json!({"type": "spawn_agent", "project_path": dir.path()}),
+            serde_json::json!({
+                "type": "spawn_agent",
+                "project_path": format!("{}/app/../..", root.display()),
+            }),
+            serde_json::json!({"type": "list_branches", "project_path": dir.path()}),
+            serde_json::json!({
+                "type": "create_worktree",
+                "project_path": root.join("app"),
+                "branch": "feature",
+                "path": dir.path().join("feature"),
+            }),
+        ] {
+            let refused = request(&server.state, &mut connection, message).await;
+            assert!(
+                matches!(
+                    refused,
+                    Some(ServerMessage::Error { code: Some(ErrorCode::InvalidPath), .. })
+                ),
+                "Expected invalid_path, got {:?}",
+                refused
+            );
         }
     }
-}
 
-/// Wait for an authentication message from the client
-async fn wait_for_auth(
-    ws_receiver: &mut futures_util::stream::SplitStream<
-        tokio_tungstenite::WebSocketStream<TcpStream>,
-    >,
-    expected_token: &str,
-) -> anyhow::Result<()> {
-    use anyhow::anyhow;
-
-    while let Some(msg) = ws_receiver.next().await {
-        match msg {
-            Ok(Message::Text(text)) => {
-                let message: ClientMessage = serde_json::from_str(&text)?;
-                match message {
-                    ClientMessage::Authenticate { token } => {
-                        if token == expected_token {
-                            return Ok(());
-                        } else {
-                            return Err(anyhow!("Invalid authentication token"));
-                        }
-                    }
-                    _ => {
-                        return Err(anyhow!("Authentication required before other messages"));
-                    }
-                }
+    #[tokio::test]
+    async fn test_spawn_env_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_config(ServerConfig::new("127.0.0.1".to_string(), 9000))
+            .with_manager(manager)
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let spawn = |env: serde_json::Value| {
+            serde_json::json!({"type": "spawn_agent", "project_path": dir.path(), "env": env})
+        };
+
+        let lang = spawn(serde_json::json!({"LANG": "C"}));
+        let spawned = request(&server.state, &mut connection, lang).await;
+        assert!(matches!(spawned, Some(ServerMessage::AgentSpawned { .. })));
+        let refused = request(
+            &server.state,
+            &mut connection,
+            spawn(serde_json::json!({"LANG": "C", "LD_PRELOAD": "/tmp/hook.so"})),
+        )
+        .await;
+        let Some(ServerMessage::Error { message, code, .. }) = refused else {
+            panic!("expected an error, got {:?}", refused);
+        };
+        assert_eq!(code, Some(ErrorCode::PermissionDenied));
+        assert!(message.contains("LD_PRELOAD"));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("frontend")).unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty.clone()));
+        let server = WebSocketServer::builder()
+            .with_config(ServerConfig::new("127.0.0.1".to_string(), 9000))
+            .with_manager(manager)
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let spawn = |cwd: &str| {
+            serde_json::json!({"type": "spawn_agent", "project_path": dir.path(), "cwd": cwd})
+        };
+
+        let spawned = request(&server.state, &mut connection, spawn("frontend")).await;
+        assert!(matches!(spawned, Some(ServerMessage::AgentSpawned { .. })));
+        assert_eq!(pty.spawns()[0].working_dir, dir.path().join("frontend"));
+
+        let missing = request(&server.state, &mut connection, spawn("backend")).await;
+        let Some(ServerMessage::Error { code, .. }) = missing else {
+            panic!("expected an error, got {:?}", missing);
+        };
+        assert_eq!(code, Some(ErrorCode::InvalidPath));
+        assert_eq!(pty.spawns().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_key_and_paste() {
+        let dir = tempfile::tempdir().unwrap();
+        // The agent switches bracketed paste on as it starts
+        let script = PtyScript::new().with_output("\x1b[?2004h");
+        let pty = Arc::new(ScriptedPtyBackend::new(script));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty.clone()));
+        let server = WebSocketServer::builder()
+            .with_config(ServerConfig::new("127.0.0.1".to_string(), 9000))
+            .with_manager(Arc::clone(&manager))
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let spawn = serde_json::json!({"type": "spawn_agent", "project_path": dir.path()});
+        let agent_id = match request(&server.state, &mut connection, spawn).await {
+            Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_id,
+            other => panic!("Expected AgentSpawned, got {:?}", other),
+        };
+        for _ in 0..100 {
+            if manager.input_modes(agent_id).await.unwrap().bracketed_paste {
+                break;
             }
-            Ok(Message::Ping(data)) => {
-                // Pings are OK during auth wait, but we can't respond here
-                // Just continue waiting
-                debug!("Received ping during auth wait: {:?}", data);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let paste = serde_json::json!({
+            "type": "agent_input", "agent_id": agent_id, "input": "a\nb", "paste": true
+        });
+        request(&server.state, &mut connection, paste).await;
+        let key = serde_json::json!({"type": "agent_key", "agent_id": agent_id, "key": "ctrl+c"});
+        request(&server.state, &mut connection, key).await;
+        assert_eq!(pty.spawns()[0].input(), b"\x1b[200~a\nb\x1b[201~\x03");
+
+        let key = serde_json::json!({"type": "agent_key", "agent_id": agent_id, "key": "hyper"});
+        let unknown = request(&server.state, &mut connection, key).await;
+        assert!(matches!(unknown, Some(ServerMessage::Error { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_clipboard() {
+        let dir = tempfile::tempdir().unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_config(ServerConfig::new("127.0.0.1".to_string(), 9000))
+            .with_manager(manager)
+            .build();
+        let (mut headset, _) = Connection::new("headset".to_string());
+        let (mut tablet, _) = Connection::new("tablet".to_string());
+        let spawn = serde_json::json!({"type": "spawn_agent", "project_path": dir.path()});
+        let agent_id = match request(&server.state, &mut headset, spawn).await {
+            Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_id,
+            other => panic!("Expected AgentSpawned, got {:?}", other),
+        };
+        let get = serde_json::json!({"type": "get_clipboard", "agent_id": agent_id});
+
+        let empty = request(&server.state, &mut tablet, get.clone()).await;
+        let Some(ServerMessage::Clipboard { text, set_at_ms, .. }) = empty else {
+            panic!("expected the clipboard, got {:?}", empty);
+        };
+        assert_eq!((text.as_str(), set_at_ms), ("", None));
+
+        let set = serde_json::json!({
+            "type": "set_clipboard", "agent_id": agent_id, "text": "cargo test -p core"
+        });
+        let copied = request(&server.state, &mut headset, set).await;
+        assert_eq!(request(&server.state, &mut tablet, get).await, copied);
+        let Some(ServerMessage::Clipboard { text, set_at_ms, .. }) = copied else {
+            panic!("expected the clipboard, got {:?}", copied);
+        };
+        assert_eq!(text, "cargo test -p core");
+        assert!(set_at_ms.is_some());
+
+        let other = serde_json::json!({"type": "get_clipboard", "agent_id": Uuid::new_v4()});
+        let Some(ServerMessage::Error { code, .. }) =
+            request(&server.state, &mut tablet, other).await
+        else {
+            panic!("expected an error");
+        };
+        assert_eq!(code, Some(ErrorCode::AgentNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_session_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_config(ServerConfig::new("127.0.0.1".to_string(), 9000))
+            .with_manager(manager)
+            .build();
+        let state = &server.state;
+        let (mut host, mut host_rx) = Connection::new("host".to_string());
+        let (mut guest, _) = Connection::new("guest".to_string());
+        let join = serde_json::json!({"type": "join_group", "group": "frontend", "version": 2});
+        let Some(ServerMessage::Error { code, .. }) = request(state, &mut guest, join.clone()).await
+        else {
+            panic!("expected an error");
+        };
+        assert_eq!(code, Some(ErrorCode::GroupNotFound));
+
+        let create = serde_json::json!({"type": "create_group", "group": "frontend", "version": 2});
+        request(state, &mut host, create).await;
+        let spawn = serde_json::json!({
+            "type": "spawn_agent", "project_path": dir.path(), "group": "frontend"
+        });
+        let agent_id = match request(state, &mut host, spawn).await {
+            Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_id,
+            other => panic!("Expected AgentSpawned, got {:?}", other),
+        };
+
+        // Joining attaches the group's agents and tells the other members
+        assert_eq!(
+            request(state, &mut guest, join).await,
+            Some(ServerMessage::GroupJoined {
+                group: "frontend".to_string(),
+                agent_ids: vec![agent_id],
+                members: vec!["guest".to_string(), "host".to_string()],
+            })
+        );
+        assert!(guest.receives_output(agent_id));
+        let notice = std::iter::from_fn(|| host_rx.try_recv().ok())
+            .find(|msg| matches!(msg, ServerMessage::GroupMembers { .. }));
+        assert_eq!(
+            notice,
+            Some(ServerMessage::GroupMembers {
+                group: "frontend".to_string(),
+                members: vec!["guest".to_string(), "host".to_string()],
+            })
+        );
+        let list = request(state, &mut guest, serde_json::json!({"type": "list_groups"})).await;
+        let Some(ServerMessage::GroupList { groups }) = list else {
+            panic!("expected the groups, got {:?}", list);
+        };
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].agent_ids, [agent_id]);
+
+        let leave = serde_json::json!({"type": "leave_group", "group": "frontend"});
+        assert_eq!(
+            request(state, &mut guest, leave.clone()).await,
+            Some(ServerMessage::GroupLeft {
+                group: "frontend".to_string()
+            })
+        );
+        assert!(!guest.receives_output(agent_id));
+        assert!(matches!(
+            request(state, &mut guest, leave).await,
+            Some(ServerMessage::Error {
+                code: Some(ErrorCode::GroupNotFound),
+                ..
+            })
+        ));
+        assert_eq!(state.groups.members()["frontend"], ["host"]);
+    }
+
+    #[tokio::test]
+    async fn test_echo_state_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::echo()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_config(ServerConfig::new("127.0.0.1".to_string(), 9000))
+            .with_manager(manager)
+            .build();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let spawn = serde_json::json!({"type": "spawn_agent", "project_path": dir.path()});
+        let agent_id = match request(&server.state, &mut connection, spawn).await {
+            Some(ServerMessage::AgentSpawned { agent_id, .. }) => agent_id,
+            other => panic!("Expected AgentSpawned, got {:?}", other),
+        };
+
+        let get = serde_json::json!({"type": "get_echo_state", "agent_id": agent_id});
+        assert_eq!(
+            request(&server.state, &mut connection, get).await,
+            Some(ServerMessage::EchoState {
+                agent_id,
+                echo: true,
+                raw: false
+            })
+        );
+        let set = serde_json::json!({"type": "set_raw_mode", "agent_id": agent_id, "raw": true});
+        assert_eq!(
+            request(&server.state, &mut connection, set).await,
+            Some(ServerMessage::EchoState {
+                agent_id,
+                echo: false,
+                raw: true
+            })
+        );
+
+        let missing = serde_json::json!({"type": "get_echo_state", "agent_id": Uuid::new_v4()});
+        let Some(ServerMessage::Error { code, .. }) =
+            request(&server.state, &mut connection, missing).await
+        else {
+            panic!("expected an error");
+        };
+        assert_eq!(code, Some(ErrorCode::AgentNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_list_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".hoc")).unwrap();
+        let project = "default_preset = \"reviewer\"\n[[presets]]\nname = \"reviewer\"\n";
+        std::fs::write(dir.path().join(".hoc/config.toml"), project).unwrap();
+        let global = BridgeConfig::parse(
+            "[[presets]]\nname = \"reviewer\"\n\n[[presets]]\nname = \"fixer\"\n",
+        )
+        .unwrap();
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000).with_presets(global.presets);
+        let state = ServerState::new(config, Federation::new());
+        let (mut connection, _) = Connection::new("test".to_string());
+
+        let listed = request(
+            &state,
+            &mut connection,
+            serde_json::json!({"type": "list_presets", "project_path": dir.path()}),
+        )
+        .await;
+        let Some(ServerMessage::PresetList { presets, default_preset, .. }) = listed else {
+            panic!("expected a preset list, got {:?}", listed);
+        };
+        // The project's preset overrides the global one of the same name
+        let names: Vec<_> = presets.iter().map(|p| (p.name.as_str(), p.source)).collect();
+        assert_eq!(
+            names,
+            [("reviewer", PresetSource::Project), ("fixer", PresetSource::Global)]
+        );
+        assert_eq!(default_preset.as_deref(), Some("reviewer"));
+    }
+
+    #[tokio::test]
+    async fn test_run_pipeline() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".hoc")).unwrap();
+        let project = r#"
+            [[pipelines]]
+            name = "build"
+            steps = [
+                { action = "spawn", agent = "builder" },
+                { action = "input", agent = "builder", input = "make" },
+                { action = "wait", agent = "builder", until = "exit" },
+                { action = "spawn", agent = "tester" },
+                { action = "input", agent = "tester", input = "test" },
+                { action = "wait", agent = "tester", timeout_secs = 10 },
+            ]
+        "#;
+        std::fs::write(dir.path().join(".hoc/config.toml"), project).unwrap();
+        let script = PtyScript::new().exit_on("make", 0).exit_on("test", 2);
+        let pty = Arc::new(ScriptedPtyBackend::new(script));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty.clone()));
+        let server = WebSocketServer::builder()
+            .with_config(ServerConfig::new("127.0.0.1".to_string(), 9000))
+            .with_manager(manager)
+            .build();
+        tokio::spawn(pipelines::run_pipelines(
+            Arc::clone(&server.state),
+            server.shutdown_tx.clone(),
+        ));
+        let mut events = server.state.pipelines.subscribe();
+        let (mut connection, _) = Connection::new("test".to_string());
+
+        let run = |pipeline: &str| {
+            serde_json::json!({"type": "run_pipeline", "project_path": dir.path(), "pipeline": pipeline})
+        };
+        let missing = request(&server.state, &mut connection, run("deploy")).await;
+        let Some(ServerMessage::Error { code, .. }) = missing else {
+            panic!("expected an error, got {:?}", missing);
+        };
+        assert_eq!(code, Some(ErrorCode::PipelineNotFound));
+
+        let Some(ServerMessage::PipelineStarted { run_id, steps, .. }) =
+            request(&server.state, &mut connection, run("build")).await
+        else {
+            panic!("expected the pipeline to start");
+        };
+        assert_eq!(steps, 6);
+        let mut actions = Vec::new();
+        let completed = loop {
+            match events.recv().await.unwrap() {
+                ServerMessage::PipelineStep { action, .. } => actions.push(action),
+                completed @ ServerMessage::PipelineCompleted { .. } => break completed,
+                other => panic!("unexpected {:?}", other),
             }
-            Ok(Message::Close(_)) => {
-                return Err(anyhow!("Connection closed during authentication"));
+        };
+        assert_eq!(actions, ["spawn", "input", "wait", "spawn", "input", "wait"]);
+        // The tester exits with code 2, failing the last step
+        let ServerMessage::PipelineCompleted {
+            run_id: completed_id,
+            success,
+            failed_step,
+            error,
+            agents,
+            ..
+        } = completed
+        else {
+            unreachable!();
+        };
+        assert_eq!(completed_id, run_id);
+        assert!(!success);
+        assert_eq!(failed_step, Some(5));
+        assert_eq!(error.as_deref(), Some("Agent tester exited with code 2"));
+        assert_eq!(agents.keys().collect::<Vec<_>>(), ["builder", "tester"]);
+        assert_eq!(pty.spawns().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_schedules() {
+        let dir = tempfile::tempdir().unwrap();
+        let global = BridgeConfig::parse(&format!(
+            "[[schedules]]\nname = \"deps\"\ncron = \"0 3 * * *\"\nproject_path = {:?}\nprompt = \"Update the dependencies\"\n",
+            dir.path()
+        ))
+        .unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty.clone()));
+        let server = WebSocketServer::builder()
+            .with_config(
+                ServerConfig::new("127.0.0.1".to_string(), 9000)
+                    .with_schedules(global.schedules.clone()),
+            )
+            .with_manager(manager)
+            .build();
+        let mut events = server.state.schedules.subscribe();
+        let (mut connection, _) = Connection::new("test".to_string());
+
+        let listed = request(
+            &server.state,
+            &mut connection,
+            serde_json::json!({"type": "list_schedules"}),
+        )
+        .await;
+        let Some(ServerMessage::ScheduleList { schedules }) = listed else {
+            panic!("expected a schedule list, got {:?}", listed);
+        };
+        assert_eq!(schedules.len(), 1);
+        assert_eq!(schedules[0].cron, "0 3 * * *");
+        assert!(schedules[0].enabled);
+        assert!(schedules[0].next_run_ms.is_some());
+
+        let enable = |schedule: &str, enabled: bool| {
+            serde_json::json!({"type": "enable_schedule", "schedule": schedule, "enabled": enabled})
+        };
+        let Some(ServerMessage::ScheduleUpdated { schedule }) =
+            request(&server.state, &mut connection, enable("deps", false)).await
+        else {
+            panic!("expected the schedule to be updated");
+        };
+        assert!(!schedule.enabled);
+        assert_eq!(schedule.next_run_ms, None);
+        let missing = request(&server.state, &mut connection, enable("nightly", true)).await;
+        let Some(ServerMessage::Error { code, .. }) = missing else {
+            panic!("expected an error, got {:?}", missing);
+        };
+        assert_eq!(code, Some(ErrorCode::ScheduleNotFound));
+
+        schedules::fire(Arc::clone(&server.state), global.schedules[0].clone()).await;
+        let Ok(ServerMessage::ScheduleFired {
+            schedule,
+            agent_id: Some(agent_id),
+            error: None,
+            ..
+        }) = events.recv().await
+        else {
+            panic!("expected the schedule to spawn an agent");
+        };
+        assert_eq!(schedule, "deps");
+        assert_eq!(pty.spawns().len(), 1);
+        assert_eq!(server.state.schedules.list()[0].last_agent_id, Some(agent_id));
+    }
+
+    #[tokio::test]
+    async fn test_webhooks() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let global = BridgeConfig::parse(&format!(
+            "[[webhooks]]\nurl = \"http://{}/events\"\nevents = [\"agent_spawned\"]\n",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder()
+            .with_config(
+                ServerConfig::new("127.0.0.1".to_string(), 9000).with_webhooks(global.webhooks),
+            )
+            .with_manager(manager)
+            .build();
+        tokio::spawn(webhooks::run_webhooks(
+            Arc::clone(&server.state),
+            server.shutdown_tx.clone(),
+        ));
+        tokio::task::yield_now().await;
+        let (mut connection, _) = Connection::new("test".to_string());
+
+        let spawn = serde_json::json!({
+            "type": "spawn_agent",
+            "project_path": dir.path(),
+            "name": "builder",
+        });
+        let Some(ServerMessage::AgentSpawned { agent_id, .. }) =
+            request(&server.state, &mut connection, spawn).await
+        else {
+            panic!("expected the agent to spawn");
+        };
+
+        let (mut stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let body = loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "the request ended early");
+            request.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((_, body)) = text.split_once("\r\n\r\n") {
+                if let Ok(body) = serde_json::from_str::<serde_json::Value>(body) {
+                    break body;
+                }
             }
-            Err(e) => {
-                return Err(anyhow!("WebSocket error during authentication: {}", e));
+        };
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(request.starts_with(b"POST /events HTTP/1.1\r\n"));
+        assert_eq!(body["event"], "agent_spawned");
+        assert_eq!(body["agent_id"], agent_id.to_string());
+        assert_eq!(body["agent"]["name"], "builder");
+        assert_eq!(body["message"]["type"], "agent_spawned");
+        assert!(body["text"].as_str().unwrap().starts_with("Agent builder started"));
+    }
+
+    #[tokio::test]
+    async fn test_roles_limit_requests() {
+        let state = test_state();
+        let (mut connection, _) = Connection::new("test".to_string());
+        connection.role = Role::Observer;
+
+        let list = serde_json::json!({"type": "list_agents"});
+        let listed = request(&state, &mut connection, list).await;
+        assert!(matches!(listed, Some(ServerMessage::AgentList { .. })));
+        let spawn = serde_json::json!({"type": "spawn_agent", "project_path": "/tmp"});
+        let refused = request(&state, &mut connection, spawn).await;
+        assert!(matches!(
+            refused,
+            Some(ServerMessage::Error { code: Some(ErrorCode::PermissionDenied), .. })
+        ));
+
+        connection.role = Role::Operator;
+        let shutdown = serde_json::json!({"type": "shutdown"});
+        let refused = request(&state, &mut connection, shutdown.clone()).await;
+        assert!(matches!(
+            refused,
+            Some(ServerMessage::Error { code: Some(ErrorCode::PermissionDenied), .. })
+        ));
+
+        connection.role = Role::Admin;
+        let mut shutdown_rx = state.shutdown_tx.subscribe();
+        let stopping = request(&state, &mut connection, shutdown).await;
+        assert!(matches!(stopping, Some(ServerMessage::ShuttingDown)));
+        assert!(shutdown_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_token_roles_from_config() {
+        let observer = TokenConfig {
+            token: "watch".to_string(),
+            role: Role::Observer,
+            name: None,
+        };
+        let config = ServerConfig::new("127.0.0.1".to_string(), 9000).with_tokens(vec![observer]);
+        let state = ServerState::new(config, Federation::new());
+        assert!(state.live.auth_required());
+        assert_eq!(state.live.role_for("watch"), Some(Role::Observer));
+        assert_eq!(state.live.admin_token(), None);
+    }
+
+    #[tokio::test]
+    async fn test_write_file_and_apply_patch() {
+        let pty = Arc::new(ScriptedPtyBackend::new(PtyScript::new()));
+        let manager = Arc::new(AgentManager::new().with_pty_backend(pty));
+        let server = WebSocketServer::builder().with_manager(manager).build();
+        let state = &server.state;
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let (mut connection, _) = Connection::new("test".to_string());
+        let spawned = request(
+            state,
+            &mut connection,
+            serde_json::json!({"type": "spawn_agent", "project_path": dir.path()}),
+        )
+        .await;
+        let Some(ServerMessage::AgentSpawned { agent_id, .. }) = spawned else {
+            panic!("Expected agent_spawned, got {:?}", spawned);
+        };
+
+        let written = request(
+            state,
+            &mut connection,
+            serde_json::json!({
+                "type": "write_file",
+                "agent_id": agent_id,
+                "path": "notes.txt",
+                "content": "one\n",
+                "stage": true,
+            }),
+        )
+        .await;
+        assert!(matches!(
+            written,
+            Some(ServerMessage::FileWritten { size: 4, staged: true, .. })
+        ));
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "one\n");
+        assert!(repo
+            .status_file(Path::new("notes.txt"))
+            .unwrap()
+            .is_index_new());
+
+        let escape = request(
+            state,
+            &mut connection,
+            serde_json::json!({
+                "type": "write_file",
+                "agent_id": agent_id,
+                "path": "../escape.txt",
+                "content": "x",
+            }),
+        )
+        .await;
+        assert!(matches!(
+            escape,
+            Some(ServerMessage::Error { code: Some(ErrorCode::InvalidPath), .. })
+        ));
+
+        let patch = "diff --git a/notes.txt b/notes.txt\n--- a/notes.txt\n+++ b/notes.txt\n\
+                     @@ -1 +1 @@\n-one\n+two\n";
+        let applied = request(
+            state,
+            &mut connection,
+            serde_json::json!({"type": "apply_patch", "agent_id": agent_id, "patch": patch}),
+        )
+        .await;
+        let Some(ServerMessage::PatchApplied { paths, staged, .. }) = applied else {
+            panic!("Expected patch_applied, got {:?}", applied);
+        };
+        assert_eq!((paths, staged), (vec!["notes.txt".to_string()], false));
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "two\n");
+    }
+
+    #[tokio::test]
+    async fn test_push_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = dir.path().join("upstream.git");
+        git2::Repository::init_bare(&upstream).unwrap();
+        let project = dir.path().join("project");
+        let repo = git2::Repository::init(&project).unwrap();
+        repo.remote("origin", &upstream.display().to_string()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "Initial", &tree, &[])
+            .unwrap();
+        let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+
+        let state = test_state();
+        let (mut connection, mut notices) = Connection::new("test".to_string());
+        let push = serde_json::json!({
+            "type": "push_branch",
+            "project_path": project.display().to_string(),
+        });
+        assert_eq!(request(&state, &mut connection, push.clone()).await, None);
+        let outcome = loop {
+            match notices.recv().await.unwrap() {
+                ServerMessage::TransferProgress { operation, .. } => {
+                    assert_eq!(operation, TransferOperation::Push);
+                }
+                ServerMessage::VersionNegotiated { .. } => {}
+                other => break other,
             }
-            _ => {
-                // Ignore other message types during auth
+        };
+        assert_eq!(
+            outcome,
+            ServerMessage::BranchPushed {
+                project_path: project.display().to_string(),
+                remote: "origin".to_string(),
+                branch: branch.clone(),
             }
-        }
+        );
+        let pushed = git2::Repository::open_bare(&upstream).unwrap();
+        assert!(pushed.find_branch(&branch, git2::BranchType::Local).is_ok());
+
+        // An unknown token secret is reported before anything is sent
+        let mut with_token = push;
+        with_token["token_secret"] = "NO_SUCH_TOKEN".into();
+        let error = request(&state, &mut connection, with_token).await;
+        assert!(matches!(
+            error,
+            Some(ServerMessage::Error { code: Some(ErrorCode::GitFailed), .. })
+        ));
     }
 
-    Err(anyhow!("Connection closed before authentication"))
-}
+    #[tokio::test]
+    async fn test_tls_connection() {
+        use tokio_rustls::rustls;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        let acceptor = TlsConfig::new(cert_path, key_path).acceptor().unwrap();
 
-    #[test]
-    fn test_server_config() {
-        let config = ServerConfig::new("127.0.0.1".to_string(), 9000);
-        assert_eq!(config.socket_addr(), "127.0.0.1:9000");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(test_state());
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let shutdown_rx = shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            let (stream, peer_addr) = listener.accept().await.unwrap();
+            let _ = accept_connection(stream, peer_addr, Some(acceptor), state, shutdown_rx).await;
+        });
+
+        // Client trusting the self-signed certificate
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(tls));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let domain = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        let stream = connector.connect(domain, stream).await.unwrap();
+        let (mut ws, _) = tokio_tungstenite::client_async("wss://localhost/", stream)
+            .await
+            .unwrap();
+
+        let welcome = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        assert!(welcome.contains(r#""type":"welcome""#));
+        let _ = shutdown_tx.send(());
     }
 
-    #[test]
-    fn test_server_config_with_token() {
-        let config =
-            ServerConfig::new("0.0.0.0".to_string(), 8080).with_token(Some("secret".to_string()));
-        assert_eq!(config.token, Some("secret".to_string()));
+    #[tokio::test]
+    async fn test_rest_requests_share_the_port() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let state = Arc::new(ServerState::new(
+            ServerConfig::new("127.0.0.1".to_string(), 0),
+            Federation::new(),
+        ));
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let (mut client, server_io) = tokio::io::duplex(64 * 1024);
+        let peer: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let session = tokio::spawn(handle_connection(
+            server_io,
+            peer,
+            Arc::clone(&state),
+            shutdown_tx.subscribe(),
+        ));
+
+        let body = r#"{"cols": 80}"#;
+        let request = format!(
+            "POST /agents HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        session.await.unwrap().unwrap();
+        // The body was read: it lacks the project path
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+        assert!(response.contains("project_path"));
     }
 
     #[tokio::test]
-    async fn test_handle_ping_message() {
-        let agent_manager = AgentManager::new();
-        let msg = r#"{"type": "ping", "seq": 42}"#;
-        let response = handle_message(msg, &agent_manager).await.unwrap();
+    async fn test_silent_clients_are_disconnected() {
+        use tokio_tungstenite::tungstenite::protocol::Role as WsRole;
 
-        match response {
-            Some(ServerMessage::Pong { seq }) => assert_eq!(seq, 42),
-            _ => panic!("Expected Some(Pong) response"),
-        }
+        let heartbeat = HeartbeatConfig::default()
+            .with_interval(Some(Duration::from_millis(50)))
+            .with_max_missed(2);
+        let config = ServerConfig::new("127.0.0.1".to_string(), 0).with_heartbeat(heartbeat);
+        let state = Arc::new(ServerState::new(config, Federation::new()));
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let connect = |client: &str| {
+            let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+            let session = tokio::spawn({
+                let state = Arc::clone(&state);
+                let client = client.to_string();
+                let shutdown_rx = shutdown_tx.subscribe();
+                async move {
+                    let ws =
+                        WebSocketStream::from_raw_socket(server_io, WsRole::Server, None).await;
+                    serve_websocket(ws, client, state, SessionOptions::default(), shutdown_rx)
+                        .await
+                }
+            });
+            (client_io, session)
+        };
+
+        // A client that keeps reading answers the pings and stays connected
+        let (client_io, live) = connect("10.0.0.5:4000");
+        let mut ws = WebSocketStream::from_raw_socket(client_io, WsRole::Client, None).await;
+        tokio::spawn(async move { while let Some(Ok(_)) = ws.next().await {} });
+
+        // One that has gone away is closed, and forgotten, after two pings
+        let (_client_io, dead) = connect("10.0.0.6:4000");
+        tokio::time::timeout(Duration::from_secs(2), dead)
+            .await
+            .expect("silent client was not disconnected")
+            .unwrap()
+            .unwrap();
+        assert!(!live.is_finished());
+        let clients = state.clients.list();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client, "10.0.0.5:4000");
+        let _ = shutdown_tx.send(());
+    }
+
+    #[test]
+    fn test_typing_notifications_are_throttled() {
+        let state = test_state();
+        let mut typing_rx = state.typing_tx.subscribe();
+        let mut typing = TypingNotifier::new(Uuid::new_v4(), "10.0.0.5:4000".to_string());
+        let agent_id = Uuid::new_v4();
+        let start = Instant::now();
+
+        typing.notify(&state, agent_id, start);
+        typing.notify(&state, agent_id, start + Duration::from_millis(100));
+        typing.notify(&state, Uuid::new_v4(), start + Duration::from_millis(200));
+        typing.notify(&state, agent_id, start + TYPING_INTERVAL);
+
+        let first = typing_rx.try_recv().unwrap();
+        assert_eq!(first.agent_id, agent_id);
+        assert_eq!(first.client, "10.0.0.5:4000");
+        assert_eq!(first.connection_id, typing.connection_id);
+        assert_ne!(typing_rx.try_recv().unwrap().agent_id, agent_id);
+        assert_eq!(typing_rx.try_recv().unwrap().agent_id, agent_id);
+        assert!(typing_rx.try_recv().is_err());
     }
 }